@@ -0,0 +1,3 @@
+package net.mullvad.mullvadvpn.model
+
+data class AccountExpiryEvent(val expiry: String, val warning: ExpiryWarning?)
@@ -0,0 +1,7 @@
+package net.mullvad.mullvadvpn.model
+
+enum class ExpiryWarning {
+    ThreeDaysLeft,
+    OneDayLeft,
+    Expired,
+}
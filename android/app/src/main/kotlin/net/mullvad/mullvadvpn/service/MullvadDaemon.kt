@@ -1,5 +1,6 @@
 package net.mullvad.mullvadvpn.service
 
+import net.mullvad.mullvadvpn.model.AccountExpiryEvent
 import net.mullvad.mullvadvpn.model.AppVersionInfo
 import net.mullvad.mullvadvpn.model.DnsOptions
 import net.mullvad.mullvadvpn.model.GeoIpLocation
@@ -19,6 +20,7 @@ class MullvadDaemon(val vpnService: MullvadVpnService) {
     val onSettingsChange = EventNotifier<Settings?>(null)
     var onTunnelStateChange = EventNotifier<TunnelState>(TunnelState.Disconnected)
 
+    var onAccountExpiryChange: ((AccountExpiryEvent) -> Unit)? = null
     var onAppVersionInfoChange: ((AppVersionInfo) -> Unit)? = null
     var onKeygenEvent: ((KeygenEvent) -> Unit)? = null
     var onRelayListChange: ((RelayList) -> Unit)? = null
@@ -80,6 +82,14 @@ class MullvadDaemon(val vpnService: MullvadVpnService) {
         return getState(daemonInterfaceAddress)
     }
 
+    fun initPlayPurchase(): String? {
+        return initPlayPurchase(daemonInterfaceAddress)
+    }
+
+    fun verifyPlayPurchase(productId: String, purchaseToken: String): Boolean {
+        return verifyPlayPurchase(daemonInterfaceAddress, productId, purchaseToken)
+    }
+
     fun getVersionInfo(): AppVersionInfo? {
         return getVersionInfo(daemonInterfaceAddress)
     }
@@ -136,6 +146,7 @@ class MullvadDaemon(val vpnService: MullvadVpnService) {
         onSettingsChange.unsubscribeAll()
         onTunnelStateChange.unsubscribeAll()
 
+        onAccountExpiryChange = null
         onAppVersionInfoChange = null
         onKeygenEvent = null
         onRelayListChange = null
@@ -167,6 +178,12 @@ class MullvadDaemon(val vpnService: MullvadVpnService) {
     private external fun getSettings(daemonInterfaceAddress: Long): Settings?
     private external fun getState(daemonInterfaceAddress: Long): TunnelState?
     private external fun getVersionInfo(daemonInterfaceAddress: Long): AppVersionInfo?
+    private external fun initPlayPurchase(daemonInterfaceAddress: Long): String?
+    private external fun verifyPlayPurchase(
+        daemonInterfaceAddress: Long,
+        productId: String,
+        purchaseToken: String
+    ): Boolean
     private external fun getWireguardKey(daemonInterfaceAddress: Long): PublicKey?
     private external fun reconnect(daemonInterfaceAddress: Long)
     private external fun clearAccountHistory(daemonInterfaceAddress: Long)
@@ -186,6 +203,10 @@ class MullvadDaemon(val vpnService: MullvadVpnService) {
     )
     private external fun verifyWireguardKey(daemonInterfaceAddress: Long): Boolean?
 
+    private fun notifyAccountExpiryEvent(event: AccountExpiryEvent) {
+        onAccountExpiryChange?.invoke(event)
+    }
+
     private fun notifyAppVersionInfoEvent(appVersionInfo: AppVersionInfo) {
         onAppVersionInfoChange?.invoke(appVersionInfo)
     }
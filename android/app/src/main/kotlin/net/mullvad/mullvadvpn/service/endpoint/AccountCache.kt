@@ -9,6 +9,7 @@ import kotlinx.coroutines.channels.sendBlocking
 import kotlinx.coroutines.delay
 import net.mullvad.mullvadvpn.ipc.Event
 import net.mullvad.mullvadvpn.ipc.Request
+import net.mullvad.mullvadvpn.model.AccountExpiryEvent
 import net.mullvad.mullvadvpn.model.GetAccountDataResult
 import net.mullvad.mullvadvpn.model.LoginStatus
 import net.mullvad.mullvadvpn.util.ExponentialBackoff
@@ -73,6 +74,10 @@ class AccountCache(private val endpoint: ServiceEndpoint) {
             handleNewAccountNumber(accountNumber)
         }
 
+        daemon.registerListener(this) { newDaemon ->
+            newDaemon?.onAccountExpiryChange = { event -> handleAccountExpiryEvent(event) }
+        }
+
         onAccountHistoryChange.subscribe(this) { history ->
             endpoint.sendEvent(Event.AccountHistory(history))
         }
@@ -112,6 +117,7 @@ class AccountCache(private val endpoint: ServiceEndpoint) {
 
     fun onDestroy() {
         endpoint.settingsListener.accountNumberNotifier.unsubscribe(this)
+        daemon.unregisterListener(this)
         jobTracker.cancelAllJobs()
 
         onAccountNumberChange.unsubscribeAll()
@@ -250,6 +256,16 @@ class AccountCache(private val endpoint: ServiceEndpoint) {
         }
     }
 
+    private fun handleAccountExpiryEvent(event: AccountExpiryEvent) {
+        synchronized(this) {
+            accountNumber?.let { account ->
+                // The expiry pushed by the daemon is up to date, so it is accepted even if the
+                // current expiry has been invalidated
+                handleNewExpiry(account, event.expiry, MAX_INVALIDATED_RETRIES)
+            }
+        }
+    }
+
     private fun handleNewExpiry(
         accountNumberUsedForFetch: String,
         expiryString: String,
@@ -76,6 +76,7 @@ class TunnelStateNotification(
                 return context.getString(R.string.invalid_dns_servers, addresses)
             }
             is ErrorStateCause.AuthFailed -> R.string.auth_failed
+            is ErrorStateCause.AccountExpired -> R.string.account_expired_blocking
             is ErrorStateCause.Ipv6Unavailable -> R.string.ipv6_unavailable
             is ErrorStateCause.SetFirewallPolicyError -> R.string.set_firewall_policy_error
             is ErrorStateCause.SetDnsError -> R.string.set_dns_error
@@ -91,6 +92,7 @@ class TunnelStateNotification(
                     ParameterGenerationError.CustomTunnelHostResultionError -> {
                         R.string.custom_tunnel_host_resolution_error
                     }
+                    ParameterGenerationError.RelayKeyMismatch -> R.string.relay_key_mismatch
                 }
             }
             is ErrorStateCause.VpnPermissionDenied -> R.string.vpn_permission_denied_error
@@ -8,6 +8,9 @@ sealed class ErrorStateCause : Parcelable {
     @Parcelize
     class AuthFailed(val reason: String?) : ErrorStateCause()
 
+    @Parcelize
+    object AccountExpired : ErrorStateCause()
+
     @Parcelize
     object Ipv6Unavailable : ErrorStateCause()
 
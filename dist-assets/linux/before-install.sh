@@ -4,8 +4,11 @@ set -eu
 if which systemctl &> /dev/null; then
     if systemctl status mullvad-daemon &> /dev/null; then
         /opt/Mullvad\ VPN/resources/mullvad-setup prepare-restart || true
+        systemctl stop mullvad-daemon.socket || true
+        systemctl disable mullvad-daemon.socket || true
         systemctl stop mullvad-daemon.service
         systemctl disable mullvad-daemon.service
+        systemctl disable mullvad-early-boot-blocking.service || true
         cp /var/log/mullvad-vpn/daemon.log /var/log/mullvad-vpn/old-install-daemon.log \
             || echo "Failed to copy old daemon log"
     fi
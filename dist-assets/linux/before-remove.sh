@@ -16,8 +16,12 @@ fi
 
 if which systemctl &> /dev/null; then
     # the user might've disabled or stopped the service themselves already
+    # Stop the socket first so that clients cannot start the daemon again
+    systemctl stop mullvad-daemon.socket || true
+    systemctl disable mullvad-daemon.socket || true
     systemctl stop mullvad-daemon.service || true
     systemctl disable mullvad-daemon.service || true
+    systemctl disable mullvad-early-boot-blocking.service || true
 elif /sbin/init --version | grep upstart &> /dev/null; then
     stop mullvad-daemon
     rm -f /etc/init/mullvad-daemon.conf
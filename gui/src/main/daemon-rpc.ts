@@ -36,6 +36,7 @@ import {
   TunnelType,
   IProxyEndpoint,
   ProxyType,
+  ObfuscationType,
   KeygenEvent,
   IWireguardPublicKey,
   ISettings,
@@ -329,6 +330,10 @@ export class DaemonRpc {
     await this.callBool(this.client.setBlockWhenDisconnected, blockWhenDisconnected);
   }
 
+  public async setBlockWhenAccountExpired(blockWhenAccountExpired: boolean): Promise<void> {
+    await this.callBool(this.client.setBlockWhenAccountExpired, blockWhenAccountExpired);
+  }
+
   public async setBridgeState(bridgeState: BridgeState): Promise<void> {
     const bridgeStateMap = {
       auto: grpcTypes.BridgeState.State.AUTO,
@@ -464,6 +469,9 @@ export class DaemonRpc {
     defaultOptions.setBlockAds(dns.defaultOptions.blockAds);
     defaultOptions.setBlockTrackers(dns.defaultOptions.blockTrackers);
     defaultOptions.setBlockMalware(dns.defaultOptions.blockMalware);
+    defaultOptions.setBlockAdultContent(dns.defaultOptions.blockAdultContent);
+    defaultOptions.setBlockGambling(dns.defaultOptions.blockGambling);
+    defaultOptions.setDnsOverTls(dns.defaultOptions.dnsOverTls);
     dnsOptions.setDefaultOptions(defaultOptions);
 
     const customOptions = new grpcTypes.CustomDnsOptions();
@@ -479,6 +487,11 @@ export class DaemonRpc {
     await this.call<grpcTypes.DnsOptions, Empty>(this.client.setDnsOptions, dnsOptions);
   }
 
+  // Applies a JSON merge patch to the settings as a single change.
+  public async applySettingsPatch(patch: Record<string, unknown>): Promise<void> {
+    await this.callString(this.client.applySettingsPatch, JSON.stringify(patch));
+  }
+
   public async verifyWireguardKey(): Promise<boolean> {
     const response = await this.callEmpty<BoolValue>(this.client.verifyWireguardKey);
     return response.getValue();
@@ -805,6 +818,10 @@ function convertFromTunnelStateErrorCause(
       return { reason: 'is_offline' };
     case grpcTypes.ErrorState.Cause.SET_DNS_ERROR:
       return { reason: 'set_dns_error' };
+    case grpcTypes.ErrorState.Cause.KILL_SWITCH_VERIFICATION_FAILED:
+      return { reason: 'kill_switch_verification_failed' };
+    case grpcTypes.ErrorState.Cause.CLOCK_SKEW:
+      return { reason: 'clock_skew', details: state.clockSkewSeconds };
     case grpcTypes.ErrorState.Cause.IPV6_UNAVAILABLE:
       return { reason: 'ipv6_unavailable' };
     case grpcTypes.ErrorState.Cause.START_TUNNEL_ERROR:
@@ -816,6 +833,8 @@ function convertFromTunnelStateErrorCause(
       };
     case grpcTypes.ErrorState.Cause.AUTH_FAILED:
       return { reason: 'auth_failed', details: state.authFailReason };
+    case grpcTypes.ErrorState.Cause.ACCOUNT_EXPIRED:
+      return { reason: 'account_expired' };
     case grpcTypes.ErrorState.Cause.TUNNEL_PARAMETER_ERROR: {
       const parameterErrorMap: Record<
         grpcTypes.ErrorState.GenerationError,
@@ -826,6 +845,7 @@ function convertFromTunnelStateErrorCause(
         [grpcTypes.ErrorState.GenerationError.NO_WIREGUARD_KEY]: 'no_wireguard_key',
         [grpcTypes.ErrorState.GenerationError.CUSTOM_TUNNEL_HOST_RESOLUTION_ERROR]:
           'custom_tunnel_host_resultion_error',
+        [grpcTypes.ErrorState.GenerationError.RELAY_KEY_MISMATCH]: 'relay_key_mismatch',
       };
       return { reason: 'tunnel_parameter_error', details: parameterErrorMap[state.parameterError] };
     }
@@ -865,6 +885,8 @@ function convertFromTunnelStateRelayInfo(
         entryEndpoint:
           state.tunnelEndpoint.entryEndpoint &&
           convertFromEntryEndpoint(state.tunnelEndpoint.entryEndpoint),
+        obfuscation: convertFromObfuscationType(state.tunnelEndpoint.obfuscation),
+        tunnelInterface: state.tunnelEndpoint.tunnelInterface || undefined,
       },
     };
   }
@@ -880,6 +902,21 @@ function convertFromTunnelType(tunnelType: grpcTypes.TunnelType): TunnelType {
   return tunnelTypeMap[tunnelType];
 }
 
+function convertFromObfuscationType(
+  obfuscation: grpcTypes.ObfuscationType,
+): ObfuscationType | undefined {
+  switch (obfuscation) {
+    case grpcTypes.ObfuscationType.OBFUSCATION_UDP2TCP:
+      return 'udp2tcp';
+    case grpcTypes.ObfuscationType.OBFUSCATION_SOCKS5:
+      return 'socks5';
+    case grpcTypes.ObfuscationType.OBFUSCATION_PLUGIN:
+      return 'plugin';
+    case grpcTypes.ObfuscationType.NO_OBFUSCATION:
+      return undefined;
+  }
+}
+
 function convertFromProxyEndpoint(proxyEndpoint: grpcTypes.ProxyEndpoint.AsObject): IProxyEndpoint {
   const proxyTypeMap: Record<grpcTypes.ProxyType, ProxyType> = {
     [grpcTypes.ProxyType.CUSTOM]: 'custom',
@@ -1096,6 +1133,9 @@ function convertFromTunnelOptions(tunnelOptions: grpcTypes.TunnelOptions.AsObjec
         blockAds: tunnelOptions.dnsOptions?.defaultOptions?.blockAds ?? false,
         blockTrackers: tunnelOptions.dnsOptions?.defaultOptions?.blockTrackers ?? false,
         blockMalware: tunnelOptions.dnsOptions?.defaultOptions?.blockMalware ?? false,
+        blockAdultContent: tunnelOptions.dnsOptions?.defaultOptions?.blockAdultContent ?? false,
+        blockGambling: tunnelOptions.dnsOptions?.defaultOptions?.blockGambling ?? false,
+        dnsOverTls: tunnelOptions.dnsOptions?.defaultOptions?.dnsOverTls ?? false,
       },
       customOptions: {
         addresses: tunnelOptions.dnsOptions?.customOptions?.addressesList ?? [],
@@ -149,6 +149,7 @@ class ApplicationMain {
     allowLan: false,
     autoConnect: false,
     blockWhenDisconnected: false,
+    blockWhenAccountExpired: true,
     showBetaReleases: false,
     splitTunnel: {
       enableExclusions: false,
@@ -194,6 +195,9 @@ class ApplicationMain {
           blockAds: false,
           blockTrackers: false,
           blockMalware: false,
+          blockAdultContent: false,
+          blockGambling: false,
+          dnsOverTls: false,
         },
         customOptions: {
           addresses: [],
@@ -48,7 +48,9 @@ export default function CustomDnsSettings() {
       dns.state === 'custom' ||
       (!dns.defaultOptions.blockAds &&
         !dns.defaultOptions.blockTrackers &&
-        !dns.defaultOptions.blockMalware),
+        !dns.defaultOptions.blockMalware &&
+        !dns.defaultOptions.blockAdultContent &&
+        !dns.defaultOptions.blockGambling),
     [dns],
   );
 
@@ -279,6 +281,8 @@ function DisabledMessage() {
   const blockAdsFeatureName = messages.pgettext('preferences-view', 'Block ads');
   const blockTrackersFeatureName = messages.pgettext('preferences-view', 'Block trackers');
   const blockMalwareFeatureName = messages.pgettext('preferences-view', 'Block malware');
+  const blockAdultContentFeatureName = messages.pgettext('preferences-view', 'Block adult content');
+  const blockGamblingFeatureName = messages.pgettext('preferences-view', 'Block gambling');
   const preferencesPageName = messages.pgettext('preferences-nav', 'Preferences');
 
   // TRANSLATORS: This is displayed when either or both of the block ads/trackers settings are
@@ -288,10 +292,12 @@ function DisabledMessage() {
   // TRANSLATORS: %(blockAdsFeatureName)s - The name displayed next to the "Block ads" toggle.
   // TRANSLATORS: %(blockTrackersFeatureName)s - The name displayed next to the "Block trackers" toggle.
   // TRANSLATORS: %(blockMalwareFeatureName)s - The name displayed next to the "Block malware" toggle.
+  // TRANSLATORS: %(blockAdultContentFeatureName)s - The name displayed next to the "Block adult content" toggle.
+  // TRANSLATORS: %(blockGamblingFeatureName)s - The name displayed next to the "Block gambling" toggle.
   // TRANSLATORS: %(preferencesPageName)s - The page title showed on top in the preferences page.
   const customDnsDisabledMessage = messages.pgettext(
     'preferences-view',
-    'Disable **%(blockAdsFeatureName)s**, **%(blockTrackersFeatureName)s** and **%(blockMalwareFeatureName)s** (under %(preferencesPageName)s) to activate this setting.',
+    'Disable **%(blockAdsFeatureName)s**, **%(blockTrackersFeatureName)s**, **%(blockMalwareFeatureName)s**, **%(blockAdultContentFeatureName)s** and **%(blockGamblingFeatureName)s** (under %(preferencesPageName)s) to activate this setting.',
   );
 
   return formatMarkdown(
@@ -299,6 +305,8 @@ function DisabledMessage() {
       blockAdsFeatureName,
       blockTrackersFeatureName,
       blockMalwareFeatureName,
+      blockAdultContentFeatureName,
+      blockGamblingFeatureName,
       preferencesPageName,
     }),
   );
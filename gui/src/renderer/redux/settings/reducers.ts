@@ -192,6 +192,9 @@ const initialState: ISettingsReduxState = {
       blockAds: false,
       blockTrackers: false,
       blockMalware: false,
+      blockAdultContent: false,
+      blockGambling: false,
+      dnsOverTls: false,
     },
     customOptions: {
       addresses: [],
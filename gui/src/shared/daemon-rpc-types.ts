@@ -32,7 +32,8 @@ export type TunnelParameterError =
   | 'no_matching_relay'
   | 'no_matching_bridge_relay'
   | 'no_wireguard_key'
-  | 'custom_tunnel_host_resultion_error';
+  | 'custom_tunnel_host_resultion_error'
+  | 'relay_key_mismatch';
 
 export type ErrorStateCause =
   | {
@@ -41,11 +42,14 @@ export type ErrorStateCause =
         | 'set_dns_error'
         | 'start_tunnel_error'
         | 'is_offline'
-        | 'split_tunnel_error';
+        | 'split_tunnel_error'
+        | 'account_expired'
+        | 'kill_switch_verification_failed';
     }
   | { reason: 'set_firewall_policy_error'; details: FirewallPolicyError }
   | { reason: 'tunnel_parameter_error'; details: TunnelParameterError }
-  | { reason: 'auth_failed'; details?: string };
+  | { reason: 'auth_failed'; details?: string }
+  | { reason: 'clock_skew'; details: number };
 
 export type AfterDisconnect = 'nothing' | 'block' | 'reconnect';
 
@@ -88,8 +92,12 @@ export interface ITunnelEndpoint {
   tunnelType: TunnelType;
   proxy?: IProxyEndpoint;
   entryEndpoint?: IEndpoint;
+  obfuscation?: ObfuscationType;
+  tunnelInterface?: string;
 }
 
+export type ObfuscationType = 'udp2tcp' | 'socks5' | 'plugin';
+
 export interface IEndpoint {
   address: string;
   transportProtocol: RelayProtocol;
@@ -290,6 +298,9 @@ export interface IDnsOptions {
     blockAds: boolean;
     blockTrackers: boolean;
     blockMalware: boolean;
+    blockAdultContent: boolean;
+    blockGambling: boolean;
+    dnsOverTls: boolean;
   };
 }
 
@@ -327,6 +338,7 @@ export interface ISettings {
   allowLan: boolean;
   autoConnect: boolean;
   blockWhenDisconnected: boolean;
+  blockWhenAccountExpired: boolean;
   showBetaReleases: boolean;
   relaySettings: RelaySettings;
   tunnelOptions: ITunnelOptions;
@@ -1,3 +1,4 @@
+import { links } from '../../config.json';
 import { hasExpired } from '../account-expiry';
 import { AuthFailureKind, parseAuthFailure } from '../auth-failure';
 import { IErrorState, TunnelState, TunnelParameterError } from '../daemon-rpc-types';
@@ -5,6 +6,7 @@ import { messages } from '../gettext';
 import {
   InAppNotification,
   InAppNotificationProvider,
+  NotificationAction,
   SystemNotificationProvider,
 } from './notification';
 
@@ -33,6 +35,7 @@ export class ErrorNotificationProvider
       return {
         message,
         critical: !!this.context.tunnelState.details.blockFailure,
+        action: getAction(this.context.tunnelState.details),
       };
     } else {
       return undefined;
@@ -56,6 +59,7 @@ export class ErrorNotificationProvider
           ? messages.pgettext('in-app-notifications', 'BLOCKING INTERNET')
           : messages.pgettext('in-app-notifications', 'NETWORK TRAFFIC MIGHT BE LEAKING'),
         subtitle,
+        action: getAction(this.context.tunnelState.details),
       };
     } else {
       return undefined;
@@ -126,6 +130,16 @@ function getMessage(errorDetails: IErrorState, accountExpiry?: string): string {
           'notifications',
           'Unable to set system DNS server. Please contact support.',
         );
+      case 'kill_switch_verification_failed':
+        return messages.pgettext(
+          'notifications',
+          'Unable to confirm that the firewall rules and routes are in place. Try reconnecting or contact support.',
+        );
+      case 'clock_skew':
+        return messages.pgettext(
+          'notifications',
+          "Your computer's clock is wrong. Set the correct date and time, then reconnect.",
+        );
       case 'start_tunnel_error':
         return messages.pgettext(
           'notifications',
@@ -143,10 +157,28 @@ function getMessage(errorDetails: IErrorState, accountExpiry?: string): string {
           'notifications',
           'Unable to communicate with Mullvad kernel driver. Try reconnecting or contact support.',
         );
+      case 'account_expired':
+        return messages.pgettext(
+          'notifications',
+          'You have no more VPN time left on this account. The app will reconnect once more time has been added.',
+        );
     }
   }
 }
 
+function getAction(errorDetails: IErrorState): NotificationAction | undefined {
+  if (!errorDetails.blockFailure && errorDetails.cause.reason === 'account_expired') {
+    return {
+      type: 'open-url',
+      url: links.purchase,
+      withAuth: true,
+      text: messages.pgettext('notifications', 'Buy more'),
+    };
+  }
+
+  return undefined;
+}
+
 function getTunnelParameterMessage(err: TunnelParameterError): string {
   switch (err) {
     /// TODO: once bridge constraints can be set, add a more descriptive error message
@@ -166,5 +198,10 @@ function getTunnelParameterMessage(err: TunnelParameterError): string {
         'notifications',
         'Unable to resolve host of custom tunnel. Try changing your settings.',
       );
+    case 'relay_key_mismatch':
+      return messages.pgettext(
+        'notifications',
+        'The key of the selected server could not be verified. Try reconnecting or selecting another server.',
+      );
   }
 }
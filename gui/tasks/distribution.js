@@ -166,6 +166,8 @@ const config = {
       { from: distAssets('binaries/x86_64-unknown-linux-gnu/sslocal'), to: '.' },
       { from: distAssets('linux/mullvad-daemon.conf'), to: '.' },
       { from: distAssets('linux/mullvad-daemon.service'), to: '.' },
+      { from: distAssets('linux/mullvad-daemon.socket'), to: '.' },
+      { from: distAssets('linux/mullvad-early-boot-blocking.service'), to: '.' },
     ],
   },
 
@@ -181,6 +183,10 @@ const config = {
       '--config-files',
       '/opt/Mullvad VPN/resources/mullvad-daemon.service',
       '--config-files',
+      '/opt/Mullvad VPN/resources/mullvad-daemon.socket',
+      '--config-files',
+      '/opt/Mullvad VPN/resources/mullvad-early-boot-blocking.service',
+      '--config-files',
       '/opt/Mullvad VPN/resources/mullvad-daemon.conf',
       distAssets('mullvad') + '=/usr/bin/',
       distAssets('mullvad-exclude') + '=/usr/bin/',
@@ -206,6 +212,10 @@ const config = {
       '--config-files',
       '/opt/Mullvad VPN/resources/mullvad-daemon.service',
       '--config-files',
+      '/opt/Mullvad VPN/resources/mullvad-daemon.socket',
+      '--config-files',
+      '/opt/Mullvad VPN/resources/mullvad-early-boot-blocking.service',
+      '--config-files',
       '/opt/Mullvad VPN/resources/mullvad-daemon.conf',
       distAssets('mullvad') + '=/usr/bin/',
       distAssets('mullvad-exclude') + '=/usr/bin/',
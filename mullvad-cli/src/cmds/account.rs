@@ -1,7 +1,10 @@
 use crate::{new_rpc_client, Command, Error, Result};
 use clap::value_t_or_exit;
 use itertools::Itertools;
-use mullvad_management_interface::{types::Timestamp, Code};
+use mullvad_management_interface::{
+    types::{self, Timestamp},
+    Code,
+};
 use mullvad_types::account::AccountToken;
 use std::io::{self, Write};
 
@@ -47,6 +50,19 @@ impl Command for Account {
                             .required(true),
                     ),
             )
+            .subcommand(
+                clap::SubCommand::with_name("list-devices")
+                    .about("List the WireGuard keys of the devices that use the account"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("revoke-device")
+                    .about("Remove the WireGuard key of another device from the account")
+                    .arg(
+                        clap::Arg::with_name("key")
+                            .help("The public key of the device, in base64")
+                            .required(true),
+                    ),
+            )
     }
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
@@ -76,6 +92,11 @@ impl Command for Account {
         } else if let Some(matches) = matches.subcommand_matches("redeem") {
             let voucher = value_t_or_exit!(matches.value_of("voucher"), String);
             self.redeem_voucher(voucher).await
+        } else if let Some(_matches) = matches.subcommand_matches("list-devices") {
+            self.list_devices().await
+        } else if let Some(matches) = matches.subcommand_matches("revoke-device") {
+            let key = value_t_or_exit!(matches.value_of("key"), String);
+            self.revoke_device(key).await
         } else {
             unreachable!("No account command given");
         }
@@ -121,9 +142,53 @@ impl Account {
         self.get().await
     }
 
-    async fn redeem_voucher(&self, mut voucher: String) -> Result<()> {
+    async fn list_devices(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let keys = rpc
+            .list_wireguard_keys(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to list the account keys", error))?
+            .into_inner()
+            .keys;
+        if keys.is_empty() {
+            println!("No keys are registered on the account");
+        }
+        for key in keys {
+            println!(
+                "{}{}",
+                base64::encode(&key.key),
+                if key.is_current { " (this device)" } else { "" }
+            );
+            println!("\tIPv4: {}", key.ipv4_address);
+            println!("\tIPv6: {}", key.ipv6_address);
+        }
+        Ok(())
+    }
+
+    async fn revoke_device(&self, key: String) -> Result<()> {
+        let key = base64::decode(key.trim()).unwrap_or_else(|error| {
+            eprintln!("Failed to decode the key: {}", error);
+            std::process::exit(1);
+        });
+        let mut rpc = new_rpc_client().await?;
+        match rpc
+            .revoke_wireguard_key(types::PublicKey { key, created: None })
+            .await
+        {
+            Ok(_) => {
+                println!("Removed the key from the account");
+                Ok(())
+            }
+            Err(error) if error.code() == Code::InvalidArgument => {
+                eprintln!("Failed to remove the key: {}", error.message());
+                std::process::exit(1);
+            }
+            Err(error) => Err(Error::RpcFailedExt("Failed to remove the key", error)),
+        }
+    }
+
+    async fn redeem_voucher(&self, voucher: String) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        voucher.retain(|c| c.is_alphanumeric());
 
         match rpc.submit_voucher(voucher).await {
             Ok(submission) => {
@@ -140,7 +205,10 @@ impl Account {
             }
             Err(err) => {
                 match err.code() {
-                    Code::NotFound | Code::ResourceExhausted => {
+                    Code::NotFound
+                    | Code::ResourceExhausted
+                    | Code::FailedPrecondition
+                    | Code::InvalidArgument => {
                         eprintln!("Failed to submit voucher: {}", err.message());
                     }
                     _ => return Err(Error::RpcFailed(err)),
@@ -1,5 +1,6 @@
 use crate::{new_rpc_client, Command, Result};
 use clap::value_t_or_exit;
+use mullvad_management_interface::types;
 
 pub struct AutoConnect;
 
@@ -11,11 +12,11 @@ impl Command for AutoConnect {
 
     fn clap_subcommand(&self) -> clap::App<'static, 'static> {
         clap::SubCommand::with_name(self.name())
-            .about("Control the daemon auto-connect setting")
+            .about("Control when the daemon connects and disconnects automatically")
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(
                 clap::SubCommand::with_name("set")
-                    .about("Change auto-connect setting")
+                    .about("Change whether to connect when the daemon starts")
                     .arg(
                         clap::Arg::with_name("policy")
                             .required(true)
@@ -24,7 +25,44 @@ impl Command for AutoConnect {
             )
             .subcommand(
                 clap::SubCommand::with_name("get")
-                    .about("Display the current auto-connect setting"),
+                    .about("Display the current auto-connect settings"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("reconnect-on-network-change")
+                    .about(
+                        "Change whether to reconnect the tunnel when the device joins a \
+                         different network. Only supported on Linux and macOS",
+                    )
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("trusted-networks")
+                    .about(
+                        "Manage the networks on which the tunnel is disconnected. It is connected \
+                         again when the device leaves them. Only supported on Linux and macOS, \
+                         and Wi-Fi networks can only be trusted by SSID on Linux with \
+                         NetworkManager",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("add")
+                            .about("Trust a Wi-Fi network or a local subnet")
+                            .arg(trusted_network_kind_arg())
+                            .arg(trusted_network_arg()),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("remove")
+                            .about("Stop trusting a Wi-Fi network or a local subnet")
+                            .arg(trusted_network_kind_arg())
+                            .arg(trusted_network_arg()),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("clear").about("Remove all trusted networks"),
+                    ),
             )
     }
 
@@ -34,12 +72,32 @@ impl Command for AutoConnect {
             self.set(auto_connect == "on").await
         } else if let Some(_matches) = matches.subcommand_matches("get") {
             self.get().await
+        } else if let Some(reconnect_matches) =
+            matches.subcommand_matches("reconnect-on-network-change")
+        {
+            let reconnect = value_t_or_exit!(reconnect_matches.value_of("policy"), String);
+            self.set_reconnect_on_network_change(reconnect == "on")
+                .await
+        } else if let Some(trusted_matches) = matches.subcommand_matches("trusted-networks") {
+            self.handle_trusted_networks_cmd(trusted_matches).await
         } else {
             unreachable!("No auto-connect command given");
         }
     }
 }
 
+fn trusted_network_kind_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("kind")
+        .required(true)
+        .possible_values(&["ssid", "subnet"])
+}
+
+fn trusted_network_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("network")
+        .help("The SSID of a Wi-Fi network, or a subnet in CIDR notation, e.g. 192.168.1.0/24")
+        .required(true)
+}
+
 impl AutoConnect {
     async fn set(&self, auto_connect: bool) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
@@ -48,10 +106,83 @@ impl AutoConnect {
         Ok(())
     }
 
+    async fn set_reconnect_on_network_change(&self, reconnect: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_reconnect_on_network_change(reconnect).await?;
+        println!("Changed reconnect on network change setting");
+        Ok(())
+    }
+
     async fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        let auto_connect = rpc.get_settings(()).await?.into_inner().auto_connect;
-        println!("Autoconnect: {}", if auto_connect { "on" } else { "off" });
+        let settings = rpc.get_settings(()).await?.into_inner();
+        println!(
+            "Autoconnect: {}",
+            if settings.auto_connect { "on" } else { "off" }
+        );
+        println!(
+            "Reconnect on network change: {}",
+            if settings.reconnect_on_network_change {
+                "on"
+            } else {
+                "off"
+            }
+        );
+
+        let trusted_networks = settings.trusted_networks.unwrap_or_default();
+        if trusted_networks.ssids.is_empty() && trusted_networks.subnets.is_empty() {
+            println!("Trusted networks: none");
+        } else {
+            println!("Trusted networks:");
+            for ssid in &trusted_networks.ssids {
+                println!("\tSSID: {}", ssid);
+            }
+            for subnet in &trusted_networks.subnets {
+                println!("\tSubnet: {}", subnet);
+            }
+        }
         Ok(())
     }
+
+    async fn handle_trusted_networks_cmd(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let mut trusted_networks = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .trusted_networks
+            .unwrap_or_default();
+
+        match matches.subcommand() {
+            ("add", Some(matches)) => {
+                let network = matches.value_of("network").unwrap().to_owned();
+                let networks = Self::networks_of_kind(&mut trusted_networks, matches);
+                if !networks.contains(&network) {
+                    networks.push(network);
+                }
+            }
+            ("remove", Some(matches)) => {
+                let network = matches.value_of("network").unwrap();
+                Self::networks_of_kind(&mut trusted_networks, matches)
+                    .retain(|trusted| trusted != network);
+            }
+            ("clear", Some(_)) => trusted_networks = types::TrustedNetworks::default(),
+            _ => unreachable!("unhandled command"),
+        }
+
+        rpc.set_trusted_networks(trusted_networks).await?;
+        println!("Changed trusted networks");
+        Ok(())
+    }
+
+    fn networks_of_kind<'a>(
+        trusted_networks: &'a mut types::TrustedNetworks,
+        matches: &clap::ArgMatches<'_>,
+    ) -> &'a mut Vec<String> {
+        match matches.value_of("kind").unwrap() {
+            "ssid" => &mut trusted_networks.ssids,
+            "subnet" => &mut trusted_networks.subnets,
+            _ => unreachable!("Invalid trusted network kind"),
+        }
+    }
 }
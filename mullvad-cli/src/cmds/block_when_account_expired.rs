@@ -0,0 +1,69 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t_or_exit;
+
+pub struct BlockWhenAccountExpired;
+
+#[mullvad_management_interface::async_trait]
+impl Command for BlockWhenAccountExpired {
+    fn name(&self) -> &'static str {
+        "block-when-expired"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Control if the system service should keep blocking network access when the account runs out of time")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Change the block when expired setting")
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get")
+                    .about("Display the current block when expired setting"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let block_when_expired = value_t_or_exit!(set_matches.value_of("policy"), String);
+            self.set(block_when_expired == "on").await
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get().await
+        } else {
+            unreachable!("No block-when-expired command given");
+        }
+    }
+}
+
+impl BlockWhenAccountExpired {
+    async fn set(&self, block_when_expired: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_block_when_account_expired(block_when_expired)
+            .await?;
+        println!("Changed block when expired setting");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let block_when_expired = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .block_when_account_expired;
+        println!(
+            "Network traffic will be {} when the account runs out of time",
+            if block_when_expired {
+                "blocked"
+            } else {
+                "allowed"
+            }
+        );
+        Ok(())
+    }
+}
@@ -3,7 +3,7 @@ use clap::{value_t, values_t};
 
 use mullvad_management_interface::types;
 use mullvad_types::relay_constraints::{
-    BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint,
+    BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint, Ownership,
 };
 use talpid_types::net::openvpn::{self, SHADOWSOCKS_CIPHERS};
 
@@ -60,6 +60,19 @@ fn create_bridge_set_subcommand() -> clap::App<'static, 'static> {
                         .required(true),
                 ),
         )
+        .subcommand(
+            clap::SubCommand::with_name("ownership")
+                .about(
+                    "Select bridge relays based on whether the servers are owned by Mullvad or \
+                        rented. The 'list' command shows which bridges are owned by Mullvad.",
+                )
+                .arg(
+                    clap::Arg::with_name("ownership")
+                        .help("Servers to use, or 'any' for no preference.")
+                        .required(true)
+                        .possible_values(&["any", "owned", "rented"]),
+                ),
+        )
         .subcommand(location::get_subcommand().about(
             "Set country or city to select bridge relays from. Use the 'list' \
              command to show available alternatives.",
@@ -189,6 +202,9 @@ impl Bridge {
             ("provider", Some(provider_matches)) => {
                 Self::handle_set_bridge_provider(provider_matches).await
             }
+            ("ownership", Some(ownership_matches)) => {
+                Self::handle_set_bridge_ownership(ownership_matches).await
+            }
             ("custom", Some(custom_matches)) => {
                 Self::handle_bridge_set_custom_settings(custom_matches).await
             }
@@ -223,7 +239,12 @@ impl Bridge {
     }
 
     async fn handle_set_bridge_location(matches: &clap::ArgMatches<'_>) -> Result<()> {
-        Self::update_bridge_settings(Some(location::get_constraint_from_args(matches)), None).await
+        Self::update_bridge_settings(
+            Some(location::get_constraint_from_args(matches)),
+            None,
+            None,
+        )
+        .await
     }
 
     async fn handle_set_bridge_provider(matches: &clap::ArgMatches<'_>) -> Result<()> {
@@ -235,12 +256,24 @@ impl Bridge {
             providers
         };
 
-        Self::update_bridge_settings(None, Some(providers)).await
+        Self::update_bridge_settings(None, Some(providers), None).await
+    }
+
+    async fn handle_set_bridge_ownership(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let ownership = match matches.value_of("ownership").unwrap() {
+            "any" => Constraint::Any,
+            "owned" => Constraint::Only(Ownership::MullvadOwned),
+            "rented" => Constraint::Only(Ownership::Rented),
+            _ => unreachable!("invalid ownership"),
+        };
+
+        Self::update_bridge_settings(None, None, Some(ownership)).await
     }
 
     async fn update_bridge_settings(
         location: Option<types::RelayLocation>,
         providers: Option<Vec<String>>,
+        ownership: Option<Constraint<Ownership>>,
     ) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let settings = rpc.get_settings(()).await?.into_inner();
@@ -255,6 +288,9 @@ impl Bridge {
                     constraints.providers =
                         types::try_providers_constraint_from_proto(&new_providers).unwrap();
                 }
+                if let Some(new_ownership) = ownership {
+                    constraints.ownership = new_ownership;
+                }
                 constraints
             }
             _ => {
@@ -266,6 +302,7 @@ impl Bridge {
                 BridgeConstraints {
                     location,
                     providers,
+                    ownership: ownership.unwrap_or_default(),
                 }
             }
         };
@@ -445,8 +482,15 @@ impl Bridge {
                 );
                 for relay in &city.relays {
                     println!(
-                        "\t\t{} ({}) - hosted by {}",
-                        relay.hostname, relay.ipv4_addr_in, relay.provider
+                        "\t\t{} ({}) - hosted by {} ({})",
+                        relay.hostname,
+                        relay.ipv4_addr_in,
+                        relay.provider,
+                        if relay.owned {
+                            "Mullvad-owned"
+                        } else {
+                            "rented"
+                        }
                     );
                 }
             }
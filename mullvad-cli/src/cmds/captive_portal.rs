@@ -0,0 +1,56 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t;
+use mullvad_management_interface::types;
+use std::time::Duration;
+
+/// How long the captive portal is unlocked for unless otherwise specified, in seconds.
+const DEFAULT_UNLOCK_SECS: &str = "120";
+
+pub struct CaptivePortal;
+
+#[mullvad_management_interface::async_trait]
+impl Command for CaptivePortal {
+    fn name(&self) -> &'static str {
+        "captive-portal"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Log in to networks that require it while all other traffic is blocked")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("allow")
+                    .about(
+                        "Detect the captive portal of the current network and allow traffic to \
+                         it for a limited time, at most 10 minutes",
+                    )
+                    .arg(
+                        clap::Arg::with_name("seconds")
+                            .help("How long to allow traffic to the portal for")
+                            .default_value(DEFAULT_UNLOCK_SECS),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(allow_matches) = matches.subcommand_matches("allow") {
+            let seconds =
+                value_t!(allow_matches.value_of("seconds"), u64).unwrap_or_else(|e| e.exit());
+            self.allow(Duration::from_secs(seconds)).await
+        } else {
+            unreachable!("No captive-portal command given");
+        }
+    }
+}
+
+impl CaptivePortal {
+    async fn allow(&self, duration: Duration) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let portal = rpc
+            .allow_captive_portal(types::Duration::from(duration))
+            .await?
+            .into_inner();
+        println!("Allowing traffic to the captive portal at {}", portal);
+        Ok(())
+    }
+}
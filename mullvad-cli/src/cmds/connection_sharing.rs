@@ -0,0 +1,77 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t_or_exit;
+
+pub struct ConnectionSharing;
+
+#[mullvad_management_interface::async_trait]
+impl Command for ConnectionSharing {
+    fn name(&self) -> &'static str {
+        "connection-sharing"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Control whether devices connected through Internet Connection Sharing or \
+                 Mobile Hotspot may use the tunnel",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Change connection sharing setting")
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["allow", "block"]),
+                    ),
+            )
+            .subcommand(clap::SubCommand::with_name("get").about(
+                "Display the current connection sharing setting and whether connection \
+                     sharing is active",
+            ))
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let policy = value_t_or_exit!(set_matches.value_of("policy"), String);
+            self.set(policy == "allow").await
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get().await
+        } else {
+            unreachable!("No connection-sharing command given");
+        }
+    }
+}
+
+impl ConnectionSharing {
+    async fn set(&self, allow_connection_sharing: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_allow_connection_sharing(allow_connection_sharing)
+            .await?;
+        println!("Changed connection sharing setting");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let allow_connection_sharing = rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .allow_connection_sharing;
+        let is_active = rpc.is_connection_sharing_active(()).await?.into_inner();
+        println!(
+            "Connection sharing setting: {}",
+            if allow_connection_sharing {
+                "allow"
+            } else {
+                "block"
+            }
+        );
+        println!(
+            "Internet Connection Sharing or Mobile Hotspot: {}",
+            if is_active { "active" } else { "inactive" }
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,563 @@
+use super::relay;
+use crate::{format, new_rpc_client, Command, Error, Result};
+use mullvad_management_interface::types::{
+    api_trace, firewall_policy, health_report, state_machine_debug_info, tunnel_debug_info,
+    tunnel_statistics, Duration, Endpoint, FeatureFlagOverride, Timestamp, TrafficCounters,
+    TransportProtocol, TunnelStatistics, TunnelType,
+};
+
+pub struct Debug;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Debug {
+    fn name(&self) -> &'static str {
+        "debug"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Inspect the internal state of the daemon")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("firewall-policy")
+                    .about("Display the firewall policy that is currently being enforced"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("tunnel").about(
+                    "Display the internals of the tunnel and the routes applied by the daemon",
+                ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("statistics")
+                    .about("Display the bytes sent and received through the tunnel")
+                    .arg(
+                        clap::Arg::with_name("watch")
+                            .long("watch")
+                            .help("Keep displaying the statistics every second"),
+                    ),
+            )
+            .subcommand(clap::SubCommand::with_name("state-machine").about(
+                "Display the state of the tunnel state machine, its most recent transitions \
+                     and the timers it is waiting on",
+            ))
+            .subcommand(clap::SubCommand::with_name("doctor").about(
+                "Check the tunnel drivers, the permissions of the daemon, the firewall, the system \
+                 clock and the connection to the API",
+            ))
+            .subcommand(clap::SubCommand::with_name("cleanup-drivers").about(
+                "Remove Wintun adapters and network profiles left behind by crashes or earlier \
+                 versions. Only available on Windows, while disconnected",
+            ))
+            .subcommand(
+                clap::SubCommand::with_name("api")
+                    .about("Inspect the communication with the API")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(clap::SubCommand::with_name("trace").about(
+                        "Display the API requests made since the daemon started, oldest first",
+                    )),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("features")
+                    .about("Display or change the feature flags of the daemon")
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .about("Turn a feature on or off, or restore its default state")
+                            .arg(clap::Arg::with_name("name").required(true))
+                            .arg(
+                                clap::Arg::with_name("state")
+                                    .required(true)
+                                    .possible_values(&["on", "off", "default"]),
+                            ),
+                    ),
+            )
+            .subcommand(
+                relay::create_custom_endpoint_subcommand("connect-endpoint").about(
+                    "Connect to the given endpoint once, without changing the relay settings. \
+                     Requires that the daemon is started with MULLVAD_DEBUG_COMMANDS=1",
+                ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("firewall-policy", Some(_)) => self.firewall_policy().await,
+            ("tunnel", Some(_)) => self.tunnel().await,
+            ("statistics", Some(statistics_matches)) => {
+                self.statistics(statistics_matches.is_present("watch"))
+                    .await
+            }
+            ("state-machine", Some(_)) => self.state_machine().await,
+            ("doctor", Some(_)) => self.doctor().await,
+            ("cleanup-drivers", Some(_)) => self.cleanup_drivers().await,
+            ("api", Some(api_matches)) => match api_matches.subcommand() {
+                ("trace", Some(_)) => self.api_trace().await,
+                _ => unreachable!("No api command given"),
+            },
+            ("features", Some(features_matches)) => match features_matches.subcommand() {
+                ("set", Some(set_matches)) => {
+                    let name = set_matches.value_of("name").unwrap();
+                    let enabled = match set_matches.value_of("state").unwrap() {
+                        "on" => Some(true),
+                        "off" => Some(false),
+                        _ => None,
+                    };
+                    self.set_feature_flag(name, enabled).await
+                }
+                _ => self.list_feature_flags().await,
+            },
+            ("connect-endpoint", Some(endpoint_matches)) => {
+                self.connect_endpoint(endpoint_matches).await
+            }
+            _ => unreachable!("No debug command given"),
+        }
+    }
+}
+
+impl Debug {
+    async fn firewall_policy(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let policy = rpc
+            .get_firewall_policy(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to obtain firewall policy", error))?
+            .into_inner();
+
+        let state = match firewall_policy::State::from_i32(policy.state) {
+            Some(firewall_policy::State::None) => "none (not blocking any traffic)",
+            Some(firewall_policy::State::Connecting) => "connecting",
+            Some(firewall_policy::State::Connected) => "connected",
+            Some(firewall_policy::State::Blocked) => "blocked",
+            None => "unknown",
+        };
+        println!("Policy: {}", state);
+        if let Some(endpoint) = &policy.peer_endpoint {
+            println!("Relay endpoint: {}", format_endpoint(endpoint));
+        }
+        if let Some(endpoint) = &policy.allowed_endpoint {
+            println!("Allowed endpoint: {}", format_endpoint(endpoint));
+        }
+        if !policy.tunnel_interface.is_empty() {
+            println!("Tunnel interface: {}", policy.tunnel_interface);
+        }
+        println!(
+            "Local network sharing: {}",
+            if policy.allow_lan { "allow" } else { "block" }
+        );
+        print_list("Local networks", &policy.lan_networks);
+        print_list("DNS servers", &policy.dns_servers);
+        print_list("Excluded networks", &policy.excluded_networks);
+
+        Ok(())
+    }
+
+    async fn tunnel(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let info = rpc
+            .get_tunnel_debug_info(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to obtain tunnel internals", error))?
+            .into_inner();
+
+        if !info.has_tunnel {
+            println!("Tunnel: none");
+        } else {
+            let tunnel_type = match TunnelType::from_i32(info.tunnel_type) {
+                Some(TunnelType::Wireguard) => "WireGuard",
+                Some(TunnelType::Openvpn) => "OpenVPN",
+                None => "unknown",
+            };
+            println!("Tunnel: {}", tunnel_type);
+        }
+
+        if let Some(adapter) = &info.adapter {
+            println!(
+                "Interface: {} ({}, {})",
+                adapter.name,
+                adapter.backend,
+                if adapter.is_running {
+                    "running"
+                } else {
+                    "stopped"
+                }
+            );
+            if let Some(mtu) = adapter.mtu {
+                println!("\tMTU: {}", mtu);
+            }
+            if !adapter.addresses.is_empty() {
+                println!("\tAddresses: {}", adapter.addresses.join(", "));
+            }
+            if !adapter.fallback_reason.is_empty() {
+                println!("\tFallback reason: {}", adapter.fallback_reason);
+            }
+        }
+
+        if !info.peers.is_empty() {
+            println!("Peers:");
+            for peer in &info.peers {
+                print_peer(peer);
+            }
+        }
+
+        if !info.obfuscators.is_empty() {
+            println!("Obfuscators:");
+            for obfuscator in &info.obfuscators {
+                let kind = match tunnel_debug_info::obfuscator::Kind::from_i32(obfuscator.kind) {
+                    Some(tunnel_debug_info::obfuscator::Kind::Udp2tcp) => "UDP-over-TCP",
+                    Some(tunnel_debug_info::obfuscator::Kind::Socks5) => "SOCKS5",
+                    Some(tunnel_debug_info::obfuscator::Kind::Plugin) => "Transport plugin",
+                    None => "unknown",
+                };
+                println!(
+                    "\t{} {} -> {} ({})",
+                    kind,
+                    obfuscator.local_address,
+                    obfuscator.remote_address,
+                    if obfuscator.is_running {
+                        "running"
+                    } else {
+                        "stopped"
+                    }
+                );
+            }
+        }
+
+        if !info.bound_interface.is_empty() {
+            println!("Sockets bound to interface: {}", info.bound_interface);
+        }
+
+        let routes: Vec<_> = info.routes.iter().map(format_route).collect();
+        print_list("Routes", &routes);
+
+        Ok(())
+    }
+
+    async fn statistics(&self, watch: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        if !watch {
+            let statistics = rpc
+                .get_tunnel_statistics(())
+                .await
+                .map_err(|error| Error::RpcFailedExt("Failed to obtain tunnel statistics", error))?
+                .into_inner();
+            print_statistics(&statistics);
+            return Ok(());
+        }
+
+        let mut stream = rpc
+            .stream_tunnel_statistics(Duration {
+                seconds: 1,
+                nanos: 0,
+            })
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to stream tunnel statistics", error))?
+            .into_inner();
+        while let Some(statistics) = stream
+            .message()
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to stream tunnel statistics", error))?
+        {
+            print_statistics(&statistics);
+        }
+        Ok(())
+    }
+
+    async fn state_machine(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let info = rpc
+            .get_tunnel_state_machine_debug_info(())
+            .await
+            .map_err(|error| {
+                Error::RpcFailedExt("Failed to obtain tunnel state machine internals", error)
+            })?
+            .into_inner();
+
+        println!("State: {}", info.state);
+        if let Some(state_entered) = &info.state_entered {
+            println!("\tEntered: {}", format_timestamp(state_entered));
+        }
+        if let Some(retry_attempt) = info.retry_attempt {
+            println!("\tRetry attempt: {}", retry_attempt);
+        }
+
+        let timers: Vec<_> = info
+            .pending_timers
+            .iter()
+            .map(|timer| {
+                let deadline = timer
+                    .deadline
+                    .as_ref()
+                    .map(format_timestamp)
+                    .unwrap_or_default();
+                format!("{} at {}", timer.action, deadline)
+            })
+            .collect();
+        print_list("Pending timers", &timers);
+
+        let transitions: Vec<_> = info.transitions.iter().map(format_transition).collect();
+        print_list("Transitions", &transitions);
+
+        Ok(())
+    }
+
+    async fn doctor(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let report = rpc
+            .run_health_check(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to run health check", error))?
+            .into_inner();
+
+        for check in &report.checks {
+            let status = match health_report::check::Status::from_i32(check.status) {
+                Some(health_report::check::Status::Ok) => "OK",
+                Some(health_report::check::Status::Warning) => "warning",
+                Some(health_report::check::Status::Failed) => "FAILED",
+                Some(health_report::check::Status::Skipped) => "skipped",
+                None => "unknown",
+            };
+            println!("{}: {}", check.name, status);
+            println!("\t{}", check.details);
+        }
+        Ok(())
+    }
+
+    async fn cleanup_drivers(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let cleanup = rpc
+            .cleanup_drivers(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to clean up drivers", error))?
+            .into_inner();
+
+        if cleanup.adapters.is_empty() && cleanup.network_profiles.is_empty() {
+            println!("Nothing to clean up");
+            return Ok(());
+        }
+        print_list("Removed adapters", &cleanup.adapters);
+        print_list("Removed network profiles", &cleanup.network_profiles);
+        if cleanup.reboot_required {
+            println!("Restart Windows to finish removing the adapters");
+        }
+        Ok(())
+    }
+
+    async fn list_feature_flags(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let flags = rpc
+            .get_feature_flags(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to obtain feature flags", error))?
+            .into_inner()
+            .flags;
+        for flag in &flags {
+            println!(
+                "{}: {}{}",
+                flag.name,
+                if flag.enabled { "on" } else { "off" },
+                if flag.overridden { "" } else { " (default)" }
+            );
+            println!("\t{}", flag.description);
+        }
+        Ok(())
+    }
+
+    async fn set_feature_flag(&self, name: &str, enabled: Option<bool>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_feature_flag(FeatureFlagOverride {
+            name: name.to_owned(),
+            enabled,
+        })
+        .await
+        .map_err(|error| Error::RpcFailedExt("Failed to set feature flag", error))?;
+        println!("Updated feature flag");
+        Ok(())
+    }
+
+    async fn connect_endpoint(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let endpoint = relay::read_custom_endpoint(matches);
+        let mut rpc = new_rpc_client().await?;
+        rpc.connect_to_endpoint(endpoint)
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to connect to the endpoint", error))?;
+        Ok(())
+    }
+
+    async fn api_trace(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let trace = rpc
+            .get_api_trace(())
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to obtain API trace", error))?
+            .into_inner();
+
+        if trace.entries.is_empty() {
+            println!("No API requests have been made");
+        }
+        for entry in &trace.entries {
+            print_api_trace_entry(entry);
+        }
+        Ok(())
+    }
+}
+
+fn print_api_trace_entry(entry: &api_trace::Entry) {
+    let timestamp = entry
+        .timestamp
+        .as_ref()
+        .map(format_timestamp)
+        .unwrap_or_default();
+    let duration_ms = entry
+        .duration
+        .as_ref()
+        .map(|duration| duration.seconds * 1000 + i64::from(duration.nanos) / 1_000_000)
+        .unwrap_or(0);
+
+    let mut formatted = format!("{} {} {}", timestamp, entry.method, entry.path);
+    if !entry.address.is_empty() {
+        formatted.push_str(&format!(" via {}", entry.address));
+    }
+    if !entry.uses_api_sni {
+        formatted.push_str(" (not the API)");
+    }
+    formatted.push_str(&format!(
+        ": {} after {} ms",
+        if entry.success { "response" } else { "error" },
+        duration_ms
+    ));
+    if entry.attempt > 1 {
+        formatted.push_str(&format!(", attempt {}", entry.attempt));
+    }
+    println!("{}", formatted);
+    println!("\t{}", entry.result);
+}
+
+fn print_peer(peer: &tunnel_debug_info::Peer) {
+    println!("\t{}", base64::encode(&peer.public_key));
+    println!("\t\tEndpoint: {}", peer.endpoint);
+    if !peer.allowed_ips.is_empty() {
+        println!("\t\tAllowed IPs: {}", peer.allowed_ips.join(", "));
+    }
+    match &peer.last_handshake {
+        Some(age) => println!("\t\tLatest handshake: {}s ago", age.seconds),
+        None => println!("\t\tLatest handshake: never"),
+    }
+    println!(
+        "\t\tTransfer: {} bytes received, {} bytes sent",
+        peer.rx_bytes, peer.tx_bytes
+    );
+}
+
+fn print_statistics(statistics: &TunnelStatistics) {
+    println!("Session: {}", format_traffic(statistics.session.as_ref()));
+    println!("Total: {}", format_traffic(statistics.cumulative.as_ref()));
+
+    let timings: Vec<_> = statistics
+        .operation_timings
+        .iter()
+        .map(format_operation_timing)
+        .collect();
+    print_list("Backend operations", &timings);
+
+    if let Some(changes) = &statistics.endpoint_changes {
+        println!("Endpoint changes: {}", changes.count);
+        if let Some(last_change) = &changes.last_change {
+            println!("\tLast change: {}", format_timestamp(last_change));
+        }
+        println!("\tSocket rebinds: {}", changes.socket_rebinds);
+        println!(
+            "\tHandshake retransmissions: {}",
+            changes.handshake_retransmissions
+        );
+    }
+}
+
+fn format_traffic(counters: Option<&TrafficCounters>) -> String {
+    let counters = counters.cloned().unwrap_or_default();
+    format!(
+        "{} bytes received, {} bytes sent",
+        counters.rx_bytes, counters.tx_bytes
+    )
+}
+
+fn format_route(route: &tunnel_debug_info::Route) -> String {
+    let mut formatted = route.destination.clone();
+    if route.gateway.is_empty() && route.interface.is_empty() {
+        formatted.push_str(" via default route");
+    } else {
+        formatted.push_str(" via");
+        if !route.gateway.is_empty() {
+            formatted.push_str(&format!(" {}", route.gateway));
+        }
+        if !route.interface.is_empty() {
+            formatted.push_str(&format!(" dev {}", route.interface));
+        }
+    }
+    if let Some(metric) = route.metric {
+        formatted.push_str(&format!(" metric {}", metric));
+    }
+    if let Some(table_id) = route.table_id {
+        formatted.push_str(&format!(" table {}", table_id));
+    }
+    formatted
+}
+
+fn format_transition(transition: &state_machine_debug_info::Transition) -> String {
+    let time = transition
+        .time
+        .as_ref()
+        .map(format_timestamp)
+        .unwrap_or_default();
+    let mut formatted = format!("{} {} -> {}", time, transition.from, transition.to);
+    if !transition.details.is_empty() {
+        formatted.push_str(&format!(" ({})", transition.details));
+    }
+    formatted.push_str(&format!(", after {}", transition.trigger));
+    formatted
+}
+
+fn format_timestamp(timestamp: &Timestamp) -> String {
+    let ndt = chrono::NaiveDateTime::from_timestamp(timestamp.seconds, timestamp.nanos as u32);
+    let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
+    utc.with_timezone(&chrono::Local)
+        .format("%Y-%m-%d %H:%M:%S%.3f")
+        .to_string()
+}
+
+fn format_operation_timing(timing: &tunnel_statistics::OperationTiming) -> String {
+    let millis = |duration: &Option<Duration>| {
+        duration
+            .as_ref()
+            .map(|duration| {
+                duration.seconds as f64 * 1000.0 + f64::from(duration.nanos) / 1_000_000.0
+            })
+            .unwrap_or(0.0)
+    };
+    format!(
+        "{}: {} calls, last {:.1} ms, average {:.1} ms, max {:.1} ms",
+        timing.operation,
+        timing.count,
+        millis(&timing.last),
+        millis(&timing.average),
+        millis(&timing.max),
+    )
+}
+
+fn format_endpoint(endpoint: &Endpoint) -> String {
+    format!(
+        "{} over {}",
+        endpoint.address,
+        format::format_protocol(
+            TransportProtocol::from_i32(endpoint.protocol).expect("invalid transport protocol")
+        ),
+    )
+}
+
+fn print_list(title: &str, items: &[String]) {
+    if items.is_empty() {
+        return;
+    }
+    println!("{}:", title);
+    for item in items {
+        println!("\t{}", item);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{new_rpc_client, Command, Result};
 use mullvad_management_interface::types;
 use mullvad_types::settings::{DnsOptions, DnsState};
-use std::convert::TryInto;
+use std::{convert::TryInto, net::IpAddr};
 
 pub struct Dns;
 
@@ -42,6 +42,24 @@ impl Command for Dns {
                                     .long("block-malware")
                                     .takes_value(false)
                                     .help("Block domains known to be used by malware"),
+                            )
+                            .arg(
+                                clap::Arg::with_name("block adult content")
+                                    .long("block-adult-content")
+                                    .takes_value(false)
+                                    .help("Block domains known to host adult content"),
+                            )
+                            .arg(
+                                clap::Arg::with_name("block gambling")
+                                    .long("block-gambling")
+                                    .takes_value(false)
+                                    .help("Block domains known to be used for gambling"),
+                            )
+                            .arg(
+                                clap::Arg::with_name("dns over tls")
+                                    .long("dns-over-tls")
+                                    .takes_value(false)
+                                    .help("Reach the resolvers on the relay using DNS-over-TLS"),
                             ),
                     )
                     .subcommand(
@@ -51,6 +69,11 @@ impl Command for Dns {
                                 clap::Arg::with_name("servers")
                                     .multiple(true)
                                     .help("One or more IP addresses pointing to DNS resolvers.")
+                                    .validator(|server| {
+                                        server.parse::<IpAddr>().map(|_| ()).map_err(|_| {
+                                            format!("'{}' is not a valid IP address", server)
+                                        })
+                                    })
                                     .required(true),
                             ),
                     ),
@@ -61,11 +84,14 @@ impl Command for Dns {
         match matches.subcommand() {
             ("set", Some(matches)) => match matches.subcommand() {
                 ("default", Some(matches)) => {
-                    self.set_default(
-                        matches.is_present("block ads"),
-                        matches.is_present("block trackers"),
-                        matches.is_present("block malware"),
-                    )
+                    self.set_default(types::DefaultDnsOptions {
+                        block_ads: matches.is_present("block ads"),
+                        block_trackers: matches.is_present("block trackers"),
+                        block_malware: matches.is_present("block malware"),
+                        block_adult_content: matches.is_present("block adult content"),
+                        block_gambling: matches.is_present("block gambling"),
+                        dns_over_tls: matches.is_present("dns over tls"),
+                    })
                     .await
                 }
                 ("custom", Some(matches)) => {
@@ -80,21 +106,12 @@ impl Command for Dns {
 }
 
 impl Dns {
-    async fn set_default(
-        &self,
-        block_ads: bool,
-        block_trackers: bool,
-        block_malware: bool,
-    ) -> Result<()> {
+    async fn set_default(&self, default_options: types::DefaultDnsOptions) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let settings = rpc.get_settings(()).await?.into_inner();
         rpc.set_dns_options(types::DnsOptions {
             state: types::dns_options::DnsState::Default as i32,
-            default_options: Some(types::DefaultDnsOptions {
-                block_ads,
-                block_trackers,
-                block_malware,
-            }),
+            default_options: Some(default_options),
             ..settings.tunnel_options.unwrap().dns_options.unwrap()
         })
         .await?;
@@ -103,17 +120,21 @@ impl Dns {
     }
 
     async fn set_custom(&self, servers: Option<Vec<String>>) -> Result<()> {
+        let servers = servers.unwrap_or_default();
         let mut rpc = new_rpc_client().await?;
         let settings = rpc.get_settings(()).await?.into_inner();
         rpc.set_dns_options(types::DnsOptions {
             state: types::dns_options::DnsState::Custom as i32,
             custom_options: Some(types::CustomDnsOptions {
-                addresses: servers.unwrap_or_default(),
+                addresses: servers.clone(),
             }),
             ..settings.tunnel_options.unwrap().dns_options.unwrap()
         })
         .await?;
-        println!("Updated DNS settings");
+        println!(
+            "Updated DNS settings. Only {} will be reachable for DNS while connected",
+            servers.join(", ")
+        );
         Ok(())
     }
 
@@ -136,6 +157,12 @@ impl Dns {
                 println!("Block ads: {}", options.default_options.block_ads);
                 println!("Block trackers: {}", options.default_options.block_trackers);
                 println!("Block malware: {}", options.default_options.block_malware);
+                println!(
+                    "Block adult content: {}",
+                    options.default_options.block_adult_content
+                );
+                println!("Block gambling: {}", options.default_options.block_gambling);
+                println!("DNS-over-TLS: {}", options.default_options.dns_over_tls);
             }
             DnsState::Custom => {
                 println!("Custom DNS: yes\nServers:");
@@ -0,0 +1,110 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t;
+use mullvad_management_interface::types;
+use std::{
+    convert::TryFrom,
+    net::{IpAddr, SocketAddr},
+};
+use talpid_types::net::{openvpn, proxy::EntryProxy as EntryProxySettings};
+
+pub struct EntryProxy;
+
+#[mullvad_management_interface::async_trait]
+impl Command for EntryProxy {
+    fn name(&self) -> &'static str {
+        "entry-proxy"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Manage the SOCKS5 proxy that all traffic to the relay is sent through. \
+                 The relay is always connected to over TCP while a proxy is set",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Connect to the relay through a SOCKS5 proxy")
+                    .arg(
+                        clap::Arg::with_name("ip")
+                            .help("Specifies the IP of the proxy server")
+                            .required(true)
+                            .index(1),
+                    )
+                    .arg(
+                        clap::Arg::with_name("port")
+                            .help("Specifies the port the proxy server is listening on")
+                            .required(true)
+                            .index(2),
+                    )
+                    .arg(
+                        clap::Arg::with_name("username")
+                            .help("Specifies the username for proxy authentication")
+                            .requires("password")
+                            .index(3),
+                    )
+                    .arg(
+                        clap::Arg::with_name("password")
+                            .help("Specifies the password for proxy authentication")
+                            .index(4),
+                    ),
+            )
+            .subcommand(clap::SubCommand::with_name("unset").about("Connect to the relay directly"))
+            .subcommand(clap::SubCommand::with_name("get").about("Display the current entry proxy"))
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("set", Some(set_matches)) => {
+                let ip = value_t!(set_matches.value_of("ip"), IpAddr).unwrap_or_else(|e| e.exit());
+                let port = value_t!(set_matches.value_of("port"), u16).unwrap_or_else(|e| e.exit());
+                let auth = match (
+                    set_matches.value_of("username"),
+                    set_matches.value_of("password"),
+                ) {
+                    (Some(username), Some(password)) => Some(openvpn::ProxyAuth {
+                        username: username.to_string(),
+                        password: password.to_string(),
+                    }),
+                    _ => None,
+                };
+                let proxy = EntryProxySettings {
+                    address: SocketAddr::new(ip, port),
+                    auth,
+                };
+                Self::set(Some(proxy)).await
+            }
+            ("unset", _) => Self::set(None).await,
+            ("get", _) => Self::get().await,
+            _ => unreachable!("unhandled command"),
+        }
+    }
+}
+
+impl EntryProxy {
+    async fn set(proxy: Option<EntryProxySettings>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_entry_proxy(types::EntryProxySettings {
+            proxy: proxy.as_ref().map(types::EntryProxy::from),
+        })
+        .await?;
+        match proxy {
+            Some(proxy) => println!("Connecting through {}", proxy),
+            None => println!("Connecting to the relay directly"),
+        }
+        Ok(())
+    }
+
+    async fn get() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        match settings
+            .entry_proxy
+            .map(|proxy| EntryProxySettings::try_from(proxy).unwrap())
+        {
+            Some(proxy) => println!("Entry proxy: {}", proxy),
+            None => println!("Entry proxy: none"),
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{new_rpc_client, Command, Result};
-use clap::value_t_or_exit;
+use clap::{value_t_or_exit, values_t_or_exit};
+use mullvad_management_interface::types::{BlockedStatePolicy, LanAllowList};
 
 pub struct Lan;
 
@@ -26,6 +27,39 @@ impl Command for Lan {
                 clap::SubCommand::with_name("get")
                     .about("Display the current local network sharing setting"),
             )
+            .subcommand(
+                clap::SubCommand::with_name("allow-list")
+                    .about("Limit local network sharing to specific private networks")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .about("Only allow the given private networks")
+                            .arg(
+                                clap::Arg::with_name("networks")
+                                    .help("Private networks in CIDR notation")
+                                    .required(true)
+                                    .multiple(true),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("clear")
+                            .about("Allow all private networks again"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("mdns")
+                    .about("Control whether mDNS is allowed while all other traffic is blocked")
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .about("Change the blocked state mDNS setting")
+                            .arg(
+                                clap::Arg::with_name("policy")
+                                    .required(true)
+                                    .possible_values(&["allow", "block"]),
+                            ),
+                    ),
+            )
     }
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
@@ -34,6 +68,22 @@ impl Command for Lan {
             self.set(allow_lan == "allow").await
         } else if let Some(_matches) = matches.subcommand_matches("get") {
             self.get().await
+        } else if let Some(allow_list_matches) = matches.subcommand_matches("allow-list") {
+            if let Some(set_matches) = allow_list_matches.subcommand_matches("set") {
+                let networks = values_t_or_exit!(set_matches.values_of("networks"), String);
+                self.set_allow_list(networks).await
+            } else if let Some(_matches) = allow_list_matches.subcommand_matches("clear") {
+                self.clear_allow_list().await
+            } else {
+                unreachable!("No allow-list command given");
+            }
+        } else if let Some(mdns_matches) = matches.subcommand_matches("mdns") {
+            if let Some(set_matches) = mdns_matches.subcommand_matches("set") {
+                let allow_mdns = value_t_or_exit!(set_matches.value_of("policy"), String);
+                self.set_allow_mdns(allow_mdns == "allow").await
+            } else {
+                unreachable!("No mdns command given");
+            }
         } else {
             unreachable!("No lan command given");
         }
@@ -48,12 +98,51 @@ impl Lan {
         Ok(())
     }
 
+    async fn set_allow_list(&self, networks: Vec<String>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_lan_allow_list(LanAllowList { networks }).await?;
+        println!("Changed local network allow list");
+        Ok(())
+    }
+
+    async fn clear_allow_list(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.clear_lan_allow_list(()).await?;
+        println!("Cleared local network allow list");
+        Ok(())
+    }
+
+    async fn set_allow_mdns(&self, allow_mdns: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_blocked_state_policy(BlockedStatePolicy { allow_mdns })
+            .await?;
+        println!("Changed blocked state mDNS setting");
+        Ok(())
+    }
+
     async fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        let allow_lan = rpc.get_settings(()).await?.into_inner().allow_lan;
+        let settings = rpc.get_settings(()).await?.into_inner();
         println!(
             "Local network sharing setting: {}",
-            if allow_lan { "allow" } else { "block" }
+            if settings.allow_lan { "allow" } else { "block" }
+        );
+        match settings.lan_allow_list {
+            Some(allow_list) => {
+                println!("Allowed local networks:");
+                for network in allow_list.networks {
+                    println!("    {}", network);
+                }
+            }
+            None => println!("Allowed local networks: all private networks"),
+        }
+        let allow_mdns = settings
+            .blocked_state_policy
+            .map(|policy| policy.allow_mdns)
+            .unwrap_or(false);
+        println!(
+            "mDNS while blocked: {}",
+            if allow_mdns { "allow" } else { "block" }
         );
         Ok(())
     }
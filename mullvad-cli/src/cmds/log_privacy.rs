@@ -0,0 +1,79 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t_or_exit;
+use mullvad_management_interface::types::{log_privacy::Level, LogPrivacy as LogPrivacySetting};
+
+pub struct LogPrivacy;
+
+#[mullvad_management_interface::async_trait]
+impl Command for LogPrivacy {
+    fn name(&self) -> &'static str {
+        "log-privacy"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Control how much identifying information is removed from the daemon and tunnel \
+                 logs. In aggressive mode, all IP addresses, relay hostnames, account numbers \
+                 and WireGuard keys are removed before log messages are written",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Change the log privacy setting")
+                    .arg(
+                        clap::Arg::with_name("level")
+                            .required(true)
+                            .possible_values(&["standard", "aggressive"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get").about("Display the current log privacy setting"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let level = value_t_or_exit!(set_matches.value_of("level"), String);
+            self.set(if level == "aggressive" {
+                Level::Aggressive
+            } else {
+                Level::Standard
+            })
+            .await
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get().await
+        } else {
+            unreachable!("No log-privacy command given");
+        }
+    }
+}
+
+impl LogPrivacy {
+    async fn set(&self, level: Level) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_log_privacy(LogPrivacySetting {
+            level: i32::from(level),
+        })
+        .await?;
+        println!("Changed log privacy setting");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.get_settings(()).await?.into_inner();
+        let level = settings
+            .log_privacy
+            .and_then(|log_privacy| Level::from_i32(log_privacy.level))
+            .unwrap_or(Level::Standard);
+        println!(
+            "Log privacy: {}",
+            match level {
+                Level::Standard => "standard",
+                Level::Aggressive => "aggressive",
+            }
+        );
+        Ok(())
+    }
+}
@@ -10,24 +10,46 @@ pub use self::auto_connect::AutoConnect;
 mod beta_program;
 pub use self::beta_program::BetaProgram;
 
+mod block_when_account_expired;
+pub use self::block_when_account_expired::BlockWhenAccountExpired;
+
 mod block_when_disconnected;
 pub use self::block_when_disconnected::BlockWhenDisconnected;
 
 mod bridge;
 pub use self::bridge::Bridge;
 
+#[cfg(target_os = "macos")]
+mod captive_portal;
+#[cfg(target_os = "macos")]
+pub use self::captive_portal::CaptivePortal;
+
 mod connect;
 pub use self::connect::Connect;
 
+#[cfg(windows)]
+mod connection_sharing;
+#[cfg(windows)]
+pub use self::connection_sharing::ConnectionSharing;
+
+mod debug;
+pub use self::debug::Debug;
+
 mod disconnect;
 pub use self::disconnect::Disconnect;
 
 mod dns;
 pub use self::dns::Dns;
 
+mod entry_proxy;
+pub use self::entry_proxy::EntryProxy;
+
 mod lan;
 pub use self::lan::Lan;
 
+mod log_privacy;
+pub use self::log_privacy::LogPrivacy;
+
 mod reconnect;
 pub use self::reconnect::Reconnect;
 
@@ -37,14 +59,23 @@ pub use self::relay::Relay;
 mod reset;
 pub use self::reset::Reset;
 
-#[cfg(any(target_os = "linux", windows))]
+mod settings;
+pub use self::settings::Settings;
+
+#[cfg(not(target_os = "android"))]
 mod split_tunnel;
-#[cfg(any(target_os = "linux", windows))]
+#[cfg(not(target_os = "android"))]
 pub use self::split_tunnel::SplitTunnel;
 
 mod status;
 pub use self::status::Status;
 
+mod strict_kill_switch;
+pub use self::strict_kill_switch::StrictKillSwitch;
+
+mod system_proxy;
+pub use self::system_proxy::SystemProxy;
+
 mod tunnel;
 pub use self::tunnel::Tunnel;
 
@@ -57,18 +88,29 @@ pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
         Box::new(Account),
         Box::new(AutoConnect),
         Box::new(BetaProgram),
+        Box::new(BlockWhenAccountExpired),
         Box::new(BlockWhenDisconnected),
         Box::new(Bridge),
+        #[cfg(target_os = "macos")]
+        Box::new(CaptivePortal),
         Box::new(Connect),
+        #[cfg(windows)]
+        Box::new(ConnectionSharing),
+        Box::new(Debug),
         Box::new(Disconnect),
         Box::new(Dns),
+        Box::new(EntryProxy),
         Box::new(Reconnect),
         Box::new(Lan),
+        Box::new(LogPrivacy),
         Box::new(Relay),
         Box::new(Reset),
-        #[cfg(any(target_os = "linux", windows))]
+        Box::new(Settings),
+        #[cfg(not(target_os = "android"))]
         Box::new(SplitTunnel),
         Box::new(Status),
+        Box::new(StrictKillSwitch),
+        Box::new(SystemProxy),
         Box::new(Tunnel),
         Box::new(Version),
     ];
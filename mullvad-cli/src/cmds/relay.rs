@@ -2,14 +2,15 @@ use crate::{location, new_rpc_client, Command, Error, Result};
 use clap::{value_t, values_t};
 use itertools::Itertools;
 use std::{
+    collections::HashMap,
     convert::TryFrom,
-    io::{self, BufRead},
+    io::{self, BufRead, Write},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     str::FromStr,
 };
 
-use mullvad_management_interface::{types, ManagementServiceClient};
-use mullvad_types::relay_constraints::{Constraint, RelaySettings};
+use mullvad_management_interface::{types, Code, ManagementServiceClient};
+use mullvad_types::relay_constraints::{Constraint, RelayConstraints, RelaySettings};
 use talpid_types::net::all_of_the_internet;
 
 pub struct Relay;
@@ -31,80 +32,8 @@ impl Command for Relay {
                     )
                     .setting(clap::AppSettings::SubcommandRequiredElseHelp)
                     .subcommand(
-                        clap::SubCommand::with_name("custom")
+                        create_custom_endpoint_subcommand("custom")
                             .about("Set a custom VPN relay")
-                            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
-                            .subcommand(clap::SubCommand::with_name("wireguard")
-                                .arg(
-                                    clap::Arg::with_name("host")
-                                        .help("Hostname or IP")
-                                        .required(true),
-                                )
-                                .arg(
-                                    clap::Arg::with_name("port")
-                                        .help("Remote network port")
-                                        .required(true),
-                                )
-                                .arg(
-                                    clap::Arg::with_name("peer-pubkey")
-                                        .help("Base64 encoded peer public key")
-                                        .required(true),
-                                )
-                                .arg(
-                                    clap::Arg::with_name("v4-gateway")
-                                        .help("IPv4 gateway address")
-                                        .required(true),
-                                )
-                                .arg(
-                                    clap::Arg::with_name("addr")
-                                        .help("Local address of wireguard tunnel")
-                                        .required(true)
-                                        .multiple(true),
-                                )
-                                .arg(
-                                    clap::Arg::with_name("protocol")
-                                        .help("Transport protocol. If TCP is selected, traffic is \
-                                               sent over TCP using a udp-over-tcp proxy")
-                                        .long("protocol")
-                                        .default_value("udp")
-                                        .possible_values(&["udp", "tcp"]),
-                                )
-                                .arg(
-                                    clap::Arg::with_name("v6-gateway")
-                                        .help("IPv6 gateway address")
-                                        .long("v6-gateway")
-                                        .takes_value(true),
-                                )
-                            )
-                            .subcommand(clap::SubCommand::with_name("openvpn")
-                                .arg(
-                                    clap::Arg::with_name("host")
-                                        .help("Hostname or IP")
-                                        .required(true),
-                                )
-                                .arg(
-                                    clap::Arg::with_name("port")
-                                        .help("Remote network port")
-                                        .required(true),
-                                )
-                                .arg(
-                                    clap::Arg::with_name("username")
-                                        .help("Username to be used with the OpenVpn relay")
-                                        .required(true),
-                                )
-                                .arg(
-                                    clap::Arg::with_name("password")
-                                        .help("Password to be used with the OpenVpn relay")
-                                        .required(true),
-                                )
-                                .arg(
-                                    clap::Arg::with_name("protocol")
-                                        .help("Transport protocol")
-                                        .long("protocol")
-                                        .default_value("udp")
-                                        .possible_values(&["udp", "tcp"]),
-                                )
-                            )
                     )
                     .subcommand(
                         location::get_subcommand()
@@ -132,10 +61,31 @@ impl Command for Relay {
                                 .required(true)
                             )
                     )
+                    .subcommand(
+                        clap::SubCommand::with_name("ownership")
+                            .about("Select relays based on whether the servers are owned by \
+                                   Mullvad or rented. The 'list' command shows which relays \
+                                   are owned by Mullvad.")
+                            .arg(
+                                clap::Arg::with_name("ownership")
+                                .help("Servers to use, or 'any' for no preference.")
+                                .required(true)
+                                .possible_values(&["any", "owned", "rented"])
+                            )
+                    )
                     .subcommand(
                         clap::SubCommand::with_name("tunnel")
                             .about("Set tunnel protocol-specific constraints.")
-                            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                            .setting(clap::AppSettings::ArgRequiredElseHelp)
+                            .arg(
+                                clap::Arg::with_name("prefer low latency")
+                                    .help("Whether relays with a low measured latency should be \
+                                           more likely to be selected among those matching the \
+                                           constraints")
+                                    .long("prefer-low-latency")
+                                    .possible_values(&["on", "off"])
+                                    .takes_value(true),
+                            )
                             .subcommand(
                                 clap::SubCommand::with_name("openvpn")
                                     .about("Set OpenVPN-specific constraints")
@@ -164,6 +114,13 @@ impl Command for Relay {
                                             .long("port")
                                             .takes_value(true),
                                     )
+                                    .arg(
+                                        clap::Arg::with_name("port range")
+                                            .help("Ports to select from when the port is 'any'. \
+                                                   Either 'any' or a range such as '1-1024'")
+                                            .long("port-range")
+                                            .takes_value(true),
+                                    )
                                     .arg(
                                         clap::Arg::with_name("transport protocol")
                                             .help("Transport protocol. If TCP is selected, traffic is \
@@ -201,7 +158,89 @@ impl Command for Relay {
             )
             .subcommand(clap::SubCommand::with_name("get"))
             .subcommand(
-                clap::SubCommand::with_name("list").about("List available countries and cities"),
+                clap::SubCommand::with_name("fallback")
+                    .about(
+                        "Manage relay constraints to fall back to, in order, when connecting \
+                         repeatedly fails",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        clap::SubCommand::with_name("add")
+                            .about("Add a fallback tier after the existing ones")
+                            .arg(
+                                clap::Arg::with_name("country")
+                                    .help("The two letter country code, or 'any' for no preference.")
+                                    .required(true)
+                                    .index(1)
+                                    .validator(location::country_code_validator),
+                            )
+                            .arg(
+                                clap::Arg::with_name("city")
+                                    .help("The three letter city code")
+                                    .index(2)
+                                    .validator(location::city_code_validator),
+                            )
+                            .arg(
+                                clap::Arg::with_name("hostname")
+                                    .help("The hostname")
+                                    .index(3)
+                                    .requires("city"),
+                            )
+                            .arg(
+                                clap::Arg::with_name("tunnel protocol")
+                                    .help("The tunnel protocol to use in this tier")
+                                    .long("tunnel-protocol")
+                                    .default_value("any")
+                                    .possible_values(&["any", "wireguard", "openvpn"]),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("list").about("Display the fallback tiers"),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("clear").about("Remove all fallback tiers"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("List available countries and cities")
+                    .arg(
+                        clap::Arg::with_name("locale")
+                            .help("Language of country and city names, e.g. 'sv' or 'zh-TW'")
+                            .long("locale")
+                            .takes_value(true),
+                    )
+                    .arg(
+                        clap::Arg::with_name("country")
+                            .help("Only list relays in the country with this two letter code")
+                            .long("country")
+                            .takes_value(true)
+                            .validator(location::country_code_validator),
+                    )
+                    .arg(
+                        clap::Arg::with_name("city")
+                            .help("Only list relays in the city with this three letter code")
+                            .long("city")
+                            .takes_value(true)
+                            .requires("country")
+                            .validator(location::city_code_validator),
+                    )
+                    .arg(
+                        clap::Arg::with_name("tunnel-protocol")
+                            .help("Only list relays that support this tunnel protocol")
+                            .long("tunnel-protocol")
+                            .takes_value(true)
+                            .possible_values(&["wireguard", "openvpn"]),
+                    )
+                    .arg(
+                        clap::Arg::with_name("interactive")
+                            .help(
+                                "Browse the relays by country and city, and select the location \
+                                 to connect to",
+                            )
+                            .long("interactive")
+                            .short("i"),
+                    ),
             )
             .subcommand(
                 clap::SubCommand::with_name("update")
@@ -214,8 +253,20 @@ impl Command for Relay {
             self.set(set_matches).await
         } else if matches.subcommand_matches("get").is_some() {
             self.get().await
-        } else if matches.subcommand_matches("list").is_some() {
-            self.list().await
+        } else if let Some(fallback_matches) = matches.subcommand_matches("fallback") {
+            match fallback_matches.subcommand() {
+                ("add", Some(add_matches)) => self.add_fallback_tier(add_matches).await,
+                ("list", _) => self.list_fallback_tiers().await,
+                ("clear", _) => self.set_fallback_tiers(vec![]).await,
+                _ => unreachable!("No relay fallback command given"),
+            }
+        } else if let Some(list_matches) = matches.subcommand_matches("list") {
+            let filter = RelayFilter::from_args(list_matches);
+            if list_matches.is_present("interactive") {
+                self.browse(list_matches.value_of("locale"), &filter).await
+            } else {
+                self.list(list_matches.value_of("locale"), &filter).await
+            }
         } else if matches.subcommand_matches("update").is_some() {
             self.update().await
         } else {
@@ -224,6 +275,110 @@ impl Command for Relay {
     }
 }
 
+/// Returns a subcommand that takes the parameters of a custom WireGuard or OpenVPN relay. They are
+/// read by [`read_custom_endpoint`].
+pub fn create_custom_endpoint_subcommand(name: &'static str) -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name(name)
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("wireguard")
+                .arg(
+                    clap::Arg::with_name("host")
+                        .help("Hostname or IP")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("port")
+                        .help("Remote network port")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("peer-pubkey")
+                        .help("Base64 encoded peer public key")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("v4-gateway")
+                        .help("IPv4 gateway address")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("addr")
+                        .help("Local address of wireguard tunnel")
+                        .required(true)
+                        .multiple(true),
+                )
+                .arg(
+                    clap::Arg::with_name("protocol")
+                        .help(
+                            "Transport protocol. If TCP is selected, traffic is \
+                           sent over TCP using a udp-over-tcp proxy",
+                        )
+                        .long("protocol")
+                        .default_value("udp")
+                        .possible_values(&["udp", "tcp"]),
+                )
+                .arg(
+                    clap::Arg::with_name("v6-gateway")
+                        .help("IPv6 gateway address")
+                        .long("v6-gateway")
+                        .takes_value(true),
+                )
+                .arg(
+                    clap::Arg::with_name("allowed-ip")
+                        .help(
+                            "Network to route through the tunnel, in CIDR \
+                           notation. Can be given multiple times. All traffic \
+                           is routed through the tunnel by default. Traffic \
+                           to other networks is blocked while connected",
+                        )
+                        .long("allowed-ip")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("openvpn")
+                .arg(
+                    clap::Arg::with_name("host")
+                        .help("Hostname or IP")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("port")
+                        .help("Remote network port")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("username")
+                        .help("Username to be used with the OpenVpn relay")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("password")
+                        .help("Password to be used with the OpenVpn relay")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::with_name("protocol")
+                        .help("Transport protocol")
+                        .long("protocol")
+                        .default_value("udp")
+                        .possible_values(&["udp", "tcp"]),
+                ),
+        )
+}
+
+/// Reads the custom relay given to a subcommand created by [`create_custom_endpoint_subcommand`].
+pub fn read_custom_endpoint(matches: &clap::ArgMatches<'_>) -> types::CustomRelaySettings {
+    match matches.subcommand() {
+        ("openvpn", Some(openvpn_matches)) => Relay::read_custom_openvpn_relay(openvpn_matches),
+        ("wireguard", Some(wg_matches)) => Relay::read_custom_wireguard_relay(wg_matches),
+        (_unknown_tunnel, _) => unreachable!("No custom relay given"),
+    }
+}
+
 impl Relay {
     async fn update_constraints(&self, update: types::RelaySettingsUpdate) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
@@ -243,13 +398,19 @@ impl Relay {
             self.set_hostname(relay_matches).await
         } else if let Some(providers_matches) = matches.subcommand_matches("provider") {
             self.set_providers(providers_matches).await
+        } else if let Some(ownership_matches) = matches.subcommand_matches("ownership") {
+            self.set_ownership(ownership_matches).await
         } else if let Some(matches) = matches.subcommand_matches("tunnel") {
+            if let Some(prefer_low_latency) = matches.value_of("prefer low latency") {
+                self.set_prefer_low_latency(prefer_low_latency == "on")
+                    .await?;
+            }
             if let Some(tunnel_matches) = matches.subcommand_matches("openvpn") {
                 self.set_openvpn_constraints(tunnel_matches).await
             } else if let Some(tunnel_matches) = matches.subcommand_matches("wireguard") {
                 self.set_wireguard_constraints(tunnel_matches).await
             } else {
-                unreachable!("Invalid tunnel protocol");
+                Ok(())
             }
         } else if let Some(tunnel_matches) = matches.subcommand_matches("tunnel-protocol") {
             self.set_tunnel_protocol(tunnel_matches).await
@@ -259,11 +420,7 @@ impl Relay {
     }
 
     async fn set_custom(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
-        let custom_endpoint = match matches.subcommand() {
-            ("openvpn", Some(openvpn_matches)) => Self::read_custom_openvpn_relay(openvpn_matches),
-            ("wireguard", Some(wg_matches)) => Self::read_custom_wireguard_relay(wg_matches),
-            (_unknown_tunnel, _) => unreachable!("No set relay command given"),
-        };
+        let custom_endpoint = read_custom_endpoint(matches);
 
         self.update_constraints(types::RelaySettingsUpdate {
             r#type: Some(types::relay_settings_update::Type::Custom(custom_endpoint)),
@@ -315,6 +472,13 @@ impl Relay {
         };
         let protocol = value_t!(matches.value_of("protocol"), String).unwrap_or_else(|e| e.exit());
         let protocol = Self::validate_transport_protocol(&protocol);
+        let allowed_ips = match matches.values_of("allowed-ip") {
+            Some(networks) => networks.map(|network| network.to_string()).collect(),
+            None => all_of_the_internet()
+                .iter()
+                .map(|network| network.to_string())
+                .collect(),
+        };
         let mut private_key_str = String::new();
         println!("Reading private key from standard input");
         let _ = io::stdin().lock().read_line(&mut private_key_str);
@@ -338,10 +502,7 @@ impl Relay {
                         }),
                         peer: Some(wireguard_config::PeerConfig {
                             public_key: peer_public_key.to_vec(),
-                            allowed_ips: all_of_the_internet()
-                                .iter()
-                                .map(|address| address.to_string())
-                                .collect(),
+                            allowed_ips,
                             endpoint: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)
                                 .to_string(),
                             protocol: protocol as i32,
@@ -390,7 +551,7 @@ impl Relay {
 
     async fn set_hostname(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
         let hostname = matches.value_of("hostname").unwrap();
-        let countries = Self::get_filtered_relays().await?;
+        let countries = Self::get_filtered_relays(None).await?;
 
         let find_relay = || {
             for country in countries {
@@ -440,7 +601,7 @@ impl Relay {
         if !location_constraint.country.is_empty() {
             // TODO: `mullvad_types::relay_constraints::LocationConstraint::matches(&relay)`
             //       could be used to guarantee consistency with the daemon.
-            let countries = Self::get_filtered_relays().await?;
+            let countries = Self::get_filtered_relays(None).await?;
             for country in &countries {
                 if country.code != location_constraint.country {
                     continue;
@@ -511,6 +672,27 @@ impl Relay {
         .await
     }
 
+    async fn set_ownership(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let ownership = match matches.value_of("ownership").unwrap() {
+            "any" => types::Ownership::Any,
+            "owned" => types::Ownership::MullvadOwned,
+            "rented" => types::Ownership::Rented,
+            _ => unreachable!("invalid ownership"),
+        };
+
+        self.update_constraints(types::RelaySettingsUpdate {
+            r#type: Some(types::relay_settings_update::Type::Normal(
+                types::NormalRelaySettingsUpdate {
+                    ownership: Some(types::OwnershipUpdate {
+                        ownership: ownership as i32,
+                    }),
+                    ..Default::default()
+                },
+            )),
+        })
+        .await
+    }
+
     async fn set_openvpn_constraints(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
         let mut openvpn_constraints = {
             let mut rpc = new_rpc_client().await?;
@@ -558,6 +740,9 @@ impl Relay {
 
         wireguard_constraints.port =
             parse_transport_port(matches, &mut wireguard_constraints.port)?;
+        if let Some(port_range) = matches.value_of("port range") {
+            wireguard_constraints.port_range = parse_port_range_constraint(port_range)?;
+        }
 
         if let Some(ipv) = matches.value_of("ip version") {
             wireguard_constraints.ip_version =
@@ -628,59 +813,124 @@ impl Relay {
         .await
     }
 
+    async fn set_prefer_low_latency(&self, prefer_low_latency: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_prefer_low_latency(prefer_low_latency).await?;
+        println!("Updated the preference for low latency relays");
+        Ok(())
+    }
+
     async fn get(&self) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        let relay_settings = rpc
-            .get_settings(())
-            .await?
-            .into_inner()
-            .relay_settings
-            .unwrap();
+        let settings = rpc.get_settings(()).await?.into_inner();
 
         println!(
             "Current constraints: {}",
-            RelaySettings::try_from(relay_settings).unwrap()
+            RelaySettings::try_from(settings.relay_settings.unwrap()).unwrap()
+        );
+        println!(
+            "Prefer low latency relays: {}",
+            if settings.prefer_low_latency {
+                "on"
+            } else {
+                "off"
+            }
         );
 
         Ok(())
     }
 
-    async fn list(&self) -> Result<()> {
-        let mut countries = Self::get_filtered_relays().await?;
-        countries.sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
-        for mut country in countries {
-            country
-                .cities
-                .sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
+    async fn get_fallback_tiers(
+        rpc: &mut ManagementServiceClient,
+    ) -> Result<Vec<types::NormalRelaySettings>> {
+        Ok(rpc
+            .get_settings(())
+            .await?
+            .into_inner()
+            .relay_fallback_tiers
+            .map(|tiers| tiers.tiers)
+            .unwrap_or_default())
+    }
+
+    async fn add_fallback_tier(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let tunnel_type = match matches.value_of("tunnel protocol").unwrap() {
+            "wireguard" => Some(types::TunnelType::Wireguard),
+            "openvpn" => Some(types::TunnelType::Openvpn),
+            "any" => None,
+            _ => unreachable!(),
+        };
+        let tier = types::NormalRelaySettings {
+            location: Some(location::get_constraint_from_args(matches)),
+            tunnel_type: tunnel_type.map(|tunnel_type| types::TunnelTypeConstraint {
+                tunnel_type: tunnel_type as i32,
+            }),
+            wireguard_constraints: Some(types::WireguardConstraints::default()),
+            openvpn_constraints: Some(types::OpenvpnConstraints::default()),
+            ..Default::default()
+        };
+        RelayConstraints::try_from(tier.clone())
+            .map_err(|_| Error::InvalidCommand("Invalid fallback tier"))?;
+
+        let mut rpc = new_rpc_client().await?;
+        let mut tiers = Self::get_fallback_tiers(&mut rpc).await?;
+        tiers.push(tier);
+        self.set_fallback_tiers(tiers).await
+    }
+
+    async fn set_fallback_tiers(&self, tiers: Vec<types::NormalRelaySettings>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_relay_fallback_tiers(types::RelayFallbackTiers { tiers })
+            .await
+            .map_err(|error| Error::RpcFailedExt("Failed to update fallback tiers", error))?;
+        println!("Relay fallback tiers updated");
+        Ok(())
+    }
+
+    async fn list_fallback_tiers(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let tiers = Self::get_fallback_tiers(&mut rpc).await?;
+        if tiers.is_empty() {
+            println!("No fallback tiers");
+        }
+        for (index, tier) in tiers.into_iter().enumerate() {
+            let tier = RelayConstraints::try_from(tier)
+                .map_err(|_| Error::CommandFailed("Received an invalid fallback tier"))?;
+            println!("{}: {}", index + 1, tier);
+        }
+        Ok(())
+    }
+
+    async fn list(&self, locale: Option<&str>, filter: &RelayFilter) -> Result<()> {
+        let countries = Self::get_sorted_relays(locale, filter).await?;
+        let latencies = Self::get_relay_latencies().await?;
+        for country in countries {
             println!("{} ({})", country.name, country.code);
-            for mut city in country.cities {
-                city.relays
-                    .sort_by(|r1, r2| natord::compare_ignore_case(&r1.hostname, &r2.hostname));
+            for city in country.cities {
                 println!(
                     "\t{} ({}) @ {:.5}°N, {:.5}°W",
                     city.name, city.code, city.latitude, city.longitude
                 );
                 for relay in &city.relays {
-                    let tunnels = relay.tunnels.as_ref().unwrap();
-                    let supports_openvpn = !tunnels.openvpn.is_empty();
-                    let supports_wireguard = !tunnels.wireguard.is_empty();
-                    let support_msg = match (supports_openvpn, supports_wireguard) {
-                        (true, true) => "OpenVPN and WireGuard",
-                        (true, false) => "OpenVPN",
-                        (false, true) => "WireGuard",
-                        _ => unreachable!("Bug in relay filtering earlier on"),
-                    };
                     let mut addresses = vec![&relay.ipv4_addr_in];
                     if !relay.ipv6_addr_in.is_empty() {
                         addresses.push(&relay.ipv6_addr_in);
                     }
-                    println!(
-                        "\t\t{} ({}) - {}, hosted by {}",
+                    print!(
+                        "\t\t{} ({}) - {}, hosted by {} ({})",
                         relay.hostname,
                         addresses.iter().join(", "),
-                        support_msg,
-                        relay.provider
+                        format_tunnel_support(relay),
+                        relay.provider,
+                        if relay.owned {
+                            "Mullvad-owned"
+                        } else {
+                            "rented"
+                        }
                     );
+                    match latencies.get(&relay.hostname) {
+                        Some(latency) => println!(", {} ms", latency),
+                        None => println!(),
+                    }
                 }
             }
             println!();
@@ -688,17 +938,131 @@ impl Relay {
         Ok(())
     }
 
+    /// Lets the user pick a country, city and relay from numbered lists, and sets the chosen
+    /// location as the relay constraint.
+    async fn browse(&self, locale: Option<&str>, filter: &RelayFilter) -> Result<()> {
+        let countries = Self::get_sorted_relays(locale, filter).await?;
+        let latencies = Self::get_relay_latencies().await?;
+        if countries.is_empty() {
+            return Err(Error::CommandFailed("No relays match the filter"));
+        }
+
+        for (index, country) in countries.iter().enumerate() {
+            let relay_count: usize = country.cities.iter().map(|city| city.relays.len()).sum();
+            println!(
+                "{:>4}. {} ({}) - {} relays",
+                index + 1,
+                country.name,
+                country.code,
+                relay_count
+            );
+        }
+        let country = prompt_choice("Country", &countries, |country| &country.code, false)?
+            .expect("A country is required");
+
+        println!("{:>4}. Any city", 0);
+        for (index, city) in country.cities.iter().enumerate() {
+            println!(
+                "{:>4}. {} ({}) - {} relays",
+                index + 1,
+                city.name,
+                city.code,
+                city.relays.len()
+            );
+        }
+        let city = prompt_choice("City", &country.cities, |city| &city.code, true)?;
+
+        let relay = match city {
+            Some(city) => {
+                println!("{:>4}. Any relay", 0);
+                println!(
+                    "      {:<16} {:<22} {:>8}  {}",
+                    "HOSTNAME", "TUNNEL PROTOCOLS", "LATENCY", "OWNERSHIP"
+                );
+                for (index, relay) in city.relays.iter().enumerate() {
+                    println!(
+                        "{:>4}. {:<16} {:<22} {:>8}  {}",
+                        index + 1,
+                        relay.hostname,
+                        format_tunnel_support(relay),
+                        latencies
+                            .get(&relay.hostname)
+                            .map(|latency| format!("{} ms", latency))
+                            .unwrap_or_else(|| "-".to_owned()),
+                        if relay.owned {
+                            "Mullvad-owned"
+                        } else {
+                            "rented"
+                        }
+                    );
+                }
+                prompt_choice("Relay", &city.relays, |relay| &relay.hostname, true)?
+            }
+            None => None,
+        };
+
+        self.update_constraints(types::RelaySettingsUpdate {
+            r#type: Some(types::relay_settings_update::Type::Normal(
+                types::NormalRelaySettingsUpdate {
+                    location: Some(location::get_constraint(
+                        country.code.as_str(),
+                        city.map(|city| city.code.as_str()),
+                        relay.map(|relay| relay.hostname.as_str()),
+                    )),
+                    ..Default::default()
+                },
+            )),
+        })
+        .await
+    }
+
+    /// Returns the relays that match `filter`, sorted by name.
+    async fn get_sorted_relays(
+        locale: Option<&str>,
+        filter: &RelayFilter,
+    ) -> Result<Vec<types::RelayListCountry>> {
+        let mut countries = Self::get_filtered_relays(locale).await?;
+        filter.apply(&mut countries);
+        countries.sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
+        for country in &mut countries {
+            country
+                .cities
+                .sort_by(|c1, c2| natord::compare_ignore_case(&c1.name, &c2.name));
+            for city in &mut country.cities {
+                city.relays
+                    .sort_by(|r1, r2| natord::compare_ignore_case(&r1.hostname, &r2.hostname));
+            }
+        }
+        Ok(countries)
+    }
+
+    /// Returns the last measured round-trip time to each relay, in milliseconds. Daemons that
+    /// do not measure latencies yield an empty map, so relays are listed without them.
+    async fn get_relay_latencies() -> Result<HashMap<String, u32>> {
+        let mut rpc = new_rpc_client().await?;
+        match rpc.get_relay_latencies(()).await {
+            Ok(response) => Ok(response.into_inner().latencies),
+            Err(status) if status.code() == Code::Unimplemented => Ok(HashMap::new()),
+            Err(error) => Err(Error::RpcFailedExt(
+                "Failed to obtain relay latencies",
+                error,
+            )),
+        }
+    }
+
     async fn update(&self) -> Result<()> {
         new_rpc_client().await?.update_relay_locations(()).await?;
         println!("Updating relay list in the background...");
         Ok(())
     }
 
-    async fn get_filtered_relays() -> Result<Vec<types::RelayListCountry>> {
+    async fn get_filtered_relays(locale: Option<&str>) -> Result<Vec<types::RelayListCountry>> {
         let mut rpc = new_rpc_client().await?;
-        let mut locations = rpc
-            .get_relay_locations(())
-            .await
+        let locations = match locale {
+            Some(locale) => rpc.get_localized_relay_locations(locale.to_owned()).await,
+            None => rpc.get_relay_locations(()).await,
+        };
+        let mut locations = locations
             .map_err(|error| Error::RpcFailedExt("Failed to obtain relay locations", error))?
             .into_inner();
 
@@ -731,6 +1095,108 @@ impl Relay {
     }
 }
 
+/// Restricts the relays that are listed by `mullvad relay list`.
+struct RelayFilter {
+    country: Option<String>,
+    city: Option<String>,
+    tunnel_protocol: Option<types::TunnelType>,
+}
+
+impl RelayFilter {
+    fn from_args(matches: &clap::ArgMatches<'_>) -> Self {
+        RelayFilter {
+            country: matches.value_of("country").map(str::to_lowercase),
+            city: matches.value_of("city").map(str::to_lowercase),
+            tunnel_protocol: matches
+                .value_of("tunnel-protocol")
+                .map(|protocol| match protocol {
+                    "wireguard" => types::TunnelType::Wireguard,
+                    "openvpn" => types::TunnelType::Openvpn,
+                    _ => unreachable!("Invalid tunnel protocol"),
+                }),
+        }
+    }
+
+    /// Removes the relays that do not match, and the cities and countries that become empty.
+    fn apply(&self, countries: &mut Vec<types::RelayListCountry>) {
+        if let Some(country) = &self.country {
+            countries.retain(|candidate| &candidate.code == country);
+        }
+        for country in countries.iter_mut() {
+            if let Some(city) = &self.city {
+                country.cities.retain(|candidate| &candidate.code == city);
+            }
+            for city in country.cities.iter_mut() {
+                city.relays.retain(|relay| {
+                    let tunnels = relay.tunnels.as_ref().unwrap();
+                    match self.tunnel_protocol {
+                        Some(types::TunnelType::Wireguard) => !tunnels.wireguard.is_empty(),
+                        Some(types::TunnelType::Openvpn) => !tunnels.openvpn.is_empty(),
+                        None => true,
+                    }
+                });
+            }
+            country.cities.retain(|city| !city.relays.is_empty());
+        }
+        countries.retain(|country| !country.cities.is_empty());
+    }
+}
+
+fn format_tunnel_support(relay: &types::Relay) -> &'static str {
+    let tunnels = relay.tunnels.as_ref().unwrap();
+    match (!tunnels.openvpn.is_empty(), !tunnels.wireguard.is_empty()) {
+        (true, true) => "OpenVPN and WireGuard",
+        (true, false) => "OpenVPN",
+        (false, true) => "WireGuard",
+        _ => unreachable!("Bug in relay filtering earlier on"),
+    }
+}
+
+/// Reads a choice from standard input, either as a number from the list or as a code. `0` or an
+/// empty line chooses none of the items if `allow_any` is set.
+fn prompt_choice<'a, T>(
+    title: &str,
+    items: &'a [T],
+    code: impl Fn(&T) -> &str,
+    allow_any: bool,
+) -> Result<Option<&'a T>> {
+    loop {
+        if allow_any {
+            print!("{} (number or code, empty for any): ", title);
+        } else {
+            print!("{} (number or code): ", title);
+        }
+        io::stdout()
+            .flush()
+            .map_err(|error| Error::FileError("Failed to write to standard output", error))?;
+
+        let mut input = String::new();
+        let read = io::stdin()
+            .lock()
+            .read_line(&mut input)
+            .map_err(|error| Error::FileError("Failed to read from standard input", error))?;
+        if read == 0 {
+            return Err(Error::CommandFailed("No location was selected"));
+        }
+        let input = input.trim();
+
+        if allow_any && (input.is_empty() || input == "0") {
+            return Ok(None);
+        }
+        let choice = match input.parse::<usize>() {
+            Ok(number) if number >= 1 => items.get(number - 1),
+            Ok(_) => None,
+            Err(_) => items
+                .iter()
+                .find(|item| code(item).eq_ignore_ascii_case(input)),
+        };
+        match choice {
+            Some(item) => return Ok(Some(item)),
+            None => eprintln!("Invalid choice: {}", input),
+        }
+    }
+}
+
 fn parse_port_constraint(raw_port: &str) -> Result<Constraint<u16>> {
     match raw_port.to_lowercase().as_str() {
         "any" => Ok(Constraint::Any),
@@ -740,6 +1206,26 @@ fn parse_port_constraint(raw_port: &str) -> Result<Constraint<u16>> {
     }
 }
 
+/// Parses a port range constraint. `None` means that any port may be used.
+fn parse_port_range_constraint(raw_range: &str) -> Result<Option<types::PortRange>> {
+    const INVALID_RANGE: Error =
+        Error::InvalidCommand("Invalid port range. Must be \"any\" or <first port>-<last port>.");
+
+    if raw_range.eq_ignore_ascii_case("any") {
+        return Ok(None);
+    }
+    let (first, last) = raw_range.split_once('-').ok_or(INVALID_RANGE)?;
+    let first = u16::from_str(first.trim()).map_err(|_| INVALID_RANGE)?;
+    let last = u16::from_str(last.trim()).map_err(|_| INVALID_RANGE)?;
+    if first > last {
+        return Err(INVALID_RANGE);
+    }
+    Ok(Some(types::PortRange {
+        first: u32::from(first),
+        last: u32::from(last),
+    }))
+}
+
 fn parse_protocol(raw_protocol: &str) -> Constraint<types::TransportProtocol> {
     match raw_protocol {
         "any" => Constraint::Any,
@@ -825,3 +1311,127 @@ fn parse_transport_port(
         )),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn relay(hostname: &str, openvpn: bool, wireguard: bool) -> types::Relay {
+        types::Relay {
+            hostname: hostname.to_owned(),
+            active: true,
+            tunnels: Some(types::RelayTunnels {
+                openvpn: if openvpn {
+                    vec![types::OpenVpnEndpointData::default()]
+                } else {
+                    vec![]
+                },
+                wireguard: if wireguard {
+                    vec![types::WireguardEndpointData::default()]
+                } else {
+                    vec![]
+                },
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn city(code: &str, relays: Vec<types::Relay>) -> types::RelayListCity {
+        types::RelayListCity {
+            name: code.to_owned(),
+            code: code.to_owned(),
+            relays,
+            ..Default::default()
+        }
+    }
+
+    fn countries() -> Vec<types::RelayListCountry> {
+        vec![
+            types::RelayListCountry {
+                name: "Sweden".to_owned(),
+                code: "se".to_owned(),
+                cities: vec![
+                    city(
+                        "got",
+                        vec![
+                            relay("se-got-001", true, false),
+                            relay("se-got-wg-001", false, true),
+                        ],
+                    ),
+                    city("sto", vec![relay("se-sto-001", true, true)]),
+                ],
+            },
+            types::RelayListCountry {
+                name: "Norway".to_owned(),
+                code: "no".to_owned(),
+                cities: vec![city("osl", vec![relay("no-osl-001", true, false)])],
+            },
+        ]
+    }
+
+    fn hostnames(countries: &[types::RelayListCountry]) -> Vec<&str> {
+        countries
+            .iter()
+            .flat_map(|country| &country.cities)
+            .flat_map(|city| &city.relays)
+            .map(|relay| relay.hostname.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_filter_without_constraints() {
+        let filter = RelayFilter {
+            country: None,
+            city: None,
+            tunnel_protocol: None,
+        };
+        let mut countries = countries();
+        filter.apply(&mut countries);
+        assert_eq!(
+            hostnames(&countries),
+            ["se-got-001", "se-got-wg-001", "se-sto-001", "no-osl-001"]
+        );
+    }
+
+    #[test]
+    fn test_filter_by_location() {
+        let filter = RelayFilter {
+            country: Some("se".to_owned()),
+            city: Some("sto".to_owned()),
+            tunnel_protocol: None,
+        };
+        let mut countries = countries();
+        filter.apply(&mut countries);
+        assert_eq!(hostnames(&countries), ["se-sto-001"]);
+        assert_eq!(countries[0].cities.len(), 1);
+    }
+
+    #[test]
+    fn test_filter_by_tunnel_protocol() {
+        let filter = RelayFilter {
+            country: None,
+            city: None,
+            tunnel_protocol: Some(types::TunnelType::Wireguard),
+        };
+        let mut countries = countries();
+        filter.apply(&mut countries);
+        assert_eq!(hostnames(&countries), ["se-got-wg-001", "se-sto-001"]);
+        assert_eq!(
+            countries.len(),
+            1,
+            "countries without matching relays are removed"
+        );
+    }
+
+    #[test]
+    fn test_filter_without_matches() {
+        let filter = RelayFilter {
+            country: Some("no".to_owned()),
+            city: None,
+            tunnel_protocol: Some(types::TunnelType::Wireguard),
+        };
+        let mut countries = countries();
+        filter.apply(&mut countries);
+        assert!(countries.is_empty());
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{new_rpc_client, Command, Error, Result};
+use std::fs;
+
+pub struct Settings;
+
+#[mullvad_management_interface::async_trait]
+impl Command for Settings {
+    fn name(&self) -> &'static str {
+        "settings"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Change several settings at once, or export and import them")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("patch")
+                    .about(
+                        "Apply a JSON merge patch to the settings. The settings are only changed \
+                         if the result is valid, and then all at once",
+                    )
+                    .arg(
+                        clap::Arg::with_name("patch")
+                            .help(
+                                "JSON object with the settings to change, in the same format as \
+                                 the settings file. Keys that are null are reset to their defaults",
+                            )
+                            .required(true),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("export")
+                    .about(
+                        "Export the settings as JSON. The account number, WireGuard key and \
+                         other settings that are specific to this device are left out",
+                    )
+                    .arg(
+                        clap::Arg::with_name("file")
+                            .help("File to write the settings to. They are printed if not given"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("import")
+                    .about(
+                        "Replace the settings with exported settings. Settings that are missing \
+                         from the file are reset to their defaults",
+                    )
+                    .arg(
+                        clap::Arg::with_name("file")
+                            .help("File with settings that were exported by this app version")
+                            .required(true),
+                    ),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("patch", Some(patch_matches)) => {
+                self.patch(patch_matches.value_of("patch").unwrap()).await
+            }
+            ("export", Some(export_matches)) => self.export(export_matches.value_of("file")).await,
+            ("import", Some(import_matches)) => {
+                self.import(import_matches.value_of("file").unwrap()).await
+            }
+            _ => unreachable!("No settings command given"),
+        }
+    }
+}
+
+impl Settings {
+    async fn patch(&self, patch: &str) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.apply_settings_patch(patch.to_owned()).await?;
+        println!("Applied settings patch");
+        Ok(())
+    }
+
+    async fn export(&self, file: Option<&str>) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let settings = rpc.export_settings(()).await?.into_inner();
+        match file {
+            Some(file) => {
+                fs::write(file, settings)
+                    .map_err(|error| Error::FileError("Failed to write settings", error))?;
+                println!("Exported settings to {}", file);
+            }
+            None => println!("{}", settings),
+        }
+        Ok(())
+    }
+
+    async fn import(&self, file: &str) -> Result<()> {
+        let settings = fs::read_to_string(file)
+            .map_err(|error| Error::FileError("Failed to read settings", error))?;
+        let mut rpc = new_rpc_client().await?;
+        rpc.import_settings(settings).await?;
+        println!("Imported settings");
+        Ok(())
+    }
+}
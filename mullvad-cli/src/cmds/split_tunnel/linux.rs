@@ -1,3 +1,4 @@
+use super::network;
 use crate::{new_rpc_client, Command, Result};
 use clap::value_t_or_exit;
 
@@ -17,11 +18,15 @@ impl Command for SplitTunnel {
             )
             .setting(clap::AppSettings::SubcommandRequiredElseHelp)
             .subcommand(create_pid_subcommand())
+            .subcommand(network::create_network_subcommand())
     }
 
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
         match matches.subcommand() {
             ("pid", Some(pid_matches)) => Self::handle_pid_cmd(pid_matches).await,
+            ("network", Some(network_matches)) => {
+                network::handle_network_cmd(network_matches).await
+            }
             _ => unreachable!("unhandled comand"),
         }
     }
@@ -32,13 +37,31 @@ fn create_pid_subcommand() -> clap::App<'static, 'static> {
         .about("Manage processes to exclude from the tunnel")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(
-            clap::SubCommand::with_name("add").arg(clap::Arg::with_name("pid").required(true)),
+            clap::SubCommand::with_name("add")
+                .about("Exclude a process and its future children from the tunnel")
+                .arg(
+                    clap::Arg::with_name("pid")
+                        .help("ID of the process to exclude")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("delete")
+                .about("Stop excluding a process from the tunnel")
+                .arg(
+                    clap::Arg::with_name("pid")
+                        .help("ID of the excluded process")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("clear")
+                .about("Stop excluding all processes from the tunnel"),
         )
         .subcommand(
-            clap::SubCommand::with_name("delete").arg(clap::Arg::with_name("pid").required(true)),
+            clap::SubCommand::with_name("list")
+                .about("List all processes excluded from the tunnel"),
         )
-        .subcommand(clap::SubCommand::with_name("clear"))
-        .subcommand(clap::SubCommand::with_name("list"))
 }
 
 impl SplitTunnel {
@@ -50,6 +73,7 @@ impl SplitTunnel {
                     .await?
                     .add_split_tunnel_process(pid)
                     .await?;
+                println!("Excluded process {} from the tunnel", pid);
                 Ok(())
             }
             ("delete", Some(matches)) => {
@@ -58,6 +82,7 @@ impl SplitTunnel {
                     .await?
                     .remove_split_tunnel_process(pid)
                     .await?;
+                println!("Stopped excluding process {} from the tunnel", pid);
                 Ok(())
             }
             ("clear", Some(_)) => {
@@ -65,6 +90,7 @@ impl SplitTunnel {
                     .await?
                     .clear_split_tunnel_processes(())
                     .await?;
+                println!("Stopped excluding all processes from the tunnel");
                 Ok(())
             }
             ("list", Some(_)) => {
@@ -0,0 +1,27 @@
+use super::network;
+use crate::{Command, Result};
+
+pub struct SplitTunnel;
+
+#[mullvad_management_interface::async_trait]
+impl Command for SplitTunnel {
+    fn name(&self) -> &'static str {
+        "split-tunnel"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Manage split tunneling")
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(network::create_network_subcommand())
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        match matches.subcommand() {
+            ("network", Some(network_matches)) => {
+                network::handle_network_cmd(network_matches).await
+            }
+            _ => unreachable!("unhandled comand"),
+        }
+    }
+}
@@ -2,9 +2,15 @@
 #[path = "linux.rs"]
 mod imp;
 
+#[cfg(target_os = "macos")]
+#[path = "macos.rs"]
+mod imp;
+
 #[cfg(windows)]
 #[path = "windows.rs"]
 mod imp;
 
-#[cfg(any(target_os = "linux", windows))]
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod network;
+
 pub use imp::*;
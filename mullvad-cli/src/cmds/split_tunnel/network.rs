@@ -0,0 +1,73 @@
+use crate::{new_rpc_client, Result};
+use clap::value_t_or_exit;
+
+pub fn create_network_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("network")
+        .about("Manage networks whose traffic should bypass the tunnel")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name("add")
+                .about("Send traffic to a network outside the tunnel")
+                .arg(
+                    clap::Arg::with_name("network")
+                        .help("IP address or network in CIDR notation, e.g. 192.168.50.0/24")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("delete")
+                .about("Stop excluding a network from the tunnel")
+                .arg(
+                    clap::Arg::with_name("network")
+                        .help("The excluded network")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("clear")
+                .about("Stop excluding all networks from the tunnel"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("list").about("List all networks excluded from the tunnel"),
+        )
+}
+
+pub async fn handle_network_cmd(matches: &clap::ArgMatches<'_>) -> Result<()> {
+    match matches.subcommand() {
+        ("add", Some(matches)) => {
+            let network = value_t_or_exit!(matches.value_of("network"), String);
+            new_rpc_client()
+                .await?
+                .add_split_tunnel_network(network.clone())
+                .await?;
+            println!("Excluded network {} from the tunnel", network);
+            Ok(())
+        }
+        ("delete", Some(matches)) => {
+            let network = value_t_or_exit!(matches.value_of("network"), String);
+            new_rpc_client()
+                .await?
+                .remove_split_tunnel_network(network.clone())
+                .await?;
+            println!("Stopped excluding network {} from the tunnel", network);
+            Ok(())
+        }
+        ("clear", Some(_)) => {
+            new_rpc_client()
+                .await?
+                .clear_split_tunnel_networks(())
+                .await?;
+            println!("Stopped excluding all networks from the tunnel");
+            Ok(())
+        }
+        ("list", Some(_)) => {
+            let settings = new_rpc_client().await?.get_settings(()).await?.into_inner();
+            println!("Excluded networks:");
+            for network in settings.excluded_networks {
+                println!("    {}", network);
+            }
+            Ok(())
+        }
+        _ => unreachable!("unhandled command"),
+    }
+}
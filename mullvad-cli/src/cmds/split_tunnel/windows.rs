@@ -45,14 +45,32 @@ fn create_app_subcommand() -> clap::App<'static, 'static> {
     clap::SubCommand::with_name("app")
         .about("Manage applications to exclude from the tunnel")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
-        .subcommand(clap::SubCommand::with_name("list"))
         .subcommand(
-            clap::SubCommand::with_name("add").arg(clap::Arg::with_name("path").required(true)),
+            clap::SubCommand::with_name("list")
+                .about("List all applications excluded from the tunnel"),
         )
         .subcommand(
-            clap::SubCommand::with_name("remove").arg(clap::Arg::with_name("path").required(true)),
+            clap::SubCommand::with_name("add")
+                .about("Exclude an application from the tunnel")
+                .arg(
+                    clap::Arg::with_name("path")
+                        .help("Path to the executable of the application")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("remove")
+                .about("Stop excluding an application from the tunnel")
+                .arg(
+                    clap::Arg::with_name("path")
+                        .help("Path to the executable of the application")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("clear")
+                .about("Stop excluding all applications from the tunnel"),
         )
-        .subcommand(clap::SubCommand::with_name("clear"))
 }
 
 impl SplitTunnel {
@@ -77,19 +95,25 @@ impl SplitTunnel {
             }
             ("add", Some(matches)) => {
                 let path = value_t_or_exit!(matches.value_of("path"), String);
-                new_rpc_client().await?.add_split_tunnel_app(path).await?;
+                new_rpc_client()
+                    .await?
+                    .add_split_tunnel_app(path.clone())
+                    .await?;
+                println!("Excluded {} from the tunnel", path);
                 Ok(())
             }
             ("remove", Some(matches)) => {
                 let path = value_t_or_exit!(matches.value_of("path"), String);
                 new_rpc_client()
                     .await?
-                    .remove_split_tunnel_app(path)
+                    .remove_split_tunnel_app(path.clone())
                     .await?;
+                println!("Stopped excluding {} from the tunnel", path);
                 Ok(())
             }
             ("clear", Some(_)) => {
                 new_rpc_client().await?.clear_split_tunnel_apps(()).await?;
+                println!("Stopped excluding all applications from the tunnel");
                 Ok(())
             }
             _ => unreachable!("unhandled subcommand"),
@@ -1,6 +1,10 @@
 use crate::{format, format::print_keygen_event, new_rpc_client, Command, Error, Result};
 use mullvad_management_interface::{
-    types::daemon_event::Event as EventType, ManagementServiceClient,
+    types::{
+        daemon_event::Event as EventType, event_subscription::Category as EventCategory,
+        EventSubscription, TunnelState,
+    },
+    ManagementServiceClient,
 };
 
 pub struct Status;
@@ -20,6 +24,16 @@ impl Command for Status {
                     .short("l")
                     .help("Prints the current location and IP. Based on GeoIP lookups"),
             )
+            .arg(
+                clap::Arg::with_name("verbose")
+                    .long("verbose")
+                    .short("v")
+                    .help("Prints the relays, obfuscation and tunnel interface that are used"),
+            )
+            .arg(clap::Arg::with_name("json").long("json").help(
+                "Prints the tunnel state as JSON, one object per line. Other output is \
+                         left out",
+            ))
             .subcommand(
                 clap::SubCommand::with_name("listen")
                     .about("Listen for VPN tunnel state changes")
@@ -34,21 +48,50 @@ impl Command for Status {
     async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
         let state = rpc.get_tunnel_state(()).await?.into_inner();
+        let json = matches.is_present("json");
 
-        format::print_state(&state);
-        if matches.is_present("location") {
-            print_location(&mut rpc).await?;
+        if json {
+            print_state_json(&state);
+        } else {
+            format::print_state(&state);
+            if matches.is_present("verbose") {
+                format::print_connection_details(&state);
+            }
+            if matches.is_present("location") {
+                print_location(&mut rpc).await?;
+            }
         }
+        print_settings_load_failure(&mut rpc).await?;
 
         if let Some(listen_matches) = matches.subcommand_matches("listen") {
-            let verbose = listen_matches.is_present("verbose");
+            let verbose = listen_matches.is_present("verbose") && !json;
 
-            let mut events = rpc.events_listen(()).await?.into_inner();
+            // Other events are only printed in verbose mode, so don't ask for them otherwise
+            let categories = if verbose {
+                vec![]
+            } else {
+                vec![
+                    EventCategory::TunnelState as i32,
+                    EventCategory::FirewallPolicyRestored as i32,
+                    EventCategory::DnsInterference as i32,
+                ]
+            };
+            let mut events = rpc
+                .events_listen_filtered(EventSubscription {
+                    categories,
+                    send_snapshot: false,
+                })
+                .await?
+                .into_inner();
 
             while let Some(event) = events.message().await? {
                 match event.event.unwrap() {
+                    EventType::TunnelState(new_state) if json => print_state_json(&new_state),
                     EventType::TunnelState(new_state) => {
                         format::print_state(&new_state);
+                        if matches.is_present("verbose") {
+                            format::print_connection_details(&new_state);
+                        }
                         use mullvad_management_interface::types::tunnel_state::State::*;
                         match new_state.state.unwrap() {
                             Connected(..) | Disconnected(..) => {
@@ -80,6 +123,26 @@ impl Command for Status {
                             print_keygen_event(&key_event);
                         }
                     }
+                    EventType::AccountExpiry(event) => {
+                        if verbose {
+                            println!("Account expiry: {:#?}", event);
+                        }
+                    }
+                    EventType::FirewallPolicyRestored(_) => {
+                        if !json {
+                            println!(
+                                "The firewall rules were changed by another program and have \
+                                 been restored"
+                            );
+                        }
+                    }
+                    EventType::DnsInterference(_) => {
+                        if !json {
+                            println!(
+                                "Another program keeps changing the DNS settings. DNS requests                                  may leak or fail"
+                            );
+                        }
+                    }
                 }
             }
         }
@@ -88,6 +151,28 @@ impl Command for Status {
     }
 }
 
+fn print_state_json(state: &TunnelState) {
+    println!(
+        "{}",
+        serde_json::to_string(state).expect("Failed to serialize tunnel state")
+    );
+}
+
+async fn print_settings_load_failure(rpc: &mut ManagementServiceClient) -> Result<()> {
+    let status = rpc.get_settings_load_status(()).await?.into_inner();
+    if !status.failed {
+        return Ok(());
+    }
+    eprintln!(
+        "Warning: The settings could not be loaded, so the defaults are being used: {}",
+        status.error
+    );
+    if !status.backup_path.is_empty() {
+        eprintln!("The previous settings were moved to {}", status.backup_path);
+    }
+    Ok(())
+}
+
 async fn print_location(rpc: &mut ManagementServiceClient) -> Result<()> {
     let location = rpc.get_current_location(()).await;
     let location = match location {
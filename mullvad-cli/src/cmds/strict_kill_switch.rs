@@ -0,0 +1,64 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t_or_exit;
+
+pub struct StrictKillSwitch;
+
+#[mullvad_management_interface::async_trait]
+impl Command for StrictKillSwitch {
+    fn name(&self) -> &'static str {
+        "strict-kill-switch"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Control if the firewall rules and routes should be confirmed to be in place \
+                 before the tunnel is reported as connected. If they cannot be confirmed, all \
+                 network traffic is blocked",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Change the strict kill switch setting")
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get")
+                    .about("Display the current strict kill switch setting"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let strict_kill_switch = value_t_or_exit!(set_matches.value_of("policy"), String);
+            self.set(strict_kill_switch == "on").await
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get().await
+        } else {
+            unreachable!("No strict-kill-switch command given");
+        }
+    }
+}
+
+impl StrictKillSwitch {
+    async fn set(&self, strict_kill_switch: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_strict_kill_switch(strict_kill_switch).await?;
+        println!("Changed strict kill switch setting");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let strict_kill_switch = rpc.get_settings(()).await?.into_inner().strict_kill_switch;
+        println!(
+            "Strict kill switch: {}",
+            if strict_kill_switch { "on" } else { "off" }
+        );
+        Ok(())
+    }
+}
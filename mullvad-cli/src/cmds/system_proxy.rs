@@ -0,0 +1,63 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t_or_exit;
+
+pub struct SystemProxy;
+
+#[mullvad_management_interface::async_trait]
+impl Command for SystemProxy {
+    fn name(&self) -> &'static str {
+        "system-proxy"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Control if the daemon may reach the API through the HTTP proxy configured in \
+                 the OS, when the API cannot be reached directly",
+            )
+            .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Change the system proxy setting")
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get")
+                    .about("Display the current system proxy setting"),
+            )
+    }
+
+    async fn run(&self, matches: &clap::ArgMatches<'_>) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let use_system_proxy = value_t_or_exit!(set_matches.value_of("policy"), String);
+            self.set(use_system_proxy == "on").await
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get().await
+        } else {
+            unreachable!("No system-proxy command given");
+        }
+    }
+}
+
+impl SystemProxy {
+    async fn set(&self, use_system_proxy: bool) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_use_system_proxy(use_system_proxy).await?;
+        println!("Changed system proxy setting");
+        Ok(())
+    }
+
+    async fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        let use_system_proxy = rpc.get_settings(()).await?.into_inner().use_system_proxy;
+        println!(
+            "The system proxy {} used for API connections",
+            if use_system_proxy { "is" } else { "is not" }
+        );
+        Ok(())
+    }
+}
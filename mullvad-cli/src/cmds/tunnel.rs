@@ -39,6 +39,8 @@ fn create_wireguard_subcommand() -> clap::App<'static, 'static> {
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
         .subcommand(create_wireguard_mtu_subcommand())
         .subcommand(create_wireguard_keys_subcommand());
+    #[cfg(any(target_os = "linux", windows))]
+    let subcmd = subcmd.subcommand(create_wireguard_interface_name_subcommand());
     #[cfg(windows)]
     {
         subcmd.subcommand(create_wireguard_use_wg_nt_subcommand())
@@ -60,6 +62,18 @@ fn create_wireguard_mtu_subcommand() -> clap::App<'static, 'static> {
         )
 }
 
+#[cfg(any(target_os = "linux", windows))]
+fn create_wireguard_interface_name_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("interface-name")
+        .about("Configure the name of the wireguard tunnel interface")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(clap::SubCommand::with_name("unset").about("Use the default interface name"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(clap::Arg::with_name("name").required(true)),
+        )
+}
+
 fn create_wireguard_keys_subcommand() -> clap::App<'static, 'static> {
     clap::SubCommand::with_name("key")
         .about("Manage your wireguard key")
@@ -170,6 +184,14 @@ impl Tunnel {
                 _ => unreachable!("unhandled command"),
             },
 
+            #[cfg(any(target_os = "linux", windows))]
+            ("interface-name", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_interface_name_get().await,
+                ("set", Some(matches)) => Self::process_wireguard_interface_name_set(matches).await,
+                ("unset", _) => Self::process_wireguard_interface_name_unset().await,
+                _ => unreachable!("unhandled command"),
+            },
+
             #[cfg(windows)]
             ("use-wireguard-nt", Some(matches)) => match matches.subcommand() {
                 ("get", _) => Self::process_wireguard_use_wg_nt_get().await,
@@ -210,6 +232,38 @@ impl Tunnel {
         Ok(())
     }
 
+    #[cfg(any(target_os = "linux", windows))]
+    async fn process_wireguard_interface_name_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options().await?;
+        let name = tunnel_options.wireguard.unwrap().interface_name;
+        println!(
+            "interface name: {}",
+            if !name.is_empty() {
+                name
+            } else {
+                "unset".to_string()
+            },
+        );
+        Ok(())
+    }
+
+    #[cfg(any(target_os = "linux", windows))]
+    async fn process_wireguard_interface_name_set(matches: &clap::ArgMatches<'_>) -> Result<()> {
+        let name = matches.value_of("name").unwrap();
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_interface_name(name.to_string()).await?;
+        println!("Wireguard interface name has been updated");
+        Ok(())
+    }
+
+    #[cfg(any(target_os = "linux", windows))]
+    async fn process_wireguard_interface_name_unset() -> Result<()> {
+        let mut rpc = new_rpc_client().await?;
+        rpc.set_wireguard_interface_name(String::new()).await?;
+        println!("Wireguard interface name has been unset");
+        Ok(())
+    }
+
     #[cfg(windows)]
     async fn process_wireguard_use_wg_nt_get() -> Result<()> {
         let tunnel_options = Self::get_tunnel_options().await?;
@@ -0,0 +1,218 @@
+use crate::{Error, Result, BIN_NAME};
+use mullvad_types::relay_list::{RelayList, RELAYS_FILENAME};
+use std::{fs, io::Write, path::Path, time::SystemTime};
+
+/// Name of the hidden subcommand that the completion scripts call to list location codes.
+const LIST_LOCATIONS_COMMAND: &str = "list-location-codes";
+
+/// Completes the arguments of any `location` subcommand with codes from the relay list.
+const BASH_LOCATION_HOOK: &str = r#"
+_mullvad_with_locations() {
+    local i
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        if [[ "${COMP_WORDS[i]}" == "location" ]]; then
+            local codes
+            codes="$(mullvad list-location-codes "${COMP_WORDS[@]:i+1:COMP_CWORD-i-1}" 2>/dev/null)"
+            COMPREPLY=($(compgen -W "${codes}" -- "${COMP_WORDS[COMP_CWORD]}"))
+            return 0
+        fi
+    done
+    _mullvad "$@"
+}
+
+complete -F _mullvad_with_locations -o bashdefault -o default mullvad
+"#;
+
+const ZSH_LOCATION_HOOK: &str = r#"
+_mullvad() {
+    local index=${words[(i)location]}
+    if (( index < CURRENT )); then
+        local -a codes
+        codes=(${(f)"$(mullvad list-location-codes ${words[index+1,CURRENT-1]} 2>/dev/null)"})
+        compadd -a codes
+    else
+        _mullvad_generated "$@"
+    fi
+}
+
+_mullvad "$@""#;
+
+const FISH_LOCATION_HOOK: &str = r#"
+function __mullvad_complete_location
+    set -l tokens (commandline -opc)
+    set -l index (contains -i -- location $tokens)
+    mullvad list-location-codes $tokens[(math $index + 1)..-1] 2>/dev/null
+end
+
+complete -c mullvad -n "__fish_seen_subcommand_from location" -f -a "(__mullvad_complete_location)"
+"#;
+
+pub fn get_subcommands() -> Vec<clap::App<'static, 'static>> {
+    vec![
+        clap::SubCommand::with_name("shell-completions")
+            .about(
+                "Generates completion scripts for your shell. Location codes are completed from \
+                 the relay list used by the daemon, except in PowerShell and Elvish",
+            )
+            .arg(
+                clap::Arg::with_name("SHELL")
+                    .required(true)
+                    .possible_values(&clap::Shell::variants()[..])
+                    .help("The shell to generate the script for"),
+            )
+            .arg(clap::Arg::with_name("DIR").help(
+                "Output directory where the shell completions are written. They are printed if \
+                 no directory is given",
+            )),
+        clap::SubCommand::with_name(LIST_LOCATIONS_COMMAND)
+            .about("List the location codes that can follow the given ones")
+            .arg(clap::Arg::with_name("country").index(1))
+            .arg(clap::Arg::with_name("city").index(2))
+            .arg(clap::Arg::with_name("hostname").index(3))
+            .setting(clap::AppSettings::Hidden),
+    ]
+}
+
+pub fn run(
+    mut app: clap::App<'static, 'static>,
+    name: &str,
+    matches: &clap::ArgMatches<'_>,
+) -> Result<()> {
+    if name == LIST_LOCATIONS_COMMAND {
+        return list_location_codes(
+            matches.value_of("country"),
+            matches.value_of("city"),
+            matches.value_of("hostname"),
+        );
+    }
+
+    let shell: clap::Shell = matches
+        .value_of("SHELL")
+        .unwrap()
+        .parse()
+        .expect("Invalid shell");
+    let mut script = vec![];
+    app.gen_completions_to(BIN_NAME, shell, &mut script);
+    let script = add_location_hook(String::from_utf8_lossy(&script).into_owned(), shell);
+
+    match matches.value_of_os("DIR") {
+        Some(dir) => {
+            let path = Path::new(dir).join(script_file_name(shell));
+            fs::write(path, script)
+                .map_err(|error| Error::FileError("Failed to write completion script", error))
+        }
+        None => std::io::stdout()
+            .write_all(script.as_bytes())
+            .map_err(|error| Error::FileError("Failed to print completion script", error)),
+    }
+}
+
+/// Appends the dynamic completion of location codes to a generated script. The script is
+/// returned unchanged if it does not look like expected.
+fn add_location_hook(script: String, shell: clap::Shell) -> String {
+    match shell {
+        clap::Shell::Bash => script + BASH_LOCATION_HOOK,
+        clap::Shell::Fish => script + FISH_LOCATION_HOOK,
+        clap::Shell::Zsh => {
+            // Rename the generated completion function so that it can be wrapped.
+            let trailing_call = "_mullvad \"$@\"";
+            match (
+                script.find("\n_mullvad() {"),
+                script.trim_end().ends_with(trailing_call),
+            ) {
+                (Some(_), true) => {
+                    let script = script.trim_end();
+                    script[..script.len() - trailing_call.len()].replacen(
+                        "\n_mullvad() {",
+                        "\n_mullvad_generated() {",
+                        1,
+                    ) + ZSH_LOCATION_HOOK
+                        + "\n"
+                }
+                _ => script,
+            }
+        }
+        _ => script,
+    }
+}
+
+/// The file names that clap uses when writing completion scripts to a directory.
+fn script_file_name(shell: clap::Shell) -> String {
+    match shell {
+        clap::Shell::Bash => format!("{}.bash", BIN_NAME),
+        clap::Shell::Fish => format!("{}.fish", BIN_NAME),
+        clap::Shell::Zsh => format!("_{}", BIN_NAME),
+        clap::Shell::PowerShell => format!("_{}.ps1", BIN_NAME),
+        clap::Shell::Elvish => format!("{}.elv", BIN_NAME),
+    }
+}
+
+/// Prints the countries, the cities in `country`, or the relays in `city`, depending on how many
+/// codes are given. Nothing is printed once a hostname has been given.
+fn list_location_codes(
+    country: Option<&str>,
+    city: Option<&str>,
+    hostname: Option<&str>,
+) -> Result<()> {
+    if hostname.is_some() {
+        return Ok(());
+    }
+    let relay_list = read_relay_list()?;
+    let codes: Vec<&str> = match (country, city) {
+        (None, _) => relay_list
+            .countries
+            .iter()
+            .map(|country| country.code.as_str())
+            .collect(),
+        (Some(country), None) => relay_list
+            .countries
+            .iter()
+            .filter(|candidate| candidate.code.eq_ignore_ascii_case(country))
+            .flat_map(|country| country.cities.iter().map(|city| city.code.as_str()))
+            .collect(),
+        (Some(country), Some(city)) => relay_list
+            .countries
+            .iter()
+            .filter(|candidate| candidate.code.eq_ignore_ascii_case(country))
+            .flat_map(|country| country.cities.iter())
+            .filter(|candidate| candidate.code.eq_ignore_ascii_case(city))
+            .flat_map(|city| city.relays.iter().map(|relay| relay.hostname.as_str()))
+            .collect(),
+    };
+    for code in codes {
+        println!("{}", code);
+    }
+    Ok(())
+}
+
+/// Reads the relay list that the daemon uses. This is the cached relay list, unless the relay list
+/// bundled with the app is newer or there is no cached relay list.
+fn read_relay_list() -> Result<RelayList> {
+    let cached = mullvad_paths::get_cache_dir()
+        .map_err(Error::PathError)
+        .and_then(|cache_dir| read_relay_list_file(&cache_dir.join(RELAYS_FILENAME)));
+    let bundled = read_relay_list_file(&mullvad_paths::get_resource_dir().join(RELAYS_FILENAME));
+
+    match (cached, bundled) {
+        (Ok((cached_modified, cached)), Ok((bundled_modified, _)))
+            if cached_modified > bundled_modified =>
+        {
+            Ok(cached)
+        }
+        (_, Ok((_, bundled))) => Ok(bundled),
+        (cached, Err(_)) => cached.map(|(_, relay_list)| relay_list),
+    }
+}
+
+/// Reads a relay list and returns it along with when it was last modified.
+fn read_relay_list_file(path: &Path) -> Result<(SystemTime, RelayList)> {
+    let file = fs::File::open(path)
+        .map_err(|error| Error::FileError("Failed to open the relay list", error))?;
+    let modified = file
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .map_err(|error| Error::FileError("Failed to read the relay list metadata", error))?;
+    let relay_list =
+        serde_json::from_reader(std::io::BufReader::new(file)).map_err(Error::ParseRelayList)?;
+    Ok((modified, relay_list))
+}
@@ -5,7 +5,8 @@ use mullvad_management_interface::types::{
     },
     tunnel_state,
     tunnel_state::State::*,
-    ErrorState, KeygenEvent, ProxyType, TransportProtocol, TunnelEndpoint, TunnelState, TunnelType,
+    ErrorState, GeoIpLocation, KeygenEvent, ObfuscationType, ProxyType, TransportProtocol,
+    TunnelEndpoint, TunnelState, TunnelType,
 };
 use mullvad_types::auth_failed::AuthFailed;
 use std::fmt::Write;
@@ -34,28 +35,66 @@ pub fn print_state(state: &TunnelState) {
     match state.state.as_ref().unwrap() {
         Error(error) => print_error_state(error.error_state.as_ref().unwrap()),
         Connected(tunnel_state::Connected { relay_info }) => {
-            let endpoint = relay_info
-                .as_ref()
-                .unwrap()
-                .tunnel_endpoint
-                .as_ref()
-                .unwrap();
-            println!("Connected to {}", format_endpoint(&endpoint));
+            let relay_info = relay_info.as_ref().unwrap();
+            let endpoint = relay_info.tunnel_endpoint.as_ref().unwrap();
+            println!(
+                "Connected to {}{}",
+                format_endpoint(&endpoint),
+                format_fallback_tier(relay_info.location.as_ref())
+            );
         }
         Connecting(tunnel_state::Connecting { relay_info }) => {
-            let endpoint = relay_info
-                .as_ref()
-                .unwrap()
-                .tunnel_endpoint
-                .as_ref()
-                .unwrap();
-            println!("Connecting to {}...", format_endpoint(&endpoint));
+            let relay_info = relay_info.as_ref().unwrap();
+            let endpoint = relay_info.tunnel_endpoint.as_ref().unwrap();
+            println!(
+                "Connecting to {}{}...",
+                format_endpoint(&endpoint),
+                format_fallback_tier(relay_info.location.as_ref())
+            );
         }
         Disconnected(_) => println!("Disconnected"),
         Disconnecting(_) => println!("Disconnecting..."),
     }
 }
 
+/// Prints how the connection is built: the relays, the obfuscation and the tunnel interface.
+pub fn print_connection_details(state: &TunnelState) {
+    let relay_info = match state.state.as_ref().unwrap() {
+        Connected(tunnel_state::Connected { relay_info })
+        | Connecting(tunnel_state::Connecting { relay_info }) => relay_info.as_ref().unwrap(),
+        _ => return,
+    };
+    let endpoint = relay_info.tunnel_endpoint.as_ref().unwrap();
+
+    if let Some(location) = &relay_info.location {
+        if !location.entry_hostname.is_empty() {
+            println!("Entry relay: {}", location.entry_hostname);
+        }
+        if !location.hostname.is_empty() {
+            println!("Exit relay: {}", location.hostname);
+        }
+        if !location.bridge_hostname.is_empty() {
+            println!("Bridge: {}", location.bridge_hostname);
+        }
+    }
+    match ObfuscationType::from_i32(endpoint.obfuscation) {
+        Some(ObfuscationType::ObfuscationUdp2tcp) => println!("Obfuscation: UDP-over-TCP"),
+        Some(ObfuscationType::ObfuscationSocks5) => println!("Obfuscation: SOCKS5 proxy"),
+        Some(ObfuscationType::ObfuscationPlugin) => println!("Obfuscation: transport plugin"),
+        Some(ObfuscationType::NoObfuscation) | None => println!("Obfuscation: none"),
+    }
+    if !endpoint.tunnel_interface.is_empty() {
+        println!("Tunnel interface: {}", endpoint.tunnel_interface);
+    }
+}
+
+fn format_fallback_tier(location: Option<&GeoIpLocation>) -> String {
+    match location.map(|location| location.relay_fallback_tier) {
+        Some(tier) if tier > 0 => format!(" (using relay fallback tier {})", tier),
+        _ => String::new(),
+    }
+}
+
 fn format_endpoint(endpoint: &TunnelEndpoint) -> String {
     let tunnel_type = TunnelType::from_i32(endpoint.tunnel_type).expect("invalid tunnel protocol");
     let mut out = format!(
@@ -145,6 +184,18 @@ fn error_state_to_string(error_state: &ErrorState) -> String {
                 )
             };
         }
+        AccountExpired => "The account has no time left",
+        KillSwitchVerificationFailed => {
+            "Failed to verify that the firewall rules and routes are in place"
+        }
+        ClockSkew => {
+            let skew_secs = error_state.clock_skew_seconds;
+            return format!(
+                "The system clock is {} seconds {}. Set the correct time and reconnect",
+                skew_secs.unsigned_abs(),
+                if skew_secs >= 0 { "ahead" } else { "behind" }
+            );
+        }
         Ipv6Unavailable => "Failed to configure IPv6 because it's disabled in the platform",
         SetFirewallPolicyError => {
             return policy_error_to_string(error_state.policy_error.as_ref().unwrap())
@@ -177,6 +228,9 @@ fn tunnel_parameter_error_to_string(parameter_error: i32) -> &'static str {
         GenerationError::CustomTunnelHostResolutionError => {
             "Can't resolve hostname for custom tunnel host"
         }
+        GenerationError::RelayKeyMismatch => {
+            "The public key of the relay does not match the relay list"
+        }
     }
 }
 
@@ -193,7 +247,7 @@ fn policy_error_to_string(policy_error: &FirewallPolicyError) -> String {
     format!("Failed to set firewall policy: {}", cause)
 }
 
-fn format_protocol(protocol: TransportProtocol) -> &'static str {
+pub fn format_protocol(protocol: TransportProtocol) -> &'static str {
     match protocol {
         TransportProtocol::Udp => "UDP",
         TransportProtocol::Tcp => "TCP",
@@ -8,6 +8,7 @@ use talpid_types::ErrorExt;
 pub use mullvad_management_interface::{self, new_rpc_client};
 
 mod cmds;
+mod completions;
 mod format;
 mod location;
 mod state;
@@ -43,6 +44,15 @@ pub enum Error {
 
     #[error(display = "Failed to listen for status updates")]
     StatusListenerFailed,
+
+    #[error(display = "{}", _0)]
+    FileError(&'static str, #[error(source)] io::Error),
+
+    #[error(display = "Failed to obtain the cache directory")]
+    PathError(#[error(source)] mullvad_paths::Error),
+
+    #[error(display = "Failed to parse the relay list")]
+    ParseRelayList(#[error(source)] serde_json::Error),
 }
 
 #[tokio::main]
@@ -74,34 +84,12 @@ async fn run() -> Result<()> {
     let commands = cmds::get_commands();
     let app = build_cli(&commands);
 
-    let app = app.subcommand(
-        clap::SubCommand::with_name("shell-completions")
-            .about("Generates completion scripts for your shell")
-            .arg(
-                clap::Arg::with_name("SHELL")
-                    .required(true)
-                    .possible_values(&clap::Shell::variants()[..])
-                    .help("The shell to generate the script for"),
-            )
-            .arg(
-                clap::Arg::with_name("DIR")
-                    .default_value("./")
-                    .help("Output directory where the shell completions are written"),
-            )
-            .setting(clap::AppSettings::Hidden),
-    );
+    let app = app.subcommands(completions::get_subcommands());
 
     let app_matches = app.get_matches();
     match app_matches.subcommand() {
-        ("shell-completions", Some(sub_matches)) => {
-            let shell = sub_matches
-                .value_of("SHELL")
-                .unwrap()
-                .parse()
-                .expect("Invalid shell");
-            let out_dir = sub_matches.value_of_os("DIR").unwrap();
-            build_cli(&commands).gen_completions(BIN_NAME, shell, out_dir);
-            Ok(())
+        (sub_name @ ("shell-completions" | "list-location-codes"), Some(sub_matches)) => {
+            completions::run(build_cli(&commands), sub_name, sub_matches)
         }
         (sub_name, Some(sub_matches)) => {
             if let Some(cmd) = commands.get(sub_name) {
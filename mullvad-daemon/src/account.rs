@@ -5,11 +5,14 @@ use mullvad_rpc::{
     rest::{self, Error as RestError, MullvadRestHandle},
     AccountsProxy,
 };
-use mullvad_types::account::{AccountToken, VoucherSubmission};
-use std::{future::Future, time::Duration};
-use talpid_core::future_retry::{
+use mullvad_types::account::{
+    AccountToken, ExpiryWarning, PlayPurchase, PlayPurchasePaymentToken, VoucherSubmission,
+};
+use std::{cmp, future::Future, time::Duration};
+use talpid_future::retry::{
     constant_interval, retry_future, retry_future_n, ExponentialBackoff, Jittered,
 };
+use talpid_types::ErrorExt;
 
 const RETRY_ACTION_INTERVAL: Duration = Duration::ZERO;
 const RETRY_ACTION_MAX_RETRIES: usize = 2;
@@ -18,6 +21,17 @@ const RETRY_EXPIRY_CHECK_INTERVAL_INITIAL: Duration = Duration::from_secs(4);
 const RETRY_EXPIRY_CHECK_INTERVAL_FACTOR: u32 = 5;
 const RETRY_EXPIRY_CHECK_INTERVAL_MAX: Duration = Duration::from_secs(24 * 60 * 60);
 
+const TIME_ADDED_CHECK_INTERVAL_INITIAL: Duration = Duration::from_secs(15);
+const TIME_ADDED_CHECK_INTERVAL_FACTOR: u32 = 2;
+const TIME_ADDED_CHECK_INTERVAL_MAX: Duration = Duration::from_secs(10 * 60);
+
+/// How often the expiry of the current account is checked when no warning threshold is near.
+const EXPIRY_WATCH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+/// How long to wait before checking the expiry again after a failed check.
+const EXPIRY_WATCH_RETRY_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Margin added to the wait for a threshold, so that the threshold has passed after the wait.
+const EXPIRY_WATCH_THRESHOLD_MARGIN: Duration = Duration::from_secs(1);
+
 pub struct Account(());
 
 #[derive(Clone)]
@@ -69,6 +83,67 @@ impl AccountHandle {
         result
     }
 
+    /// Checks the expiry of an account periodically and passes it to `on_expiry`. Checks are made
+    /// more often around the times when a new expiry warning takes effect.
+    pub fn watch_expiry(
+        &self,
+        token: AccountToken,
+        on_expiry: impl Fn(DateTime<Utc>) + Send + 'static,
+    ) -> AbortHandle {
+        let handle = self.clone();
+        let (future, abort_handle) = abortable(async move {
+            loop {
+                let _ = handle.api_availability.wait_online().await;
+                let delay = match handle.check_expiry(token.clone()).await {
+                    Ok(expiry) => {
+                        on_expiry(expiry);
+                        next_expiry_check(expiry, Utc::now())
+                    }
+                    Err(error) => {
+                        log::debug!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to check account expiry")
+                        );
+                        EXPIRY_WATCH_RETRY_INTERVAL
+                    }
+                };
+                tokio::time::sleep(delay).await;
+            }
+        });
+        tokio::spawn(future);
+        abort_handle
+    }
+
+    /// Polls the API until the account has time left, and returns the new expiry date.
+    pub fn wait_for_time_added(&self, token: AccountToken) -> impl Future<Output = DateTime<Utc>> {
+        let proxy = self.proxy.clone();
+        let api_availability = self.api_availability.clone();
+        let retry_strategy = Jittered::jitter(
+            ExponentialBackoff::new(
+                TIME_ADDED_CHECK_INTERVAL_INITIAL,
+                TIME_ADDED_CHECK_INTERVAL_FACTOR,
+            )
+            .max_delay(TIME_ADDED_CHECK_INTERVAL_MAX),
+        );
+        let future_generator = move || {
+            let wait_online = api_availability.wait_online();
+            let expiry_fut = proxy.get_expiry(token.clone());
+            let api_availability = api_availability.clone();
+            async move {
+                let _ = wait_online.await;
+                let result = expiry_fut.await;
+                handle_expiry_result_inner(&result, &api_availability);
+                result.ok().filter(|expiry| *expiry >= Utc::now())
+            }
+        };
+        let should_retry = |expiry: &Option<DateTime<Utc>>| expiry.is_none();
+        async move {
+            retry_future(future_generator, should_retry, retry_strategy)
+                .await
+                .expect("expiry is only returned once it is in the future")
+        }
+    }
+
     pub async fn submit_voucher(
         &mut self,
         account_token: AccountToken,
@@ -90,6 +165,41 @@ impl AccountHandle {
         result
     }
 
+    pub fn init_play_purchase(
+        &self,
+        account_token: AccountToken,
+    ) -> impl Future<Output = Result<PlayPurchasePaymentToken, rest::Error>> {
+        let mut proxy = self.proxy.clone();
+        let api_handle = self.api_availability.clone();
+        retry_future_n(
+            move || proxy.init_play_purchase(account_token.clone()),
+            move |result| Self::should_retry(result, &api_handle),
+            constant_interval(RETRY_ACTION_INTERVAL),
+            RETRY_ACTION_MAX_RETRIES,
+        )
+    }
+
+    pub async fn verify_play_purchase(
+        &self,
+        account_token: AccountToken,
+        play_purchase: PlayPurchase,
+    ) -> Result<(), rest::Error> {
+        let mut proxy = self.proxy.clone();
+        let api_handle = self.api_availability.clone();
+        let result = retry_future_n(
+            move || proxy.verify_play_purchase(account_token.clone(), play_purchase.clone()),
+            move |result| Self::should_retry(result, &api_handle),
+            constant_interval(RETRY_ACTION_INTERVAL),
+            RETRY_ACTION_MAX_RETRIES,
+        )
+        .await;
+        if result.is_ok() {
+            self.initial_check_abort_handle.abort();
+            self.api_availability.resume_background();
+        }
+        result
+    }
+
     fn should_retry<T>(result: &Result<T, RestError>, api_handle: &ApiAvailabilityHandle) -> bool {
         match result {
             Err(error) if error.is_network_error() => !api_handle.get_state().is_offline(),
@@ -148,6 +258,19 @@ impl Account {
     }
 }
 
+/// Returns how long to wait before checking the expiry of an account that expires at `expiry`.
+fn next_expiry_check(expiry: DateTime<Utc>, now: DateTime<Utc>) -> Duration {
+    ExpiryWarning::next_threshold(expiry, now)
+        .and_then(|threshold| (threshold - now).to_std().ok())
+        .map(|until_threshold| {
+            cmp::min(
+                until_threshold + EXPIRY_WATCH_THRESHOLD_MARGIN,
+                EXPIRY_WATCH_INTERVAL,
+            )
+        })
+        .unwrap_or(EXPIRY_WATCH_INTERVAL)
+}
+
 fn handle_expiry_result_inner(
     result: &Result<chrono::DateTime<chrono::Utc>, mullvad_rpc::rest::Error>,
     api_availability: &ApiAvailabilityHandle,
@@ -171,3 +294,28 @@ fn handle_expiry_result_inner(
         Err(_) => false,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_next_expiry_check() {
+        let now = Utc::now();
+        assert_eq!(
+            next_expiry_check(now + chrono::Duration::days(30), now),
+            EXPIRY_WATCH_INTERVAL
+        );
+        assert_eq!(
+            next_expiry_check(
+                now + chrono::Duration::days(3) + chrono::Duration::hours(1),
+                now
+            ),
+            Duration::from_secs(60 * 60) + EXPIRY_WATCH_THRESHOLD_MARGIN
+        );
+        assert_eq!(
+            next_expiry_check(now - chrono::Duration::days(1), now),
+            EXPIRY_WATCH_INTERVAL
+        );
+    }
+}
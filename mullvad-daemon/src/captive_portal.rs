@@ -0,0 +1,366 @@
+//! Detects captive portals and temporarily lets traffic to them through the firewall, so that the
+//! user can log in to the network while all other traffic remains blocked.
+//!
+//! The portal is detected by sending a plain HTTP request to the API. A captive portal intercepts
+//! the request and redirects it to its login page, while the API itself does not redirect to any
+//! other host. A firewall exception for HTTP and HTTPS to the login page is then installed for a
+//! bounded time. All exceptions only apply to the physical interface, and are removed when the
+//! unlock ends or is aborted.
+
+use futures::channel::{mpsc, oneshot};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Weak,
+    time::Duration,
+};
+use talpid_core::tunnel_state_machine::TunnelCommand;
+use talpid_types::net::{Endpoint, TransportProtocol};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    task::JoinHandle,
+};
+
+/// Scope of the firewall exception that lets the probe through.
+const PROBE_EXCEPTION_SCOPE: &str = "captive-portal-probe";
+/// Scope of the firewall exception that lets the lookup of the portal host through.
+const DNS_EXCEPTION_SCOPE: &str = "captive-portal-dns";
+/// Scope of the firewall exception that lets traffic to the portal through.
+const PORTAL_EXCEPTION_SCOPE: &str = "captive-portal";
+
+const PROBE_PORT: u16 = 80;
+/// Ports that the portal may be reached on.
+const PORTAL_PORTS: [u16; 2] = [80, 443];
+const DNS_PORT: u16 = 53;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Upper bound on the size of the response headers read from the probe.
+const MAX_RESPONSE_SIZE: usize = 16 * 1024;
+
+/// The longest time that the firewall may be unlocked for.
+pub const MAX_UNLOCK_DURATION: Duration = Duration::from_secs(10 * 60);
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "The tunnel state machine is not running")]
+    TunnelStateMachineUnavailable,
+
+    #[error(display = "Failed to update the firewall exceptions")]
+    SetFirewallException,
+
+    #[error(display = "The captive portal probe failed")]
+    Probe(#[error(source)] io::Error),
+
+    #[error(display = "The captive portal probe timed out")]
+    ProbeTimeout,
+
+    #[error(display = "No captive portal was detected")]
+    NoPortalDetected,
+
+    #[error(display = "Failed to resolve the captive portal host {}", _0)]
+    ResolvePortal(String, #[error(source)] io::Error),
+
+    #[error(
+        display = "No DNS servers to resolve the captive portal host {} with",
+        _0
+    )]
+    NoSystemDnsServers(String),
+}
+
+/// Detects a captive portal and allows traffic to it for `duration`. Responds with the address of
+/// the portal once the exception is in place. Any exception installed by a previous call is
+/// removed first. Every exception installed by the task is removed when it completes or is
+/// aborted.
+pub(crate) fn spawn_unlock(
+    tunnel_command_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    api_address: IpAddr,
+    duration: Duration,
+    result_tx: oneshot::Sender<Result<IpAddr, Error>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(error) = set_exception(&tunnel_command_tx, PORTAL_EXCEPTION_SCOPE, None).await {
+            let _ = result_tx.send(Err(error));
+            return;
+        }
+
+        let portal = match detect_portal(&tunnel_command_tx, api_address).await {
+            Ok(portal) => portal,
+            Err(error) => {
+                let _ = result_tx.send(Err(error));
+                return;
+            }
+        };
+        let endpoints = PORTAL_PORTS
+            .iter()
+            .map(|port| Endpoint::new(portal, *port, TransportProtocol::Tcp))
+            .collect();
+        let exception = match ExceptionGuard::add(
+            &tunnel_command_tx,
+            PORTAL_EXCEPTION_SCOPE,
+            endpoints,
+        )
+        .await
+        {
+            Ok(exception) => exception,
+            Err(error) => {
+                let _ = result_tx.send(Err(error));
+                return;
+            }
+        };
+        log::info!(
+            "Allowing traffic to captive portal at {} for {} seconds",
+            portal,
+            duration.as_secs()
+        );
+        let _ = result_tx.send(Ok(portal));
+
+        tokio::time::sleep(duration).await;
+        log::info!("Blocking traffic to captive portal at {}", portal);
+        if let Err(error) = exception.remove().await {
+            log::error!("Failed to remove captive portal exception: {}", error);
+        }
+    })
+}
+
+/// Probes for a captive portal with a temporary firewall exception for the API in place, and
+/// resolves the host that it redirects to.
+async fn detect_portal(
+    tunnel_command_tx: &Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    api_address: IpAddr,
+) -> Result<IpAddr, Error> {
+    let exception = ExceptionGuard::add(
+        tunnel_command_tx,
+        PROBE_EXCEPTION_SCOPE,
+        vec![Endpoint::new(
+            api_address,
+            PROBE_PORT,
+            TransportProtocol::Tcp,
+        )],
+    )
+    .await?;
+    let result = tokio::time::timeout(PROBE_TIMEOUT, probe(api_address))
+        .await
+        .unwrap_or(Err(Error::ProbeTimeout));
+    exception.remove().await?;
+
+    let portal_host = result?.ok_or(Error::NoPortalDetected)?;
+    match portal_host.parse() {
+        Ok(address) => Ok(address),
+        Err(_) => resolve_portal(tunnel_command_tx, portal_host).await,
+    }
+}
+
+/// Resolves the portal host with the DNS servers of the network, since DNS is otherwise blocked or
+/// sent through the tunnel. The servers are only reachable while the lookup is in progress.
+async fn resolve_portal(
+    tunnel_command_tx: &Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    portal_host: String,
+) -> Result<IpAddr, Error> {
+    let servers = system_dns_servers(tunnel_command_tx).await?;
+    if servers.is_empty() {
+        return Err(Error::NoSystemDnsServers(portal_host));
+    }
+    let endpoints = servers
+        .iter()
+        .flat_map(|server| {
+            [TransportProtocol::Udp, TransportProtocol::Tcp]
+                .into_iter()
+                .map(move |protocol| Endpoint::new(*server, DNS_PORT, protocol))
+        })
+        .collect();
+    let exception = ExceptionGuard::add(tunnel_command_tx, DNS_EXCEPTION_SCOPE, endpoints).await?;
+    let result = tokio::time::timeout(
+        PROBE_TIMEOUT,
+        talpid_core::resolver::lookup_with_servers(&portal_host, &servers),
+    )
+    .await
+    .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "Lookup timed out")));
+    exception.remove().await?;
+
+    let addresses = result.map_err(|error| Error::ResolvePortal(portal_host.clone(), error))?;
+    addresses.into_iter().next().ok_or_else(|| {
+        Error::ResolvePortal(
+            portal_host,
+            io::Error::new(io::ErrorKind::NotFound, "No addresses found"),
+        )
+    })
+}
+
+/// Sends a plain HTTP request to the API. Returns the host that the response redirects to, unless
+/// it is the API itself.
+async fn probe(api_address: IpAddr) -> Result<Option<String>, Error> {
+    let api_host = mullvad_rpc::api_host();
+    let mut stream = TcpStream::connect(SocketAddr::new(api_address, PROBE_PORT))
+        .await
+        .map_err(Error::Probe)?;
+    let request = format!(
+        "GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        api_host
+    );
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(Error::Probe)?;
+
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 4096];
+    while !response.windows(4).any(|window| window == b"\r\n\r\n")
+        && response.len() < MAX_RESPONSE_SIZE
+    {
+        let read = stream.read(&mut buffer).await.map_err(Error::Probe)?;
+        if read == 0 {
+            break;
+        }
+        response.extend_from_slice(&buffer[..read]);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    Ok(redirect_host(&response).filter(|host| {
+        !host.eq_ignore_ascii_case(api_host) && host.parse::<IpAddr>().ok() != Some(api_address)
+    }))
+}
+
+/// Returns the host of the `Location` header if `response` is an HTTP redirect.
+fn redirect_host(response: &str) -> Option<String> {
+    let mut lines = response.lines();
+    let status = lines.next()?.split_whitespace().nth(1)?;
+    if !matches!(status, "301" | "302" | "303" | "307" | "308") {
+        return None;
+    }
+    let location = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("location"))
+        .map(|(_, value)| value.trim())?;
+
+    let authority = location.split_once("://")?.1;
+    let authority = authority
+        .split(|c| c == '/' || c == '?' || c == '#')
+        .next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = if let Some(ipv6_host) = host.strip_prefix('[') {
+        ipv6_host.split(']').next()?
+    } else {
+        host.split(':').next()?
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some(host.to_owned())
+}
+
+/// A firewall exception that is removed when the guard is dropped, so that it does not outlive
+/// an aborted task.
+struct ExceptionGuard {
+    tunnel_command_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    scope: &'static str,
+    installed: bool,
+}
+
+impl ExceptionGuard {
+    async fn add(
+        tunnel_command_tx: &Weak<mpsc::UnboundedSender<TunnelCommand>>,
+        scope: &'static str,
+        endpoints: Vec<Endpoint>,
+    ) -> Result<Self, Error> {
+        let guard = ExceptionGuard {
+            tunnel_command_tx: tunnel_command_tx.clone(),
+            scope,
+            installed: true,
+        };
+        set_exception(tunnel_command_tx, scope, Some(endpoints)).await?;
+        Ok(guard)
+    }
+
+    /// Removes the exception and waits for the result.
+    async fn remove(mut self) -> Result<(), Error> {
+        self.installed = false;
+        set_exception(&self.tunnel_command_tx, self.scope, None).await
+    }
+}
+
+impl Drop for ExceptionGuard {
+    fn drop(&mut self) {
+        if !self.installed {
+            return;
+        }
+        log::debug!("Removing abandoned firewall exception \"{}\"", self.scope);
+        let (tx, _rx) = oneshot::channel();
+        if let Some(tunnel_command_tx) = self.tunnel_command_tx.upgrade() {
+            let _ = tunnel_command_tx
+                .unbounded_send(TunnelCommand::SetFirewallException(self.scope, None, tx));
+        }
+    }
+}
+
+async fn set_exception(
+    tunnel_command_tx: &Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    scope: &'static str,
+    endpoints: Option<Vec<Endpoint>>,
+) -> Result<(), Error> {
+    let (tx, rx) = oneshot::channel();
+    tunnel_command_tx
+        .upgrade()
+        .ok_or(Error::TunnelStateMachineUnavailable)?
+        .unbounded_send(TunnelCommand::SetFirewallException(scope, endpoints, tx))
+        .map_err(|_| Error::TunnelStateMachineUnavailable)?;
+    match rx.await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(Error::SetFirewallException),
+        Err(_) => Err(Error::TunnelStateMachineUnavailable),
+    }
+}
+
+async fn system_dns_servers(
+    tunnel_command_tx: &Weak<mpsc::UnboundedSender<TunnelCommand>>,
+) -> Result<Vec<IpAddr>, Error> {
+    let (tx, rx) = oneshot::channel();
+    tunnel_command_tx
+        .upgrade()
+        .ok_or(Error::TunnelStateMachineUnavailable)?
+        .unbounded_send(TunnelCommand::GetSystemDnsServers(tx))
+        .map_err(|_| Error::TunnelStateMachineUnavailable)?;
+    rx.await.map_err(|_| Error::TunnelStateMachineUnavailable)
+}
+
+#[cfg(test)]
+mod test {
+    use super::redirect_host;
+
+    #[test]
+    fn test_redirect_host() {
+        assert_eq!(
+            redirect_host(
+                "HTTP/1.1 302 Found\r\nContent-Length: 0\r\nLocation: http://10.0.0.1:8080/login\
+                 ?url=x\r\n\r\n"
+            ),
+            Some("10.0.0.1".to_owned())
+        );
+        assert_eq!(
+            redirect_host("HTTP/1.1 301 Moved\r\nlocation: https://portal.example.com\r\n\r\n"),
+            Some("portal.example.com".to_owned())
+        );
+        assert_eq!(
+            redirect_host("HTTP/1.1 307 Temporary Redirect\r\nLocation: http://[fe80::1]/\r\n\r\n"),
+            Some("fe80::1".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_no_redirect_host() {
+        assert_eq!(
+            redirect_host("HTTP/1.1 200 OK\r\nLocation: http://a/\r\n\r\n"),
+            None
+        );
+        assert_eq!(
+            redirect_host("HTTP/1.1 302 Found\r\n\r\nLocation: http://a/\r\n"),
+            None
+        );
+        assert_eq!(
+            redirect_host("HTTP/1.1 302 Found\r\nLocation: /login\r\n\r\n"),
+            None
+        );
+        assert_eq!(redirect_host(""), None);
+    }
+}
@@ -1,15 +1,19 @@
 use clap::{crate_authors, crate_description, crate_name, App, Arg};
 
-use crate::version;
+use crate::{logging::LogFormat, version};
+use talpid_core::logging::RotationLimits;
 
 #[derive(Debug)]
 pub struct Config {
     pub log_level: log::LevelFilter,
     pub log_to_file: bool,
     pub log_stdout_timestamps: bool,
+    pub log_format: LogFormat,
+    pub log_rotation_limits: RotationLimits,
     pub run_as_service: bool,
     pub register_service: bool,
     pub restart_service: bool,
+    pub initialize_firewall_and_exit: bool,
 }
 
 pub fn get_config() -> &'static Config {
@@ -30,18 +34,32 @@ pub fn create_config() -> Config {
     };
     let log_to_file = !matches.is_present("disable_log_to_file");
     let log_stdout_timestamps = !matches.is_present("disable_stdout_timestamps");
+    let log_format = matches
+        .value_of("log_format")
+        .map(|format| format.parse().expect("invalid log format"))
+        .unwrap_or(LogFormat::Text);
+    let mut log_rotation_limits = RotationLimits::default();
+    if let Some(max_size) = matches.value_of("log_max_size") {
+        let max_size: u64 = max_size.parse().expect("invalid log size");
+        log_rotation_limits.max_size = max_size * 1024 * 1024;
+    }
 
     let run_as_service = cfg!(windows) && matches.is_present("run_as_service");
     let register_service = cfg!(windows) && matches.is_present("register_service");
     let restart_service = cfg!(windows) && matches.is_present("restart_service");
+    let initialize_firewall_and_exit =
+        cfg!(target_os = "linux") && matches.is_present("initialize_early_boot_firewall");
 
     Config {
         log_level,
         log_to_file,
         log_stdout_timestamps,
+        log_format,
+        log_rotation_limits,
         run_as_service,
         register_service,
         restart_service,
+        initialize_firewall_and_exit,
     }
 }
 
@@ -57,6 +75,8 @@ lazy_static::lazy_static! {
     MULLVAD_RPC_SOCKET_PATH    Location of the management interface device.
                                It refers to Unix domain socket on Unix based platforms, and named pipe on Windows.
                                [Default: {}]
+    MULLVAD_DEBUG_COMMANDS     Set to 1 to allow management interface commands that are only meant
+                               for testing, such as connecting to an arbitrary endpoint.
 
 ",
         mullvad_paths::get_default_resource_dir().display(),
@@ -87,6 +107,27 @@ fn create_app() -> App<'static, 'static> {
             Arg::with_name("disable_stdout_timestamps")
                 .long("disable-stdout-timestamps")
                 .help("Don't log timestamps when logging to stdout, useful when running as a systemd service")
+        )
+        .arg(
+            Arg::with_name("log_format")
+                .long("log-format")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .help("Format of the log lines. The json format writes one JSON object per line"),
+        )
+        .arg(
+            Arg::with_name("log_max_size")
+                .long("log-max-size")
+                .takes_value(true)
+                .value_name("MiB")
+                .validator(|value| {
+                    value
+                        .parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|_| "must be a whole number of MiB".to_owned())
+                })
+                .help("Size at which the daemon log is rotated. Only one old log is kept"),
         );
 
     if cfg!(windows) {
@@ -106,5 +147,12 @@ fn create_app() -> App<'static, 'static> {
                 .help("Restarts the existing system service"),
         )
     }
+    if cfg!(target_os = "linux") {
+        app = app.arg(
+            Arg::with_name("initialize_early_boot_firewall")
+                .long("initialize-early-boot-firewall")
+                .help("Block all traffic if lockdown mode is enabled, then exit. Used during early boot, before the daemon has started"),
+        )
+    }
     app
 }
@@ -0,0 +1,64 @@
+//! Blocks all traffic during early boot on Linux, before networking is started, if lockdown mode
+//! is enabled. This runs as a separate systemd unit and exits once the rules are in place. The
+//! daemon atomically replaces them with its own policy when it starts.
+
+use crate::settings::SettingsPersister;
+use talpid_core::firewall::{
+    self, Firewall, FirewallArguments, FirewallPolicy, InitialFirewallState,
+};
+use talpid_types::ErrorExt;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Unable to get settings dir")]
+    SettingsDirError(#[error(source)] mullvad_paths::Error),
+
+    #[error(display = "Failed to initialize the firewall")]
+    InitFirewallError(#[error(source)] firewall::Error),
+
+    #[error(display = "Failed to apply the blocking firewall policy")]
+    ApplyPolicyError(#[error(source)] firewall::Error),
+}
+
+/// Applies a blocking firewall policy if lockdown mode is enabled in the settings. Nothing is
+/// done if the settings cannot be read, since the daemon would use the defaults in that case.
+pub async fn initialize_firewall() -> Result<(), Error> {
+    let settings_dir = mullvad_paths::settings_dir().map_err(Error::SettingsDirError)?;
+    let settings = match SettingsPersister::read(&settings_dir).await {
+        Ok(settings) => settings,
+        Err(error) => {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Not blocking traffic during boot")
+            );
+            return Ok(());
+        }
+    };
+
+    if !settings.block_when_disconnected {
+        log::info!("Lockdown mode is disabled. Not blocking traffic during boot");
+        return Ok(());
+    }
+
+    let mut firewall = Firewall::new(FirewallArguments {
+        initial_state: InitialFirewallState::None,
+        allow_lan: settings.allow_lan,
+        lan_allow_list: settings.lan_allow_list.clone(),
+        blocked_state_policy: settings.blocked_state_policy,
+    })
+    .map_err(Error::InitFirewallError)?;
+
+    // The API address is not known yet, so it is allowed by the daemon once it has started
+    firewall
+        .apply_policy(FirewallPolicy::Blocked {
+            allow_lan: settings.allow_lan,
+            lan_allow_list: settings.lan_allow_list,
+            allowed_endpoint: None,
+            blocked_state_policy: settings.blocked_state_policy,
+        })
+        .map_err(Error::ApplyPolicyError)?;
+
+    log::info!("Blocking traffic until the daemon has started");
+    Ok(())
+}
@@ -0,0 +1,155 @@
+//! Flags that gate daemon features independently of the user facing settings. New features can be
+//! rolled out enabled or disabled by default, and a feature that misbehaves can be turned off
+//! without removing the setting that controls it.
+//!
+//! The state of a flag can be overridden in the settings. Flags that are not overridden have their
+//! default state.
+
+use mullvad_types::settings::Settings;
+use std::collections::BTreeMap;
+
+/// A daemon feature that is gated by a flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Preferring relays with a low measured latency.
+    LowLatencyRelaySelection,
+    /// Using the WireGuardNT driver instead of wireguard-go.
+    #[cfg(windows)]
+    WireguardNt,
+    /// Only allowing the API through the firewall while the daemon is using it.
+    OnDemandApiAccess,
+}
+
+impl Feature {
+    /// All features that are gated by a flag.
+    pub const ALL: &'static [Feature] = &[
+        Feature::LowLatencyRelaySelection,
+        #[cfg(windows)]
+        Feature::WireguardNt,
+        Feature::OnDemandApiAccess,
+    ];
+
+    /// The name of the flag, as it is stored in the settings.
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::LowLatencyRelaySelection => "low_latency_relay_selection",
+            #[cfg(windows)]
+            Feature::WireguardNt => "wireguard_nt",
+            Feature::OnDemandApiAccess => "on_demand_api_access",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Feature::LowLatencyRelaySelection => {
+                "Prefer relays with a low measured latency when it is enabled in the settings"
+            }
+            #[cfg(windows)]
+            Feature::WireguardNt => "Use WireGuardNT when it is enabled in the settings",
+            Feature::OnDemandApiAccess => {
+                "Only allow the API through the firewall while the daemon is using it"
+            }
+        }
+    }
+
+    pub fn enabled_by_default(self) -> bool {
+        match self {
+            Feature::LowLatencyRelaySelection => true,
+            #[cfg(windows)]
+            Feature::WireguardNt => true,
+            Feature::OnDemandApiAccess => true,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|feature| feature.name() == name)
+    }
+}
+
+/// The state of a flag.
+#[derive(Debug, Clone)]
+pub struct FlagStatus {
+    pub feature: Feature,
+    pub enabled: bool,
+    /// Whether the state comes from the settings rather than the default.
+    pub overridden: bool,
+}
+
+/// Returns whether `feature` is enabled, given the overrides in the settings.
+pub fn is_enabled(overrides: &BTreeMap<String, bool>, feature: Feature) -> bool {
+    overrides
+        .get(feature.name())
+        .copied()
+        .unwrap_or_else(|| feature.enabled_by_default())
+}
+
+/// Returns the state of all flags.
+pub fn statuses(overrides: &BTreeMap<String, bool>) -> Vec<FlagStatus> {
+    Feature::ALL
+        .iter()
+        .map(|&feature| FlagStatus {
+            feature,
+            enabled: is_enabled(overrides, feature),
+            overridden: overrides.contains_key(feature.name()),
+        })
+        .collect()
+}
+
+/// Turns off the settings of features whose flags are disabled. The result is what the rest of
+/// the daemon should act on, but it must never be saved or shown to the user.
+pub fn apply(mut settings: Settings) -> Settings {
+    let overrides = &settings.feature_flags;
+    if !is_enabled(overrides, Feature::LowLatencyRelaySelection) {
+        settings.prefer_low_latency = false;
+    }
+    #[cfg(windows)]
+    if !is_enabled(overrides, Feature::WireguardNt) {
+        settings.tunnel_options.wireguard.options.use_wireguard_nt = false;
+    }
+    settings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_overrides() {
+        let mut overrides = BTreeMap::new();
+        assert!(is_enabled(&overrides, Feature::LowLatencyRelaySelection));
+
+        overrides.insert("low_latency_relay_selection".to_owned(), false);
+        assert!(!is_enabled(&overrides, Feature::LowLatencyRelaySelection));
+
+        let statuses = statuses(&overrides);
+        let status = statuses
+            .iter()
+            .find(|status| status.feature == Feature::LowLatencyRelaySelection)
+            .unwrap();
+        assert!(!status.enabled);
+        assert!(status.overridden);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut settings = Settings::default();
+        settings.prefer_low_latency = true;
+        assert!(apply(settings.clone()).prefer_low_latency);
+
+        settings
+            .feature_flags
+            .insert(Feature::LowLatencyRelaySelection.name().to_owned(), false);
+        assert!(!apply(settings).prefer_low_latency);
+    }
+
+    #[test]
+    fn test_from_name() {
+        for &feature in Feature::ALL {
+            assert_eq!(Feature::from_name(feature.name()), Some(feature));
+        }
+        assert_eq!(Feature::from_name("unknown"), None);
+    }
+}
@@ -0,0 +1,47 @@
+//! Periodically asks the tunnel state machine to read back the firewall rules, so that rules
+//! removed or modified by another program are detected and restored.
+//!
+//! The firewall on Android is managed by the OS, so the watchdog is not used there.
+
+use crate::{DaemonEventSender, InternalDaemonEvent};
+use futures::channel::{mpsc, oneshot};
+use std::{sync::Weak, time::Duration};
+use talpid_core::{mpsc::Sender, tunnel_state_machine::TunnelCommand};
+
+/// How often the firewall rules are verified.
+const VERIFICATION_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Spawns a task that verifies the firewall rules every `VERIFICATION_INTERVAL`, and sends
+/// `InternalDaemonEvent::FirewallPolicyRestored` to the daemon whenever they had to be restored.
+/// The task exits when the tunnel state machine or the daemon stops listening.
+pub(crate) fn spawn(
+    tunnel_command_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    daemon_tx: DaemonEventSender,
+) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(VERIFICATION_INTERVAL).await;
+
+            let (result_tx, result_rx) = oneshot::channel();
+            let sent = tunnel_command_tx
+                .upgrade()
+                .map(|tx| {
+                    tx.unbounded_send(TunnelCommand::VerifyFirewallPolicy(result_tx))
+                        .is_ok()
+                })
+                .unwrap_or(false);
+            if !sent {
+                break;
+            }
+
+            if let Ok(true) = result_rx.await {
+                if daemon_tx
+                    .send(InternalDaemonEvent::FirewallPolicyRestored)
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        }
+    });
+}
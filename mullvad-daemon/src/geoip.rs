@@ -1,76 +1,274 @@
-use futures::join;
-use mullvad_rpc::{
-    self,
-    rest::{Error, RequestServiceHandle},
+//! Caches the location of this device as looked up with <https://am.i.mullvad.net>, so that all
+//! frontends share the same lookups. The cache is cleared whenever the tunnel state changes, and
+//! the location is looked up again once the tunnel is connected or disconnected.
+
+use crate::{DaemonEventSender, InternalDaemonEvent};
+use futures::{
+    channel::oneshot,
+    future::{abortable, AbortHandle, BoxFuture},
 };
-use mullvad_types::location::{AmIMullvad, GeoIpLocation};
+use mullvad_rpc::GeoIpProxy;
+use mullvad_types::location::GeoIpLocation;
+use std::time::{Duration, Instant};
 use talpid_types::ErrorExt;
 
-const URI_V4: &str = "https://ipv4.am.i.mullvad.net/json";
-const URI_V6: &str = "https://ipv6.am.i.mullvad.net/json";
-
-pub async fn send_location_request(
-    request_sender: RequestServiceHandle,
-) -> Result<GeoIpLocation, Error> {
-    let v4_sender = request_sender.clone();
-    let v4_future = async move {
-        let location = send_location_request_internal(URI_V4, v4_sender).await?;
-        Ok::<GeoIpLocation, Error>(GeoIpLocation::from(location))
-    };
-    let v6_sender = request_sender.clone();
-    let v6_future = async move {
-        let location = send_location_request_internal(URI_V6, v6_sender).await?;
-        Ok::<GeoIpLocation, Error>(GeoIpLocation::from(location))
-    };
-
-    let (v4_result, v6_result) = join!(v4_future, v6_future);
-
-    match (v4_result, v6_result) {
-        (Ok(mut v4), Ok(v6)) => {
-            v4.ipv6 = v6.ipv6;
-            v4.mullvad_exit_ip = v4.mullvad_exit_ip && v6.mullvad_exit_ip;
-            Ok(v4)
+/// How long a looked up location is used before it is looked up again.
+const LOCATION_TTL: Duration = Duration::from_secs(10 * 60);
+/// Minimum time between the start of two lookups. Lookups started sooner are delayed.
+const MIN_LOOKUP_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Looks up the location of this device. Resolves to `None` if the lookup fails.
+type LookupFn = Box<dyn Fn() -> BoxFuture<'static, Option<GeoIpLocation>> + Send + Sync>;
+
+pub(crate) struct LocationCache {
+    lookup_location: LookupFn,
+    daemon_tx: DaemonEventSender,
+    /// Incremented when the cache is cleared, so that the results of older lookups are ignored.
+    generation: u64,
+    location: Option<(GeoIpLocation, Instant)>,
+    /// Set while a lookup is in progress.
+    lookup: Option<AbortHandle>,
+    /// When the last lookup was started, or is delayed until.
+    last_lookup: Option<Instant>,
+    /// Requests waiting for the current lookup to complete.
+    waiting: Vec<oneshot::Sender<Option<GeoIpLocation>>>,
+}
+
+impl LocationCache {
+    pub fn new(proxy: GeoIpProxy, daemon_tx: DaemonEventSender) -> Self {
+        let lookup_location: LookupFn = Box::new(move || {
+            let proxy = proxy.clone();
+            Box::pin(async move {
+                proxy
+                    .get_location()
+                    .await
+                    .map_err(|error| {
+                        log::warn!(
+                            "{}",
+                            error.display_chain_with_msg("Unable to fetch GeoIP location")
+                        );
+                    })
+                    .ok()
+            })
+        });
+        Self::with_lookup(lookup_location, daemon_tx)
+    }
+
+    fn with_lookup(lookup_location: LookupFn, daemon_tx: DaemonEventSender) -> Self {
+        LocationCache {
+            lookup_location,
+            daemon_tx,
+            generation: 0,
+            location: None,
+            lookup: None,
+            last_lookup: None,
+            waiting: vec![],
+        }
+    }
+
+    /// Returns the last looked up location, unless it is too old.
+    pub fn get(&self) -> Option<GeoIpLocation> {
+        self.location
+            .as_ref()
+            .filter(|(_, looked_up_at)| looked_up_at.elapsed() < LOCATION_TTL)
+            .map(|(location, _)| location.clone())
+    }
+
+    /// Forgets the last looked up location and cancels any ongoing lookup. Waiting requests are
+    /// kept.
+    pub fn clear(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.location = None;
+        if let Some(lookup) = self.lookup.take() {
+            lookup.abort();
         }
-        (Ok(v4), Err(e)) => {
-            log_network_error(e, "IPv6");
-            Ok(v4)
+    }
+
+    /// Answers `tx` when the current lookup completes, starting one if none is in progress.
+    pub fn wait_for_lookup(&mut self, tx: oneshot::Sender<Option<GeoIpLocation>>) {
+        self.waiting.push(tx);
+        if self.lookup.is_none() {
+            self.start_lookup();
         }
-        (Err(e), Ok(v6)) => {
-            log_network_error(e, "IPv4");
-            Ok(v6)
+    }
+
+    /// Returns the requests that are waiting for a lookup.
+    pub fn take_waiting(&mut self) -> Vec<oneshot::Sender<Option<GeoIpLocation>>> {
+        std::mem::take(&mut self.waiting)
+    }
+
+    /// Looks up the location, replacing any ongoing lookup. The result is sent to the daemon as
+    /// `InternalDaemonEvent::LocationLookupCompleted`.
+    pub fn start_lookup(&mut self) {
+        if let Some(lookup) = self.lookup.take() {
+            lookup.abort();
         }
-        (Err(e_v4), Err(_)) => Err(e_v4),
+
+        let delay = self.schedule_lookup(Instant::now());
+        let lookup = (self.lookup_location)();
+        let daemon_tx = self.daemon_tx.clone();
+        let generation = self.generation;
+        let (future, abort_handle) = abortable(async move {
+            tokio::time::sleep(delay).await;
+            let location = lookup.await;
+            let _ = daemon_tx.send(InternalDaemonEvent::LocationLookupCompleted(
+                generation, location,
+            ));
+        });
+        tokio::spawn(future);
+        self.lookup = Some(abort_handle);
+    }
+
+    /// Returns how long a lookup started at `now` has to be delayed to respect
+    /// `MIN_LOOKUP_INTERVAL`, and records it as started once the delay has passed.
+    fn schedule_lookup(&mut self, now: Instant) -> Duration {
+        let delay = self
+            .last_lookup
+            .map(|last_lookup| (last_lookup + MIN_LOOKUP_INTERVAL).saturating_duration_since(now))
+            .unwrap_or_default();
+        self.last_lookup = Some(now + delay);
+        delay
     }
-}
 
-async fn send_location_request_internal(
-    uri: &'static str,
-    service: RequestServiceHandle,
-) -> Result<AmIMullvad, Error> {
-    let future_service = service.clone();
-    let request = mullvad_rpc::rest::RestRequest::get(uri)?;
-    let response = future_service.request(request).await?;
-    mullvad_rpc::rest::deserialize_body(response).await
+    /// Stores the result of a lookup. Returns `false` if the lookup was started before the cache
+    /// was last cleared, in which case the result is ignored.
+    pub fn lookup_completed(&mut self, generation: u64, location: Option<GeoIpLocation>) -> bool {
+        if generation != self.generation {
+            return false;
+        }
+        self.lookup = None;
+        if let Some(location) = location {
+            self.location = Some((location, Instant::now()));
+        }
+        true
+    }
 }
 
-fn log_network_error(err: Error, version: &'static str) {
-    let err_message = &format!("Unable to fetch {} GeoIP location", version);
-    match err {
-        Error::HyperError(hyper_err) if hyper_err.is_connect() => {
-            if let Some(cause) = hyper_err.into_cause() {
-                if let Some(err) = cause.downcast_ref::<std::io::Error>() {
-                    // Don't log ENETUNREACH errors, they are not informative.
-                    if err.raw_os_error() == Some(libc::ENETUNREACH) {
-                        return;
-                    }
-                    log::debug!("{}: Hyper connect error: {}", err_message, cause);
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{channel::mpsc, FutureExt, StreamExt};
+    use std::{future::Future, sync::Arc};
+
+    /// A daemon that only handles the events of a `LocationCache`.
+    struct TestDaemon {
+        cache: LocationCache,
+        events: mpsc::UnboundedReceiver<InternalDaemonEvent>,
+        _event_tx: Arc<mpsc::UnboundedSender<InternalDaemonEvent>>,
+    }
+
+    impl TestDaemon {
+        fn new(lookup: impl Fn() -> Option<GeoIpLocation> + Send + Sync + 'static) -> Self {
+            let (event_tx, events) = mpsc::unbounded();
+            let event_tx = Arc::new(event_tx);
+            let cache = LocationCache::with_lookup(
+                Box::new(move || futures::future::ready(lookup()).boxed()),
+                DaemonEventSender::new(Arc::downgrade(&event_tx)),
+            );
+            TestDaemon {
+                cache,
+                events,
+                _event_tx: event_tx,
+            }
+        }
+
+        /// Waits for a lookup to complete, and returns its generation and result.
+        async fn next_lookup(&mut self) -> (u64, Option<GeoIpLocation>) {
+            match self.events.next().await {
+                Some(InternalDaemonEvent::LocationLookupCompleted(generation, location)) => {
+                    (generation, location)
                 }
-            } else {
-                log::error!("Hyper Connection error did not contain a cause!");
+                _ => panic!("Expected a completed location lookup"),
             }
         }
-        any_other_error => {
-            log::debug!("{}", any_other_error.display_chain_with_msg(err_message));
+    }
+
+    fn test_location(country: &str) -> GeoIpLocation {
+        GeoIpLocation {
+            ipv4: None,
+            ipv6: None,
+            country: country.to_owned(),
+            city: None,
+            latitude: 0.0,
+            longitude: 0.0,
+            mullvad_exit_ip: false,
+            hostname: None,
+            bridge_hostname: None,
+            entry_hostname: None,
+            relay_fallback_tier: None,
         }
-    };
+    }
+
+    /// Runs `test` with the clock paused, so that delayed lookups complete immediately.
+    fn run(test: impl Future<Output = ()>) {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap()
+            .block_on(test)
+    }
+
+    #[test]
+    fn test_stale_lookup_is_ignored() {
+        run(async {
+            let mut daemon = TestDaemon::new(|| Some(test_location("Sweden")));
+            daemon.cache.start_lookup();
+            let (generation, location) = daemon.next_lookup().await;
+
+            // The tunnel state changed before the daemon handled the result
+            daemon.cache.clear();
+            assert!(!daemon.cache.lookup_completed(generation, location));
+            assert_eq!(daemon.cache.get(), None);
+
+            daemon.cache.start_lookup();
+            let (generation, location) = daemon.next_lookup().await;
+            assert!(daemon.cache.lookup_completed(generation, location));
+            assert_eq!(daemon.cache.get(), Some(test_location("Sweden")));
+        });
+    }
+
+    #[test]
+    fn test_lookup_delay() {
+        let mut daemon = TestDaemon::new(|| None);
+        let now = Instant::now();
+        let half_interval = MIN_LOOKUP_INTERVAL / 2;
+
+        assert_eq!(daemon.cache.schedule_lookup(now), Duration::ZERO);
+        assert_eq!(
+            daemon.cache.schedule_lookup(now + half_interval),
+            half_interval
+        );
+        // The delayed lookup counts as started when its delay has passed
+        assert_eq!(
+            daemon.cache.schedule_lookup(now + half_interval),
+            MIN_LOOKUP_INTERVAL + half_interval
+        );
+        assert_eq!(
+            daemon.cache.schedule_lookup(now + 4 * MIN_LOOKUP_INTERVAL),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_waiting_after_clear() {
+        run(async {
+            let mut daemon = TestDaemon::new(|| Some(test_location("Sweden")));
+            let (tx, rx) = oneshot::channel();
+            daemon.cache.wait_for_lookup(tx);
+
+            // The tunnel state changes before the lookup completes, which replaces it
+            daemon.cache.clear();
+            daemon.cache.start_lookup();
+            assert_eq!(daemon.cache.waiting.len(), 1);
+
+            let (generation, location) = daemon.next_lookup().await;
+            assert!(daemon.cache.lookup_completed(generation, location.clone()));
+            for tx in daemon.cache.take_waiting() {
+                let _ = tx.send(location.clone());
+            }
+            assert_eq!(rx.await.unwrap(), Some(test_location("Sweden")));
+            assert!(daemon.cache.take_waiting().is_empty());
+        });
+    }
 }
@@ -0,0 +1,287 @@
+//! Self-diagnostics that check the prerequisites of the daemon: the tunnel drivers, the
+//! permissions of the daemon, the firewall, the system clock and the connection to the API. The
+//! result is meant to point users and support at the likely cause of a tunnel that won't connect.
+//! The checks only read the state of the system, so running them never changes anything.
+
+use futures::channel::{mpsc, oneshot};
+use std::{
+    path::Path,
+    sync::Weak,
+    time::{Duration, Instant},
+};
+use talpid_core::tunnel_state_machine::TunnelCommand;
+use talpid_types::ErrorExt;
+
+/// How long to wait for each check that depends on the tunnel state machine or the API.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of a single check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// The check passed, but something was found that may cause problems.
+    Warning,
+    Failed,
+    /// The check could not be performed, usually because a check it depends on failed.
+    Skipped,
+}
+
+/// Result of a single check.
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: Status,
+    pub details: String,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, status: Status, details: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            details: details.into(),
+        }
+    }
+}
+
+/// Runs all checks and returns their results, in the order they were run.
+pub(crate) async fn run(
+    tunnel_command_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    api_proxy: mullvad_rpc::ApiProxy,
+) -> Vec<CheckResult> {
+    let mut results = vec![check_drivers(), check_permissions().await];
+    results.push(check_firewall(tunnel_command_tx).await);
+
+    let start = Instant::now();
+    let api_result = tokio::time::timeout(CHECK_TIMEOUT, api_proxy.get_api_addrs())
+        .await
+        .ok()
+        .map(|result| result.map(|_| start.elapsed()));
+    results.extend(api_checks(api_result));
+
+    for result in &results {
+        log::debug!(
+            "Health check \"{}\": {:?}: {}",
+            result.name,
+            result.status,
+            result.details
+        );
+    }
+    results
+}
+
+fn check_drivers() -> CheckResult {
+    const NAME: &str = "drivers";
+
+    #[cfg(windows)]
+    {
+        let resource_dir = mullvad_paths::get_resource_dir();
+        let missing: Vec<_> = ["wintun.dll", "mullvad-wireguard.dll"]
+            .iter()
+            .filter(|dll| !resource_dir.join(dll).exists())
+            .copied()
+            .collect();
+        if missing.is_empty() {
+            CheckResult::new(NAME, Status::Ok, "Wintun and WireGuardNT are installed")
+        } else {
+            CheckResult::new(
+                NAME,
+                Status::Failed,
+                format!(
+                    "Missing from {}: {}",
+                    resource_dir.display(),
+                    missing.join(", ")
+                ),
+            )
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        if Path::new("/dev/net/tun").exists() {
+            CheckResult::new(NAME, Status::Ok, "/dev/net/tun is present")
+        } else {
+            CheckResult::new(
+                NAME,
+                Status::Failed,
+                "/dev/net/tun is missing. The tun kernel module may not be loaded",
+            )
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        CheckResult::new(NAME, Status::Skipped, "utun devices are built into macOS")
+    }
+}
+
+async fn check_permissions() -> CheckResult {
+    const NAME: &str = "permissions";
+
+    #[cfg(unix)]
+    if !nix::unistd::Uid::effective().is_root() {
+        return CheckResult::new(NAME, Status::Failed, "The daemon is not running as root");
+    }
+
+    let dirs = [
+        ("settings", mullvad_paths::settings_dir()),
+        ("cache", mullvad_paths::cache_dir()),
+    ];
+    for (kind, dir) in dirs {
+        let result = match dir {
+            Ok(dir) => check_writable(&dir).await.map_err(|error| {
+                error.display_chain_with_msg(&format!("Cannot write to {}", dir.display()))
+            }),
+            Err(error) => Err(error.display_chain()),
+        };
+        if let Err(message) = result {
+            return CheckResult::new(
+                NAME,
+                Status::Failed,
+                format!("Bad {} directory: {}", kind, message),
+            );
+        }
+    }
+
+    CheckResult::new(
+        NAME,
+        Status::Ok,
+        "The settings and cache directories are writable",
+    )
+}
+
+/// Checks that `dir` is a directory that is not read-only, without writing to it.
+async fn check_writable(dir: &Path) -> std::io::Result<()> {
+    let metadata = tokio::fs::metadata(dir).await?;
+    if !metadata.is_dir() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "Not a directory",
+        ));
+    }
+    if metadata.permissions().readonly() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "The directory is read-only",
+        ));
+    }
+    Ok(())
+}
+
+async fn check_firewall(
+    tunnel_command_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+) -> CheckResult {
+    const NAME: &str = "firewall";
+
+    let (result_tx, result_rx) = oneshot::channel();
+    let sent = tunnel_command_tx
+        .upgrade()
+        .map(|tx| {
+            tx.unbounded_send(TunnelCommand::CheckFirewallPolicy(result_tx))
+                .is_ok()
+        })
+        .unwrap_or(false);
+    if !sent {
+        return CheckResult::new(NAME, Status::Failed, "The tunnel state machine has stopped");
+    }
+
+    match tokio::time::timeout(CHECK_TIMEOUT, result_rx).await {
+        Ok(Ok(Some(true))) => CheckResult::new(NAME, Status::Ok, "The firewall rules are in place"),
+        Ok(Ok(Some(false))) => CheckResult::new(
+            NAME,
+            Status::Failed,
+            "The firewall rules have been removed or modified by another program",
+        ),
+        Ok(Ok(None)) => CheckResult::new(
+            NAME,
+            Status::Warning,
+            "The firewall rules could not be read back",
+        ),
+        Ok(Err(_)) => CheckResult::new(
+            NAME,
+            Status::Failed,
+            "The tunnel state machine did not respond",
+        ),
+        Err(_) => CheckResult::new(
+            NAME,
+            Status::Failed,
+            format!(
+                "The tunnel state machine did not respond within {}s",
+                CHECK_TIMEOUT.as_secs()
+            ),
+        ),
+    }
+}
+
+/// Turns the result of an API request into the results of the system clock and API checks. The
+/// result is `None` if the request timed out. A wrong system clock can only be detected when it
+/// makes the API certificate invalid.
+fn api_checks(result: Option<Result<Duration, mullvad_rpc::rest::Error>>) -> [CheckResult; 2] {
+    const CLOCK: &str = "system clock";
+    const API: &str = "API";
+
+    match result {
+        Some(Ok(elapsed)) => [
+            CheckResult::new(
+                CLOCK,
+                Status::Ok,
+                "The API certificate is valid at the system time",
+            ),
+            CheckResult::new(
+                API,
+                Status::Ok,
+                format!("Reached the API in {} ms", elapsed.as_millis()),
+            ),
+        ],
+        Some(Err(mullvad_rpc::rest::Error::ClockSkew(skew))) => [
+            CheckResult::new(
+                CLOCK,
+                Status::Failed,
+                format!("The system clock is at least {}", skew),
+            ),
+            CheckResult::new(
+                API,
+                Status::Failed,
+                "The API certificate cannot be validated because of the system clock",
+            ),
+        ],
+        Some(Err(error)) => [
+            CheckResult::new(CLOCK, Status::Skipped, "The API could not be reached"),
+            CheckResult::new(API, Status::Failed, error.display_chain()),
+        ],
+        None => [
+            CheckResult::new(CLOCK, Status::Skipped, "The API could not be reached"),
+            CheckResult::new(
+                API,
+                Status::Failed,
+                format!("No response within {}s", CHECK_TIMEOUT.as_secs()),
+            ),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_api_checks() {
+        let [clock, api] = api_checks(Some(Ok(Duration::from_millis(120))));
+        assert_eq!(clock.status, Status::Ok);
+        assert_eq!(api.status, Status::Ok);
+        assert_eq!(api.details, "Reached the API in 120 ms");
+
+        let skew = mullvad_rpc::ClockSkew::Behind(Duration::from_secs(3 * 24 * 60 * 60));
+        let [clock, api] = api_checks(Some(Err(mullvad_rpc::rest::Error::ClockSkew(skew))));
+        assert_eq!(clock.status, Status::Failed);
+        assert_eq!(api.status, Status::Failed);
+
+        let [clock, api] = api_checks(Some(Err(mullvad_rpc::rest::Error::SendError)));
+        assert_eq!(clock.status, Status::Skipped);
+        assert_eq!(api.status, Status::Failed);
+
+        let [clock, api] = api_checks(None);
+        assert_eq!(clock.status, Status::Skipped);
+        assert_eq!(api.status, Status::Failed);
+    }
+}
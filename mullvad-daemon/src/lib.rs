@@ -6,52 +6,75 @@ extern crate serde;
 
 mod account;
 pub mod account_history;
+#[cfg(target_os = "macos")]
+pub mod captive_portal;
+#[cfg(target_os = "linux")]
+pub mod early_boot_firewall;
 pub mod exception_logging;
 #[cfg(target_os = "macos")]
 pub mod exclusion_gid;
+pub mod feature_flags;
+#[cfg(not(target_os = "android"))]
+mod firewall_watchdog;
 mod geoip;
+#[cfg(not(target_os = "android"))]
+pub mod health_check;
+mod location_names;
 pub mod logging;
 #[cfg(not(target_os = "android"))]
 pub mod management_interface;
 mod migrations;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod network_monitor;
 mod relays;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod resume_monitor;
 #[cfg(not(target_os = "android"))]
 pub mod rpc_uniqueness_check;
 pub mod runtime;
+mod secure_storage;
+#[cfg(windows)]
+pub mod session;
 pub mod settings;
 mod target_state;
 pub mod version;
 mod version_check;
 
-use crate::target_state::PersistentTargetState;
+use crate::{feature_flags::Feature, target_state::PersistentTargetState};
+use chrono::{DateTime, Utc};
 use futures::{
     channel::{mpsc, oneshot},
     future::{abortable, AbortHandle, Future},
     StreamExt,
 };
+use ipnetwork::IpNetwork;
 use mullvad_rpc::availability::ApiAvailabilityHandle;
 use mullvad_types::{
-    account::{AccountData, AccountToken, VoucherSubmission},
+    account::{
+        AccountData, AccountExpiryEvent, AccountToken, PlayPurchase, PlayPurchasePaymentToken,
+        VoucherSubmission,
+    },
+    auth_failed::AuthFailed,
     endpoint::MullvadEndpoint,
     location::GeoIpLocation,
     relay_constraints::{
-        BridgeSettings, BridgeState, Constraint, InternalBridgeConstraints, RelaySettings,
-        RelaySettingsUpdate,
+        BridgeSettings, BridgeState, Constraint, InternalBridgeConstraints, RelayConstraints,
+        RelaySettings, RelaySettingsUpdate, TransportPort,
     },
     relay_list::{Relay, RelayList},
-    settings::{DnsOptions, DnsState, Settings},
+    settings::{DnsOptions, DnsState, LogPrivacy, Settings},
     states::{TargetState, TunnelState},
     version::{AppVersion, AppVersionInfo},
     wireguard::{KeygenEvent, RotationInterval},
+    CustomTunnelEndpoint,
 };
 use settings::SettingsPersister;
 #[cfg(target_os = "android")]
 use std::os::unix::io::RawFd;
 #[cfg(not(target_os = "android"))]
 use std::path::Path;
-#[cfg(target_os = "windows")]
-use std::{collections::HashSet, ffi::OsString};
 use std::{
+    collections::{BTreeMap, HashMap},
     marker::PhantomData,
     mem,
     net::{IpAddr, Ipv4Addr},
@@ -60,20 +83,27 @@ use std::{
     sync::{mpsc as sync_mpsc, Arc, Weak},
     time::Duration,
 };
+#[cfg(target_os = "windows")]
+use std::{collections::HashSet, ffi::OsString};
 #[cfg(any(target_os = "linux", windows))]
 use talpid_core::split_tunnel;
 use talpid_core::{
     mpsc::Sender,
     tunnel_state_machine::{self, TunnelCommand, TunnelParametersGenerator},
 };
+use talpid_future::retry::{ExponentialBackoff, Jittered};
 #[cfg(target_os = "android")]
 use talpid_types::android::AndroidContext;
 use talpid_types::{
+    firewall::{BlockedStatePolicy, FirewallPolicyDescription},
     net::{
-        openvpn, AllowedEndpoint, Endpoint, TransportProtocol, TunnelEndpoint, TunnelParameters,
-        TunnelType,
+        openvpn, proxy::EntryProxy, AllowedEndpoint, Endpoint, TransportProtocol, TunnelEndpoint,
+        TunnelParameters, TunnelType,
+    },
+    tunnel::{
+        ErrorStateCause, ParameterGenerationError, StateMachineDebugInfo, TunnelDebugInfo,
+        TunnelStateTransition, TunnelStatistics,
     },
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
     ErrorExt,
 };
 #[cfg(not(target_os = "android"))]
@@ -91,13 +121,37 @@ const FIRST_KEY_PUSH_TIMEOUT: Duration = Duration::from_secs(5);
 /// Delay between generating a new WireGuard key and reconnecting
 const WG_RECONNECT_DELAY: Duration = Duration::from_secs(4 * 60);
 
+/// Retry strategy for sending problem reports while the API is unreachable. Attempts that fail
+/// while the device is offline are not counted, since the report is sent once it is back online.
+const PROBLEM_REPORT_RETRY_INTERVAL_INITIAL: Duration = Duration::from_secs(5);
+const PROBLEM_REPORT_RETRY_INTERVAL_FACTOR: u32 = 2;
+const PROBLEM_REPORT_RETRY_INTERVAL_MAX: Duration = Duration::from_secs(5 * 60);
+const PROBLEM_REPORT_MAX_RETRIES: usize = 8;
+
+/// Number of consecutive failed connection attempts after which the system clock is checked
+/// against the API. A wrong clock makes TLS certificates invalid and WireGuard handshakes fail.
+#[cfg(not(target_os = "android"))]
+const CLOCK_CHECK_RETRY_ATTEMPT: u32 = 4;
+/// The largest difference between the system clock and the time of the API that is tolerated.
+#[cfg(not(target_os = "android"))]
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
 /// When we want to block certain contents with the help of DNS server side,
 /// we compute the resolver IP to use based on these constants. The last
 /// byte can be ORed together to combine multiple block lists.
 const DNS_BLOCKING_IP_BASE: Ipv4Addr = Ipv4Addr::new(100, 64, 0, 0);
-const DNS_AD_BLOCKING_IP_BIT: u8 = 0b001;
-const DNS_TRACKER_BLOCKING_IP_BIT: u8 = 0b010;
-const DNS_MALWARE_BLOCKING_IP_BIT: u8 = 0b100;
+const DNS_AD_BLOCKING_IP_BIT: u8 = 0b00001;
+const DNS_TRACKER_BLOCKING_IP_BIT: u8 = 0b00010;
+const DNS_MALWARE_BLOCKING_IP_BIT: u8 = 0b00100;
+const DNS_ADULT_BLOCKING_IP_BIT: u8 = 0b01000;
+const DNS_GAMBLING_BLOCKING_IP_BIT: u8 = 0b10000;
+
+lazy_static::lazy_static! {
+    /// Allows management interface commands that are only meant for testing.
+    static ref DEBUG_COMMANDS_ENABLED: bool = std::env::var("MULLVAD_DEBUG_COMMANDS")
+        .map(|v| v == "1")
+        .unwrap_or(false);
+}
 
 pub type ResponseTx<T, E> = oneshot::Sender<Result<T, E>>;
 
@@ -148,6 +202,15 @@ pub enum Error {
     #[error(display = "No account history available for the token")]
     NoAccountTokenHistory,
 
+    #[error(display = "The key is used by this device and cannot be revoked")]
+    RevokeCurrentKey,
+
+    #[error(display = "The voucher code is not formatted correctly")]
+    InvalidVoucherFormat,
+
+    #[error(display = "Debug commands are disabled. Set MULLVAD_DEBUG_COMMANDS=1 to enable them")]
+    DebugCommandsDisabled,
+
     #[error(display = "Settings error")]
     SettingsError(#[error(source)] settings::Error),
 
@@ -190,11 +253,35 @@ pub enum Error {
     #[error(display = "Failed to read dir entries")]
     ReadDirError(#[error(source)] io::Error),
 
+    #[cfg(windows)]
+    #[error(display = "Drivers can only be cleaned up while disconnected")]
+    CleanupDriversWhileConnected,
+
     #[cfg(target_os = "macos")]
     #[error(display = "Failed to set exclusion group")]
     GroupIdError(#[error(source)] io::Error),
 }
 
+/// The current values of everything that frontends are notified about. New event subscribers can
+/// receive it so that they don't miss changes made before they subscribed.
+pub struct EventSnapshot {
+    pub tunnel_state: TunnelState,
+    pub settings: Settings,
+    pub relay_list: RelayList,
+    pub app_version_info: Option<AppVersionInfo>,
+}
+
+/// A problem report that was collected by a frontend and is sent to support by the daemon.
+pub struct ProblemReport {
+    pub email: String,
+    pub message: String,
+    pub log: String,
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Function that receives an [`EventSnapshot`]. It is called on the daemon's event loop.
+pub type EventSnapshotCallback = Box<dyn FnOnce(EventSnapshot) + Send>;
+
 /// Enum representing commands that can be sent to the daemon.
 pub enum DaemonCommand {
     /// Set target state. Does nothing if the daemon already has the state that is being set.
@@ -203,7 +290,8 @@ pub enum DaemonCommand {
     Reconnect(oneshot::Sender<bool>),
     /// Request the current state.
     GetState(oneshot::Sender<TunnelState>),
-    /// Get the current geographical location.
+    /// Get the current geographical location. Looked up locations are cached until the tunnel
+    /// state changes.
     GetCurrentLocation(oneshot::Sender<Option<GeoIpLocation>>),
     CreateNewAccount(ResponseTx<String, Error>),
     /// Request the metadata for an account.
@@ -215,12 +303,22 @@ pub enum DaemonCommand {
     GetWwwAuthToken(ResponseTx<String, Error>),
     /// Submit voucher to add time to the current account. Returns time added in seconds
     SubmitVoucher(ResponseTx<VoucherSubmission, Error>, String),
+    /// Start a Google Play purchase for the current account
+    InitPlayPurchase(ResponseTx<PlayPurchasePaymentToken, Error>),
+    /// Verify a Google Play purchase, which adds time to the current account
+    VerifyPlayPurchase(ResponseTx<(), Error>, PlayPurchase),
     /// Request account history
     GetAccountHistory(oneshot::Sender<Option<AccountToken>>),
     /// Remove the last used account, if there is one
     ClearAccountHistory(ResponseTx<(), Error>),
     /// Get the list of countries and cities where there are relays.
     GetRelayLocations(oneshot::Sender<RelayList>),
+    /// Get the last measured round-trip time to each relay, keyed by hostname.
+    GetRelayLatencies(oneshot::Sender<HashMap<String, Duration>>),
+    /// Call the function with the current state. Since it runs on the daemon's event loop, no
+    /// tunnel state or settings changes can happen until it returns. This lets a subscriber be
+    /// added without missing any changes.
+    GetEventSnapshot(EventSnapshotCallback),
     /// Trigger an asynchronous relay list update. This returns before the relay list is actually
     /// updated.
     UpdateRelayLocations,
@@ -228,20 +326,59 @@ pub enum DaemonCommand {
     SetAccount(ResponseTx<(), settings::Error>, Option<AccountToken>),
     /// Place constraints on the type of tunnel and relay
     UpdateRelaySettings(ResponseTx<(), settings::Error>, RelaySettingsUpdate),
+    /// Set the relay constraints to fall back to on repeated connection failures
+    SetRelayFallbackTiers(ResponseTx<(), settings::Error>, Vec<RelayConstraints>),
     /// Set the allow LAN setting.
     SetAllowLan(ResponseTx<(), settings::Error>, bool),
+    /// Limit LAN access to the given private networks, or allow all private networks if `None`.
+    SetLanAllowList(ResponseTx<(), settings::Error>, Option<Vec<IpNetwork>>),
+    /// Set which local network services are permitted while traffic is blocked.
+    SetBlockedStatePolicy(ResponseTx<(), settings::Error>, BlockedStatePolicy),
     /// Set the beta program setting.
     SetShowBetaReleases(ResponseTx<(), settings::Error>, bool),
+    /// Set how much identifying information is removed from the logs.
+    SetLogPrivacy(ResponseTx<(), settings::Error>, LogPrivacy),
     /// Set the block_when_disconnected setting.
     SetBlockWhenDisconnected(ResponseTx<(), settings::Error>, bool),
+    /// Set the block_when_account_expired setting.
+    SetBlockWhenAccountExpired(ResponseTx<(), settings::Error>, bool),
+    /// Set the strict_kill_switch setting.
+    SetStrictKillSwitch(ResponseTx<(), settings::Error>, bool),
+    /// Set whether relays with a low latency are preferred.
+    SetPreferLowLatency(ResponseTx<(), settings::Error>, bool),
+    /// Set whether API connections may go through the HTTP proxy configured in the OS
+    SetUseSystemProxy(ResponseTx<(), settings::Error>, bool),
+    /// Apply a JSON merge patch to the settings, changing all patched settings at once.
+    ApplySettingsPatch(ResponseTx<(), settings::Error>, String),
+    /// Serialize the settings that are not specific to this device
+    ExportSettings(ResponseTx<String, settings::Error>),
+    /// Replace the settings with settings that were exported on this or another device
+    ImportSettings(ResponseTx<(), settings::Error>, String),
+    /// Set whether clients of Internet Connection Sharing or Mobile Hotspot may use the tunnel.
+    #[cfg(windows)]
+    SetAllowConnectionSharing(ResponseTx<(), settings::Error>, bool),
+    /// Return whether Internet Connection Sharing or Mobile Hotspot is currently active.
+    #[cfg(windows)]
+    IsConnectionSharingActive(oneshot::Sender<bool>),
     /// Set the auto-connect setting.
     SetAutoConnect(ResponseTx<(), settings::Error>, bool),
+    /// Set whether a secured tunnel is reconnected when the device joins a different network.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    SetReconnectOnNetworkChange(ResponseTx<(), settings::Error>, bool),
+    /// Set the networks on which the tunnel is disconnected automatically.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    SetTrustedNetworks(
+        ResponseTx<(), settings::Error>,
+        mullvad_types::settings::TrustedNetworks,
+    ),
     /// Set the mssfix argument for OpenVPN
     SetOpenVpnMssfix(ResponseTx<(), settings::Error>, Option<u16>),
     /// Set proxy details for OpenVPN
     SetBridgeSettings(ResponseTx<(), settings::Error>, BridgeSettings),
     /// Set proxy state
     SetBridgeState(ResponseTx<(), settings::Error>, BridgeState),
+    /// Set or unset the SOCKS5 proxy that all traffic to the relay is sent through
+    SetEntryProxy(ResponseTx<(), settings::Error>, Option<EntryProxy>),
     /// Set if IPv6 should be enabled in the tunnel
     SetEnableIpv6(ResponseTx<(), settings::Error>, bool),
     /// Set DNS options or servers to use
@@ -249,20 +386,58 @@ pub enum DaemonCommand {
     /// Toggle macOS network check leak
     /// Set MTU for wireguard tunnels
     SetWireguardMtu(ResponseTx<(), settings::Error>, Option<u16>),
+    /// Set the name of the WireGuard tunnel interface, or use the default name if `None`
+    #[cfg(any(target_os = "linux", windows))]
+    SetWireguardInterfaceName(ResponseTx<(), settings::Error>, Option<String>),
     /// Set automatic key rotation interval for wireguard tunnels
     SetWireguardRotationInterval(ResponseTx<(), settings::Error>, Option<RotationInterval>),
     /// Get the daemon settings
     GetSettings(oneshot::Sender<Settings>),
+    /// Get the state of all feature flags
+    GetFeatureFlags(oneshot::Sender<Vec<feature_flags::FlagStatus>>),
+    /// Override the state of a feature flag, or restore its default state if it is `None`
+    SetFeatureFlag(ResponseTx<(), settings::Error>, String, Option<bool>),
+    /// Get the reason that the settings could not be loaded at startup, if they could not
+    GetSettingsLoadFailure(oneshot::Sender<Option<settings::LoadFailure>>),
     /// Generate new wireguard key
     GenerateWireguardKey(ResponseTx<wireguard::KeygenEvent, Error>),
     /// Return a public key of the currently set wireguard private key, if there is one
     GetWireguardKey(ResponseTx<Option<wireguard::PublicKey>, Error>),
     /// Verify if the currently set wireguard key is valid.
     VerifyWireguardKey(ResponseTx<bool, Error>),
+    /// List the wireguard keys that are registered on the account
+    ListAccountKeys(ResponseTx<Vec<wireguard::RegisteredKey>, Error>),
+    /// Remove a wireguard key that belongs to another device from the account
+    RevokeAccountKey(ResponseTx<(), Error>, wireguard::PublicKey),
     /// Get information about the currently running and latest app versions
     GetVersionInfo(oneshot::Sender<Option<AppVersionInfo>>),
     /// Get current version of the app
     GetCurrentVersion(oneshot::Sender<AppVersion>),
+    /// Describe the firewall policy that is currently being enforced
+    GetFirewallPolicy(oneshot::Sender<FirewallPolicyDescription>),
+    /// Describe the internals of the tunnel and the routes applied by the daemon
+    GetTunnelDebugInfo(oneshot::Sender<TunnelDebugInfo>),
+    /// Return the traffic through the current tunnel and in total
+    GetTunnelStatistics(oneshot::Sender<TunnelStatistics>),
+    /// Return the state of the tunnel state machine and its most recent transitions
+    GetTunnelStateMachineDebugInfo(oneshot::Sender<StateMachineDebugInfo>),
+    /// Check the drivers, permissions, firewall, system clock and API connectivity
+    #[cfg(not(target_os = "android"))]
+    RunHealthCheck(oneshot::Sender<Vec<health_check::CheckResult>>),
+    /// Remove Wintun adapters and network profiles left behind by crashes or earlier versions
+    #[cfg(windows)]
+    CleanupDrivers(ResponseTx<talpid_core::tunnel::DriverCleanup, Error>),
+    /// Get the API requests made since the daemon started
+    GetApiTrace(oneshot::Sender<Vec<mullvad_rpc::ApiTraceEntry>>),
+    /// Send a problem report to support. Sending is retried while the API is unreachable
+    SendProblemReport(ResponseTx<(), Error>, ProblemReport),
+    /// Connect to the given endpoint instead of a relay until the tunnel is disconnected. Only
+    /// allowed when debug commands are enabled
+    ConnectToEndpoint(ResponseTx<(), Error>, CustomTunnelEndpoint),
+    /// Detect a captive portal and allow traffic to it for the given duration. Responds with the
+    /// address of the portal
+    #[cfg(target_os = "macos")]
+    AllowCaptivePortal(ResponseTx<IpAddr, captive_portal::Error>, Duration),
     /// Remove settings and clear the cache
     #[cfg(not(target_os = "android"))]
     FactoryReset(ResponseTx<(), Error>),
@@ -278,6 +453,15 @@ pub enum DaemonCommand {
     /// Clear list of processes excluded from the tunnel
     #[cfg(target_os = "linux")]
     ClearSplitTunnelProcesses(ResponseTx<(), split_tunnel::Error>),
+    /// Exclude traffic to a network from the tunnel
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    AddSplitTunnelNetwork(ResponseTx<(), settings::Error>, IpNetwork),
+    /// Remove network from list of networks excluded from the tunnel
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    RemoveSplitTunnelNetwork(ResponseTx<(), settings::Error>, IpNetwork),
+    /// Clear list of networks excluded from the tunnel
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    ClearSplitTunnelNetworks(ResponseTx<(), settings::Error>),
     /// Exclude traffic of an application from the tunnel
     #[cfg(windows)]
     AddSplitTunnelApp(ResponseTx<(), Error>, PathBuf),
@@ -313,8 +497,9 @@ pub(crate) enum InternalDaemonEvent {
     ),
     /// A command sent to the daemon.
     Command(DaemonCommand),
-    /// Daemon shutdown triggered by a signal, ctrl-c or similar.
-    TriggerShutdown,
+    /// Daemon shutdown triggered by a signal, ctrl-c or similar. The boolean is false if the
+    /// daemon is stopped because the system is shutting down.
+    TriggerShutdown(bool),
     /// Wireguard key generation event
     WgKeyEvent(
         (
@@ -326,9 +511,41 @@ pub(crate) enum InternalDaemonEvent {
     NewAccountEvent(AccountToken, oneshot::Sender<Result<String, Error>>),
     /// The background job fetching new `AppVersionInfo`s got a new info object.
     NewAppVersionInfo(AppVersionInfo),
+    /// The API reported the expiry date of an account.
+    AccountExpiry(AccountToken, DateTime<Utc>),
+    /// A location lookup started by the location cache completed, or failed.
+    LocationLookupCompleted(u64, Option<GeoIpLocation>),
     /// The split tunnel paths or state were updated.
     #[cfg(target_os = "windows")]
     ExcludedPathsEvent(ExcludedPathsUpdate, oneshot::Sender<Result<(), Error>>),
+    /// A user session was connected, disconnected, locked, etc.
+    #[cfg(windows)]
+    SessionChange(session::SessionChange),
+    /// The computer woke up from sleep.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    Resumed,
+    /// The device went from being offline to being online.
+    NetworkOnline,
+    /// The device joined a different network, or the initial network was looked up.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    NetworkChanged(network_monitor::CurrentNetwork),
+    /// A new relay list was fetched and is now used by the relay selector.
+    RelayListUpdated,
+    /// The firewall rules had been removed or modified by another program, and were restored.
+    #[cfg(not(target_os = "android"))]
+    FirewallPolicyRestored,
+    /// Another program kept overwriting the DNS settings.
+    #[cfg(target_os = "macos")]
+    DnsInterference,
+    /// The API reported whether a WireGuard key is still registered on an account.
+    WireguardKeyValidated(
+        AccountToken,
+        talpid_types::net::wireguard::PublicKey,
+        Result<bool, wireguard::Error>,
+    ),
+    /// The system clock was checked against the API after repeated connection failures.
+    #[cfg(not(target_os = "android"))]
+    ClockChecked(Result<Option<mullvad_rpc::ClockSkew>, mullvad_rpc::rest::Error>),
 }
 
 #[cfg(target_os = "windows")]
@@ -518,6 +735,22 @@ pub trait EventListener {
 
     /// Notify clients of a key generation event.
     fn notify_key_event(&self, key_event: KeygenEvent);
+
+    /// Notify that the expiry of the current account changed or crossed a warning threshold.
+    fn notify_account_expiry(&self, event: AccountExpiryEvent);
+
+    /// Notify that the firewall rules had been removed or modified by another program, and were
+    /// restored.
+    #[cfg(not(target_os = "android"))]
+    fn notify_firewall_policy_restored(&self);
+
+    /// Notify that another program kept overwriting the DNS settings.
+    #[cfg(target_os = "macos")]
+    fn notify_dns_interference(&self);
+
+    /// Send the current tunnel state and settings again to the clients in a Windows session.
+    #[cfg(windows)]
+    fn resync_session(&self, session_id: u32, tunnel_state: TunnelState, settings: Settings);
 }
 
 pub struct Daemon<L: EventListener> {
@@ -530,6 +763,24 @@ pub struct Daemon<L: EventListener> {
     rx: mpsc::UnboundedReceiver<InternalDaemonEvent>,
     tx: DaemonEventSender,
     reconnection_job: Option<AbortHandle>,
+    /// Set while the current account is out of time. Aborts the job waiting for time to be added.
+    account_expired_job: Option<AbortHandle>,
+    /// Periodically checks the expiry of the current account.
+    account_expiry_watcher: Option<AbortHandle>,
+    /// The last account expiry that clients were notified of.
+    account_expiry_event: Option<AccountExpiryEvent>,
+    /// Set while the WireGuard key is being checked against the API, so that checks triggered in
+    /// quick succession are coalesced into one.
+    key_validation_in_flight: bool,
+    /// Set while the system clock is being checked against the API.
+    #[cfg(not(target_os = "android"))]
+    clock_check_in_flight: bool,
+    /// Set while a captive portal is being detected or is unlocked. Aborting it removes the
+    /// firewall exceptions that it installed.
+    #[cfg(target_os = "macos")]
+    captive_portal_unlock: Option<tokio::task::JoinHandle<()>>,
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    network_policy: network_monitor::NetworkPolicyState,
     event_listener: L,
     settings: SettingsPersister,
     account_history: account_history::AccountHistory,
@@ -539,10 +790,25 @@ pub struct Daemon<L: EventListener> {
     wireguard_key_manager: wireguard::KeyManager,
     version_updater_handle: version_check::VersionUpdaterHandle,
     relay_selector: relays::RelaySelector,
+    location_cache: geoip::LocationCache,
     last_generated_relay: Option<Relay>,
     last_generated_bridge_relay: Option<Relay>,
     last_generated_entry_relay: Option<Relay>,
+    /// Endpoint that is connected to instead of the relay settings, set by a debug command. It
+    /// is never saved, and is cleared when the tunnel is disconnected or the relay settings
+    /// change.
+    test_endpoint: Option<CustomTunnelEndpoint>,
+    /// Index of the relay fallback tier that `last_generated_relay` was selected from, if any.
+    last_generated_fallback_tier: Option<u32>,
+    /// Hostnames and WireGuard public keys of the relays used as peers by the last generated
+    /// tunnel parameters. These are checked against the relay list when it is updated.
+    last_generated_relay_keys: Vec<(String, talpid_types::net::wireguard::PublicKey)>,
     app_version_info: Option<AppVersionInfo>,
+    /// The user session attached to the physical console.
+    #[cfg(windows)]
+    console_session: Option<session::ConsoleSession>,
+    #[cfg(windows)]
+    resource_dir: PathBuf,
     shutdown_tasks: Vec<Pin<Box<dyn Future<Output = ()>>>>,
     /// oneshot channel that completes once the tunnel state machine has been shut down
     tunnel_state_machine_shutdown_signal: oneshot::Receiver<()>,
@@ -580,6 +846,9 @@ where
             );
         }
         let mut settings = SettingsPersister::load(&settings_dir).await;
+        talpid_core::logging::redact::set_aggressive(
+            settings.log_privacy == LogPrivacy::Aggressive,
+        );
 
         if version::is_beta_version() {
             let _ = settings.set_show_beta_releases(true).await;
@@ -619,6 +888,7 @@ where
         )
         .await
         .map_err(Error::InitRpcFactory)?;
+        rpc_runtime.set_use_system_proxy(settings.use_system_proxy);
 
         let api_availability = rpc_runtime.availability_handle();
         api_availability.suspend();
@@ -626,22 +896,46 @@ where
         let initial_api_endpoint =
             Self::get_allowed_endpoint(rpc_runtime.address_cache.peek_address());
 
+        #[cfg(windows)]
+        Self::log_driver_cleanup(&talpid_core::tunnel::cleanup_drivers(
+            &resource_dir,
+            settings
+                .tunnel_options
+                .wireguard
+                .options
+                .interface_name
+                .as_deref(),
+        ));
+
         let (offline_state_tx, offline_state_rx) = mpsc::unbounded();
+        #[cfg(target_os = "macos")]
+        let (dns_interference_tx, dns_interference_rx) = mpsc::unbounded();
         let tunnel_command_tx = tunnel_state_machine::spawn(
             tunnel_state_machine::InitialTunnelState {
                 allow_lan: settings.allow_lan,
+                lan_allow_list: settings.lan_allow_list.clone(),
+                blocked_state_policy: settings.blocked_state_policy,
                 block_when_disconnected: settings.block_when_disconnected,
+                strict_kill_switch: settings.strict_kill_switch,
                 dns_servers: Self::get_dns_resolvers(&settings.tunnel_options.dns_options),
+                #[cfg(target_os = "linux")]
+                dns_over_tls: Self::use_dns_over_tls(&settings.tunnel_options.dns_options),
                 allowed_endpoint: initial_api_endpoint,
                 reset_firewall: *target_state != TargetState::Secured,
                 #[cfg(windows)]
                 exclude_paths,
+                #[cfg(windows)]
+                allow_connection_sharing: settings.allow_connection_sharing,
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                excluded_networks: settings.excluded_networks.clone(),
             },
             tunnel_parameters_generator,
             log_dir,
             resource_dir.clone(),
             internal_event_tx.to_specialized_sender(),
             offline_state_tx,
+            #[cfg(target_os = "macos")]
+            dns_interference_tx,
             tunnel_state_machine_shutdown_tx,
             #[cfg(target_os = "macos")]
             exclusion_gid,
@@ -651,40 +945,62 @@ where
         .await
         .map_err(Error::TunnelError)?;
 
+        let api_access = rpc_runtime.api_access();
+        api_access.set_request_tx(Self::forward_api_access_requests(Arc::downgrade(
+            &tunnel_command_tx,
+        )));
+        // The firewall starts out allowing this address
+        api_access.set_api_address(rpc_runtime.address_cache.peek_address());
+        api_access.set_on_demand(feature_flags::is_enabled(
+            &settings.feature_flags,
+            Feature::OnDemandApiAccess,
+        ));
+
         let address_change_runtime = runtime.clone();
-        let tunnel_cmd_weak_tx = Arc::downgrade(&tunnel_command_tx);
         rpc_runtime.set_address_change_listener(move |address| {
-            let (result_tx, result_rx) = oneshot::channel();
-            let tx = tunnel_cmd_weak_tx.clone();
-            address_change_runtime.block_on(async move {
-                if let Some(tx) = tx.upgrade() {
-                    let _ = tx.unbounded_send(TunnelCommand::AllowEndpoint(
-                        Self::get_allowed_endpoint(address),
-                        result_tx,
-                    ));
-                    result_rx.await.map_err(|_| ())
-                } else {
-                    Err(())
-                }
-            })
+            match api_access.set_api_address(address) {
+                Some(result_rx) => address_change_runtime.block_on(result_rx).map_err(|_| ()),
+                None => Ok(()),
+            }
         });
 
         let rpc_handle = rpc_runtime.mullvad_rest_handle();
 
-        Self::forward_offline_state(api_availability.clone(), offline_state_rx).await;
+        Self::forward_offline_state(
+            api_availability.clone(),
+            offline_state_rx,
+            internal_event_tx.clone(),
+        )
+        .await;
+        #[cfg(target_os = "macos")]
+        Self::forward_dns_interference(dns_interference_rx, internal_event_tx.clone());
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        resume_monitor::spawn(internal_event_tx.clone());
+        #[cfg(not(target_os = "android"))]
+        firewall_watchdog::spawn(
+            Arc::downgrade(&tunnel_command_tx),
+            internal_event_tx.clone(),
+        );
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        network_monitor::spawn(internal_event_tx.clone());
 
         let relay_list_listener = event_listener.clone();
+        let relay_list_daemon_tx = internal_event_tx.clone();
         let on_relay_list_update = move |relay_list: &RelayList| {
             relay_list_listener.notify_relay_list(relay_list.clone());
+            let _ = relay_list_daemon_tx.send(InternalDaemonEvent::RelayListUpdated);
         };
 
-        let relay_selector = relays::RelaySelector::new(
+        let mut relay_selector = relays::RelaySelector::new(
             rpc_handle.clone(),
             on_relay_list_update,
             &resource_dir,
             &cache_dir,
             api_availability.clone(),
         );
+        relay_selector.set_prefer_low_latency(
+            feature_flags::apply(settings.to_settings()).prefer_low_latency,
+        );
 
         let app_version_info = version_check::load_cache(&cache_dir).await;
         let (version_updater, version_updater_handle) = version_check::VersionUpdater::new(
@@ -717,6 +1033,11 @@ where
         // Attempt to download a fresh relay list
         relay_selector.update().await;
 
+        let location_cache = geoip::LocationCache::new(
+            mullvad_rpc::GeoIpProxy::new(rpc_runtime.rest_handle()),
+            internal_event_tx.clone(),
+        );
+
         let mut daemon = Daemon {
             tunnel_command_tx,
             tunnel_state: TunnelState::Disconnected,
@@ -727,6 +1048,16 @@ where
             rx: internal_event_rx,
             tx: internal_event_tx,
             reconnection_job: None,
+            account_expired_job: None,
+            account_expiry_watcher: None,
+            account_expiry_event: None,
+            key_validation_in_flight: false,
+            #[cfg(not(target_os = "android"))]
+            clock_check_in_flight: false,
+            #[cfg(target_os = "macos")]
+            captive_portal_unlock: None,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            network_policy: network_monitor::NetworkPolicyState::default(),
             event_listener,
             settings,
             account_history,
@@ -736,21 +1067,57 @@ where
             wireguard_key_manager,
             version_updater_handle,
             relay_selector,
+            location_cache,
             last_generated_relay: None,
             last_generated_bridge_relay: None,
             last_generated_entry_relay: None,
+            test_endpoint: None,
+            last_generated_relay_keys: Vec::new(),
+            last_generated_fallback_tier: None,
             app_version_info,
+            #[cfg(windows)]
+            console_session: session::ConsoleSession::current(),
+            #[cfg(windows)]
+            resource_dir,
             shutdown_tasks: vec![],
             tunnel_state_machine_shutdown_signal,
         };
 
         daemon.ensure_wireguard_keys_for_current_account().await;
+        daemon.restart_account_expiry_watcher();
 
         api_availability.unsuspend();
 
         Ok(daemon)
     }
 
+    /// Forwards requests to allow the API through the firewall to the tunnel state machine.
+    fn forward_api_access_requests(
+        tunnel_command_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    ) -> mpsc::UnboundedSender<mullvad_rpc::ApiAccessRequest> {
+        let (request_tx, mut request_rx) = mpsc::unbounded();
+        tokio::spawn(async move {
+            while let Some((address, done_tx)) = request_rx.next().await {
+                let (result_tx, result_rx) = oneshot::channel();
+                let command = TunnelCommand::AllowEndpoint(
+                    address.map(Self::get_allowed_endpoint),
+                    result_tx,
+                );
+                let sent = tunnel_command_tx
+                    .upgrade()
+                    .map(|tx| tx.unbounded_send(command).is_ok())
+                    .unwrap_or(false);
+                if !sent {
+                    break;
+                }
+                if result_rx.await.is_ok() {
+                    let _ = done_tx.send(());
+                }
+            }
+        });
+        request_tx
+    }
+
     fn get_allowed_endpoint(api_address: std::net::SocketAddr) -> AllowedEndpoint {
         let endpoint = Endpoint::from_socket_address(api_address, TransportProtocol::Tcp);
 
@@ -790,6 +1157,12 @@ where
                 if options.default_options.block_malware {
                     last_byte |= DNS_MALWARE_BLOCKING_IP_BIT;
                 }
+                if options.default_options.block_adult_content {
+                    last_byte |= DNS_ADULT_BLOCKING_IP_BIT;
+                }
+                if options.default_options.block_gambling {
+                    last_byte |= DNS_GAMBLING_BLOCKING_IP_BIT;
+                }
 
                 if last_byte != 0 {
                     let mut dns_ip = DNS_BLOCKING_IP_BASE.octets();
@@ -809,6 +1182,13 @@ where
         }
     }
 
+    /// Returns whether the resolvers on the relay should be reached using DNS-over-TLS. Custom
+    /// DNS servers are always reached using plaintext DNS.
+    #[cfg(target_os = "linux")]
+    fn use_dns_over_tls(options: &DnsOptions) -> bool {
+        options.state == DnsState::Default && options.default_options.dns_over_tls
+    }
+
     /// Consume the `Daemon` and run the main event loop. Blocks until an error happens or a
     /// shutdown event is received.
     pub async fn run(mut self) -> Result<(), Error> {
@@ -901,7 +1281,7 @@ where
                     .await
             }
             Command(command) => self.handle_command(command).await,
-            TriggerShutdown => self.trigger_shutdown_event(),
+            TriggerShutdown(user_init_shutdown) => self.trigger_shutdown_event(user_init_shutdown),
             WgKeyEvent(key_event) => self.handle_wireguard_key_event(key_event).await,
             NewAccountEvent(account_token, tx) => {
                 self.handle_new_account_event(account_token, tx).await
@@ -909,8 +1289,37 @@ where
             NewAppVersionInfo(app_version_info) => {
                 self.handle_new_app_version_info(app_version_info)
             }
+            AccountExpiry(account_token, expiry) => {
+                self.handle_account_expiry(account_token, expiry).await
+            }
+            LocationLookupCompleted(generation, location) => {
+                self.handle_location_lookup_completed(generation, location)
+            }
             #[cfg(windows)]
             ExcludedPathsEvent(update, tx) => self.handle_new_excluded_paths(update, tx).await,
+            #[cfg(windows)]
+            SessionChange(change) => self.handle_session_change(change),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Resumed => self.validate_wireguard_key(),
+            NetworkOnline => self.validate_wireguard_key(),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            NetworkChanged(network) => self.handle_network_changed(network).await,
+            RelayListUpdated => self.handle_relay_list_updated(),
+            #[cfg(not(target_os = "android"))]
+            FirewallPolicyRestored => {
+                log::warn!(
+                    "The firewall rules were changed by another program and have been restored"
+                );
+                self.event_listener.notify_firewall_policy_restored();
+            }
+            #[cfg(target_os = "macos")]
+            DnsInterference => self.event_listener.notify_dns_interference(),
+            WireguardKeyValidated(account_token, public_key, result) => {
+                self.handle_wireguard_key_validated(account_token, public_key, result)
+                    .await
+            }
+            #[cfg(not(target_os = "android"))]
+            ClockChecked(result) => self.handle_clock_checked(result),
         }
     }
 
@@ -938,9 +1347,26 @@ where
 
         self.unschedule_reconnect();
 
+        logging::set_tunnel_state_context(match tunnel_state {
+            TunnelState::Disconnected => "disconnected",
+            TunnelState::Connecting { .. } => "connecting",
+            TunnelState::Connected { .. } => "connected",
+            TunnelState::Disconnecting(..) => "disconnecting",
+            TunnelState::Error(..) => "error",
+        });
         log::debug!("New tunnel state: {:?}", tunnel_state);
         match tunnel_state {
-            TunnelState::Disconnected => self.state.disconnected(),
+            TunnelState::Disconnected => {
+                self.state.disconnected();
+                self.relay_selector.probe_latencies();
+            }
+            TunnelState::Connected { ref endpoint, .. } => {
+                log::info!("Tunnel established: {}", endpoint);
+                if let Some(relay) = self.first_hop_relay() {
+                    self.relay_selector
+                        .record_connection_success(&relay.hostname);
+                }
+            }
             TunnelState::Error(ref error_state) => {
                 if error_state.is_blocking() {
                     log::info!(
@@ -954,124 +1380,542 @@ where
                     );
                 }
 
-                if let ErrorStateCause::AuthFailed(_) = error_state.cause() {
-                    self.schedule_reconnect(Duration::from_secs(60)).await
+                if let ErrorStateCause::AuthFailed(reason) = error_state.cause() {
+                    let account_expired = reason
+                        .as_ref()
+                        .map(|reason| AuthFailed::from(reason.as_str()).is_expired_account())
+                        .unwrap_or(false);
+                    if account_expired {
+                        self.handle_account_out_of_time().await;
+                    } else {
+                        self.schedule_reconnect(Duration::from_secs(60)).await
+                    }
                 }
             }
             _ => {}
         }
 
         self.tunnel_state = tunnel_state.clone();
+        self.refresh_location();
         self.event_listener.notify_new_state(tunnel_state);
     }
 
-    async fn reset_rpc_sockets_on_tunnel_state_transition(
-        &mut self,
-        tunnel_state_transition: &TunnelStateTransition,
-    ) {
-        match (&self.tunnel_state, &tunnel_state_transition) {
-            // only reset the API sockets if when connected or leaving the connected state
-            (&TunnelState::Connected { .. }, _) | (_, &TunnelStateTransition::Connected(_)) => {
-                self.rpc_handle.service().reset().await;
+    /// Clears the cached location after a tunnel state change, and looks it up again if the
+    /// tunnel is connected or disconnected. Otherwise, waiting requests are answered without a
+    /// lookup.
+    fn refresh_location(&mut self) {
+        self.location_cache.clear();
+        match self.tunnel_state {
+            TunnelState::Disconnected | TunnelState::Connected { .. } => {
+                self.location_cache.start_lookup()
             }
-            _ => (),
-        };
+            _ => {
+                let location = self.current_location(None);
+                for tx in self.location_cache.take_waiting() {
+                    Self::oneshot_send(tx, location.clone(), "current location");
+                }
+            }
+        }
     }
 
-    async fn handle_generate_tunnel_parameters(
+    fn handle_location_lookup_completed(
         &mut self,
-        tunnel_parameters_tx: &sync_mpsc::Sender<
-            Result<TunnelParameters, ParameterGenerationError>,
-        >,
-        retry_attempt: u32,
+        generation: u64,
+        location: Option<GeoIpLocation>,
     ) {
+        if !self
+            .location_cache
+            .lookup_completed(generation, location.clone())
+        {
+            return;
+        }
+        if let (TunnelState::Disconnected, Some(location)) = (&self.tunnel_state, &location) {
+            self.relay_selector
+                .set_device_location(location.latitude, location.longitude);
+        }
+        let location = self.current_location(location);
+        for tx in self.location_cache.take_waiting() {
+            Self::oneshot_send(tx, location.clone(), "current location");
+        }
+    }
+
+    #[cfg(windows)]
+    fn handle_session_change(&mut self, change: session::SessionChange) {
+        log::debug!("Session {} changed: {:?}", change.session_id, change.kind);
+
+        if session::ConsoleSession::update(&mut self.console_session, &change) {
+            match &self.console_session {
+                Some(console_session) => log::info!("Console is now used by {}", console_session),
+                None => log::info!("No session is attached to the console"),
+            }
+        }
+
+        if change.kind.should_resync_frontends() {
+            // Frontends in a session that was switched away from or locked may have dropped their
+            // event stream, or hold a stale view. Resend the current state to them.
+            self.event_listener.resync_session(
+                change.session_id,
+                self.tunnel_state.clone(),
+                self.settings.to_settings(),
+            );
+        }
+    }
+
+    async fn handle_account_expiry(&mut self, account_token: AccountToken, expiry: DateTime<Utc>) {
+        if self.settings.get_account_token().as_ref() != Some(&account_token) {
+            return;
+        }
+
+        let event = AccountExpiryEvent::new(expiry, Utc::now());
+        if self.account_expiry_event.as_ref() != Some(&event) {
+            if let Some(warning) = event.warning {
+                log::info!("Account expiry warning: {:?}", warning);
+            }
+            self.account_expiry_event = Some(event.clone());
+            self.event_listener.notify_account_expiry(event);
+        }
+
+        if expiry >= Utc::now() {
+            if let Some(job) = self.account_expired_job.take() {
+                job.abort();
+                log::info!("Time was added to the account. Resuming");
+                self.reconnect_tunnel();
+            }
+        } else if *self.target_state == TargetState::Secured {
+            self.handle_account_out_of_time().await;
+        }
+    }
+
+    /// Starts checking the expiry of the current account, if there is one, and forgets the expiry
+    /// of the previous account.
+    fn restart_account_expiry_watcher(&mut self) {
+        if let Some(watcher) = self.account_expiry_watcher.take() {
+            watcher.abort();
+        }
+        self.account_expiry_event = None;
+
         if let Some(account_token) = self.settings.get_account_token() {
-            let result = match self.settings.get_relay_settings() {
-                RelaySettings::CustomTunnelEndpoint(custom_relay) => {
-                    self.last_generated_relay = None;
-                    self.last_generated_entry_relay = None;
-                    custom_relay
-                        // TODO(emilsp): generate proxy settings for custom tunnels
-                        .to_tunnel_parameters(self.settings.tunnel_options.clone(), None)
-                        .map_err(|e| {
-                            log::error!("Failed to resolve hostname for custom tunnel config: {}", e);
-                            ParameterGenerationError::CustomTunnelHostResultionError
-                        })
+            let daemon_tx = self.tx.clone();
+            let token = account_token.clone();
+            self.account_expiry_watcher =
+                Some(self.account.watch_expiry(account_token, move |expiry| {
+                    let _ =
+                        daemon_tx.send(InternalDaemonEvent::AccountExpiry(token.clone(), expiry));
+                }));
+        }
+    }
+
+    /// Stops connection attempts until the API reports that time has been added to the account.
+    /// Depending on `block_when_account_expired`, the tunnel either keeps blocking or disconnects.
+    async fn handle_account_out_of_time(&mut self) {
+        if self.account_expired_job.is_some() {
+            return;
+        }
+        let account_token = match self.settings.get_account_token() {
+            Some(account_token) => account_token,
+            None => return,
+        };
+
+        log::info!("The account has run out of time");
+
+        let wait_for_time = self.account.wait_for_time_added(account_token.clone());
+        let daemon_tx = self.tx.clone();
+        let (future, abort_handle) = abortable(async move {
+            let expiry = wait_for_time.await;
+            let _ = daemon_tx.send(InternalDaemonEvent::AccountExpiry(account_token, expiry));
+        });
+        tokio::spawn(future);
+        self.account_expired_job = Some(abort_handle);
+
+        self.apply_account_expired_policy();
+    }
+
+    /// Returns the hostnames of the relays that `parameters` uses as WireGuard peers, along with
+    /// the public keys that the tunnel will use for them.
+    fn wireguard_relay_keys(
+        &self,
+        parameters: &TunnelParameters,
+    ) -> Vec<(String, talpid_types::net::wireguard::PublicKey)> {
+        let connection = match parameters {
+            TunnelParameters::Wireguard(params) => &params.connection,
+            TunnelParameters::OpenVpn(_) => return vec![],
+        };
+        let exit_relay = match self.last_generated_relay.as_ref() {
+            Some(relay) => relay,
+            None => return vec![],
+        };
+        match (&connection.exit_peer, &self.last_generated_entry_relay) {
+            (Some(exit_peer), Some(entry_relay)) => vec![
+                (
+                    entry_relay.hostname.clone(),
+                    connection.peer.public_key.clone(),
+                ),
+                (exit_relay.hostname.clone(), exit_peer.public_key.clone()),
+            ],
+            _ => vec![(
+                exit_relay.hostname.clone(),
+                connection.peer.public_key.clone(),
+            )],
+        }
+    }
+
+    /// Checks the public keys of the relays in use against an updated relay list. A relay that is
+    /// listed with a different key blocks the tunnel, while a relay that has been removed from
+    /// the list only causes a reconnect to another relay.
+    fn handle_relay_list_updated(&mut self) {
+        if !matches!(
+            self.tunnel_state,
+            TunnelState::Connecting { .. } | TunnelState::Connected { .. }
+        ) {
+            return;
+        }
+        let mut relay_unavailable = false;
+        for (hostname, public_key) in &self.last_generated_relay_keys {
+            match self
+                .relay_selector
+                .wireguard_key_status(hostname, public_key)
+            {
+                relays::RelayKeyStatus::Published => (),
+                relays::RelayKeyStatus::Mismatch => {
+                    log::error!(
+                        "The public key used for relay {} does not match the updated relay list",
+                        hostname
+                    );
+                    self.send_tunnel_command(TunnelCommand::Block(
+                        ErrorStateCause::TunnelParameterError(
+                            ParameterGenerationError::RelayKeyMismatch,
+                        ),
+                    ));
+                    return;
                 }
-                RelaySettings::Normal(constraints) => {
-                    let endpoint = self
-                        .relay_selector
-                        .get_tunnel_endpoint(
-                            &constraints,
-                            self.settings.get_bridge_state(),
-                            retry_attempt,
-                            self.settings.get_wireguard().is_some(),
-                        )
-                        .ok();
-                    if let Some(relays::RelaySelectorResult {
-                        exit_relay,
-                        entry_relay,
-                        endpoint,
-                    }) = endpoint
-                    {
-                        let result = self
-                            .create_tunnel_parameters(
-                                &exit_relay,
-                                endpoint,
-                                account_token,
-                                retry_attempt,
-                            )
-                            .await;
-                        self.last_generated_relay = Some(exit_relay);
-                        self.last_generated_entry_relay = entry_relay;
-                        match result {
-                            Ok(result) => Ok(result),
-                            Err(Error::NoKeyAvailable) => {
-                                Err(ParameterGenerationError::NoWireguardKey)
-                            }
-                            Err(Error::NoBridgeAvailable) => {
-                                Err(ParameterGenerationError::NoMatchingBridgeRelay)
-                            }
-                            Err(err) => {
-                                log::error!(
-                                    "{}",
-                                    err.display_chain_with_msg(
-                                        "Failed to generate tunnel parameters"
-                                    )
-                                );
-                                Err(ParameterGenerationError::NoMatchingRelay)
-                            }
-                        }
-                    } else {
-                        Err(ParameterGenerationError::NoMatchingRelay)
-                    }
+                relays::RelayKeyStatus::RelayUnavailable => {
+                    log::info!("Relay {} is no longer available", hostname);
+                    relay_unavailable = true;
                 }
-            };
-            if tunnel_parameters_tx.send(result).is_err() {
-                log::error!("Failed to send tunnel parameters");
             }
+        }
+        if relay_unavailable {
+            self.reconnect_tunnel();
+        }
+    }
+
+    /// Blocks or disconnects the tunnel while the account is out of time. The target state is left
+    /// unchanged, so that the tunnel resumes once time has been added to the account, or when the
+    /// daemon is restarted.
+    fn apply_account_expired_policy(&mut self) {
+        if *self.target_state != TargetState::Secured {
+            return;
+        }
+        if self.settings.block_when_account_expired {
+            self.send_tunnel_command(TunnelCommand::Block(ErrorStateCause::AccountExpired));
         } else {
-            log::error!("No account token configured");
+            log::info!("Disconnecting because the account has no time left");
+            self.disconnect_tunnel();
         }
     }
 
-    async fn create_tunnel_parameters(
-        &mut self,
-        relay: &Relay,
-        endpoint: MullvadEndpoint,
-        account_token: String,
-        retry_attempt: u32,
-    ) -> Result<TunnelParameters, Error> {
-        let tunnel_options = self.settings.tunnel_options.clone();
-        let location = relay.location.as_ref().expect("Relay has no location set");
-        self.last_generated_bridge_relay = None;
-        match endpoint {
-            MullvadEndpoint::OpenVpn(endpoint) => {
-                let proxy_settings = match &self.settings.bridge_settings {
-                    BridgeSettings::Normal(settings) => {
-                        let bridge_constraints = InternalBridgeConstraints {
+    /// Checks whether the WireGuard key is still registered on the account. This is done when the
+    /// computer wakes up or comes back online, since the key may have been removed from another
+    /// device in the meantime. Does nothing if a check is already in progress.
+    fn validate_wireguard_key(&mut self) {
+        if self.key_validation_in_flight {
+            log::debug!("WireGuard key validation is already in progress");
+            return;
+        }
+        let account_token = match self.settings.get_account_token() {
+            Some(account_token) => account_token,
+            None => return,
+        };
+        let public_key = match self.settings.get_wireguard() {
+            Some(wg_data) => wg_data.private_key.public_key(),
+            None => return,
+        };
+
+        log::debug!("Validating WireGuard key");
+
+        let verification_rpc = self
+            .wireguard_key_manager
+            .verify_wireguard_key(account_token.clone(), public_key.clone());
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            let result = verification_rpc.await;
+            let _ = daemon_tx.send(InternalDaemonEvent::WireguardKeyValidated(
+                account_token,
+                public_key,
+                result,
+            ));
+        });
+        self.key_validation_in_flight = true;
+    }
+
+    async fn handle_wireguard_key_validated(
+        &mut self,
+        account_token: AccountToken,
+        public_key: talpid_types::net::wireguard::PublicKey,
+        result: Result<bool, wireguard::Error>,
+    ) {
+        self.key_validation_in_flight = false;
+
+        let is_valid = match result {
+            Ok(is_valid) => is_valid,
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to validate WireGuard key")
+                );
+                return;
+            }
+        };
+        let current_key = self
+            .settings
+            .get_wireguard()
+            .map(|wg_data| wg_data.private_key.public_key());
+        if is_valid
+            || self.settings.get_account_token().as_ref() != Some(&account_token)
+            || current_key.as_ref() != Some(&public_key)
+        {
+            return;
+        }
+
+        log::warn!("The WireGuard key has been removed from the account");
+
+        self.wireguard_key_manager.reset();
+        self.wireguard_key_manager.stop_automatic_rotation();
+        if let Err(error) = self.settings.set_wireguard(None).await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Error resetting WireGuard key")
+            );
+            return;
+        }
+        self.event_listener
+            .notify_settings(self.settings.to_settings());
+
+        if let Some(TunnelType::Wireguard) = self.get_target_tunnel_type() {
+            self.reconnect_tunnel();
+        }
+    }
+
+    /// Checks the system clock against the API, which can be reached in every tunnel state. This
+    /// is done when connection attempts keep failing, since a wrong clock prevents any tunnel from
+    /// being established.
+    #[cfg(not(target_os = "android"))]
+    fn check_clock(&mut self) {
+        if self.clock_check_in_flight {
+            return;
+        }
+        self.clock_check_in_flight = true;
+
+        log::debug!("Checking the system clock against the API");
+
+        let api_proxy = mullvad_rpc::ApiProxy::new(self.rpc_handle.clone());
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            let result = api_proxy.get_clock_skew().await;
+            let _ = daemon_tx.send(InternalDaemonEvent::ClockChecked(result));
+        });
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn handle_clock_checked(
+        &mut self,
+        result: Result<Option<mullvad_rpc::ClockSkew>, mullvad_rpc::rest::Error>,
+    ) {
+        self.clock_check_in_flight = false;
+
+        let skew = match result {
+            Ok(Some(skew)) => skew,
+            Ok(None) => {
+                log::debug!("The API response has no date to check the system clock against");
+                return;
+            }
+            Err(error) => {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to check the system clock")
+                );
+                return;
+            }
+        };
+        if skew.offset() < MAX_CLOCK_SKEW {
+            log::debug!("The system clock is {}", skew);
+            return;
+        }
+
+        log::error!("The system clock is {}", skew);
+        if matches!(self.tunnel_state, TunnelState::Connecting { .. }) {
+            self.send_tunnel_command(TunnelCommand::Block(ErrorStateCause::ClockSkew(
+                skew.as_signed_secs(),
+            )));
+        }
+    }
+
+    async fn reset_rpc_sockets_on_tunnel_state_transition(
+        &mut self,
+        tunnel_state_transition: &TunnelStateTransition,
+    ) {
+        match (&self.tunnel_state, &tunnel_state_transition) {
+            // only reset the API sockets if when connected or leaving the connected state
+            (&TunnelState::Connected { .. }, _) | (_, &TunnelStateTransition::Connected(_)) => {
+                self.rpc_runtime.set_tunnel_connected(matches!(
+                    tunnel_state_transition,
+                    TunnelStateTransition::Connected(_)
+                ));
+                self.rpc_handle.service().reset().await;
+            }
+            _ => (),
+        };
+    }
+
+    async fn handle_generate_tunnel_parameters(
+        &mut self,
+        tunnel_parameters_tx: &sync_mpsc::Sender<
+            Result<TunnelParameters, ParameterGenerationError>,
+        >,
+        retry_attempt: u32,
+    ) {
+        if retry_attempt > 0 {
+            // The previous attempt failed
+            if let Some(relay) = self.first_hop_relay() {
+                self.relay_selector
+                    .record_connection_failure(&relay.hostname);
+            }
+        }
+        #[cfg(not(target_os = "android"))]
+        if retry_attempt == CLOCK_CHECK_RETRY_ATTEMPT {
+            self.check_clock();
+        }
+
+        if let Some(account_token) = self.settings.get_account_token() {
+            let relay_settings = match &self.test_endpoint {
+                Some(endpoint) => RelaySettings::CustomTunnelEndpoint(endpoint.clone()),
+                None => self.settings.get_relay_settings(),
+            };
+            let result = match relay_settings {
+                RelaySettings::CustomTunnelEndpoint(custom_relay) => {
+                    self.last_generated_relay = None;
+                    self.last_generated_entry_relay = None;
+                    self.last_generated_fallback_tier = None;
+                    self.last_generated_relay_keys.clear();
+                    custom_relay
+                        // TODO(emilsp): generate proxy settings for custom tunnels
+                        .to_tunnel_parameters(self.settings.tunnel_options.clone(), None)
+                        .map_err(|e| {
+                            log::error!("Failed to resolve hostname for custom tunnel config: {}", e);
+                            ParameterGenerationError::CustomTunnelHostResultionError
+                        })
+                }
+                RelaySettings::Normal(constraints) => {
+                    let mut tiers = vec![constraints];
+                    tiers.extend(self.settings.relay_fallback_tiers.iter().cloned());
+                    if self.settings.entry_proxy.is_some() {
+                        // The entry proxy can only carry TCP traffic to the relay
+                        tiers.iter_mut().for_each(Self::constrain_to_tcp);
+                    }
+                    let endpoint = self
+                        .relay_selector
+                        .get_tunnel_endpoint_from_tiers(
+                            &tiers,
+                            self.settings.get_bridge_state(),
+                            retry_attempt,
+                            self.settings.get_wireguard().is_some(),
+                        )
+                        .ok();
+                    if let Some((
+                        tier,
+                        relays::RelaySelectorResult {
+                            exit_relay,
+                            entry_relay,
+                            endpoint,
+                        },
+                    )) = endpoint
+                    {
+                        if tier > 0 {
+                            log::info!("Selected relay from fallback tier {}", tier);
+                        }
+                        let result = self
+                            .create_tunnel_parameters(
+                                &exit_relay,
+                                endpoint,
+                                account_token,
+                                retry_attempt,
+                            )
+                            .await;
+                        self.last_generated_relay = Some(exit_relay);
+                        self.last_generated_entry_relay = entry_relay;
+                        self.last_generated_fallback_tier =
+                            Some(tier as u32).filter(|&tier| tier > 0);
+                        match result {
+                            Ok(result) => {
+                                self.last_generated_relay_keys = self.wireguard_relay_keys(&result);
+                                Ok(result)
+                            }
+                            Err(Error::NoKeyAvailable) => {
+                                Err(ParameterGenerationError::NoWireguardKey)
+                            }
+                            Err(Error::NoBridgeAvailable) => {
+                                Err(ParameterGenerationError::NoMatchingBridgeRelay)
+                            }
+                            Err(err) => {
+                                log::error!(
+                                    "{}",
+                                    err.display_chain_with_msg(
+                                        "Failed to generate tunnel parameters"
+                                    )
+                                );
+                                Err(ParameterGenerationError::NoMatchingRelay)
+                            }
+                        }
+                    } else {
+                        Err(ParameterGenerationError::NoMatchingRelay)
+                    }
+                }
+            };
+            if tunnel_parameters_tx.send(result).is_err() {
+                log::error!("Failed to send tunnel parameters");
+            }
+        } else {
+            log::error!("No account token configured");
+        }
+    }
+
+    fn constrain_to_tcp(constraints: &mut RelayConstraints) {
+        let tcp_port = |port: Constraint<TransportPort>| match port {
+            Constraint::Only(port) if port.protocol == TransportProtocol::Tcp => {
+                Constraint::Only(port)
+            }
+            _ => Constraint::Only(TransportPort {
+                protocol: TransportProtocol::Tcp,
+                port: Constraint::Any,
+            }),
+        };
+        constraints.openvpn_constraints.port = tcp_port(constraints.openvpn_constraints.port);
+        constraints.wireguard_constraints.port = tcp_port(constraints.wireguard_constraints.port);
+    }
+
+    async fn create_tunnel_parameters(
+        &mut self,
+        relay: &Relay,
+        endpoint: MullvadEndpoint,
+        account_token: String,
+        retry_attempt: u32,
+    ) -> Result<TunnelParameters, Error> {
+        let tunnel_options = feature_flags::apply(self.settings.to_settings()).tunnel_options;
+        let location = relay.location.as_ref().expect("Relay has no location set");
+        self.last_generated_bridge_relay = None;
+        match endpoint {
+            MullvadEndpoint::OpenVpn(endpoint) => {
+                let proxy_settings = match &self.settings.bridge_settings {
+                    _ if self.settings.entry_proxy.is_some() => self
+                        .settings
+                        .entry_proxy
+                        .as_ref()
+                        .map(EntryProxy::to_openvpn_proxy_settings),
+                    BridgeSettings::Normal(settings) => {
+                        let bridge_constraints = InternalBridgeConstraints {
                             location: settings.location.clone(),
                             providers: settings.providers.clone(),
+                            ownership: settings.ownership,
                             // FIXME: This is temporary while talpid-core only supports TCP proxies
                             transport_protocol: Constraint::Only(TransportProtocol::Tcp),
                         };
@@ -1147,6 +1991,7 @@ where
                     },
                     options: tunnel_options.wireguard.options,
                     generic_options: tunnel_options.generic,
+                    entry_proxy: self.settings.entry_proxy.clone(),
                 }
                 .into())
             }
@@ -1186,41 +2031,107 @@ where
             SetTargetState(tx, state) => self.on_set_target_state(tx, state).await,
             Reconnect(tx) => self.on_reconnect(tx),
             GetState(tx) => self.on_get_state(tx),
-            GetCurrentLocation(tx) => self.on_get_current_location(tx).await,
+            GetCurrentLocation(tx) => self.on_get_current_location(tx),
             CreateNewAccount(tx) => self.on_create_new_account(tx).await,
             GetAccountData(tx, account_token) => self.on_get_account_data(tx, account_token).await,
             GetWwwAuthToken(tx) => self.on_get_www_auth_token(tx).await,
             SubmitVoucher(tx, voucher) => self.on_submit_voucher(tx, voucher).await,
+            InitPlayPurchase(tx) => self.on_init_play_purchase(tx),
+            VerifyPlayPurchase(tx, play_purchase) => {
+                self.on_verify_play_purchase(tx, play_purchase)
+            }
             GetRelayLocations(tx) => self.on_get_relay_locations(tx),
+            GetRelayLatencies(tx) => self.on_get_relay_latencies(tx),
+            GetEventSnapshot(callback) => self.on_get_event_snapshot(callback),
             UpdateRelayLocations => self.on_update_relay_locations().await,
             SetAccount(tx, account_token) => self.on_set_account(tx, account_token).await,
             GetAccountHistory(tx) => self.on_get_account_history(tx),
             ClearAccountHistory(tx) => self.on_clear_account_history(tx).await,
             UpdateRelaySettings(tx, update) => self.on_update_relay_settings(tx, update).await,
+            SetRelayFallbackTiers(tx, tiers) => self.on_set_relay_fallback_tiers(tx, tiers).await,
             SetAllowLan(tx, allow_lan) => self.on_set_allow_lan(tx, allow_lan).await,
+            SetLanAllowList(tx, lan_allow_list) => {
+                self.on_set_lan_allow_list(tx, lan_allow_list).await
+            }
+            SetBlockedStatePolicy(tx, policy) => self.on_set_blocked_state_policy(tx, policy).await,
             SetShowBetaReleases(tx, enabled) => self.on_set_show_beta_releases(tx, enabled).await,
+            SetLogPrivacy(tx, log_privacy) => self.on_set_log_privacy(tx, log_privacy).await,
             SetBlockWhenDisconnected(tx, block_when_disconnected) => {
                 self.on_set_block_when_disconnected(tx, block_when_disconnected)
                     .await
             }
+            SetBlockWhenAccountExpired(tx, block_when_account_expired) => {
+                self.on_set_block_when_account_expired(tx, block_when_account_expired)
+                    .await
+            }
+            SetStrictKillSwitch(tx, strict_kill_switch) => {
+                self.on_set_strict_kill_switch(tx, strict_kill_switch).await
+            }
+            SetPreferLowLatency(tx, prefer_low_latency) => {
+                self.on_set_prefer_low_latency(tx, prefer_low_latency).await
+            }
+            SetUseSystemProxy(tx, use_system_proxy) => {
+                self.on_set_use_system_proxy(tx, use_system_proxy).await
+            }
+            ApplySettingsPatch(tx, patch) => self.on_apply_settings_patch(tx, patch).await,
+            ExportSettings(tx) => self.on_export_settings(tx),
+            ImportSettings(tx, settings) => self.on_import_settings(tx, settings).await,
+            #[cfg(windows)]
+            SetAllowConnectionSharing(tx, allow_connection_sharing) => {
+                self.on_set_allow_connection_sharing(tx, allow_connection_sharing)
+                    .await
+            }
+            #[cfg(windows)]
+            IsConnectionSharingActive(tx) => self.on_is_connection_sharing_active(tx),
             SetAutoConnect(tx, auto_connect) => self.on_set_auto_connect(tx, auto_connect).await,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            SetReconnectOnNetworkChange(tx, reconnect) => {
+                self.on_set_reconnect_on_network_change(tx, reconnect).await
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            SetTrustedNetworks(tx, trusted_networks) => {
+                self.on_set_trusted_networks(tx, trusted_networks).await
+            }
             SetOpenVpnMssfix(tx, mssfix_arg) => self.on_set_openvpn_mssfix(tx, mssfix_arg).await,
             SetBridgeSettings(tx, bridge_settings) => {
                 self.on_set_bridge_settings(tx, bridge_settings).await
             }
             SetBridgeState(tx, bridge_state) => self.on_set_bridge_state(tx, bridge_state).await,
+            SetEntryProxy(tx, entry_proxy) => self.on_set_entry_proxy(tx, entry_proxy).await,
             SetEnableIpv6(tx, enable_ipv6) => self.on_set_enable_ipv6(tx, enable_ipv6).await,
             SetDnsOptions(tx, dns_servers) => self.on_set_dns_options(tx, dns_servers).await,
             SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu).await,
+            #[cfg(any(target_os = "linux", windows))]
+            SetWireguardInterfaceName(tx, name) => {
+                self.on_set_wireguard_interface_name(tx, name).await
+            }
             SetWireguardRotationInterval(tx, interval) => {
                 self.on_set_wireguard_rotation_interval(tx, interval).await
             }
             GetSettings(tx) => self.on_get_settings(tx),
+            GetFeatureFlags(tx) => self.on_get_feature_flags(tx),
+            SetFeatureFlag(tx, name, enabled) => self.on_set_feature_flag(tx, name, enabled).await,
+            GetSettingsLoadFailure(tx) => self.on_get_settings_load_failure(tx),
             GenerateWireguardKey(tx) => self.on_generate_wireguard_key(tx).await,
             GetWireguardKey(tx) => self.on_get_wireguard_key(tx).await,
             VerifyWireguardKey(tx) => self.on_verify_wireguard_key(tx).await,
+            ListAccountKeys(tx) => self.on_list_account_keys(tx),
+            RevokeAccountKey(tx, key) => self.on_revoke_account_key(tx, key),
             GetVersionInfo(tx) => self.on_get_version_info(tx).await,
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
+            GetFirewallPolicy(tx) => self.on_get_firewall_policy(tx),
+            GetTunnelDebugInfo(tx) => self.on_get_tunnel_debug_info(tx),
+            GetTunnelStatistics(tx) => self.on_get_tunnel_statistics(tx),
+            GetTunnelStateMachineDebugInfo(tx) => self.on_get_tunnel_state_machine_debug_info(tx),
+            #[cfg(not(target_os = "android"))]
+            RunHealthCheck(tx) => self.on_run_health_check(tx),
+            #[cfg(windows)]
+            CleanupDrivers(tx) => self.on_cleanup_drivers(tx),
+            GetApiTrace(tx) => self.on_get_api_trace(tx),
+            SendProblemReport(tx, report) => self.on_send_problem_report(tx, report),
+            ConnectToEndpoint(tx, endpoint) => self.on_connect_to_endpoint(tx, endpoint).await,
+            #[cfg(target_os = "macos")]
+            AllowCaptivePortal(tx, duration) => self.on_allow_captive_portal(tx, duration),
             #[cfg(not(target_os = "android"))]
             FactoryReset(tx) => self.on_factory_reset(tx).await,
             #[cfg(target_os = "linux")]
@@ -1231,6 +2142,16 @@ where
             RemoveSplitTunnelProcess(tx, pid) => self.on_remove_split_tunnel_process(tx, pid),
             #[cfg(target_os = "linux")]
             ClearSplitTunnelProcesses(tx) => self.on_clear_split_tunnel_processes(tx),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            AddSplitTunnelNetwork(tx, network) => {
+                self.on_add_split_tunnel_network(tx, network).await
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            RemoveSplitTunnelNetwork(tx, network) => {
+                self.on_remove_split_tunnel_network(tx, network).await
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            ClearSplitTunnelNetworks(tx) => self.on_clear_split_tunnel_networks(tx).await,
             #[cfg(windows)]
             AddSplitTunnelApp(tx, path) => self.on_add_split_tunnel_app(tx, path).await,
             #[cfg(windows)]
@@ -1241,7 +2162,7 @@ where
             SetSplitTunnelState(tx, enabled) => self.on_set_split_tunnel_state(tx, enabled).await,
             #[cfg(target_os = "windows")]
             UseWireGuardNt(tx, state) => self.on_use_wireguard_nt(tx, state).await,
-            Shutdown => self.trigger_shutdown_event(),
+            Shutdown => self.trigger_shutdown_event(true),
             PrepareRestart => self.on_prepare_restart(),
             #[cfg(target_os = "android")]
             BypassSocket(fd, tx) => self.on_bypass_socket(fd, tx),
@@ -1385,6 +2306,10 @@ where
         new_target_state: TargetState,
     ) {
         if self.state.is_running() {
+            // An explicit choice overrides what was done automatically on a trusted network
+            self.target_state
+                .set_disconnected_on_trusted_network(false)
+                .await;
             let state_change_initated = self.set_target_state(new_target_state).await;
             Self::oneshot_send(tx, state_change_initated, "state change initiated");
         } else {
@@ -1406,56 +2331,50 @@ where
         Self::oneshot_send(tx, self.tunnel_state.clone(), "current state");
     }
 
-    async fn on_get_current_location(&mut self, tx: oneshot::Sender<Option<GeoIpLocation>>) {
-        use self::TunnelState::*;
-
-        match &self.tunnel_state {
-            Disconnected => {
-                let location = self.get_geo_location();
-                tokio::spawn(async {
-                    Self::oneshot_send(tx, location.await.ok(), "current location");
-                });
-            }
-            Connecting { location, .. } => {
-                Self::oneshot_send(tx, location.clone(), "current location")
-            }
-            Disconnecting(..) => {
-                Self::oneshot_send(tx, self.build_location_from_relay(), "current location")
-            }
-            Connected { location, .. } => {
-                let relay_location = location.clone();
-                let location_future = self.get_geo_location();
-                tokio::spawn(async {
-                    let location = location_future.await;
-                    Self::oneshot_send(
+    fn on_get_current_location(&mut self, tx: oneshot::Sender<Option<GeoIpLocation>>) {
+        match self.tunnel_state {
+            TunnelState::Disconnected | TunnelState::Connected { .. } => {
+                match self.location_cache.get() {
+                    Some(location) => Self::oneshot_send(
                         tx,
-                        location.ok().map(|fetched_location| GeoIpLocation {
-                            ipv4: fetched_location.ipv4,
-                            ipv6: fetched_location.ipv6,
-                            ..relay_location.unwrap_or(fetched_location)
-                        }),
+                        self.current_location(Some(location)),
                         "current location",
-                    );
-                });
-            }
-            Error(_) => {
-                // We are not online at all at this stage so no location data is available.
-                Self::oneshot_send(tx, None, "current location");
+                    ),
+                    None => self.location_cache.wait_for_lookup(tx),
+                }
             }
+            _ => Self::oneshot_send(tx, self.current_location(None), "current location"),
         }
     }
 
-    fn get_geo_location(&mut self) -> impl Future<Output = Result<GeoIpLocation, ()>> {
-        let rpc_service = self.rpc_runtime.rest_handle();
-        async {
-            geoip::send_location_request(rpc_service)
-                .await
-                .map_err(|e| {
-                    log::warn!("Unable to fetch GeoIP location: {}", e.display_chain());
-                })
+    /// Returns the location to report in the current tunnel state, given the location that was
+    /// looked up in it, if any. While connected, only the IP addresses are taken from the lookup
+    /// if the location of the relay is known. Nothing is looked up in the other states.
+    fn current_location(&self, looked_up: Option<GeoIpLocation>) -> Option<GeoIpLocation> {
+        use self::TunnelState::*;
+
+        match &self.tunnel_state {
+            Disconnected => looked_up,
+            Connecting { location, .. } => location.clone(),
+            Disconnecting(..) => self.build_location_from_relay(),
+            Connected { location, .. } => looked_up.map(|looked_up| GeoIpLocation {
+                ipv4: looked_up.ipv4,
+                ipv6: looked_up.ipv6,
+                ..location.clone().unwrap_or(looked_up)
+            }),
+            // We are not online at all at this stage so no location data is available.
+            Error(_) => None,
         }
     }
 
+    /// Returns the relay or bridge that the last generated tunnel parameters connect to first.
+    fn first_hop_relay(&self) -> Option<&Relay> {
+        self.last_generated_bridge_relay
+            .as_ref()
+            .or(self.last_generated_entry_relay.as_ref())
+            .or(self.last_generated_relay.as_ref())
+    }
+
     fn build_location_from_relay(&self) -> Option<GeoIpLocation> {
         let relay = self.last_generated_relay.as_ref()?;
         let bridge_hostname = self
@@ -1480,6 +2399,7 @@ where
             hostname: Some(hostname),
             bridge_hostname,
             entry_hostname,
+            relay_fallback_tier: self.last_generated_fallback_tier,
         })
     }
 
@@ -1504,8 +2424,12 @@ where
         account_token: AccountToken,
     ) {
         let account = self.account.clone();
+        let daemon_tx = self.tx.clone();
         tokio::spawn(async move {
-            let result = account.check_expiry(account_token).await;
+            let result = account.check_expiry(account_token.clone()).await;
+            if let Ok(expiry) = result {
+                let _ = daemon_tx.send(InternalDaemonEvent::AccountExpiry(account_token, expiry));
+            }
             Self::oneshot_send(
                 tx,
                 result.map(|expiry| AccountData { expiry }),
@@ -1539,15 +2463,31 @@ where
         tx: ResponseTx<VoucherSubmission, Error>,
         voucher: String,
     ) {
+        let voucher = match mullvad_types::account::normalize_voucher_code(&voucher) {
+            Some(voucher) => voucher,
+            None => {
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::InvalidVoucherFormat),
+                    "submit_voucher response",
+                );
+                return;
+            }
+        };
         if let Some(account_token) = self.settings.get_account_token() {
             let mut account = self.account.clone();
+            let daemon_tx = self.tx.clone();
             tokio::spawn(async move {
+                let result = account.submit_voucher(account_token.clone(), voucher).await;
+                if let Ok(ref submission) = result {
+                    let _ = daemon_tx.send(InternalDaemonEvent::AccountExpiry(
+                        account_token,
+                        submission.new_expiry,
+                    ));
+                }
                 Self::oneshot_send(
                     tx,
-                    account
-                        .submit_voucher(account_token, voucher)
-                        .await
-                        .map_err(Error::RestError),
+                    result.map_err(Error::RestError),
                     "submit_voucher response",
                 );
             });
@@ -1556,20 +2496,89 @@ where
         }
     }
 
-    fn on_get_relay_locations(&mut self, tx: oneshot::Sender<RelayList>) {
-        Self::oneshot_send(tx, self.relay_selector.get_locations(), "relay locations");
-    }
-
-    async fn on_update_relay_locations(&mut self) {
-        self.relay_selector.update().await;
+    fn on_init_play_purchase(&mut self, tx: ResponseTx<PlayPurchasePaymentToken, Error>) {
+        let account_token = match self.settings.get_account_token() {
+            Some(account_token) => account_token,
+            None => {
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::NoAccountToken),
+                    "init_play_purchase response",
+                );
+                return;
+            }
+        };
+        let future = self.account.init_play_purchase(account_token);
+        tokio::spawn(async move {
+            Self::oneshot_send(
+                tx,
+                future.await.map_err(Error::RestError),
+                "init_play_purchase response",
+            );
+        });
     }
 
-    async fn on_set_account(
-        &mut self,
-        tx: ResponseTx<(), settings::Error>,
-        account_token: Option<String>,
-    ) {
-        match self.set_account(account_token.clone()).await {
+    fn on_verify_play_purchase(&mut self, tx: ResponseTx<(), Error>, play_purchase: PlayPurchase) {
+        let account_token = match self.settings.get_account_token() {
+            Some(account_token) => account_token,
+            None => {
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::NoAccountToken),
+                    "verify_play_purchase response",
+                );
+                return;
+            }
+        };
+        let account = self.account.clone();
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            let result = account
+                .verify_play_purchase(account_token.clone(), play_purchase)
+                .await;
+            let verified = result.is_ok();
+            Self::oneshot_send(
+                tx,
+                result.map_err(Error::RestError),
+                "verify_play_purchase response",
+            );
+            // The API does not return the new expiry, so fetch it to update the clients
+            if verified {
+                if let Ok(expiry) = account.check_expiry(account_token.clone()).await {
+                    let _ =
+                        daemon_tx.send(InternalDaemonEvent::AccountExpiry(account_token, expiry));
+                }
+            }
+        });
+    }
+
+    fn on_get_relay_locations(&mut self, tx: oneshot::Sender<RelayList>) {
+        Self::oneshot_send(tx, self.relay_selector.get_locations(), "relay locations");
+    }
+
+    fn on_get_relay_latencies(&mut self, tx: oneshot::Sender<HashMap<String, Duration>>) {
+        Self::oneshot_send(tx, self.relay_selector.get_latencies(), "relay latencies");
+    }
+
+    fn on_get_event_snapshot(&mut self, callback: EventSnapshotCallback) {
+        callback(EventSnapshot {
+            tunnel_state: self.tunnel_state.clone(),
+            settings: self.settings.to_settings(),
+            relay_list: self.relay_selector.get_locations(),
+            app_version_info: self.app_version_info.clone(),
+        });
+    }
+
+    async fn on_update_relay_locations(&mut self) {
+        self.relay_selector.update().await;
+    }
+
+    async fn on_set_account(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        account_token: Option<String>,
+    ) {
+        match self.set_account(account_token.clone()).await {
             Ok(account_changed) => {
                 if account_changed {
                     match account_token {
@@ -1607,6 +2616,11 @@ where
             self.event_listener
                 .notify_settings(self.settings.to_settings());
 
+            if let Some(job) = self.account_expired_job.take() {
+                job.abort();
+            }
+            self.restart_account_expiry_watcher();
+
             let history_token = match account_token {
                 Some(token) => token,
                 None => previous_token.clone().unwrap_or("".to_string()),
@@ -1735,6 +2749,187 @@ where
         );
     }
 
+    fn on_get_firewall_policy(&mut self, tx: oneshot::Sender<FirewallPolicyDescription>) {
+        self.send_tunnel_command(TunnelCommand::DescribeFirewallPolicy(tx));
+    }
+
+    fn on_get_tunnel_debug_info(&mut self, tx: oneshot::Sender<TunnelDebugInfo>) {
+        self.send_tunnel_command(TunnelCommand::DescribeTunnel(tx));
+    }
+
+    fn on_get_tunnel_statistics(&mut self, tx: oneshot::Sender<TunnelStatistics>) {
+        self.send_tunnel_command(TunnelCommand::GetTunnelStatistics(tx));
+    }
+
+    fn on_get_tunnel_state_machine_debug_info(
+        &mut self,
+        tx: oneshot::Sender<StateMachineDebugInfo>,
+    ) {
+        self.send_tunnel_command(TunnelCommand::DescribeStateMachine(tx));
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn on_run_health_check(&mut self, tx: oneshot::Sender<Vec<health_check::CheckResult>>) {
+        let tunnel_command_tx = Arc::downgrade(&self.tunnel_command_tx);
+        let api_proxy = mullvad_rpc::ApiProxy::new(self.rpc_handle.clone());
+        tokio::spawn(async move {
+            let results = health_check::run(tunnel_command_tx, api_proxy).await;
+            Self::oneshot_send(tx, results, "health check response");
+        });
+    }
+
+    #[cfg(windows)]
+    fn on_cleanup_drivers(&mut self, tx: ResponseTx<talpid_core::tunnel::DriverCleanup, Error>) {
+        // Cleaning up in the daemon loop ensures that no tunnel is started until it has finished
+        if !matches!(self.tunnel_state, TunnelState::Disconnected) {
+            Self::oneshot_send(
+                tx,
+                Err(Error::CleanupDriversWhileConnected),
+                "cleanup_drivers response",
+            );
+            return;
+        }
+        let cleanup = talpid_core::tunnel::cleanup_drivers(
+            &self.resource_dir,
+            self.settings
+                .tunnel_options
+                .wireguard
+                .options
+                .interface_name
+                .as_deref(),
+        );
+        Self::log_driver_cleanup(&cleanup);
+        Self::oneshot_send(tx, Ok(cleanup), "cleanup_drivers response");
+    }
+
+    #[cfg(windows)]
+    fn log_driver_cleanup(cleanup: &talpid_core::tunnel::DriverCleanup) {
+        for adapter in &cleanup.adapters {
+            log::info!("Removed orphaned Wintun adapter \"{}\"", adapter);
+        }
+        for profile in &cleanup.network_profiles {
+            log::info!("Removed stale network profile \"{}\"", profile);
+        }
+        if cleanup.reboot_required {
+            log::warn!("A reboot is required to finish removing the Wintun adapters");
+        }
+    }
+
+    fn on_get_api_trace(&mut self, tx: oneshot::Sender<Vec<mullvad_rpc::ApiTraceEntry>>) {
+        Self::oneshot_send(tx, self.rpc_runtime.api_trace().entries(), "API trace");
+    }
+
+    fn on_send_problem_report(&mut self, tx: ResponseTx<(), Error>, report: ProblemReport) {
+        let proxy = mullvad_rpc::ProblemReportProxy::new(self.rpc_handle.clone());
+        let api_availability = self.rpc_runtime.availability_handle();
+        tokio::spawn(async move {
+            let result = Self::send_problem_report(proxy, api_availability, report)
+                .await
+                .map_err(|error| {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to send problem report")
+                    );
+                    Error::RestError(error)
+                });
+            Self::oneshot_send(tx, result, "send_problem_report response");
+        });
+    }
+
+    /// Sends a problem report, retrying while the API is unreachable. While the device is offline,
+    /// the report is kept until it comes back online.
+    async fn send_problem_report(
+        proxy: mullvad_rpc::ProblemReportProxy,
+        api_availability: ApiAvailabilityHandle,
+        report: ProblemReport,
+    ) -> Result<(), mullvad_rpc::rest::Error> {
+        let mut retry_delays = Jittered::jitter(
+            ExponentialBackoff::new(
+                PROBLEM_REPORT_RETRY_INTERVAL_INITIAL,
+                PROBLEM_REPORT_RETRY_INTERVAL_FACTOR,
+            )
+            .max_delay(PROBLEM_REPORT_RETRY_INTERVAL_MAX),
+        );
+        let mut retries = 0;
+        loop {
+            if api_availability.get_state().is_offline() {
+                log::info!("Sending the problem report once the device is online");
+            }
+            let _ = api_availability.wait_online().await;
+
+            let error = match proxy
+                .problem_report(
+                    &report.email,
+                    &report.message,
+                    &report.log,
+                    &report.metadata,
+                )
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(error) if error.is_network_error() => error,
+                Err(error) => return Err(error),
+            };
+
+            if api_availability.get_state().is_offline() {
+                continue;
+            }
+            if retries >= PROBLEM_REPORT_MAX_RETRIES {
+                return Err(error);
+            }
+            retries += 1;
+            log::debug!(
+                "{}",
+                error.display_chain_with_msg("Failed to send problem report. Retrying")
+            );
+            if let Some(delay) = retry_delays.next() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+
+    async fn on_connect_to_endpoint(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        endpoint: CustomTunnelEndpoint,
+    ) {
+        if !*DEBUG_COMMANDS_ENABLED {
+            Self::oneshot_send(
+                tx,
+                Err(Error::DebugCommandsDisabled),
+                "connect_to_endpoint response",
+            );
+            return;
+        }
+        log::warn!(
+            "Connecting to {} instead of a relay until the tunnel is disconnected",
+            endpoint.host
+        );
+        self.test_endpoint = Some(endpoint);
+        if !self.set_target_state(TargetState::Secured).await {
+            self.reconnect_tunnel();
+        }
+        Self::oneshot_send(tx, Ok(()), "connect_to_endpoint response");
+    }
+
+    #[cfg(target_os = "macos")]
+    fn on_allow_captive_portal(
+        &mut self,
+        tx: ResponseTx<IpAddr, captive_portal::Error>,
+        duration: Duration,
+    ) {
+        if let Some(unlock) = self.captive_portal_unlock.take() {
+            unlock.abort();
+        }
+        let api_address = self.rpc_runtime.address_cache.peek_address().ip();
+        self.captive_portal_unlock = Some(captive_portal::spawn_unlock(
+            Arc::downgrade(&self.tunnel_command_tx),
+            api_address,
+            duration.min(captive_portal::MAX_UNLOCK_DURATION),
+            tx,
+        ));
+    }
+
     #[cfg(not(target_os = "android"))]
     async fn on_factory_reset(&mut self, tx: ResponseTx<(), Error>) {
         let mut last_error = Ok(());
@@ -1827,6 +3022,65 @@ where
         Self::oneshot_send(tx, result, "clear_split_tunnel_processes response");
     }
 
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn on_add_split_tunnel_network(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        network: IpNetwork,
+    ) {
+        let mut networks = self.settings.excluded_networks.clone();
+        if !networks.contains(&network) {
+            networks.push(network);
+        }
+        self.set_excluded_networks(tx, networks, "add_split_tunnel_network response")
+            .await;
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn on_remove_split_tunnel_network(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        network: IpNetwork,
+    ) {
+        let mut networks = self.settings.excluded_networks.clone();
+        networks.retain(|excluded| *excluded != network);
+        self.set_excluded_networks(tx, networks, "remove_split_tunnel_network response")
+            .await;
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn on_clear_split_tunnel_networks(&mut self, tx: ResponseTx<(), settings::Error>) {
+        self.set_excluded_networks(tx, vec![], "clear_split_tunnel_networks response")
+            .await;
+    }
+
+    /// Update the excluded networks in both the settings and tunnel
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn set_excluded_networks(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        networks: Vec<IpNetwork>,
+        response_msg: &'static str,
+    ) {
+        match self.settings.set_excluded_networks(networks.clone()).await {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::SetExcludedNetworks(networks));
+                }
+                Self::oneshot_send(tx, Ok(()), response_msg);
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to set excluded networks")
+                );
+                Self::oneshot_send(tx, Err(error), response_msg);
+            }
+        }
+    }
+
     /// Update the split app paths in both the settings and tunnel
     #[cfg(windows)]
     async fn set_split_tunnel_paths(
@@ -1960,134 +3214,751 @@ where
             .map_err(Error::SettingsError);
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "use_wireguard_nt response");
+                Self::oneshot_send(tx, Ok(()), "use_wireguard_nt response");
+                if settings_changed {
+                    talpid_core::tunnel::wireguard::reset_wireguard_nt_fallback();
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        log::info!("Initiating tunnel restart");
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to save settings")
+                );
+                Self::oneshot_send(tx, Err(error), "use_wireguard_nt response");
+            }
+        }
+    }
+
+    async fn on_update_relay_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        update: RelaySettingsUpdate,
+    ) {
+        let save_result = self.settings.update_relay_settings(update).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "update_relay_settings response");
+                let had_test_endpoint = self.test_endpoint.take().is_some();
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+                if settings_changed || had_test_endpoint {
+                    log::info!("Initiating tunnel restart because the relay settings changed");
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "update_relay_settings response");
+            }
+        }
+    }
+
+    async fn on_set_relay_fallback_tiers(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        tiers: Vec<RelayConstraints>,
+    ) {
+        let save_result = self.settings.set_relay_fallback_tiers(tiers).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_relay_fallback_tiers response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if self.last_generated_fallback_tier.is_some() {
+                        log::info!(
+                            "Initiating tunnel restart because the relay fallback tiers changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_relay_fallback_tiers response");
+            }
+        }
+    }
+
+    async fn on_set_allow_lan(&mut self, tx: ResponseTx<(), settings::Error>, allow_lan: bool) {
+        let save_result = self.settings.set_allow_lan(allow_lan).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_allow_lan response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::AllowLan(allow_lan));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_allow_lan response");
+            }
+        }
+    }
+
+    async fn on_set_lan_allow_list(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        lan_allow_list: Option<Vec<IpNetwork>>,
+    ) {
+        let save_result = self
+            .settings
+            .set_lan_allow_list(lan_allow_list.clone())
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_lan_allow_list response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::SetLanAllowList(lan_allow_list));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_lan_allow_list response");
+            }
+        }
+    }
+
+    async fn on_set_blocked_state_policy(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        blocked_state_policy: BlockedStatePolicy,
+    ) {
+        let save_result = self
+            .settings
+            .set_blocked_state_policy(blocked_state_policy)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_blocked_state_policy response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::SetBlockedStatePolicy(
+                        blocked_state_policy,
+                    ));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_blocked_state_policy response");
+            }
+        }
+    }
+
+    async fn on_set_show_beta_releases(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
+        let save_result = self.settings.set_show_beta_releases(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_show_beta_releases response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    let mut handle = self.version_updater_handle.clone();
+                    handle.set_show_beta_releases(enabled).await;
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_show_beta_releases response");
+            }
+        }
+    }
+
+    async fn on_set_log_privacy(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        log_privacy: LogPrivacy,
+    ) {
+        let save_result = self.settings.set_log_privacy(log_privacy).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_log_privacy response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    log::info!("Log privacy level set to {:?}", log_privacy);
+                    talpid_core::logging::redact::set_aggressive(
+                        log_privacy == LogPrivacy::Aggressive,
+                    );
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_log_privacy response");
+            }
+        }
+    }
+
+    async fn on_set_block_when_disconnected(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        block_when_disconnected: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_block_when_disconnected(block_when_disconnected)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_block_when_disconnected response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
+                        block_when_disconnected,
+                    ));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_block_when_disconnected response");
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    async fn on_set_allow_connection_sharing(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        allow_connection_sharing: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_allow_connection_sharing(allow_connection_sharing)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_allow_connection_sharing response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::AllowConnectionSharing(
+                        allow_connection_sharing,
+                    ));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_allow_connection_sharing response");
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    fn on_is_connection_sharing_active(&self, tx: oneshot::Sender<bool>) {
+        let is_active = talpid_core::windows::connection_sharing::is_connection_sharing_active()
+            .unwrap_or_else(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to detect Internet Connection Sharing")
+                );
+                false
+            });
+        Self::oneshot_send(tx, is_active, "is_connection_sharing_active response");
+    }
+
+    async fn on_set_block_when_account_expired(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        block_when_account_expired: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_block_when_account_expired(block_when_account_expired)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_block_when_account_expired response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if self.account_expired_job.is_some() {
+                        self.apply_account_expired_policy();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_block_when_account_expired response");
+            }
+        }
+    }
+
+    async fn on_set_strict_kill_switch(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        strict_kill_switch: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_strict_kill_switch(strict_kill_switch)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_strict_kill_switch response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::StrictKillSwitch(strict_kill_switch));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_strict_kill_switch response");
+            }
+        }
+    }
+
+    async fn on_set_prefer_low_latency(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        prefer_low_latency: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_prefer_low_latency(prefer_low_latency)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_prefer_low_latency response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.relay_selector.set_prefer_low_latency(
+                        feature_flags::apply(self.settings.to_settings()).prefer_low_latency,
+                    );
+                    if matches!(self.tunnel_state, TunnelState::Disconnected) {
+                        self.relay_selector.probe_latencies();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_prefer_low_latency response");
+            }
+        }
+    }
+
+    async fn on_set_use_system_proxy(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        use_system_proxy: bool,
+    ) {
+        let save_result = self.settings.set_use_system_proxy(use_system_proxy).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_use_system_proxy response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.rpc_runtime.set_use_system_proxy(use_system_proxy);
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_use_system_proxy response");
+            }
+        }
+    }
+
+    async fn on_apply_settings_patch(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        patch: String,
+    ) {
+        let previous_settings = self.settings.to_settings();
+        match self.settings.apply_patch(&patch).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "apply_settings_patch response");
                 if settings_changed {
-                    self.event_listener
-                        .notify_settings(self.settings.to_settings());
-                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
-                        log::info!("Initiating tunnel restart");
-                        self.reconnect_tunnel();
-                    }
+                    self.apply_settings_changes(&previous_settings).await;
                 }
             }
-            Err(error) => {
+            Err(e) => {
                 log::error!(
                     "{}",
-                    error.display_chain_with_msg("Unable to save settings")
+                    e.display_chain_with_msg("Unable to apply settings patch")
                 );
-                Self::oneshot_send(tx, Err(error), "use_wireguard_nt response");
+                Self::oneshot_send(tx, Err(e), "apply_settings_patch response");
             }
         }
     }
 
-    async fn on_update_relay_settings(
-        &mut self,
-        tx: ResponseTx<(), settings::Error>,
-        update: RelaySettingsUpdate,
-    ) {
-        let save_result = self.settings.update_relay_settings(update).await;
-        match save_result {
+    fn on_export_settings(&self, tx: ResponseTx<String, settings::Error>) {
+        Self::oneshot_send(tx, self.settings.export(), "export_settings response");
+    }
+
+    async fn on_import_settings(&mut self, tx: ResponseTx<(), settings::Error>, settings: String) {
+        let previous_settings = self.settings.to_settings();
+        match self.settings.import(&settings).await {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "update_relay_settings response");
+                Self::oneshot_send(tx, Ok(()), "import_settings response");
                 if settings_changed {
-                    self.event_listener
-                        .notify_settings(self.settings.to_settings());
-                    log::info!("Initiating tunnel restart because the relay settings changed");
-                    self.reconnect_tunnel();
+                    log::info!("Imported settings");
+                    self.apply_settings_changes(&previous_settings).await;
                 }
             }
             Err(e) => {
-                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "update_relay_settings response");
+                log::error!("{}", e.display_chain_with_msg("Unable to import settings"));
+                Self::oneshot_send(tx, Err(e), "import_settings response");
             }
         }
     }
 
-    async fn on_set_allow_lan(&mut self, tx: ResponseTx<(), settings::Error>, allow_lan: bool) {
-        let save_result = self.settings.set_allow_lan(allow_lan).await;
-        match save_result {
+    async fn on_set_feature_flag(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        name: String,
+        enabled: Option<bool>,
+    ) {
+        let previous_settings = self.settings.to_settings();
+        match self.settings.set_feature_flag(&name, enabled).await {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_allow_lan response");
+                Self::oneshot_send(tx, Ok(()), "set_feature_flag response");
                 if settings_changed {
-                    self.event_listener
-                        .notify_settings(self.settings.to_settings());
-                    self.send_tunnel_command(TunnelCommand::AllowLan(allow_lan));
+                    log::info!("Feature flag \"{}\" set to {:?}", name, enabled);
+                    self.apply_settings_changes(&previous_settings).await;
                 }
             }
             Err(e) => {
-                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_allow_lan response");
+                log::error!("{}", e.display_chain_with_msg("Unable to set feature flag"));
+                Self::oneshot_send(tx, Err(e), "set_feature_flag response");
             }
         }
     }
 
-    async fn on_set_show_beta_releases(
+    fn on_get_feature_flags(&self, tx: oneshot::Sender<Vec<feature_flags::FlagStatus>>) {
+        Self::oneshot_send(
+            tx,
+            feature_flags::statuses(&self.settings.feature_flags),
+            "feature flags",
+        );
+    }
+
+    /// Switches between allowing the API through the firewall at all times and only while it is
+    /// being used.
+    fn set_on_demand_api_access(&self, on_demand: bool) {
+        self.rpc_runtime.api_access().set_on_demand(on_demand);
+    }
+
+    /// Propagates the differences between `previous_settings` and the current settings after
+    /// several settings were changed at once. Frontends are notified once, and the tunnel is
+    /// reconnected at most once.
+    async fn apply_settings_changes(&mut self, previous_settings: &Settings) {
+        let settings = self.settings.to_settings();
+        self.event_listener.notify_settings(settings.clone());
+
+        // Only the effective settings matter from here on
+        let settings = feature_flags::apply(settings);
+        let previous_settings = &feature_flags::apply(previous_settings.clone());
+
+        if settings.allow_lan != previous_settings.allow_lan {
+            self.send_tunnel_command(TunnelCommand::AllowLan(settings.allow_lan));
+        }
+        if settings.lan_allow_list != previous_settings.lan_allow_list {
+            self.send_tunnel_command(TunnelCommand::SetLanAllowList(
+                settings.lan_allow_list.clone(),
+            ));
+        }
+        if settings.blocked_state_policy != previous_settings.blocked_state_policy {
+            self.send_tunnel_command(TunnelCommand::SetBlockedStatePolicy(
+                settings.blocked_state_policy,
+            ));
+        }
+        if settings.block_when_disconnected != previous_settings.block_when_disconnected {
+            self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
+                settings.block_when_disconnected,
+            ));
+        }
+        if settings.strict_kill_switch != previous_settings.strict_kill_switch {
+            self.send_tunnel_command(TunnelCommand::StrictKillSwitch(settings.strict_kill_switch));
+        }
+        #[cfg(windows)]
+        if settings.allow_connection_sharing != previous_settings.allow_connection_sharing {
+            self.send_tunnel_command(TunnelCommand::AllowConnectionSharing(
+                settings.allow_connection_sharing,
+            ));
+        }
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        if settings.excluded_networks != previous_settings.excluded_networks {
+            self.send_tunnel_command(TunnelCommand::SetExcludedNetworks(
+                settings.excluded_networks.clone(),
+            ));
+        }
+        let dns_options = &settings.tunnel_options.dns_options;
+        if *dns_options != previous_settings.tunnel_options.dns_options {
+            #[cfg(target_os = "linux")]
+            self.send_tunnel_command(TunnelCommand::DnsOverTls(Self::use_dns_over_tls(
+                dns_options,
+            )));
+            self.send_tunnel_command(TunnelCommand::Dns(Self::get_dns_resolvers(dns_options)));
+        }
+        if settings.show_beta_releases != previous_settings.show_beta_releases {
+            let mut handle = self.version_updater_handle.clone();
+            handle
+                .set_show_beta_releases(settings.show_beta_releases)
+                .await;
+        }
+        if settings.log_privacy != previous_settings.log_privacy {
+            log::info!("Log privacy level set to {:?}", settings.log_privacy);
+            talpid_core::logging::redact::set_aggressive(
+                settings.log_privacy == LogPrivacy::Aggressive,
+            );
+        }
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        if settings.trusted_networks != previous_settings.trusted_networks {
+            self.apply_trusted_networks().await;
+        }
+        if settings.use_system_proxy != previous_settings.use_system_proxy {
+            self.rpc_runtime
+                .set_use_system_proxy(settings.use_system_proxy);
+        }
+        let on_demand_api_access =
+            feature_flags::is_enabled(&settings.feature_flags, Feature::OnDemandApiAccess);
+        if on_demand_api_access
+            != feature_flags::is_enabled(
+                &previous_settings.feature_flags,
+                Feature::OnDemandApiAccess,
+            )
+        {
+            self.set_on_demand_api_access(on_demand_api_access);
+        }
+        if settings.prefer_low_latency != previous_settings.prefer_low_latency {
+            self.relay_selector
+                .set_prefer_low_latency(settings.prefer_low_latency);
+            if matches!(self.tunnel_state, TunnelState::Disconnected) {
+                self.relay_selector.probe_latencies();
+            }
+        }
+        if settings.tunnel_options.wireguard.rotation_interval
+            != previous_settings.tunnel_options.wireguard.rotation_interval
+        {
+            self.ensure_key_rotation().await;
+        }
+        if settings.block_when_account_expired != previous_settings.block_when_account_expired
+            && self.account_expired_job.is_some()
+        {
+            self.apply_account_expired_policy();
+        }
+
+        let connected_tunnel_type = self.get_connected_tunnel_type();
+        let wireguard_options = &settings.tunnel_options.wireguard.options;
+        let previous_wireguard_options = &previous_settings.tunnel_options.wireguard.options;
+        #[cfg(windows)]
+        if wireguard_options.use_wireguard_nt != previous_wireguard_options.use_wireguard_nt {
+            talpid_core::tunnel::wireguard::reset_wireguard_nt_fallback();
+        }
+        // The MTU can be changed without reconnecting, so it is compared separately
+        let wireguard_mtu_changed = wireguard_options.mtu != previous_wireguard_options.mtu;
+        let mut other_wireguard_options = previous_wireguard_options.clone();
+        other_wireguard_options.mtu = wireguard_options.mtu;
+        let bridge_state_changed =
+            settings.get_bridge_state() != previous_settings.get_bridge_state();
+
+        let should_reconnect = settings.get_relay_settings()
+            != previous_settings.get_relay_settings()
+            || (settings.relay_fallback_tiers != previous_settings.relay_fallback_tiers
+                && self.last_generated_fallback_tier.is_some())
+            || ((settings.bridge_settings != previous_settings.bridge_settings
+                || bridge_state_changed)
+                && self.bridge_change_affects_tunnel(bridge_state_changed))
+            || settings.entry_proxy != previous_settings.entry_proxy
+            || settings.tunnel_options.generic != previous_settings.tunnel_options.generic
+            || (settings.tunnel_options.openvpn != previous_settings.tunnel_options.openvpn
+                && connected_tunnel_type == Some(TunnelType::OpenVpn))
+            || (*wireguard_options != other_wireguard_options
+                && connected_tunnel_type == Some(TunnelType::Wireguard));
+        if should_reconnect {
+            log::info!("Initiating tunnel restart because the settings changed");
+            self.reconnect_tunnel();
+        } else if wireguard_mtu_changed && connected_tunnel_type == Some(TunnelType::Wireguard) {
+            self.apply_wireguard_mtu(wireguard_options.mtu);
+        }
+    }
+
+    /// Changes the MTU of a connected WireGuard tunnel without reconnecting. The tunnel is
+    /// reconnected instead if the MTU cannot be changed on this platform or tunnel.
+    fn apply_wireguard_mtu(&mut self, mtu: Option<u16>) {
+        let (result_tx, result_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::SetWireguardMtu(mtu, result_tx));
+        let daemon_tx = self.tx.to_specialized_sender();
+
+        tokio::spawn(async move {
+            if result_rx.await.unwrap_or(false) {
+                log::debug!("Changed the WireGuard MTU without reconnecting");
+                return;
+            }
+            log::info!("Initiating tunnel restart because the WireGuard MTU setting changed");
+            let (tx, rx) = oneshot::channel();
+            let _ = daemon_tx.send(DaemonCommand::Reconnect(tx));
+            // suppress "unable to send" warning:
+            let _ = rx.await;
+        });
+    }
+
+    /// Returns whether a change to the bridge settings or bridge state can affect the current
+    /// tunnel. Bridges are only used with OpenVPN, so a WireGuard tunnel is unaffected, unless
+    /// changing the bridge state may cause an OpenVPN relay to be selected instead.
+    fn bridge_change_affects_tunnel(&self, bridge_state_changed: bool) -> bool {
+        if self.get_connected_tunnel_type() != Some(TunnelType::Wireguard) {
+            return true;
+        }
+        if !bridge_state_changed {
+            return false;
+        }
+        if self.last_generated_fallback_tier.is_some() {
+            return true;
+        }
+        match self.settings.get_relay_settings() {
+            RelaySettings::Normal(constraints) => {
+                constraints.tunnel_protocol != Constraint::Only(TunnelType::Wireguard)
+            }
+            RelaySettings::CustomTunnelEndpoint(_) => false,
+        }
+    }
+
+    async fn on_set_auto_connect(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        enabled: bool,
+        auto_connect: bool,
     ) {
-        let save_result = self.settings.set_show_beta_releases(enabled).await;
+        let save_result = self.settings.set_auto_connect(auto_connect).await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_show_beta_releases response");
+                Self::oneshot_send(tx, Ok(()), "set auto-connect response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    let mut handle = self.version_updater_handle.clone();
-                    handle.set_show_beta_releases(enabled).await;
                 }
             }
             Err(e) => {
                 log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_show_beta_releases response");
+                Self::oneshot_send(tx, Err(e), "set auto-connect response");
             }
         }
     }
 
-    async fn on_set_block_when_disconnected(
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn on_set_reconnect_on_network_change(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        block_when_disconnected: bool,
+        reconnect: bool,
     ) {
         let save_result = self
             .settings
-            .set_block_when_disconnected(block_when_disconnected)
+            .set_reconnect_on_network_change(reconnect)
             .await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_block_when_disconnected response");
+                Self::oneshot_send(tx, Ok(()), "set reconnect on network change response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
-                        block_when_disconnected,
-                    ));
                 }
             }
             Err(e) => {
                 log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_block_when_disconnected response");
+                Self::oneshot_send(tx, Err(e), "set reconnect on network change response");
             }
         }
     }
 
-    async fn on_set_auto_connect(
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn on_set_trusted_networks(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        auto_connect: bool,
+        trusted_networks: mullvad_types::settings::TrustedNetworks,
     ) {
-        let save_result = self.settings.set_auto_connect(auto_connect).await;
+        let save_result = self.settings.set_trusted_networks(trusted_networks).await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set auto-connect response");
+                Self::oneshot_send(tx, Ok(()), "set trusted networks response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
+                    self.apply_trusted_networks().await;
                 }
             }
             Err(e) => {
                 log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set auto-connect response");
+                Self::oneshot_send(tx, Err(e), "set trusted networks response");
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn handle_network_changed(&mut self, network: network_monitor::CurrentNetwork) {
+        let joined_other_network = self
+            .network_policy
+            .current_network
+            .replace(network)
+            .is_some();
+        self.apply_trusted_networks().await;
+
+        if joined_other_network
+            && self.settings.reconnect_on_network_change
+            && self.network_policy.on_trusted_network != Some(true)
+            && matches!(
+                self.tunnel_state,
+                TunnelState::Connecting { .. } | TunnelState::Connected { .. }
+            )
+        {
+            log::info!("Reconnecting since the device joined a different network");
+            self.reconnect_tunnel();
+        }
+    }
+
+    /// Disconnects the tunnel when the device joins a trusted network, and connects it again
+    /// when the device leaves the network if it was disconnected for that reason.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn apply_trusted_networks(&mut self) {
+        let trusted = self
+            .network_policy
+            .current_network
+            .as_ref()
+            .map(|network| {
+                self.settings
+                    .trusted_networks
+                    .contains(&network.ssids, &network.subnets)
+            })
+            .unwrap_or(false);
+        if self.network_policy.on_trusted_network == Some(trusted) {
+            return;
+        }
+        self.network_policy.on_trusted_network = Some(trusted);
+
+        if trusted {
+            if *self.target_state == TargetState::Secured {
+                log::info!("Disconnecting since the device joined a trusted network");
+                self.target_state
+                    .set_disconnected_on_trusted_network(true)
+                    .await;
+                self.set_target_state(TargetState::Unsecured).await;
+            }
+        } else if self.target_state.disconnected_on_trusted_network() {
+            self.target_state
+                .set_disconnected_on_trusted_network(false)
+                .await;
+            if *self.target_state == TargetState::Unsecured {
+                log::info!("Connecting since the device left the trusted network");
+                self.set_target_state(TargetState::Secured).await;
             }
         }
     }
@@ -2129,7 +4000,10 @@ where
                 if settings_changes {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.reconnect_tunnel();
+                    if self.bridge_change_affects_tunnel(false) {
+                        log::info!("Initiating tunnel restart because the bridge settings changed");
+                        self.reconnect_tunnel();
+                    }
                 };
                 Self::oneshot_send(tx, Ok(()), "set_bridge_settings");
             }
@@ -2154,8 +4028,10 @@ where
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    log::info!("Initiating tunnel restart because bridge state changed");
-                    self.reconnect_tunnel();
+                    if self.bridge_change_affects_tunnel(true) {
+                        log::info!("Initiating tunnel restart because bridge state changed");
+                        self.reconnect_tunnel();
+                    }
                 }
                 Ok(())
             }
@@ -2170,6 +4046,32 @@ where
         Self::oneshot_send(tx, result, "on_set_bridge_state response");
     }
 
+    async fn on_set_entry_proxy(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        entry_proxy: Option<EntryProxy>,
+    ) {
+        let result = match self.settings.set_entry_proxy(entry_proxy).await {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    log::info!("Initiating tunnel restart because the entry proxy changed");
+                    self.reconnect_tunnel();
+                }
+                Ok(())
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to set new entry proxy")
+                );
+                Err(error)
+            }
+        };
+        Self::oneshot_send(tx, result, "on_set_entry_proxy response");
+    }
+
     async fn on_set_enable_ipv6(&mut self, tx: ResponseTx<(), settings::Error>, enable_ipv6: bool) {
         let save_result = self.settings.set_enable_ipv6(enable_ipv6).await;
         match save_result {
@@ -2201,7 +4103,11 @@ where
                 if settings_changed {
                     let settings = self.settings.to_settings();
                     let resolvers = Self::get_dns_resolvers(&settings.tunnel_options.dns_options);
+                    #[cfg(target_os = "linux")]
+                    let dns_over_tls = Self::use_dns_over_tls(&settings.tunnel_options.dns_options);
                     self.event_listener.notify_settings(settings);
+                    #[cfg(target_os = "linux")]
+                    self.send_tunnel_command(TunnelCommand::DnsOverTls(dns_over_tls));
                     self.send_tunnel_command(TunnelCommand::Dns(resolvers));
                 }
             }
@@ -2221,12 +4127,38 @@ where
         match save_result {
             Ok(settings_changed) => {
                 Self::oneshot_send(tx, Ok(()), "set_wireguard_mtu response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        self.apply_wireguard_mtu(mtu);
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_mtu response");
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "linux", windows))]
+    async fn on_set_wireguard_interface_name(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        name: Option<String>,
+    ) {
+        let save_result = self.settings.set_wireguard_interface_name(name).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_interface_name response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
                     if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
                         log::info!(
-                            "Initiating tunnel restart because the WireGuard MTU setting changed"
+                            "Initiating tunnel restart because the WireGuard interface name \
+                             changed"
                         );
                         self.reconnect_tunnel();
                     }
@@ -2234,7 +4166,7 @@ where
             }
             Err(e) => {
                 log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_wireguard_mtu response");
+                Self::oneshot_send(tx, Err(e), "set_wireguard_interface_name response");
             }
         }
     }
@@ -2386,17 +4318,87 @@ where
         });
     }
 
+    fn on_list_account_keys(&mut self, tx: ResponseTx<Vec<wireguard::RegisteredKey>, Error>) {
+        let account = match self.settings.get_account_token() {
+            Some(account) => account,
+            None => {
+                Self::oneshot_send(tx, Err(Error::NoAccountToken), "list_account_keys response");
+                return;
+            }
+        };
+        let list_rpc = self.wireguard_key_manager.list_keys(account);
+
+        tokio::spawn(async move {
+            let result = list_rpc.await.map_err(Self::map_key_manager_error);
+            Self::oneshot_send(tx, result, "list_account_keys response");
+        });
+    }
+
+    fn on_revoke_account_key(&mut self, tx: ResponseTx<(), Error>, key: wireguard::PublicKey) {
+        let account = match self.settings.get_account_token() {
+            Some(account) => account,
+            None => {
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::NoAccountToken),
+                    "revoke_account_key response",
+                );
+                return;
+            }
+        };
+        let is_current_key = self
+            .settings
+            .get_wireguard()
+            .map(|wg_data| wg_data.private_key.public_key() == key)
+            .unwrap_or(false);
+        if is_current_key {
+            Self::oneshot_send(
+                tx,
+                Err(Error::RevokeCurrentKey),
+                "revoke_account_key response",
+            );
+            return;
+        }
+        let remove_rpc = self.wireguard_key_manager.remove_key(account, key);
+
+        tokio::spawn(async move {
+            let result = remove_rpc.await.map_err(Self::map_key_manager_error);
+            Self::oneshot_send(tx, result, "revoke_account_key response");
+        });
+    }
+
+    fn map_key_manager_error(error: wireguard::Error) -> Error {
+        match error {
+            wireguard::Error::RestError(error) => Error::RestError(error),
+            wireguard::Error::ApiCheckError(error) => Error::ApiCheckError(error),
+            wireguard::Error::TooManyKeys => Error::TooManyKeys,
+        }
+    }
+
     fn on_get_settings(&self, tx: oneshot::Sender<Settings>) {
         Self::oneshot_send(tx, self.settings.to_settings(), "get_settings response");
     }
 
+    fn on_get_settings_load_failure(&self, tx: oneshot::Sender<Option<settings::LoadFailure>>) {
+        Self::oneshot_send(
+            tx,
+            self.settings.load_failure().cloned(),
+            "settings load failure",
+        );
+    }
+
     fn oneshot_send<T>(tx: oneshot::Sender<T>, t: T, msg: &'static str) {
         if tx.send(t).is_err() {
             log::warn!("Unable to send {} to the daemon command sender", msg);
         }
     }
 
-    fn trigger_shutdown_event(&mut self) {
+    fn trigger_shutdown_event(&mut self, user_init_shutdown: bool) {
+        // Keep blocking traffic while the system shuts down and until the daemon is started
+        // again, if the tunnel was supposed to be up
+        if !user_init_shutdown {
+            self.on_prepare_restart();
+        }
         self.state.shutdown(&self.tunnel_state);
         self.disconnect_tunnel();
     }
@@ -2442,6 +4444,7 @@ where
     async fn forward_offline_state(
         api_availability: ApiAvailabilityHandle,
         mut offline_state_rx: mpsc::UnboundedReceiver<bool>,
+        daemon_tx: DaemonEventSender,
     ) {
         let initial_state = offline_state_rx
             .next()
@@ -2449,8 +4452,30 @@ where
             .expect("missing initial offline state");
         api_availability.set_offline(initial_state);
         tokio::spawn(async move {
+            let mut was_offline = initial_state;
             while let Some(is_offline) = offline_state_rx.next().await {
                 api_availability.set_offline(is_offline);
+                if was_offline && !is_offline {
+                    let _ = daemon_tx.send(InternalDaemonEvent::NetworkOnline);
+                }
+                was_offline = is_offline;
+            }
+        });
+    }
+
+    #[cfg(target_os = "macos")]
+    fn forward_dns_interference(
+        mut dns_interference_rx: mpsc::UnboundedReceiver<()>,
+        daemon_tx: DaemonEventSender,
+    ) {
+        tokio::spawn(async move {
+            while let Some(()) = dns_interference_rx.next().await {
+                if daemon_tx
+                    .send(InternalDaemonEvent::DnsInterference)
+                    .is_err()
+                {
+                    break;
+                }
             }
         });
     }
@@ -2466,7 +4491,10 @@ where
 
             match *self.target_state {
                 TargetState::Secured => self.connect_tunnel(),
-                TargetState::Unsecured => self.disconnect_tunnel(),
+                TargetState::Unsecured => {
+                    self.test_endpoint = None;
+                    self.disconnect_tunnel()
+                }
             }
             true
         } else {
@@ -2475,6 +4503,11 @@ where
     }
 
     fn connect_tunnel(&mut self) {
+        if self.account_expired_job.is_some() {
+            log::debug!("Not connecting since the account has no time left");
+            self.apply_account_expired_policy();
+            return;
+        }
         self.send_tunnel_command(TunnelCommand::Connect);
     }
 
@@ -2577,6 +4610,13 @@ where
             tx: self.tx.clone(),
         }
     }
+
+    #[cfg(windows)]
+    pub fn session_handle(&self) -> DaemonSessionHandle {
+        DaemonSessionHandle {
+            tx: self.tx.clone(),
+        }
+    }
 }
 
 pub struct DaemonShutdownHandle {
@@ -2584,8 +4624,24 @@ pub struct DaemonShutdownHandle {
 }
 
 impl DaemonShutdownHandle {
-    pub fn shutdown(&self) {
-        let _ = self.tx.send(InternalDaemonEvent::TriggerShutdown);
+    /// Stops the daemon. `user_init_shutdown` should be false if the daemon is stopped because
+    /// the system is shutting down.
+    pub fn shutdown(&self, user_init_shutdown: bool) {
+        let _ = self
+            .tx
+            .send(InternalDaemonEvent::TriggerShutdown(user_init_shutdown));
+    }
+}
+
+#[cfg(windows)]
+pub struct DaemonSessionHandle {
+    tx: DaemonEventSender,
+}
+
+#[cfg(windows)]
+impl DaemonSessionHandle {
+    pub fn session_changed(&self, change: session::SessionChange) {
+        let _ = self.tx.send(InternalDaemonEvent::SessionChange(change));
     }
 }
 
@@ -2621,6 +4677,21 @@ impl TunnelParametersGenerator for MullvadTunnelParametersGenerator {
     }
 }
 
+pub use talpid_core::tunnel::wireguard::transport_plugin::{
+    TransportHandle, TunnelTransportPlugin,
+};
+
+/// Makes WireGuard tunnels carry their traffic to the entry relay over `plugin` instead of plain
+/// UDP or UDP-over-TCP. This is meant for developing experimental obfuscators outside of this
+/// repository, in a binary that registers the plugin before starting the daemon.
+pub fn register_transport_plugin(plugin: Arc<dyn TunnelTransportPlugin>) {
+    log::warn!(
+        "Using experimental transport plugin \"{}\" for WireGuard",
+        plugin.name()
+    );
+    talpid_core::tunnel::wireguard::transport_plugin::register(plugin);
+}
+
 /// Bump filehandle limit
 #[cfg(target_os = "macos")]
 pub fn bump_filehandle_limit() {
@@ -0,0 +1,176 @@
+//! Translations of country and city names in the relay list. The translation tables are the
+//! `relay-locations.po` files that are shipped with the GUI, embedded at compile time.
+
+use mullvad_types::relay_list::RelayList;
+use std::collections::HashMap;
+
+macro_rules! translation_tables {
+    ($($locale:literal),* $(,)?) => {
+        &[$(
+            (
+                $locale,
+                include_str!(concat!("../../gui/locales/", $locale, "/relay-locations.po")),
+            ),
+        )*]
+    };
+}
+
+/// Bundled translation tables, keyed by locale.
+static TRANSLATION_TABLES: &[(&str, &str)] = translation_tables!(
+    "da", "de", "es", "fi", "fr", "it", "ja", "ko", "my", "nb", "nl", "pl", "pt", "ru", "sv", "th",
+    "tr", "zh-CN", "zh-TW",
+);
+
+/// Translates the country and city names in `relay_list` into `locale`. Names that have no
+/// translation, or all names if there is no translation table for the locale, are left in English.
+/// Returns whether a translation table was found.
+pub fn localize_relay_list(relay_list: &mut RelayList, locale: &str) -> bool {
+    let table = match find_table(locale) {
+        Some(table) => parse_po(table),
+        None => return false,
+    };
+    let translate = |name: &mut String| {
+        if let Some(translation) = table.get(name.as_str()) {
+            *name = translation.clone();
+        }
+    };
+
+    for country in &mut relay_list.countries {
+        translate(&mut country.name);
+        for city in &mut country.cities {
+            translate(&mut city.name);
+            for relay in &mut city.relays {
+                if let Some(location) = relay.location.as_mut() {
+                    translate(&mut location.country);
+                    translate(&mut location.city);
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Finds the translation table for `locale`, which may be given as e.g. `zh-TW`, `zh_TW` or
+/// `pt-BR`. Falls back on the language alone if there is no table for the exact region.
+fn find_table(locale: &str) -> Option<&'static str> {
+    let locale = locale.trim().replace('_', "-");
+    let language = locale.split('-').next().unwrap_or_default();
+    let lookup = |wanted: &str| {
+        TRANSLATION_TABLES
+            .iter()
+            .find(|(locale, _)| locale.eq_ignore_ascii_case(wanted))
+            .map(|(_, table)| *table)
+    };
+    lookup(&locale).or_else(|| lookup(language))
+}
+
+/// Parses the `msgid`/`msgstr` pairs of a gettext catalog. Untranslated entries are omitted.
+fn parse_po(contents: &str) -> HashMap<String, String> {
+    enum Field {
+        None,
+        Id,
+        Str,
+    }
+
+    let mut translations = HashMap::new();
+    let mut msgid = String::new();
+    let mut msgstr = String::new();
+    let mut field = Field::None;
+
+    let mut flush = |msgid: &mut String, msgstr: &mut String| {
+        if !msgid.is_empty() && !msgstr.is_empty() {
+            translations.insert(std::mem::take(msgid), std::mem::take(msgstr));
+        }
+        msgid.clear();
+        msgstr.clear();
+    };
+
+    for line in contents.lines().map(str::trim) {
+        if let Some(value) = line.strip_prefix("msgid ") {
+            flush(&mut msgid, &mut msgstr);
+            msgid = unquote(value);
+            field = Field::Id;
+        } else if let Some(value) = line.strip_prefix("msgstr ") {
+            msgstr = unquote(value);
+            field = Field::Str;
+        } else if line.starts_with('"') {
+            match field {
+                Field::Id => msgid.push_str(&unquote(line)),
+                Field::Str => msgstr.push_str(&unquote(line)),
+                Field::None => (),
+            }
+        } else {
+            field = Field::None;
+        }
+    }
+    flush(&mut msgid, &mut msgstr);
+
+    translations
+}
+
+/// Removes the surrounding quotes from a gettext string and resolves its escape sequences.
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some(other) => result.push(other),
+            None => (),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CATALOG: &str = r#"msgid ""
+msgstr ""
+"Language: sv_SE\n"
+
+#. AL
+msgid "Albania"
+msgstr "Albanien"
+
+#. US ATL
+msgid "Atlanta, GA"
+msgstr ""
+
+#. GR ATH
+msgid ""
+"Ath"
+"ens"
+msgstr "Aten \"GR\""
+"#;
+
+    #[test]
+    fn test_parse_po() {
+        let translations = parse_po(CATALOG);
+        assert_eq!(translations.len(), 2);
+        assert_eq!(translations["Albania"], "Albanien");
+        assert_eq!(translations["Athens"], "Aten \"GR\"");
+        assert!(!translations.contains_key("Atlanta, GA"));
+    }
+
+    #[test]
+    fn test_find_table() {
+        assert!(find_table("sv").is_some());
+        assert!(find_table("zh_tw").is_some());
+        assert_eq!(find_table("pt-BR"), find_table("pt"));
+        assert!(find_table("xx").is_none());
+        assert!(find_table("").is_none());
+    }
+}
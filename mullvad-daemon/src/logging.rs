@@ -2,8 +2,8 @@ use fern::{
     colors::{Color, ColoredLevelConfig},
     Output,
 };
-use std::{fmt, io, path::PathBuf};
-use talpid_core::logging::rotate_log;
+use std::{fmt, io, path::PathBuf, str::FromStr, sync::Mutex};
+use talpid_core::logging::{redact, rotate_log, RotatingLogFile, RotationLimits};
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -61,10 +61,45 @@ const LINE_SEPARATOR: &str = "\r\n";
 
 const DATE_TIME_FORMAT_STR: &str = "[%Y-%m-%d %H:%M:%S%.3f]";
 
+lazy_static::lazy_static! {
+    /// Name of the current tunnel state, which is included in structured log lines.
+    static ref TUNNEL_STATE_CONTEXT: Mutex<&'static str> = Mutex::new("disconnected");
+}
+
+/// How log lines are formatted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines.
+    Text,
+    /// One JSON object per line, for log aggregation tools.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("Unknown log format: {}", other)),
+        }
+    }
+}
+
+/// Sets the tunnel state that is included in structured log lines from now on.
+pub fn set_tunnel_state_context(tunnel_state: &'static str) {
+    *TUNNEL_STATE_CONTEXT
+        .lock()
+        .expect("Tunnel state context lock poisoned") = tunnel_state;
+}
+
 pub fn init_logger(
     log_level: log::LevelFilter,
     log_file: Option<&PathBuf>,
     output_timestamp: bool,
+    log_format: LogFormat,
+    rotation_limits: RotationLimits,
 ) -> Result<(), Error> {
     let mut top_dispatcher = fern::Dispatch::new().level(log_level);
     for silenced_crate in WARNING_SILENCED_CRATES {
@@ -80,6 +115,7 @@ pub fn init_logger(
     let stdout_formatter = Formatter {
         output_timestamp,
         output_color: true,
+        log_format,
     };
     let stdout_dispatcher = fern::Dispatch::new()
         .format(move |out, message, record| stdout_formatter.output_msg(out, message, record))
@@ -91,14 +127,16 @@ pub fn init_logger(
         let file_formatter = Formatter {
             output_timestamp: true,
             output_color: false,
+            log_format,
         };
-        let f = fern::log_file(log_file).map_err(|source| Error::WriteFile {
-            path: log_file.display().to_string(),
-            source,
-        })?;
+        let f =
+            RotatingLogFile::new(log_file, rotation_limits).map_err(|source| Error::WriteFile {
+                path: log_file.display().to_string(),
+                source,
+            })?;
         let file_dispatcher = fern::Dispatch::new()
             .format(move |out, message, record| file_formatter.output_msg(out, message, record))
-            .chain(Output::file(f, LINE_SEPARATOR));
+            .chain(Output::writer(Box::new(f), LINE_SEPARATOR));
         top_dispatcher = top_dispatcher.chain(file_dispatcher);
     }
     #[cfg(all(target_os = "android", debug_assertions))]
@@ -125,10 +163,11 @@ fn one_level_quieter(level: log::LevelFilter) -> log::LevelFilter {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Debug)]
 struct Formatter {
     pub output_timestamp: bool,
     pub output_color: bool,
+    pub log_format: LogFormat,
 }
 
 impl Formatter {
@@ -154,7 +193,21 @@ impl Formatter {
         message: &fmt::Arguments<'_>,
         record: &log::Record<'_>,
     ) {
-        let message = escape_newlines(format!("{}", message));
+        if self.log_format == LogFormat::Json {
+            let tunnel_state = *TUNNEL_STATE_CONTEXT
+                .lock()
+                .expect("Tunnel state context lock poisoned");
+            let line = json_line(
+                chrono::Utc::now(),
+                record,
+                &redact::scrub(&message.to_string()),
+                tunnel_state,
+            );
+            out.finish(format_args!("{}", line));
+            return;
+        }
+
+        let message = escape_newlines(redact::scrub(&message.to_string()).into_owned());
 
         out.finish(format_args!(
             "{}[{}][{}] {}",
@@ -166,6 +219,24 @@ impl Formatter {
     }
 }
 
+/// Formats a log record as a single line of JSON. Newlines in the message are escaped by the JSON
+/// encoding.
+fn json_line(
+    timestamp: chrono::DateTime<chrono::Utc>,
+    record: &log::Record<'_>,
+    message: &str,
+    tunnel_state: &str,
+) -> String {
+    serde_json::json!({
+        "timestamp": timestamp.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        "level": record.level().as_str(),
+        "module": record.target(),
+        "message": message,
+        "tunnel_state": tunnel_state,
+    })
+    .to_string()
+}
+
 #[cfg(not(windows))]
 fn escape_newlines(text: String) -> String {
     text
@@ -175,3 +246,30 @@ fn escape_newlines(text: String) -> String {
 fn escape_newlines(text: String) -> String {
     text.replace("\n", LINE_SEPARATOR)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_json_line() {
+        let timestamp = chrono::Utc.ymd(2022, 3, 1).and_hms_milli(12, 30, 0, 250);
+        let line = json_line(
+            timestamp,
+            &log::Record::builder()
+                .level(log::Level::Warn)
+                .target("mullvad_daemon::relays")
+                .build(),
+            "No relays\nmatch",
+            "connecting",
+        );
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert!(!line.contains('\n'));
+        assert_eq!(value["timestamp"], "2022-03-01T12:30:00.250Z");
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["module"], "mullvad_daemon::relays");
+        assert_eq!(value["message"], "No relays\nmatch");
+        assert_eq!(value["tunnel_state"], "connecting");
+    }
+}
@@ -51,6 +51,8 @@ fn init_logging(config: &cli::Config) -> Result<Option<PathBuf>, String> {
         config.log_level,
         log_file.as_ref(),
         config.log_stdout_timestamps,
+        config.log_format,
+        config.log_rotation_limits,
     )
     .map_err(|e| e.display_chain_with_msg("Unable to initialize logger"))?;
     log_panics::init();
@@ -95,20 +97,38 @@ async fn run_platform(config: &cli::Config, log_dir: Option<PathBuf>) -> Result<
     }
 }
 
-#[cfg(not(windows))]
+#[cfg(target_os = "linux")]
+async fn run_platform(config: &cli::Config, log_dir: Option<PathBuf>) -> Result<(), String> {
+    if config.initialize_firewall_and_exit {
+        return mullvad_daemon::early_boot_firewall::initialize_firewall()
+            .await
+            .map_err(|e| e.display_chain());
+    }
+    run_standalone(log_dir).await
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
 async fn run_platform(_config: &cli::Config, log_dir: Option<PathBuf>) -> Result<(), String> {
     run_standalone(log_dir).await
 }
 
 async fn run_standalone(log_dir: Option<PathBuf>) -> Result<(), String> {
-    if rpc_uniqueness_check::is_another_instance_running().await {
-        return Err("Another instance of the daemon is already running".to_owned());
-    }
+    // systemd only passes the socket to a single instance, and keeps it around between restarts
+    #[cfg(target_os = "linux")]
+    let socket_activated = mullvad_management_interface::is_socket_activated();
+    #[cfg(not(target_os = "linux"))]
+    let socket_activated = false;
+
+    if !socket_activated {
+        if rpc_uniqueness_check::is_another_instance_running().await {
+            return Err("Another instance of the daemon is already running".to_owned());
+        }
 
-    #[cfg(any(target_os = "macos", target_os = "linux"))]
-    if let Err(err) = tokio::fs::remove_file(mullvad_paths::get_rpc_socket_path()).await {
-        if err.kind() != std::io::ErrorKind::NotFound {
-            log::error!("Failed to remove old RPC socket: {}", err);
+        #[cfg(any(target_os = "macos", target_os = "linux"))]
+        if let Err(err) = tokio::fs::remove_file(mullvad_paths::get_rpc_socket_path()).await {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                log::error!("Failed to remove old RPC socket: {}", err);
+            }
         }
     }
 
@@ -119,7 +139,7 @@ async fn run_standalone(log_dir: Option<PathBuf>) -> Result<(), String> {
     let daemon = create_daemon(log_dir).await?;
 
     let shutdown_handle = daemon.shutdown_handle();
-    shutdown::set_shutdown_signal_handler(move || shutdown_handle.shutdown())
+    shutdown::set_shutdown_signal_handler(move || shutdown_handle.shutdown(true))
         .map_err(|e| e.display_chain())?;
 
     daemon.run().await.map_err(|e| e.display_chain())?;
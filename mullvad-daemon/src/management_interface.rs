@@ -1,11 +1,18 @@
-use crate::{account_history, settings, DaemonCommand, DaemonCommandSender, EventListener};
+use crate::{
+    account_history, health_check, location_names, settings, DaemonCommand, DaemonCommandSender,
+    EventListener, EventSnapshot,
+};
 use futures::{
     channel::{mpsc, oneshot},
     StreamExt,
 };
+use ipnetwork::IpNetwork;
 use mullvad_management_interface::{
-    types::{self, daemon_event, management_service_server::ManagementService},
-    Code, Request, Response, Status,
+    types::{
+        self, daemon_event, event_subscription::Category as EventCategory,
+        management_service_server::ManagementService,
+    },
+    ClientInfo, Code, Request, Response, Status,
 };
 use mullvad_paths;
 use mullvad_rpc::{rest::Error as RestError, StatusCode};
@@ -13,9 +20,9 @@ use mullvad_rpc::{rest::Error as RestError, StatusCode};
 use mullvad_types::settings::DnsOptions;
 use mullvad_types::{
     account::AccountToken,
-    relay_constraints::{BridgeSettings, BridgeState, RelaySettingsUpdate},
+    relay_constraints::{BridgeSettings, BridgeState, RelayConstraints, RelaySettingsUpdate},
     relay_list::RelayList,
-    settings::Settings,
+    settings::{LogPrivacy, Settings},
     states::{TargetState, TunnelState},
     version,
     wireguard::{RotationInterval, RotationIntervalError},
@@ -29,7 +36,7 @@ use std::{
     sync::Arc,
     time::Duration,
 };
-use talpid_types::ErrorExt;
+use talpid_types::{firewall::BlockedStatePolicy, net::proxy::EntryProxy, ErrorExt};
 use tokio_stream::wrappers::{ReceiverStream, UnboundedReceiverStream};
 
 #[derive(err_derive::Error, Debug)]
@@ -42,27 +49,84 @@ pub enum Error {
 
 struct ManagementServiceImpl {
     daemon_tx: DaemonCommandSender,
-    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<RwLock<Vec<EventSubscriber>>>,
 }
 
 pub type ServiceResult<T> = std::result::Result<Response<T>, Status>;
 type EventsListenerReceiver = UnboundedReceiverStream<Result<types::DaemonEvent, Status>>;
 type EventsListenerSender = tokio::sync::mpsc::UnboundedSender<Result<types::DaemonEvent, Status>>;
 
+/// A client that listens for daemon events.
+struct EventSubscriber {
+    tx: EventsListenerSender,
+    /// Categories of events that are sent to the client. All events are sent if this is empty.
+    categories: Vec<EventCategory>,
+    /// The Windows session that the client runs in.
+    #[cfg(windows)]
+    session_id: Option<u32>,
+}
+
+/// Categories of the events that were sent before clients could choose. Clients that do not
+/// choose only get these, so that they never see events they do not understand.
+const LEGACY_EVENT_CATEGORIES: &[EventCategory] = &[
+    EventCategory::TunnelState,
+    EventCategory::Settings,
+    EventCategory::RelayList,
+    EventCategory::VersionInfo,
+    EventCategory::KeyEvent,
+];
+
+impl EventSubscriber {
+    #[cfg_attr(not(windows), allow(unused_variables))]
+    fn new(tx: EventsListenerSender, categories: Vec<EventCategory>, client: &ClientInfo) -> Self {
+        Self {
+            tx,
+            categories,
+            #[cfg(windows)]
+            session_id: client.session_id,
+        }
+    }
+
+    /// Sends `event` if the client subscribed to its category. Returns `false` if the client is
+    /// gone.
+    fn send(&self, event: &types::DaemonEvent) -> bool {
+        let category = match event_category(event) {
+            Some(category) => category,
+            None => return true,
+        };
+        if !self.categories.is_empty() && !self.categories.contains(&category) {
+            return true;
+        }
+        self.tx.send(Ok(event.clone())).is_ok()
+    }
+}
+
+/// Interval at which tunnel statistics are streamed if the client does not choose one.
+const DEFAULT_STATISTICS_INTERVAL: Duration = Duration::from_secs(1);
+/// Shortest interval at which tunnel statistics are streamed.
+const MIN_STATISTICS_INTERVAL: Duration = Duration::from_millis(250);
+
 const INVALID_VOUCHER_MESSAGE: &str = "This voucher code is invalid";
 const USED_VOUCHER_MESSAGE: &str = "This voucher code has already been used";
+const EXPIRED_VOUCHER_MESSAGE: &str = "This voucher code has expired";
 
 #[mullvad_management_interface::async_trait]
 impl ManagementService for ManagementServiceImpl {
     type GetRelayLocationsStream = ReceiverStream<Result<types::RelayListCountry, Status>>;
+    type GetLocalizedRelayLocationsStream = ReceiverStream<Result<types::RelayListCountry, Status>>;
     type GetSplitTunnelProcessesStream = UnboundedReceiverStream<Result<i32, Status>>;
+    type StreamTunnelStatisticsStream = ReceiverStream<Result<types::TunnelStatistics, Status>>;
     type EventsListenStream = EventsListenerReceiver;
+    type EventsListenFilteredStream = EventsListenerReceiver;
 
     // Control and get the tunnel state
     //
 
-    async fn connect_tunnel(&self, _: Request<()>) -> ServiceResult<bool> {
+    #[cfg_attr(not(windows), allow(unused_variables))]
+    async fn connect_tunnel(&self, request: Request<()>) -> ServiceResult<bool> {
         log::debug!("connect_tunnel");
+        #[cfg(windows)]
+        log::debug!("Connect requested by {}", requester(&request));
 
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::SetTargetState(tx, TargetState::Secured))?;
@@ -70,8 +134,11 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(connect_issued))
     }
 
-    async fn disconnect_tunnel(&self, _: Request<()>) -> ServiceResult<bool> {
+    #[cfg_attr(not(windows), allow(unused_variables))]
+    async fn disconnect_tunnel(&self, request: Request<()>) -> ServiceResult<bool> {
         log::debug!("disconnect_tunnel");
+        #[cfg(windows)]
+        log::debug!("Disconnect requested by {}", requester(&request));
 
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::SetTargetState(tx, TargetState::Unsecured))?;
@@ -98,11 +165,56 @@ impl ManagementService for ManagementServiceImpl {
     // Control the daemon and receive events
     //
 
-    async fn events_listen(&self, _: Request<()>) -> ServiceResult<Self::EventsListenStream> {
+    async fn events_listen(&self, request: Request<()>) -> ServiceResult<Self::EventsListenStream> {
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
 
         let mut subscriptions = self.subscriptions.write();
-        subscriptions.push(tx);
+        subscriptions.push(EventSubscriber::new(
+            tx,
+            LEGACY_EVENT_CATEGORIES.to_vec(),
+            &ClientInfo::of(&request),
+        ));
+
+        Ok(Response::new(UnboundedReceiverStream::new(rx)))
+    }
+
+    async fn events_listen_filtered(
+        &self,
+        request: Request<types::EventSubscription>,
+    ) -> ServiceResult<Self::EventsListenFilteredStream> {
+        let client = ClientInfo::of(&request);
+        let request = request.into_inner();
+        log::debug!("events_listen_filtered");
+
+        let categories = request
+            .categories
+            .into_iter()
+            .map(|category| {
+                EventCategory::from_i32(category)
+                    .ok_or_else(|| Status::invalid_argument("invalid event category"))
+            })
+            .collect::<Result<Vec<_>, Status>>()?;
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let subscriber = EventSubscriber::new(tx, categories, &client);
+
+        if request.send_snapshot {
+            // Register the subscriber on the daemon's event loop so that no changes are missed
+            // between the snapshot and the first event.
+            let subscriptions = self.subscriptions.clone();
+            self.send_command_to_daemon(DaemonCommand::GetEventSnapshot(Box::new(
+                move |snapshot| {
+                    for event in snapshot_events(snapshot) {
+                        if !subscriber.send(&event) {
+                            return;
+                        }
+                    }
+                    subscriptions.write().push(subscriber);
+                },
+            )))?;
+        } else {
+            self.subscriptions.write().push(subscriber);
+        }
 
         Ok(Response::new(UnboundedReceiverStream::new(rx)))
     }
@@ -119,10 +231,13 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(()))
     }
 
-    async fn factory_reset(&self, _: Request<()>) -> ServiceResult<()> {
+    #[cfg_attr(not(windows), allow(unused_variables))]
+    async fn factory_reset(&self, request: Request<()>) -> ServiceResult<()> {
         #[cfg(not(target_os = "android"))]
         {
             log::debug!("factory_reset");
+            #[cfg(windows)]
+            log::info!("Factory reset requested by {}", requester(&request));
             let (tx, rx) = oneshot::channel();
             self.send_command_to_daemon(DaemonCommand::FactoryReset(tx))?;
             self.wait_for_result(rx)
@@ -144,6 +259,195 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(version))
     }
 
+    async fn get_firewall_policy(&self, _: Request<()>) -> ServiceResult<types::FirewallPolicy> {
+        log::debug!("get_firewall_policy");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetFirewallPolicy(tx))?;
+        let policy = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::FirewallPolicy::from(policy)))
+    }
+
+    async fn get_tunnel_debug_info(&self, _: Request<()>) -> ServiceResult<types::TunnelDebugInfo> {
+        log::debug!("get_tunnel_debug_info");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetTunnelDebugInfo(tx))?;
+        let info = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::TunnelDebugInfo::from(info)))
+    }
+
+    async fn get_tunnel_statistics(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::TunnelStatistics> {
+        log::debug!("get_tunnel_statistics");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetTunnelStatistics(tx))?;
+        let statistics = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::TunnelStatistics::from(statistics)))
+    }
+
+    async fn stream_tunnel_statistics(
+        &self,
+        request: Request<types::Duration>,
+    ) -> ServiceResult<Self::StreamTunnelStatisticsStream> {
+        let interval = Duration::try_from(request.into_inner())
+            .map_err(|_| Status::invalid_argument("unexpected negative duration"))?;
+        let interval = if interval == Duration::ZERO {
+            DEFAULT_STATISTICS_INTERVAL
+        } else {
+            cmp::max(interval, MIN_STATISTICS_INTERVAL)
+        };
+        log::debug!("stream_tunnel_statistics({:?})", interval);
+
+        let daemon_tx = self.daemon_tx.clone();
+        let (stream_tx, stream_rx) = tokio::sync::mpsc::channel(1);
+        tokio::spawn(async move {
+            loop {
+                let (tx, rx) = oneshot::channel();
+                if daemon_tx
+                    .send(DaemonCommand::GetTunnelStatistics(tx))
+                    .is_err()
+                {
+                    break;
+                }
+                let statistics = match rx.await {
+                    Ok(statistics) => types::TunnelStatistics::from(statistics),
+                    Err(_) => break,
+                };
+                if stream_tx.send(Ok(statistics)).await.is_err() {
+                    break;
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(stream_rx)))
+    }
+
+    async fn get_tunnel_state_machine_debug_info(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::StateMachineDebugInfo> {
+        log::debug!("get_tunnel_state_machine_debug_info");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetTunnelStateMachineDebugInfo(tx))?;
+        let info = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::StateMachineDebugInfo::from(info)))
+    }
+
+    async fn run_health_check(&self, _: Request<()>) -> ServiceResult<types::HealthReport> {
+        use health_check::Status as CheckStatus;
+        use types::health_report::check;
+
+        log::debug!("run_health_check");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RunHealthCheck(tx))?;
+        let checks = self
+            .wait_for_result(rx)
+            .await?
+            .into_iter()
+            .map(|result| types::health_report::Check {
+                name: result.name.to_owned(),
+                status: i32::from(match result.status {
+                    CheckStatus::Ok => check::Status::Ok,
+                    CheckStatus::Warning => check::Status::Warning,
+                    CheckStatus::Failed => check::Status::Failed,
+                    CheckStatus::Skipped => check::Status::Skipped,
+                }),
+                details: result.details,
+            })
+            .collect();
+        Ok(Response::new(types::HealthReport { checks }))
+    }
+
+    #[cfg(windows)]
+    async fn cleanup_drivers(&self, _: Request<()>) -> ServiceResult<types::DriverCleanup> {
+        log::debug!("cleanup_drivers");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::CleanupDrivers(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|cleanup| {
+                Response::new(types::DriverCleanup {
+                    adapters: cleanup.adapters,
+                    network_profiles: cleanup.network_profiles,
+                    reboot_required: cleanup.reboot_required,
+                })
+            })
+            .map_err(map_daemon_error)
+    }
+    #[cfg(not(windows))]
+    async fn cleanup_drivers(&self, _: Request<()>) -> ServiceResult<types::DriverCleanup> {
+        Err(Status::unimplemented(
+            "Driver cleanup is only supported on Windows",
+        ))
+    }
+
+    async fn connect_to_endpoint(
+        &self,
+        request: Request<types::CustomRelaySettings>,
+    ) -> ServiceResult<()> {
+        log::debug!("connect_to_endpoint");
+        let endpoint = mullvad_types::CustomTunnelEndpoint::try_from(request.into_inner())?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ConnectToEndpoint(tx, endpoint))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    async fn get_api_trace(&self, _: Request<()>) -> ServiceResult<types::ApiTrace> {
+        log::debug!("get_api_trace");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetApiTrace(tx))?;
+        let entries = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::ApiTrace {
+            entries: entries.into_iter().map(convert_api_trace_entry).collect(),
+        }))
+    }
+
+    async fn send_problem_report(
+        &self,
+        request: Request<types::ProblemReport>,
+    ) -> ServiceResult<()> {
+        log::debug!("send_problem_report");
+        let report = request.into_inner();
+        let report = crate::ProblemReport {
+            email: report.email,
+            message: report.message,
+            log: report.log,
+            metadata: report.metadata.into_iter().collect(),
+        };
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SendProblemReport(tx, report))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
+    #[cfg(target_os = "macos")]
+    async fn allow_captive_portal(
+        &self,
+        request: Request<types::Duration>,
+    ) -> ServiceResult<String> {
+        let duration = Duration::try_from(request.into_inner())
+            .map_err(|_| Status::invalid_argument("unexpected negative duration"))?;
+        log::debug!("allow_captive_portal({:?})", duration);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::AllowCaptivePortal(tx, duration))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|address| Response::new(address.to_string()))
+            .map_err(map_captive_portal_error)
+    }
+    #[cfg(not(target_os = "macos"))]
+    async fn allow_captive_portal(&self, _: Request<types::Duration>) -> ServiceResult<String> {
+        Err(Status::unimplemented(
+            "Captive portal detection is only supported on macOS",
+        ))
+    }
+
     async fn get_version_info(&self, _: Request<()>) -> ServiceResult<types::AppVersionInfo> {
         log::debug!("get_version_info");
 
@@ -181,6 +485,27 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_relay_fallback_tiers(
+        &self,
+        request: Request<types::RelayFallbackTiers>,
+    ) -> ServiceResult<()> {
+        log::debug!("set_relay_fallback_tiers");
+        let (tx, rx) = oneshot::channel();
+        let tiers = request
+            .into_inner()
+            .tiers
+            .into_iter()
+            .map(RelayConstraints::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let message = DaemonCommand::SetRelayFallbackTiers(tx, tiers);
+        self.send_command_to_daemon(message)?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn get_relay_locations(
         &self,
         _: Request<()>,
@@ -190,25 +515,39 @@ impl ManagementService for ManagementServiceImpl {
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::GetRelayLocations(tx))?;
         let locations = self.wait_for_result(rx).await?;
+        Ok(Response::new(stream_relay_locations(locations)))
+    }
 
-        let (stream_tx, stream_rx) =
-            tokio::sync::mpsc::channel(cmp::max(1, locations.countries.len()));
+    async fn get_localized_relay_locations(
+        &self,
+        request: Request<String>,
+    ) -> ServiceResult<Self::GetLocalizedRelayLocationsStream> {
+        let locale = request.into_inner();
+        log::debug!("get_localized_relay_locations({})", locale);
 
-        tokio::spawn(async move {
-            for country in locations.countries.into_iter() {
-                if let Err(error) = stream_tx
-                    .send(Ok(types::RelayListCountry::from(country)))
-                    .await
-                {
-                    log::error!(
-                        "Error while sending relays to client: {}",
-                        error.display_chain()
-                    );
-                }
-            }
-        });
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetRelayLocations(tx))?;
+        let mut locations = self.wait_for_result(rx).await?;
+        if !location_names::localize_relay_list(&mut locations, &locale) {
+            log::debug!("No relay location translations for locale \"{}\"", locale);
+        }
+        Ok(Response::new(stream_relay_locations(locations)))
+    }
 
-        Ok(Response::new(ReceiverStream::new(stream_rx)))
+    async fn get_relay_latencies(&self, _: Request<()>) -> ServiceResult<types::RelayLatencies> {
+        log::debug!("get_relay_latencies");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetRelayLatencies(tx))?;
+        let latencies = self.wait_for_result(rx).await?;
+        Ok(Response::new(types::RelayLatencies {
+            latencies: latencies
+                .into_iter()
+                .map(|(hostname, latency)| {
+                    let millis = u32::try_from(latency.as_millis()).unwrap_or(u32::MAX);
+                    (hostname, millis)
+                })
+                .collect(),
+        }))
     }
 
     async fn get_current_location(&self, _: Request<()>) -> ServiceResult<types::GeoIpLocation> {
@@ -250,6 +589,31 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_entry_proxy(
+        &self,
+        request: Request<types::EntryProxySettings>,
+    ) -> ServiceResult<()> {
+        let entry_proxy = request
+            .into_inner()
+            .proxy
+            .map(EntryProxy::try_from)
+            .transpose()?;
+
+        log::debug!(
+            "set_entry_proxy({})",
+            entry_proxy
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_else(|| "none".to_owned())
+        );
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetEntryProxy(tx, entry_proxy))?;
+        let settings_result = self.wait_for_result(rx).await?;
+        settings_result
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     // Settings
     //
 
@@ -262,6 +626,59 @@ impl ManagementService for ManagementServiceImpl {
             .map(|settings| Response::new(types::Settings::from(&settings)))
     }
 
+    async fn get_settings_load_status(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::SettingsLoadStatus> {
+        log::debug!("get_settings_load_status");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetSettingsLoadFailure(tx))?;
+        let status = match self.wait_for_result(rx).await? {
+            Some(failure) => types::SettingsLoadStatus {
+                failed: true,
+                error: failure.error,
+                backup_path: failure
+                    .backup_path
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            },
+            None => types::SettingsLoadStatus::default(),
+        };
+        Ok(Response::new(status))
+    }
+
+    async fn get_feature_flags(&self, _: Request<()>) -> ServiceResult<types::FeatureFlags> {
+        log::debug!("get_feature_flags");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetFeatureFlags(tx))?;
+        let flags = self
+            .wait_for_result(rx)
+            .await?
+            .into_iter()
+            .map(|status| types::feature_flags::Flag {
+                name: status.feature.name().to_owned(),
+                description: status.feature.description().to_owned(),
+                enabled: status.enabled,
+                overridden: status.overridden,
+            })
+            .collect();
+        Ok(Response::new(types::FeatureFlags { flags }))
+    }
+
+    async fn set_feature_flag(
+        &self,
+        request: Request<types::FeatureFlagOverride>,
+    ) -> ServiceResult<()> {
+        let flag = request.into_inner();
+        log::debug!("set_feature_flag({}, {:?})", flag.name, flag.enabled);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetFeatureFlag(tx, flag.name, flag.enabled))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_allow_lan(&self, request: Request<bool>) -> ServiceResult<()> {
         let allow_lan = request.into_inner();
         log::debug!("set_allow_lan({})", allow_lan);
@@ -273,6 +690,62 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_lan_allow_list(&self, request: Request<types::LanAllowList>) -> ServiceResult<()> {
+        let networks = request
+            .into_inner()
+            .networks
+            .iter()
+            .map(|network| {
+                let network: IpNetwork = network
+                    .parse()
+                    .map_err(|_| Status::invalid_argument("invalid IP network"))?;
+                if !talpid_core::firewall::is_private_network(&network) {
+                    return Err(Status::invalid_argument(format!(
+                        "{} is not a private network",
+                        network
+                    )));
+                }
+                Ok(network)
+            })
+            .collect::<Result<Vec<_>, Status>>()?;
+        if networks.is_empty() {
+            return Err(Status::invalid_argument(
+                "the LAN allow list must not be empty",
+            ));
+        }
+        log::debug!("set_lan_allow_list({:?})", networks);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetLanAllowList(tx, Some(networks)))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn clear_lan_allow_list(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("clear_lan_allow_list");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetLanAllowList(tx, None))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_blocked_state_policy(
+        &self,
+        request: Request<types::BlockedStatePolicy>,
+    ) -> ServiceResult<()> {
+        let policy = BlockedStatePolicy::from(request.into_inner());
+        log::debug!("set_blocked_state_policy({:?})", policy);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetBlockedStatePolicy(tx, policy))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_show_beta_releases(&self, request: Request<bool>) -> ServiceResult<()> {
         let enabled = request.into_inner();
         log::debug!("set_show_beta_releases({})", enabled);
@@ -284,6 +757,17 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_log_privacy(&self, request: Request<types::LogPrivacy>) -> ServiceResult<()> {
+        let log_privacy = LogPrivacy::try_from(request.into_inner())?;
+        log::debug!("set_log_privacy({:?})", log_privacy);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetLogPrivacy(tx, log_privacy))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_block_when_disconnected(&self, request: Request<bool>) -> ServiceResult<()> {
         let block_when_disconnected = request.into_inner();
         log::debug!("set_block_when_disconnected({})", block_when_disconnected);
@@ -298,6 +782,88 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    async fn set_block_when_account_expired(&self, request: Request<bool>) -> ServiceResult<()> {
+        let block_when_account_expired = request.into_inner();
+        log::debug!(
+            "set_block_when_account_expired({})",
+            block_when_account_expired
+        );
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetBlockWhenAccountExpired(
+            tx,
+            block_when_account_expired,
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_strict_kill_switch(&self, request: Request<bool>) -> ServiceResult<()> {
+        let strict_kill_switch = request.into_inner();
+        log::debug!("set_strict_kill_switch({})", strict_kill_switch);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetStrictKillSwitch(tx, strict_kill_switch))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn apply_settings_patch(&self, request: Request<String>) -> ServiceResult<()> {
+        let patch = request.into_inner();
+        log::debug!("apply_settings_patch");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ApplySettingsPatch(tx, patch))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn export_settings(&self, _: Request<()>) -> ServiceResult<String> {
+        log::debug!("export_settings");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ExportSettings(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn import_settings(&self, request: Request<String>) -> ServiceResult<()> {
+        let settings = request.into_inner();
+        log::debug!("import_settings");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ImportSettings(tx, settings))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_prefer_low_latency(&self, request: Request<bool>) -> ServiceResult<()> {
+        let prefer_low_latency = request.into_inner();
+        log::debug!("set_prefer_low_latency({})", prefer_low_latency);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetPreferLowLatency(tx, prefer_low_latency))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
+    async fn set_use_system_proxy(&self, request: Request<bool>) -> ServiceResult<()> {
+        let use_system_proxy = request.into_inner();
+        log::debug!("set_use_system_proxy({})", use_system_proxy);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetUseSystemProxy(tx, use_system_proxy))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+
     async fn set_auto_connect(&self, request: Request<bool>) -> ServiceResult<()> {
         let auto_connect = request.into_inner();
         log::debug!("set_auto_connect({})", auto_connect);
@@ -309,6 +875,54 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn set_reconnect_on_network_change(&self, request: Request<bool>) -> ServiceResult<()> {
+        let reconnect = request.into_inner();
+        log::debug!("set_reconnect_on_network_change({})", reconnect);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetReconnectOnNetworkChange(tx, reconnect))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    async fn set_reconnect_on_network_change(&self, _: Request<bool>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn set_trusted_networks(
+        &self,
+        request: Request<types::TrustedNetworks>,
+    ) -> ServiceResult<()> {
+        let request = request.into_inner();
+        let subnets = request
+            .subnets
+            .iter()
+            .map(|subnet| {
+                subnet
+                    .parse::<IpNetwork>()
+                    .map_err(|_| Status::invalid_argument("invalid IP network"))
+            })
+            .collect::<Result<Vec<_>, Status>>()?;
+        let trusted_networks = mullvad_types::settings::TrustedNetworks {
+            ssids: request.ssids,
+            subnets,
+        };
+        log::debug!("set_trusted_networks({:?})", trusted_networks);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetTrustedNetworks(tx, trusted_networks))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    async fn set_trusted_networks(&self, _: Request<types::TrustedNetworks>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
     async fn set_openvpn_mssfix(&self, request: Request<u32>) -> ServiceResult<()> {
         let mssfix = request.into_inner();
         let mssfix = if mssfix != 0 {
@@ -337,6 +951,25 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_settings_error)
     }
 
+    #[cfg(any(target_os = "linux", windows))]
+    async fn set_wireguard_interface_name(&self, request: Request<String>) -> ServiceResult<()> {
+        let name = request.into_inner();
+        log::debug!("set_wireguard_interface_name({:?})", name);
+        let name = if !name.is_empty() { Some(name) } else { None };
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetWireguardInterfaceName(tx, name))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(any(target_os = "linux", windows)))]
+    async fn set_wireguard_interface_name(&self, _: Request<String>) -> ServiceResult<()> {
+        Err(Status::unimplemented(
+            "Setting the interface name is only supported on Linux and Windows",
+        ))
+    }
+
     async fn set_enable_ipv6(&self, request: Request<bool>) -> ServiceResult<()> {
         let enable_ipv6 = request.into_inner();
         log::debug!("set_enable_ipv6({})", enable_ipv6);
@@ -479,6 +1112,33 @@ impl ManagementService for ManagementServiceImpl {
             })
     }
 
+    async fn init_play_purchase(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::PlayPurchasePaymentToken> {
+        log::debug!("init_play_purchase");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::InitPlayPurchase(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(|token| Response::new(types::PlayPurchasePaymentToken { token }))
+            .map_err(map_daemon_error)
+    }
+
+    async fn verify_play_purchase(
+        &self,
+        request: Request<types::PlayPurchase>,
+    ) -> ServiceResult<()> {
+        log::debug!("verify_play_purchase");
+        let play_purchase = mullvad_types::account::PlayPurchase::from(request.into_inner());
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::VerifyPlayPurchase(tx, play_purchase))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
     // WireGuard key management
     //
 
@@ -548,6 +1208,38 @@ impl ManagementService for ManagementServiceImpl {
             .map_err(map_daemon_error)
     }
 
+    async fn list_wireguard_keys(&self, _: Request<()>) -> ServiceResult<types::WireguardKeyList> {
+        log::debug!("list_wireguard_keys");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetWireguardKey(tx))?;
+        let current_key = self
+            .wait_for_result(rx)
+            .await?
+            .map_err(map_daemon_error)?
+            .map(|public_key| public_key.key);
+
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ListAccountKeys(tx))?;
+        let keys = self.wait_for_result(rx).await?.map_err(map_daemon_error)?;
+        Ok(Response::new(types::WireguardKeyList {
+            keys: keys
+                .into_iter()
+                .map(|key| types::wireguard_key_list::Key::new(key, current_key.as_ref()))
+                .collect(),
+        }))
+    }
+
+    async fn revoke_wireguard_key(&self, request: Request<types::PublicKey>) -> ServiceResult<()> {
+        log::debug!("revoke_wireguard_key");
+        let key = talpid_types::net::wireguard::PublicKey::try_from(request.into_inner())?;
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RevokeAccountKey(tx, key))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_daemon_error)
+    }
+
     // Split tunneling
     //
 
@@ -630,10 +1322,69 @@ impl ManagementService for ManagementServiceImpl {
         }
     }
 
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn add_split_tunnel_network(&self, request: Request<String>) -> ServiceResult<()> {
+        let network: IpNetwork = request
+            .into_inner()
+            .parse()
+            .map_err(|_| Status::invalid_argument("invalid IP network"))?;
+        log::debug!("add_split_tunnel_network({})", network);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::AddSplitTunnelNetwork(tx, network))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(map_settings_error)
+            .map(Response::new)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    async fn add_split_tunnel_network(&self, _: Request<String>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn remove_split_tunnel_network(&self, request: Request<String>) -> ServiceResult<()> {
+        let network: IpNetwork = request
+            .into_inner()
+            .parse()
+            .map_err(|_| Status::invalid_argument("invalid IP network"))?;
+        log::debug!("remove_split_tunnel_network({})", network);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::RemoveSplitTunnelNetwork(tx, network))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(map_settings_error)
+            .map(Response::new)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    async fn remove_split_tunnel_network(&self, _: Request<String>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn clear_split_tunnel_networks(&self, _: Request<()>) -> ServiceResult<()> {
+        log::debug!("clear_split_tunnel_networks");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::ClearSplitTunnelNetworks(tx))?;
+        self.wait_for_result(rx)
+            .await?
+            .map_err(map_settings_error)
+            .map(Response::new)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    async fn clear_split_tunnel_networks(&self, _: Request<()>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
     #[cfg(windows)]
     async fn add_split_tunnel_app(&self, request: Request<String>) -> ServiceResult<()> {
         log::debug!("add_split_tunnel_app");
+        let requester = requester(&request);
         let path = PathBuf::from(request.into_inner());
+        log::debug!(
+            "Adding split tunnel app requested by {}: {}",
+            requester,
+            path.display()
+        );
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::AddSplitTunnelApp(tx, path))?;
         self.wait_for_result(rx)
@@ -649,7 +1400,13 @@ impl ManagementService for ManagementServiceImpl {
     #[cfg(windows)]
     async fn remove_split_tunnel_app(&self, request: Request<String>) -> ServiceResult<()> {
         log::debug!("remove_split_tunnel_app");
+        let requester = requester(&request);
         let path = PathBuf::from(request.into_inner());
+        log::debug!(
+            "Removing split tunnel app requested by {}: {}",
+            requester,
+            path.display()
+        );
         let (tx, rx) = oneshot::channel();
         self.send_command_to_daemon(DaemonCommand::RemoveSplitTunnelApp(tx, path))?;
         self.wait_for_result(rx)
@@ -708,6 +1465,37 @@ impl ManagementService for ManagementServiceImpl {
     async fn set_use_wireguard_nt(&self, _: Request<bool>) -> ServiceResult<()> {
         Ok(Response::new(()))
     }
+
+    #[cfg(windows)]
+    async fn set_allow_connection_sharing(&self, request: Request<bool>) -> ServiceResult<()> {
+        let allow_connection_sharing = request.into_inner();
+        log::debug!("set_allow_connection_sharing({})", allow_connection_sharing);
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::SetAllowConnectionSharing(
+            tx,
+            allow_connection_sharing,
+        ))?;
+        self.wait_for_result(rx)
+            .await?
+            .map(Response::new)
+            .map_err(map_settings_error)
+    }
+    #[cfg(not(windows))]
+    async fn set_allow_connection_sharing(&self, _: Request<bool>) -> ServiceResult<()> {
+        Ok(Response::new(()))
+    }
+
+    #[cfg(windows)]
+    async fn is_connection_sharing_active(&self, _: Request<()>) -> ServiceResult<bool> {
+        log::debug!("is_connection_sharing_active");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::IsConnectionSharingActive(tx))?;
+        self.wait_for_result(rx).await.map(Response::new)
+    }
+    #[cfg(not(windows))]
+    async fn is_connection_sharing_active(&self, _: Request<()>) -> ServiceResult<bool> {
+        Ok(Response::new(false))
+    }
 }
 
 impl ManagementServiceImpl {
@@ -729,7 +1517,7 @@ impl ManagementInterfaceServer {
     pub async fn start(
         tunnel_tx: DaemonCommandSender,
     ) -> Result<(String, ManagementInterfaceEventBroadcaster), Error> {
-        let subscriptions = Arc::<RwLock<Vec<EventsListenerSender>>>::default();
+        let subscriptions = Arc::<RwLock<Vec<EventSubscriber>>>::default();
 
         let socket_path = mullvad_paths::get_rpc_socket_path()
             .to_string_lossy()
@@ -766,54 +1554,43 @@ impl ManagementInterfaceServer {
 /// A handle that allows broadcasting messages to all subscribers of the management interface.
 #[derive(Clone)]
 pub struct ManagementInterfaceEventBroadcaster {
-    subscriptions: Arc<RwLock<Vec<EventsListenerSender>>>,
+    subscriptions: Arc<RwLock<Vec<EventSubscriber>>>,
     _close_handle: mpsc::Sender<()>,
 }
 
 impl EventListener for ManagementInterfaceEventBroadcaster {
     /// Sends a new state update to all `new_state` subscribers of the management interface.
     fn notify_new_state(&self, new_state: TunnelState) {
-        self.notify(types::DaemonEvent {
-            event: Some(daemon_event::Event::TunnelState(types::TunnelState::from(
-                new_state,
-            ))),
-        })
+        self.notify(tunnel_state_event(new_state))
     }
 
     /// Sends settings to all `settings` subscribers of the management interface.
     fn notify_settings(&self, settings: Settings) {
         log::debug!("Broadcasting new settings");
-        self.notify(types::DaemonEvent {
-            event: Some(daemon_event::Event::Settings(types::Settings::from(
-                &settings,
-            ))),
-        })
+        self.notify(settings_event(&settings))
+    }
+
+    /// Sends the tunnel state and settings to the subscribers running in the given session.
+    #[cfg(windows)]
+    fn resync_session(&self, session_id: u32, tunnel_state: TunnelState, settings: Settings) {
+        log::debug!("Resending state to clients in session {}", session_id);
+        let events = [tunnel_state_event(tunnel_state), settings_event(&settings)];
+        let mut subscriptions = self.subscriptions.write();
+        subscriptions.retain(|subscriber| {
+            subscriber.session_id != Some(session_id)
+                || events.iter().all(|event| subscriber.send(event))
+        });
     }
 
     /// Sends relays to all subscribers of the management interface.
     fn notify_relay_list(&self, relay_list: RelayList) {
         log::debug!("Broadcasting new relay list");
-        let mut new_list = types::RelayList {
-            countries: Vec::new(),
-        };
-        new_list.countries.reserve(relay_list.countries.len());
-        for country in relay_list.countries.into_iter() {
-            new_list
-                .countries
-                .push(types::RelayListCountry::from(country));
-        }
-        self.notify(types::DaemonEvent {
-            event: Some(daemon_event::Event::RelayList(new_list)),
-        })
+        self.notify(relay_list_event(relay_list))
     }
 
     fn notify_app_version(&self, app_version_info: version::AppVersionInfo) {
         log::debug!("Broadcasting new app version info");
-        self.notify(types::DaemonEvent {
-            event: Some(daemon_event::Event::VersionInfo(
-                types::AppVersionInfo::from(app_version_info),
-            )),
-        })
+        self.notify(version_info_event(app_version_info))
     }
 
     fn notify_key_event(&self, key_event: mullvad_types::wireguard::KeygenEvent) {
@@ -824,16 +1601,141 @@ impl EventListener for ManagementInterfaceEventBroadcaster {
             ))),
         })
     }
+
+    fn notify_account_expiry(&self, event: mullvad_types::account::AccountExpiryEvent) {
+        log::debug!("Broadcasting account expiry");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::AccountExpiry(
+                types::AccountExpiryEvent::from(event),
+            )),
+        })
+    }
+
+    fn notify_firewall_policy_restored(&self) {
+        log::debug!("Broadcasting restored firewall policy");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::FirewallPolicyRestored(
+                types::FirewallPolicyRestored {},
+            )),
+        })
+    }
+
+    #[cfg(target_os = "macos")]
+    fn notify_dns_interference(&self) {
+        log::debug!("Broadcasting DNS interference");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::DnsInterference(
+                types::DnsInterference {},
+            )),
+        })
+    }
 }
 
 impl ManagementInterfaceEventBroadcaster {
     fn notify(&self, value: types::DaemonEvent) {
         let mut subscriptions = self.subscriptions.write();
         // TODO: using write-lock everywhere. use a mutex instead?
-        subscriptions.retain(|tx| tx.send(Ok(value.clone())).is_ok());
+        subscriptions.retain(|subscriber| subscriber.send(&value));
+    }
+}
+
+fn tunnel_state_event(tunnel_state: TunnelState) -> types::DaemonEvent {
+    types::DaemonEvent {
+        event: Some(daemon_event::Event::TunnelState(types::TunnelState::from(
+            tunnel_state,
+        ))),
+    }
+}
+
+fn settings_event(settings: &Settings) -> types::DaemonEvent {
+    types::DaemonEvent {
+        event: Some(daemon_event::Event::Settings(types::Settings::from(
+            settings,
+        ))),
+    }
+}
+
+fn relay_list_event(relay_list: RelayList) -> types::DaemonEvent {
+    let mut new_list = types::RelayList {
+        countries: Vec::new(),
+    };
+    new_list.countries.reserve(relay_list.countries.len());
+    for country in relay_list.countries.into_iter() {
+        new_list
+            .countries
+            .push(types::RelayListCountry::from(country));
+    }
+    types::DaemonEvent {
+        event: Some(daemon_event::Event::RelayList(new_list)),
+    }
+}
+
+fn version_info_event(app_version_info: version::AppVersionInfo) -> types::DaemonEvent {
+    types::DaemonEvent {
+        event: Some(daemon_event::Event::VersionInfo(
+            types::AppVersionInfo::from(app_version_info),
+        )),
+    }
+}
+
+/// Describes the user that sent `request`, for logging requests that change the daemon state.
+#[cfg(windows)]
+fn requester<T>(request: &Request<T>) -> String {
+    match ClientInfo::of(request).session_id {
+        Some(session_id) => crate::session::describe_session(session_id),
+        None => "unknown user".to_owned(),
     }
 }
 
+/// Converts a snapshot of the daemon state into the events that a new subscriber receives first.
+fn snapshot_events(snapshot: EventSnapshot) -> Vec<types::DaemonEvent> {
+    let mut events = vec![
+        tunnel_state_event(snapshot.tunnel_state),
+        settings_event(&snapshot.settings),
+        relay_list_event(snapshot.relay_list),
+    ];
+    if let Some(app_version_info) = snapshot.app_version_info {
+        events.push(version_info_event(app_version_info));
+    }
+    events
+}
+
+fn event_category(event: &types::DaemonEvent) -> Option<EventCategory> {
+    Some(match event.event.as_ref()? {
+        daemon_event::Event::TunnelState(_) => EventCategory::TunnelState,
+        daemon_event::Event::Settings(_) => EventCategory::Settings,
+        daemon_event::Event::RelayList(_) => EventCategory::RelayList,
+        daemon_event::Event::VersionInfo(_) => EventCategory::VersionInfo,
+        daemon_event::Event::KeyEvent(_) => EventCategory::KeyEvent,
+        daemon_event::Event::AccountExpiry(_) => EventCategory::AccountExpiry,
+        daemon_event::Event::FirewallPolicyRestored(_) => EventCategory::FirewallPolicyRestored,
+        daemon_event::Event::DnsInterference(_) => EventCategory::DnsInterference,
+    })
+}
+
+/// Streams the countries in `locations` to a client, one message per country.
+fn stream_relay_locations(
+    locations: RelayList,
+) -> ReceiverStream<Result<types::RelayListCountry, Status>> {
+    let (stream_tx, stream_rx) = tokio::sync::mpsc::channel(cmp::max(1, locations.countries.len()));
+
+    tokio::spawn(async move {
+        for country in locations.countries.into_iter() {
+            if let Err(error) = stream_tx
+                .send(Ok(types::RelayListCountry::from(country)))
+                .await
+            {
+                log::error!(
+                    "Error while sending relays to client: {}",
+                    error.display_chain()
+                );
+            }
+        }
+    });
+
+    ReceiverStream::new(stream_rx)
+}
+
 /// Converts [`mullvad_daemon::Error`] into a tonic status.
 fn map_daemon_error(error: crate::Error) -> Status {
     use crate::Error as DaemonError;
@@ -847,6 +1749,12 @@ fn map_daemon_error(error: crate::Error) -> Status {
         DaemonError::NoAccountToken | DaemonError::NoAccountTokenHistory => {
             Status::unauthenticated(error.to_string())
         }
+        DaemonError::DebugCommandsDisabled => Status::permission_denied(error.to_string()),
+        #[cfg(windows)]
+        DaemonError::CleanupDriversWhileConnected => Status::failed_precondition(error.to_string()),
+        DaemonError::RevokeCurrentKey | DaemonError::InvalidVoucherFormat => {
+            Status::invalid_argument(error.to_string())
+        }
         error => Status::unknown(error.to_string()),
     }
 }
@@ -868,6 +1776,43 @@ fn map_split_tunnel_error(error: talpid_core::split_tunnel::Error) -> Status {
     }
 }
 
+fn convert_api_trace_entry(entry: mullvad_rpc::ApiTraceEntry) -> types::api_trace::Entry {
+    types::api_trace::Entry {
+        timestamp: Some(types::Timestamp {
+            seconds: entry.timestamp.timestamp(),
+            nanos: entry.timestamp.timestamp_subsec_nanos() as i32,
+        }),
+        method: entry.method,
+        path: entry.path,
+        address: entry
+            .address
+            .map(|address| address.to_string())
+            .unwrap_or_default(),
+        uses_api_sni: entry.uses_api_sni,
+        result: entry.result,
+        success: entry.success,
+        duration: Some(types::Duration::from(entry.duration)),
+        attempt: entry.attempt,
+    }
+}
+
+#[cfg(target_os = "macos")]
+/// Converts a [`crate::captive_portal::Error`] into a tonic status.
+fn map_captive_portal_error(error: crate::captive_portal::Error) -> Status {
+    use crate::captive_portal::Error;
+
+    match error {
+        Error::NoPortalDetected => Status::not_found(error.to_string()),
+        Error::Probe(..)
+        | Error::ProbeTimeout
+        | Error::ResolvePortal(..)
+        | Error::NoSystemDnsServers(..) => Status::unavailable(error.display_chain()),
+        Error::TunnelStateMachineUnavailable | Error::SetFirewallException => {
+            Status::internal(error.to_string())
+        }
+    }
+}
+
 /// Converts a REST API voucher error into a tonic status.
 fn map_rest_voucher_error(error: RestError) -> Status {
     match error {
@@ -878,6 +1823,10 @@ fn map_rest_voucher_error(error: RestError) -> Status {
                 Status::new(Code::ResourceExhausted, USED_VOUCHER_MESSAGE)
             }
 
+            &mullvad_rpc::VOUCHER_EXPIRED => {
+                Status::new(Code::FailedPrecondition, EXPIRED_VOUCHER_MESSAGE)
+            }
+
             error => Status::unknown(format!("Voucher error: {}", error)),
         },
         error => map_rest_error(error),
@@ -894,6 +1843,10 @@ fn map_rest_error(error: RestError) -> Status {
         }
         RestError::TimeoutError(_elapsed) => Status::deadline_exceeded("API request timed out"),
         RestError::HyperError(_) => Status::unavailable("Cannot reach the API"),
+        RestError::ClockSkew(skew) => Status::failed_precondition(format!(
+            "The API certificate is not valid at the current time. The system clock is at least {}",
+            skew
+        )),
         error => Status::unknown(format!("REST error: {}", error)),
     }
 }
@@ -910,6 +1863,18 @@ fn map_settings_error(error: settings::Error) -> Status {
         settings::Error::SerializeError(..) | settings::Error::ParseError(..) => {
             Status::new(Code::Internal, error.to_string())
         }
+        settings::Error::InvalidPatch
+        | settings::Error::ProtectedSetting(..)
+        | settings::Error::MalformedPatchedSettings(..)
+        | settings::Error::EmptyLanAllowList
+        | settings::Error::NonPrivateLanNetwork(..)
+        | settings::Error::UnknownFeatureFlag(..)
+        | settings::Error::InvalidInterfaceName(..)
+        | settings::Error::InvalidImport
+        | settings::Error::IncompatibleImport
+        | settings::Error::MalformedImportedSettings(..) => {
+            Status::invalid_argument(error.display_chain())
+        }
     }
 }
 
@@ -30,8 +30,14 @@
 //! 1. Write a comment in the new module about how the format changed, what it needs to migrate.
 //! 1. Implement the migration and add adequate tests.
 //! 1. Add to the changelog: "Settings format updated to `vY`"
+//!
+//! Before migrated settings are written, the original file is copied to
+//! `settings-backup-vX.json`, where `X` is the version it had. This makes it possible to recover
+//! the settings if a migration turns out to be broken. The migrated settings are written to a
+//! temporary file that then replaces the settings file, so that a crash while writing never
+//! leaves a truncated file behind.
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tokio::{
     fs,
     io::{self, AsyncWriteExt},
@@ -65,6 +71,9 @@ pub enum Error {
     #[error(display = "Unable to write new settings")]
     WriteError(#[error(source)] io::Error),
 
+    #[error(display = "Unable to back up the settings before migrating them")]
+    BackupError(#[error(source)] io::Error),
+
     #[error(display = "Failed to read the account history")]
     ReadHistoryError(#[error(source)] io::Error),
 
@@ -101,6 +110,7 @@ pub async fn migrate_all(cache_dir: &Path, settings_dir: &Path) -> Result<()> {
     if !settings.is_object() {
         return Err(Error::NoMatchingVersion);
     }
+    let original_settings = settings.clone();
 
     v1::migrate(&mut settings)?;
     v2::migrate(&mut settings)?;
@@ -111,24 +121,68 @@ pub async fn migrate_all(cache_dir: &Path, settings_dir: &Path) -> Result<()> {
     account_history::migrate_location(cache_dir, settings_dir).await;
     account_history::migrate_formats(settings_dir, &mut settings).await?;
 
+    if settings == original_settings {
+        return Ok(());
+    }
+
+    let backup_path = backup_path(settings_dir, &original_settings);
+    log::info!(
+        "Backing up settings to {} before migrating them",
+        backup_path.display()
+    );
+    write_private_file(&backup_path, &settings_bytes)
+        .await
+        .map_err(Error::BackupError)?;
+
     let buffer = serde_json::to_string_pretty(&settings).map_err(Error::SerializeError)?;
+    write_private_file(&path, buffer.as_bytes())
+        .await
+        .map_err(Error::WriteError)
+}
+
+/// Returns the path that settings of the same version as `settings` are backed up to before
+/// being migrated. Settings without a version predate versioning and are treated as `V1`.
+fn backup_path(settings_dir: &Path, settings: &serde_json::Value) -> PathBuf {
+    let version = settings
+        .get("settings_version")
+        .and_then(|version| version.as_u64())
+        .unwrap_or(1);
+    settings_dir.join(format!("settings-backup-v{}.json", version))
+}
 
+/// Atomically replaces the file at `path` with `contents`, by writing them to a temporary file
+/// in the same directory and renaming it. The file may only be accessed by its owner, since the
+/// settings contain the account number.
+async fn write_private_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp_path = temp_path(path);
     let mut options = fs::OpenOptions::new();
     #[cfg(unix)]
     {
         options.mode(0o600);
     }
-    let mut file = options
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(&path)
-        .await
-        .map_err(Error::WriteError)?;
-    file.write_all(&buffer.into_bytes())
-        .await
-        .map_err(Error::WriteError)?;
-    Ok(())
+    let result = async {
+        let mut file = options
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&temp_path)
+            .await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        fs::rename(&temp_path, path).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path).await;
+    }
+    result
+}
+
+/// Returns the path of the temporary file that is written before replacing `path`.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    PathBuf::from(temp_path)
 }
 
 #[cfg(windows)]
@@ -324,3 +378,34 @@ mod windows {
         unsafe { IsWellKnownSid(sid as *const SID as *mut _, well_known_sid_type) == TRUE }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{backup_path, temp_path};
+    use std::path::Path;
+
+    #[test]
+    fn test_backup_path() {
+        let settings_dir = Path::new("/etc/mullvad-vpn");
+        assert_eq!(
+            backup_path(
+                settings_dir,
+                &serde_json::json!({ "settings_version": 4, "allow_lan": true })
+            ),
+            settings_dir.join("settings-backup-v4.json")
+        );
+        assert_eq!(
+            backup_path(settings_dir, &serde_json::json!({ "allow_lan": true })),
+            settings_dir.join("settings-backup-v1.json")
+        );
+    }
+
+    #[test]
+    fn test_temp_path() {
+        let settings_dir = Path::new("/etc/mullvad-vpn");
+        assert_eq!(
+            temp_path(&settings_dir.join("settings.json")),
+            settings_dir.join("settings.json.tmp")
+        );
+    }
+}
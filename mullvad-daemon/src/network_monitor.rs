@@ -0,0 +1,296 @@
+//! Looks up which network the device is connected to, so that the daemon can reconnect the
+//! tunnel when it changes and disconnect it on trusted networks.
+//!
+//! On Linux, the network is looked up again when NetworkManager reports a change, and Wi-Fi
+//! SSIDs are read from it. Elsewhere, or without NetworkManager, the network is polled and can
+//! only be recognized by its subnets.
+
+use crate::{DaemonEventSender, InternalDaemonEvent};
+use futures::channel::mpsc;
+#[cfg(target_os = "linux")]
+use futures::StreamExt;
+use ipnetwork::IpNetwork;
+use nix::{ifaddrs::getifaddrs, net::if_::InterfaceFlags, sys::socket::SockAddr};
+use std::{
+    net::{IpAddr, Ipv6Addr},
+    sync::Arc,
+    time::Duration,
+};
+use talpid_core::mpsc::Sender;
+#[cfg(target_os = "linux")]
+use talpid_dbus::network_manager::NetworkManager;
+#[cfg(target_os = "linux")]
+use talpid_types::ErrorExt;
+
+/// How often the current network is looked up when changes cannot be watched.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How long to wait for a burst of change notifications to end before looking up the network.
+#[cfg(target_os = "linux")]
+const SETTLE_DELAY: Duration = Duration::from_secs(1);
+
+/// Prefixes of the names of virtual interfaces that are created for containers and virtual
+/// machines, and do not say anything about the network that the device is connected to.
+const VIRTUAL_INTERFACE_PREFIXES: &[&str] = &["docker", "br-", "virbr", "veth", "vnet"];
+
+/// The network that the device is connected to, identified by the subnets of its physical
+/// interfaces and the SSIDs of any Wi-Fi networks.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CurrentNetwork {
+    pub ssids: Vec<String>,
+    pub subnets: Vec<IpNetwork>,
+}
+
+/// What the daemon knows about the current network when applying the connect policies.
+#[derive(Debug, Default)]
+pub struct NetworkPolicyState {
+    /// The last network that was reported, if any.
+    pub current_network: Option<CurrentNetwork>,
+    /// Whether the current network was found to be trusted the last time it was checked, or
+    /// `None` if it has not been checked yet. Whether the tunnel was disconnected on a trusted
+    /// network is persisted with the target state, so that it survives daemon restarts.
+    pub on_trusted_network: Option<bool>,
+}
+
+/// Spawns a task that sends `InternalDaemonEvent::NetworkChanged` to the daemon with the initial
+/// network, and whenever it changes. The task exits when the daemon stops listening.
+pub(crate) fn spawn(daemon_tx: DaemonEventSender) {
+    tokio::spawn(async move {
+        let reader = Arc::new(NetworkReader::new());
+        let mut changes = watch_changes();
+        let mut last_network = None;
+
+        while !daemon_tx.is_closed() {
+            let task_reader = reader.clone();
+            let network =
+                match tokio::task::spawn_blocking(move || task_reader.current_network()).await {
+                    Ok(network) => network,
+                    Err(_) => break,
+                };
+            if last_network.as_ref() != Some(&network) {
+                log::debug!("Current network: {:?}", network);
+                if daemon_tx
+                    .send(InternalDaemonEvent::NetworkChanged(network.clone()))
+                    .is_err()
+                {
+                    break;
+                }
+                last_network = Some(network);
+            }
+
+            let changed = match &mut changes {
+                Some(changes) => wait_for_change(changes).await,
+                None => false,
+            };
+            if !changed {
+                // Also covers the watcher stopping, in which case the network is polled instead
+                changes = None;
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    });
+}
+
+/// Waits for a change notification, and for any that immediately follow it. Returns false if no
+/// more notifications will be sent.
+#[cfg(target_os = "linux")]
+async fn wait_for_change(changes: &mut mpsc::UnboundedReceiver<()>) -> bool {
+    if changes.next().await.is_none() {
+        return false;
+    }
+    tokio::time::sleep(SETTLE_DELAY).await;
+    while let Ok(Some(())) = changes.try_next() {}
+    true
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn wait_for_change(_changes: &mut mpsc::UnboundedReceiver<()>) -> bool {
+    false
+}
+
+/// Returns a channel that receives a message whenever NetworkManager reports a network change,
+/// or `None` if NetworkManager is not available.
+#[cfg(target_os = "linux")]
+fn watch_changes() -> Option<mpsc::UnboundedReceiver<()>> {
+    let mut network_manager = match NetworkManager::new_connection() {
+        Ok(network_manager) => network_manager,
+        Err(error) => {
+            log::debug!(
+                "{}",
+                error.display_chain_with_msg("Polling for network changes")
+            );
+            return None;
+        }
+    };
+    if let Err(error) = network_manager.connectivity() {
+        log::debug!(
+            "{}",
+            error.display_chain_with_msg("Polling for network changes")
+        );
+        return None;
+    }
+
+    let (tx, rx) = mpsc::unbounded();
+    std::thread::spawn(move || {
+        let should_continue_tx = tx.clone();
+        let result = network_manager.watch_network_changes(
+            move || {
+                let _ = tx.unbounded_send(());
+            },
+            move || !should_continue_tx.is_closed(),
+        );
+        if let Err(error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to watch NetworkManager for network changes")
+            );
+        }
+    });
+    Some(rx)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch_changes() -> Option<mpsc::UnboundedReceiver<()>> {
+    None
+}
+
+/// Looks up the current network. On Linux, NetworkManager is queried over a single D-Bus
+/// connection that is kept for as long as the monitor runs.
+struct NetworkReader {
+    #[cfg(target_os = "linux")]
+    network_manager: Option<NetworkManager>,
+}
+
+impl NetworkReader {
+    fn new() -> Self {
+        NetworkReader {
+            #[cfg(target_os = "linux")]
+            network_manager: NetworkManager::new_connection()
+                .map_err(|error| {
+                    log::debug!(
+                        "{}",
+                        error.display_chain_with_msg("Not reading Wi-Fi SSIDs from NetworkManager")
+                    );
+                })
+                .ok(),
+        }
+    }
+
+    fn current_network(&self) -> CurrentNetwork {
+        CurrentNetwork {
+            ssids: self.wifi_ssids(),
+            subnets: local_subnets(),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn wifi_ssids(&self) -> Vec<String> {
+        // Without NetworkManager, Wi-Fi networks can still be recognized by their subnets
+        self.network_manager
+            .as_ref()
+            .and_then(|network_manager| network_manager.wifi_ssids().ok())
+            .unwrap_or_default()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn wifi_ssids(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+/// Returns the subnets of all interfaces that are up, except loopback and point-to-point
+/// interfaces, and virtual interfaces for containers and virtual machines. Point-to-point
+/// interfaces include the tunnel interface.
+fn local_subnets() -> Vec<IpNetwork> {
+    let addresses = match getifaddrs() {
+        Ok(addresses) => addresses,
+        Err(error) => {
+            log::debug!("Failed to obtain interface addresses: {}", error);
+            return vec![];
+        }
+    };
+
+    let mut subnets: Vec<IpNetwork> = addresses
+        .filter(|address| {
+            address
+                .flags
+                .contains(InterfaceFlags::IFF_UP | InterfaceFlags::IFF_RUNNING)
+                && !address
+                    .flags
+                    .intersects(InterfaceFlags::IFF_LOOPBACK | InterfaceFlags::IFF_POINTOPOINT)
+                && !is_virtual_interface(&address.interface_name)
+        })
+        .filter_map(|address| {
+            let ip = sockaddr_ip(address.address?)?;
+            let prefix = prefix_len(sockaddr_ip(address.netmask?)?);
+            let network = IpNetwork::new(ip, prefix).ok()?;
+            let subnet = IpNetwork::new(network.network(), prefix).ok()?;
+            if identifies_network(&subnet) {
+                Some(subnet)
+            } else {
+                None
+            }
+        })
+        .collect();
+    subnets.sort_by_key(|subnet| (subnet.network(), subnet.prefix()));
+    subnets.dedup();
+    subnets
+}
+
+fn is_virtual_interface(name: &str) -> bool {
+    VIRTUAL_INTERFACE_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// Returns whether a subnet can tell networks apart. Link-local subnets are the same on every
+/// network, and single-address prefixes are used for temporary IPv6 addresses, which change
+/// regularly. Temporary addresses with a shorter prefix share the subnet of the stable address.
+fn identifies_network(subnet: &IpNetwork) -> bool {
+    match subnet {
+        IpNetwork::V4(subnet) => !subnet.ip().is_link_local(),
+        IpNetwork::V6(subnet) => !is_ipv6_link_local(subnet.ip()) && subnet.prefix() < 128,
+    }
+}
+
+fn is_ipv6_link_local(address: Ipv6Addr) -> bool {
+    (address.segments()[0] & 0xffc0) == 0xfe80
+}
+
+fn sockaddr_ip(address: SockAddr) -> Option<IpAddr> {
+    match address {
+        SockAddr::Inet(address) => Some(address.to_std().ip()),
+        _ => None,
+    }
+}
+
+fn prefix_len(netmask: IpAddr) -> u8 {
+    match netmask {
+        IpAddr::V4(netmask) => u32::from(netmask).count_ones() as u8,
+        IpAddr::V6(netmask) => u128::from(netmask).count_ones() as u8,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_identifies_network() {
+        let network = |subnet: &str| identifies_network(&subnet.parse().unwrap());
+        assert!(network("192.168.1.0/24"));
+        assert!(network("2001:db8:1::/64"));
+        assert!(!network("169.254.0.0/16"));
+        assert!(!network("fe80::/64"));
+        assert!(!network("2001:db8:1::1234/128"));
+    }
+
+    #[test]
+    fn test_virtual_interfaces() {
+        assert!(is_virtual_interface("docker0"));
+        assert!(is_virtual_interface("br-4f2c1a7e9b3d"));
+        assert!(is_virtual_interface("virbr0"));
+        assert!(is_virtual_interface("veth1a2b3c4"));
+        assert!(!is_virtual_interface("wlan0"));
+        assert!(!is_virtual_interface("enp3s0"));
+    }
+}
@@ -0,0 +1,141 @@
+//! Keeps track of which relays connection attempts have recently failed for, so that the relay
+//! selector can prefer other relays while retrying.
+
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Failures older than this are forgotten.
+const FAILURE_MEMORY: Duration = Duration::from_secs(10 * 60);
+/// Each recent failure halves the weight of a relay, up to this many failures.
+const MAX_PENALIZED_FAILURES: u32 = 4;
+/// Weights are scaled up by this factor when any relay is penalized, so that penalized relays keep
+/// a non-zero weight.
+const FAILURE_WEIGHT_SCALE: u64 = 1 << MAX_PENALIZED_FAILURES;
+
+/// Connection attempt statistics of a single relay.
+#[derive(Debug, Default, Clone)]
+struct RelayHistory {
+    /// When the attempts that failed since the last successful one were made.
+    recent_failures: Vec<Instant>,
+    /// When a tunnel was last established to the relay.
+    last_success: Option<Instant>,
+}
+
+impl RelayHistory {
+    fn recent_failure_count(&self) -> u32 {
+        self.recent_failures
+            .iter()
+            .filter(|failure| failure.elapsed() < FAILURE_MEMORY)
+            .count() as u32
+    }
+}
+
+/// Connection attempt statistics, keyed by relay hostname. Clones share the same statistics.
+#[derive(Clone, Default)]
+pub struct ConnectionHistory {
+    inner: Arc<Mutex<HashMap<String, RelayHistory>>>,
+}
+
+impl ConnectionHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that a connection attempt to the relay `hostname` failed.
+    pub fn record_failure(&self, hostname: &str) {
+        let mut inner = self.inner.lock();
+        let history = inner.entry(hostname.to_owned()).or_default();
+        history
+            .recent_failures
+            .retain(|failure| failure.elapsed() < FAILURE_MEMORY);
+        history.recent_failures.push(Instant::now());
+        match history.last_success {
+            Some(last_success) => log::debug!(
+                "Failed to connect to {} ({} recent failures, last connected {} seconds ago)",
+                hostname,
+                history.recent_failures.len(),
+                last_success.elapsed().as_secs()
+            ),
+            None => log::debug!(
+                "Failed to connect to {} ({} recent failures)",
+                hostname,
+                history.recent_failures.len()
+            ),
+        }
+    }
+
+    /// Records that a tunnel was established to the relay `hostname`, which clears its failures.
+    pub fn record_success(&self, hostname: &str) {
+        let mut inner = self.inner.lock();
+        let history = inner.entry(hostname.to_owned()).or_default();
+        history.recent_failures.clear();
+        history.last_success = Some(Instant::now());
+    }
+
+    /// Returns the number of recent failed connection attempts to the relay `hostname`.
+    pub fn recent_failures(&self, hostname: &str) -> u32 {
+        self.inner
+            .lock()
+            .get(hostname)
+            .map(RelayHistory::recent_failure_count)
+            .unwrap_or(0)
+    }
+
+    /// Lowers the weights of relays that connection attempts have recently failed for. Each
+    /// recent failure halves the weight. If no relay has failed recently, the weights are
+    /// returned as they are. Otherwise, all weights are scaled by `FAILURE_WEIGHT_SCALE` first.
+    pub fn adjust_weights<'a>(
+        &self,
+        hostnames: impl Iterator<Item = &'a str>,
+        weights: Vec<u64>,
+    ) -> Vec<u64> {
+        let failures: Vec<u32> = hostnames
+            .map(|hostname| self.recent_failures(hostname).min(MAX_PENALIZED_FAILURES))
+            .collect();
+        if failures.iter().all(|&failures| failures == 0) {
+            return weights;
+        }
+        weights
+            .into_iter()
+            .zip(failures)
+            .map(|(weight, failures)| weight.saturating_mul(FAILURE_WEIGHT_SCALE) >> failures)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_relays_are_deprioritized() {
+        let history = ConnectionHistory::new();
+        let hostnames = ["a", "b", "c"];
+        let weights = vec![100, 100, 100];
+
+        assert_eq!(
+            history.adjust_weights(hostnames.iter().cloned(), weights.clone()),
+            weights
+        );
+
+        history.record_failure("a");
+        history.record_failure("b");
+        history.record_failure("b");
+        let scale = FAILURE_WEIGHT_SCALE;
+        assert_eq!(
+            history.adjust_weights(hostnames.iter().cloned(), weights.clone()),
+            vec![100 * scale / 2, 100 * scale / 4, 100 * scale]
+        );
+
+        history.record_success("b");
+        assert_eq!(history.recent_failures("b"), 0);
+        assert_eq!(
+            history.adjust_weights(hostnames.iter().cloned(), weights),
+            vec![100 * scale / 2, 100 * scale, 100 * scale]
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! Measures the round-trip time to relays, so that the relay selector can prefer the relays that
+//! are closest to this device in terms of latency.
+
+use futures::stream::{self, StreamExt};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::net::TcpStream;
+
+/// How long measurements are used before the relays are probed again.
+const PROBE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Relays that have not responded within this time are considered unreachable.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Port that the TCP handshake is attempted on. Whether the port is open does not matter, since a
+/// refused connection also takes one round trip.
+const PROBE_PORT: u16 = 443;
+/// Maximum number of relays that are probed at the same time.
+const MAX_CONCURRENT_PROBES: usize = 16;
+
+/// Round-trip times to relays, keyed by hostname. Clones share the same measurements.
+#[derive(Clone, Default)]
+pub struct RelayLatencies {
+    inner: Arc<Mutex<LatenciesInner>>,
+}
+
+#[derive(Default)]
+struct LatenciesInner {
+    latencies: HashMap<String, Duration>,
+    last_probe: Option<Instant>,
+}
+
+impl RelayLatencies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the last measured round-trip time to the relay `hostname`.
+    pub fn get(&self, hostname: &str) -> Option<Duration> {
+        self.inner.lock().latencies.get(hostname).cloned()
+    }
+
+    /// Returns all measured round-trip times, keyed by hostname.
+    pub fn snapshot(&self) -> HashMap<String, Duration> {
+        self.inner.lock().latencies.clone()
+    }
+
+    pub(super) fn insert(&self, hostname: String, latency: Duration) {
+        self.inner.lock().latencies.insert(hostname, latency);
+    }
+
+    /// Returns whether the relays should be probed, and if so, marks them as being probed now.
+    fn begin_probe(&self) -> bool {
+        let mut inner = self.inner.lock();
+        let should_probe = inner
+            .last_probe
+            .map(|last_probe| last_probe.elapsed() >= PROBE_INTERVAL)
+            .unwrap_or(true);
+        if should_probe {
+            inner.last_probe = Some(Instant::now());
+        }
+        should_probe
+    }
+
+    /// Measures the round-trip time to the given relays in the background, unless they were
+    /// probed recently. Must be called from within a tokio runtime.
+    pub fn probe(&self, relays: Vec<(String, IpAddr)>) {
+        if !self.begin_probe() {
+            return;
+        }
+        let latencies = self.clone();
+        tokio::spawn(async move {
+            log::debug!("Measuring the latency to {} relays", relays.len());
+            stream::iter(relays)
+                .for_each_concurrent(MAX_CONCURRENT_PROBES, |(hostname, address)| {
+                    let latencies = latencies.clone();
+                    async move {
+                        if let Some(latency) = measure_latency(address).await {
+                            latencies.insert(hostname, latency);
+                        }
+                    }
+                })
+                .await;
+            log::debug!("Finished measuring relay latencies");
+        });
+    }
+}
+
+/// Returns the time it takes to complete or be refused a TCP handshake with `address`.
+async fn measure_latency(address: IpAddr) -> Option<Duration> {
+    let start = Instant::now();
+    let result = tokio::time::timeout(
+        PROBE_TIMEOUT,
+        TcpStream::connect(SocketAddr::new(address, PROBE_PORT)),
+    )
+    .await
+    .ok()?;
+    match result {
+        Ok(_) => Some(start.elapsed()),
+        Err(error) if error.kind() == io::ErrorKind::ConnectionRefused => Some(start.elapsed()),
+        Err(_) => None,
+    }
+}
@@ -2,54 +2,47 @@
 //! updated as well.
 
 use chrono::{DateTime, Local};
-use ipnetwork::IpNetwork;
+use mullvad_relay_selector::StdRng;
 use mullvad_rpc::{availability::ApiAvailabilityHandle, rest::MullvadRestHandle};
 use mullvad_types::{
-    endpoint::{MullvadEndpoint, MullvadWireguardEndpoint},
     location::Location,
-    relay_constraints::{
-        BridgeState, Constraint, InternalBridgeConstraints, LocationConstraint, Match,
-        OpenVpnConstraints, Providers, RelayConstraints, Set, TransportPort, WireguardConstraints,
-    },
-    relay_list::{Relay, RelayList, WireguardEndpointData},
+    relay_constraints::{BridgeState, InternalBridgeConstraints, RelayConstraints},
+    relay_list::{Relay, RelayList, RELAYS_FILENAME},
 };
 use parking_lot::Mutex;
-use rand::{self, seq::SliceRandom, Rng};
 use std::{
     io,
     net::IpAddr,
     path::Path,
     sync::Arc,
-    time::{self, SystemTime},
+    time::{self, Duration, SystemTime},
 };
 use talpid_types::{
-    net::{openvpn::ProxySettings, wireguard, IpVersion, TransportProtocol, TunnelType},
+    net::{openvpn::ProxySettings, wireguard},
     ErrorExt,
 };
 
+pub use mullvad_relay_selector::RelaySelectorResult;
+
 use crate::relays::updater::RelayListUpdater;
 
-use self::{
-    matcher::{RelayMatcher, TunnelMatcher, WireguardMatcher},
-    updater::RelayListUpdaterHandle,
-};
+use self::{history::ConnectionHistory, latency::RelayLatencies, updater::RelayListUpdaterHandle};
 
-mod matcher;
+mod history;
+mod latency;
 mod updater;
 
 const DATE_TIME_FORMAT_STR: &str = "%Y-%m-%d %H:%M:%S%.3f";
-const RELAYS_FILENAME: &str = "relays.json";
 
-const DEFAULT_WIREGUARD_PORT: u16 = 51820;
-const WIREGUARD_EXIT_CONSTRAINTS: WireguardMatcher = WireguardMatcher {
-    peer: None,
-    port: Constraint::Only(TransportPort {
-        protocol: TransportProtocol::Udp,
-        port: Constraint::Only(DEFAULT_WIREGUARD_PORT),
-    }),
-    ip_version: Constraint::Only(IpVersion::V4),
-};
-const WIREGUARD_TCP_PORTS: [(u16, u16); 3] = [(80, 80), (443, 443), (5001, 5001)];
+/// Environment variable that sets the seed of the RNG used to select relays, so that the
+/// selections made by a daemon can be reproduced from the seed that it logged.
+const RELAY_SELECTOR_SEED_ENV_VAR: &str = "MULLVAD_RELAY_SELECTOR_SEED";
+
+/// Relay weights are scaled up by this factor when adjusted for latency, so that the adjustment
+/// is not lost to rounding.
+const LATENCY_WEIGHT_SCALE: f64 = 1000.0;
+/// Lower bound for measured latencies, to avoid dividing by zero.
+const MIN_LATENCY: Duration = Duration::from_millis(1);
 
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
@@ -60,9 +53,6 @@ pub enum Error {
     #[error(display = "Failed to write relay cache file to disk")]
     WriteRelayCache(#[error(source)] io::Error),
 
-    #[error(display = "No relays matching current constraints")]
-    NoRelay,
-
     #[error(display = "Failure in serialization of the relay list")]
     Serialize(#[error(source)] serde_json::Error),
 
@@ -86,41 +76,7 @@ impl ParsedRelays {
     }
 
     pub fn from_relay_list(relay_list: RelayList, last_updated: SystemTime) -> Self {
-        let mut relays = Vec::new();
-        for country in &relay_list.countries {
-            let country_name = country.name.clone();
-            let country_code = country.code.clone();
-            for city in &country.cities {
-                let city_name = city.name.clone();
-                let city_code = city.code.clone();
-                let latitude = city.latitude;
-                let longitude = city.longitude;
-                for relay in &city.relays {
-                    let mut relay_with_location = relay.clone();
-                    relay_with_location.location = Some(Location {
-                        country: country_name.clone(),
-                        country_code: country_code.clone(),
-                        city: city_name.clone(),
-                        city_code: city_code.clone(),
-                        latitude,
-                        longitude,
-                    });
-
-                    for wg_tunnel in &relay.tunnels.wireguard {
-                        relay_with_location
-                            .tunnels
-                            .wireguard
-                            .push(WireguardEndpointData {
-                                protocol: TransportProtocol::Tcp,
-                                port_ranges: WIREGUARD_TCP_PORTS.to_vec(),
-                                ..wg_tunnel.clone()
-                            });
-                    }
-
-                    relays.push(relay_with_location);
-                }
-            }
-        }
+        let relays = mullvad_relay_selector::relays_from_list(&relay_list);
         ParsedRelays {
             last_updated,
             locations: relay_list,
@@ -164,6 +120,20 @@ impl ParsedRelays {
 pub struct RelaySelector {
     parsed_relays: Arc<Mutex<ParsedRelays>>,
     updater: Option<RelayListUpdaterHandle>,
+    /// Latitude and longitude of this device, as last reported by a GeoIP lookup made outside
+    /// the tunnel.
+    device_coordinates: Option<(f64, f64)>,
+    /// Measured round-trip times to the relays.
+    latencies: RelayLatencies,
+    /// Whether relays with a low latency are more likely to be selected.
+    prefer_low_latency: bool,
+    /// Recent connection attempts, used to avoid relays that keep failing.
+    history: ConnectionHistory,
+    /// Makes all random choices when selecting relays, endpoints and bridges.
+    rng: Mutex<StdRng>,
+    /// Set if the seed was given by `MULLVAD_RELAY_SELECTOR_SEED`. Latencies are then ignored,
+    /// since they differ between measurements and would make the selections irreproducible.
+    reproducible: bool,
 }
 
 impl RelaySelector {
@@ -202,631 +172,215 @@ impl RelaySelector {
             api_availability,
         );
 
+        let fixed_seed = Self::fixed_rng_seed();
+        let seed = fixed_seed.unwrap_or_else(rand::random);
+        log::info!("Relay selection seed: {}", seed);
+        if fixed_seed.is_some() {
+            log::info!("Ignoring relay latencies to keep the relay selection reproducible");
+        }
+
         RelaySelector {
             parsed_relays,
             updater: Some(updater),
+            device_coordinates: None,
+            latencies: RelayLatencies::new(),
+            prefer_low_latency: false,
+            history: ConnectionHistory::new(),
+            rng: Mutex::new(mullvad_relay_selector::new_rng(seed)),
+            reproducible: fixed_seed.is_some(),
+        }
+    }
+
+    /// Returns the seed set by `MULLVAD_RELAY_SELECTOR_SEED`, if it is set and valid.
+    fn fixed_rng_seed() -> Option<u64> {
+        let seed = std::env::var(RELAY_SELECTOR_SEED_ENV_VAR).ok()?;
+        match seed.parse() {
+            Ok(seed) => Some(seed),
+            Err(_) => {
+                log::error!("Ignoring invalid {}: {}", RELAY_SELECTOR_SEED_ENV_VAR, seed);
+                None
+            }
         }
     }
 
-    /// Download the newest relay list.
-    pub async fn update(&self) {
-        if let Some(mut updater) = self.updater.clone() {
-            let _ = updater.update_relay_list().await;
-        }
+    /// Sets the location of this device, which is used to estimate the latency to entry relays.
+    pub fn set_device_location(&mut self, latitude: f64, longitude: f64) {
+        self.device_coordinates = Some((latitude, longitude));
     }
 
-    /// Returns all countries and cities. The cities in the object returned does not have any
-    /// relays in them.
-    pub fn get_locations(&mut self) -> RelayList {
-        self.parsed_relays.lock().locations().clone()
+    /// Sets whether relays with a low latency should be more likely to be selected. Latencies are
+    /// only measured while this is enabled, and never when the selection must be reproducible.
+    pub fn set_prefer_low_latency(&mut self, prefer_low_latency: bool) {
+        self.prefer_low_latency = prefer_low_latency && !self.reproducible;
     }
 
-    /// Returns a random relay and relay endpoint matching the given constraints and with
-    /// preferences applied.
-    pub fn get_tunnel_endpoint(
-        &self,
-        relay_constraints: &RelayConstraints,
-        bridge_state: BridgeState,
-        retry_attempt: u32,
-        wg_key_exists: bool,
-    ) -> Result<RelaySelectorResult, Error> {
-        match relay_constraints.tunnel_protocol {
-            Constraint::Only(TunnelType::OpenVpn) => self.get_openvpn_endpoint(
-                &relay_constraints.location,
-                &relay_constraints.providers,
-                relay_constraints.openvpn_constraints.clone(),
-                bridge_state,
-                retry_attempt,
-            ),
-
-            Constraint::Only(TunnelType::Wireguard) => self.get_wireguard_endpoint(
-                &relay_constraints.location,
-                &relay_constraints.providers,
-                &relay_constraints.wireguard_constraints,
-                retry_attempt,
-            ),
-            Constraint::Any => self.get_any_tunnel_endpoint(
-                relay_constraints,
-                bridge_state,
-                retry_attempt,
-                wg_key_exists,
-            ),
+    /// Measures the latency to all active relays in the background, unless this was done
+    /// recently. This should only be called while traffic is not sent through a tunnel.
+    pub fn probe_latencies(&self) {
+        if !self.prefer_low_latency {
+            return;
         }
+        let relays = self
+            .parsed_relays
+            .lock()
+            .relays()
+            .iter()
+            .filter(|relay| relay.active)
+            .map(|relay| (relay.hostname.clone(), IpAddr::from(relay.ipv4_addr_in)))
+            .collect();
+        self.latencies.probe(relays);
     }
 
-    /// Returns an OpenVpn endpoint, should only ever be used when the user has specified the tunnel
-    /// protocol as only OpenVPN.
-    fn get_openvpn_endpoint(
-        &self,
-        location: &Constraint<LocationConstraint>,
-        providers: &Constraint<Providers>,
-        openvpn_constraints: OpenVpnConstraints,
-        bridge_state: BridgeState,
-        retry_attempt: u32,
-    ) -> Result<RelaySelectorResult, Error> {
-        let mut relay_matcher = RelayMatcher {
-            location: location.clone(),
-            providers: providers.clone(),
-            tunnel: openvpn_constraints,
-        };
-
-        if relay_matcher.tunnel.port.is_any() && bridge_state == BridgeState::On {
-            relay_matcher.tunnel.port = Constraint::Only(TransportPort {
-                protocol: TransportProtocol::Tcp,
-                port: Constraint::Any,
-            });
-
-            return self.get_tunnel_endpoint_internal(&relay_matcher);
-        }
-
-        let mut preferred_relay_matcher = relay_matcher.clone();
-
-        let (preferred_port, preferred_protocol) =
-            Self::preferred_openvpn_constraints(retry_attempt);
-        let should_try_preferred = match &mut preferred_relay_matcher.tunnel.port {
-            any @ Constraint::Any => {
-                *any = Constraint::Only(TransportPort {
-                    protocol: preferred_protocol,
-                    port: preferred_port,
-                });
-                true
-            }
-            Constraint::Only(ref mut port_constraints)
-                if port_constraints.protocol == preferred_protocol
-                    && port_constraints.port.is_any() =>
-            {
-                port_constraints.port = preferred_port;
-                true
-            }
-            _ => false,
-        };
-
-        if should_try_preferred {
-            self.get_tunnel_endpoint_internal(&preferred_relay_matcher)
-                .or_else(|_| self.get_tunnel_endpoint_internal(&relay_matcher))
-        } else {
-            self.get_tunnel_endpoint_internal(&relay_matcher)
-        }
+    /// Returns the last measured round-trip time to each relay, keyed by hostname. Latencies are
+    /// only measured while low latency relays are preferred.
+    pub fn get_latencies(&self) -> std::collections::HashMap<String, Duration> {
+        self.latencies.snapshot()
     }
 
-    fn get_wireguard_multi_hop_endpoint(
-        &self,
-        mut entry_matcher: RelayMatcher<WireguardMatcher>,
-        exit_location: Constraint<LocationConstraint>,
-    ) -> Result<RelaySelectorResult, Error> {
-        let mut exit_matcher = RelayMatcher {
-            location: exit_location,
-            tunnel: WIREGUARD_EXIT_CONSTRAINTS.clone().into(),
-            ..entry_matcher.clone()
-        };
-
-        let (exit_relay, entry_relay, exit_endpoint, mut entry_endpoint) =
-            if entry_matcher.location.is_subset(&exit_matcher.location) {
-                let (entry_relay, entry_endpoint) = self.get_entry_endpoint(&entry_matcher)?;
-                exit_matcher.set_peer(entry_relay.clone());
-                let exit_result = self.get_tunnel_endpoint_internal(&exit_matcher)?;
-                (
-                    exit_result.exit_relay,
-                    entry_relay,
-                    exit_result.endpoint,
-                    entry_endpoint,
-                )
-            } else {
-                let exit_result = self.get_tunnel_endpoint_internal(&exit_matcher)?;
-
-                entry_matcher.set_peer(exit_result.exit_relay.clone());
-                let (entry_relay, entry_endpoint) = self.get_entry_endpoint(&entry_matcher)?;
-                (
-                    exit_result.exit_relay,
-                    entry_relay,
-                    exit_result.endpoint,
-                    entry_endpoint,
-                )
-            };
-
-        Self::set_entry_peers(&exit_endpoint.unwrap_wireguard().peer, &mut entry_endpoint);
-
-        log::info!(
-            "Selected entry relay {} at {} going through {} at {}",
-            entry_relay.hostname,
-            entry_endpoint.peer.endpoint.ip(),
-            exit_relay.hostname,
-            exit_endpoint.to_endpoint().address.ip(),
-        );
-        let result = RelaySelectorResult::wireguard_multihop_endpoint(
-            exit_relay,
-            entry_endpoint,
-            entry_relay,
-        );
-        return Ok(result);
+    /// Records that a connection attempt to the relay `hostname` failed, which makes it less likely
+    /// to be selected for a while.
+    pub fn record_connection_failure(&self, hostname: &str) {
+        self.history.record_failure(hostname);
     }
 
-    /// Returns a WireGuard endpoint, should only ever be used when the user has specified the
-    /// tunnel protocol as only WireGuard.
-    fn get_wireguard_endpoint(
-        &self,
-        location: &Constraint<LocationConstraint>,
-        providers: &Constraint<Providers>,
-        wireguard_constraints: &WireguardConstraints,
-        retry_attempt: u32,
-    ) -> Result<RelaySelectorResult, Error> {
-        let mut entry_relay_matcher = RelayMatcher {
-            location: location.clone(),
-            providers: providers.clone(),
-            tunnel: wireguard_constraints.clone().into(),
-        };
-
-        let mut preferred_matcher: RelayMatcher<WireguardMatcher> = entry_relay_matcher.clone();
-        preferred_matcher.tunnel.port = preferred_matcher
-            .tunnel
-            .port
-            .or(Self::preferred_wireguard_port(retry_attempt));
+    /// Records that a tunnel was established to the relay `hostname`.
+    pub fn record_connection_success(&self, hostname: &str) {
+        self.history.record_success(hostname);
+    }
 
-        if !wireguard_constraints.use_multihop {
-            return self
-                .get_tunnel_endpoint_internal(&preferred_matcher)
-                .or_else(|_| self.get_tunnel_endpoint_internal(&entry_relay_matcher));
+    /// Download the newest relay list.
+    pub async fn update(&self) {
+        if let Some(mut updater) = self.updater.clone() {
+            let _ = updater.update_relay_list().await;
         }
+    }
 
-        entry_relay_matcher.location = wireguard_constraints.entry_location.clone();
-        entry_relay_matcher.tunnel.port = entry_relay_matcher
-            .tunnel
-            .port
-            .or(Self::preferred_wireguard_port(retry_attempt));
-        self.get_wireguard_multi_hop_endpoint(entry_relay_matcher, location.clone())
+    /// Returns all countries and cities. The cities in the object returned does not have any
+    /// relays in them.
+    pub fn get_locations(&mut self) -> RelayList {
+        self.parsed_relays.lock().locations().clone()
     }
 
-    /// Returns a tunnel endpoint of any type, should only be used when the user hasn't specified a
-    /// tunnel protocol.
-    fn get_any_tunnel_endpoint(
+    /// Compares `public_key` against the keys published for the relay `hostname` in the current
+    /// relay list.
+    pub fn wireguard_key_status(
         &self,
-        relay_constraints: &RelayConstraints,
-        bridge_state: BridgeState,
-        retry_attempt: u32,
-        wg_key_exists: bool,
-    ) -> Result<RelaySelectorResult, Error> {
-        let preferred_constraints = self.preferred_constraints(
-            &relay_constraints,
-            bridge_state,
-            retry_attempt,
-            wg_key_exists,
-        );
-        let original_matcher: RelayMatcher<_> = relay_constraints.clone().into();
-
-        let preferred_tunnel_protocol = preferred_constraints.tunnel_protocol;
-        let preferred_matcher: RelayMatcher<_> = preferred_constraints.into();
-
-        match preferred_tunnel_protocol {
-            Constraint::Only(TunnelType::Wireguard)
-                if relay_constraints.wireguard_constraints.use_multihop =>
-            {
-                let exit_location = relay_constraints.location.clone();
-                let mut preferred_entry_matcher = preferred_matcher.to_wireguard_matcher();
-                preferred_entry_matcher.location = relay_constraints
-                    .wireguard_constraints
-                    .entry_location
-                    .clone();
-                let mut original_entry_matcher = original_matcher.to_wireguard_matcher();
-                original_entry_matcher.location = relay_constraints
-                    .wireguard_constraints
-                    .entry_location
-                    .clone();
-                self.get_wireguard_multi_hop_endpoint(
-                    preferred_entry_matcher,
-                    exit_location.clone(),
-                )
-                .or_else(|_| {
-                    self.get_wireguard_multi_hop_endpoint(original_entry_matcher, exit_location)
-                })
-            }
-
-            _ => {
-                if let Ok(result) = self.get_tunnel_endpoint_internal(&preferred_matcher) {
-                    log::debug!(
-                        "Relay matched on highest preference for retry attempt {}",
-                        retry_attempt
-                    );
-                    Ok(result)
-                } else if let Ok(result) = self.get_tunnel_endpoint_internal(&original_matcher) {
-                    log::debug!(
-                        "Relay matched on second preference for retry attempt {}",
-                        retry_attempt
-                    );
-                    Ok(result)
-                } else {
-                    log::warn!("No relays matching {}", &relay_constraints);
-                    Err(Error::NoRelay)
-                }
-            }
+        hostname: &str,
+        public_key: &wireguard::PublicKey,
+    ) -> RelayKeyStatus {
+        let parsed_relays = self.parsed_relays.lock();
+        let relay = match parsed_relays
+            .relays()
+            .iter()
+            .find(|relay| relay.hostname == hostname && relay.active)
+        {
+            Some(relay) => relay,
+            None => return RelayKeyStatus::RelayUnavailable,
+        };
+        if relay
+            .tunnels
+            .wireguard
+            .iter()
+            .any(|endpoint| &endpoint.public_key == public_key)
+        {
+            RelayKeyStatus::Published
+        } else {
+            RelayKeyStatus::Mismatch
         }
     }
 
-    // This function ignores the tunnel type constraint on purpose.
-    fn preferred_constraints(
+    /// Returns a random relay and relay endpoint matching one of the given ordered constraint
+    /// tiers, along with the index of the tier that was used.
+    pub fn get_tunnel_endpoint_from_tiers(
         &self,
-        original_constraints: &RelayConstraints,
+        tiers: &[RelayConstraints],
         bridge_state: BridgeState,
         retry_attempt: u32,
         wg_key_exists: bool,
-    ) -> RelayConstraints {
-        let (preferred_port, preferred_protocol, preferred_tunnel) = self
-            .preferred_tunnel_constraints(
+    ) -> Result<(usize, RelaySelectorResult), mullvad_relay_selector::Error> {
+        self.select(|selector| {
+            selector.get_tunnel_endpoint_from_tiers(
+                tiers,
+                bridge_state,
                 retry_attempt,
-                &original_constraints.location,
-                &original_constraints.providers,
                 wg_key_exists,
-            );
-
-        let mut relay_constraints = original_constraints.clone();
-        relay_constraints.openvpn_constraints = Default::default();
-
-        // Highest priority preference. Where we prefer OpenVPN using UDP. But without changing
-        // any constraints that are explicitly specified.
-        match original_constraints.tunnel_protocol {
-            // If no tunnel protocol is selected, use preferred constraints
-            Constraint::Any => {
-                if bridge_state == BridgeState::On {
-                    relay_constraints.openvpn_constraints = OpenVpnConstraints {
-                        port: Constraint::Only(TransportPort {
-                            protocol: TransportProtocol::Tcp,
-                            port: Constraint::Any,
-                        }),
-                    };
-                } else if original_constraints.openvpn_constraints.port.is_any() {
-                    relay_constraints.openvpn_constraints = OpenVpnConstraints {
-                        port: Constraint::Only(TransportPort {
-                            protocol: preferred_protocol,
-                            port: preferred_port,
-                        }),
-                    };
-                } else {
-                    relay_constraints.openvpn_constraints =
-                        original_constraints.openvpn_constraints;
-                }
-
-                if relay_constraints.wireguard_constraints.port.is_any() {
-                    relay_constraints.wireguard_constraints.port =
-                        Constraint::Only(TransportPort {
-                            protocol: preferred_protocol,
-                            port: preferred_port,
-                        });
-                }
-
-                relay_constraints.tunnel_protocol = Constraint::Only(preferred_tunnel);
-            }
-            Constraint::Only(TunnelType::OpenVpn) => {
-                let openvpn_constraints = &mut relay_constraints.openvpn_constraints;
-                *openvpn_constraints = original_constraints.openvpn_constraints;
-                if bridge_state == BridgeState::On && openvpn_constraints.port.is_any() {
-                    openvpn_constraints.port = Constraint::Only(TransportPort {
-                        protocol: TransportProtocol::Tcp,
-                        port: Constraint::Any,
-                    });
-                } else if openvpn_constraints.port.is_any() {
-                    let (preferred_port, preferred_protocol) =
-                        Self::preferred_openvpn_constraints(retry_attempt);
-                    openvpn_constraints.port = Constraint::Only(TransportPort {
-                        protocol: preferred_protocol,
-                        port: preferred_port,
-                    });
-                }
-            }
-            Constraint::Only(TunnelType::Wireguard) => {
-                relay_constraints.wireguard_constraints =
-                    original_constraints.wireguard_constraints.clone();
-                if relay_constraints.wireguard_constraints.port.is_any() {
-                    relay_constraints.wireguard_constraints.port =
-                        Self::preferred_wireguard_port(retry_attempt);
-                }
-            }
-        };
-
-        if relay_constraints.wireguard_constraints.port.is_any() {
-            relay_constraints.wireguard_constraints.port = Constraint::Only(TransportPort {
-                port: preferred_port,
-                protocol: TransportProtocol::Udp,
-            });
-        }
-
-        relay_constraints.tunnel_protocol = Constraint::Only(preferred_tunnel);
-
-        relay_constraints
-    }
-
-    fn get_entry_endpoint(
-        &self,
-        matcher: &RelayMatcher<WireguardMatcher>,
-    ) -> Result<(Relay, MullvadWireguardEndpoint), Error> {
-        let matching_relays: Vec<Relay> = self
-            .parsed_relays
-            .lock()
-            .relays()
-            .iter()
-            .filter(|relay| relay.active)
-            .filter_map(|relay| matcher.filter_matching_relay(relay))
-            .collect();
-
-        let relay = self
-            .pick_random_relay(&matching_relays)
-            .map(|relay| relay.clone())
-            .ok_or(Error::NoRelay)?;
-        let endpoint = matcher
-            .mullvad_endpoint(&relay)
-            .ok_or(Error::NoRelay)?
-            .unwrap_wireguard()
-            .clone();
-
-        Ok((relay, endpoint))
-    }
-
-    fn set_entry_peers(
-        exit_peer: &wireguard::PeerConfig,
-        entry_endpoint: &mut MullvadWireguardEndpoint,
-    ) {
-        entry_endpoint.peer.allowed_ips = vec![IpNetwork::from(exit_peer.endpoint.ip())];
-        entry_endpoint.exit_peer = Some(exit_peer.clone());
+            )
+        })
     }
 
     pub fn get_auto_proxy_settings(
-        &mut self,
+        &self,
         bridge_constraints: &InternalBridgeConstraints,
         location: &Location,
         retry_attempt: u32,
     ) -> Option<(ProxySettings, Relay)> {
-        if !self.should_use_bridge(retry_attempt) {
-            return None;
-        }
-
-        // For now, only TCP tunnels are supported.
-        if let Constraint::Only(TransportProtocol::Udp) = bridge_constraints.transport_protocol {
-            return None;
-        }
-
-        self.get_proxy_settings(bridge_constraints, location)
+        self.select(|selector| {
+            selector.get_auto_proxy_settings(bridge_constraints, location, retry_attempt)
+        })
     }
 
     pub fn should_use_bridge(&self, retry_attempt: u32) -> bool {
-        // shouldn't use a bridge for the first 3 times
-        retry_attempt > 3 &&
-            // i.e. 4th and 5th with bridge, 6th & 7th without
-            // The test is to see whether the current _couple of connections_ is even or not.
-            // | retry_attempt                | 4 | 5 | 6 | 7 | 8 | 9 |
-            // | (retry_attempt % 4) < 2      | t | t | f | f | t | t |
-            (retry_attempt % 4) < 2
+        mullvad_relay_selector::RelaySelector::should_use_bridge(retry_attempt)
     }
 
     pub fn get_proxy_settings(
-        &mut self,
+        &self,
         constraints: &InternalBridgeConstraints,
         location: &Location,
     ) -> Option<(ProxySettings, Relay)> {
-        let mut matching_relays: Vec<Relay> = self
-            .parsed_relays
-            .lock()
-            .relays()
+        self.select(|selector| selector.get_proxy_settings(constraints, location))
+    }
+
+    /// Calls `select` with a selector over the current relay list, which weighs relays by their
+    /// latency and connection history.
+    fn select<T>(&self, select: impl FnOnce(&mullvad_relay_selector::RelaySelector<'_>) -> T) -> T {
+        let parsed_relays = self.parsed_relays.lock();
+        let mut rng = self.rng.lock();
+        let weights = |relays: &[Relay]| {
+            self.history.adjust_weights(
+                relays.iter().map(|relay| relay.hostname.as_str()),
+                self.relay_weights(relays),
+            )
+        };
+        let mut selector =
+            mullvad_relay_selector::RelaySelector::new(parsed_relays.relays(), &mut *rng);
+        selector.set_weights(&weights);
+        selector.set_device_coordinates(self.device_coordinates);
+        select(&selector)
+    }
+
+    /// Returns the weight of each relay when picking one at random. If low latency relays are
+    /// preferred, each weight is scaled by the square of the ratio between the lowest latency and
+    /// the latency of the relay. A relay with twice the latency is thus a quarter as likely to be
+    /// picked. Relays that have not been measured are treated as the slowest measured relay.
+    fn relay_weights(&self, relays: &[Relay]) -> Vec<u64> {
+        let unadjusted_weights = || relays.iter().map(|relay| relay.weight).collect();
+        if !self.prefer_low_latency {
+            return unadjusted_weights();
+        }
+        let latencies: Vec<Option<Duration>> = relays
             .iter()
-            .filter(|relay| relay.active)
-            .filter_map(|relay| Self::matching_bridge_relay(relay, constraints))
+            .map(|relay| {
+                self.latencies
+                    .get(&relay.hostname)
+                    .map(|latency| latency.max(MIN_LATENCY))
+            })
             .collect();
-
-        if matching_relays.is_empty() {
-            return None;
-        }
-
-        matching_relays.sort_by_cached_key(|relay| {
-            (relay.location.as_ref().unwrap().distance_from(&location) * 1000.0) as i64
-        });
-        matching_relays.get(0).and_then(|relay| {
-            self.pick_random_bridge(&relay)
-                .map(|bridge| (bridge, relay.clone()))
-        })
-    }
-
-    /// Returns preferred constraints
-    #[allow(unused_variables)]
-    fn preferred_tunnel_constraints(
-        &self,
-        retry_attempt: u32,
-        location_constraint: &Constraint<LocationConstraint>,
-        providers_constraint: &Constraint<Providers>,
-        wg_key_exists: bool,
-    ) -> (Constraint<u16>, TransportProtocol, TunnelType) {
-        #[cfg(target_os = "windows")]
-        {
-            let location_supports_openvpn =
-                self.parsed_relays.lock().relays().iter().any(|relay| {
-                    relay.active
-                        && !relay.tunnels.openvpn.is_empty()
-                        && location_constraint.matches(relay)
-                        && providers_constraint.matches(relay)
-                });
-            if location_supports_openvpn {
-                let (preferred_port, preferred_protocol) =
-                    Self::preferred_openvpn_constraints(retry_attempt);
-                return (preferred_port, preferred_protocol, TunnelType::OpenVpn);
-            }
-        }
-
-        let location_supports_wireguard = self.parsed_relays.lock().relays().iter().any(|relay| {
-            relay.active
-                && !relay.tunnels.wireguard.is_empty()
-                && location_constraint.matches(relay)
-                && providers_constraint.matches(relay)
-        });
-        // If location does not support WireGuard, defer to preferred OpenVPN tunnel
-        // constraints
-        if !location_supports_wireguard || !wg_key_exists {
-            let (preferred_port, preferred_protocol) =
-                Self::preferred_openvpn_constraints(retry_attempt);
-            return (preferred_port, preferred_protocol, TunnelType::OpenVpn);
-        }
-
-        // Try out WireGuard in the first two connection attempts, first with any port,
-        // afterwards on port 53. Afterwards, connect through OpenVPN alternating between UDP
-        // on any port twice and TCP on port 443 once.
-        match retry_attempt {
-            0 => (
-                Constraint::Any,
-                TransportProtocol::Udp,
-                TunnelType::Wireguard,
-            ),
-            1 => (
-                Constraint::Only(53),
-                TransportProtocol::Udp,
-                TunnelType::Wireguard,
-            ),
-            _ => {
-                let (preferred_port, preferred_protocol) =
-                    Self::preferred_openvpn_constraints(retry_attempt - 2);
-                (preferred_port, preferred_protocol, TunnelType::OpenVpn)
-            }
-        }
-    }
-
-    fn preferred_wireguard_port(retry_attempt: u32) -> Constraint<TransportPort> {
-        // This ensures that if after the first 2 failed attempts the daemon does not
-        // connect, then afterwards 2 of each 4 successive attempts will try to connect
-        // on port 53.
-        let port = match retry_attempt % 4 {
-            0 | 1 => Constraint::Any,
-            _ => Constraint::Only(53),
+        let lowest_latency = latencies.iter().flatten().min().cloned();
+        let highest_latency = latencies.iter().flatten().max().cloned();
+        let (lowest_latency, highest_latency) = match (lowest_latency, highest_latency) {
+            (Some(lowest), Some(highest)) => (lowest, highest),
+            _ => return unadjusted_weights(),
         };
-        Constraint::Only(TransportPort {
-            port,
-            protocol: TransportProtocol::Udp,
-        })
-    }
-
-    fn preferred_openvpn_constraints(retry_attempt: u32) -> (Constraint<u16>, TransportProtocol) {
-        // Prefer UDP by default. But if that has failed a couple of times, then try TCP port
-        // 443, which works for many with UDP problems. After that, just alternate
-        // between protocols.
-        // If the tunnel type constraint is set OpenVpn, from the 4th attempt onwards, every two
-        // retry attempts OpenVpn constraints should be set to TCP as a bridge will be used,
-        // and to UDP for the next two attempts. If the tunnel type is specified to be _Any_
-        // and on not-Windows, the first two tries are used for WireGuard and don't
-        // affect counting here.
-        match retry_attempt {
-            0 | 1 => (Constraint::Any, TransportProtocol::Udp),
-            2 | 3 => (Constraint::Only(443), TransportProtocol::Tcp),
-            attempt if attempt % 2 == 0 => (Constraint::Any, TransportProtocol::Udp),
-            _ => (Constraint::Any, TransportProtocol::Tcp),
-        }
-    }
-
-    /// Returns a random relay endpoint if any is matching the given constraints.
-    fn get_tunnel_endpoint_internal<T: TunnelMatcher>(
-        &self,
-        matcher: &RelayMatcher<T>,
-    ) -> Result<RelaySelectorResult, Error> {
-        let matching_relays: Vec<Relay> = self
-            .parsed_relays
-            .lock()
-            .relays()
+        relays
             .iter()
-            .filter(|relay| relay.active)
-            .filter_map(|relay| matcher.filter_matching_relay(relay))
-            .collect();
-
-        self.pick_random_relay(&matching_relays)
-            .and_then(|selected_relay| {
-                let endpoint = matcher.mullvad_endpoint(&selected_relay);
-                let addr_in = endpoint
-                    .as_ref()
-                    .map(|endpoint| endpoint.to_endpoint().address.ip())
-                    .unwrap_or(IpAddr::from(selected_relay.ipv4_addr_in));
-                log::info!("Selected relay {} at {}", selected_relay.hostname, addr_in);
-                endpoint.map(|endpoint| RelaySelectorResult::new(endpoint, selected_relay.clone()))
-            })
-            .ok_or(Error::NoRelay)
-    }
-
-    fn matching_bridge_relay(
-        relay: &Relay,
-        constraints: &InternalBridgeConstraints,
-    ) -> Option<Relay> {
-        if !constraints.location.matches(relay) {
-            return None;
-        }
-        if !constraints.providers.matches(relay) {
-            return None;
-        }
-
-        let mut filtered_relay = relay.clone();
-        filtered_relay
-            .bridges
-            .shadowsocks
-            .retain(|bridge| constraints.transport_protocol.matches_eq(&bridge.protocol));
-        if filtered_relay.bridges.shadowsocks.is_empty() {
-            return None;
-        }
-
-        Some(filtered_relay)
-    }
-
-    /// Pick a random relay from the given slice. Will return `None` if the given slice is empty.
-    /// If all of the relays have a weight of 0, one will be picked at random without bias,
-    /// otherwise roulette wheel selection will be used to pick only relays with non-zero
-    /// weights.
-    fn pick_random_relay<'a>(&self, relays: &'a [Relay]) -> Option<&'a Relay> {
-        let total_weight: u64 = relays.iter().map(|relay| relay.weight).sum();
-        let mut rng = rand::thread_rng();
-        if total_weight == 0 {
-            relays.choose(&mut rng)
-        } else {
-            // Pick a random number in the range 1..=total_weight. This choses the relay with a
-            // non-zero weight.
-            let mut i: u64 = rng.gen_range(1, total_weight + 1);
-            Some(
-                relays
-                    .iter()
-                    .find(|relay| {
-                        i = i.saturating_sub(relay.weight);
-                        i == 0
-                    })
-                    .expect("At least one relay must've had a weight above 0"),
-            )
-        }
-    }
-
-    /// Picks a random bridge from a relay.
-    fn pick_random_bridge(&self, relay: &Relay) -> Option<ProxySettings> {
-        relay
-            .bridges
-            .shadowsocks
-            .choose(&mut rand::thread_rng())
-            .map(|shadowsocks_endpoint| {
-                log::info!(
-                    "Selected Shadowsocks bridge {} at {}:{}/{}",
-                    relay.hostname,
-                    relay.ipv4_addr_in,
-                    shadowsocks_endpoint.port,
-                    shadowsocks_endpoint.protocol
-                );
-                shadowsocks_endpoint
-                    .clone()
-                    .to_proxy_settings(relay.ipv4_addr_in.into())
+            .zip(latencies)
+            .map(|(relay, latency)| {
+                let latency = latency.unwrap_or(highest_latency);
+                let factor = (lowest_latency.as_secs_f64() / latency.as_secs_f64()).powi(2);
+                (relay.weight as f64 * factor * LATENCY_WEIGHT_SCALE).round() as u64
             })
+            .collect()
     }
 
     /// Try to read the relays from disk, preferring the newer ones.
@@ -837,6 +391,14 @@ impl RelaySelector {
         // prefer the resource path's relay list if the cached one doesn't exist or was modified
         // before the resource one was created.
         let cached_relays = ParsedRelays::from_file(cache_path);
+        if let Err(Error::Serialize(error)) = &cached_relays {
+            // Most likely a partially written cache from before cache writes were atomic
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Removing corrupt relay cache")
+            );
+            let _ = std::fs::remove_file(cache_path);
+        }
         let bundled_relays = match ParsedRelays::from_file(resource_path) {
             Ok(bundled_relays) => bundled_relays,
             Err(e) => {
@@ -857,46 +419,25 @@ impl RelaySelector {
     }
 }
 
-#[derive(Debug)]
-pub struct RelaySelectorResult {
-    pub exit_relay: Relay,
-    pub endpoint: MullvadEndpoint,
-    pub entry_relay: Option<Relay>,
-}
-
-impl RelaySelectorResult {
-    fn new(endpoint: MullvadEndpoint, exit_relay: Relay) -> Self {
-        Self {
-            exit_relay,
-            endpoint,
-            entry_relay: None,
-        }
-    }
-
-    fn wireguard_multihop_endpoint(
-        exit_relay: Relay,
-        endpoint: MullvadWireguardEndpoint,
-        entry: Relay,
-    ) -> Self {
-        Self {
-            exit_relay,
-            endpoint: MullvadEndpoint::Wireguard(endpoint),
-            entry_relay: Some(entry),
-        }
-    }
+/// Result of comparing the WireGuard key of a relay against the relay list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayKeyStatus {
+    /// The key is published for the relay.
+    Published,
+    /// The relay is listed with other keys.
+    Mismatch,
+    /// The relay has been removed from the list or is inactive.
+    RelayUnavailable,
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use mullvad_types::{
-        relay_constraints::RelayConstraints,
-        relay_list::{
-            OpenVpnEndpointData, Relay, RelayBridges, RelayListCity, RelayListCountry,
-            RelayTunnels, WireguardEndpointData,
-        },
+    use mullvad_types::relay_list::{
+        OpenVpnEndpointData, RelayBridges, RelayListCity, RelayListCountry, RelayTunnels,
+        WireguardEndpointData,
     };
-    use talpid_types::net::wireguard::PublicKey;
+    use talpid_types::net::{wireguard::PublicKey, TransportProtocol};
 
     lazy_static::lazy_static! {
         static ref RELAYS: RelayList = RelayList {
@@ -1010,461 +551,75 @@ mod test {
                 SystemTime::now(),
             ))),
             updater: None,
+            device_coordinates: None,
+            latencies: RelayLatencies::new(),
+            prefer_low_latency: false,
+            history: ConnectionHistory::new(),
+            rng: Mutex::new(mullvad_relay_selector::new_rng(0)),
+            reproducible: false,
         }
     }
 
     #[test]
-    fn test_preferred_tunnel_protocol() {
-        let relay_selector = new_relay_selector();
-
-        // Prefer WG if the location only supports it
-        let location = LocationConstraint::Hostname(
-            "se".to_string(),
-            "got".to_string(),
-            "se9-wireguard".to_string(),
-        );
-        let relay_constraints = RelayConstraints {
-            location: Constraint::Only(location.clone()),
-            tunnel_protocol: Constraint::Any,
-            ..RelayConstraints::default()
-        };
-
-        let preferred =
-            relay_selector.preferred_constraints(&relay_constraints, BridgeState::Off, 0, true);
-        assert_eq!(
-            preferred.tunnel_protocol,
-            Constraint::Only(TunnelType::Wireguard)
-        );
-
-        for attempt in 0..10 {
-            assert!(relay_selector
-                .get_any_tunnel_endpoint(&relay_constraints, BridgeState::Off, attempt, true)
-                .is_ok());
-        }
-
-        // Prefer OpenVPN if the location only supports it
-        let location = LocationConstraint::Hostname(
-            "se".to_string(),
-            "got".to_string(),
-            "se-got-001".to_string(),
-        );
-        let relay_constraints = RelayConstraints {
-            location: Constraint::Only(location.clone()),
-            tunnel_protocol: Constraint::Any,
-            ..RelayConstraints::default()
-        };
-
-        let preferred =
-            relay_selector.preferred_constraints(&relay_constraints, BridgeState::Off, 0, true);
-        assert_eq!(
-            preferred.tunnel_protocol,
-            Constraint::Only(TunnelType::OpenVpn)
-        );
-
-        for attempt in 0..10 {
-            assert!(relay_selector
-                .get_any_tunnel_endpoint(&relay_constraints, BridgeState::Off, attempt, true)
-                .is_ok());
-        }
-
-        // Prefer OpenVPN on Windows when possible
-        #[cfg(windows)]
-        {
-            let relay_constraints = RelayConstraints::default();
-            for attempt in 0..10 {
-                let preferred = relay_selector.preferred_constraints(
-                    &relay_constraints,
-                    BridgeState::Off,
-                    attempt,
-                    true,
-                );
-                assert_eq!(
-                    preferred.tunnel_protocol,
-                    Constraint::Only(TunnelType::OpenVpn)
-                );
-                match relay_selector.get_any_tunnel_endpoint(
-                    &relay_constraints,
-                    BridgeState::Off,
-                    attempt,
-                    true,
-                ) {
-                    Ok(result) if matches!(result.endpoint, MullvadEndpoint::OpenVpn(_)) => (),
-                    _ => panic!("OpenVPN endpoint was not selected"),
-                }
-            }
-        }
-    }
-
-    #[test]
-    fn test_wg_entry_hostname_collision() {
-        let relay_selector = new_relay_selector();
-
-        let location1 = LocationConstraint::Hostname(
-            "se".to_string(),
-            "got".to_string(),
-            "se9-wireguard".to_string(),
-        );
-        let location2 = LocationConstraint::Hostname(
-            "se".to_string(),
-            "got".to_string(),
-            "se10-wireguard".to_string(),
-        );
-
-        let mut relay_constraints = RelayConstraints {
-            location: Constraint::Only(location1.clone()),
-            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
-            ..RelayConstraints::default()
-        };
-
-        relay_constraints.wireguard_constraints.use_multihop = true;
-        relay_constraints.wireguard_constraints.entry_location = Constraint::Only(location1);
-
-        // The same host cannot be used for entry and exit
-        assert!(relay_selector
-            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
-            .is_err());
-
-        relay_constraints.wireguard_constraints.entry_location = Constraint::Only(location2);
-
-        // If the entry and exit differ, this should succeed
-        assert!(relay_selector
-            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
-            .is_ok());
-    }
-
-    #[test]
-    fn test_wg_entry_filter() -> Result<(), String> {
-        let relay_selector = new_relay_selector();
-
-        let specific_hostname = "se10-wireguard";
-
-        let location_general = LocationConstraint::City("se".to_string(), "got".to_string());
-        let location_specific = LocationConstraint::Hostname(
-            "se".to_string(),
-            "got".to_string(),
-            specific_hostname.to_string(),
-        );
-
-        let mut relay_constraints = RelayConstraints {
-            location: Constraint::Only(location_general.clone()),
-            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
-            ..RelayConstraints::default()
-        };
-
-        relay_constraints.wireguard_constraints.use_multihop = true;
-        relay_constraints.wireguard_constraints.entry_location =
-            Constraint::Only(location_specific.clone());
-
-        // The exit must not equal the entry
-        let exit_relay = relay_selector
-            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
-            .map_err(|error| error.to_string())?
-            .exit_relay;
-
-        assert_ne!(exit_relay.hostname, specific_hostname);
-
-        relay_constraints.location = Constraint::Only(location_specific);
-        relay_constraints.wireguard_constraints.entry_location = Constraint::Only(location_general);
-
-        // The entry must not equal the exit
-        let RelaySelectorResult {
-            exit_relay,
-            endpoint,
-            ..
-        } = relay_selector
-            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
-            .map_err(|error| error.to_string())?;
-
-        assert_eq!(exit_relay.hostname, specific_hostname);
-
-        let endpoint = endpoint.unwrap_wireguard();
-        assert_eq!(
-            exit_relay.ipv4_addr_in,
-            endpoint.exit_peer.as_ref().unwrap().endpoint.ip()
-        );
-        assert_ne!(exit_relay.ipv4_addr_in, endpoint.peer.endpoint.ip());
-
-        Ok(())
-    }
-
-    #[test]
-    fn test_bridge_constraints() -> Result<(), String> {
+    fn test_wireguard_key_status() {
         let relay_selector = new_relay_selector();
+        let published_key =
+            PublicKey::from_base64("BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=").unwrap();
+        let other_key =
+            PublicKey::from_base64("veGD6/aEY6sMfN3Ls7YWPmNgu3AheO7nQqsFT47YSws=").unwrap();
 
-        let location = LocationConstraint::Hostname(
-            "se".to_string(),
-            "got".to_string(),
-            "se-got-001".to_string(),
-        );
-        let mut relay_constraints = RelayConstraints {
-            location: Constraint::Only(location.clone()),
-            tunnel_protocol: Constraint::Any,
-            ..RelayConstraints::default()
-        };
-        relay_constraints.openvpn_constraints.port = Constraint::Only(TransportPort {
-            protocol: TransportProtocol::Udp,
-            port: Constraint::Any,
-        });
-
-        let preferred =
-            relay_selector.preferred_constraints(&relay_constraints, BridgeState::On, 0, true);
         assert_eq!(
-            preferred.tunnel_protocol,
-            Constraint::Only(TunnelType::OpenVpn)
+            relay_selector.wireguard_key_status("se9-wireguard", &published_key),
+            RelayKeyStatus::Published
         );
-        // NOTE: TCP is preferred for bridges
         assert_eq!(
-            preferred.openvpn_constraints.port,
-            Constraint::Only(TransportPort {
-                protocol: TransportProtocol::Tcp,
-                port: Constraint::Any,
-            })
-        );
-
-        // Ignore bridge state where WireGuard is used
-        let location = LocationConstraint::Hostname(
-            "se".to_string(),
-            "got".to_string(),
-            "se10-wireguard".to_string(),
+            relay_selector.wireguard_key_status("se9-wireguard", &other_key),
+            RelayKeyStatus::Mismatch
         );
-        let relay_constraints = RelayConstraints {
-            location: Constraint::Only(location),
-            tunnel_protocol: Constraint::Any,
-            ..RelayConstraints::default()
-        };
-        let preferred =
-            relay_selector.preferred_constraints(&relay_constraints, BridgeState::On, 0, true);
         assert_eq!(
-            preferred.tunnel_protocol,
-            Constraint::Only(TunnelType::Wireguard)
+            relay_selector.wireguard_key_status("se-removed-001", &published_key),
+            RelayKeyStatus::RelayUnavailable
         );
 
-        // Handle bridge setting when falling back on OpenVPN
-        let mut relay_constraints = RelayConstraints {
-            location: Constraint::Any,
-            tunnel_protocol: Constraint::Any,
-            ..RelayConstraints::default()
-        };
-        relay_constraints.openvpn_constraints.port = Constraint::Only(TransportPort {
-            protocol: TransportProtocol::Udp,
-            port: Constraint::Any,
-        });
-        #[cfg(all(unix, not(target_os = "android")))]
-        {
-            let preferred =
-                relay_selector.preferred_constraints(&relay_constraints, BridgeState::On, 0, true);
-            assert_eq!(
-                preferred.tunnel_protocol,
-                Constraint::Only(TunnelType::Wireguard)
-            );
-        }
-        let preferred =
-            relay_selector.preferred_constraints(&relay_constraints, BridgeState::On, 2, true);
-        assert_eq!(
-            preferred.tunnel_protocol,
-            Constraint::Only(TunnelType::OpenVpn)
-        );
+        relay_selector
+            .parsed_relays
+            .lock()
+            .relays
+            .iter_mut()
+            .find(|relay| relay.hostname == "se9-wireguard")
+            .unwrap()
+            .active = false;
         assert_eq!(
-            preferred.openvpn_constraints.port,
-            Constraint::Only(TransportPort {
-                protocol: TransportProtocol::Tcp,
-                port: Constraint::Any,
-            })
-        );
-
-        Ok(())
-    }
-
-    #[test]
-    fn test_wg_relay_with_no_key() {
-        let mut relay_constraints = RelayConstraints {
-            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
-            ..RelayConstraints::default()
-        };
-
-        let relay_selector = new_relay_selector();
-
-        let result = relay_selector
-            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, false)
-            .expect("Failed to get WireGuard relay when WireGuard relay was specified as the only tunnel protocol");
-
-        assert!(matches!(result.endpoint, MullvadEndpoint::Wireguard(_)));
-
-        relay_constraints.tunnel_protocol = Constraint::Any;
-        let result = relay_selector
-            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, false)
-            .expect("Failed to get OpenVPN relay with tunnel protocol constraint set to Any and without a WireGuard key");
-
-        assert!(matches!(result.endpoint, MullvadEndpoint::OpenVpn(_)));
-
-        let wireguard_specific_location = LocationConstraint::Hostname(
-            "se".to_string(),
-            "got".to_string(),
-            "se9-wireguard".to_string(),
-        );
-        relay_constraints.location = Constraint::Only(wireguard_specific_location);
-
-        let result = relay_selector
-            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, false)
-            .expect(
-                "Failed to get a valid WireGuard relay when tunnel constraints are set to any
-                tunnel protocol and with a wireguard specific location without a wireguard key",
-            );
-
-        assert!(matches!(result.endpoint, MullvadEndpoint::Wireguard(_)));
-
-        let result = relay_selector
-            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
-            .expect(
-                "Failed to get a valid WireGuard relay when tunnel constraints are set to any
-                tunnel protocol and with a wireguard specific location with a wireguard key",
-            );
-
-        assert!(matches!(result.endpoint, MullvadEndpoint::Wireguard(_)));
-    }
-
-    #[test]
-    fn test_selecting_any_relay_will_consider_multihop() {
-        let relay_constraints = RelayConstraints {
-            wireguard_constraints: WireguardConstraints {
-                use_multihop: true,
-                ..WireguardConstraints::default()
-            },
-            // This has to be explicit otherwise Android will chose WireGuard when default
-            // constructing.
-            tunnel_protocol: Constraint::Any,
-            ..RelayConstraints::default()
-        };
-
-        let relay_selector = new_relay_selector();
-
-        let result = relay_selector.get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
-            .expect("Failed to get relay when tunnel constraints are set to Any and retrying the selection");
-        // Windows will ignore WireGuard until WireGuard is supported well enough
-        // TODO: Remove this caveat once Windows defaults to using WireGuard
-        #[cfg(target_os = "windows")]
-        assert!(
-            matches!(result.endpoint, MullvadEndpoint::OpenVpn(_)) && result.entry_relay.is_none()
-        );
-
-        #[cfg(not(target_os = "windows"))]
-        assert!(
-            matches!(result.endpoint, MullvadEndpoint::Wireguard(_))
-                && result.entry_relay.is_some()
+            relay_selector.wireguard_key_status("se9-wireguard", &published_key),
+            RelayKeyStatus::RelayUnavailable
         );
     }
 
-    const WIREGUARD_MULTIHOP_CONSTRAINTS: RelayConstraints = RelayConstraints {
-        location: Constraint::Any,
-        providers: Constraint::Any,
-        wireguard_constraints: WireguardConstraints {
-            use_multihop: true,
-            port: Constraint::Any,
-            ip_version: Constraint::Any,
-            entry_location: Constraint::Any,
-        },
-        tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
-        openvpn_constraints: OpenVpnConstraints {
-            port: Constraint::Any,
-        },
-    };
-
-    #[test]
-    fn test_selecting_wireguard_location_will_consider_multihop() {
-        let relay_selector = new_relay_selector();
-
-        let result = relay_selector.get_tunnel_endpoint(&WIREGUARD_MULTIHOP_CONSTRAINTS, BridgeState::Off, 0, true)
-
-            .expect("Failed to get relay when tunnel constraints are set to Any and retrying the selection");
-
-        assert!(result.entry_relay.is_some());
-        let endpoint = result.endpoint.unwrap_wireguard();
-        assert!(matches!(endpoint.peer.protocol, TransportProtocol::Udp));
-        assert!(matches!(
-            endpoint.exit_peer.as_ref().unwrap().protocol,
-            TransportProtocol::Udp
-        ));
-    }
-
-    #[test]
-    fn test_selecting_wg_multihop_tcp() {
-        let mut relay_constraints = WIREGUARD_MULTIHOP_CONSTRAINTS.clone();
-        relay_constraints.wireguard_constraints.port = Constraint::Only(TransportPort {
-            port: Constraint::Any,
-            protocol: TransportProtocol::Tcp,
-        });
-
-        let relay_selector = new_relay_selector();
-
-        let result = relay_selector
-            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
-            .expect("Failed to get WireGuard TCP multihop relay");
-
-        assert!(result.entry_relay.is_some());
-        let endpoint = result.endpoint.unwrap_wireguard();
-        assert!(matches!(endpoint.peer.protocol, TransportProtocol::Tcp));
-        assert!(matches!(
-            endpoint.exit_peer.as_ref().unwrap().protocol,
-            TransportProtocol::Udp
-        ));
-    }
-
     #[test]
-    fn test_selecting_wg_tcp() {
-        let relay_constraints = RelayConstraints {
-            wireguard_constraints: WireguardConstraints {
-                port: Constraint::Only(TransportPort {
-                    port: Constraint::Any,
-                    protocol: TransportProtocol::Tcp,
-                }),
-                ..WireguardConstraints::default()
-            },
-            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
-            ..RelayConstraints::default()
-        };
-
-        let relay_selector = new_relay_selector();
-
-        let result = relay_selector
-            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
-            .expect("Failed to get WireGuard TCP relay");
-        let endpoint = result.endpoint.unwrap_wireguard();
-        assert!(matches!(endpoint.peer.protocol, TransportProtocol::Tcp));
-        assert!(endpoint.exit_peer.is_none());
-    }
-
-    #[test]
-    fn test_selecting_wg_multihop_ports() {
-        let mut relay_constraints = WIREGUARD_MULTIHOP_CONSTRAINTS.clone();
-        let relay_selector = new_relay_selector();
-
-        const INVALID_UDP_PORTS: [u16; 2] = [80, 443];
-        for attempt in 0..1000 {
-            let result = relay_selector
-                .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, attempt, true)
-                .expect("Failed to get WireGuard TCP multihop relay");
-            assert!(!INVALID_UDP_PORTS.contains(&result.endpoint.to_endpoint().address.port()));
-            assert_eq!(
-                result.endpoint.unwrap_wireguard().peer.protocol,
-                TransportProtocol::Udp
-            );
-        }
-
-        relay_constraints.wireguard_constraints.port = Constraint::Only(TransportPort {
-            port: Constraint::Any,
-            protocol: TransportProtocol::Tcp,
-        });
-
-        const VALID_TCP_PORTS: [u16; 3] = [80, 443, 5001];
-        for attempt in 0..1000 {
-            let result = relay_selector
-                .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, attempt, true)
-                .expect("Failed to get WireGuard TCP multihop relay");
-            assert!(VALID_TCP_PORTS.contains(&result.endpoint.to_endpoint().address.port()));
-            assert_eq!(
-                result.endpoint.unwrap_wireguard().peer.protocol,
-                TransportProtocol::Tcp
-            );
-        }
+    fn test_low_latency_relay_weights() {
+        let mut relay_selector = new_relay_selector();
+        let relays: Vec<Relay> = relay_selector.parsed_relays.lock().relays()[..3].to_vec();
+        relay_selector
+            .latencies
+            .insert(relays[0].hostname.clone(), Duration::from_millis(10));
+        relay_selector
+            .latencies
+            .insert(relays[1].hostname.clone(), Duration::from_millis(20));
+
+        let unadjusted: Vec<u64> = relays.iter().map(|relay| relay.weight).collect();
+        assert_eq!(relay_selector.relay_weights(&relays), unadjusted);
+
+        relay_selector.set_prefer_low_latency(true);
+        let weights = relay_selector.relay_weights(&relays);
+        let scale = LATENCY_WEIGHT_SCALE as u64;
+        assert_eq!(weights[0], relays[0].weight * scale);
+        assert_eq!(weights[1], relays[1].weight * scale / 4);
+        // Relays without a measurement are treated as the slowest measured relay
+        assert_eq!(weights[2], relays[2].weight * scale / 4);
+
+        // Latencies are ignored when the seed is fixed, since they vary between runs
+        relay_selector.reproducible = true;
+        relay_selector.set_prefer_low_latency(true);
+        assert_eq!(relay_selector.relay_weights(&relays), unadjusted);
     }
 }
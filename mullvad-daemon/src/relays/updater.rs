@@ -12,9 +12,8 @@ use std::{
     sync::Arc,
     time::{Duration, Instant, SystemTime},
 };
-use talpid_core::future_retry::{retry_future, ExponentialBackoff, Jittered};
+use talpid_future::retry::{retry_future, ExponentialBackoff, Jittered};
 use talpid_types::ErrorExt;
-use tokio::fs::File;
 
 /// How often the updater should wake up to check the cache of the in-memory cache of relays.
 /// This check is very cheap. The only reason to not have it very often is because if downloading
@@ -198,14 +197,9 @@ impl RelayListUpdater {
     /// Write a `RelayList` to the cache file.
     async fn cache_relays(cache_path: &Path, relays: &RelayList) -> Result<(), Error> {
         log::debug!("Writing relays cache to {}", cache_path.display());
-        let mut file = File::create(cache_path)
-            .await
-            .map_err(Error::OpenRelayCache)?;
         let bytes = serde_json::to_vec_pretty(relays).map_err(Error::Serialize)?;
-        let mut slice: &[u8] = bytes.as_slice();
-        let _ = tokio::io::copy(&mut slice, &mut file)
+        mullvad_rpc::atomic_file::write(cache_path, bytes)
             .await
-            .map_err(Error::WriteRelayCache)?;
-        Ok(())
+            .map_err(Error::WriteRelayCache)
     }
 }
@@ -0,0 +1,48 @@
+//! Detects when the computer wakes up from sleep by comparing the wall clock to the monotonic
+//! clock, which does not advance while the computer is suspended.
+//!
+//! On Windows, suspend and resume are instead reported by the offline monitor in `talpid-core`.
+
+use crate::{DaemonEventSender, InternalDaemonEvent};
+use std::time::{Duration, Instant, SystemTime};
+use talpid_core::mpsc::Sender;
+
+/// How often the clocks are compared.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// How far the wall clock must get ahead of the monotonic clock between two polls for it to be
+/// considered a wake from sleep rather than a clock adjustment.
+const MIN_SLEEP_DURATION: Duration = Duration::from_secs(30);
+
+/// Spawns a task that sends `InternalDaemonEvent::Resumed` to the daemon whenever the computer
+/// has woken up from sleep. The task exits when the daemon stops listening.
+pub(crate) fn spawn(daemon_tx: DaemonEventSender) {
+    tokio::spawn(async move {
+        let mut last_instant = Instant::now();
+        let mut last_system_time = SystemTime::now();
+
+        while !daemon_tx.is_closed() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+
+            let instant = Instant::now();
+            let system_time = SystemTime::now();
+
+            let monotonic_elapsed = instant.duration_since(last_instant);
+            let wall_elapsed = system_time
+                .duration_since(last_system_time)
+                .unwrap_or(Duration::ZERO);
+
+            if wall_elapsed > monotonic_elapsed + MIN_SLEEP_DURATION {
+                log::info!(
+                    "Detected wake from sleep after approximately {}s",
+                    (wall_elapsed - monotonic_elapsed).as_secs()
+                );
+                if daemon_tx.send(InternalDaemonEvent::Resumed).is_err() {
+                    break;
+                }
+            }
+
+            last_instant = instant;
+            last_system_time = system_time;
+        }
+    });
+}
@@ -0,0 +1,110 @@
+//! Keeps each secret in its own file in a directory that only the daemon can read. On Windows,
+//! this relies on the settings directory being in the profile of the system account.
+
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+pub const STORAGE_NAME: &str = "a key file that only the daemon can read";
+
+/// Directory in the settings directory that holds one file per secret.
+const SECRETS_DIR: &str = "secrets";
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to create the secrets directory")]
+    CreateDir(#[error(source)] io::Error),
+
+    #[error(display = "Failed to read a secret")]
+    Read(#[error(source)] io::Error),
+
+    #[error(display = "Failed to write a secret")]
+    Write(#[error(source)] io::Error),
+
+    #[error(display = "Failed to delete a secret")]
+    Delete(#[error(source)] io::Error),
+}
+
+pub struct SecureStorage {
+    dir: PathBuf,
+}
+
+impl SecureStorage {
+    pub fn open(settings_dir: &Path) -> Result<Self, Error> {
+        let dir = settings_dir.join(SECRETS_DIR);
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        builder.mode(0o700);
+        builder.create(&dir).map_err(Error::CreateDir)?;
+        // The mode is only applied when the directory is created
+        #[cfg(unix)]
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).map_err(Error::CreateDir)?;
+        Ok(SecureStorage { dir })
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        match fs::read(self.dir.join(key)) {
+            Ok(value) => Ok(Some(value)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(Error::Read(error)),
+        }
+    }
+
+    /// Writes the secret to a new file that is then moved into place, so that a partially
+    /// written secret is never read.
+    pub fn set(&self, key: &str, value: &[u8]) -> Result<(), Error> {
+        let temp_path = self.dir.join(format!("{}.tmp", key));
+        // The mode is only applied when the file is created
+        let _ = fs::remove_file(&temp_path);
+        let mut options = fs::OpenOptions::new();
+        options.create_new(true).write(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&temp_path).map_err(Error::Write)?;
+        file.write_all(value)
+            .and_then(|()| file.sync_all())
+            .map_err(Error::Write)?;
+        fs::rename(&temp_path, self.dir.join(key)).map_err(Error::Write)
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), Error> {
+        match fs::remove_file(self.dir.join(key)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(Error::Delete(error)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_secret_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings_dir = dir.path();
+        let storage = SecureStorage::open(settings_dir).unwrap();
+
+        assert_eq!(storage.get("secret").unwrap(), None);
+        storage.set("secret", b"first").unwrap();
+        storage.set("secret", b"second").unwrap();
+        assert_eq!(storage.get("secret").unwrap(), Some(b"second".to_vec()));
+
+        #[cfg(unix)]
+        {
+            let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&settings_dir.join(SECRETS_DIR)), 0o700);
+            assert_eq!(mode(&settings_dir.join(SECRETS_DIR).join("secret")), 0o600);
+        }
+
+        storage.delete("secret").unwrap();
+        storage.delete("secret").unwrap();
+        assert_eq!(storage.get("secret").unwrap(), None);
+    }
+}
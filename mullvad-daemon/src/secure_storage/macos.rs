@@ -0,0 +1,48 @@
+use security_framework::{base, passwords};
+
+pub const STORAGE_NAME: &str = "the keychain";
+
+/// Service name of the generic passwords of this app.
+const SERVICE: &str = "net.mullvad.vpn";
+/// Returned when there is no matching keychain item.
+const ERR_SEC_ITEM_NOT_FOUND: i32 = -25300;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to read from the keychain")]
+    Read(#[error(source)] base::Error),
+
+    #[error(display = "Failed to write to the keychain")]
+    Write(#[error(source)] base::Error),
+
+    #[error(display = "Failed to delete from the keychain")]
+    Delete(#[error(source)] base::Error),
+}
+
+pub struct SecureStorage(());
+
+impl SecureStorage {
+    pub fn open() -> Result<Self, Error> {
+        Ok(SecureStorage(()))
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        match passwords::get_generic_password(SERVICE, key) {
+            Ok(value) => Ok(Some(value)),
+            Err(error) if error.code() == ERR_SEC_ITEM_NOT_FOUND => Ok(None),
+            Err(error) => Err(Error::Read(error)),
+        }
+    }
+
+    pub fn set(&self, key: &str, value: &[u8]) -> Result<(), Error> {
+        passwords::set_generic_password(SERVICE, key, value).map_err(Error::Write)
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), Error> {
+        match passwords::delete_generic_password(SERVICE, key) {
+            Err(error) if error.code() != ERR_SEC_ITEM_NOT_FOUND => Err(Error::Delete(error)),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Stores secrets in the credential store of the OS: the Windows Credential Manager or the macOS
+//! Keychain. Where there is no credential store that the daemon can reach, such as on Linux and
+//! Android, or if it cannot be used, secrets are kept in a key file that only the daemon can read.
+//!
+//! libsecret is not used on Linux. The daemon runs as root as a system service, so it has no
+//! session bus to reach a Secret Service provider on, and no user who could unlock a keyring.
+
+#[cfg(target_os = "macos")]
+#[path = "macos.rs"]
+mod imp;
+
+#[cfg(windows)]
+#[path = "windows.rs"]
+mod imp;
+
+mod file;
+
+use std::path::Path;
+use talpid_types::ErrorExt;
+
+/// Name of the secret that holds the secret settings, as a JSON object.
+const SETTINGS_SECRET: &str = "settings-secrets";
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[cfg(any(windows, target_os = "macos"))]
+    #[error(display = "Failed to access the credential store of the OS")]
+    CredentialStore(#[error(source)] imp::Error),
+
+    #[error(display = "Failed to access the key file")]
+    KeyFile(#[error(source)] file::Error),
+}
+
+pub struct SecureStorage {
+    #[cfg(any(windows, target_os = "macos"))]
+    credential_store: Option<imp::SecureStorage>,
+    /// Used if there is no credential store. Secrets that were written to it while the
+    /// credential store could not be used are removed when secrets are next written to the
+    /// credential store.
+    key_file: Option<file::SecureStorage>,
+}
+
+impl SecureStorage {
+    /// Opens the credential store of the OS, and the key file in `settings_dir`. Returns `None`
+    /// if neither can be used, in which case secrets have to be stored elsewhere.
+    pub fn open(settings_dir: &Path) -> Option<Self> {
+        #[cfg(any(windows, target_os = "macos"))]
+        let credential_store = match imp::SecureStorage::open() {
+            Ok(storage) => Some(storage),
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Unable to use the credential store of the OS. Using a key file instead"
+                    )
+                );
+                None
+            }
+        };
+        let key_file = match file::SecureStorage::open(settings_dir) {
+            Ok(storage) => Some(storage),
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to use the key file")
+                );
+                None
+            }
+        };
+
+        #[cfg(any(windows, target_os = "macos"))]
+        let has_credential_store = credential_store.is_some();
+        #[cfg(not(any(windows, target_os = "macos")))]
+        let has_credential_store = false;
+        if !has_credential_store && key_file.is_none() {
+            return None;
+        }
+
+        Some(SecureStorage {
+            #[cfg(any(windows, target_os = "macos"))]
+            credential_store,
+            key_file,
+        })
+    }
+
+    /// Describes where the secrets are stored, for logging.
+    pub fn name(&self) -> &'static str {
+        #[cfg(any(windows, target_os = "macos"))]
+        if self.credential_store.is_some() {
+            return imp::STORAGE_NAME;
+        }
+        file::STORAGE_NAME
+    }
+
+    /// Returns the stored settings secrets, if any.
+    pub fn get(&self) -> Result<Option<String>, Error> {
+        #[cfg(any(windows, target_os = "macos"))]
+        if let Some(storage) = &self.credential_store {
+            if let Some(secret) = storage
+                .get(SETTINGS_SECRET)
+                .map_err(Error::CredentialStore)?
+            {
+                return Ok(Some(String::from_utf8_lossy(&secret).into_owned()));
+            }
+        }
+        let secret = match &self.key_file {
+            Some(storage) => storage.get(SETTINGS_SECRET).map_err(Error::KeyFile)?,
+            None => None,
+        };
+        Ok(secret.map(|secret| String::from_utf8_lossy(&secret).into_owned()))
+    }
+
+    /// Replaces the stored settings secrets.
+    pub fn set(&self, secrets: &str) -> Result<(), Error> {
+        #[cfg(any(windows, target_os = "macos"))]
+        if let Some(storage) = &self.credential_store {
+            storage
+                .set(SETTINGS_SECRET, secrets.as_bytes())
+                .map_err(Error::CredentialStore)?;
+            return self.delete_key_file();
+        }
+        match &self.key_file {
+            Some(storage) => storage
+                .set(SETTINGS_SECRET, secrets.as_bytes())
+                .map_err(Error::KeyFile),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the stored settings secrets, if any.
+    pub fn delete(&self) -> Result<(), Error> {
+        #[cfg(any(windows, target_os = "macos"))]
+        if let Some(storage) = &self.credential_store {
+            storage
+                .delete(SETTINGS_SECRET)
+                .map_err(Error::CredentialStore)?;
+        }
+        self.delete_key_file()
+    }
+
+    fn delete_key_file(&self) -> Result<(), Error> {
+        match &self.key_file {
+            Some(storage) => storage.delete(SETTINGS_SECRET).map_err(Error::KeyFile),
+            None => Ok(()),
+        }
+    }
+}
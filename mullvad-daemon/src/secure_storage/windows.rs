@@ -0,0 +1,92 @@
+use std::{ffi::OsStr, io, iter, os::windows::ffi::OsStrExt, ptr, slice};
+use winapi::{
+    shared::{minwindef::DWORD, winerror::ERROR_NOT_FOUND},
+    um::wincred::{
+        CredDeleteW, CredFree, CredReadW, CredWriteW, CREDENTIALW, CRED_PERSIST_LOCAL_MACHINE,
+        CRED_TYPE_GENERIC, PCREDENTIALW,
+    },
+};
+
+pub const STORAGE_NAME: &str = "the Credential Manager";
+
+/// Prepended to keys to form the target names of the credentials.
+const TARGET_NAME_PREFIX: &str = "Mullvad VPN/";
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to read a credential")]
+    Read(#[error(source)] io::Error),
+
+    #[error(display = "Failed to write a credential")]
+    Write(#[error(source)] io::Error),
+
+    #[error(display = "Failed to delete a credential")]
+    Delete(#[error(source)] io::Error),
+}
+
+/// Stores generic credentials of the user that the daemon runs as, which is `LocalSystem`.
+pub struct SecureStorage(());
+
+impl SecureStorage {
+    pub fn open() -> Result<Self, Error> {
+        Ok(SecureStorage(()))
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let target_name = target_name(key);
+        let mut credential: PCREDENTIALW = ptr::null_mut();
+        if unsafe { CredReadW(target_name.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) } == 0 {
+            return match io::Error::last_os_error() {
+                error if is_not_found(&error) => Ok(None),
+                error => Err(Error::Read(error)),
+            };
+        }
+        let value = unsafe {
+            slice::from_raw_parts(
+                (*credential).CredentialBlob,
+                (*credential).CredentialBlobSize as usize,
+            )
+            .to_vec()
+        };
+        unsafe { CredFree(credential as *mut _) };
+        Ok(Some(value))
+    }
+
+    pub fn set(&self, key: &str, value: &[u8]) -> Result<(), Error> {
+        let mut target_name = target_name(key);
+        let mut value = value.to_vec();
+        let mut credential: CREDENTIALW = unsafe { std::mem::zeroed() };
+        credential.Type = CRED_TYPE_GENERIC;
+        credential.TargetName = target_name.as_mut_ptr();
+        credential.CredentialBlobSize = value.len() as DWORD;
+        credential.CredentialBlob = value.as_mut_ptr();
+        credential.Persist = CRED_PERSIST_LOCAL_MACHINE;
+        if unsafe { CredWriteW(&mut credential, 0) } == 0 {
+            return Err(Error::Write(io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), Error> {
+        let target_name = target_name(key);
+        if unsafe { CredDeleteW(target_name.as_ptr(), CRED_TYPE_GENERIC, 0) } == 0 {
+            let error = io::Error::last_os_error();
+            if !is_not_found(&error) {
+                return Err(Error::Delete(error));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn target_name(key: &str) -> Vec<u16> {
+    OsStr::new(&format!("{}{}", TARGET_NAME_PREFIX, key))
+        .encode_wide()
+        .chain(iter::once(0))
+        .collect()
+}
+
+fn is_not_found(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(ERROR_NOT_FOUND as i32)
+}
@@ -0,0 +1,231 @@
+//! Tracks which Windows session is attached to the physical console, and looks up the users of
+//! sessions, so that requests made while users are switched or connected over RDP can be
+//! attributed to the right user.
+
+use std::{fmt, mem, ptr, slice};
+use winapi::{
+    ctypes::c_void,
+    shared::{minwindef::ULONG, ntdef::LUID, ntstatus::STATUS_SUCCESS},
+    um::{
+        ntlsa::{
+            LsaEnumerateLogonSessions, LsaFreeReturnBuffer, LsaGetLogonSessionData,
+            SECURITY_LOGON_SESSION_DATA,
+        },
+        winbase::WTSGetActiveConsoleSessionId,
+    },
+};
+
+/// Returned by `WTSGetActiveConsoleSessionId` when no session is attached to the console.
+const NO_CONSOLE_SESSION: u32 = 0xFFFFFFFF;
+
+/// The kind of session change reported by the service control manager.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionChangeKind {
+    ConsoleConnect,
+    ConsoleDisconnect,
+    RemoteConnect,
+    RemoteDisconnect,
+    Logon,
+    Logoff,
+    Lock,
+    Unlock,
+}
+
+impl SessionChangeKind {
+    /// Returns whether a frontend running in the session may have missed daemon events, or is
+    /// reattaching to its desktop, and should therefore be sent the current state again.
+    pub fn should_resync_frontends(&self) -> bool {
+        match self {
+            SessionChangeKind::ConsoleConnect
+            | SessionChangeKind::RemoteConnect
+            | SessionChangeKind::Logon
+            | SessionChangeKind::Unlock => true,
+            SessionChangeKind::ConsoleDisconnect
+            | SessionChangeKind::RemoteDisconnect
+            | SessionChangeKind::Logoff
+            | SessionChangeKind::Lock => false,
+        }
+    }
+}
+
+/// A change to a user session, along with the user owning the session, if known.
+#[derive(Debug, Clone)]
+pub struct SessionChange {
+    pub kind: SessionChangeKind,
+    pub session_id: u32,
+    pub user: Option<String>,
+}
+
+/// The session currently attached to the physical console.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsoleSession {
+    pub session_id: u32,
+    pub user: Option<String>,
+}
+
+impl ConsoleSession {
+    /// Returns the session that is attached to the console right now, if any. The user name is
+    /// not known until a session change naming the user is received.
+    pub fn current() -> Option<Self> {
+        let session_id = unsafe { WTSGetActiveConsoleSessionId() };
+        if session_id == NO_CONSOLE_SESSION {
+            return None;
+        }
+        Some(ConsoleSession {
+            session_id,
+            user: None,
+        })
+    }
+
+    /// Updates the tracked console session in response to a session change.
+    /// Returns whether the console session changed.
+    pub fn update(current: &mut Option<ConsoleSession>, change: &SessionChange) -> bool {
+        let new_session = match change.kind {
+            SessionChangeKind::ConsoleConnect => Some(ConsoleSession {
+                session_id: change.session_id,
+                user: change.user.clone(),
+            }),
+            SessionChangeKind::ConsoleDisconnect | SessionChangeKind::Logoff
+                if current.as_ref().map(|session| session.session_id)
+                    == Some(change.session_id) =>
+            {
+                None
+            }
+            SessionChangeKind::Logon | SessionChangeKind::Unlock
+                if current.as_ref().map(|session| session.session_id)
+                    == Some(change.session_id) =>
+            {
+                Some(ConsoleSession {
+                    session_id: change.session_id,
+                    user: change.user.clone(),
+                })
+            }
+            _ => return false,
+        };
+        if *current == new_session {
+            return false;
+        }
+        *current = new_session;
+        true
+    }
+}
+
+impl fmt::Display for ConsoleSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "{} (session {})", user, self.session_id),
+            None => write!(f, "session {}", self.session_id),
+        }
+    }
+}
+
+const SECURITY_LOGON_TYPE_INTERACTIVE: u32 = 2;
+
+/// Information about the logon session of a Windows session.
+pub struct LogonSessionInfo {
+    /// Whether the user logged on interactively, as opposed to over the network or as a service.
+    pub interactive: bool,
+    pub user: String,
+}
+
+/// Returns information about the logon session belonging to `session_id`, if there is one.
+pub fn logon_session_info(session_id: u32) -> Option<LogonSessionInfo> {
+    let mut logon_session_count: ULONG = 0;
+    let mut logon_session_list: *mut LUID = ptr::null_mut();
+    let status =
+        unsafe { LsaEnumerateLogonSessions(&mut logon_session_count, &mut logon_session_list) };
+    if status != STATUS_SUCCESS {
+        log::warn!("LsaEnumerateLogonSessions() failed, error code: {}", status);
+        return None;
+    }
+    // SAFETY: `logon_session_list` is not mutated before being freed.
+    let logons = unsafe { slice::from_raw_parts(logon_session_list, logon_session_count as usize) };
+    let mut info = None;
+    for logon in logons {
+        let mut session_data: *mut SECURITY_LOGON_SESSION_DATA = ptr::null_mut();
+        // SAFETY: `LsaGetLogonSessionData` does not mutate `logon`
+        let status =
+            unsafe { LsaGetLogonSessionData(logon as *const _ as *mut _, &mut session_data) };
+        if status != STATUS_SUCCESS {
+            log::warn!("LsaGetLogonSessionData() failed, error code: {}", status);
+            continue;
+        }
+        let data = unsafe { &*session_data };
+        if data.Session == session_id {
+            // SAFETY: `UserName` is valid until `session_data` is freed. `Length` is in bytes.
+            let user_name = if data.UserName.Buffer.is_null() {
+                &[]
+            } else {
+                unsafe {
+                    slice::from_raw_parts(
+                        data.UserName.Buffer,
+                        usize::from(data.UserName.Length) / mem::size_of::<u16>(),
+                    )
+                }
+            };
+            info = Some(LogonSessionInfo {
+                interactive: data.LogonType == SECURITY_LOGON_TYPE_INTERACTIVE,
+                user: String::from_utf16_lossy(user_name),
+            });
+        }
+        unsafe { LsaFreeReturnBuffer(session_data as *mut c_void) };
+        if info.is_some() {
+            break;
+        }
+    }
+    mem::drop(logons);
+    unsafe { LsaFreeReturnBuffer(logon_session_list as *mut c_void) };
+    info
+}
+
+/// Describes the user of `session_id`, for attributing requests made from that session.
+pub fn describe_session(session_id: u32) -> String {
+    match logon_session_info(session_id) {
+        Some(info) => format!("{} (session {})", info.user, session_id),
+        None => format!("session {}", session_id),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn change(kind: SessionChangeKind, session_id: u32) -> SessionChange {
+        SessionChange {
+            kind,
+            session_id,
+            user: Some(format!("user{}", session_id)),
+        }
+    }
+
+    #[test]
+    fn test_fast_user_switch() {
+        let mut console = Some(ConsoleSession {
+            session_id: 1,
+            user: Some("user1".to_owned()),
+        });
+
+        assert!(ConsoleSession::update(
+            &mut console,
+            &change(SessionChangeKind::ConsoleDisconnect, 1)
+        ));
+        assert_eq!(console, None);
+
+        assert!(ConsoleSession::update(
+            &mut console,
+            &change(SessionChangeKind::ConsoleConnect, 2)
+        ));
+        assert_eq!(console.as_ref().map(|session| session.session_id), Some(2));
+
+        // Disconnecting an RDP session must not affect the console session
+        assert!(!ConsoleSession::update(
+            &mut console,
+            &change(SessionChangeKind::RemoteDisconnect, 3)
+        ));
+        assert!(!ConsoleSession::update(
+            &mut console,
+            &change(SessionChangeKind::Logoff, 1)
+        ));
+        assert_eq!(console.as_ref().map(|session| session.session_id), Some(2));
+    }
+}
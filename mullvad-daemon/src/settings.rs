@@ -1,23 +1,29 @@
+use crate::{feature_flags::Feature, secure_storage::SecureStorage};
 #[cfg(not(target_os = "android"))]
 use futures::TryFutureExt;
+use ipnetwork::IpNetwork;
 use mullvad_types::{
-    relay_constraints::{BridgeSettings, BridgeState, RelaySettingsUpdate},
-    settings::{DnsOptions, Settings},
+    relay_constraints::{BridgeSettings, BridgeState, RelayConstraints, RelaySettingsUpdate},
+    settings::{DnsOptions, LogPrivacy, Settings, TrustedNetworks},
     wireguard::{RotationInterval, WireguardData},
 };
+use serde_json::Value;
 #[cfg(target_os = "windows")]
 use std::collections::HashSet;
 use std::{
+    mem,
     ops::Deref,
     path::{Path, PathBuf},
 };
-use talpid_types::ErrorExt;
+use talpid_types::{firewall::BlockedStatePolicy, net::proxy::EntryProxy, ErrorExt};
 use tokio::{
     fs,
     io::{self, AsyncWriteExt},
 };
 
 const SETTINGS_FILE: &str = "settings.json";
+/// Settings that cannot be loaded are moved here instead of being overwritten by the defaults.
+const UNREADABLE_SETTINGS_FILE: &str = "settings-unreadable.json";
 
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
@@ -40,25 +46,105 @@ pub enum Error {
 
     #[error(display = "Unable to set settings file permissions")]
     SetPermissions(#[error(source)] io::Error),
+
+    #[error(display = "The settings patch is not a valid JSON object")]
+    InvalidPatch,
+
+    #[error(display = "The setting \"{}\" cannot be changed with a patch", _0)]
+    ProtectedSetting(&'static str),
+
+    #[error(display = "The patched settings are malformed")]
+    MalformedPatchedSettings(#[error(source)] serde_json::Error),
+
+    #[error(display = "The LAN allow list must not be empty")]
+    EmptyLanAllowList,
+
+    #[error(display = "{} is not a private network", _0)]
+    NonPrivateLanNetwork(IpNetwork),
+
+    #[error(display = "There is no feature flag named \"{}\"", _0)]
+    UnknownFeatureFlag(String),
+
+    #[error(display = "\"{}\" is not a valid interface name", _0)]
+    InvalidInterfaceName(String),
+
+    #[error(display = "The imported settings are not a valid JSON object")]
+    InvalidImport,
+
+    #[error(display = "The imported settings were exported by an incompatible app version")]
+    IncompatibleImport,
+
+    #[error(display = "The imported settings are malformed")]
+    MalformedImportedSettings(#[error(source)] serde_json::Error),
+
+    #[error(display = "Unable to read the settings secrets from the credential store")]
+    ReadSecrets(#[error(source)] crate::secure_storage::Error),
+
+    #[error(display = "The settings secrets in the credential store are malformed")]
+    MalformedSecrets(#[error(source)] serde_json::Error),
+}
+
+/// Top-level settings that are specific to this device, and that are thus left out of exported
+/// settings.
+const DEVICE_SETTINGS: &[&str] = &[
+    "account_token",
+    "wireguard",
+    // Excluded apps are paths on this device, and must be accepted by the split tunnel driver.
+    #[cfg(windows)]
+    "split_tunnel",
+];
+
+/// Top-level settings that are kept in the credential store of the OS, if it can be used, instead
+/// of the settings file.
+const SECRET_SETTINGS: &[&str] = &["account_token", "wireguard"];
+
+/// Top-level settings that are managed by the daemon, and that thus may not be patched.
+const PROTECTED_SETTINGS: &[&str] = &[
+    "account_token",
+    "wireguard",
+    "settings_version",
+    // Changing the excluded apps requires the split tunnel driver to accept them first.
+    #[cfg(windows)]
+    "split_tunnel",
+];
+
+/// Describes why the settings, or the secrets kept outside of them, could not be loaded at
+/// startup. The daemon then runs without them until the user changes the settings.
+#[derive(Debug, Clone)]
+pub struct LoadFailure {
+    /// Description of the error that prevented the settings from being loaded.
+    pub error: String,
+    /// Where the unreadable settings were moved, if they were moved.
+    pub backup_path: Option<PathBuf>,
 }
 
 #[derive(Debug)]
 pub struct SettingsPersister {
     settings: Settings,
     path: PathBuf,
+    load_failure: Option<LoadFailure>,
+    secure_storage: Option<SecureStorage>,
+    /// The secrets that are known to be in `secure_storage`, as a JSON object.
+    stored_secrets: Option<String>,
 }
 
 impl SettingsPersister {
-    /// Loads user settings from file. If it fails, it returns the defaults.
+    /// Loads user settings from file. If it fails, it returns the defaults. The unreadable file
+    /// is kept, and the failure is available from [`SettingsPersister::load_failure`].
     pub async fn load(settings_dir: &Path) -> Self {
         let path = settings_dir.join(SETTINGS_FILE);
+        let mut load_failure = None;
         let (mut settings, mut should_save) = match Self::load_from_file(&path).await {
             Ok(value) => value,
             Err(error) => {
-                log::warn!(
+                log::error!(
                     "{}",
                     error.display_chain_with_msg("Failed to load settings. Using defaults.")
                 );
+                load_failure = Some(LoadFailure {
+                    error: error.display_chain(),
+                    backup_path: Self::keep_unreadable_settings(&path, settings_dir).await,
+                });
                 (Settings::default(), true)
             }
         };
@@ -69,7 +155,50 @@ impl SettingsPersister {
                 Self::update_field(&mut settings.tunnel_options.generic.enable_ipv6, true);
         }
 
-        let mut persister = SettingsPersister { settings, path };
+        let mut secure_storage = SecureStorage::open(settings_dir);
+        let mut stored_secrets = None;
+        if let Some(storage) = &secure_storage {
+            if settings.get_account_token().is_some() || settings.get_wireguard().is_some() {
+                // Saving the settings moves the secrets out of the settings file
+                log::info!(
+                    "Moving the account number and WireGuard key to {}",
+                    storage.name()
+                );
+                should_save = true;
+            } else {
+                match Self::read_secrets(&settings, storage) {
+                    Ok(Some((with_secrets, secrets))) => {
+                        settings = with_secrets;
+                        stored_secrets = Some(secrets);
+                    }
+                    Ok(None) => (),
+                    Err(error) => {
+                        // Storing secrets now could overwrite the ones that could not be read
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg(
+                                "Failed to load secrets. Storing them in the settings file"
+                            )
+                        );
+                        if load_failure.is_none() {
+                            load_failure = Some(LoadFailure {
+                                error: error.display_chain(),
+                                backup_path: None,
+                            });
+                        }
+                        secure_storage = None;
+                    }
+                }
+            }
+        }
+
+        let mut persister = SettingsPersister {
+            settings,
+            path,
+            load_failure,
+            secure_storage,
+            stored_secrets,
+        };
 
         if should_save {
             if let Err(error) = persister.save().await {
@@ -83,6 +212,15 @@ impl SettingsPersister {
         persister
     }
 
+    /// Reads the settings from file without falling back to the defaults or writing anything.
+    pub async fn read(settings_dir: &Path) -> Result<Settings, Error> {
+        let path = settings_dir.join(SETTINGS_FILE);
+        let settings_bytes = fs::read(&path)
+            .await
+            .map_err(|error| Error::ReadError(path.display().to_string(), error))?;
+        Self::load_from_bytes(&settings_bytes)
+    }
+
     async fn load_from_file(path: &Path) -> Result<(Settings, bool), Error> {
         log::info!("Loading settings from {}", path.display());
 
@@ -104,11 +242,116 @@ impl SettingsPersister {
         serde_json::from_slice(bytes).map_err(Error::ParseError)
     }
 
+    /// Returns `settings` with the secrets in `storage` added, along with the stored secrets, or
+    /// `None` if there are no stored secrets.
+    fn read_secrets(
+        settings: &Settings,
+        storage: &SecureStorage,
+    ) -> Result<Option<(Settings, String)>, Error> {
+        let secrets = match storage.get().map_err(Error::ReadSecrets)? {
+            Some(secrets) => secrets,
+            None => return Ok(None),
+        };
+        let with_secrets = Self::with_secrets(settings, &secrets)?;
+        Ok(Some((with_secrets, secrets)))
+    }
+
+    /// Returns `settings` with the secret settings in the JSON object `secrets` added.
+    fn with_secrets(settings: &Settings, secrets: &str) -> Result<Settings, Error> {
+        let mut secrets: serde_json::Map<String, Value> =
+            serde_json::from_str(secrets).map_err(Error::MalformedSecrets)?;
+        let mut value = serde_json::to_value(settings).map_err(Error::SerializeError)?;
+        if let Some(object) = value.as_object_mut() {
+            for key in SECRET_SETTINGS {
+                if let Some(secret) = secrets.remove(*key) {
+                    object.insert(key.to_string(), secret);
+                }
+            }
+        }
+        serde_json::from_value(value).map_err(Error::MalformedSecrets)
+    }
+
+    /// Removes the secret settings from the serialized settings, and returns those that are set
+    /// as a JSON object.
+    fn take_secrets(value: &mut Value) -> Value {
+        let mut secrets = serde_json::Map::new();
+        if let Some(object) = value.as_object_mut() {
+            for key in SECRET_SETTINGS {
+                match object.remove(*key) {
+                    None | Some(Value::Null) => (),
+                    Some(secret) => {
+                        secrets.insert(key.to_string(), secret);
+                    }
+                }
+            }
+        }
+        Value::Object(secrets)
+    }
+
+    /// Writes `secrets` to the secure storage, unless it already holds them. The stored secrets
+    /// are removed if there are none.
+    fn store_secrets(&mut self, secrets: &Value) -> Result<(), crate::secure_storage::Error> {
+        let storage = match &self.secure_storage {
+            Some(storage) => storage,
+            None => return Ok(()),
+        };
+        let secrets = secrets.to_string();
+        if self.stored_secrets.as_ref() == Some(&secrets) {
+            return Ok(());
+        }
+        if secrets == "{}" {
+            storage.delete()?;
+        } else {
+            storage.set(&secrets)?;
+        }
+        self.stored_secrets = Some(secrets);
+        Ok(())
+    }
+
+    /// Moves settings that could not be loaded out of the way, so that saving the defaults does
+    /// not destroy them. Returns the new path of the file if it was moved.
+    async fn keep_unreadable_settings(path: &Path, settings_dir: &Path) -> Option<PathBuf> {
+        let backup_path = settings_dir.join(UNREADABLE_SETTINGS_FILE);
+        match fs::rename(path, &backup_path).await {
+            Ok(()) => {
+                log::info!("Moved unreadable settings to {}", backup_path.display());
+                Some(backup_path)
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to keep the unreadable settings")
+                );
+                None
+            }
+        }
+    }
+
+    /// Returns why the settings could not be loaded at startup, if they could not.
+    pub fn load_failure(&self) -> Option<&LoadFailure> {
+        self.load_failure.as_ref()
+    }
+
     /// Serializes the settings and saves them to the file it was loaded from.
     async fn save(&mut self) -> Result<(), Error> {
         log::debug!("Writing settings to {}", self.path.display());
 
-        let buffer = serde_json::to_string_pretty(&self.settings).map_err(Error::SerializeError)?;
+        let mut value = serde_json::to_value(&self.settings).map_err(Error::SerializeError)?;
+        if self.secure_storage.is_some() {
+            let secrets = Self::take_secrets(&mut value);
+            if let Err(error) = self.store_secrets(&secrets) {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to store secrets securely. Saving them in the settings file"
+                    )
+                );
+                self.stored_secrets = None;
+                value = serde_json::to_value(&self.settings).map_err(Error::SerializeError)?;
+            }
+        }
+
+        let buffer = serde_json::to_string_pretty(&value).map_err(Error::SerializeError)?;
         let mut options = fs::OpenOptions::new();
         #[cfg(unix)]
         {
@@ -153,6 +396,7 @@ impl SettingsPersister {
     #[cfg(not(target_os = "android"))]
     pub async fn reset(&mut self) -> Result<(), Error> {
         self.settings = Settings::default();
+        self.load_failure = None;
         let path = self.path.clone();
         self.save()
             .or_else(|e| async move {
@@ -172,6 +416,103 @@ impl SettingsPersister {
         self.settings.clone()
     }
 
+    /// Applies a JSON merge patch (RFC 7386) to the settings. Keys set to `null` are reset to
+    /// their defaults. The patched settings are validated as a whole, and nothing is changed
+    /// unless they are valid and could be saved.
+    pub async fn apply_patch(&mut self, patch: &str) -> Result<bool, Error> {
+        let mut patched = Self::patch_settings(&self.settings, patch)?;
+        remove_unknown_feature_flags(&mut patched);
+        validate(&patched)?;
+        if patched == self.settings {
+            return Ok(false);
+        }
+        let previous = mem::replace(&mut self.settings, patched);
+        if let Err(error) = self.save().await {
+            self.settings = previous;
+            return Err(error);
+        }
+        Ok(true)
+    }
+
+    fn patch_settings(settings: &Settings, patch: &str) -> Result<Settings, Error> {
+        let patch: Value = serde_json::from_str(patch).map_err(|_| Error::InvalidPatch)?;
+        let patch_object = patch.as_object().ok_or(Error::InvalidPatch)?;
+        if let Some(key) = PROTECTED_SETTINGS
+            .iter()
+            .find(|key| patch_object.contains_key(**key))
+        {
+            return Err(Error::ProtectedSetting(key));
+        }
+
+        let mut value = serde_json::to_value(settings).map_err(Error::SerializeError)?;
+        merge_patch(&mut value, patch);
+        serde_json::from_value(value).map_err(Error::MalformedPatchedSettings)
+    }
+
+    /// Serializes the settings so that they can be imported on another device. Settings that are
+    /// specific to this device, such as the account number and WireGuard key, are left out.
+    pub fn export(&self) -> Result<String, Error> {
+        let mut value = serde_json::to_value(&self.settings).map_err(Error::SerializeError)?;
+        if let Some(object) = value.as_object_mut() {
+            for key in DEVICE_SETTINGS {
+                object.remove(*key);
+            }
+        }
+        serde_json::to_string_pretty(&value).map_err(Error::SerializeError)
+    }
+
+    /// Replaces the settings with settings created by [`SettingsPersister::export`]. Settings
+    /// that are missing from `exported` are reset to their defaults, while settings that are
+    /// specific to this device are kept. Nothing is changed unless the imported settings are
+    /// valid and could be saved.
+    pub async fn import(&mut self, exported: &str) -> Result<bool, Error> {
+        let mut imported = Self::import_settings(&self.settings, exported)?;
+        remove_unknown_feature_flags(&mut imported);
+        validate(&imported)?;
+        if imported == self.settings {
+            return Ok(false);
+        }
+        let previous = mem::replace(&mut self.settings, imported);
+        if let Err(error) = self.save().await {
+            self.settings = previous;
+            return Err(error);
+        }
+        Ok(true)
+    }
+
+    fn import_settings(settings: &Settings, exported: &str) -> Result<Settings, Error> {
+        let exported: Value = serde_json::from_str(exported).map_err(|_| Error::InvalidImport)?;
+        let mut exported = match exported {
+            Value::Object(object) => object,
+            _ => return Err(Error::InvalidImport),
+        };
+        // The format of the settings is only known for the current version
+        let current_version =
+            serde_json::to_value(settings.get_settings_version()).map_err(Error::SerializeError)?;
+        if exported.remove("settings_version") != Some(current_version) {
+            return Err(Error::IncompatibleImport);
+        }
+        if let Some(key) = PROTECTED_SETTINGS
+            .iter()
+            .find(|key| exported.contains_key(**key))
+        {
+            return Err(Error::ProtectedSetting(key));
+        }
+
+        let mut value = serde_json::to_value(Settings::default()).map_err(Error::SerializeError)?;
+        let current = serde_json::to_value(settings).map_err(Error::SerializeError)?;
+        let object = value
+            .as_object_mut()
+            .expect("Settings are not serialized as an object");
+        for key in DEVICE_SETTINGS {
+            if let Some(device_value) = current.get(*key) {
+                object.insert((*key).to_owned(), device_value.clone());
+            }
+        }
+        object.extend(exported);
+        serde_json::from_value(value).map_err(Error::MalformedImportedSettings)
+    }
+
     /// Changes account number to the one given. Also saves the new settings to disk.
     /// The boolean in the Result indicates if the account token changed or not
     pub async fn set_account_token(
@@ -195,11 +536,46 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_relay_fallback_tiers(
+        &mut self,
+        relay_fallback_tiers: Vec<RelayConstraints>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.relay_fallback_tiers,
+            relay_fallback_tiers,
+        );
+        self.update(should_save).await
+    }
+
     pub async fn set_allow_lan(&mut self, allow_lan: bool) -> Result<bool, Error> {
         let should_save = Self::update_field(&mut self.settings.allow_lan, allow_lan);
         self.update(should_save).await
     }
 
+    pub async fn set_lan_allow_list(
+        &mut self,
+        lan_allow_list: Option<Vec<IpNetwork>>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.lan_allow_list, lan_allow_list);
+        self.update(should_save).await
+    }
+
+    pub async fn set_blocked_state_policy(
+        &mut self,
+        blocked_state_policy: BlockedStatePolicy,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.blocked_state_policy,
+            blocked_state_policy,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_log_privacy(&mut self, log_privacy: LogPrivacy) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.log_privacy, log_privacy);
+        self.update(should_save).await
+    }
+
     pub async fn set_block_when_disconnected(
         &mut self,
         block_when_disconnected: bool,
@@ -211,11 +587,93 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    #[cfg(windows)]
+    pub async fn set_allow_connection_sharing(
+        &mut self,
+        allow_connection_sharing: bool,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.allow_connection_sharing,
+            allow_connection_sharing,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_block_when_account_expired(
+        &mut self,
+        block_when_account_expired: bool,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.block_when_account_expired,
+            block_when_account_expired,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_strict_kill_switch(
+        &mut self,
+        strict_kill_switch: bool,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.strict_kill_switch, strict_kill_switch);
+        self.update(should_save).await
+    }
+
+    pub async fn set_prefer_low_latency(
+        &mut self,
+        prefer_low_latency: bool,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.prefer_low_latency, prefer_low_latency);
+        self.update(should_save).await
+    }
+
+    pub async fn set_use_system_proxy(&mut self, use_system_proxy: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.use_system_proxy, use_system_proxy);
+        self.update(should_save).await
+    }
+
+    /// Overrides the state of a feature flag, or restores its default state if `enabled` is
+    /// `None`.
+    pub async fn set_feature_flag(
+        &mut self,
+        name: &str,
+        enabled: Option<bool>,
+    ) -> Result<bool, Error> {
+        if Feature::from_name(name).is_none() {
+            return Err(Error::UnknownFeatureFlag(name.to_owned()));
+        }
+        let previous = match enabled {
+            Some(enabled) => self.settings.feature_flags.insert(name.to_owned(), enabled),
+            None => self.settings.feature_flags.remove(name),
+        };
+        self.update(previous != enabled).await
+    }
+
     pub async fn set_auto_connect(&mut self, auto_connect: bool) -> Result<bool, Error> {
         let should_save = Self::update_field(&mut self.settings.auto_connect, auto_connect);
         self.update(should_save).await
     }
 
+    pub async fn set_reconnect_on_network_change(
+        &mut self,
+        reconnect_on_network_change: bool,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.reconnect_on_network_change,
+            reconnect_on_network_change,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_trusted_networks(
+        &mut self,
+        trusted_networks: TrustedNetworks,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.trusted_networks, trusted_networks);
+        self.update(should_save).await
+    }
+
     pub async fn set_openvpn_mssfix(&mut self, openvpn_mssfix: Option<u16>) -> Result<bool, Error> {
         let should_save = Self::update_field(
             &mut self.settings.tunnel_options.openvpn.mssfix,
@@ -244,6 +702,26 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    #[cfg(any(target_os = "linux", windows))]
+    pub async fn set_wireguard_interface_name(
+        &mut self,
+        name: Option<String>,
+    ) -> Result<bool, Error> {
+        if let Some(name) = &name {
+            validate_interface_name(name)?;
+        }
+        let should_save = Self::update_field(
+            &mut self
+                .settings
+                .tunnel_options
+                .wireguard
+                .options
+                .interface_name,
+            name,
+        );
+        self.update(should_save).await
+    }
+
     pub async fn set_wireguard_rotation_interval(
         &mut self,
         interval: Option<RotationInterval>,
@@ -277,6 +755,24 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_entry_proxy(
+        &mut self,
+        entry_proxy: Option<EntryProxy>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.entry_proxy, entry_proxy);
+        self.update(should_save).await
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub async fn set_excluded_networks(
+        &mut self,
+        excluded_networks: Vec<IpNetwork>,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.excluded_networks, excluded_networks);
+        self.update(should_save).await
+    }
+
     #[cfg(windows)]
     pub async fn set_split_tunnel_apps(&mut self, paths: HashSet<PathBuf>) -> Result<bool, Error> {
         let should_save = paths != self.settings.split_tunnel.apps;
@@ -325,6 +821,68 @@ impl SettingsPersister {
     }
 }
 
+/// Merges `patch` into `target` as described by RFC 7386.
+fn merge_patch(target: &mut Value, patch: Value) {
+    match patch {
+        Value::Object(patch) => {
+            if !target.is_object() {
+                *target = Value::Object(Default::default());
+            }
+            let target = target.as_object_mut().unwrap();
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(&key);
+                } else {
+                    merge_patch(target.entry(key).or_insert(Value::Null), value);
+                }
+            }
+        }
+        patch => *target = patch,
+    }
+}
+
+/// Checks constraints that involve more than the types of the settings. This must pass before
+/// settings that were not set through the individual setters are applied.
+pub fn validate(settings: &Settings) -> Result<(), Error> {
+    if let Some(lan_allow_list) = &settings.lan_allow_list {
+        if lan_allow_list.is_empty() {
+            return Err(Error::EmptyLanAllowList);
+        }
+        if let Some(network) = lan_allow_list
+            .iter()
+            .find(|network| !talpid_core::firewall::is_private_network(network))
+        {
+            return Err(Error::NonPrivateLanNetwork(*network));
+        }
+    }
+    #[cfg(any(target_os = "linux", windows))]
+    if let Some(name) = &settings.tunnel_options.wireguard.options.interface_name {
+        validate_interface_name(name)?;
+    }
+    Ok(())
+}
+
+/// Removes feature flags that don't exist in this version or on this platform, such as flags in
+/// settings that were exported on another platform.
+fn remove_unknown_feature_flags(settings: &mut Settings) {
+    settings.feature_flags.retain(|name, _| {
+        let known = Feature::from_name(name).is_some();
+        if !known {
+            log::warn!("Ignoring unknown feature flag \"{}\"", name);
+        }
+        known
+    });
+}
+
+#[cfg(any(target_os = "linux", windows))]
+fn validate_interface_name(name: &str) -> Result<(), Error> {
+    if talpid_types::net::wireguard::is_valid_interface_name(name) {
+        Ok(())
+    } else {
+        Err(Error::InvalidInterfaceName(name.to_owned()))
+    }
+}
+
 impl Deref for SettingsPersister {
     type Target = Settings;
 
@@ -335,10 +893,146 @@ impl Deref for SettingsPersister {
 
 #[cfg(test)]
 mod test {
-    use super::SettingsPersister;
-    use mullvad_types::settings::SettingsVersion;
+    use super::{remove_unknown_feature_flags, Error, SettingsPersister};
+    use mullvad_types::settings::{Settings, SettingsVersion};
     use serde_json;
 
+    #[test]
+    fn test_patch_settings() {
+        let mut settings = Settings::default();
+        settings.allow_lan = true;
+        settings.auto_connect = true;
+        let patched = SettingsPersister::patch_settings(
+            &settings,
+            r#"{
+                "allow_lan": false,
+                "auto_connect": null,
+                "tunnel_options": { "generic": { "enable_ipv6": true } }
+            }"#,
+        )
+        .unwrap();
+
+        assert!(!patched.allow_lan);
+        assert_eq!(patched.auto_connect, Settings::default().auto_connect);
+        assert!(patched.tunnel_options.generic.enable_ipv6);
+        assert_eq!(patched.get_relay_settings(), settings.get_relay_settings());
+    }
+
+    #[test]
+    fn test_patch_settings_rejected() {
+        let settings = Settings::default();
+        assert!(matches!(
+            SettingsPersister::patch_settings(&settings, "[]"),
+            Err(Error::InvalidPatch)
+        ));
+        assert!(matches!(
+            SettingsPersister::patch_settings(&settings, r#"{ "account_token": "1234" }"#),
+            Err(Error::ProtectedSetting("account_token"))
+        ));
+        assert!(matches!(
+            SettingsPersister::patch_settings(&settings, r#"{ "allow_lan": "yes" }"#),
+            Err(Error::MalformedPatchedSettings(_))
+        ));
+    }
+
+    #[test]
+    fn test_export_import_settings() {
+        let mut settings = Settings::default();
+        settings.set_account_token(Some("1234123412341234".to_owned()));
+        settings.allow_lan = true;
+        settings.auto_connect = true;
+
+        let persister = SettingsPersister {
+            settings: settings.clone(),
+            path: Default::default(),
+            load_failure: None,
+            secure_storage: None,
+            stored_secrets: None,
+        };
+        let exported = persister.export().unwrap();
+        assert!(!exported.contains("account_token"));
+
+        let mut other_settings = Settings::default();
+        other_settings.set_account_token(Some("5678567856785678".to_owned()));
+        other_settings.block_when_disconnected = true;
+        let imported = SettingsPersister::import_settings(&other_settings, &exported).unwrap();
+
+        assert!(imported.allow_lan);
+        assert!(imported.auto_connect);
+        assert!(!imported.block_when_disconnected);
+        assert_eq!(
+            imported.get_account_token(),
+            Some("5678567856785678".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_remove_unknown_feature_flags() {
+        let mut settings = Settings::default();
+        settings
+            .feature_flags
+            .insert("low_latency_relay_selection".to_owned(), false);
+        settings
+            .feature_flags
+            .insert("not_a_feature".to_owned(), true);
+
+        remove_unknown_feature_flags(&mut settings);
+        assert_eq!(
+            settings.feature_flags.into_iter().collect::<Vec<_>>(),
+            vec![("low_latency_relay_selection".to_owned(), false)]
+        );
+    }
+
+    #[test]
+    fn test_import_settings_rejected() {
+        let settings = Settings::default();
+        assert!(matches!(
+            SettingsPersister::import_settings(&settings, "[]"),
+            Err(Error::InvalidImport)
+        ));
+        assert!(matches!(
+            SettingsPersister::import_settings(&settings, r#"{ "allow_lan": true }"#),
+            Err(Error::IncompatibleImport)
+        ));
+        assert!(matches!(
+            SettingsPersister::import_settings(
+                &settings,
+                r#"{ "settings_version": 2, "allow_lan": true }"#
+            ),
+            Err(Error::IncompatibleImport)
+        ));
+    }
+
+    #[test]
+    fn test_secrets_round_trip() {
+        let mut settings = Settings::default();
+        settings.set_account_token(Some("1234".to_owned()));
+        settings.allow_lan = true;
+
+        let mut value = serde_json::to_value(&settings).unwrap();
+        let secrets = SettingsPersister::take_secrets(&mut value);
+        assert_eq!(secrets, serde_json::json!({ "account_token": "1234" }));
+        assert!(value.get("account_token").is_none());
+        assert!(value.get("wireguard").is_none());
+
+        let without_secrets: Settings = serde_json::from_value(value).unwrap();
+        assert_eq!(without_secrets.get_account_token(), None);
+        assert!(without_secrets.allow_lan);
+
+        let with_secrets =
+            SettingsPersister::with_secrets(&without_secrets, &secrets.to_string()).unwrap();
+        assert_eq!(with_secrets, settings);
+
+        // Only secret settings are read from the credential store
+        let with_secrets =
+            SettingsPersister::with_secrets(&without_secrets, r#"{ "allow_lan": false }"#).unwrap();
+        assert!(with_secrets.allow_lan);
+        assert!(matches!(
+            SettingsPersister::with_secrets(&without_secrets, "[]"),
+            Err(Error::MalformedSecrets(_))
+        ));
+    }
+
     #[test]
     #[should_panic]
     fn test_deserialization_failure_version_too_small() {
@@ -1,25 +1,20 @@
 use crate::cli;
-use mullvad_daemon::{runtime::new_runtime_builder, DaemonShutdownHandle};
+use mullvad_daemon::{
+    runtime::new_runtime_builder,
+    session::{logon_session_info, SessionChange, SessionChangeKind},
+    DaemonSessionHandle, DaemonShutdownHandle,
+};
 use std::{
     env,
     ffi::{OsStr, OsString},
-    mem, ptr, slice,
     sync::{
         atomic::{AtomicBool, AtomicUsize, Ordering},
-        mpsc, Arc,
+        mpsc, Arc, Mutex,
     },
     thread,
     time::{Duration, Instant},
 };
 use talpid_types::ErrorExt;
-use winapi::{
-    ctypes::c_void,
-    shared::{minwindef::ULONG, ntdef::LUID, ntstatus::STATUS_SUCCESS},
-    um::ntlsa::{
-        LsaEnumerateLogonSessions, LsaFreeReturnBuffer, LsaGetLogonSessionData,
-        SECURITY_LOGON_SESSION_DATA,
-    },
-};
 use windows_service::{
     service::{
         PowerEventParam, Service, ServiceAccess, ServiceAction, ServiceActionType, ServiceControl,
@@ -41,6 +36,15 @@ const SERVICE_FAILURE_RESET_PERIOD: Duration = Duration::from_secs(60 * 15);
 
 const SERVICE_RESTART_TIMEOUT: Duration = Duration::from_secs(60 * 2);
 
+/// How long the service control manager is told to wait for the next status update while the
+/// service is starting or stopping.
+const PENDING_WAIT_HINT: Duration = Duration::from_secs(10);
+/// How often progress is reported while the service is starting or stopping.
+const PENDING_PROGRESS_INTERVAL: Duration = Duration::from_secs(3);
+/// Stop reporting progress after this long, so that the service control manager can detect a
+/// service that is stuck starting or stopping.
+const PENDING_MAX_DURATION: Duration = Duration::from_secs(60 * 2);
+
 lazy_static::lazy_static! {
     static ref SERVICE_ACCESS: ServiceAccess = ServiceAccess::QUERY_CONFIG
     | ServiceAccess::CHANGE_CONFIG
@@ -93,8 +97,12 @@ pub fn handle_service_main(_arguments: Vec<OsString>) {
     };
     let mut persistent_service_status = PersistentServiceStatus::new(status_handle);
     persistent_service_status
-        .set_pending_start(Duration::from_secs(1))
+        .set_pending_start(PENDING_WAIT_HINT)
         .unwrap();
+    start_progress_reporter(
+        persistent_service_status.clone(),
+        ServiceState::StartPending,
+    );
 
     let clean_shutdown = Arc::new(AtomicBool::new(false));
 
@@ -115,11 +123,13 @@ pub fn handle_service_main(_arguments: Vec<OsString>) {
     let result = runtime.block_on(crate::create_daemon(log_dir));
     let result = if let Ok(daemon) = result {
         let shutdown_handle = daemon.shutdown_handle();
+        let session_handle = daemon.session_handle();
 
         // Register monitor that translates `ServiceControl` to Daemon events
         start_event_monitor(
             persistent_service_status.clone(),
             shutdown_handle,
+            session_handle,
             event_rx,
             clean_shutdown.clone(),
         );
@@ -158,6 +168,7 @@ pub fn handle_service_main(_arguments: Vec<OsString>) {
 fn start_event_monitor(
     mut persistent_service_status: PersistentServiceStatus,
     shutdown_handle: DaemonShutdownHandle,
+    session_handle: DaemonSessionHandle,
     event_rx: mpsc::Receiver<ServiceControl>,
     clean_shutdown: Arc<AtomicBool>,
 ) -> thread::JoinHandle<()> {
@@ -167,11 +178,18 @@ fn start_event_monitor(
             match event {
                 ServiceControl::Stop | ServiceControl::Preshutdown => {
                     persistent_service_status
-                        .set_pending_stop(Duration::from_secs(10))
+                        .set_pending_stop(PENDING_WAIT_HINT)
                         .unwrap();
+                    start_progress_reporter(
+                        persistent_service_status.clone(),
+                        ServiceState::StopPending,
+                    );
 
                     clean_shutdown.store(true, Ordering::Release);
-                    shutdown_handle.shutdown();
+                    // A preshutdown means that the whole system is going down, so the daemon
+                    // should keep blocking traffic if the tunnel is supposed to be up
+                    let user_init_shutdown = !matches!(event, ServiceControl::Preshutdown);
+                    shutdown_handle.shutdown(user_init_shutdown);
                 }
                 ServiceControl::PowerEvent(details) => match details {
                     PowerEventParam::Suspend => {
@@ -183,8 +201,16 @@ fn start_event_monitor(
                     _ => (),
                 },
                 ServiceControl::SessionChange(details) => {
+                    let session_id = details.notification.session_id;
                     if details.reason == SessionChangeReason::SessionLogoff {
-                        hibernation_detector.register_logoff(details.notification.session_id);
+                        hibernation_detector.register_logoff(session_id);
+                    }
+                    if let Some(kind) = session_change_kind(details.reason) {
+                        session_handle.session_changed(SessionChange {
+                            kind,
+                            session_id,
+                            user: logon_session_info(session_id).map(|info| info.user),
+                        });
                     }
                 }
                 _ => (),
@@ -193,11 +219,57 @@ fn start_event_monitor(
     })
 }
 
+/// Start a thread that keeps telling the service control manager that the service is making
+/// progress, for as long as it remains in the pending state `pending_state`.
+fn start_progress_reporter(
+    mut persistent_service_status: PersistentServiceStatus,
+    pending_state: ServiceState,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let started = Instant::now();
+        loop {
+            thread::sleep(PENDING_PROGRESS_INTERVAL);
+            if started.elapsed() >= PENDING_MAX_DURATION {
+                log::warn!("Service is still in state {:?}", pending_state);
+                break;
+            }
+            match persistent_service_status.report_progress(pending_state, PENDING_WAIT_HINT) {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to report service progress")
+                    );
+                    break;
+                }
+            }
+        }
+    })
+}
+
+fn session_change_kind(reason: SessionChangeReason) -> Option<SessionChangeKind> {
+    match reason {
+        SessionChangeReason::ConsoleConnect => Some(SessionChangeKind::ConsoleConnect),
+        SessionChangeReason::ConsoleDisconnect => Some(SessionChangeKind::ConsoleDisconnect),
+        SessionChangeReason::RemoteConnect => Some(SessionChangeKind::RemoteConnect),
+        SessionChangeReason::RemoteDisconnect => Some(SessionChangeKind::RemoteDisconnect),
+        SessionChangeReason::SessionLogon => Some(SessionChangeKind::Logon),
+        SessionChangeReason::SessionLogoff => Some(SessionChangeKind::Logoff),
+        SessionChangeReason::SessionLock => Some(SessionChangeKind::Lock),
+        SessionChangeReason::SessionUnlock => Some(SessionChangeKind::Unlock),
+        _ => None,
+    }
+}
+
 /// Service status helper with persistent checkpoint counter.
 #[derive(Debug, Clone)]
 struct PersistentServiceStatus {
     status_handle: ServiceStatusHandle,
     checkpoint_counter: Arc<AtomicUsize>,
+    /// The last reported state. The lock is held while reporting so that updates are never
+    /// reordered.
+    current_state: Arc<Mutex<ServiceState>>,
 }
 
 impl PersistentServiceStatus {
@@ -205,6 +277,7 @@ impl PersistentServiceStatus {
         PersistentServiceStatus {
             status_handle,
             checkpoint_counter: Arc::new(AtomicUsize::new(1)),
+            current_state: Arc::new(Mutex::new(ServiceState::StartPending)),
         }
     }
 
@@ -242,12 +315,39 @@ impl PersistentServiceStatus {
         self.report_status(ServiceState::Stopped, Duration::default(), exit_code)
     }
 
+    /// Report that the service is still in `pending_state` and making progress. Returns `false`
+    /// without reporting anything if the service has moved on to another state.
+    fn report_progress(
+        &mut self,
+        pending_state: ServiceState,
+        wait_hint: Duration,
+    ) -> windows_service::Result<bool> {
+        let current_state = self.current_state.lock().unwrap();
+        if *current_state != pending_state {
+            return Ok(false);
+        }
+        self.set_service_status(pending_state, wait_hint, ServiceExitCode::default())?;
+        Ok(true)
+    }
+
     /// Private helper to report the service status update.
     fn report_status(
         &mut self,
         next_state: ServiceState,
         wait_hint: Duration,
         exit_code: ServiceExitCode,
+    ) -> windows_service::Result<()> {
+        let mut current_state = self.current_state.lock().unwrap();
+        self.set_service_status(next_state, wait_hint, exit_code)?;
+        *current_state = next_state;
+        Ok(())
+    }
+
+    fn set_service_status(
+        &mut self,
+        next_state: ServiceState,
+        wait_hint: Duration,
+        exit_code: ServiceExitCode,
     ) -> windows_service::Result<()> {
         // Automatically bump the checkpoint when updating the pending events to tell the system
         // that the service is making a progress in transition from pending to final state.
@@ -445,53 +545,18 @@ struct HibernationDetector {
     should_restart: bool,
 }
 
-const SECURITY_LOGON_TYPE_INTERACTIVE: u32 = 2;
-
 impl HibernationDetector {
     /// Register a session logoff.
     /// The logoff event is discarded unless the session was/is interactive.
     fn register_logoff(&mut self, session_id: u32) {
-        if Self::is_interactive_session(session_id) {
+        let interactive = logon_session_info(session_id)
+            .map(|info| info.interactive)
+            .unwrap_or(false);
+        if interactive {
             self.logoff_time = Some(Instant::now());
         }
     }
 
-    fn is_interactive_session(session_id: u32) -> bool {
-        let mut logon_session_count: ULONG = 0;
-        let mut logon_session_list: *mut LUID = ptr::null_mut();
-        let status =
-            unsafe { LsaEnumerateLogonSessions(&mut logon_session_count, &mut logon_session_list) };
-        if status != STATUS_SUCCESS {
-            log::warn!("LsaEnumerateLogonSessions() failed, error code: {}", status);
-            return false;
-        }
-        // SAFETY: `logon_session_list` is not mutated before being freed.
-        let logons =
-            unsafe { slice::from_raw_parts(logon_session_list, logon_session_count as usize) };
-        let mut interactive = false;
-        for logon in logons {
-            let mut session_data: *mut SECURITY_LOGON_SESSION_DATA = ptr::null_mut();
-            // SAFETY: `LsaGetLogonSessionData` does not mutate `logon`
-            let status =
-                unsafe { LsaGetLogonSessionData(logon as *const _ as *mut _, &mut session_data) };
-            if status != STATUS_SUCCESS {
-                log::warn!("LsaGetLogonSessionData() failed, error code: {}", status);
-                continue;
-            }
-            let candidate_correct_session = unsafe { *session_data }.Session == session_id;
-            let candidate_interactive =
-                unsafe { *session_data }.LogonType == SECURITY_LOGON_TYPE_INTERACTIVE;
-            unsafe { LsaFreeReturnBuffer(session_data as *mut c_void) };
-            if candidate_correct_session {
-                interactive = candidate_interactive;
-                break;
-            }
-        }
-        mem::drop(logons);
-        unsafe { LsaFreeReturnBuffer(logon_session_list as *mut c_void) };
-        interactive
-    }
-
     /// Register a machine suspend event.
     fn register_suspend(&mut self) {
         if let Some(logoff_time) = &self.logoff_time {
@@ -1,4 +1,5 @@
 use mullvad_types::states::TargetState;
+use serde::{Deserialize, Serialize};
 use std::{
     ops::Deref,
     path::{Path, PathBuf},
@@ -13,24 +14,44 @@ const TARGET_START_STATE_FILE: &str = "target-start-state.json";
 /// Persists the target state to a file, which is only removed if the instance is dropped cleanly.
 pub struct PersistentTargetState {
     state: TargetState,
+    disconnected_on_trusted_network: bool,
     cache_path: PathBuf,
     locked: bool,
 }
 
+/// Contents of the cache file. Caches written by older versions only contain the target state.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum CachedTargetState {
+    Full {
+        state: TargetState,
+        #[serde(default)]
+        disconnected_on_trusted_network: bool,
+    },
+    StateOnly(TargetState),
+}
+
 impl PersistentTargetState {
     /// Initialize using the current target state (if there is one)
     pub async fn new(cache_dir: &Path) -> Self {
         let cache_path = cache_dir.join(TARGET_START_STATE_FILE);
         let mut update_cache = false;
-        let state = match fs::read_to_string(&cache_path).await {
+        let (state, disconnected_on_trusted_network) = match fs::read_to_string(&cache_path).await {
             Ok(content) => serde_json::from_str(&content)
-                .map(|state| {
+                .map(|cached| {
+                    let (state, disconnected_on_trusted_network) = match cached {
+                        CachedTargetState::Full {
+                            state,
+                            disconnected_on_trusted_network,
+                        } => (state, disconnected_on_trusted_network),
+                        CachedTargetState::StateOnly(state) => (state, false),
+                    };
                     log::info!(
                         "Loaded cached target state \"{}\" from {}",
                         state,
                         cache_path.display()
                     );
-                    state
+                    (state, disconnected_on_trusted_network)
                 })
                 .unwrap_or_else(|error| {
                     log::error!(
@@ -38,24 +59,25 @@ impl PersistentTargetState {
                         error.display_chain_with_msg("Failed to parse cached target tunnel state")
                     );
                     update_cache = true;
-                    TargetState::Secured
+                    (TargetState::Secured, false)
                 }),
             Err(error) => {
                 if error.kind() == io::ErrorKind::NotFound {
                     log::debug!("No cached target state to load");
-                    DEFAULT_TARGET_STATE
+                    (DEFAULT_TARGET_STATE, false)
                 } else {
                     log::error!(
                         "{}",
                         error.display_chain_with_msg("Failed to read cached target tunnel state")
                     );
                     update_cache = true;
-                    TargetState::Secured
+                    (TargetState::Secured, false)
                 }
             }
         };
         let state = PersistentTargetState {
             state,
+            disconnected_on_trusted_network,
             cache_path,
             locked: false,
         };
@@ -70,6 +92,7 @@ impl PersistentTargetState {
         let cache_path = cache_dir.join(TARGET_START_STATE_FILE);
         let state = PersistentTargetState {
             state,
+            disconnected_on_trusted_network: false,
             cache_path,
             locked: false,
         };
@@ -84,6 +107,20 @@ impl PersistentTargetState {
         }
     }
 
+    /// Returns whether the tunnel was disconnected because a trusted network was joined, and
+    /// should be connected again when it is left.
+    #[cfg_attr(not(any(target_os = "linux", target_os = "macos")), allow(dead_code))]
+    pub fn disconnected_on_trusted_network(&self) -> bool {
+        self.disconnected_on_trusted_network
+    }
+
+    pub async fn set_disconnected_on_trusted_network(&mut self, disconnected: bool) {
+        if disconnected != self.disconnected_on_trusted_network {
+            self.disconnected_on_trusted_network = disconnected;
+            self.save().await;
+        }
+    }
+
     /// Prevent the file from being removed when the instance is dropped.
     pub fn lock(&mut self) {
         self.locked = true;
@@ -109,9 +146,13 @@ impl PersistentTargetState {
             "Saving tunnel target state to {}",
             self.cache_path.display()
         );
-        match serde_json::to_string(&self.state) {
+        let cached = CachedTargetState::Full {
+            state: self.state,
+            disconnected_on_trusted_network: self.disconnected_on_trusted_network,
+        };
+        match serde_json::to_string(&cached) {
             Ok(data) => {
-                if let Err(error) = fs::write(&self.cache_path, data).await {
+                if let Err(error) = mullvad_rpc::atomic_file::write(&self.cache_path, data).await {
                     log::error!(
                         "{}",
                         error.display_chain_with_msg("Failed to write cache target state")
@@ -149,3 +190,29 @@ impl Deref for PersistentTargetState {
         &self.state
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_cached_state() {
+        let cached: CachedTargetState = serde_json::from_str("\"secured\"").unwrap();
+        assert!(matches!(
+            cached,
+            CachedTargetState::StateOnly(TargetState::Secured)
+        ));
+
+        let cached: CachedTargetState = serde_json::from_str(
+            r#"{"state": "unsecured", "disconnected_on_trusted_network": true}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            cached,
+            CachedTargetState::Full {
+                state: TargetState::Unsecured,
+                disconnected_on_trusted_network: true,
+            }
+        ));
+    }
+}
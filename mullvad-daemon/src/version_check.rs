@@ -18,7 +18,7 @@ use std::{
 };
 use talpid_core::mpsc::Sender;
 use talpid_types::ErrorExt;
-use tokio::fs::{self, File};
+use tokio::fs;
 
 const VERSION_INFO_FILENAME: &str = "version-info.json";
 
@@ -202,7 +202,7 @@ impl VersionUpdater {
                 .map_err(Error::Download)
         };
 
-        Box::pin(talpid_core::future_retry::retry_future_n(
+        Box::pin(talpid_future::retry::retry_future_n(
             download_future_factory,
             move |result| Self::should_retry_immediate(result, &api_handle),
             std::iter::repeat(IMMEDIATE_UPDATE_INTERVAL_ERROR),
@@ -243,7 +243,7 @@ impl VersionUpdater {
             }
         };
 
-        Box::pin(talpid_core::future_retry::retry_future(
+        Box::pin(talpid_future::retry::retry_future(
             download_future_factory,
             |result| result.is_err(),
             std::iter::repeat(UPDATE_INTERVAL_ERROR),
@@ -262,17 +262,11 @@ impl VersionUpdater {
             "Writing version check cache to {}",
             self.cache_path.display()
         );
-        let mut file = File::create(&self.cache_path)
-            .await
-            .map_err(Error::WriteVersionCache)?;
         let cached_app_version = CachedAppVersionInfo::from(last_app_version_info.clone());
-        let mut buf = serde_json::to_vec_pretty(&cached_app_version).map_err(Error::Serialize)?;
-        let mut read_buf: &[u8] = buf.as_mut();
-
-        let _ = tokio::io::copy(&mut read_buf, &mut file)
+        let buf = serde_json::to_vec_pretty(&cached_app_version).map_err(Error::Serialize)?;
+        mullvad_rpc::atomic_file::write(&self.cache_path, buf)
             .await
-            .map_err(Error::WriteVersionCache)?;
-        Ok(())
+            .map_err(Error::WriteVersionCache)
     }
 
     fn response_to_version_info(
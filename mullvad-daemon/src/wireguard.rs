@@ -9,11 +9,9 @@ pub use mullvad_types::wireguard::*;
 use std::{future::Future, pin::Pin, time::Duration};
 
 use futures::future::{abortable, AbortHandle};
-#[cfg(not(target_os = "android"))]
-use talpid_core::future_retry::constant_interval;
-use talpid_core::{
-    future_retry::{retry_future, retry_future_n, ExponentialBackoff, Jittered},
-    mpsc::Sender,
+use talpid_core::mpsc::Sender;
+use talpid_future::retry::{
+    constant_interval, retry_future, retry_future_n, ExponentialBackoff, Jittered,
 };
 
 pub use talpid_types::net::wireguard::{
@@ -32,7 +30,6 @@ const RETRY_INTERVAL_INITIAL: Duration = Duration::from_secs(4);
 const RETRY_INTERVAL_FACTOR: u32 = 5;
 const RETRY_INTERVAL_MAX: Duration = Duration::from_secs(24 * 60 * 60);
 
-#[cfg(not(target_os = "android"))]
 const SHORT_RETRY_INTERVAL: Duration = Duration::ZERO;
 
 const MAX_KEY_REMOVAL_RETRIES: usize = 2;
@@ -143,8 +140,20 @@ impl KeyManager {
         }
     }
 
+    /// Lists the keys that are registered on an account.
+    pub fn list_keys(
+        &self,
+        account: AccountToken,
+    ) -> impl Future<Output = Result<Vec<RegisteredKey>>> {
+        let mut rpc = mullvad_rpc::WireguardKeyProxy::new(self.http_handle.clone());
+        async move {
+            rpc.list_wireguard_keys(account)
+                .await
+                .map_err(Self::map_rpc_error)
+        }
+    }
+
     /// Removes a key from an account
-    #[cfg(not(target_os = "android"))]
     pub fn remove_key(
         &self,
         account: AccountToken,
@@ -489,7 +498,8 @@ impl KeyManager {
         self.abort_scheduler_tx = Some(abort_handle);
     }
 
-    fn stop_automatic_rotation(&mut self) {
+    /// Stop the automatic key rotation job, if it is running.
+    pub fn stop_automatic_rotation(&mut self) {
         if let Some(abort_handle) = self.abort_scheduler_tx.take() {
             log::info!("Stopping automatic key rotation");
             abort_handle.abort();
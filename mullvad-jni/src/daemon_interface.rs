@@ -1,7 +1,9 @@
 use futures::{channel::oneshot, executor::block_on};
 use mullvad_daemon::{DaemonCommand, DaemonCommandSender};
 use mullvad_types::{
-    account::{AccountData, AccountToken, VoucherSubmission},
+    account::{
+        AccountData, AccountToken, PlayPurchase, PlayPurchasePaymentToken, VoucherSubmission,
+    },
     location::GeoIpLocation,
     relay_constraints::RelaySettingsUpdate,
     relay_list::RelayList,
@@ -167,6 +169,26 @@ impl DaemonInterface {
             .ok_or(Error::NoResponse)
     }
 
+    pub fn init_play_purchase(&self) -> Result<PlayPurchasePaymentToken> {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_command(DaemonCommand::InitPlayPurchase(tx))?;
+
+        block_on(rx)
+            .map_err(|_| Error::NoResponse)?
+            .map_err(Error::from)
+    }
+
+    pub fn verify_play_purchase(&self, play_purchase: PlayPurchase) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_command(DaemonCommand::VerifyPlayPurchase(tx, play_purchase))?;
+
+        block_on(rx)
+            .map_err(|_| Error::NoResponse)?
+            .map_err(Error::from)
+    }
+
     pub fn reconnect(&self) -> Result<()> {
         let (tx, _) = oneshot::channel();
 
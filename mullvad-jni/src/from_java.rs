@@ -121,6 +121,7 @@ impl<'env> FromJava<'env> for RelayConstraintsUpdate {
 
         RelayConstraintsUpdate {
             location: FromJava::from_java(env, location),
+            ownership: None,
             tunnel_protocol: None,
             openvpn_constraints: None,
             wireguard_constraints: None,
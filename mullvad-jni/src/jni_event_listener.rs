@@ -7,8 +7,8 @@ use jnix::{
 };
 use mullvad_daemon::EventListener;
 use mullvad_types::{
-    relay_list::RelayList, settings::Settings, states::TunnelState, version::AppVersionInfo,
-    wireguard::KeygenEvent,
+    account::AccountExpiryEvent, relay_list::RelayList, settings::Settings, states::TunnelState,
+    version::AppVersionInfo, wireguard::KeygenEvent,
 };
 use std::{sync::mpsc, thread};
 use talpid_types::ErrorExt;
@@ -27,6 +27,7 @@ pub enum Error {
 }
 
 enum Event {
+    AccountExpiry(AccountExpiryEvent),
     KeygenEvent(KeygenEvent),
     RelayList(RelayList),
     Settings(Settings),
@@ -63,11 +64,16 @@ impl EventListener for JniEventListener {
     fn notify_app_version(&self, app_version_info: AppVersionInfo) {
         let _ = self.0.send(Event::AppVersionInfo(app_version_info));
     }
+
+    fn notify_account_expiry(&self, event: AccountExpiryEvent) {
+        let _ = self.0.send(Event::AccountExpiry(event));
+    }
 }
 
 struct JniEventHandler<'env> {
     env: JnixEnv<'env>,
     mullvad_ipc_client: JObject<'env>,
+    notify_account_expiry_event: JMethodID<'env>,
     notify_app_version_info_event: JMethodID<'env>,
     notify_keygen_event: JMethodID<'env>,
     notify_relay_list_event: JMethodID<'env>,
@@ -117,6 +123,12 @@ impl<'env> JniEventHandler<'env> {
         events: mpsc::Receiver<Event>,
     ) -> Result<Self, Error> {
         let class = env.get_class("net/mullvad/mullvadvpn/service/MullvadDaemon");
+        let notify_account_expiry_event = Self::get_method_id(
+            &env,
+            &class,
+            "notifyAccountExpiryEvent",
+            "(Lnet/mullvad/mullvadvpn/model/AccountExpiryEvent;)V",
+        )?;
         let notify_app_version_info_event = Self::get_method_id(
             &env,
             &class,
@@ -151,6 +163,7 @@ impl<'env> JniEventHandler<'env> {
         Ok(JniEventHandler {
             env,
             mullvad_ipc_client,
+            notify_account_expiry_event,
             notify_app_version_info_event,
             notify_keygen_event,
             notify_relay_list_event,
@@ -173,6 +186,9 @@ impl<'env> JniEventHandler<'env> {
     fn run(&mut self) {
         while let Ok(event) = self.events.recv() {
             match event {
+                Event::AccountExpiry(expiry_event) => {
+                    self.handle_account_expiry_event(expiry_event)
+                }
                 Event::KeygenEvent(keygen_event) => self.handle_keygen_event(keygen_event),
                 Event::RelayList(relay_list) => self.handle_relay_list_event(relay_list),
                 Event::Settings(settings) => self.handle_settings(settings),
@@ -184,6 +200,26 @@ impl<'env> JniEventHandler<'env> {
         }
     }
 
+    fn handle_account_expiry_event(&self, event: AccountExpiryEvent) {
+        let java_account_expiry_event = event.into_java(&self.env);
+
+        let result = self.env.call_method_unchecked(
+            self.mullvad_ipc_client,
+            self.notify_account_expiry_event,
+            JavaType::Primitive(Primitive::Void),
+            &[JValue::Object(java_account_expiry_event.as_obj())],
+        );
+
+        if let Err(error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg(
+                    "Failed to call MullvadDaemon.notifyAccountExpiryEvent"
+                )
+            );
+        }
+    }
+
     fn handle_keygen_event(&self, event: KeygenEvent) {
         let java_keygen_event = event.into_java(&self.env);
 
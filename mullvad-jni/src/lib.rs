@@ -23,7 +23,7 @@ use mullvad_daemon::{
 };
 use mullvad_rpc::{rest::Error as RestError, StatusCode};
 use mullvad_types::{
-    account::{AccountData, VoucherSubmission},
+    account::{AccountData, PlayPurchase, VoucherSubmission},
     settings::DnsOptions,
 };
 use std::{
@@ -172,8 +172,14 @@ fn start_logging(log_dir: &Path) -> Result<(), String> {
 fn initialize_logging(log_dir: &Path) -> Result<(), String> {
     let log_file = log_dir.join(LOG_FILENAME);
 
-    logging::init_logger(log::LevelFilter::Debug, Some(&log_file), true)
-        .map_err(|error| error.display_chain_with_msg("Failed to start logger"))?;
+    logging::init_logger(
+        log::LevelFilter::Debug,
+        Some(&log_file),
+        true,
+        logging::LogFormat::Text,
+        Default::default(),
+    )
+    .map_err(|error| error.display_chain_with_msg("Failed to start logger"))?;
     exception_logging::enable();
     log_panics::init();
 
@@ -890,6 +896,57 @@ pub extern "system" fn Java_net_mullvad_mullvadvpn_service_MullvadDaemon_shutdow
     }
 }
 
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_mullvad_mullvadvpn_service_MullvadDaemon_initPlayPurchase<'env>(
+    env: JNIEnv<'env>,
+    _: JObject<'_>,
+    daemon_interface_address: jlong,
+) -> JObject<'env> {
+    let env = JnixEnv::from(env);
+
+    if let Some(daemon_interface) = get_daemon_interface(daemon_interface_address) {
+        match daemon_interface.init_play_purchase() {
+            Ok(token) => token.into_java(&env).forget(),
+            Err(error) => {
+                log_request_error("initialize Google Play purchase", &error);
+                JObject::null()
+            }
+        }
+    } else {
+        JObject::null()
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_net_mullvad_mullvadvpn_service_MullvadDaemon_verifyPlayPurchase(
+    env: JNIEnv<'_>,
+    _: JObject<'_>,
+    daemon_interface_address: jlong,
+    productId: JString<'_>,
+    purchaseToken: JString<'_>,
+) -> jboolean {
+    let env = JnixEnv::from(env);
+
+    if let Some(daemon_interface) = get_daemon_interface(daemon_interface_address) {
+        let play_purchase = PlayPurchase {
+            product_id: String::from_java(&env, productId),
+            purchase_token: String::from_java(&env, purchaseToken),
+        };
+
+        match daemon_interface.verify_play_purchase(play_purchase) {
+            Ok(()) => true as jboolean,
+            Err(error) => {
+                log_request_error("verify Google Play purchase", &error);
+                false as jboolean
+            }
+        }
+    } else {
+        false as jboolean
+    }
+}
+
 #[no_mangle]
 #[allow(non_snake_case)]
 pub extern "system" fn Java_net_mullvad_mullvadvpn_service_MullvadDaemon_submitVoucher<'env>(
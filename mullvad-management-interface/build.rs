@@ -1,5 +1,84 @@
+/// Messages that make up the tunnel state. These can be serialized, so that clients can output the
+/// state in a machine-readable format.
+const SERIALIZABLE_TYPES: &[&str] = &[
+    ".mullvad_daemon.management_interface.TunnelState",
+    ".mullvad_daemon.management_interface.TunnelStateRelayInfo",
+    ".mullvad_daemon.management_interface.TunnelEndpoint",
+    ".mullvad_daemon.management_interface.Endpoint",
+    ".mullvad_daemon.management_interface.ProxyEndpoint",
+    ".mullvad_daemon.management_interface.GeoIpLocation",
+    ".mullvad_daemon.management_interface.ErrorState",
+    ".mullvad_daemon.management_interface.TransportProtocol",
+    ".mullvad_daemon.management_interface.TunnelType",
+    ".mullvad_daemon.management_interface.ObfuscationType",
+    ".mullvad_daemon.management_interface.ProxyType",
+    ".mullvad_daemon.management_interface.AfterDisconnect",
+];
+
+/// Enum fields of the serializable messages, and the function in `types::enum_field` that
+/// serializes them. Prost stores enum fields as `i32`, so they would otherwise be serialized as
+/// numbers.
+const ENUM_FIELDS: &[(&str, &str)] = &[
+    (
+        ".mullvad_daemon.management_interface.TunnelState.Disconnecting.after_disconnect",
+        "after_disconnect",
+    ),
+    (
+        ".mullvad_daemon.management_interface.TunnelEndpoint.protocol",
+        "transport_protocol",
+    ),
+    (
+        ".mullvad_daemon.management_interface.TunnelEndpoint.tunnel_type",
+        "tunnel_type",
+    ),
+    (
+        ".mullvad_daemon.management_interface.TunnelEndpoint.obfuscation",
+        "obfuscation_type",
+    ),
+    (
+        ".mullvad_daemon.management_interface.Endpoint.protocol",
+        "transport_protocol",
+    ),
+    (
+        ".mullvad_daemon.management_interface.ProxyEndpoint.protocol",
+        "transport_protocol",
+    ),
+    (
+        ".mullvad_daemon.management_interface.ProxyEndpoint.proxy_type",
+        "proxy_type",
+    ),
+    (
+        ".mullvad_daemon.management_interface.ErrorState.cause",
+        "error_state_cause",
+    ),
+    (
+        ".mullvad_daemon.management_interface.ErrorState.parameter_error",
+        "generation_error",
+    ),
+    (
+        ".mullvad_daemon.management_interface.ErrorState.FirewallPolicyError.type",
+        "firewall_policy_error_type",
+    ),
+];
+
 fn main() {
     const PROTO_FILE: &str = "proto/management_interface.proto";
-    tonic_build::compile_protos(PROTO_FILE).unwrap();
+    let mut builder = tonic_build::configure();
+    for path in SERIALIZABLE_TYPES {
+        builder = builder.type_attribute(
+            path,
+            "#[derive(serde::Serialize)] #[serde(rename_all = \"snake_case\")]",
+        );
+    }
+    for (path, serializer) in ENUM_FIELDS {
+        builder = builder.field_attribute(
+            path,
+            format!(
+                "#[serde(serialize_with = \"crate::types::enum_field::{}\")]",
+                serializer
+            ),
+        );
+    }
+    builder.compile(&[PROTO_FILE], &["proto"]).unwrap();
     println!("cargo:rerun-if-changed={}", PROTO_FILE);
 }
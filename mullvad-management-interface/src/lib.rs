@@ -1,11 +1,30 @@
+//! gRPC interface used to control the Mullvad VPN daemon. The service is defined in
+//! `proto/management_interface.proto`, and this crate contains the generated client and server
+//! along with conversions between the protobuf types and the types used by the daemon.
+//!
+//! Frontends connect with [`new_rpc_client`]. Changes to the tunnel state, settings and relay list
+//! are pushed to clients that subscribe with `events_listen`, which returns a stream of
+//! [`types::DaemonEvent`]s. `events_listen_filtered` limits the stream to some categories of
+//! events and can start it with the current value of each. Account expiry events are only sent
+//! to clients that subscribe with `events_listen_filtered`.
+//!
+//! On Linux, the server can be socket activated by systemd. If a listening socket is passed to the
+//! daemon, it is used instead of creating one at the socket path.
+
 pub mod types;
+#[cfg(windows)]
+mod windows;
 
+use futures::Stream;
 use parity_tokio_ipc::Endpoint as IpcEndpoint;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{FromRawFd, RawFd};
 #[cfg(unix)]
 use std::{env, fs, os::unix::fs::PermissionsExt};
 use std::{
     future::Future,
     io,
+    path::PathBuf,
     pin::Pin,
     task::{Context, Poll},
 };
@@ -25,6 +44,10 @@ lazy_static::lazy_static! {
         .ok();
 }
 
+/// The first file descriptor passed by systemd socket activation.
+#[cfg(target_os = "linux")]
+const SD_LISTEN_FDS_START: RawFd = 3;
+
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
 pub enum Error {
@@ -53,9 +76,15 @@ pub enum Error {
     SetGidError(#[error(source)] nix::Error),
 }
 
+/// Connects to the daemon over the management interface socket. The path of the socket can be
+/// overridden with `MULLVAD_RPC_SOCKET_PATH`.
 pub async fn new_rpc_client() -> Result<ManagementServiceClient, Error> {
-    let ipc_path = mullvad_paths::get_rpc_socket_path();
+    new_rpc_client_at(mullvad_paths::get_rpc_socket_path()).await
+}
 
+/// Connects to a daemon whose management interface socket, or named pipe on Windows, is at
+/// `ipc_path`.
+pub async fn new_rpc_client_at(ipc_path: PathBuf) -> Result<ManagementServiceClient, Error> {
     // The URI will be ignored
     let channel = Endpoint::from_static("lttp://[::]:50051")
         .connect_with_connector(service_fn(move |_: Uri| {
@@ -69,50 +98,136 @@ pub async fn new_rpc_client() -> Result<ManagementServiceClient, Error> {
 
 pub type ServerJoinHandle = tokio::task::JoinHandle<Result<(), Error>>;
 
+/// Information about the client at the other end of a management interface connection.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    /// The Windows session that the client process runs in, if it could be determined.
+    #[cfg(windows)]
+    pub session_id: Option<u32>,
+}
+
+impl ClientInfo {
+    /// Returns information about the client that sent `request`.
+    pub fn of<T>(request: &Request<T>) -> Self {
+        request
+            .extensions()
+            .get::<ClientInfo>()
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// Returns whether systemd has passed a listening socket for the management interface to this
+/// process. The socket then outlives the daemon, so it must not be removed.
+#[cfg(target_os = "linux")]
+pub fn is_socket_activated() -> bool {
+    activated_socket_fd().is_some()
+}
+
+/// Returns the listening socket passed by systemd, if any. The environment variables are only
+/// valid for the process whose ID is in `LISTEN_PID`, so they are ignored by child processes.
+#[cfg(target_os = "linux")]
+fn activated_socket_fd() -> Option<RawFd> {
+    let pid: u32 = env::var("LISTEN_PID").ok()?.parse().ok()?;
+    if pid != std::process::id() {
+        return None;
+    }
+    let num_fds: u32 = env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if num_fds != 1 {
+        log::warn!(
+            "Expected a single socket from systemd, got {}. Ignoring them",
+            num_fds
+        );
+        return None;
+    }
+    Some(SD_LISTEN_FDS_START)
+}
+
 pub async fn spawn_rpc_server<T: ManagementService, F: Future<Output = ()> + Send + 'static>(
     service: T,
     abort_rx: F,
 ) -> std::result::Result<ServerJoinHandle, Error> {
+    #[cfg(unix)]
     use futures::stream::TryStreamExt;
+    #[cfg(unix)]
     use parity_tokio_ipc::SecurityAttributes;
 
+    #[cfg(target_os = "linux")]
+    if let Some(fd) = activated_socket_fd() {
+        log::debug!("Using the management interface socket passed by systemd");
+        // SAFETY: systemd passes ownership of the listening socket to this process, and it is
+        // only taken once since the server is only spawned once.
+        let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+        listener
+            .set_nonblocking(true)
+            .map_err(Error::StartServerError)?;
+        let listener =
+            tokio::net::UnixListener::from_std(listener).map_err(Error::StartServerError)?;
+        let incoming = futures::stream::poll_fn(move |cx| {
+            listener.poll_accept(cx).map(|result| {
+                Some(result.map(|(stream, _addr)| StreamBox(stream, ClientInfo::default())))
+            })
+        });
+        return Ok(spawn_server(service, incoming, abort_rx));
+    }
+
     let socket_path = mullvad_paths::get_rpc_socket_path();
 
-    let mut endpoint = IpcEndpoint::new(socket_path.to_string_lossy().to_string());
-    endpoint.set_security_attributes(
-        SecurityAttributes::allow_everyone_create()
-            .map_err(Error::SecurityAttributes)?
-            .set_mode(0o766)
-            .map_err(Error::SecurityAttributes)?,
-    );
-    let incoming = endpoint.incoming().map_err(Error::StartServerError)?;
+    // The pipe is created without `parity-tokio-ipc` on Windows, so that the session of each
+    // client can be looked up
+    #[cfg(windows)]
+    let incoming = windows::incoming(socket_path.to_string_lossy().to_string())
+        .map_err(Error::StartServerError)?;
 
     #[cfg(unix)]
-    if let Some(group_name) = &*MULLVAD_MANAGEMENT_SOCKET_GROUP {
-        let group = nix::unistd::Group::from_name(group_name)
-            .map_err(Error::ObtainGidError)?
-            .ok_or(Error::NoGidError)?;
-        nix::unistd::chown(&socket_path, None, Some(group.gid)).map_err(Error::SetGidError)?;
-        fs::set_permissions(&socket_path, PermissionsExt::from_mode(0o760))
-            .map_err(Error::PermissionsError)?;
-    }
+    let incoming = {
+        let mut endpoint = IpcEndpoint::new(socket_path.to_string_lossy().to_string());
+        endpoint.set_security_attributes(
+            SecurityAttributes::allow_everyone_create()
+                .map_err(Error::SecurityAttributes)?
+                .set_mode(0o766)
+                .map_err(Error::SecurityAttributes)?,
+        );
+        let incoming = endpoint.incoming().map_err(Error::StartServerError)?;
+
+        if let Some(group_name) = &*MULLVAD_MANAGEMENT_SOCKET_GROUP {
+            let group = nix::unistd::Group::from_name(group_name)
+                .map_err(Error::ObtainGidError)?
+                .ok_or(Error::NoGidError)?;
+            nix::unistd::chown(&socket_path, None, Some(group.gid)).map_err(Error::SetGidError)?;
+            fs::set_permissions(&socket_path, PermissionsExt::from_mode(0o760))
+                .map_err(Error::PermissionsError)?;
+        }
+
+        incoming.map_ok(|stream| StreamBox(stream, ClientInfo::default()))
+    };
+
+    Ok(spawn_server(service, incoming, abort_rx))
+}
 
-    Ok(tokio::spawn(async move {
+fn spawn_server<T, F, S, IO>(service: T, incoming: S, abort_rx: F) -> ServerJoinHandle
+where
+    T: ManagementService,
+    F: Future<Output = ()> + Send + 'static,
+    S: Stream<Item = io::Result<StreamBox<IO>>> + Send + 'static,
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
         Server::builder()
             .add_service(ManagementServiceServer::new(service))
-            .serve_with_incoming_shutdown(incoming.map_ok(StreamBox), abort_rx)
+            .serve_with_incoming_shutdown(incoming, abort_rx)
             .await
             .map_err(Error::GrpcTransportError)
-    }))
+    })
 }
 
 #[derive(Debug)]
-struct StreamBox<T: AsyncRead + AsyncWrite>(pub T);
+struct StreamBox<T: AsyncRead + AsyncWrite>(pub T, ClientInfo);
 impl<T: AsyncRead + AsyncWrite> Connected for StreamBox<T> {
-    type ConnectInfo = Option<()>;
+    type ConnectInfo = ClientInfo;
 
     fn connect_info(&self) -> Self::ConnectInfo {
-        None
+        self.1.clone()
     }
 }
 impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for StreamBox<T> {
@@ -6,6 +6,39 @@ use talpid_types::ErrorExt;
 
 tonic::include_proto!("mullvad_daemon.management_interface");
 
+/// Serializers for the enum fields of the tunnel state, which prost stores as `i32`. The name of
+/// the variant is written instead of its number. Unknown values are written as numbers.
+mod enum_field {
+    use serde::Serialize;
+
+    macro_rules! enum_field_serializers {
+        ($($name:ident: $type:ty),* $(,)?) => {
+            $(
+                pub fn $name<S: serde::Serializer>(
+                    value: &i32,
+                    serializer: S,
+                ) -> Result<S::Ok, S::Error> {
+                    match <$type>::from_i32(*value) {
+                        Some(variant) => variant.serialize(serializer),
+                        None => serializer.serialize_i32(*value),
+                    }
+                }
+            )*
+        };
+    }
+
+    enum_field_serializers! {
+        after_disconnect: super::AfterDisconnect,
+        transport_protocol: super::TransportProtocol,
+        tunnel_type: super::TunnelType,
+        obfuscation_type: super::ObfuscationType,
+        proxy_type: super::ProxyType,
+        error_state_cause: super::error_state::Cause,
+        generation_error: super::error_state::GenerationError,
+        firewall_policy_error_type: super::error_state::firewall_policy_error::ErrorType,
+    }
+}
+
 impl From<mullvad_types::location::GeoIpLocation> for GeoIpLocation {
     fn from(geoip: mullvad_types::location::GeoIpLocation) -> GeoIpLocation {
         GeoIpLocation {
@@ -19,6 +52,7 @@ impl From<mullvad_types::location::GeoIpLocation> for GeoIpLocation {
             hostname: geoip.hostname.unwrap_or_default(),
             bridge_hostname: geoip.bridge_hostname.unwrap_or_default(),
             entry_hostname: geoip.entry_hostname.unwrap_or_default(),
+            relay_fallback_tier: geoip.relay_fallback_tier.unwrap_or_default(),
         }
     }
 }
@@ -42,10 +76,20 @@ impl From<talpid_types::net::TunnelEndpoint> for TunnelEndpoint {
                     net::proxy::ProxyType::Custom => i32::from(ProxyType::Custom),
                 },
             }),
-            entry_endpoint: endpoint.entry_endpoint.map(|entry| Endpoint {
-                address: entry.address.to_string(),
-                protocol: i32::from(TransportProtocol::from(entry.protocol)),
+            entry_endpoint: endpoint.entry_endpoint.map(Endpoint::from),
+            obfuscation: i32::from(match endpoint.obfuscation {
+                Some(talpid_types::tunnel::ObfuscatorKind::Udp2Tcp) => {
+                    ObfuscationType::ObfuscationUdp2tcp
+                }
+                Some(talpid_types::tunnel::ObfuscatorKind::Socks5) => {
+                    ObfuscationType::ObfuscationSocks5
+                }
+                Some(talpid_types::tunnel::ObfuscatorKind::Plugin) => {
+                    ObfuscationType::ObfuscationPlugin
+                }
+                None => ObfuscationType::NoObfuscation,
             }),
+            tunnel_interface: endpoint.tunnel_interface.unwrap_or_default(),
         }
     }
 }
@@ -123,6 +167,9 @@ impl From<mullvad_types::states::TunnelState> for TunnelState {
                             talpid_tunnel::ErrorStateCause::AuthFailed(_) => {
                                 i32::from(Cause::AuthFailed)
                             }
+                            talpid_tunnel::ErrorStateCause::AccountExpired => {
+                                i32::from(Cause::AccountExpired)
+                            }
                             talpid_tunnel::ErrorStateCause::Ipv6Unavailable => {
                                 i32::from(Cause::Ipv6Unavailable)
                             }
@@ -132,6 +179,14 @@ impl From<mullvad_types::states::TunnelState> for TunnelState {
                             talpid_tunnel::ErrorStateCause::SetDnsError => {
                                 i32::from(Cause::SetDnsError)
                             }
+                            #[cfg(not(target_os = "android"))]
+                            talpid_tunnel::ErrorStateCause::KillSwitchVerificationFailed => {
+                                i32::from(Cause::KillSwitchVerificationFailed)
+                            }
+                            #[cfg(not(target_os = "android"))]
+                            talpid_tunnel::ErrorStateCause::ClockSkew(_) => {
+                                i32::from(Cause::ClockSkew)
+                            }
                             talpid_tunnel::ErrorStateCause::StartTunnelError => {
                                 i32::from(Cause::StartTunnelError)
                             }
@@ -176,6 +231,9 @@ impl From<mullvad_types::states::TunnelState> for TunnelState {
                             talpid_tunnel::ParameterGenerationError::CustomTunnelHostResultionError => {
                                 i32::from(GenerationError::CustomTunnelHostResolutionError)
                             }
+                            talpid_tunnel::ParameterGenerationError::RelayKeyMismatch => {
+                                i32::from(GenerationError::RelayKeyMismatch)
+                            }
                         }
                             } else {
                                 0
@@ -188,6 +246,11 @@ impl From<mullvad_types::states::TunnelState> for TunnelState {
                             } else {
                                 None
                             },
+                        clock_skew_seconds: match error_state.cause() {
+                            #[cfg(not(target_os = "android"))]
+                            talpid_tunnel::ErrorStateCause::ClockSkew(skew_secs) => *skew_secs,
+                            _ => 0,
+                        },
                     }),
                 })
             }
@@ -229,6 +292,62 @@ impl From<mullvad_types::wireguard::PublicKey> for PublicKey {
     }
 }
 
+impl TryFrom<PublicKey> for talpid_types::net::wireguard::PublicKey {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(public_key: PublicKey) -> Result<Self, Self::Error> {
+        if public_key.key.len() != 32 {
+            return Err(FromProtobufTypeError::InvalidArgument("invalid public key"));
+        }
+        let mut key = [0; 32];
+        key.copy_from_slice(&public_key.key);
+        Ok(talpid_types::net::wireguard::PublicKey::from(key))
+    }
+}
+
+impl wireguard_key_list::Key {
+    pub fn new(
+        registered_key: mullvad_types::wireguard::RegisteredKey,
+        current_key: Option<&talpid_types::net::wireguard::PublicKey>,
+    ) -> Self {
+        Self {
+            key: registered_key.pubkey.as_bytes().to_vec(),
+            is_current: current_key == Some(&registered_key.pubkey),
+            ipv4_address: registered_key.addresses.ipv4_address.to_string(),
+            ipv6_address: registered_key.addresses.ipv6_address.to_string(),
+        }
+    }
+}
+
+impl From<PlayPurchase> for mullvad_types::account::PlayPurchase {
+    fn from(play_purchase: PlayPurchase) -> Self {
+        mullvad_types::account::PlayPurchase {
+            product_id: play_purchase.product_id,
+            purchase_token: play_purchase.purchase_token,
+        }
+    }
+}
+
+impl From<mullvad_types::account::AccountExpiryEvent> for AccountExpiryEvent {
+    fn from(event: mullvad_types::account::AccountExpiryEvent) -> Self {
+        use account_expiry_event::Warning;
+        use mullvad_types::account::ExpiryWarning;
+
+        AccountExpiryEvent {
+            expiry: Some(Timestamp {
+                seconds: event.expiry.timestamp(),
+                nanos: 0,
+            }),
+            warning: i32::from(match event.warning {
+                None => Warning::None,
+                Some(ExpiryWarning::ThreeDaysLeft) => Warning::ThreeDaysLeft,
+                Some(ExpiryWarning::OneDayLeft) => Warning::OneDayLeft,
+                Some(ExpiryWarning::Expired) => Warning::Expired,
+            }),
+        }
+    }
+}
+
 impl From<mullvad_types::version::AppVersionInfo> for AppVersionInfo {
     fn from(version_info: mullvad_types::version::AppVersionInfo) -> Self {
         Self {
@@ -287,6 +406,204 @@ impl From<mullvad_types::ConnectionConfig> for ConnectionConfig {
     }
 }
 
+impl From<talpid_types::net::Endpoint> for Endpoint {
+    fn from(endpoint: talpid_types::net::Endpoint) -> Self {
+        Endpoint {
+            address: endpoint.address.to_string(),
+            protocol: i32::from(TransportProtocol::from(endpoint.protocol)),
+        }
+    }
+}
+
+impl From<talpid_types::firewall::FirewallPolicyDescription> for FirewallPolicy {
+    fn from(policy: talpid_types::firewall::FirewallPolicyDescription) -> Self {
+        use talpid_types::firewall::FirewallPolicyState;
+
+        FirewallPolicy {
+            state: i32::from(match policy.state {
+                FirewallPolicyState::None => firewall_policy::State::None,
+                FirewallPolicyState::Connecting => firewall_policy::State::Connecting,
+                FirewallPolicyState::Connected => firewall_policy::State::Connected,
+                FirewallPolicyState::Blocked => firewall_policy::State::Blocked,
+            }),
+            peer_endpoint: policy.peer_endpoint.map(Endpoint::from),
+            allowed_endpoint: policy.allowed_endpoint.map(Endpoint::from),
+            tunnel_interface: policy.tunnel_interface.unwrap_or_default(),
+            allow_lan: policy.allow_lan,
+            lan_networks: policy
+                .lan_networks
+                .iter()
+                .map(|network| network.to_string())
+                .collect(),
+            dns_servers: policy
+                .dns_servers
+                .iter()
+                .map(|server| server.to_string())
+                .collect(),
+            excluded_networks: policy
+                .excluded_networks
+                .iter()
+                .map(|network| network.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl From<talpid_types::firewall::BlockedStatePolicy> for BlockedStatePolicy {
+    fn from(policy: talpid_types::firewall::BlockedStatePolicy) -> Self {
+        BlockedStatePolicy {
+            allow_mdns: policy.allow_mdns,
+        }
+    }
+}
+
+impl From<BlockedStatePolicy> for talpid_types::firewall::BlockedStatePolicy {
+    fn from(policy: BlockedStatePolicy) -> Self {
+        talpid_types::firewall::BlockedStatePolicy {
+            allow_mdns: policy.allow_mdns,
+        }
+    }
+}
+
+impl From<talpid_types::tunnel::TrafficCounters> for TrafficCounters {
+    fn from(counters: talpid_types::tunnel::TrafficCounters) -> Self {
+        TrafficCounters {
+            tx_bytes: counters.tx_bytes,
+            rx_bytes: counters.rx_bytes,
+        }
+    }
+}
+
+impl From<talpid_types::tunnel::TunnelStatistics> for TunnelStatistics {
+    fn from(statistics: talpid_types::tunnel::TunnelStatistics) -> Self {
+        TunnelStatistics {
+            session: Some(TrafficCounters::from(statistics.session)),
+            cumulative: Some(TrafficCounters::from(statistics.cumulative)),
+            operation_timings: statistics
+                .operation_timings
+                .into_iter()
+                .map(|timing| tunnel_statistics::OperationTiming {
+                    operation: timing.operation,
+                    count: timing.count,
+                    last: Some(Duration::from(timing.last)),
+                    average: Some(Duration::from(timing.average)),
+                    max: Some(Duration::from(timing.max)),
+                })
+                .collect(),
+            endpoint_changes: statistics.endpoint_changes.map(|changes| {
+                tunnel_statistics::EndpointChanges {
+                    count: changes.count,
+                    last_change: changes.last_change.map(Timestamp::from),
+                    socket_rebinds: changes.socket_rebinds,
+                    handshake_retransmissions: changes.handshake_retransmissions,
+                }
+            }),
+        }
+    }
+}
+
+impl From<talpid_types::tunnel::TunnelDebugInfo> for TunnelDebugInfo {
+    fn from(info: talpid_types::tunnel::TunnelDebugInfo) -> Self {
+        use talpid_types::tunnel::ObfuscatorKind;
+
+        TunnelDebugInfo {
+            has_tunnel: info.tunnel_type.is_some(),
+            tunnel_type: match info.tunnel_type {
+                Some(talpid_types::net::TunnelType::OpenVpn) => i32::from(TunnelType::Openvpn),
+                Some(talpid_types::net::TunnelType::Wireguard) | None => {
+                    i32::from(TunnelType::Wireguard)
+                }
+            },
+            adapter: info.adapter.map(|adapter| tunnel_debug_info::Adapter {
+                name: adapter.name,
+                backend: adapter.backend,
+                is_running: adapter.is_running,
+                mtu: adapter.mtu.map(u32::from),
+                addresses: adapter
+                    .addresses
+                    .iter()
+                    .map(|address| address.to_string())
+                    .collect(),
+                fallback_reason: adapter.fallback_reason.unwrap_or_default(),
+            }),
+            peers: info
+                .peers
+                .into_iter()
+                .map(|peer| tunnel_debug_info::Peer {
+                    public_key: peer.public_key.as_bytes().to_vec(),
+                    endpoint: peer.endpoint.to_string(),
+                    allowed_ips: peer
+                        .allowed_ips
+                        .iter()
+                        .map(|network| network.to_string())
+                        .collect(),
+                    tx_bytes: peer.tx_bytes,
+                    rx_bytes: peer.rx_bytes,
+                    last_handshake: peer.last_handshake.map(Duration::from),
+                })
+                .collect(),
+            obfuscators: info
+                .obfuscators
+                .into_iter()
+                .map(|obfuscator| tunnel_debug_info::Obfuscator {
+                    kind: i32::from(match obfuscator.kind {
+                        ObfuscatorKind::Udp2Tcp => tunnel_debug_info::obfuscator::Kind::Udp2tcp,
+                        ObfuscatorKind::Socks5 => tunnel_debug_info::obfuscator::Kind::Socks5,
+                        ObfuscatorKind::Plugin => tunnel_debug_info::obfuscator::Kind::Plugin,
+                    }),
+                    local_address: obfuscator.local_address.to_string(),
+                    remote_address: obfuscator.remote_address.to_string(),
+                    is_running: obfuscator.is_running,
+                })
+                .collect(),
+            bound_interface: info.bound_interface.unwrap_or_default(),
+            routes: info
+                .routes
+                .into_iter()
+                .map(|route| tunnel_debug_info::Route {
+                    destination: route.destination.to_string(),
+                    gateway: route
+                        .gateway
+                        .map(|gateway| gateway.to_string())
+                        .unwrap_or_default(),
+                    interface: route.interface.unwrap_or_default(),
+                    metric: route.metric,
+                    table_id: route.table_id,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<talpid_types::tunnel::StateMachineDebugInfo> for StateMachineDebugInfo {
+    fn from(info: talpid_types::tunnel::StateMachineDebugInfo) -> Self {
+        StateMachineDebugInfo {
+            state: info.state,
+            state_entered: Some(Timestamp::from(info.state_entered)),
+            retry_attempt: info.retry_attempt,
+            pending_timers: info
+                .pending_timers
+                .into_iter()
+                .map(|timer| state_machine_debug_info::PendingTimer {
+                    action: timer.action,
+                    deadline: Some(Timestamp::from(timer.deadline)),
+                })
+                .collect(),
+            transitions: info
+                .transitions
+                .into_iter()
+                .map(|transition| state_machine_debug_info::Transition {
+                    time: Some(Timestamp::from(transition.time)),
+                    from: transition.from,
+                    to: transition.to,
+                    details: transition.details.unwrap_or_default(),
+                    trigger: transition.trigger,
+                })
+                .collect(),
+        }
+    }
+}
+
 impl From<talpid_types::net::TransportProtocol> for TransportProtocol {
     fn from(protocol: talpid_types::net::TransportProtocol) -> Self {
         match protocol {
@@ -386,21 +703,104 @@ impl From<&mullvad_types::settings::Settings> for Settings {
         #[cfg(not(windows))]
         let split_tunnel = None;
 
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let excluded_networks = settings
+            .excluded_networks
+            .iter()
+            .map(|network| network.to_string())
+            .collect();
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        let excluded_networks = vec![];
+
         Self {
             account_token: settings.get_account_token().unwrap_or_default(),
             relay_settings: Some(RelaySettings::from(settings.get_relay_settings())),
             bridge_settings: Some(BridgeSettings::from(settings.bridge_settings.clone())),
             bridge_state: Some(BridgeState::from(settings.get_bridge_state())),
             allow_lan: settings.allow_lan,
+            lan_allow_list: settings
+                .lan_allow_list
+                .as_ref()
+                .map(|networks| LanAllowList {
+                    networks: networks.iter().map(|network| network.to_string()).collect(),
+                }),
+            blocked_state_policy: Some(BlockedStatePolicy::from(settings.blocked_state_policy)),
             block_when_disconnected: settings.block_when_disconnected,
+            block_when_account_expired: settings.block_when_account_expired,
+            strict_kill_switch: settings.strict_kill_switch,
             auto_connect: settings.auto_connect,
+            reconnect_on_network_change: settings.reconnect_on_network_change,
+            trusted_networks: Some(TrustedNetworks::from(&settings.trusted_networks)),
             tunnel_options: Some(TunnelOptions::from(&settings.tunnel_options)),
             show_beta_releases: settings.show_beta_releases,
+            log_privacy: Some(LogPrivacy::from(settings.log_privacy)),
             split_tunnel,
+            entry_proxy: settings.entry_proxy.as_ref().map(EntryProxy::from),
+            excluded_networks,
+            #[cfg(windows)]
+            allow_connection_sharing: settings.allow_connection_sharing,
+            #[cfg(not(windows))]
+            allow_connection_sharing: false,
+            relay_fallback_tiers: Some(RelayFallbackTiers {
+                tiers: settings
+                    .relay_fallback_tiers
+                    .iter()
+                    .cloned()
+                    .map(NormalRelaySettings::from)
+                    .collect(),
+            }),
+            prefer_low_latency: settings.prefer_low_latency,
+            use_system_proxy: settings.use_system_proxy,
+        }
+    }
+}
+
+impl From<&mullvad_types::settings::TrustedNetworks> for TrustedNetworks {
+    fn from(trusted_networks: &mullvad_types::settings::TrustedNetworks) -> Self {
+        TrustedNetworks {
+            ssids: trusted_networks.ssids.clone(),
+            subnets: trusted_networks
+                .subnets
+                .iter()
+                .map(|subnet| subnet.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl From<&talpid_types::net::proxy::EntryProxy> for EntryProxy {
+    fn from(proxy: &talpid_types::net::proxy::EntryProxy) -> Self {
+        Self {
+            address: proxy.address.to_string(),
+            auth: proxy
+                .auth
+                .as_ref()
+                .map(|auth| bridge_settings::RemoteProxyAuth {
+                    username: auth.username.clone(),
+                    password: auth.password.clone(),
+                }),
         }
     }
 }
 
+impl TryFrom<EntryProxy> for talpid_types::net::proxy::EntryProxy {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(proxy: EntryProxy) -> Result<Self, Self::Error> {
+        let address = proxy
+            .address
+            .parse()
+            .map_err(|_| FromProtobufTypeError::InvalidArgument("failed to parse proxy address"))?;
+        let auth = proxy
+            .auth
+            .map(|auth| talpid_types::net::openvpn::ProxyAuth {
+                username: auth.username,
+                password: auth.password,
+            });
+        Ok(talpid_types::net::proxy::EntryProxy { address, auth })
+    }
+}
+
 impl From<mullvad_types::relay_constraints::BridgeState> for BridgeState {
     fn from(state: mullvad_types::relay_constraints::BridgeState) -> Self {
         use mullvad_types::relay_constraints::BridgeState;
@@ -414,6 +814,18 @@ impl From<mullvad_types::relay_constraints::BridgeState> for BridgeState {
     }
 }
 
+impl From<mullvad_types::settings::LogPrivacy> for LogPrivacy {
+    fn from(log_privacy: mullvad_types::settings::LogPrivacy) -> Self {
+        use mullvad_types::settings::LogPrivacy;
+        Self {
+            level: i32::from(match log_privacy {
+                LogPrivacy::Standard => log_privacy::Level::Standard,
+                LogPrivacy::Aggressive => log_privacy::Level::Aggressive,
+            }),
+        }
+    }
+}
+
 impl From<mullvad_types::relay_constraints::BridgeSettings> for BridgeSettings {
     fn from(settings: mullvad_types::relay_constraints::BridgeSettings) -> Self {
         use mullvad_types::relay_constraints::BridgeSettings as MullvadBridgeSettings;
@@ -428,6 +840,7 @@ impl From<mullvad_types::relay_constraints::BridgeSettings> for BridgeSettings {
                         .option()
                         .map(RelayLocation::from),
                     providers: convert_providers_constraint(&constraints.providers),
+                    ownership: i32::from(Ownership::from(constraints.ownership)),
                 })
             }
             MullvadBridgeSettings::Custom(proxy_settings) => match proxy_settings {
@@ -467,7 +880,6 @@ impl From<mullvad_types::relay_constraints::BridgeSettings> for BridgeSettings {
 impl From<mullvad_types::relay_constraints::RelaySettings> for RelaySettings {
     fn from(settings: mullvad_types::relay_constraints::RelaySettings) -> Self {
         use mullvad_types::relay_constraints::RelaySettings as MullvadRelaySettings;
-        use talpid_types::net as talpid_net;
 
         let endpoint = match settings {
             MullvadRelaySettings::CustomTunnelEndpoint(endpoint) => {
@@ -477,50 +889,7 @@ impl From<mullvad_types::relay_constraints::RelaySettings> for RelaySettings {
                 })
             }
             MullvadRelaySettings::Normal(constraints) => {
-                relay_settings::Endpoint::Normal(NormalRelaySettings {
-                    location: constraints.location.option().map(RelayLocation::from),
-                    providers: convert_providers_constraint(&constraints.providers),
-                    tunnel_type: match constraints.tunnel_protocol {
-                        Constraint::Any => None,
-                        Constraint::Only(talpid_net::TunnelType::Wireguard) => {
-                            Some(TunnelType::Wireguard)
-                        }
-                        Constraint::Only(talpid_net::TunnelType::OpenVpn) => {
-                            Some(TunnelType::Openvpn)
-                        }
-                    }
-                    .map(|tunnel_type| TunnelTypeConstraint {
-                        tunnel_type: i32::from(tunnel_type),
-                    }),
-
-                    wireguard_constraints: Some(WireguardConstraints {
-                        port: constraints
-                            .wireguard_constraints
-                            .port
-                            .option()
-                            .map(TransportPort::from),
-                        ip_version: constraints
-                            .wireguard_constraints
-                            .ip_version
-                            .option()
-                            .map(IpVersion::from)
-                            .map(IpVersionConstraint::from),
-                        use_multihop: constraints.wireguard_constraints.use_multihop,
-                        entry_location: constraints
-                            .wireguard_constraints
-                            .entry_location
-                            .option()
-                            .map(RelayLocation::from),
-                    }),
-
-                    openvpn_constraints: Some(OpenvpnConstraints {
-                        port: constraints
-                            .openvpn_constraints
-                            .port
-                            .option()
-                            .map(TransportPort::from),
-                    }),
-                })
+                relay_settings::Endpoint::Normal(NormalRelaySettings::from(constraints))
             }
         };
 
@@ -530,6 +899,59 @@ impl From<mullvad_types::relay_constraints::RelaySettings> for RelaySettings {
     }
 }
 
+impl From<mullvad_types::relay_constraints::RelayConstraints> for NormalRelaySettings {
+    fn from(constraints: mullvad_types::relay_constraints::RelayConstraints) -> Self {
+        use talpid_types::net as talpid_net;
+
+        NormalRelaySettings {
+            location: constraints.location.option().map(RelayLocation::from),
+            providers: convert_providers_constraint(&constraints.providers),
+            ownership: i32::from(Ownership::from(constraints.ownership)),
+            tunnel_type: match constraints.tunnel_protocol {
+                Constraint::Any => None,
+                Constraint::Only(talpid_net::TunnelType::Wireguard) => Some(TunnelType::Wireguard),
+                Constraint::Only(talpid_net::TunnelType::OpenVpn) => Some(TunnelType::Openvpn),
+            }
+            .map(|tunnel_type| TunnelTypeConstraint {
+                tunnel_type: i32::from(tunnel_type),
+            }),
+
+            wireguard_constraints: Some(WireguardConstraints {
+                port: constraints
+                    .wireguard_constraints
+                    .port
+                    .option()
+                    .map(TransportPort::from),
+                port_range: constraints
+                    .wireguard_constraints
+                    .port_range
+                    .option()
+                    .map(PortRange::from),
+                ip_version: constraints
+                    .wireguard_constraints
+                    .ip_version
+                    .option()
+                    .map(IpVersion::from)
+                    .map(IpVersionConstraint::from),
+                use_multihop: constraints.wireguard_constraints.use_multihop,
+                entry_location: constraints
+                    .wireguard_constraints
+                    .entry_location
+                    .option()
+                    .map(RelayLocation::from),
+            }),
+
+            openvpn_constraints: Some(OpenvpnConstraints {
+                port: constraints
+                    .openvpn_constraints
+                    .port
+                    .option()
+                    .map(TransportPort::from),
+            }),
+        }
+    }
+}
+
 impl From<&mullvad_types::settings::DnsOptions> for DnsOptions {
     fn from(options: &mullvad_types::settings::DnsOptions) -> Self {
         DnsOptions {
@@ -541,6 +963,9 @@ impl From<&mullvad_types::settings::DnsOptions> for DnsOptions {
                 block_ads: options.default_options.block_ads,
                 block_trackers: options.default_options.block_trackers,
                 block_malware: options.default_options.block_malware,
+                block_adult_content: options.default_options.block_adult_content,
+                block_gambling: options.default_options.block_gambling,
+                dns_over_tls: options.default_options.dns_over_tls,
             }),
             custom_options: Some(CustomDnsOptions {
                 addresses: options
@@ -570,6 +995,15 @@ impl From<&mullvad_types::settings::TunnelOptions> for TunnelOptions {
                 use_wireguard_nt: options.wireguard.options.use_wireguard_nt,
                 #[cfg(not(windows))]
                 use_wireguard_nt: false,
+                #[cfg(any(target_os = "linux", windows))]
+                interface_name: options
+                    .wireguard
+                    .options
+                    .interface_name
+                    .clone()
+                    .unwrap_or_default(),
+                #[cfg(not(any(target_os = "linux", windows)))]
+                interface_name: String::new(),
             }),
             generic: Some(tunnel_options::GenericOptions {
                 enable_ipv6: options.generic.enable_ipv6,
@@ -715,8 +1149,14 @@ impl TryFrom<&WireguardConstraints> for mullvad_types::relay_constraints::Wiregu
             None => None,
         };
 
+        let port_range = match &constraints.port_range {
+            Some(range) => Some(mullvad_constraints::PortRange::try_from(range.clone())?),
+            None => None,
+        };
+
         Ok(mullvad_constraints::WireguardConstraints {
             port: Constraint::from(wireguard_transport_port),
+            port_range: Constraint::from(port_range),
             ip_version: Constraint::from(ip_version),
             use_multihop: constraints.use_multihop,
             entry_location: constraints
@@ -728,6 +1168,29 @@ impl TryFrom<&WireguardConstraints> for mullvad_types::relay_constraints::Wiregu
     }
 }
 
+impl From<mullvad_types::relay_constraints::PortRange> for PortRange {
+    fn from(range: mullvad_types::relay_constraints::PortRange) -> Self {
+        PortRange {
+            first: u32::from(range.first()),
+            last: u32::from(range.last()),
+        }
+    }
+}
+
+impl TryFrom<PortRange> for mullvad_types::relay_constraints::PortRange {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(range: PortRange) -> Result<Self, Self::Error> {
+        let first = u16::try_from(range.first)
+            .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid port"))?;
+        let last = u16::try_from(range.last)
+            .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid port"))?;
+        mullvad_types::relay_constraints::PortRange::new(first, last).ok_or(
+            FromProtobufTypeError::InvalidArgument("the first port is greater than the last port"),
+        )
+    }
+}
+
 impl TryFrom<&OpenvpnConstraints> for mullvad_types::relay_constraints::OpenVpnConstraints {
     type Error = FromProtobufTypeError;
 
@@ -752,7 +1215,6 @@ impl TryFrom<RelaySettings> for mullvad_types::relay_constraints::RelaySettings
         settings: RelaySettings,
     ) -> Result<mullvad_types::relay_constraints::RelaySettings, Self::Error> {
         use mullvad_types::{relay_constraints as mullvad_constraints, CustomTunnelEndpoint};
-        use talpid_types::net;
 
         let update_value =
             settings
@@ -764,57 +1226,66 @@ impl TryFrom<RelaySettings> for mullvad_types::relay_constraints::RelaySettings
 
         match update_value {
             relay_settings::Endpoint::Custom(settings) => {
-                let config = settings
-                    .config
-                    .ok_or(FromProtobufTypeError::InvalidArgument(
-                        "missing relay connection config",
-                    ))?;
-                let config = mullvad_types::ConnectionConfig::try_from(config)?;
                 Ok(mullvad_constraints::RelaySettings::CustomTunnelEndpoint(
-                    CustomTunnelEndpoint {
-                        host: settings.host,
-                        config,
-                    },
+                    CustomTunnelEndpoint::try_from(settings)?,
                 ))
             }
 
             relay_settings::Endpoint::Normal(settings) => {
-                let location = settings
-                    .location
-                    .map(Constraint::<mullvad_types::relay_constraints::LocationConstraint>::from)
-                    .unwrap_or(Constraint::Any);
-                let providers = try_providers_constraint_from_proto(&settings.providers)?;
-                let tunnel_protocol = settings
-                    .tunnel_type
-                    .map(Constraint::<net::TunnelType>::try_from)
-                    .transpose()?
-                    .unwrap_or(Constraint::Any);
-                let openvpn_constraints =
-                    mullvad_constraints::OpenVpnConstraints::try_from(
-                        &settings.openvpn_constraints.ok_or(
-                            FromProtobufTypeError::InvalidArgument("missing openvpn constraints"),
-                        )?,
-                    )?;
-                let wireguard_constraints = mullvad_constraints::WireguardConstraints::try_from(
-                    &settings.wireguard_constraints.ok_or(
-                        FromProtobufTypeError::InvalidArgument("missing wireguard constraints"),
-                    )?,
-                )?;
-
                 Ok(mullvad_constraints::RelaySettings::Normal(
-                    mullvad_constraints::RelayConstraints {
-                        location,
-                        providers,
-                        tunnel_protocol,
-                        wireguard_constraints,
-                        openvpn_constraints,
-                    },
+                    mullvad_constraints::RelayConstraints::try_from(settings)?,
                 ))
             }
         }
     }
 }
 
+impl TryFrom<NormalRelaySettings> for mullvad_types::relay_constraints::RelayConstraints {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(
+        settings: NormalRelaySettings,
+    ) -> Result<mullvad_types::relay_constraints::RelayConstraints, Self::Error> {
+        use mullvad_types::relay_constraints as mullvad_constraints;
+        use talpid_types::net;
+
+        let location = settings
+            .location
+            .map(Constraint::<mullvad_types::relay_constraints::LocationConstraint>::from)
+            .unwrap_or(Constraint::Any);
+        let providers = try_providers_constraint_from_proto(&settings.providers)?;
+        let ownership = try_ownership_constraint_from_i32(settings.ownership)?;
+        let tunnel_protocol = settings
+            .tunnel_type
+            .map(Constraint::<net::TunnelType>::try_from)
+            .transpose()?
+            .unwrap_or(Constraint::Any);
+        let openvpn_constraints = mullvad_constraints::OpenVpnConstraints::try_from(
+            &settings
+                .openvpn_constraints
+                .ok_or(FromProtobufTypeError::InvalidArgument(
+                    "missing openvpn constraints",
+                ))?,
+        )?;
+        let wireguard_constraints = mullvad_constraints::WireguardConstraints::try_from(
+            &settings
+                .wireguard_constraints
+                .ok_or(FromProtobufTypeError::InvalidArgument(
+                    "missing wireguard constraints",
+                ))?,
+        )?;
+
+        Ok(mullvad_constraints::RelayConstraints {
+            location,
+            providers,
+            ownership,
+            tunnel_protocol,
+            wireguard_constraints,
+            openvpn_constraints,
+        })
+    }
+}
+
 impl TryFrom<RelaySettingsUpdate> for mullvad_types::relay_constraints::RelaySettingsUpdate {
     type Error = FromProtobufTypeError;
 
@@ -833,22 +1304,11 @@ impl TryFrom<RelaySettingsUpdate> for mullvad_types::relay_constraints::RelaySet
                 ))?;
 
         match update_value {
-            relay_settings_update::Type::Custom(settings) => {
-                let config = settings
-                    .config
-                    .ok_or(FromProtobufTypeError::InvalidArgument(
-                        "missing relay connection config",
-                    ))?;
-                let config = mullvad_types::ConnectionConfig::try_from(config)?;
-                Ok(
-                    mullvad_constraints::RelaySettingsUpdate::CustomTunnelEndpoint(
-                        CustomTunnelEndpoint {
-                            host: settings.host,
-                            config,
-                        },
-                    ),
-                )
-            }
+            relay_settings_update::Type::Custom(settings) => Ok(
+                mullvad_constraints::RelaySettingsUpdate::CustomTunnelEndpoint(
+                    CustomTunnelEndpoint::try_from(settings)?,
+                ),
+            ),
 
             relay_settings_update::Type::Normal(settings) => {
                 // If `location` isn't provided, no changes are made.
@@ -864,6 +1324,13 @@ impl TryFrom<RelaySettingsUpdate> for mullvad_types::relay_constraints::RelaySet
                 } else {
                     None
                 };
+                let ownership = if let Some(ref ownership_update) = settings.ownership {
+                    Some(try_ownership_constraint_from_i32(
+                        ownership_update.ownership,
+                    )?)
+                } else {
+                    None
+                };
                 let tunnel_protocol = if let Some(update) = settings.tunnel_type {
                     Some(
                         update
@@ -895,6 +1362,7 @@ impl TryFrom<RelaySettingsUpdate> for mullvad_types::relay_constraints::RelaySet
                     mullvad_constraints::RelayConstraintsUpdate {
                         location,
                         providers,
+                        ownership,
                         tunnel_protocol,
                         wireguard_constraints,
                         openvpn_constraints,
@@ -925,6 +1393,22 @@ impl TryFrom<TunnelTypeConstraint> for Constraint<talpid_types::net::TunnelType>
     }
 }
 
+impl TryFrom<CustomRelaySettings> for mullvad_types::CustomTunnelEndpoint {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(settings: CustomRelaySettings) -> Result<Self, Self::Error> {
+        let config = settings
+            .config
+            .ok_or(FromProtobufTypeError::InvalidArgument(
+                "missing relay connection config",
+            ))?;
+        Ok(mullvad_types::CustomTunnelEndpoint {
+            host: settings.host,
+            config: mullvad_types::ConnectionConfig::try_from(config)?,
+        })
+    }
+}
+
 impl TryFrom<ConnectionConfig> for mullvad_types::ConnectionConfig {
     type Error = FromProtobufTypeError;
 
@@ -1029,6 +1513,11 @@ impl TryFrom<ConnectionConfig> for mullvad_types::ConnectionConfig {
                         .map_err(|_| FromProtobufTypeError::InvalidArgument("invalid address"))?;
                     allowed_ips.push(address);
                 }
+                if allowed_ips.is_empty() {
+                    return Err(FromProtobufTypeError::InvalidArgument(
+                        "the peer has no allowed IPs",
+                    ));
+                }
 
                 Ok(mullvad_types::ConnectionConfig::Wireguard(
                     wireguard::ConnectionConfig {
@@ -1092,11 +1581,13 @@ impl TryFrom<BridgeSettings> for mullvad_types::relay_constraints::BridgeSetting
                     }
                 };
                 let providers = try_providers_constraint_from_proto(&constraints.providers)?;
+                let ownership = try_ownership_constraint_from_i32(constraints.ownership)?;
 
                 Ok(mullvad_constraints::BridgeSettings::Normal(
                     mullvad_constraints::BridgeConstraints {
                         location,
                         providers,
+                        ownership,
                     },
                 ))
             }
@@ -1163,6 +1654,22 @@ impl TryFrom<BridgeState> for mullvad_types::relay_constraints::BridgeState {
     }
 }
 
+impl TryFrom<LogPrivacy> for mullvad_types::settings::LogPrivacy {
+    type Error = FromProtobufTypeError;
+
+    fn try_from(log_privacy: LogPrivacy) -> Result<Self, Self::Error> {
+        match log_privacy::Level::from_i32(log_privacy.level) {
+            Some(log_privacy::Level::Standard) => Ok(mullvad_types::settings::LogPrivacy::Standard),
+            Some(log_privacy::Level::Aggressive) => {
+                Ok(mullvad_types::settings::LogPrivacy::Aggressive)
+            }
+            None => Err(FromProtobufTypeError::InvalidArgument(
+                "invalid log privacy level",
+            )),
+        }
+    }
+}
+
 impl TryFrom<TunnelOptions> for mullvad_types::settings::TunnelOptions {
     type Error = FromProtobufTypeError;
 
@@ -1207,6 +1714,12 @@ impl TryFrom<TunnelOptions> for mullvad_types::settings::TunnelOptions {
                     },
                     #[cfg(windows)]
                     use_wireguard_nt: wireguard_options.use_wireguard_nt,
+                    #[cfg(any(target_os = "linux", windows))]
+                    interface_name: if !wireguard_options.interface_name.is_empty() {
+                        Some(wireguard_options.interface_name.clone())
+                    } else {
+                        None
+                    },
                 },
                 rotation_interval: wireguard_options
                     .rotation_interval
@@ -1271,6 +1784,9 @@ impl TryFrom<DnsOptions> for mullvad_types::settings::DnsOptions {
                 block_ads: default_options.block_ads,
                 block_trackers: default_options.block_trackers,
                 block_malware: default_options.block_malware,
+                block_adult_content: default_options.block_adult_content,
+                block_gambling: default_options.block_gambling,
+                dns_over_tls: default_options.dns_over_tls,
             },
             custom_options: MullvadCustomDnsOptions {
                 addresses: custom_options
@@ -1326,6 +1842,38 @@ pub fn try_providers_constraint_from_proto(
     }
 }
 
+pub fn try_ownership_constraint_from_i32(
+    ownership: i32,
+) -> Result<Constraint<mullvad_types::relay_constraints::Ownership>, FromProtobufTypeError> {
+    Ownership::from_i32(ownership)
+        .map(Constraint::from)
+        .ok_or(FromProtobufTypeError::InvalidArgument("invalid ownership"))
+}
+
+impl From<Ownership> for Constraint<mullvad_types::relay_constraints::Ownership> {
+    fn from(ownership: Ownership) -> Self {
+        use mullvad_types::relay_constraints::Ownership as MullvadOwnership;
+
+        match ownership {
+            Ownership::Any => Constraint::Any,
+            Ownership::MullvadOwned => Constraint::Only(MullvadOwnership::MullvadOwned),
+            Ownership::Rented => Constraint::Only(MullvadOwnership::Rented),
+        }
+    }
+}
+
+impl From<Constraint<mullvad_types::relay_constraints::Ownership>> for Ownership {
+    fn from(ownership: Constraint<mullvad_types::relay_constraints::Ownership>) -> Self {
+        use mullvad_types::relay_constraints::Ownership as MullvadOwnership;
+
+        match ownership {
+            Constraint::Any => Ownership::Any,
+            Constraint::Only(MullvadOwnership::MullvadOwned) => Ownership::MullvadOwned,
+            Constraint::Only(MullvadOwnership::Rented) => Ownership::Rented,
+        }
+    }
+}
+
 fn convert_providers_constraint(
     providers: &Constraint<mullvad_types::relay_constraints::Providers>,
 ) -> Vec<String> {
@@ -1342,3 +1890,45 @@ impl From<FromProtobufTypeError> for crate::Status {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_enum_fields_serialized_as_names() {
+        let state = TunnelState {
+            state: Some(tunnel_state::State::Disconnecting(
+                tunnel_state::Disconnecting {
+                    after_disconnect: AfterDisconnect::Reconnect as i32,
+                },
+            )),
+        };
+        assert_eq!(
+            serde_json::to_value(&state).unwrap(),
+            json!({ "state": { "disconnecting": { "after_disconnect": "reconnect" } } })
+        );
+
+        let error_state = ErrorState {
+            cause: error_state::Cause::TunnelParameterError as i32,
+            parameter_error: error_state::GenerationError::NoMatchingRelay as i32,
+            ..ErrorState::default()
+        };
+        let value = serde_json::to_value(&error_state).unwrap();
+        assert_eq!(value["cause"], json!("tunnel_parameter_error"));
+        assert_eq!(value["parameter_error"], json!("no_matching_relay"));
+    }
+
+    #[test]
+    fn test_unknown_enum_value_serialized_as_number() {
+        let endpoint = Endpoint {
+            address: "10.0.0.1:53".to_owned(),
+            protocol: 7,
+        };
+        assert_eq!(
+            serde_json::to_value(&endpoint).unwrap(),
+            json!({ "address": "10.0.0.1:53", "protocol": 7 })
+        );
+    }
+}
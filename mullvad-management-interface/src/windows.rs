@@ -0,0 +1,125 @@
+//! Named pipe server for the management interface. Unlike the one in `parity-tokio-ipc`, it keeps
+//! the pipe handle accessible, which is needed to find out which session a client runs in.
+
+use super::{ClientInfo, StreamBox};
+use futures::Stream;
+use std::{
+    ffi::OsStr,
+    io, iter,
+    os::windows::{ffi::OsStrExt, io::AsRawHandle},
+    ptr,
+};
+use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+use winapi::{
+    shared::{
+        minwindef::{DWORD, FALSE, ULONG},
+        sddl::{ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1},
+    },
+    um::{
+        minwinbase::SECURITY_ATTRIBUTES,
+        winbase::{GetNamedPipeClientSessionId, LocalFree},
+        winnt::PSECURITY_DESCRIPTOR,
+    },
+};
+
+/// Allows everyone to read from and write to the pipe.
+const PIPE_SECURITY_DESCRIPTOR: &str = "D:(A;;GRGW;;;WD)";
+const PIPE_BUFFER_SIZE: u32 = 65536;
+
+/// Returns a stream of connections made to the named pipe at `path`.
+pub fn incoming(
+    path: String,
+) -> io::Result<impl Stream<Item = io::Result<StreamBox<NamedPipeServer>>> + 'static> {
+    let security_descriptor = SecurityDescriptor::from_sddl(PIPE_SECURITY_DESCRIPTOR)?;
+    let listener = create_listener(&path, &security_descriptor, true)?;
+
+    Ok(futures::stream::try_unfold(
+        (listener, path, security_descriptor),
+        |(listener, path, security_descriptor)| async move {
+            listener.connect().await?;
+            let new_listener = create_listener(&path, &security_descriptor, false)?;
+
+            let client_info = ClientInfo {
+                session_id: client_session_id(&listener),
+            };
+            Ok(Some((
+                StreamBox(listener, client_info),
+                (new_listener, path, security_descriptor),
+            )))
+        },
+    ))
+}
+
+fn create_listener(
+    path: &str,
+    security_descriptor: &SecurityDescriptor,
+    first_instance: bool,
+) -> io::Result<NamedPipeServer> {
+    let mut attributes = SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as DWORD,
+        lpSecurityDescriptor: security_descriptor.0,
+        bInheritHandle: FALSE,
+    };
+    // SAFETY: `attributes` points to a valid security descriptor for the duration of the call.
+    unsafe {
+        ServerOptions::new()
+            .first_pipe_instance(first_instance)
+            .reject_remote_clients(true)
+            .access_inbound(true)
+            .access_outbound(true)
+            .in_buffer_size(PIPE_BUFFER_SIZE)
+            .out_buffer_size(PIPE_BUFFER_SIZE)
+            .create_with_security_attributes_raw(path, &mut attributes as *mut _ as *mut _)
+    }
+}
+
+/// Returns the session that the client connected to `pipe` runs in.
+fn client_session_id(pipe: &NamedPipeServer) -> Option<u32> {
+    let mut session_id: ULONG = 0;
+    // SAFETY: `pipe` is a valid pipe handle for the duration of the call.
+    let result = unsafe { GetNamedPipeClientSessionId(pipe.as_raw_handle() as _, &mut session_id) };
+    if result == 0 {
+        log::warn!(
+            "Failed to get the session of a management interface client: {}",
+            io::Error::last_os_error()
+        );
+        return None;
+    }
+    Some(session_id)
+}
+
+/// A security descriptor allocated by `ConvertStringSecurityDescriptorToSecurityDescriptorW`.
+struct SecurityDescriptor(PSECURITY_DESCRIPTOR);
+
+// SAFETY: The descriptor is never mutated after it has been created.
+unsafe impl Send for SecurityDescriptor {}
+
+impl SecurityDescriptor {
+    fn from_sddl(sddl: &str) -> io::Result<Self> {
+        let sddl: Vec<u16> = OsStr::new(sddl)
+            .encode_wide()
+            .chain(iter::once(0))
+            .collect();
+        let mut descriptor = ptr::null_mut();
+        // SAFETY: `sddl` is nul-terminated and outlives the call.
+        let result = unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                SDDL_REVISION_1 as DWORD,
+                &mut descriptor,
+                ptr::null_mut(),
+            )
+        };
+        if result == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(SecurityDescriptor(descriptor))
+    }
+}
+
+impl Drop for SecurityDescriptor {
+    fn drop(&mut self) {
+        // SAFETY: The descriptor was allocated with `LocalAlloc` and is not used after this.
+        unsafe { LocalFree(self.0) };
+    }
+}
@@ -10,6 +10,8 @@ use std::{
     fs::{self, File},
     io::{self, BufWriter, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
 };
 use talpid_types::ErrorExt;
 
@@ -20,6 +22,11 @@ const LOG_MAX_READ_BYTES: usize = 128 * 1024;
 const EXTRA_BYTES: usize = 32 * 1024;
 /// Fit five logs plus some system information in the report.
 const REPORT_MAX_SIZE: usize = (5 * LOG_MAX_READ_BYTES) + EXTRA_BYTES;
+/// Maximum number of bytes of network information to include. Must fit in `EXTRA_BYTES`.
+const NETWORK_INFO_MAX_BYTES: usize = 16 * 1024;
+
+/// Hostnames shorter than this are not redacted, since they would match too much unrelated text.
+const MIN_REDACTED_HOSTNAME_LEN: usize = 4;
 
 /// Field delimeter in generated problem report
 const LOG_DELIMITER: &str = "====================";
@@ -32,6 +39,8 @@ const LINE_SEPARATOR: &str = "\n";
 const LINE_SEPARATOR: &str = "\r\n";
 
 const MAX_SEND_ATTEMPTS: usize = 3;
+/// Delay before the second attempt to send a report. It is doubled for every following attempt.
+const SEND_RETRY_DELAY: Duration = Duration::from_secs(2);
 
 /// Custom macro to write a line to an output formatter that uses platform-specific newline
 /// character sequences.
@@ -71,6 +80,10 @@ pub enum Error {
     #[error(display = "Failed to send problem report {} times", MAX_SEND_ATTEMPTS)]
     SendFailedTooManyTimes,
 
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "The daemon failed to send the problem report")]
+    DaemonSendError(#[error(source)] mullvad_management_interface::Status),
+
     #[error(display = "Unable to spawn Tokio runtime")]
     CreateRuntime(#[error(source)] io::Error),
 
@@ -168,6 +181,10 @@ pub fn collect_report(
         Err(error) => problem_report.add_error("Failed to collect logcat", &error),
     }
 
+    if let Some((program, args)) = network_info_command() {
+        problem_report.add_command_output("Network interfaces", program, args);
+    }
+
     problem_report.add_logs(extra_logs);
 
     write_problem_report(&output_path, &problem_report).map_err(|source| Error::WriteReportError {
@@ -238,6 +255,29 @@ fn frontend_log_dir() -> Option<Result<PathBuf, LogError>> {
     }
 }
 
+/// Returns the command that lists the network interfaces and their addresses.
+fn network_info_command() -> Option<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "linux") {
+        Some(("ip", &["address"]))
+    } else if cfg!(target_os = "macos") {
+        Some(("ifconfig", &[]))
+    } else if cfg!(windows) {
+        Some(("ipconfig", &["/all"]))
+    } else {
+        None
+    }
+}
+
+/// Returns the hostname of this computer, if it is long enough to be redacted.
+fn local_hostname() -> Option<String> {
+    let output = Command::new("hostname").output().ok()?;
+    let hostname = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if !output.status.success() || hostname.len() < MIN_REDACTED_HOSTNAME_LEN {
+        return None;
+    }
+    Some(hostname)
+}
+
 fn is_tunnel_log(path: &Path) -> bool {
     match path.file_name() {
         Some(file_name) => file_name.to_string_lossy().contains("openvpn"),
@@ -279,6 +319,26 @@ pub fn send_problem_report(
         .build()
         .map_err(Error::CreateRuntime)?;
 
+    // The daemon only lets the API through the firewall for its own use, so the report is sent
+    // through it when it is running
+    #[cfg(not(target_os = "android"))]
+    match runtime.block_on(mullvad_management_interface::new_rpc_client()) {
+        Ok(rpc) => {
+            return runtime.block_on(send_through_daemon(
+                rpc,
+                user_email,
+                user_message,
+                &report_content,
+                &metadata,
+            ));
+        }
+        Err(error) => log::debug!(
+            "{}",
+            error
+                .display_chain_with_msg("Failed to connect to the daemon, sending report directly")
+        ),
+    }
+
     let mut rpc_manager = runtime
         .block_on(mullvad_rpc::MullvadRpcRuntime::with_cache(
             None,
@@ -291,7 +351,12 @@ pub fn send_problem_report(
     let rpc_client = mullvad_rpc::ProblemReportProxy::new(rpc_manager.mullvad_rest_handle());
 
     runtime.block_on(async move {
-        for _attempt in 0..MAX_SEND_ATTEMPTS {
+        let mut retry_delay = SEND_RETRY_DELAY;
+        for attempt in 0..MAX_SEND_ATTEMPTS {
+            if attempt > 0 {
+                tokio::time::sleep(retry_delay).await;
+                retry_delay *= 2;
+            }
             match rpc_client
                 .problem_report(user_email, user_message, &report_content, &metadata)
                 .await
@@ -316,6 +381,25 @@ pub fn send_problem_report(
     })
 }
 
+#[cfg(not(target_os = "android"))]
+async fn send_through_daemon(
+    mut rpc: mullvad_management_interface::ManagementServiceClient,
+    user_email: &str,
+    user_message: &str,
+    report_content: &str,
+    metadata: &BTreeMap<String, String>,
+) -> Result<(), Error> {
+    rpc.send_problem_report(mullvad_management_interface::types::ProblemReport {
+        email: user_email.to_owned(),
+        message: user_message.to_owned(),
+        log: report_content.to_owned(),
+        metadata: metadata.clone().into_iter().collect(),
+    })
+    .await
+    .map(|_| ())
+    .map_err(Error::DaemonSendError)
+}
+
 fn write_problem_report(path: &Path, problem_report: &ProblemReport) -> io::Result<()> {
     let file = File::create(path)?;
     let mut permissions = file.metadata()?.permissions();
@@ -331,6 +415,7 @@ struct ProblemReport {
     logs: Vec<(String, String)>,
     log_paths: HashSet<PathBuf>,
     redact_custom_strings: Vec<String>,
+    hostname: Option<String>,
 }
 
 impl ProblemReport {
@@ -344,6 +429,7 @@ impl ProblemReport {
             logs: Vec::new(),
             log_paths: HashSet::new(),
             redact_custom_strings,
+            hostname: local_hostname(),
         }
     }
 
@@ -378,6 +464,26 @@ impl ProblemReport {
         }
     }
 
+    /// Attach the output of a command to the report. The error is added instead if the command
+    /// cannot be run.
+    pub fn add_command_output(&mut self, label: &'static str, program: &str, args: &[&str]) {
+        match Command::new(program).args(args).output() {
+            Ok(output) => {
+                let mut content = String::from_utf8_lossy(&output.stdout).into_owned();
+                if content.len() > NETWORK_INFO_MAX_BYTES {
+                    let mut end = NETWORK_INFO_MAX_BYTES;
+                    while !content.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    content.truncate(end);
+                }
+                let content = self.redact(&content);
+                self.logs.push((label.to_string(), content));
+            }
+            Err(error) => self.add_error("Failed to collect network information", &error),
+        }
+    }
+
     /// Attach an error to the report.
     pub fn add_error(&mut self, message: &'static str, error: &impl ErrorExt) {
         let redacted_error = self.redact(&error.display_chain());
@@ -389,7 +495,8 @@ impl ProblemReport {
         let out2 = Self::redact_home_dir(&out1);
         let out3 = Self::redact_network_info(&out2);
         let out4 = Self::redact_guids(&out3);
-        self.redact_custom_strings(&out4).to_string()
+        let out5 = self.redact_hostname(&out4);
+        self.redact_custom_strings(&out5).to_string()
     }
 
     fn redact_account_number(input: &str) -> Cow<'_, str> {
@@ -433,6 +540,13 @@ impl ProblemReport {
         RE.replace_all(input, "[REDACTED]")
     }
 
+    fn redact_hostname<'a>(&self, input: &'a str) -> Cow<'a, str> {
+        match &self.hostname {
+            Some(hostname) => Cow::from(input.replace(hostname.as_str(), "[REDACTED HOSTNAME]")),
+            None => Cow::from(input),
+        }
+    }
+
     fn redact_custom_strings<'a>(&self, input: &'a str) -> Cow<'a, str> {
         // Can probably me made a lot faster with aho-corasick if optimization is ever needed.
         let mut out = Cow::from(input);
@@ -641,6 +755,16 @@ mod tests {
         assert_does_not_redact("09:47:59");
     }
 
+    #[test]
+    fn redacts_hostname() {
+        let mut report = ProblemReport::new(vec![]);
+        report.hostname = Some("my-laptop".to_owned());
+        assert_eq!(
+            report.redact("Connecting from my-laptop.local"),
+            "Connecting from [REDACTED HOSTNAME].local"
+        );
+    }
+
     fn assert_redacts(input: &str) {
         let report = ProblemReport::new(vec![]);
         let actual = report.redact(&format!("pre {} post", input));
@@ -0,0 +1,540 @@
+//! Selects relays and endpoints that match a set of constraints.
+//!
+//! Everything in this crate is a pure function of a relay list, the constraints, and an RNG and
+//! relay weights that the caller provides. Seeding the RNG with [`new_rng`] thus makes the
+//! selection reproducible, so that a relay choice can be replayed from the seed, the weights and
+//! the relay list that produced it.
+//!
+//! When changing relay selection, please verify if `docs/relay-selector.md` needs to be
+//! updated as well.
+
+#![deny(rust_2018_idioms)]
+
+use mullvad_types::{
+    location::Location,
+    relay_constraints::{InternalBridgeConstraints, Match},
+    relay_list::{Relay, RelayList, WireguardEndpointData},
+};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use talpid_types::net::{openvpn::ProxySettings, TransportProtocol};
+
+pub mod matcher;
+mod selector;
+
+pub use matcher::{
+    AnyTunnelMatcher, OpenVpnMatcher, RelayMatcher, TunnelMatcher, WireguardMatcher,
+};
+pub use rand::rngs::StdRng;
+pub use selector::{Error, RelaySelector, RelaySelectorResult};
+
+/// Port ranges of the TCP endpoints that every WireGuard relay has, in addition to the ones in
+/// the relay list.
+const WIREGUARD_TCP_PORTS: [(u16, u16); 3] = [(80, 80), (443, 443), (5001, 5001)];
+
+/// Returns an RNG that makes the same choices every time it is created with the same seed, for
+/// the same version of this crate.
+pub fn new_rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+/// Returns all relays in `relay_list`, with their locations set and with WireGuard endpoints over
+/// TCP added.
+pub fn relays_from_list(relay_list: &RelayList) -> Vec<Relay> {
+    let mut relays = Vec::new();
+    for country in &relay_list.countries {
+        for city in &country.cities {
+            for relay in &city.relays {
+                let mut relay_with_location = relay.clone();
+                relay_with_location.location = Some(Location {
+                    country: country.name.clone(),
+                    country_code: country.code.clone(),
+                    city: city.name.clone(),
+                    city_code: city.code.clone(),
+                    latitude: city.latitude,
+                    longitude: city.longitude,
+                });
+
+                for wg_tunnel in &relay.tunnels.wireguard {
+                    relay_with_location
+                        .tunnels
+                        .wireguard
+                        .push(WireguardEndpointData {
+                            protocol: TransportProtocol::Tcp,
+                            port_ranges: WIREGUARD_TCP_PORTS.to_vec(),
+                            ..wg_tunnel.clone()
+                        });
+                }
+
+                relays.push(relay_with_location);
+            }
+        }
+    }
+    relays
+}
+
+/// Returns the active relays that match `matcher`. Only matching endpoints are included in the
+/// returned relays.
+pub fn matching_relays<T: TunnelMatcher>(
+    relays: &[Relay],
+    matcher: &RelayMatcher<T>,
+) -> Vec<Relay> {
+    relays
+        .iter()
+        .filter(|relay| relay.active)
+        .filter_map(|relay| matcher.filter_matching_relay(relay))
+        .collect()
+}
+
+/// Returns `relay` with only the bridges that match `constraints`, or `None` if the relay or
+/// none of its bridges match.
+pub fn matching_bridge_relay(
+    relay: &Relay,
+    constraints: &InternalBridgeConstraints,
+) -> Option<Relay> {
+    if !constraints.location.matches(relay) {
+        return None;
+    }
+    if !constraints.providers.matches(relay) {
+        return None;
+    }
+    if !constraints.ownership.matches(relay) {
+        return None;
+    }
+
+    let mut filtered_relay = relay.clone();
+    filtered_relay
+        .bridges
+        .shadowsocks
+        .retain(|bridge| constraints.transport_protocol.matches_eq(&bridge.protocol));
+    if filtered_relay.bridges.shadowsocks.is_empty() {
+        return None;
+    }
+
+    Some(filtered_relay)
+}
+
+/// Pick a random relay from the given slice, where `weights` holds the weight of each relay.
+/// Will return `None` if the given slice is empty. If all of the relays have a weight of 0, one
+/// will be picked at random without bias, otherwise roulette wheel selection will be used to pick
+/// only relays with non-zero weights.
+pub fn pick_weighted_relay<'a>(
+    relays: &'a [Relay],
+    weights: Vec<u64>,
+    rng: &mut impl Rng,
+) -> Option<&'a Relay> {
+    let total_weight: u64 = weights.iter().sum();
+    if total_weight == 0 {
+        relays.choose(rng)
+    } else {
+        // Pick a random number in the range 1..=total_weight. This choses the relay with a
+        // non-zero weight.
+        let mut i: u64 = rng.gen_range(1, total_weight + 1);
+        Some(
+            relays
+                .iter()
+                .zip(weights)
+                .find(|(_, weight)| {
+                    i = i.saturating_sub(*weight);
+                    i == 0
+                })
+                .map(|(relay, _)| relay)
+                .expect("At least one relay must've had a weight above 0"),
+        )
+    }
+}
+
+/// Picks a random bridge from a relay.
+pub fn pick_bridge(relay: &Relay, rng: &mut impl Rng) -> Option<ProxySettings> {
+    relay
+        .bridges
+        .shadowsocks
+        .choose(rng)
+        .map(|shadowsocks_endpoint| {
+            log::info!(
+                "Selected Shadowsocks bridge {} at {}:{}/{}",
+                relay.hostname,
+                relay.ipv4_addr_in,
+                shadowsocks_endpoint.port,
+                shadowsocks_endpoint.protocol
+            );
+            shadowsocks_endpoint
+                .clone()
+                .to_proxy_settings(relay.ipv4_addr_in.into())
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_types::{
+        endpoint::MullvadEndpoint,
+        location::Location,
+        relay_constraints::{
+            Constraint, LocationConstraint, OpenVpnConstraints, Ownership, PortRange, Providers,
+            RelayConstraints, TransportPort, WireguardConstraints,
+        },
+        relay_list::{
+            OpenVpnEndpointData, RelayBridges, RelayTunnels, ShadowsocksEndpointData,
+            WireguardEndpointData,
+        },
+    };
+    use talpid_types::net::{wireguard::PublicKey, IpVersion, TransportProtocol, TunnelType};
+
+    fn wireguard_data(
+        port_ranges: Vec<(u16, u16)>,
+        protocol: TransportProtocol,
+    ) -> WireguardEndpointData {
+        WireguardEndpointData {
+            port_ranges,
+            ipv4_gateway: "10.64.0.1".parse().unwrap(),
+            ipv6_gateway: "fc00:bbbb:bbbb:bb01::1".parse().unwrap(),
+            public_key: PublicKey::from_base64("BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=")
+                .unwrap(),
+            protocol,
+        }
+    }
+
+    fn relay(hostname: &str, country_code: &str, city_code: &str) -> Relay {
+        Relay {
+            hostname: hostname.to_string(),
+            ipv4_addr_in: "185.213.154.68".parse().unwrap(),
+            ipv6_addr_in: Some("2a03:1b20:5:f011::a09f".parse().unwrap()),
+            include_in_country: true,
+            active: true,
+            owned: true,
+            provider: "31173".to_string(),
+            weight: 1,
+            tunnels: RelayTunnels {
+                openvpn: vec![OpenVpnEndpointData {
+                    port: 1194,
+                    protocol: TransportProtocol::Udp,
+                }],
+                wireguard: vec![wireguard_data(
+                    vec![(53, 53), (4000, 33433)],
+                    TransportProtocol::Udp,
+                )],
+            },
+            bridges: RelayBridges {
+                shadowsocks: vec![ShadowsocksEndpointData {
+                    port: 443,
+                    cipher: "aes-256-gcm".to_string(),
+                    password: "mullvad".to_string(),
+                    protocol: TransportProtocol::Tcp,
+                }],
+            },
+            location: Some(Location {
+                country: country_code.to_string(),
+                country_code: country_code.to_string(),
+                city: city_code.to_string(),
+                city_code: city_code.to_string(),
+                latitude: 0.0,
+                longitude: 0.0,
+            }),
+        }
+    }
+
+    fn wireguard_matcher(constraints: WireguardConstraints) -> RelayMatcher<WireguardMatcher> {
+        RelayMatcher {
+            location: Constraint::Any,
+            providers: Constraint::Any,
+            ownership: Constraint::Any,
+            tunnel: constraints.into(),
+        }
+    }
+
+    fn udp_port(port: Constraint<u16>) -> Constraint<TransportPort> {
+        Constraint::Only(TransportPort {
+            protocol: TransportProtocol::Udp,
+            port,
+        })
+    }
+
+    #[test]
+    fn test_port_inside_port_range() {
+        let relay = relay("se9-wireguard", "se", "got");
+        let matcher = wireguard_matcher(WireguardConstraints {
+            port: udp_port(Constraint::Only(5000)),
+            ..WireguardConstraints::default()
+        });
+        let endpoint = matcher
+            .mullvad_endpoint(&relay, &mut new_rng(0))
+            .expect("no endpoint for port inside a range");
+        assert_eq!(endpoint.to_endpoint().address.port(), 5000);
+
+        let matcher = wireguard_matcher(WireguardConstraints {
+            port: udp_port(Constraint::Only(100)),
+            ..WireguardConstraints::default()
+        });
+        assert!(matcher.filter_matching_relay(&relay).is_none());
+        assert!(matcher.mullvad_endpoint(&relay, &mut new_rng(0)).is_none());
+    }
+
+    #[test]
+    fn test_port_range_intersection() {
+        let relay = relay("se9-wireguard", "se", "got");
+
+        // The range overlaps only the upper end of the second port range of the relay
+        let matcher = wireguard_matcher(WireguardConstraints {
+            port_range: Constraint::Only(PortRange::new(33000, 40000).unwrap()),
+            ..WireguardConstraints::default()
+        });
+        assert!(matcher.filter_matching_relay(&relay).is_some());
+        let mut rng = new_rng(0);
+        for _ in 0..100 {
+            let port = matcher
+                .mullvad_endpoint(&relay, &mut rng)
+                .unwrap()
+                .to_endpoint()
+                .address
+                .port();
+            assert!(
+                (33000..=33433).contains(&port),
+                "port {} outside range",
+                port
+            );
+        }
+
+        // A range consisting of a single port at the boundary of a port range of the relay
+        let matcher = wireguard_matcher(WireguardConstraints {
+            port_range: Constraint::Only(PortRange::new(53, 53).unwrap()),
+            ..WireguardConstraints::default()
+        });
+        let endpoint = matcher.mullvad_endpoint(&relay, &mut new_rng(0)).unwrap();
+        assert_eq!(endpoint.to_endpoint().address.port(), 53);
+
+        // A range between the port ranges of the relay
+        let matcher = wireguard_matcher(WireguardConstraints {
+            port_range: Constraint::Only(PortRange::new(54, 3999).unwrap()),
+            ..WireguardConstraints::default()
+        });
+        assert!(matcher.filter_matching_relay(&relay).is_none());
+        assert!(matcher.mullvad_endpoint(&relay, &mut new_rng(0)).is_none());
+    }
+
+    #[test]
+    fn test_port_range_ignored_for_specific_port() {
+        let relay = relay("se9-wireguard", "se", "got");
+        let matcher = wireguard_matcher(WireguardConstraints {
+            port: udp_port(Constraint::Only(53)),
+            port_range: Constraint::Only(PortRange::new(4000, 5000).unwrap()),
+            ..WireguardConstraints::default()
+        });
+        let endpoint = matcher.mullvad_endpoint(&relay, &mut new_rng(0)).unwrap();
+        assert_eq!(endpoint.to_endpoint().address.port(), 53);
+    }
+
+    #[test]
+    fn test_transport_protocol_mismatch() {
+        let mut relay = relay("se9-wireguard", "se", "got");
+        let matcher = wireguard_matcher(WireguardConstraints {
+            port: Constraint::Only(TransportPort {
+                protocol: TransportProtocol::Tcp,
+                port: Constraint::Any,
+            }),
+            ..WireguardConstraints::default()
+        });
+        assert!(matcher.filter_matching_relay(&relay).is_none());
+
+        relay
+            .tunnels
+            .wireguard
+            .push(wireguard_data(vec![(443, 443)], TransportProtocol::Tcp));
+        let filtered = matcher.filter_matching_relay(&relay).unwrap();
+        assert_eq!(filtered.tunnels.wireguard.len(), 1);
+        assert_eq!(
+            filtered.tunnels.wireguard[0].protocol,
+            TransportProtocol::Tcp
+        );
+        let endpoint = matcher
+            .mullvad_endpoint(&filtered, &mut new_rng(0))
+            .unwrap();
+        assert_eq!(
+            endpoint.unwrap_wireguard().peer.protocol,
+            TransportProtocol::Tcp
+        );
+    }
+
+    #[test]
+    fn test_ipv6_without_address() {
+        let mut relay = relay("se9-wireguard", "se", "got");
+        let matcher = wireguard_matcher(WireguardConstraints {
+            ip_version: Constraint::Only(IpVersion::V6),
+            ..WireguardConstraints::default()
+        });
+        let endpoint = matcher.mullvad_endpoint(&relay, &mut new_rng(0)).unwrap();
+        assert!(endpoint.to_endpoint().address.is_ipv6());
+
+        relay.ipv6_addr_in = None;
+        assert!(matcher.filter_matching_relay(&relay).is_none());
+        assert!(matcher.mullvad_endpoint(&relay, &mut new_rng(0)).is_none());
+    }
+
+    #[test]
+    fn test_multihop_peer_excluded() {
+        let relay = relay("se9-wireguard", "se", "got");
+        let mut matcher = wireguard_matcher(WireguardConstraints::default());
+        assert!(matcher.filter_matching_relay(&relay).is_some());
+        matcher.set_peer(relay.clone());
+        assert!(matcher.filter_matching_relay(&relay).is_none());
+    }
+
+    #[test]
+    fn test_location_provider_and_ownership() {
+        let mut relay = relay("se9-wireguard", "se", "got");
+        let mut matcher = wireguard_matcher(WireguardConstraints::default());
+
+        matcher.location = Constraint::Only(LocationConstraint::City(
+            "se".to_string(),
+            "got".to_string(),
+        ));
+        assert!(matcher.filter_matching_relay(&relay).is_some());
+        matcher.location = Constraint::Only(LocationConstraint::City(
+            "se".to_string(),
+            "sto".to_string(),
+        ));
+        assert!(matcher.filter_matching_relay(&relay).is_none());
+        matcher.location = Constraint::Only(LocationConstraint::Hostname(
+            "se".to_string(),
+            "got".to_string(),
+            "se10-wireguard".to_string(),
+        ));
+        assert!(matcher.filter_matching_relay(&relay).is_none());
+
+        // Relays that are excluded from their country only match more specific locations
+        matcher.location = Constraint::Only(LocationConstraint::Country("se".to_string()));
+        assert!(matcher.filter_matching_relay(&relay).is_some());
+        relay.include_in_country = false;
+        assert!(matcher.filter_matching_relay(&relay).is_none());
+        matcher.location = Constraint::Only(LocationConstraint::Hostname(
+            "se".to_string(),
+            "got".to_string(),
+            "se9-wireguard".to_string(),
+        ));
+        assert!(matcher.filter_matching_relay(&relay).is_some());
+
+        // Every constraint must match
+        matcher.providers = Constraint::Only(
+            Providers::new(vec!["31173".to_string(), "M247".to_string()].into_iter()).unwrap(),
+        );
+        matcher.ownership = Constraint::Only(Ownership::MullvadOwned);
+        assert!(matcher.filter_matching_relay(&relay).is_some());
+        matcher.ownership = Constraint::Only(Ownership::Rented);
+        assert!(matcher.filter_matching_relay(&relay).is_none());
+        matcher.ownership = Constraint::Any;
+        matcher.providers =
+            Constraint::Only(Providers::new(vec!["M247".to_string()].into_iter()).unwrap());
+        assert!(matcher.filter_matching_relay(&relay).is_none());
+    }
+
+    #[test]
+    fn test_any_tunnel_merges_endpoints() {
+        let relay = relay("se9-wireguard", "se", "got");
+
+        let matcher: RelayMatcher<AnyTunnelMatcher> = RelayConstraints::default().into();
+        let filtered = matcher.filter_matching_relay(&relay).unwrap();
+        assert_eq!(filtered.tunnels.openvpn.len(), 1);
+        assert_eq!(filtered.tunnels.wireguard.len(), 1);
+
+        // Only the tunnel type that still has matching endpoints is kept
+        let matcher: RelayMatcher<AnyTunnelMatcher> = RelayConstraints {
+            openvpn_constraints: OpenVpnConstraints {
+                port: Constraint::Only(TransportPort {
+                    protocol: TransportProtocol::Tcp,
+                    port: Constraint::Any,
+                }),
+            },
+            ..RelayConstraints::default()
+        }
+        .into();
+        let filtered = matcher.filter_matching_relay(&relay).unwrap();
+        assert!(filtered.tunnels.openvpn.is_empty());
+        assert_eq!(filtered.tunnels.wireguard.len(), 1);
+
+        // An explicit tunnel type ignores endpoints of the other type
+        let matcher: RelayMatcher<AnyTunnelMatcher> = RelayConstraints {
+            tunnel_protocol: Constraint::Only(TunnelType::OpenVpn),
+            wireguard_constraints: WireguardConstraints {
+                port: udp_port(Constraint::Only(100)),
+                ..WireguardConstraints::default()
+            },
+            ..RelayConstraints::default()
+        }
+        .into();
+        let filtered = matcher.filter_matching_relay(&relay).unwrap();
+        assert_eq!(filtered.tunnels.openvpn.len(), 1);
+        assert!(filtered.tunnels.wireguard.is_empty());
+        let endpoint = matcher
+            .mullvad_endpoint(&filtered, &mut new_rng(0))
+            .unwrap();
+        assert!(matches!(endpoint, MullvadEndpoint::OpenVpn(_)));
+    }
+
+    #[test]
+    fn test_inactive_relays_not_matched() {
+        let mut relays = vec![
+            relay("se9-wireguard", "se", "got"),
+            relay("se10-wireguard", "se", "got"),
+        ];
+        relays[0].active = false;
+        let matching =
+            matching_relays(&relays, &wireguard_matcher(WireguardConstraints::default()));
+        assert_eq!(matching.len(), 1);
+        assert_eq!(matching[0].hostname, "se10-wireguard");
+    }
+
+    #[test]
+    fn test_weighted_pick() {
+        let relays = vec![
+            relay("se9-wireguard", "se", "got"),
+            relay("se10-wireguard", "se", "got"),
+        ];
+        let mut rng = new_rng(0);
+        for _ in 0..100 {
+            let picked = pick_weighted_relay(&relays, vec![0, 1], &mut rng).unwrap();
+            assert_eq!(picked.hostname, "se10-wireguard");
+        }
+        assert!(pick_weighted_relay(&relays, vec![0, 0], &mut rng).is_some());
+        assert!(pick_weighted_relay(&[], vec![], &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_same_seed_same_selection() {
+        let relays: Vec<Relay> = (0..20)
+            .map(|i| relay(&format!("se{}-wireguard", i), "se", "got"))
+            .collect();
+        let matcher = wireguard_matcher(WireguardConstraints::default());
+
+        let select = |seed| {
+            let mut rng = new_rng(seed);
+            (0..10)
+                .map(|_| {
+                    let relay =
+                        pick_weighted_relay(&relays, vec![1; relays.len()], &mut rng).unwrap();
+                    let endpoint = matcher.mullvad_endpoint(relay, &mut rng).unwrap();
+                    (relay.hostname.clone(), endpoint.to_endpoint())
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(select(1234), select(1234));
+        assert_ne!(select(1234), select(4321));
+    }
+
+    #[test]
+    fn test_bridge_ownership_constraint() {
+        let relay = relay("se-got-br-001", "se", "got");
+        let mut constraints = InternalBridgeConstraints {
+            location: Constraint::Any,
+            providers: Constraint::Any,
+            ownership: Constraint::Only(Ownership::MullvadOwned),
+            transport_protocol: Constraint::Only(TransportProtocol::Tcp),
+        };
+        assert!(matching_bridge_relay(&relay, &constraints).is_some());
+
+        constraints.ownership = Constraint::Only(Ownership::Rented);
+        assert!(matching_bridge_relay(&relay, &constraints).is_none());
+
+        constraints.ownership = Constraint::Any;
+        constraints.transport_protocol = Constraint::Only(TransportProtocol::Udp);
+        assert!(matching_bridge_relay(&relay, &constraints).is_none());
+    }
+}
@@ -1,8 +1,8 @@
 use mullvad_types::{
     endpoint::{MullvadEndpoint, MullvadWireguardEndpoint},
     relay_constraints::{
-        Constraint, LocationConstraint, Match, OpenVpnConstraints, Providers, RelayConstraints,
-        TransportPort, WireguardConstraints,
+        Constraint, LocationConstraint, Match, OpenVpnConstraints, Ownership, PortRange, Providers,
+        RelayConstraints, TransportPort, WireguardConstraints,
     },
     relay_list::{Relay, RelayTunnels, WireguardEndpointData},
 };
@@ -14,6 +14,7 @@ use talpid_types::net::{all_of_the_internet, wireguard, IpVersion, TunnelType};
 pub struct RelayMatcher<T: TunnelMatcher> {
     pub location: Constraint<LocationConstraint>,
     pub providers: Constraint<Providers>,
+    pub ownership: Constraint<Ownership>,
     pub tunnel: T,
 }
 
@@ -22,6 +23,7 @@ impl From<RelayConstraints> for RelayMatcher<AnyTunnelMatcher> {
         Self {
             location: constraints.location,
             providers: constraints.providers,
+            ownership: constraints.ownership,
             tunnel: AnyTunnelMatcher {
                 wireguard: constraints.wireguard_constraints.into(),
                 openvpn: constraints.openvpn_constraints,
@@ -37,6 +39,7 @@ impl RelayMatcher<AnyTunnelMatcher> {
             tunnel: self.tunnel.wireguard,
             location: self.location,
             providers: self.providers,
+            ownership: self.ownership,
         }
     }
 }
@@ -51,15 +54,18 @@ impl<T: TunnelMatcher> RelayMatcher<T> {
     /// Filter a relay and its endpoints based on constraints.
     /// Only matching endpoints are included in the returned Relay.
     pub fn filter_matching_relay(&self, relay: &Relay) -> Option<Relay> {
-        if !self.location.matches(relay) || !self.providers.matches(relay) {
+        if !self.location.matches(relay)
+            || !self.providers.matches(relay)
+            || !self.ownership.matches(relay)
+        {
             return None;
         }
 
         self.tunnel.filter_matching_endpoints(relay)
     }
 
-    pub fn mullvad_endpoint(&self, relay: &Relay) -> Option<MullvadEndpoint> {
-        self.tunnel.mullvad_endpoint(relay)
+    pub fn mullvad_endpoint(&self, relay: &Relay, rng: &mut impl Rng) -> Option<MullvadEndpoint> {
+        self.tunnel.mullvad_endpoint(relay, rng)
     }
 }
 
@@ -71,8 +77,8 @@ pub trait TunnelMatcher: Clone {
     /// Only matching endpoints are included in the returned Relay.
     fn filter_matching_endpoints(&self, relay: &Relay) -> Option<Relay>;
     /// Constructs a MullvadEndpoint for a given Relay using extra data from the relay matcher
-    /// itself.
-    fn mullvad_endpoint(&self, relay: &Relay) -> Option<MullvadEndpoint>;
+    /// itself. Any random choices are made with `rng`.
+    fn mullvad_endpoint(&self, relay: &Relay, rng: &mut impl Rng) -> Option<MullvadEndpoint>;
 }
 
 impl TunnelMatcher for OpenVpnMatcher {
@@ -95,11 +101,11 @@ impl TunnelMatcher for OpenVpnMatcher {
         Some(relay)
     }
 
-    fn mullvad_endpoint(&self, relay: &Relay) -> Option<MullvadEndpoint> {
+    fn mullvad_endpoint(&self, relay: &Relay, rng: &mut impl Rng) -> Option<MullvadEndpoint> {
         relay
             .tunnels
             .openvpn
-            .choose(&mut rand::thread_rng())
+            .choose(rng)
             .cloned()
             .map(|endpoint| endpoint.into_mullvad_endpoint(relay.ipv4_addr_in.into()))
     }
@@ -141,24 +147,24 @@ impl TunnelMatcher for AnyTunnelMatcher {
         }
     }
 
-    fn mullvad_endpoint(&self, relay: &Relay) -> Option<MullvadEndpoint> {
+    fn mullvad_endpoint(&self, relay: &Relay, rng: &mut impl Rng) -> Option<MullvadEndpoint> {
         #[cfg(not(target_os = "android"))]
         match self.tunnel_type {
             Constraint::Any => vec![
-                self.openvpn.mullvad_endpoint(relay),
-                self.wireguard.mullvad_endpoint(relay),
+                self.openvpn.mullvad_endpoint(relay, rng),
+                self.wireguard.mullvad_endpoint(relay, rng),
             ]
             .into_iter()
             .filter_map(|relay| relay)
             .collect::<Vec<_>>()
-            .choose(&mut rand::thread_rng())
+            .choose(rng)
             .cloned(),
-            Constraint::Only(TunnelType::OpenVpn) => self.openvpn.mullvad_endpoint(relay),
-            Constraint::Only(TunnelType::Wireguard) => self.wireguard.mullvad_endpoint(relay),
+            Constraint::Only(TunnelType::OpenVpn) => self.openvpn.mullvad_endpoint(relay, rng),
+            Constraint::Only(TunnelType::Wireguard) => self.wireguard.mullvad_endpoint(relay, rng),
         }
 
         #[cfg(target_os = "android")]
-        self.wireguard.mullvad_endpoint(relay)
+        self.wireguard.mullvad_endpoint(relay, rng)
     }
 }
 
@@ -168,6 +174,7 @@ pub struct WireguardMatcher {
     /// It's stored here so we can exclude it from further selections being made.
     pub peer: Option<Relay>,
     pub port: Constraint<TransportPort>,
+    pub port_range: Constraint<PortRange>,
     pub ip_version: Constraint<IpVersion>,
 }
 
@@ -176,9 +183,10 @@ impl WireguardMatcher {
         &self,
         relay: &Relay,
         data: WireguardEndpointData,
+        rng: &mut impl Rng,
     ) -> Option<MullvadEndpoint> {
         let host = self.get_address_for_wireguard_relay(relay)?;
-        let port = self.get_port_for_wireguard_relay(&data)?;
+        let port = self.get_port_for_wireguard_relay(&data, rng)?;
         let peer_config = wireguard::PeerConfig {
             public_key: data.public_key,
             endpoint: SocketAddr::new(host, port),
@@ -200,7 +208,11 @@ impl WireguardMatcher {
         }
     }
 
-    fn get_port_for_wireguard_relay(&self, data: &WireguardEndpointData) -> Option<u16> {
+    fn get_port_for_wireguard_relay(
+        &self,
+        data: &WireguardEndpointData,
+        rng: &mut impl Rng,
+    ) -> Option<u16> {
         match self
             .port
             .as_ref()
@@ -208,17 +220,18 @@ impl WireguardMatcher {
             .unwrap_or(Constraint::Any)
         {
             Constraint::Any => {
+                let port_ranges = self.available_port_ranges(data);
                 let get_port_amount =
                     |range: &(u16, u16)| -> u64 { (1 + range.1 - range.0) as u64 };
-                let port_amount: u64 = data.port_ranges.iter().map(get_port_amount).sum();
+                let port_amount: u64 = port_ranges.iter().map(get_port_amount).sum();
 
                 if port_amount < 1 {
                     return None;
                 }
 
-                let mut port_index = rand::thread_rng().gen_range(0, port_amount);
+                let mut port_index = rng.gen_range(0, port_amount);
 
-                for range in data.port_ranges.iter() {
+                for range in port_ranges.iter() {
                     let ports_in_range = get_port_amount(range);
                     if port_index < ports_in_range {
                         return Some(port_index as u16 + range.0);
@@ -241,6 +254,18 @@ impl WireguardMatcher {
             }
         }
     }
+
+    /// Returns the port ranges of an endpoint that are allowed by the port range constraint.
+    fn available_port_ranges(&self, data: &WireguardEndpointData) -> Vec<(u16, u16)> {
+        match self.port_range {
+            Constraint::Any => data.port_ranges.clone(),
+            Constraint::Only(port_range) => data
+                .port_ranges
+                .iter()
+                .filter_map(|range| port_range.intersection(*range))
+                .collect(),
+        }
+    }
 }
 
 impl From<WireguardConstraints> for WireguardMatcher {
@@ -248,6 +273,7 @@ impl From<WireguardConstraints> for WireguardMatcher {
         Self {
             peer: None,
             port: constraints.port,
+            port_range: constraints.port_range,
             ip_version: constraints.ip_version,
         }
     }
@@ -255,20 +281,23 @@ impl From<WireguardConstraints> for WireguardMatcher {
 
 impl Match<WireguardEndpointData> for WireguardMatcher {
     fn matches(&self, endpoint: &WireguardEndpointData) -> bool {
-        match self.port {
-            Constraint::Any => true,
+        let port = match self.port {
+            Constraint::Any => Constraint::Any,
             Constraint::Only(TransportPort { port, protocol }) => {
                 if protocol != endpoint.protocol {
                     return false;
                 }
-                match port {
-                    Constraint::Any => true,
-                    Constraint::Only(port) => endpoint
-                        .port_ranges
-                        .iter()
-                        .any(|range| (port >= range.0 && port <= range.1)),
-                }
+                port
             }
+        };
+        match port {
+            Constraint::Any => {
+                self.port_range.is_any() || !self.available_port_ranges(endpoint).is_empty()
+            }
+            Constraint::Only(port) => endpoint
+                .port_ranges
+                .iter()
+                .any(|range| (port >= range.0 && port <= range.1)),
         }
     }
 }
@@ -283,6 +312,9 @@ impl TunnelMatcher for WireguardMatcher {
         {
             return None;
         }
+        if self.ip_version == Constraint::Only(IpVersion::V6) && relay.ipv6_addr_in.is_none() {
+            return None;
+        }
 
         let tunnels = relay
             .tunnels
@@ -302,7 +334,7 @@ impl TunnelMatcher for WireguardMatcher {
         Some(relay)
     }
 
-    fn mullvad_endpoint(&self, relay: &Relay) -> Option<MullvadEndpoint> {
+    fn mullvad_endpoint(&self, relay: &Relay, rng: &mut impl Rng) -> Option<MullvadEndpoint> {
         let valid_relays = relay
             .tunnels
             .wireguard
@@ -313,7 +345,7 @@ impl TunnelMatcher for WireguardMatcher {
             })
             .collect::<Vec<_>>();
         valid_relays
-            .choose(&mut rand::thread_rng())
-            .and_then(|wg_tunnel| self.wg_data_to_endpoint(relay, (*wg_tunnel).clone()))
+            .choose(rng)
+            .and_then(|wg_tunnel| self.wg_data_to_endpoint(relay, (*wg_tunnel).clone(), rng))
     }
 }
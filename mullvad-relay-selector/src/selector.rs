@@ -0,0 +1,1568 @@
+//! Selects a relay, an endpoint and a bridge for a connection attempt, given the relay
+//! constraints and how many attempts have failed so far.
+
+use crate::{
+    matching_bridge_relay, matching_relays, pick_bridge, pick_weighted_relay, RelayMatcher, StdRng,
+    TunnelMatcher, WireguardMatcher,
+};
+use ipnetwork::IpNetwork;
+use mullvad_types::{
+    endpoint::{MullvadEndpoint, MullvadWireguardEndpoint},
+    location::Location,
+    relay_constraints::{
+        BridgeState, Constraint, InternalBridgeConstraints, LocationConstraint, Match,
+        OpenVpnConstraints, Ownership, PortRange, Providers, RelayConstraints, Set, TransportPort,
+        WireguardConstraints,
+    },
+    relay_list::Relay,
+};
+use std::{cell::RefCell, cmp::Ordering, net::IpAddr};
+use talpid_types::net::{
+    openvpn::ProxySettings, wireguard, IpVersion, TransportProtocol, TunnelType,
+};
+
+const DEFAULT_WIREGUARD_PORT: u16 = 51820;
+const WIREGUARD_EXIT_CONSTRAINTS: WireguardMatcher = WireguardMatcher {
+    peer: None,
+    port: Constraint::Only(TransportPort {
+        protocol: TransportProtocol::Udp,
+        port: Constraint::Only(DEFAULT_WIREGUARD_PORT),
+    }),
+    port_range: Constraint::Any,
+    ip_version: Constraint::Only(IpVersion::V4),
+};
+/// Ports that OpenVPN connection attempts cycle through when the port is not constrained.
+const OPENVPN_UDP_PORTS: [u16; 2] = [1194, 1300];
+const OPENVPN_TCP_PORTS: [u16; 2] = [443, 80];
+
+/// Number of connection attempts made with each relay fallback tier before moving on to the next
+/// one. This covers a full cycle of the preferred ports.
+const RELAY_FALLBACK_ATTEMPTS_PER_TIER: u32 = 4;
+
+/// Estimated round-trip time per kilometer of path. Light travels about 200 km per millisecond in
+/// fiber, and actual routes are rarely straight lines.
+const ESTIMATED_RTT_MS_PER_KM: f64 = 0.015;
+/// Bridges whose estimated path latency is within this margin of the lowest one are considered
+/// equally good, and one of them is picked at random.
+const BRIDGE_LATENCY_MARGIN_MS: f64 = 5.0;
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "No relays matching current constraints")]
+    NoRelay,
+}
+
+/// Selects relays from a snapshot of the relay list. All random choices are made with the RNG
+/// that is passed in, so the same RNG state, relays and weights always yield the same selection.
+pub struct RelaySelector<'a> {
+    relays: &'a [Relay],
+    rng: RefCell<&'a mut StdRng>,
+    /// Returns the weight of each relay when picking one at random.
+    weights: Option<&'a dyn Fn(&[Relay]) -> Vec<u64>>,
+    /// Latitude and longitude of this device, used to estimate the latency through bridges.
+    device_coordinates: Option<(f64, f64)>,
+}
+
+impl<'a> RelaySelector<'a> {
+    /// Returns a selector that picks among `relays`, which must have their locations set, as
+    /// done by [`crate::relays_from_list`].
+    pub fn new(relays: &'a [Relay], rng: &'a mut StdRng) -> Self {
+        RelaySelector {
+            relays,
+            rng: RefCell::new(rng),
+            weights: None,
+            device_coordinates: None,
+        }
+    }
+
+    /// Sets the function that computes the weight of each relay in a list of candidates. By
+    /// default, the weights from the relay list are used.
+    pub fn set_weights(&mut self, weights: &'a dyn Fn(&[Relay]) -> Vec<u64>) {
+        self.weights = Some(weights);
+    }
+
+    /// Sets the location of this device, which is used to estimate the latency to bridges.
+    pub fn set_device_coordinates(&mut self, coordinates: Option<(f64, f64)>) {
+        self.device_coordinates = coordinates;
+    }
+
+    /// Returns a random relay and relay endpoint matching the given constraints and with
+    /// preferences applied.
+    pub fn get_tunnel_endpoint(
+        &self,
+        relay_constraints: &RelayConstraints,
+        bridge_state: BridgeState,
+        retry_attempt: u32,
+        wg_key_exists: bool,
+    ) -> Result<RelaySelectorResult, Error> {
+        match relay_constraints.tunnel_protocol {
+            Constraint::Only(TunnelType::OpenVpn) => self.get_openvpn_endpoint(
+                &relay_constraints.location,
+                &relay_constraints.providers,
+                relay_constraints.ownership,
+                relay_constraints.openvpn_constraints.clone(),
+                bridge_state,
+                retry_attempt,
+            ),
+
+            Constraint::Only(TunnelType::Wireguard) => self.get_wireguard_endpoint(
+                &relay_constraints.location,
+                &relay_constraints.providers,
+                relay_constraints.ownership,
+                &relay_constraints.wireguard_constraints,
+                retry_attempt,
+            ),
+            Constraint::Any => self.get_any_tunnel_endpoint(
+                relay_constraints,
+                bridge_state,
+                retry_attempt,
+                wg_key_exists,
+            ),
+        }
+    }
+
+    /// Returns a random relay and relay endpoint matching one of the given ordered constraint
+    /// tiers, along with the index of the tier that was used. Every
+    /// `RELAY_FALLBACK_ATTEMPTS_PER_TIER` attempts, selection moves on to the next tier. Tiers
+    /// that no relay matches are skipped.
+    pub fn get_tunnel_endpoint_from_tiers(
+        &self,
+        tiers: &[RelayConstraints],
+        bridge_state: BridgeState,
+        retry_attempt: u32,
+        wg_key_exists: bool,
+    ) -> Result<(usize, RelaySelectorResult), Error> {
+        let first_tier = std::cmp::min(
+            (retry_attempt / RELAY_FALLBACK_ATTEMPTS_PER_TIER) as usize,
+            tiers.len().saturating_sub(1),
+        );
+
+        for (tier, constraints) in tiers.iter().enumerate().skip(first_tier) {
+            match self.get_tunnel_endpoint(constraints, bridge_state, retry_attempt, wg_key_exists)
+            {
+                Ok(result) => return Ok((tier, result)),
+                Err(_) => log::debug!("No relay matches constraint tier {}: {}", tier, constraints),
+            }
+        }
+        Err(Error::NoRelay)
+    }
+
+    /// Returns an OpenVpn endpoint, should only ever be used when the user has specified the tunnel
+    /// protocol as only OpenVPN.
+    fn get_openvpn_endpoint(
+        &self,
+        location: &Constraint<LocationConstraint>,
+        providers: &Constraint<Providers>,
+        ownership: Constraint<Ownership>,
+        openvpn_constraints: OpenVpnConstraints,
+        bridge_state: BridgeState,
+        retry_attempt: u32,
+    ) -> Result<RelaySelectorResult, Error> {
+        let mut relay_matcher = RelayMatcher {
+            location: location.clone(),
+            providers: providers.clone(),
+            ownership,
+            tunnel: openvpn_constraints,
+        };
+
+        if relay_matcher.tunnel.port.is_any() && bridge_state == BridgeState::On {
+            relay_matcher.tunnel.port = Constraint::Only(TransportPort {
+                protocol: TransportProtocol::Tcp,
+                port: Constraint::Any,
+            });
+
+            return self.get_tunnel_endpoint_internal(&relay_matcher);
+        }
+
+        // Cycle through the transports and ports that are not constrained, so that a relay that
+        // keeps failing is retried over a different transport.
+        let preferred_protocol = match relay_matcher.tunnel.port {
+            Constraint::Any => Self::preferred_openvpn_constraints(retry_attempt).1,
+            Constraint::Only(TransportPort {
+                protocol,
+                port: Constraint::Any,
+            }) => protocol,
+            Constraint::Only(_) => return self.get_tunnel_endpoint_internal(&relay_matcher),
+        };
+
+        let mut protocol_relay_matcher = relay_matcher.clone();
+        protocol_relay_matcher.tunnel.port = Constraint::Only(TransportPort {
+            protocol: preferred_protocol,
+            port: Constraint::Any,
+        });
+        let mut preferred_relay_matcher = relay_matcher.clone();
+        preferred_relay_matcher.tunnel.port = Constraint::Only(TransportPort {
+            protocol: preferred_protocol,
+            port: Self::preferred_openvpn_port(preferred_protocol, retry_attempt),
+        });
+
+        self.get_tunnel_endpoint_internal(&preferred_relay_matcher)
+            .or_else(|_| self.get_tunnel_endpoint_internal(&protocol_relay_matcher))
+            .or_else(|_| self.get_tunnel_endpoint_internal(&relay_matcher))
+    }
+
+    fn get_wireguard_multi_hop_endpoint(
+        &self,
+        mut entry_matcher: RelayMatcher<WireguardMatcher>,
+        exit_location: Constraint<LocationConstraint>,
+    ) -> Result<RelaySelectorResult, Error> {
+        let mut exit_matcher = RelayMatcher {
+            location: exit_location,
+            tunnel: WIREGUARD_EXIT_CONSTRAINTS.clone().into(),
+            ..entry_matcher.clone()
+        };
+
+        let (exit_relay, entry_relay, exit_endpoint, mut entry_endpoint) =
+            if entry_matcher.location.is_subset(&exit_matcher.location) {
+                let (entry_relay, entry_endpoint) = self.get_entry_endpoint(&entry_matcher)?;
+                exit_matcher.set_peer(entry_relay.clone());
+                let exit_result = self.get_tunnel_endpoint_internal(&exit_matcher)?;
+                (
+                    exit_result.exit_relay,
+                    entry_relay,
+                    exit_result.endpoint,
+                    entry_endpoint,
+                )
+            } else {
+                let exit_result = self.get_tunnel_endpoint_internal(&exit_matcher)?;
+
+                entry_matcher.set_peer(exit_result.exit_relay.clone());
+                let (entry_relay, entry_endpoint) = self.get_entry_endpoint(&entry_matcher)?;
+                (
+                    exit_result.exit_relay,
+                    entry_relay,
+                    exit_result.endpoint,
+                    entry_endpoint,
+                )
+            };
+
+        Self::set_entry_peers(&exit_endpoint.unwrap_wireguard().peer, &mut entry_endpoint);
+
+        log::info!(
+            "Selected entry relay {} at {} going through {} at {}",
+            entry_relay.hostname,
+            entry_endpoint.peer.endpoint.ip(),
+            exit_relay.hostname,
+            exit_endpoint.to_endpoint().address.ip(),
+        );
+        let result = RelaySelectorResult::wireguard_multihop_endpoint(
+            exit_relay,
+            entry_endpoint,
+            entry_relay,
+        );
+        return Ok(result);
+    }
+
+    /// Returns a WireGuard endpoint, should only ever be used when the user has specified the
+    /// tunnel protocol as only WireGuard.
+    fn get_wireguard_endpoint(
+        &self,
+        location: &Constraint<LocationConstraint>,
+        providers: &Constraint<Providers>,
+        ownership: Constraint<Ownership>,
+        wireguard_constraints: &WireguardConstraints,
+        retry_attempt: u32,
+    ) -> Result<RelaySelectorResult, Error> {
+        let mut entry_relay_matcher = RelayMatcher {
+            location: location.clone(),
+            providers: providers.clone(),
+            ownership,
+            tunnel: wireguard_constraints.clone().into(),
+        };
+
+        let mut preferred_matcher: RelayMatcher<WireguardMatcher> = entry_relay_matcher.clone();
+        preferred_matcher.tunnel.port =
+            preferred_matcher
+                .tunnel
+                .port
+                .or(Self::preferred_wireguard_port(
+                    retry_attempt,
+                    &wireguard_constraints.port_range,
+                ));
+        preferred_matcher.tunnel.ip_version =
+            Self::preferred_wireguard_ip_version(retry_attempt, wireguard_constraints.ip_version);
+
+        if !wireguard_constraints.use_multihop {
+            return self
+                .get_tunnel_endpoint_internal(&preferred_matcher)
+                .or_else(|_| self.get_tunnel_endpoint_internal(&entry_relay_matcher));
+        }
+
+        entry_relay_matcher.location = wireguard_constraints.entry_location.clone();
+        entry_relay_matcher.tunnel.port =
+            entry_relay_matcher
+                .tunnel
+                .port
+                .or(Self::preferred_wireguard_port(
+                    retry_attempt,
+                    &wireguard_constraints.port_range,
+                ));
+        self.get_wireguard_multi_hop_endpoint(entry_relay_matcher, location.clone())
+    }
+
+    /// Returns a tunnel endpoint of any type, should only be used when the user hasn't specified a
+    /// tunnel protocol.
+    fn get_any_tunnel_endpoint(
+        &self,
+        relay_constraints: &RelayConstraints,
+        bridge_state: BridgeState,
+        retry_attempt: u32,
+        wg_key_exists: bool,
+    ) -> Result<RelaySelectorResult, Error> {
+        let preferred_constraints = self.preferred_constraints(
+            &relay_constraints,
+            bridge_state,
+            retry_attempt,
+            wg_key_exists,
+        );
+        let original_matcher: RelayMatcher<_> = relay_constraints.clone().into();
+
+        let preferred_tunnel_protocol = preferred_constraints.tunnel_protocol;
+        let preferred_matcher: RelayMatcher<_> = preferred_constraints.into();
+
+        match preferred_tunnel_protocol {
+            Constraint::Only(TunnelType::Wireguard)
+                if relay_constraints.wireguard_constraints.use_multihop =>
+            {
+                let exit_location = relay_constraints.location.clone();
+                let mut preferred_entry_matcher = preferred_matcher.to_wireguard_matcher();
+                preferred_entry_matcher.location = relay_constraints
+                    .wireguard_constraints
+                    .entry_location
+                    .clone();
+                let mut original_entry_matcher = original_matcher.to_wireguard_matcher();
+                original_entry_matcher.location = relay_constraints
+                    .wireguard_constraints
+                    .entry_location
+                    .clone();
+                self.get_wireguard_multi_hop_endpoint(
+                    preferred_entry_matcher,
+                    exit_location.clone(),
+                )
+                .or_else(|_| {
+                    self.get_wireguard_multi_hop_endpoint(original_entry_matcher, exit_location)
+                })
+            }
+
+            _ => {
+                if let Ok(result) = self.get_tunnel_endpoint_internal(&preferred_matcher) {
+                    log::debug!(
+                        "Relay matched on highest preference for retry attempt {}",
+                        retry_attempt
+                    );
+                    Ok(result)
+                } else if let Ok(result) = self.get_tunnel_endpoint_internal(&original_matcher) {
+                    log::debug!(
+                        "Relay matched on second preference for retry attempt {}",
+                        retry_attempt
+                    );
+                    Ok(result)
+                } else {
+                    log::warn!("No relays matching {}", &relay_constraints);
+                    Err(Error::NoRelay)
+                }
+            }
+        }
+    }
+
+    // This function ignores the tunnel type constraint on purpose.
+    fn preferred_constraints(
+        &self,
+        original_constraints: &RelayConstraints,
+        bridge_state: BridgeState,
+        retry_attempt: u32,
+        wg_key_exists: bool,
+    ) -> RelayConstraints {
+        let (preferred_port, preferred_protocol, preferred_tunnel) = self
+            .preferred_tunnel_constraints(
+                retry_attempt,
+                &original_constraints.location,
+                &original_constraints.providers,
+                original_constraints.ownership,
+                wg_key_exists,
+            );
+
+        let mut relay_constraints = original_constraints.clone();
+        relay_constraints.openvpn_constraints = Default::default();
+
+        // Highest priority preference. Where we prefer OpenVPN using UDP. But without changing
+        // any constraints that are explicitly specified.
+        match original_constraints.tunnel_protocol {
+            // If no tunnel protocol is selected, use preferred constraints
+            Constraint::Any => {
+                if bridge_state == BridgeState::On {
+                    relay_constraints.openvpn_constraints = OpenVpnConstraints {
+                        port: Constraint::Only(TransportPort {
+                            protocol: TransportProtocol::Tcp,
+                            port: Constraint::Any,
+                        }),
+                    };
+                } else if original_constraints.openvpn_constraints.port.is_any() {
+                    relay_constraints.openvpn_constraints = OpenVpnConstraints {
+                        port: Constraint::Only(TransportPort {
+                            protocol: preferred_protocol,
+                            port: preferred_port,
+                        }),
+                    };
+                } else {
+                    relay_constraints.openvpn_constraints =
+                        original_constraints.openvpn_constraints;
+                }
+
+                if relay_constraints.wireguard_constraints.port.is_any() {
+                    relay_constraints.wireguard_constraints.port =
+                        Constraint::Only(TransportPort {
+                            protocol: preferred_protocol,
+                            port: Self::limit_preferred_wireguard_port(
+                                preferred_port,
+                                &original_constraints.wireguard_constraints.port_range,
+                            ),
+                        });
+                }
+
+                relay_constraints.tunnel_protocol = Constraint::Only(preferred_tunnel);
+            }
+            Constraint::Only(TunnelType::OpenVpn) => {
+                let openvpn_constraints = &mut relay_constraints.openvpn_constraints;
+                *openvpn_constraints = original_constraints.openvpn_constraints;
+                if bridge_state == BridgeState::On && openvpn_constraints.port.is_any() {
+                    openvpn_constraints.port = Constraint::Only(TransportPort {
+                        protocol: TransportProtocol::Tcp,
+                        port: Constraint::Any,
+                    });
+                } else if openvpn_constraints.port.is_any() {
+                    let (preferred_port, preferred_protocol) =
+                        Self::preferred_openvpn_constraints(retry_attempt);
+                    openvpn_constraints.port = Constraint::Only(TransportPort {
+                        protocol: preferred_protocol,
+                        port: preferred_port,
+                    });
+                }
+            }
+            Constraint::Only(TunnelType::Wireguard) => {
+                relay_constraints.wireguard_constraints =
+                    original_constraints.wireguard_constraints.clone();
+                if relay_constraints.wireguard_constraints.port.is_any() {
+                    relay_constraints.wireguard_constraints.port = Self::preferred_wireguard_port(
+                        retry_attempt,
+                        &original_constraints.wireguard_constraints.port_range,
+                    );
+                }
+            }
+        };
+
+        if relay_constraints.wireguard_constraints.port.is_any() {
+            relay_constraints.wireguard_constraints.port = Constraint::Only(TransportPort {
+                port: Self::limit_preferred_wireguard_port(
+                    preferred_port,
+                    &original_constraints.wireguard_constraints.port_range,
+                ),
+                protocol: TransportProtocol::Udp,
+            });
+        }
+
+        relay_constraints.tunnel_protocol = Constraint::Only(preferred_tunnel);
+
+        relay_constraints
+    }
+
+    fn get_entry_endpoint(
+        &self,
+        matcher: &RelayMatcher<WireguardMatcher>,
+    ) -> Result<(Relay, MullvadWireguardEndpoint), Error> {
+        let matching_relays = matching_relays(self.relays, matcher);
+
+        let mut rng = self.rng.borrow_mut();
+        let relay = self
+            .pick_random_relay(&matching_relays, &mut **rng)
+            .map(|relay| relay.clone())
+            .ok_or(Error::NoRelay)?;
+        let endpoint = matcher
+            .mullvad_endpoint(&relay, &mut **rng)
+            .ok_or(Error::NoRelay)?
+            .unwrap_wireguard()
+            .clone();
+
+        Ok((relay, endpoint))
+    }
+
+    fn set_entry_peers(
+        exit_peer: &wireguard::PeerConfig,
+        entry_endpoint: &mut MullvadWireguardEndpoint,
+    ) {
+        entry_endpoint.peer.allowed_ips = vec![IpNetwork::from(exit_peer.endpoint.ip())];
+        entry_endpoint.exit_peer = Some(exit_peer.clone());
+    }
+
+    pub fn get_auto_proxy_settings(
+        &self,
+        bridge_constraints: &InternalBridgeConstraints,
+        location: &Location,
+        retry_attempt: u32,
+    ) -> Option<(ProxySettings, Relay)> {
+        if !Self::should_use_bridge(retry_attempt) {
+            return None;
+        }
+
+        // For now, only TCP tunnels are supported.
+        if let Constraint::Only(TransportProtocol::Udp) = bridge_constraints.transport_protocol {
+            return None;
+        }
+
+        self.get_proxy_settings(bridge_constraints, location)
+    }
+
+    pub fn should_use_bridge(retry_attempt: u32) -> bool {
+        // shouldn't use a bridge for the first 3 times
+        retry_attempt > 3 &&
+            // i.e. 4th and 5th with bridge, 6th & 7th without
+            // The test is to see whether the current _couple of connections_ is even or not.
+            // | retry_attempt                | 4 | 5 | 6 | 7 | 8 | 9 |
+            // | (retry_attempt % 4) < 2      | t | t | f | f | t | t |
+            (retry_attempt % 4) < 2
+    }
+
+    pub fn get_proxy_settings(
+        &self,
+        constraints: &InternalBridgeConstraints,
+        location: &Location,
+    ) -> Option<(ProxySettings, Relay)> {
+        let mut matching_relays: Vec<(f64, Relay)> = self
+            .relays
+            .iter()
+            .filter(|relay| relay.active)
+            .filter_map(|relay| matching_bridge_relay(relay, constraints))
+            .map(|relay| {
+                let latency =
+                    Self::estimate_bridge_latency(&relay, location, self.device_coordinates);
+                (latency, relay)
+            })
+            .collect();
+
+        matching_relays.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let lowest_latency = matching_relays.first()?.0;
+        let best_relays: Vec<Relay> = matching_relays
+            .into_iter()
+            .take_while(|(latency, _)| *latency <= lowest_latency + BRIDGE_LATENCY_MARGIN_MS)
+            .map(|(_, relay)| relay)
+            .collect();
+
+        let mut rng = self.rng.borrow_mut();
+        let relay = self.pick_random_relay(&best_relays, &mut **rng)?;
+        log::debug!(
+            "Estimated latency through bridge {}: {:.0} ms",
+            relay.hostname,
+            Self::estimate_bridge_latency(relay, location, self.device_coordinates)
+        );
+        pick_bridge(relay, &mut **rng).map(|bridge| (bridge, relay.clone()))
+    }
+
+    /// Estimates the round-trip time from this device to the exit location when going through
+    /// `bridge`. Only the bridge-to-exit part is considered if the device location is unknown.
+    fn estimate_bridge_latency(
+        bridge: &Relay,
+        exit_location: &Location,
+        device_coordinates: Option<(f64, f64)>,
+    ) -> f64 {
+        let bridge_location = bridge.location.as_ref().expect("Relay has no location set");
+        let mut distance = bridge_location.distance_from(exit_location);
+        if let Some((latitude, longitude)) = device_coordinates {
+            distance += bridge_location.distance_from_coordinates(latitude, longitude);
+        }
+        distance * ESTIMATED_RTT_MS_PER_KM
+    }
+
+    /// Returns preferred constraints
+    #[allow(unused_variables)]
+    fn preferred_tunnel_constraints(
+        &self,
+        retry_attempt: u32,
+        location_constraint: &Constraint<LocationConstraint>,
+        providers_constraint: &Constraint<Providers>,
+        ownership_constraint: Constraint<Ownership>,
+        wg_key_exists: bool,
+    ) -> (Constraint<u16>, TransportProtocol, TunnelType) {
+        #[cfg(target_os = "windows")]
+        {
+            let location_supports_openvpn = self.relays.iter().any(|relay| {
+                relay.active
+                    && !relay.tunnels.openvpn.is_empty()
+                    && location_constraint.matches(relay)
+                    && providers_constraint.matches(relay)
+                    && ownership_constraint.matches(relay)
+            });
+            if location_supports_openvpn {
+                let (preferred_port, preferred_protocol) =
+                    Self::preferred_openvpn_constraints(retry_attempt);
+                return (preferred_port, preferred_protocol, TunnelType::OpenVpn);
+            }
+        }
+
+        let location_supports_wireguard = self.relays.iter().any(|relay| {
+            relay.active
+                && !relay.tunnels.wireguard.is_empty()
+                && location_constraint.matches(relay)
+                && providers_constraint.matches(relay)
+                && ownership_constraint.matches(relay)
+        });
+        // If location does not support WireGuard, defer to preferred OpenVPN tunnel
+        // constraints
+        if !location_supports_wireguard || !wg_key_exists {
+            let (preferred_port, preferred_protocol) =
+                Self::preferred_openvpn_constraints(retry_attempt);
+            return (preferred_port, preferred_protocol, TunnelType::OpenVpn);
+        }
+
+        // Try out WireGuard in the first two connection attempts, first with any port,
+        // afterwards on port 53. Afterwards, connect through OpenVPN alternating between UDP
+        // on any port twice and TCP on port 443 once.
+        match retry_attempt {
+            0 => (
+                Constraint::Any,
+                TransportProtocol::Udp,
+                TunnelType::Wireguard,
+            ),
+            1 => (
+                Constraint::Only(53),
+                TransportProtocol::Udp,
+                TunnelType::Wireguard,
+            ),
+            _ => {
+                let (preferred_port, preferred_protocol) =
+                    Self::preferred_openvpn_constraints(retry_attempt - 2);
+                (preferred_port, preferred_protocol, TunnelType::OpenVpn)
+            }
+        }
+    }
+
+    fn preferred_wireguard_port(
+        retry_attempt: u32,
+        port_range: &Constraint<PortRange>,
+    ) -> Constraint<TransportPort> {
+        // This ensures that if after the first 2 failed attempts the daemon does not
+        // connect, then afterwards 2 of each 4 successive attempts will try to connect
+        // on port 53.
+        let port = match retry_attempt % 4 {
+            0 | 1 => Constraint::Any,
+            _ => Constraint::Only(53),
+        };
+        Constraint::Only(TransportPort {
+            port: Self::limit_preferred_wireguard_port(port, port_range),
+            protocol: TransportProtocol::Udp,
+        })
+    }
+
+    /// Uses IPv6 for every fourth attempt if the IP version is not constrained, in case IPv4
+    /// traffic to the relays is blocked. The first attempts always use IPv4, since IPv6 is not
+    /// available on every network.
+    fn preferred_wireguard_ip_version(
+        retry_attempt: u32,
+        ip_version: Constraint<IpVersion>,
+    ) -> Constraint<IpVersion> {
+        match ip_version {
+            Constraint::Any if retry_attempt % 4 == 3 => Constraint::Only(IpVersion::V6),
+            ip_version => ip_version,
+        }
+    }
+
+    /// Ignores a preferred WireGuard port that is outside of the port range constraint, since
+    /// preferences must not override the constraints.
+    fn limit_preferred_wireguard_port(
+        port: Constraint<u16>,
+        port_range: &Constraint<PortRange>,
+    ) -> Constraint<u16> {
+        match (port, port_range) {
+            (Constraint::Only(port), Constraint::Only(range)) if !range.contains(port) => {
+                Constraint::Any
+            }
+            _ => port,
+        }
+    }
+
+    fn preferred_openvpn_constraints(retry_attempt: u32) -> (Constraint<u16>, TransportProtocol) {
+        // Prefer UDP by default, first on port 1194 and then on port 1300. If that has failed,
+        // then try TCP on ports 443 and 80, which works for many with UDP problems.
+        // If the tunnel type constraint is set OpenVpn, from the 4th attempt onwards, every two
+        // retry attempts OpenVpn constraints should be set to TCP as a bridge will be used,
+        // and to UDP for the next two attempts. If the tunnel type is specified to be _Any_
+        // and on not-Windows, the first two tries are used for WireGuard and don't
+        // affect counting here.
+        let protocol = match retry_attempt {
+            0 | 1 => TransportProtocol::Udp,
+            2 | 3 => TransportProtocol::Tcp,
+            attempt if attempt % 4 < 2 => TransportProtocol::Tcp,
+            _ => TransportProtocol::Udp,
+        };
+        (
+            Self::preferred_openvpn_port(protocol, retry_attempt),
+            protocol,
+        )
+    }
+
+    /// Alternates between the preferred OpenVPN ports for the given transport protocol.
+    fn preferred_openvpn_port(protocol: TransportProtocol, retry_attempt: u32) -> Constraint<u16> {
+        let ports = match protocol {
+            TransportProtocol::Udp => &OPENVPN_UDP_PORTS,
+            TransportProtocol::Tcp => &OPENVPN_TCP_PORTS,
+        };
+        Constraint::Only(ports[retry_attempt as usize % ports.len()])
+    }
+
+    /// Returns a random relay endpoint if any is matching the given constraints.
+    fn get_tunnel_endpoint_internal<T: TunnelMatcher>(
+        &self,
+        matcher: &RelayMatcher<T>,
+    ) -> Result<RelaySelectorResult, Error> {
+        let matching_relays = matching_relays(self.relays, matcher);
+
+        let mut rng = self.rng.borrow_mut();
+        self.pick_random_relay(&matching_relays, &mut **rng)
+            .and_then(|selected_relay| {
+                let endpoint = matcher.mullvad_endpoint(&selected_relay, &mut **rng);
+                let addr_in = endpoint
+                    .as_ref()
+                    .map(|endpoint| endpoint.to_endpoint().address.ip())
+                    .unwrap_or(IpAddr::from(selected_relay.ipv4_addr_in));
+                log::info!("Selected relay {} at {}", selected_relay.hostname, addr_in);
+                endpoint.map(|endpoint| RelaySelectorResult::new(endpoint, selected_relay.clone()))
+            })
+            .ok_or(Error::NoRelay)
+    }
+
+    /// Pick a random relay from the given slice. Will return `None` if the given slice is empty.
+    fn pick_random_relay<'b>(&self, relays: &'b [Relay], rng: &mut StdRng) -> Option<&'b Relay> {
+        let weights = match self.weights {
+            Some(weights) => weights(relays),
+            None => relays.iter().map(|relay| relay.weight).collect(),
+        };
+        pick_weighted_relay(relays, weights, rng)
+    }
+}
+
+#[derive(Debug)]
+pub struct RelaySelectorResult {
+    pub exit_relay: Relay,
+    pub endpoint: MullvadEndpoint,
+    pub entry_relay: Option<Relay>,
+}
+
+impl RelaySelectorResult {
+    fn new(endpoint: MullvadEndpoint, exit_relay: Relay) -> Self {
+        Self {
+            exit_relay,
+            endpoint,
+            entry_relay: None,
+        }
+    }
+
+    fn wireguard_multihop_endpoint(
+        exit_relay: Relay,
+        endpoint: MullvadWireguardEndpoint,
+        entry: Relay,
+    ) -> Self {
+        Self {
+            exit_relay,
+            endpoint: MullvadEndpoint::Wireguard(endpoint),
+            entry_relay: Some(entry),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{new_rng, relays_from_list};
+    use mullvad_types::relay_list::{
+        OpenVpnEndpointData, RelayBridges, RelayList, RelayListCity, RelayListCountry,
+        RelayTunnels, WireguardEndpointData,
+    };
+    use talpid_types::net::wireguard::PublicKey;
+
+    fn relay_list() -> RelayList {
+        RelayList {
+            etag: None,
+            countries: vec![RelayListCountry {
+                name: "Sweden".to_string(),
+                code: "se".to_string(),
+                cities: vec![RelayListCity {
+                    name: "Gothenburg".to_string(),
+                    code: "got".to_string(),
+                    latitude: 57.70887,
+                    longitude: 11.97456,
+                    relays: vec![
+                        Relay {
+                            hostname: "se9-wireguard".to_string(),
+                            ipv4_addr_in: "185.213.154.68".parse().unwrap(),
+                            ipv6_addr_in: Some("2a03:1b20:5:f011::a09f".parse().unwrap()),
+                            include_in_country: true,
+                            active: true,
+                            owned: true,
+                            provider: "31173".to_string(),
+                            weight: 1,
+                            tunnels: RelayTunnels {
+                                openvpn: vec![],
+                                wireguard: vec![WireguardEndpointData {
+                                    port_ranges: vec![
+                                        (53, 53),
+                                        (4000, 33433),
+                                        (33565, 51820),
+                                        (52000, 60000),
+                                    ],
+                                    ipv4_gateway: "10.64.0.1".parse().unwrap(),
+                                    ipv6_gateway: "fc00:bbbb:bbbb:bb01::1".parse().unwrap(),
+                                    public_key: PublicKey::from_base64(
+                                        "BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=",
+                                    )
+                                    .unwrap(),
+                                    protocol: TransportProtocol::Udp,
+                                }],
+                            },
+                            bridges: RelayBridges {
+                                shadowsocks: vec![],
+                            },
+                            location: None,
+                        },
+                        Relay {
+                            hostname: "se10-wireguard".to_string(),
+                            ipv4_addr_in: "185.213.154.69".parse().unwrap(),
+                            ipv6_addr_in: Some("2a03:1b20:5:f011::a10f".parse().unwrap()),
+                            include_in_country: true,
+                            active: true,
+                            owned: true,
+                            provider: "31173".to_string(),
+                            weight: 1,
+                            tunnels: RelayTunnels {
+                                openvpn: vec![],
+                                wireguard: vec![WireguardEndpointData {
+                                    port_ranges: vec![
+                                        (53, 53),
+                                        (4000, 33433),
+                                        (33565, 51820),
+                                        (52000, 60000),
+                                    ],
+                                    ipv4_gateway: "10.64.0.1".parse().unwrap(),
+                                    ipv6_gateway: "fc00:bbbb:bbbb:bb01::1".parse().unwrap(),
+                                    public_key: PublicKey::from_base64(
+                                        "veGD6/aEY6sMfN3Ls7YWPmNgu3AheO7nQqsFT47YSws=",
+                                    )
+                                    .unwrap(),
+                                    protocol: TransportProtocol::Udp,
+                                }],
+                            },
+                            bridges: RelayBridges {
+                                shadowsocks: vec![],
+                            },
+                            location: None,
+                        },
+                        Relay {
+                            hostname: "se-got-001".to_string(),
+                            ipv4_addr_in: "185.213.154.131".parse().unwrap(),
+                            ipv6_addr_in: None,
+                            include_in_country: true,
+                            active: true,
+                            owned: true,
+                            provider: "31173".to_string(),
+                            weight: 1,
+                            tunnels: RelayTunnels {
+                                openvpn: vec![
+                                    OpenVpnEndpointData {
+                                        port: 1194,
+                                        protocol: TransportProtocol::Udp,
+                                    },
+                                    OpenVpnEndpointData {
+                                        port: 443,
+                                        protocol: TransportProtocol::Tcp,
+                                    },
+                                    OpenVpnEndpointData {
+                                        port: 80,
+                                        protocol: TransportProtocol::Tcp,
+                                    },
+                                ],
+                                wireguard: vec![],
+                            },
+                            bridges: RelayBridges {
+                                shadowsocks: vec![],
+                            },
+                            location: None,
+                        },
+                    ],
+                }],
+            }],
+        }
+    }
+
+    fn test_relays() -> Vec<Relay> {
+        relays_from_list(&relay_list())
+    }
+
+    #[test]
+    fn test_preferred_tunnel_protocol() {
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+
+        // Prefer WG if the location only supports it
+        let location = LocationConstraint::Hostname(
+            "se".to_string(),
+            "got".to_string(),
+            "se9-wireguard".to_string(),
+        );
+        let relay_constraints = RelayConstraints {
+            location: Constraint::Only(location.clone()),
+            tunnel_protocol: Constraint::Any,
+            ..RelayConstraints::default()
+        };
+
+        let preferred =
+            relay_selector.preferred_constraints(&relay_constraints, BridgeState::Off, 0, true);
+        assert_eq!(
+            preferred.tunnel_protocol,
+            Constraint::Only(TunnelType::Wireguard)
+        );
+
+        for attempt in 0..10 {
+            assert!(relay_selector
+                .get_any_tunnel_endpoint(&relay_constraints, BridgeState::Off, attempt, true)
+                .is_ok());
+        }
+
+        // Prefer OpenVPN if the location only supports it
+        let location = LocationConstraint::Hostname(
+            "se".to_string(),
+            "got".to_string(),
+            "se-got-001".to_string(),
+        );
+        let relay_constraints = RelayConstraints {
+            location: Constraint::Only(location.clone()),
+            tunnel_protocol: Constraint::Any,
+            ..RelayConstraints::default()
+        };
+
+        let preferred =
+            relay_selector.preferred_constraints(&relay_constraints, BridgeState::Off, 0, true);
+        assert_eq!(
+            preferred.tunnel_protocol,
+            Constraint::Only(TunnelType::OpenVpn)
+        );
+
+        for attempt in 0..10 {
+            assert!(relay_selector
+                .get_any_tunnel_endpoint(&relay_constraints, BridgeState::Off, attempt, true)
+                .is_ok());
+        }
+
+        // Prefer OpenVPN on Windows when possible
+        #[cfg(windows)]
+        {
+            let relay_constraints = RelayConstraints::default();
+            for attempt in 0..10 {
+                let preferred = relay_selector.preferred_constraints(
+                    &relay_constraints,
+                    BridgeState::Off,
+                    attempt,
+                    true,
+                );
+                assert_eq!(
+                    preferred.tunnel_protocol,
+                    Constraint::Only(TunnelType::OpenVpn)
+                );
+                match relay_selector.get_any_tunnel_endpoint(
+                    &relay_constraints,
+                    BridgeState::Off,
+                    attempt,
+                    true,
+                ) {
+                    Ok(result) if matches!(result.endpoint, MullvadEndpoint::OpenVpn(_)) => (),
+                    _ => panic!("OpenVPN endpoint was not selected"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_wg_entry_hostname_collision() {
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+
+        let location1 = LocationConstraint::Hostname(
+            "se".to_string(),
+            "got".to_string(),
+            "se9-wireguard".to_string(),
+        );
+        let location2 = LocationConstraint::Hostname(
+            "se".to_string(),
+            "got".to_string(),
+            "se10-wireguard".to_string(),
+        );
+
+        let mut relay_constraints = RelayConstraints {
+            location: Constraint::Only(location1.clone()),
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+
+        relay_constraints.wireguard_constraints.use_multihop = true;
+        relay_constraints.wireguard_constraints.entry_location = Constraint::Only(location1);
+
+        // The same host cannot be used for entry and exit
+        assert!(relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
+            .is_err());
+
+        relay_constraints.wireguard_constraints.entry_location = Constraint::Only(location2);
+
+        // If the entry and exit differ, this should succeed
+        assert!(relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_relay_fallback_tiers() {
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+
+        let missing_location = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("xx".to_string())),
+            ..RelayConstraints::default()
+        };
+        let swedish_wireguard = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".to_string())),
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+        let anything = RelayConstraints::default();
+        let tiers = [missing_location, swedish_wireguard, anything];
+
+        // Tiers without matching relays are skipped
+        let (tier, _) = relay_selector
+            .get_tunnel_endpoint_from_tiers(&tiers, BridgeState::Off, 0, true)
+            .expect("Failed to select relay");
+        assert_eq!(tier, 1);
+
+        // Repeated failures move selection down the tiers, ending at the last one
+        for (retry_attempt, expected_tier) in &[(3, 1), (4, 1), (8, 2), (100, 2)] {
+            let (tier, _) = relay_selector
+                .get_tunnel_endpoint_from_tiers(&tiers, BridgeState::Off, *retry_attempt, true)
+                .expect("Failed to select relay");
+            assert_eq!(tier, *expected_tier);
+        }
+
+        // Fail if no tier matches any relay
+        assert!(relay_selector
+            .get_tunnel_endpoint_from_tiers(&tiers[..1], BridgeState::Off, 0, true)
+            .is_err());
+    }
+
+    #[test]
+    fn test_wg_entry_filter() -> Result<(), String> {
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+
+        let specific_hostname = "se10-wireguard";
+
+        let location_general = LocationConstraint::City("se".to_string(), "got".to_string());
+        let location_specific = LocationConstraint::Hostname(
+            "se".to_string(),
+            "got".to_string(),
+            specific_hostname.to_string(),
+        );
+
+        let mut relay_constraints = RelayConstraints {
+            location: Constraint::Only(location_general.clone()),
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+
+        relay_constraints.wireguard_constraints.use_multihop = true;
+        relay_constraints.wireguard_constraints.entry_location =
+            Constraint::Only(location_specific.clone());
+
+        // The exit must not equal the entry
+        let exit_relay = relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
+            .map_err(|error| error.to_string())?
+            .exit_relay;
+
+        assert_ne!(exit_relay.hostname, specific_hostname);
+
+        relay_constraints.location = Constraint::Only(location_specific);
+        relay_constraints.wireguard_constraints.entry_location = Constraint::Only(location_general);
+
+        // The entry must not equal the exit
+        let RelaySelectorResult {
+            exit_relay,
+            endpoint,
+            ..
+        } = relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
+            .map_err(|error| error.to_string())?;
+
+        assert_eq!(exit_relay.hostname, specific_hostname);
+
+        let endpoint = endpoint.unwrap_wireguard();
+        assert_eq!(
+            exit_relay.ipv4_addr_in,
+            endpoint.exit_peer.as_ref().unwrap().endpoint.ip()
+        );
+        assert_ne!(exit_relay.ipv4_addr_in, endpoint.peer.endpoint.ip());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bridge_constraints() -> Result<(), String> {
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+
+        let location = LocationConstraint::Hostname(
+            "se".to_string(),
+            "got".to_string(),
+            "se-got-001".to_string(),
+        );
+        let mut relay_constraints = RelayConstraints {
+            location: Constraint::Only(location.clone()),
+            tunnel_protocol: Constraint::Any,
+            ..RelayConstraints::default()
+        };
+        relay_constraints.openvpn_constraints.port = Constraint::Only(TransportPort {
+            protocol: TransportProtocol::Udp,
+            port: Constraint::Any,
+        });
+
+        let preferred =
+            relay_selector.preferred_constraints(&relay_constraints, BridgeState::On, 0, true);
+        assert_eq!(
+            preferred.tunnel_protocol,
+            Constraint::Only(TunnelType::OpenVpn)
+        );
+        // NOTE: TCP is preferred for bridges
+        assert_eq!(
+            preferred.openvpn_constraints.port,
+            Constraint::Only(TransportPort {
+                protocol: TransportProtocol::Tcp,
+                port: Constraint::Any,
+            })
+        );
+
+        // Ignore bridge state where WireGuard is used
+        let location = LocationConstraint::Hostname(
+            "se".to_string(),
+            "got".to_string(),
+            "se10-wireguard".to_string(),
+        );
+        let relay_constraints = RelayConstraints {
+            location: Constraint::Only(location),
+            tunnel_protocol: Constraint::Any,
+            ..RelayConstraints::default()
+        };
+        let preferred =
+            relay_selector.preferred_constraints(&relay_constraints, BridgeState::On, 0, true);
+        assert_eq!(
+            preferred.tunnel_protocol,
+            Constraint::Only(TunnelType::Wireguard)
+        );
+
+        // Handle bridge setting when falling back on OpenVPN
+        let mut relay_constraints = RelayConstraints {
+            location: Constraint::Any,
+            tunnel_protocol: Constraint::Any,
+            ..RelayConstraints::default()
+        };
+        relay_constraints.openvpn_constraints.port = Constraint::Only(TransportPort {
+            protocol: TransportProtocol::Udp,
+            port: Constraint::Any,
+        });
+        #[cfg(all(unix, not(target_os = "android")))]
+        {
+            let preferred =
+                relay_selector.preferred_constraints(&relay_constraints, BridgeState::On, 0, true);
+            assert_eq!(
+                preferred.tunnel_protocol,
+                Constraint::Only(TunnelType::Wireguard)
+            );
+        }
+        let preferred =
+            relay_selector.preferred_constraints(&relay_constraints, BridgeState::On, 2, true);
+        assert_eq!(
+            preferred.tunnel_protocol,
+            Constraint::Only(TunnelType::OpenVpn)
+        );
+        assert_eq!(
+            preferred.openvpn_constraints.port,
+            Constraint::Only(TransportPort {
+                protocol: TransportProtocol::Tcp,
+                port: Constraint::Any,
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_openvpn_transport_cycling() {
+        let expected = [
+            (1194, TransportProtocol::Udp),
+            (1300, TransportProtocol::Udp),
+            (443, TransportProtocol::Tcp),
+            (80, TransportProtocol::Tcp),
+            (443, TransportProtocol::Tcp),
+            (80, TransportProtocol::Tcp),
+            (1194, TransportProtocol::Udp),
+            (1300, TransportProtocol::Udp),
+        ];
+        for (attempt, (port, protocol)) in expected.iter().enumerate() {
+            assert_eq!(
+                RelaySelector::preferred_openvpn_constraints(attempt as u32),
+                (Constraint::Only(*port), *protocol)
+            );
+        }
+
+        // Fall back on any port of the preferred protocol if the relay lacks the preferred port
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+        let result = relay_selector
+            .get_openvpn_endpoint(
+                &Constraint::Any,
+                &Constraint::Any,
+                Constraint::Any,
+                OpenVpnConstraints::default(),
+                BridgeState::Off,
+                1,
+            )
+            .expect("Failed to get OpenVPN relay");
+        let endpoint = result.endpoint.to_endpoint();
+        assert_eq!(endpoint.protocol, TransportProtocol::Udp);
+        assert_eq!(endpoint.address.port(), 1194);
+    }
+
+    #[test]
+    fn test_wireguard_ip_version_cycling() {
+        for attempt in 0..8 {
+            let expected = if attempt % 4 == 3 {
+                Constraint::Only(IpVersion::V6)
+            } else {
+                Constraint::Any
+            };
+            assert_eq!(
+                RelaySelector::preferred_wireguard_ip_version(attempt, Constraint::Any),
+                expected
+            );
+            assert_eq!(
+                RelaySelector::preferred_wireguard_ip_version(
+                    attempt,
+                    Constraint::Only(IpVersion::V4)
+                ),
+                Constraint::Only(IpVersion::V4)
+            );
+        }
+
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+        let relay_constraints = RelayConstraints {
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+        let result = relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 3, true)
+            .expect("Failed to get WireGuard relay");
+        assert!(result.endpoint.to_endpoint().address.is_ipv6());
+    }
+
+    #[test]
+    fn test_bridge_latency_includes_device_path() {
+        let bridge = test_relays()[0].clone();
+        let bridge_location = bridge.location.clone().unwrap();
+        // Stockholm
+        let exit_location = Location {
+            latitude: 59.3289,
+            longitude: 18.0649,
+            ..bridge_location.clone()
+        };
+
+        let without_device = RelaySelector::estimate_bridge_latency(&bridge, &exit_location, None);
+        let device_near_bridge = RelaySelector::estimate_bridge_latency(
+            &bridge,
+            &exit_location,
+            Some((bridge_location.latitude, bridge_location.longitude)),
+        );
+        // New York
+        let device_far_away = RelaySelector::estimate_bridge_latency(
+            &bridge,
+            &exit_location,
+            Some((40.7128, -74.0060)),
+        );
+
+        assert!(without_device > 0.0);
+        assert!((device_near_bridge - without_device).abs() < 0.001);
+        assert!(device_far_away > without_device + BRIDGE_LATENCY_MARGIN_MS);
+    }
+
+    #[test]
+    fn test_wg_relay_with_no_key() {
+        let mut relay_constraints = RelayConstraints {
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+
+        let result = relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, false)
+            .expect("Failed to get WireGuard relay when WireGuard relay was specified as the only tunnel protocol");
+
+        assert!(matches!(result.endpoint, MullvadEndpoint::Wireguard(_)));
+
+        relay_constraints.tunnel_protocol = Constraint::Any;
+        let result = relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, false)
+            .expect("Failed to get OpenVPN relay with tunnel protocol constraint set to Any and without a WireGuard key");
+
+        assert!(matches!(result.endpoint, MullvadEndpoint::OpenVpn(_)));
+
+        let wireguard_specific_location = LocationConstraint::Hostname(
+            "se".to_string(),
+            "got".to_string(),
+            "se9-wireguard".to_string(),
+        );
+        relay_constraints.location = Constraint::Only(wireguard_specific_location);
+
+        let result = relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, false)
+            .expect(
+                "Failed to get a valid WireGuard relay when tunnel constraints are set to any
+                tunnel protocol and with a wireguard specific location without a wireguard key",
+            );
+
+        assert!(matches!(result.endpoint, MullvadEndpoint::Wireguard(_)));
+
+        let result = relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
+            .expect(
+                "Failed to get a valid WireGuard relay when tunnel constraints are set to any
+                tunnel protocol and with a wireguard specific location with a wireguard key",
+            );
+
+        assert!(matches!(result.endpoint, MullvadEndpoint::Wireguard(_)));
+    }
+
+    #[test]
+    fn test_selecting_any_relay_will_consider_multihop() {
+        let relay_constraints = RelayConstraints {
+            wireguard_constraints: WireguardConstraints {
+                use_multihop: true,
+                ..WireguardConstraints::default()
+            },
+            // This has to be explicit otherwise Android will chose WireGuard when default
+            // constructing.
+            tunnel_protocol: Constraint::Any,
+            ..RelayConstraints::default()
+        };
+
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+
+        let result = relay_selector.get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
+            .expect("Failed to get relay when tunnel constraints are set to Any and retrying the selection");
+        // Windows will ignore WireGuard until WireGuard is supported well enough
+        // TODO: Remove this caveat once Windows defaults to using WireGuard
+        #[cfg(target_os = "windows")]
+        assert!(
+            matches!(result.endpoint, MullvadEndpoint::OpenVpn(_)) && result.entry_relay.is_none()
+        );
+
+        #[cfg(not(target_os = "windows"))]
+        assert!(
+            matches!(result.endpoint, MullvadEndpoint::Wireguard(_))
+                && result.entry_relay.is_some()
+        );
+    }
+
+    const WIREGUARD_MULTIHOP_CONSTRAINTS: RelayConstraints = RelayConstraints {
+        location: Constraint::Any,
+        providers: Constraint::Any,
+        ownership: Constraint::Any,
+        wireguard_constraints: WireguardConstraints {
+            use_multihop: true,
+            port: Constraint::Any,
+            port_range: Constraint::Any,
+            ip_version: Constraint::Any,
+            entry_location: Constraint::Any,
+        },
+        tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+        openvpn_constraints: OpenVpnConstraints {
+            port: Constraint::Any,
+        },
+    };
+
+    #[test]
+    fn test_selecting_wireguard_location_will_consider_multihop() {
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+
+        let result = relay_selector.get_tunnel_endpoint(&WIREGUARD_MULTIHOP_CONSTRAINTS, BridgeState::Off, 0, true)
+
+            .expect("Failed to get relay when tunnel constraints are set to Any and retrying the selection");
+
+        assert!(result.entry_relay.is_some());
+        let endpoint = result.endpoint.unwrap_wireguard();
+        assert!(matches!(endpoint.peer.protocol, TransportProtocol::Udp));
+        assert!(matches!(
+            endpoint.exit_peer.as_ref().unwrap().protocol,
+            TransportProtocol::Udp
+        ));
+    }
+
+    #[test]
+    fn test_selecting_wg_multihop_tcp() {
+        let mut relay_constraints = WIREGUARD_MULTIHOP_CONSTRAINTS.clone();
+        relay_constraints.wireguard_constraints.port = Constraint::Only(TransportPort {
+            port: Constraint::Any,
+            protocol: TransportProtocol::Tcp,
+        });
+
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+
+        let result = relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
+            .expect("Failed to get WireGuard TCP multihop relay");
+
+        assert!(result.entry_relay.is_some());
+        let endpoint = result.endpoint.unwrap_wireguard();
+        assert!(matches!(endpoint.peer.protocol, TransportProtocol::Tcp));
+        assert!(matches!(
+            endpoint.exit_peer.as_ref().unwrap().protocol,
+            TransportProtocol::Udp
+        ));
+    }
+
+    #[test]
+    fn test_selecting_wg_tcp() {
+        let relay_constraints = RelayConstraints {
+            wireguard_constraints: WireguardConstraints {
+                port: Constraint::Only(TransportPort {
+                    port: Constraint::Any,
+                    protocol: TransportProtocol::Tcp,
+                }),
+                ..WireguardConstraints::default()
+            },
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+
+        let result = relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
+            .expect("Failed to get WireGuard TCP relay");
+        let endpoint = result.endpoint.unwrap_wireguard();
+        assert!(matches!(endpoint.peer.protocol, TransportProtocol::Tcp));
+        assert!(endpoint.exit_peer.is_none());
+    }
+
+    #[test]
+    fn test_selecting_wg_multihop_ports() {
+        let mut relay_constraints = WIREGUARD_MULTIHOP_CONSTRAINTS.clone();
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+
+        const INVALID_UDP_PORTS: [u16; 2] = [80, 443];
+        for attempt in 0..1000 {
+            let result = relay_selector
+                .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, attempt, true)
+                .expect("Failed to get WireGuard TCP multihop relay");
+            assert!(!INVALID_UDP_PORTS.contains(&result.endpoint.to_endpoint().address.port()));
+            assert_eq!(
+                result.endpoint.unwrap_wireguard().peer.protocol,
+                TransportProtocol::Udp
+            );
+        }
+
+        relay_constraints.wireguard_constraints.port = Constraint::Only(TransportPort {
+            port: Constraint::Any,
+            protocol: TransportProtocol::Tcp,
+        });
+
+        const VALID_TCP_PORTS: [u16; 3] = [80, 443, 5001];
+        for attempt in 0..1000 {
+            let result = relay_selector
+                .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, attempt, true)
+                .expect("Failed to get WireGuard TCP multihop relay");
+            assert!(VALID_TCP_PORTS.contains(&result.endpoint.to_endpoint().address.port()));
+            assert_eq!(
+                result.endpoint.unwrap_wireguard().peer.protocol,
+                TransportProtocol::Tcp
+            );
+        }
+    }
+
+    #[test]
+    fn test_ownership_constraint() {
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+
+        let mut relay_constraints = RelayConstraints {
+            ownership: Constraint::Only(Ownership::MullvadOwned),
+            ..RelayConstraints::default()
+        };
+        for attempt in 0..10 {
+            let result = relay_selector
+                .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, attempt, true)
+                .expect("Failed to get Mullvad-owned relay");
+            assert!(result.exit_relay.owned);
+        }
+
+        relay_constraints.ownership = Constraint::Only(Ownership::Rented);
+        assert!(relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
+            .is_err());
+    }
+
+    #[test]
+    fn test_wireguard_port_range_constraint() {
+        let relays = test_relays();
+        let mut rng = new_rng(0);
+        let relay_selector = RelaySelector::new(&relays, &mut rng);
+        let port_range = PortRange::new(33000, 34000).unwrap();
+
+        let mut relay_constraints = RelayConstraints {
+            wireguard_constraints: WireguardConstraints {
+                port_range: Constraint::Only(port_range),
+                ..WireguardConstraints::default()
+            },
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+
+        for attempt in 0..10 {
+            let result = relay_selector
+                .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, attempt, true)
+                .expect("Failed to get WireGuard relay within the port range");
+            let port = result.endpoint.to_endpoint().address.port();
+            assert!(port_range.contains(port), "port {} is out of range", port);
+            assert!(port <= 33433 || port >= 33565);
+        }
+
+        relay_constraints.wireguard_constraints.port_range =
+            Constraint::Only(PortRange::new(33434, 33564).unwrap());
+        assert!(relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0, true)
+            .is_err());
+    }
+}
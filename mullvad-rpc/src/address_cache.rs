@@ -8,10 +8,7 @@ use std::{
     sync::{Arc, Mutex},
 };
 use talpid_types::ErrorExt;
-use tokio::{
-    fs,
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-};
+use tokio::fs;
 
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
@@ -220,19 +217,13 @@ impl AddressCache {
             addresses.swap(0, choice % addresses_len);
         }
 
-        let temp_path = write_path.with_file_name("api-cache.temp");
-
-        let mut file = fs::File::create(&temp_path).await?;
         let mut contents = addresses
             .iter()
             .map(ToString::to_string)
             .collect::<Vec<String>>()
             .join("\n");
         contents += "\n";
-        file.write_all(contents.as_bytes()).await?;
-        file.sync_data().await?;
-
-        fs::rename(&temp_path, write_path).await
+        crate::atomic_file::write(write_path, contents).await
     }
 }
 
@@ -310,16 +301,27 @@ impl<'a> DerefMut for AddressCacheTransaction<'a> {
 }
 
 async fn read_address_file(path: &Path) -> Result<Vec<SocketAddr>, Error> {
-    let file = fs::File::open(path)
-        .await
-        .map_err(|error| Error::OpenAddressCache(error))?;
-    let mut lines = BufReader::new(file).lines();
+    let contents = fs::read_to_string(path).await.map_err(|error| {
+        if error.kind() == io::ErrorKind::NotFound {
+            Error::OpenAddressCache(error)
+        } else {
+            Error::ReadAddressCache(error)
+        }
+    })?;
+    Ok(parse_addresses(&contents))
+}
+
+/// Parses one address per line. Every line is terminated by a newline, so if the last line is
+/// not, the file was only partially written and the last line may be a truncated address.
+fn parse_addresses(contents: &str) -> Vec<SocketAddr> {
+    let mut lines: Vec<&str> = contents.lines().collect();
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        log::warn!("The address cache was not completely written. Ignoring its last line");
+        lines.pop();
+    }
+
     let mut addresses = vec![];
-    while let Some(line) = lines
-        .next_line()
-        .await
-        .map_err(|error| Error::ReadAddressCache(error))?
-    {
+    for line in lines {
         match line.trim().parse() {
             Ok(address) => addresses.push(address),
             Err(err) => {
@@ -327,5 +329,22 @@ async fn read_address_file(path: &Path) -> Result<Vec<SocketAddr>, Error> {
             }
         }
     }
-    Ok(addresses)
+    addresses
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_truncated_addresses() {
+        let addresses = parse_addresses("193.138.218.78:443\n185.213.154.66:443\n");
+        assert_eq!(addresses.len(), 2);
+
+        let addresses = parse_addresses("193.138.218.78:443\n185.213.154.66:44");
+        assert_eq!(
+            addresses,
+            vec!["193.138.218.78:443".parse::<SocketAddr>().unwrap()]
+        );
+    }
 }
@@ -0,0 +1,328 @@
+//! Firewall access to the API on demand. Normally, the current API address is allowed through the
+//! firewall at all times. When access is on demand, the address is only allowed while a request
+//! to the API is in flight, and stops being allowed once no request has been made for
+//! [`REVOKE_DELAY`]. Idle connections kept open between requests do not keep the API allowed.
+//!
+//! Connections through the system proxy go to the proxy rather than to the API, so the proxy is
+//! allowed in place of the API address for as long as such a connection is open, in either mode.
+
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{FutureExt, Shared},
+};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::runtime::Handle;
+
+/// Asks for `Some(address)` to be allowed through the firewall, or for the previously allowed
+/// address to stop being allowed. The sender is signaled once the firewall has been updated.
+pub type ApiAccessRequest = (Option<SocketAddr>, oneshot::Sender<()>);
+
+/// Completes once a requested firewall change has been applied. It fails if the change could not
+/// be made.
+pub type PendingChange = Shared<oneshot::Receiver<()>>;
+
+/// How long the API stays reachable after the last request to it finished. This lets requests
+/// that follow each other closely share the same firewall change.
+const REVOKE_DELAY: Duration = Duration::from_secs(5);
+
+/// Tracks the requests and connections to the API and requests firewall access for them, shared by
+/// all request services of an RPC runtime.
+#[derive(Clone)]
+pub struct ApiAccess {
+    inner: Arc<Mutex<ApiAccessInner>>,
+    handle: Handle,
+}
+
+struct ApiAccessInner {
+    on_demand: bool,
+    request_tx: Option<mpsc::UnboundedSender<ApiAccessRequest>>,
+    /// Number of requests and connection attempts that need the API to be reachable.
+    leases: usize,
+    /// The address that has been allowed through the firewall, if any.
+    allowed_address: Option<SocketAddr>,
+    /// The firewall change that allows `allowed_address`. Everyone who needs the address waits
+    /// for it, not only the one who requested it.
+    pending_change: Option<PendingChange>,
+    /// The address that connections were last opened to. Requests that reuse an open connection
+    /// need this address to be allowed.
+    connected_address: Option<SocketAddr>,
+    /// The current API address, which is allowed at all times unless access is on demand.
+    api_address: Option<SocketAddr>,
+    /// Incremented whenever a lease is acquired, so that a pending revocation can tell whether
+    /// the API has been used since it was scheduled.
+    generation: u64,
+}
+
+/// Keeps the API address allowed through the firewall for as long as it exists.
+pub struct ApiAccessLease {
+    access: ApiAccess,
+}
+
+impl ApiAccess {
+    pub fn new(handle: Handle) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(ApiAccessInner {
+                on_demand: false,
+                request_tx: None,
+                leases: 0,
+                allowed_address: None,
+                pending_change: None,
+                connected_address: None,
+                api_address: None,
+                generation: 0,
+            })),
+            handle,
+        }
+    }
+
+    /// Sets the channel that firewall changes are requested on. Access is not requested until
+    /// this has been set.
+    pub fn set_request_tx(&self, request_tx: mpsc::UnboundedSender<ApiAccessRequest>) {
+        self.inner.lock().unwrap().request_tx = Some(request_tx);
+    }
+
+    /// Sets whether the API should only be allowed through the firewall while it is used. When
+    /// this is enabled, the standing access is revoked right away. When it is disabled, the API
+    /// address is allowed again.
+    pub fn set_on_demand(&self, on_demand: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.on_demand == on_demand {
+            return;
+        }
+        inner.on_demand = on_demand;
+        if inner.leases == 0 {
+            let idle_address = inner.idle_address();
+            inner.allow(idle_address);
+        }
+    }
+
+    /// Sets the current API address. Unless access is on demand, it is allowed through the
+    /// firewall right away, and the returned receiver is signaled once it has been.
+    pub fn set_api_address(&self, address: SocketAddr) -> Option<PendingChange> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.api_address = Some(address);
+        if inner.on_demand || inner.leases > 0 {
+            return None;
+        }
+        inner.allow(Some(address))
+    }
+
+    /// Allows `address` through the firewall before a connection to it is opened, unless it
+    /// already is, and returns a lease that keeps it allowed. Returns `None` if firewall changes
+    /// cannot be requested.
+    pub async fn acquire(&self, address: SocketAddr) -> Option<ApiAccessLease> {
+        self.acquire_inner(Some(address)).await
+    }
+
+    /// Allows the address that connections were last opened to through the firewall, so that a
+    /// request can reuse an open connection, and returns a lease that keeps the API reachable
+    /// until the request has finished. Returns `None` if firewall changes cannot be requested.
+    pub async fn acquire_for_request(&self) -> Option<ApiAccessLease> {
+        self.acquire_inner(None).await
+    }
+
+    async fn acquire_inner(&self, address: Option<SocketAddr>) -> Option<ApiAccessLease> {
+        // The lease is counted and the change is requested under the same lock, so that a
+        // revocation cannot slip in between
+        let (address, pending_change) = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.request_tx.as_ref()?;
+            inner.leases += 1;
+            inner.generation = inner.generation.wrapping_add(1);
+            if address.is_some() {
+                inner.connected_address = address;
+            }
+            let address = address.or(inner.connected_address);
+            let pending_change = match address {
+                Some(address) => inner.allow(Some(address)),
+                None => None,
+            };
+            (address, pending_change)
+        };
+        let lease = ApiAccessLease {
+            access: self.clone(),
+        };
+
+        if let Some(pending_change) = pending_change {
+            if pending_change.await.is_err() {
+                log::error!("Failed to allow the API through the firewall");
+                let mut inner = self.inner.lock().unwrap();
+                if inner.allowed_address == address {
+                    inner.allowed_address = None;
+                    inner.pending_change = None;
+                }
+            }
+        }
+        Some(lease)
+    }
+
+    fn release(&self) {
+        let generation = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.leases -= 1;
+            if inner.leases > 0 || inner.allowed_address == inner.idle_address() {
+                return;
+            }
+            inner.generation
+        };
+
+        let access = self.clone();
+        self.handle.spawn(async move {
+            tokio::time::sleep(REVOKE_DELAY).await;
+            access.revoke_if_unused(generation);
+        });
+    }
+
+    fn revoke_if_unused(&self, generation: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.leases > 0 || inner.generation != generation {
+            return;
+        }
+        let idle_address = inner.idle_address();
+        inner.allow(idle_address);
+    }
+}
+
+impl ApiAccessInner {
+    /// The address that should be allowed while the API is not in use.
+    fn idle_address(&self) -> Option<SocketAddr> {
+        if self.on_demand {
+            None
+        } else {
+            self.api_address
+        }
+    }
+
+    /// Allows `address` through the firewall in place of the currently allowed address. If it
+    /// already is allowed, or is about to be, the change that allows it is returned.
+    fn allow(&mut self, address: Option<SocketAddr>) -> Option<PendingChange> {
+        if self.allowed_address != address {
+            self.allowed_address = address;
+            self.pending_change = self.send_request(address).map(FutureExt::shared);
+        }
+        self.pending_change.clone()
+    }
+
+    /// Requests a firewall change. Requests are sent while the lock is held, so that they arrive
+    /// in the order the state changed in.
+    fn send_request(&self, address: Option<SocketAddr>) -> Option<oneshot::Receiver<()>> {
+        let request_tx = self.request_tx.as_ref()?;
+        let (done_tx, done_rx) = oneshot::channel();
+        match request_tx.unbounded_send((address, done_tx)) {
+            Ok(()) => Some(done_rx),
+            Err(_) => {
+                log::error!("Failed to request firewall access to the API");
+                None
+            }
+        }
+    }
+}
+
+impl Drop for ApiAccessLease {
+    fn drop(&mut self) {
+        self.access.release();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_on_demand_access() {
+        let access = ApiAccess::new(Handle::current());
+        let (request_tx, mut request_rx) = mpsc::unbounded();
+        access.set_request_tx(request_tx);
+        let address: SocketAddr = "192.0.2.1:443".parse().unwrap();
+
+        access.set_api_address(address);
+        let (requested, done_tx) = request_rx.next().await.unwrap();
+        assert_eq!(requested, Some(address));
+        done_tx.send(()).unwrap();
+
+        // The API address is already allowed at all times
+        drop(access.acquire(address).await.unwrap());
+        assert!(request_rx.try_next().is_err());
+
+        access.set_on_demand(true);
+        let (requested, _) = request_rx.next().await.unwrap();
+        assert_eq!(requested, None);
+
+        let lease_task = tokio::spawn({
+            let access = access.clone();
+            async move { access.acquire(address).await }
+        });
+        let (requested, done_tx) = request_rx.next().await.unwrap();
+        assert_eq!(requested, Some(address));
+
+        // A second connection to the same address waits for the same firewall change
+        let mut second_lease = Box::pin(access.acquire(address));
+        assert!(futures::poll!(&mut second_lease).is_pending());
+
+        done_tx.send(()).unwrap();
+        let lease = lease_task.await.unwrap().unwrap();
+        let second_lease = second_lease.await.unwrap();
+        assert!(request_rx.try_next().is_err());
+        let generation = access.inner.lock().unwrap().generation;
+
+        // Access is kept while a connection is open, or if the API was used after the
+        // revocation was scheduled
+        drop(lease);
+        access.revoke_if_unused(generation);
+        drop(second_lease);
+        access.revoke_if_unused(generation.wrapping_sub(1));
+        assert!(request_rx.try_next().is_err());
+
+        access.revoke_if_unused(generation);
+        let (requested, _) = request_rx.next().await.unwrap();
+        assert_eq!(requested, None);
+
+        // A request that reuses the open connection allows its address again
+        let request_lease_task = tokio::spawn({
+            let access = access.clone();
+            async move { access.acquire_for_request().await }
+        });
+        let (requested, done_tx) = request_rx.next().await.unwrap();
+        assert_eq!(requested, Some(address));
+        done_tx.send(()).unwrap();
+        assert!(request_lease_task.await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_access() {
+        let access = ApiAccess::new(Handle::current());
+        let (request_tx, mut request_rx) = mpsc::unbounded();
+        access.set_request_tx(request_tx);
+        let api_address: SocketAddr = "192.0.2.1:443".parse().unwrap();
+        let proxy_address: SocketAddr = "192.0.2.2:3128".parse().unwrap();
+
+        access.set_api_address(api_address);
+        let (requested, _) = request_rx.next().await.unwrap();
+        assert_eq!(requested, Some(api_address));
+
+        // The proxy replaces the API address while it is used
+        let lease_task = tokio::spawn({
+            let access = access.clone();
+            async move { access.acquire(proxy_address).await }
+        });
+        let (requested, done_tx) = request_rx.next().await.unwrap();
+        assert_eq!(requested, Some(proxy_address));
+        done_tx.send(()).unwrap();
+        let lease = lease_task.await.unwrap().unwrap();
+
+        // A new API address takes effect once the proxy is no longer used
+        let new_api_address: SocketAddr = "192.0.2.3:443".parse().unwrap();
+        assert!(access.set_api_address(new_api_address).is_none());
+
+        let generation = access.inner.lock().unwrap().generation;
+        drop(lease);
+        access.revoke_if_unused(generation);
+        let (requested, _) = request_rx.next().await.unwrap();
+        assert_eq!(requested, Some(new_api_address));
+    }
+}
@@ -0,0 +1,64 @@
+//! Crash-safe replacement of cache files. A file written with [`write`] either has its old or its
+//! new contents, even if the device loses power in the middle of the write.
+
+use std::{
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+};
+use tokio::{fs, io::AsyncWriteExt};
+
+/// Replaces the contents of `path` with `contents`. The data is written to a temporary file in
+/// the same directory, which is flushed to disk and then renamed to `path`. On Unix, the directory
+/// is also flushed so that the rename itself is durable.
+pub async fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let temp_path = temp_path(path);
+
+    let result = async {
+        let mut file = fs::File::create(&temp_path).await?;
+        file.write_all(contents.as_ref()).await?;
+        file.sync_all().await?;
+        drop(file);
+        fs::rename(&temp_path, path).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path).await;
+    }
+    result?;
+
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        fs::File::open(dir).await?.sync_all().await?;
+    }
+
+    Ok(())
+}
+
+/// Returns the path of the temporary file that is used while writing `path`.
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut file_name = path
+        .file_name()
+        .map(|name| name.to_owned())
+        .unwrap_or_else(OsString::new);
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_temp_path() {
+        assert_eq!(
+            temp_path(Path::new("/cache/relays.json")),
+            Path::new("/cache/relays.json.tmp")
+        );
+    }
+}
@@ -0,0 +1,391 @@
+//! Detects API certificate validation failures that are caused by a wrong system clock, such as on
+//! computers with a dead CMOS battery.
+//!
+//! When validation fails with the system time, the certificate is validated again at a time within
+//! its validity period. If that succeeds, the chain and signatures are fine and only the clock is
+//! wrong. The connection then fails with a [`ClockSkew`] error, unless
+//! `MULLVAD_API_ALLOW_CLOCK_SKEW` is set, in which case the certificate is accepted so that the app
+//! can still reach the API.
+
+use crate::rest::Response;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use hyper::header;
+use std::{
+    error::Error as StdError,
+    fmt, io,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio_rustls::rustls::{
+    self,
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    Certificate, ServerName,
+};
+
+lazy_static::lazy_static! {
+    /// Whether API certificates that are only invalid because of the system clock are accepted.
+    static ref ALLOW_CLOCK_SKEW: bool = {
+        let allow = std::env::var("MULLVAD_API_ALLOW_CLOCK_SKEW")
+            .map(|value| value != "0")
+            .unwrap_or(false);
+        if allow {
+            log::warn!("Ignoring the system clock when validating API certificates");
+        }
+        allow
+    };
+}
+
+/// The smallest difference between the system clock and the `Date` header of an API response that
+/// is logged.
+const MIN_LOGGED_OFFSET: Duration = Duration::from_secs(5 * 60);
+
+/// How far the system clock is from the actual time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSkew {
+    /// The system clock is ahead of the actual time.
+    Ahead(Duration),
+    /// The system clock is behind the actual time.
+    Behind(Duration),
+}
+
+impl ClockSkew {
+    fn between(system_time: SystemTime, actual_time: SystemTime) -> Self {
+        match system_time.duration_since(actual_time) {
+            Ok(offset) => ClockSkew::Ahead(offset),
+            Err(error) => ClockSkew::Behind(error.duration()),
+        }
+    }
+
+    /// Returns the absolute difference between the system clock and the actual time.
+    pub fn offset(&self) -> Duration {
+        match *self {
+            ClockSkew::Ahead(offset) | ClockSkew::Behind(offset) => offset,
+        }
+    }
+
+    /// Returns the difference between the system clock and the actual time in whole seconds. The
+    /// result is positive if the system clock is ahead.
+    pub fn as_signed_secs(&self) -> i64 {
+        let secs = i64::try_from(self.offset().as_secs()).unwrap_or(i64::MAX);
+        match self {
+            ClockSkew::Ahead(_) => secs,
+            ClockSkew::Behind(_) => -secs,
+        }
+    }
+}
+
+impl fmt::Display for ClockSkew {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.offset().as_secs();
+        let (amount, unit) = match secs {
+            0..=59 => (secs, "second"),
+            60..=3599 => (secs / 60, "minute"),
+            3600..=86399 => (secs / 3600, "hour"),
+            _ => (secs / 86400, "day"),
+        };
+        write!(
+            f,
+            "{} {}{}",
+            amount,
+            unit,
+            if amount == 1 { "" } else { "s" }
+        )?;
+        match self {
+            ClockSkew::Ahead(_) => write!(f, " ahead"),
+            ClockSkew::Behind(_) => write!(f, " behind"),
+        }
+    }
+}
+
+impl StdError for ClockSkew {}
+
+/// Returns the clock skew that caused the connection behind `error` to fail, if any.
+pub(crate) fn from_hyper_error(error: &hyper::Error) -> Option<ClockSkew> {
+    let mut source = error.source();
+    while let Some(error) = source {
+        if let Some(io_error) = error.downcast_ref::<io::Error>() {
+            return io_error.get_ref()?.downcast_ref::<ClockSkew>().copied();
+        }
+        source = error.source();
+    }
+    None
+}
+
+/// Logs how far the system clock is from the `Date` header of an API response. This is only done
+/// when the system clock is ignored during certificate validation, since the offset is otherwise
+/// reported through [`ClockSkew`] errors.
+pub(crate) fn log_response_date_offset(response: &Response) {
+    if !*ALLOW_CLOCK_SKEW {
+        return;
+    }
+    if let Some(skew) = response_date_skew(response) {
+        if skew.offset() >= MIN_LOGGED_OFFSET {
+            log::warn!("According to the API, the system clock is {}", skew);
+        }
+    }
+}
+
+/// Returns how far the system clock is from the `Date` header of an API response, if the response
+/// has a valid one. The header has a resolution of one second.
+pub(crate) fn response_date_skew(response: &Response) -> Option<ClockSkew> {
+    let date = response
+        .headers()
+        .get(header::DATE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok())?;
+    Some(ClockSkew::between(
+        SystemTime::now(),
+        SystemTime::from(date),
+    ))
+}
+
+/// Certificate verifier that tells apart certificates that are invalid only because of the system
+/// clock. A new verifier must be used for each connection.
+pub(crate) struct ClockSkewVerifier {
+    inner: Arc<WebPkiVerifier>,
+    detected_skew: Mutex<Option<ClockSkew>>,
+}
+
+impl ClockSkewVerifier {
+    /// `inner` must not have a certificate transparency policy, since SCTs are not passed on when
+    /// validating the certificate again.
+    pub fn new(inner: Arc<WebPkiVerifier>) -> Self {
+        Self {
+            inner,
+            detected_skew: Mutex::new(None),
+        }
+    }
+
+    /// Returns the clock skew detected while validating the server certificate, if any.
+    pub fn detected_skew(&self) -> Option<ClockSkew> {
+        *self.detected_skew.lock().unwrap()
+    }
+}
+
+impl ServerCertVerifier for ClockSkewVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let error = match self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        ) {
+            Ok(verified) => return Ok(verified),
+            Err(error) => error,
+        };
+
+        let (not_before, not_after) = match certificate_validity(&end_entity.0) {
+            Some(validity) => validity,
+            None => return Err(error),
+        };
+        let skew = if now > not_after {
+            ClockSkew::between(now, not_after)
+        } else if now < not_before {
+            ClockSkew::between(now, not_before)
+        } else {
+            return Err(error);
+        };
+
+        let valid_time = not_before + not_after.duration_since(not_before).unwrap_or_default() / 2;
+        let verified = self
+            .inner
+            .verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                &mut std::iter::empty(),
+                ocsp_response,
+                valid_time,
+            )
+            .map_err(|_| error.clone())?;
+
+        *self.detected_skew.lock().unwrap() = Some(skew);
+
+        if *ALLOW_CLOCK_SKEW {
+            log::warn!(
+                "Accepting API certificate that is not valid at the current time. The system \
+                 clock is at least {}",
+                skew
+            );
+            Ok(verified)
+        } else {
+            Err(error)
+        }
+    }
+}
+
+/// Returns the `notBefore` and `notAfter` times of a DER encoded X.509 certificate.
+fn certificate_validity(certificate: &[u8]) -> Option<(SystemTime, SystemTime)> {
+    const SEQUENCE: u8 = 0x30;
+    const INTEGER: u8 = 0x02;
+    const EXPLICIT_VERSION: u8 = 0xa0;
+
+    let (tag, certificate, _) = read_der(certificate)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let (tag, tbs_certificate, _) = read_der(certificate)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+
+    let (mut tag, _, mut rest) = read_der(tbs_certificate)?;
+    if tag == EXPLICIT_VERSION {
+        let (serial_tag, _, serial_rest) = read_der(rest)?;
+        tag = serial_tag;
+        rest = serial_rest;
+    }
+    if tag != INTEGER {
+        return None;
+    }
+    // Skip the signature algorithm and the issuer
+    let (_, _, rest) = read_der(rest)?;
+    let (_, _, rest) = read_der(rest)?;
+
+    let (tag, validity, _) = read_der(rest)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let (not_before_tag, not_before, rest) = read_der(validity)?;
+    let (not_after_tag, not_after, _) = read_der(rest)?;
+
+    Some((
+        parse_der_time(not_before_tag, not_before)?,
+        parse_der_time(not_after_tag, not_after)?,
+    ))
+}
+
+/// Reads a DER element from the start of `input`. Returns the tag, the contents and the remaining
+/// input.
+fn read_der(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first_length_byte, mut input) = input.split_first()?;
+
+    let length = if first_length_byte < 0x80 {
+        usize::from(first_length_byte)
+    } else {
+        let num_bytes = usize::from(first_length_byte & 0x7f);
+        if num_bytes == 0 || num_bytes > std::mem::size_of::<u32>() || input.len() < num_bytes {
+            return None;
+        }
+        let (length_bytes, rest) = input.split_at(num_bytes);
+        input = rest;
+        length_bytes
+            .iter()
+            .fold(0usize, |length, &byte| (length << 8) | usize::from(byte))
+    };
+
+    if input.len() < length {
+        return None;
+    }
+    let (contents, rest) = input.split_at(length);
+    Some((tag, contents, rest))
+}
+
+/// Parses a DER encoded `UTCTime` or `GeneralizedTime` in the `YYMMDDHHMMSSZ` or
+/// `YYYYMMDDHHMMSSZ` format, as required for certificate validity periods by RFC 5280.
+fn parse_der_time(tag: u8, contents: &[u8]) -> Option<SystemTime> {
+    const UTC_TIME: u8 = 0x17;
+    const GENERALIZED_TIME: u8 = 0x18;
+
+    let time = std::str::from_utf8(contents).ok()?.strip_suffix('Z')?;
+    if !time.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let (year, time) = match (tag, time.len()) {
+        (UTC_TIME, 12) => {
+            let year: i32 = time[..2].parse().ok()?;
+            (
+                if year >= 50 { 1900 + year } else { 2000 + year },
+                &time[2..],
+            )
+        }
+        (GENERALIZED_TIME, 14) => (time[..4].parse().ok()?, &time[4..]),
+        _ => return None,
+    };
+    let field = |index: usize| time[index..index + 2].parse::<u32>().ok();
+
+    let time = NaiveDate::from_ymd_opt(year, field(0)?, field(2)?)?.and_hms_opt(
+        field(4)?,
+        field(6)?,
+        field(8)?,
+    )?;
+    let secs = u64::try_from(Utc.from_utc_datetime(&time).timestamp()).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_certificate_validity() {
+        let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(
+            crate::tls_stream::LE_ROOT_CERT,
+        ))
+        .unwrap();
+        let (not_before, not_after) = certificate_validity(&certs[0]).unwrap();
+
+        // ISRG Root X1 is valid from 2015-06-04 11:04:38 UTC to 2035-06-04 11:04:38 UTC
+        assert_eq!(not_before, UNIX_EPOCH + Duration::from_secs(1433415878));
+        assert_eq!(not_after, UNIX_EPOCH + Duration::from_secs(2064567878));
+    }
+
+    #[test]
+    fn test_truncated_certificate() {
+        let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(
+            crate::tls_stream::LE_ROOT_CERT,
+        ))
+        .unwrap();
+        assert_eq!(certificate_validity(&certs[0][..200]), None);
+    }
+
+    #[test]
+    fn test_parse_der_time() {
+        assert_eq!(
+            parse_der_time(0x17, b"700101000000Z"),
+            Some(UNIX_EPOCH + Duration::from_secs(0))
+        );
+        assert_eq!(
+            parse_der_time(0x18, b"19700102000000Z"),
+            Some(UNIX_EPOCH + Duration::from_secs(86400))
+        );
+        assert_eq!(parse_der_time(0x17, b"700101000000"), None);
+        assert_eq!(parse_der_time(0x17, b"19700101000000Z"), None);
+        assert_eq!(parse_der_time(0x17, b"701301000000Z"), None);
+    }
+
+    #[test]
+    fn test_clock_skew_signed_secs() {
+        assert_eq!(
+            ClockSkew::Ahead(Duration::from_secs(90)).as_signed_secs(),
+            90
+        );
+        assert_eq!(
+            ClockSkew::Behind(Duration::from_secs(90)).as_signed_secs(),
+            -90
+        );
+    }
+
+    #[test]
+    fn test_clock_skew_display() {
+        assert_eq!(
+            ClockSkew::Ahead(Duration::from_secs(3 * 86400 + 5)).to_string(),
+            "3 days ahead"
+        );
+        assert_eq!(
+            ClockSkew::Behind(Duration::from_secs(90)).to_string(),
+            "1 minute behind"
+        );
+    }
+}
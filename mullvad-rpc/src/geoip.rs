@@ -0,0 +1,73 @@
+//! A module dedicated to looking up the location of this device with <https://am.i.mullvad.net>.
+
+use crate::rest::{self, Error, RequestServiceHandle};
+use futures::join;
+use mullvad_types::location::{AmIMullvad, GeoIpLocation};
+use talpid_types::ErrorExt;
+
+const URI_V4: &str = "https://ipv4.am.i.mullvad.net/json";
+const URI_V6: &str = "https://ipv6.am.i.mullvad.net/json";
+
+/// Looks up the location and public IP addresses of this device, and whether its traffic exits
+/// through a Mullvad relay.
+#[derive(Clone)]
+pub struct GeoIpProxy {
+    service: RequestServiceHandle,
+}
+
+impl GeoIpProxy {
+    pub fn new(service: RequestServiceHandle) -> Self {
+        Self { service }
+    }
+
+    /// Looks up the location over both IPv4 and IPv6. Fails only if both lookups fail.
+    pub async fn get_location(&self) -> Result<GeoIpLocation, Error> {
+        let (v4_result, v6_result) = join!(self.send_request(URI_V4), self.send_request(URI_V6));
+
+        match (v4_result, v6_result) {
+            (Ok(mut v4), Ok(v6)) => {
+                v4.ipv6 = v6.ipv6;
+                v4.mullvad_exit_ip = v4.mullvad_exit_ip && v6.mullvad_exit_ip;
+                Ok(v4)
+            }
+            (Ok(v4), Err(e)) => {
+                log_network_error(e, "IPv6");
+                Ok(v4)
+            }
+            (Err(e), Ok(v6)) => {
+                log_network_error(e, "IPv4");
+                Ok(v6)
+            }
+            (Err(e_v4), Err(_)) => Err(e_v4),
+        }
+    }
+
+    async fn send_request(&self, uri: &'static str) -> Result<GeoIpLocation, Error> {
+        let request = rest::RestRequest::get(uri)?;
+        let response = self.service.request(request).await?;
+        let location: AmIMullvad = rest::deserialize_body(response).await?;
+        Ok(GeoIpLocation::from(location))
+    }
+}
+
+fn log_network_error(err: Error, version: &'static str) {
+    let err_message = &format!("Unable to fetch {} GeoIP location", version);
+    match err {
+        Error::HyperError(hyper_err) if hyper_err.is_connect() => {
+            if let Some(cause) = hyper_err.into_cause() {
+                if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+                    // Don't log ENETUNREACH errors, they are not informative.
+                    if err.raw_os_error() == Some(libc::ENETUNREACH) {
+                        return;
+                    }
+                    log::debug!("{}: Hyper connect error: {}", err_message, cause);
+                }
+            } else {
+                log::error!("Hyper Connection error did not contain a cause!");
+            }
+        }
+        any_other_error => {
+            log::debug!("{}", any_other_error.display_chain_with_msg(err_message));
+        }
+    };
+}
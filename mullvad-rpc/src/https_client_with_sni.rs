@@ -1,5 +1,7 @@
 use crate::{
     abortable_stream::{AbortableStream, AbortableStreamHandle},
+    api_access::{ApiAccess, ApiAccessLease},
+    system_proxy::{self, SystemProxyState},
     tls_stream::TlsStream,
 };
 use futures::{channel::mpsc, StreamExt};
@@ -20,7 +22,10 @@ use std::{
     net::{IpAddr, SocketAddr},
     pin::Pin,
     str::{self, FromStr},
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     task::{Context, Poll},
     time::Duration,
 };
@@ -43,11 +48,32 @@ impl HttpsConnectorWithSniHandle {
     }
 }
 
+/// Whether traffic to the API is routed through the tunnel, shared by all connectors of an RPC
+/// runtime. While it is, connections are neither bypassed nor sent through the system proxy, as
+/// both would let them out on the local network.
+#[derive(Clone, Default)]
+pub struct TunnelRouteState {
+    in_tunnel: Arc<AtomicBool>,
+}
+
+impl TunnelRouteState {
+    pub fn set_in_tunnel(&self, in_tunnel: bool) {
+        self.in_tunnel.store(in_tunnel, Ordering::SeqCst);
+    }
+
+    pub fn is_in_tunnel(&self) -> bool {
+        self.in_tunnel.load(Ordering::SeqCst)
+    }
+}
+
 /// A Connector for the `https` scheme.
 #[derive(Clone)]
 pub struct HttpsConnectorWithSni {
     inner: Arc<Mutex<HttpsConnectorWithSniInner>>,
     sni_hostname: Option<String>,
+    system_proxy: SystemProxyState,
+    tunnel_route: TunnelRouteState,
+    api_access: ApiAccess,
     #[cfg(target_os = "android")]
     socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
 }
@@ -63,6 +89,9 @@ impl HttpsConnectorWithSni {
     pub fn new(
         handle: Handle,
         sni_hostname: Option<String>,
+        system_proxy: SystemProxyState,
+        tunnel_route: TunnelRouteState,
+        api_access: ApiAccess,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> (Self, HttpsConnectorWithSniHandle) {
         let (tx, mut rx): (_, mpsc::UnboundedReceiver<()>) = mpsc::unbounded();
@@ -88,6 +117,9 @@ impl HttpsConnectorWithSni {
             HttpsConnectorWithSni {
                 inner,
                 sni_hostname,
+                system_proxy,
+                tunnel_route,
+                api_access,
                 #[cfg(target_os = "android")]
                 socket_bypass_tx,
             },
@@ -125,6 +157,131 @@ impl HttpsConnectorWithSni {
             .map_err(|err| io::Error::new(io::ErrorKind::TimedOut, err))?
     }
 
+    /// Connects to `addr`. If the system proxy is enabled and the connection fails, `hostname` is
+    /// reached through the proxy instead. Once the proxy has been needed, it is tried first until
+    /// it fails. While the tunnel is up, `addr` is always connected to directly, through the
+    /// tunnel. Otherwise, the returned lease keeps the address that was connected to allowed
+    /// through the firewall until the connection has been set up.
+    async fn connect(
+        addr: SocketAddr,
+        hostname: &str,
+        system_proxy: &SystemProxyState,
+        tunnel_route: &TunnelRouteState,
+        api_access: &ApiAccess,
+        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+    ) -> io::Result<(TcpStream, Option<ApiAccessLease>)> {
+        if tunnel_route.is_in_tunnel() {
+            let stream = Self::open_socket(
+                addr,
+                #[cfg(target_os = "android")]
+                None,
+            )
+            .await?;
+            return Ok((stream, None));
+        }
+
+        if !system_proxy.is_enabled() {
+            return Self::connect_directly(
+                addr,
+                api_access,
+                #[cfg(target_os = "android")]
+                socket_bypass_tx,
+            )
+            .await;
+        }
+
+        if system_proxy.is_preferred() {
+            match Self::connect_through_system_proxy(
+                hostname,
+                addr.port(),
+                api_access,
+                #[cfg(target_os = "android")]
+                socket_bypass_tx.clone(),
+            )
+            .await
+            {
+                Ok(stream) => return Ok(stream),
+                Err(error) => {
+                    log::warn!("Failed to connect through the system proxy: {}", error);
+                    system_proxy.set_preferred(false);
+                }
+            }
+        }
+
+        let direct_error = match Self::connect_directly(
+            addr,
+            api_access,
+            #[cfg(target_os = "android")]
+            socket_bypass_tx.clone(),
+        )
+        .await
+        {
+            Ok(connection) => return Ok(connection),
+            Err(error) => error,
+        };
+
+        match Self::connect_through_system_proxy(
+            hostname,
+            addr.port(),
+            api_access,
+            #[cfg(target_os = "android")]
+            socket_bypass_tx,
+        )
+        .await
+        {
+            Ok(connection) => {
+                log::info!("Connecting to {} through the system proxy", hostname);
+                system_proxy.set_preferred(true);
+                Ok(connection)
+            }
+            Err(error) => {
+                log::debug!("Failed to connect through the system proxy: {}", error);
+                Err(direct_error)
+            }
+        }
+    }
+
+    async fn connect_directly(
+        addr: SocketAddr,
+        api_access: &ApiAccess,
+        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+    ) -> io::Result<(TcpStream, Option<ApiAccessLease>)> {
+        let lease = api_access.acquire(addr).await;
+        let stream = Self::open_socket(
+            addr,
+            #[cfg(target_os = "android")]
+            socket_bypass_tx,
+        )
+        .await?;
+        Ok((stream, lease))
+    }
+
+    /// Connects to `hostname` through the system proxy. The proxy, rather than the API, is
+    /// allowed through the firewall for the connection.
+    async fn connect_through_system_proxy(
+        hostname: &str,
+        port: u16,
+        api_access: &ApiAccess,
+        #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
+    ) -> io::Result<(TcpStream, Option<ApiAccessLease>)> {
+        let proxy = system_proxy::find(hostname)
+            .await
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No system proxy is set"))?;
+        let proxy_uri = Uri::from_str(&format!("http://{}", proxy))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let proxy_addr = Self::resolve_address(&proxy_uri).await?;
+
+        let lease = api_access.acquire(proxy_addr).await;
+        let stream = Self::open_socket(
+            proxy_addr,
+            #[cfg(target_os = "android")]
+            socket_bypass_tx,
+        )
+        .await?;
+        let stream = system_proxy::open_tunnel(stream, hostname, port).await?;
+        Ok((stream, lease))
+    }
+
     async fn resolve_address(uri: &Uri) -> io::Result<SocketAddr> {
         let hostname = uri.host().ok_or(io::Error::new(
             io::ErrorKind::InvalidInput,
@@ -175,6 +332,9 @@ impl Service<Uri> for HttpsConnectorWithSni {
                 io::Error::new(io::ErrorKind::InvalidInput, "invalid url, missing host")
             });
         let inner = self.inner.clone();
+        let system_proxy = self.system_proxy.clone();
+        let tunnel_route = self.tunnel_route.clone();
+        let api_access = self.api_access.clone();
         #[cfg(target_os = "android")]
         let socket_bypass_tx = self.socket_bypass_tx.clone();
 
@@ -189,8 +349,12 @@ impl Service<Uri> for HttpsConnectorWithSni {
             let hostname = sni_hostname?;
             let addr = Self::resolve_address(&uri).await?;
 
-            let tokio_connection = Self::open_socket(
+            let (tokio_connection, api_access_lease) = Self::connect(
                 addr,
+                &hostname,
+                &system_proxy,
+                &tunnel_route,
+                &api_access,
                 #[cfg(target_os = "android")]
                 socket_bypass_tx,
             )
@@ -204,7 +368,10 @@ impl Service<Uri> for HttpsConnectorWithSni {
                 inner.stream_handles.push(socket_handle);
             }
 
-            Ok(TlsStream::connect_https(tcp_stream, &hostname).await?)
+            let tls_stream = TlsStream::connect_https(tcp_stream, &hostname).await?;
+            // From here on, the requests that use the connection keep the API reachable
+            drop(api_access_lease);
+            Ok(tls_stream)
         };
 
         Box::pin(fut)
@@ -5,7 +5,7 @@ use chrono::{offset::Utc, DateTime};
 use futures::channel::mpsc;
 use hyper::Method;
 use mullvad_types::{
-    account::{AccountToken, VoucherSubmission},
+    account::{AccountToken, PlayPurchase, PlayPurchasePaymentToken, VoucherSubmission},
     version::AppVersion,
 };
 use std::{
@@ -17,21 +17,31 @@ use std::{
 };
 use talpid_types::{net::wireguard, ErrorExt};
 
+pub mod atomic_file;
 pub mod availability;
 use availability::{ApiAvailability, ApiAvailabilityHandle};
 pub mod rest;
 
 mod abortable_stream;
+mod api_access;
+mod clock_skew;
 mod https_client_with_sni;
+mod system_proxy;
 mod tls_stream;
 #[cfg(target_os = "android")]
 pub use crate::https_client_with_sni::SocketBypassRequest;
 
 mod address_cache;
+mod geoip;
 mod relay_list;
+mod trace;
 pub use address_cache::{AddressCache, CurrentAddressChangeListener};
+pub use api_access::{ApiAccess, ApiAccessRequest};
+pub use clock_skew::ClockSkew;
+pub use geoip::GeoIpProxy;
 pub use hyper::StatusCode;
 pub use relay_list::RelayListProxy;
+pub use trace::{ApiTrace, ApiTraceEntry};
 
 /// Error code returned by the Mullvad API if the voucher has alreaby been used.
 pub const VOUCHER_USED: &str = "VOUCHER_USED";
@@ -39,6 +49,9 @@ pub const VOUCHER_USED: &str = "VOUCHER_USED";
 /// Error code returned by the Mullvad API if the voucher code is invalid.
 pub const INVALID_VOUCHER: &str = "INVALID_VOUCHER";
 
+/// Error code returned by the Mullvad API if the voucher has expired.
+pub const VOUCHER_EXPIRED: &str = "VOUCHER_EXPIRED";
+
 /// Error code returned by the Mullvad API if the account token is invalid.
 pub const INVALID_ACCOUNT: &str = "INVALID_ACCOUNT";
 
@@ -47,6 +60,10 @@ pub const INVALID_AUTH: &str = "INVALID_AUTH";
 
 pub const API_IP_CACHE_FILENAME: &str = "api-ip-address.txt";
 
+/// Name of the file in the settings directory that overrides the API endpoint in builds with the
+/// `api-override` feature. The environment variables take precedence over it.
+pub const API_OVERRIDE_FILENAME: &str = "api-override.json";
+
 lazy_static::lazy_static! {
     static ref API: ApiEndpoint = ApiEndpoint::get();
 }
@@ -56,6 +73,68 @@ struct ApiEndpoint {
     host: String,
     addr: SocketAddr,
     disable_address_cache: bool,
+    /// PEM encoded root certificates to trust instead of the compiled in ones.
+    root_certs: Option<Vec<u8>>,
+}
+
+/// Contents of [`API_OVERRIDE_FILENAME`]. All fields are optional.
+#[cfg(feature = "api-override")]
+#[derive(Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+struct ApiOverrideConfig {
+    host: Option<String>,
+    address: Option<SocketAddr>,
+    root_cert: Option<std::path::PathBuf>,
+}
+
+#[cfg(feature = "api-override")]
+impl ApiOverrideConfig {
+    /// Reads the config from the settings directory, or returns an empty config if there is none.
+    fn load() -> Self {
+        match mullvad_paths::get_settings_dir() {
+            Ok(dir) => Self::load_from(&dir.join(API_OVERRIDE_FILENAME)),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Reads the config from `path`. An empty config is returned if the file does not exist or
+    /// cannot be parsed, so that a bad override does not prevent the API from being used.
+    fn load_from(path: &std::path::Path) -> Self {
+        match std::fs::read(path) {
+            Ok(contents) => Self::parse(path, &contents),
+            Err(error) => {
+                if error.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!("Failed to read {}: {}", path.display(), error);
+                }
+                Self::default()
+            }
+        }
+    }
+
+    fn parse(path: &std::path::Path, contents: &[u8]) -> Self {
+        serde_json::from_slice(contents).unwrap_or_else(|error| {
+            log::error!(
+                "Ignoring {} since it is not a valid API override: {}",
+                path.display(),
+                error
+            );
+            Self::default()
+        })
+    }
+
+    /// Replaces the fields that are also set by environment variables, which take precedence.
+    fn with_env_overrides(
+        self,
+        host: Option<String>,
+        address: Option<SocketAddr>,
+        root_cert: Option<std::path::PathBuf>,
+    ) -> Self {
+        ApiOverrideConfig {
+            host: host.or(self.host),
+            address: address.or(self.address),
+            root_cert: root_cert.or(self.root_cert),
+        }
+    }
 }
 
 impl ApiEndpoint {
@@ -63,8 +142,8 @@ impl ApiEndpoint {
     ///
     /// # Panics
     ///
-    /// Panics if `MULLVAD_API_ADDR` has invalid contents or if only one of
-    /// `MULLVAD_API_ADDR` or `MULLVAD_API_HOST` has been set but not the other.
+    /// Panics if `MULLVAD_API_ADDR` has invalid contents, if only one of the host and the address
+    /// is overridden but not the other, or if the root certificate file cannot be read.
     fn get() -> ApiEndpoint {
         const API_HOST_DEFAULT: &str = "api.mullvad.net";
         const API_IP_DEFAULT: IpAddr = IpAddr::V4(Ipv4Addr::new(193, 138, 218, 78));
@@ -81,31 +160,59 @@ impl ApiEndpoint {
 
         let host_var = read_var("MULLVAD_API_HOST");
         let address_var = read_var("MULLVAD_API_ADDR");
+        let root_cert_var = read_var("MULLVAD_API_ROOT_CERT_FILE");
 
+        #[cfg_attr(not(feature = "api-override"), allow(unused_mut))]
         let mut api = ApiEndpoint {
             host: API_HOST_DEFAULT.to_owned(),
             addr: SocketAddr::new(API_IP_DEFAULT, API_PORT_DEFAULT),
             disable_address_cache: false,
+            root_certs: None,
         };
 
-        if cfg!(feature = "api-override") {
-            match (host_var, address_var) {
+        #[cfg(feature = "api-override")]
+        {
+            let address_var = address_var.map(|address| {
+                address
+                    .parse()
+                    .expect("MULLVAD_API_ADDR is not a valid socketaddr")
+            });
+            let config = ApiOverrideConfig::load().with_env_overrides(
+                host_var,
+                address_var,
+                root_cert_var.map(std::path::PathBuf::from),
+            );
+
+            match (config.host, config.address) {
                 (None, None) => (),
-                (Some(_), None) => panic!("MULLVAD_API_HOST is set, but not MULLVAD_API_ADDR"),
-                (None, Some(_)) => panic!("MULLVAD_API_ADDR is set, but not MULLVAD_API_HOST"),
+                (Some(_), None) => panic!("The API host is overridden, but not the address"),
+                (None, Some(_)) => panic!("The API address is overridden, but not the host"),
                 (Some(user_host), Some(user_addr)) => {
                     api.host = user_host;
-                    api.addr = user_addr
-                        .parse()
-                        .expect("MULLVAD_API_ADDR is not a valid socketaddr");
+                    api.addr = user_addr;
                     api.disable_address_cache = true;
                     log::debug!("Overriding API. Using {} at {}", api.host, api.addr);
                 }
             }
-        } else {
-            if host_var.is_some() || address_var.is_some() {
+
+            if let Some(path) = config.root_cert {
+                let root_certs = std::fs::read(&path).unwrap_or_else(|error| {
+                    panic!(
+                        "Failed to read API root certificates {}: {}",
+                        path.display(),
+                        error
+                    )
+                });
+                api.root_certs = Some(root_certs);
+                log::debug!("Overriding API root certificates with {}", path.display());
+            }
+        }
+        #[cfg(not(feature = "api-override"))]
+        {
+            if host_var.is_some() || address_var.is_some() || root_cert_var.is_some() {
                 log::warn!(
-                    "MULLVAD_API_HOST and MULLVAD_API_ADDR are ignored in production builds"
+                    "MULLVAD_API_HOST, MULLVAD_API_ADDR and MULLVAD_API_ROOT_CERT_FILE are \
+                     ignored in production builds"
                 );
             }
         }
@@ -113,11 +220,20 @@ impl ApiEndpoint {
     }
 }
 
+/// Returns the hostname that the API is reached at.
+pub fn api_host() -> &'static str {
+    &API.host
+}
+
 /// A type that helps with the creation of RPC connections.
 pub struct MullvadRpcRuntime {
     handle: tokio::runtime::Handle,
     pub address_cache: AddressCache,
     api_availability: availability::ApiAvailability,
+    trace: ApiTrace,
+    system_proxy: system_proxy::SystemProxyState,
+    tunnel_route: https_client_with_sni::TunnelRouteState,
+    api_access: ApiAccess,
     #[cfg(target_os = "android")]
     socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
 }
@@ -149,9 +265,13 @@ impl MullvadRpcRuntime {
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> Result<Self, Error> {
         Ok(MullvadRpcRuntime {
+            api_access: ApiAccess::new(handle.clone()),
             handle,
             address_cache: AddressCache::new(vec![API.addr], None)?,
             api_availability: ApiAvailability::new(availability::State::default()),
+            trace: ApiTrace::new(),
+            system_proxy: Default::default(),
+            tunnel_route: Default::default(),
             #[cfg(target_os = "android")]
             socket_bypass_tx,
         })
@@ -209,9 +329,13 @@ impl MullvadRpcRuntime {
         };
 
         Ok(MullvadRpcRuntime {
+            api_access: ApiAccess::new(handle.clone()),
             handle,
             address_cache,
             api_availability: ApiAvailability::new(availability::State::default()),
+            trace: ApiTrace::new(),
+            system_proxy: Default::default(),
+            tunnel_route: Default::default(),
             #[cfg(target_os = "android")]
             socket_bypass_tx,
         })
@@ -232,6 +356,10 @@ impl MullvadRpcRuntime {
             sni_hostname,
             self.api_availability.handle(),
             self.address_cache.clone(),
+            self.trace.clone(),
+            self.system_proxy.clone(),
+            self.tunnel_route.clone(),
+            self.api_access.clone(),
             #[cfg(target_os = "android")]
             self.socket_bypass_tx.clone(),
         );
@@ -240,6 +368,25 @@ impl MullvadRpcRuntime {
         handle
     }
 
+    /// Sets whether connections that cannot be established directly may go through the HTTP proxy
+    /// configured in the OS. This applies to all request services created by this runtime.
+    pub fn set_use_system_proxy(&self, enabled: bool) {
+        self.system_proxy.set_enabled(enabled);
+    }
+
+    /// Sets whether the tunnel is up. While it is, API connections are routed through the tunnel
+    /// and are never sent outside of it. Existing connections are not affected, so they should be
+    /// reset when this changes.
+    pub fn set_tunnel_connected(&self, connected: bool) {
+        self.tunnel_route.set_in_tunnel(connected);
+    }
+
+    /// Returns the handle that controls when the API is allowed through the firewall. This applies
+    /// to all request services created by this runtime.
+    pub fn api_access(&self) -> ApiAccess {
+        self.api_access.clone()
+    }
+
     /// Returns a request factory initialized to create requests for the master API
     pub fn mullvad_rest_handle(&mut self) -> rest::MullvadRestHandle {
         let service = self.new_request_service(Some(API.host.clone()));
@@ -269,6 +416,11 @@ impl MullvadRpcRuntime {
     pub fn availability_handle(&self) -> ApiAvailabilityHandle {
         self.api_availability.handle()
     }
+
+    /// Returns the log of requests made by the request services of this runtime.
+    pub fn api_trace(&self) -> ApiTrace {
+        self.trace.clone()
+    }
 }
 
 #[derive(Clone)]
@@ -349,6 +501,55 @@ impl AccountsProxy {
         async move { rest::deserialize_body(response.await?).await }
     }
 
+    /// Starts a Google Play purchase and returns the token that identifies the account in it.
+    pub fn init_play_purchase(
+        &mut self,
+        account_token: AccountToken,
+    ) -> impl Future<Output = Result<PlayPurchasePaymentToken, rest::Error>> {
+        #[derive(serde::Deserialize)]
+        struct PlayPurchaseInitResponse {
+            obfuscated_id: String,
+        }
+
+        let service = self.handle.service.clone();
+        let response = rest::send_request(
+            &self.handle.factory,
+            service,
+            "/v1/google-play/init",
+            Method::POST,
+            Some(account_token),
+            &[StatusCode::OK],
+        );
+
+        async move {
+            let response: PlayPurchaseInitResponse =
+                rest::deserialize_body(response.await?).await?;
+            Ok(response.obfuscated_id)
+        }
+    }
+
+    /// Verifies a Google Play purchase, which adds the purchased time to the account.
+    pub fn verify_play_purchase(
+        &mut self,
+        account_token: AccountToken,
+        play_purchase: PlayPurchase,
+    ) -> impl Future<Output = Result<(), rest::Error>> {
+        let service = self.handle.service.clone();
+        let response = rest::post_request_with_json(
+            &self.handle.factory,
+            service,
+            "/v1/google-play/verify",
+            &play_purchase,
+            Some(account_token),
+            &[StatusCode::ACCEPTED],
+        );
+
+        async move {
+            response.await?;
+            Ok(())
+        }
+    }
+
     pub fn get_www_auth_token(
         &self,
         account: AccountToken,
@@ -557,6 +758,26 @@ impl WireguardKeyProxy {
         rest::deserialize_body(response).await
     }
 
+    /// Lists the WireGuard keys that are registered on the account.
+    pub async fn list_wireguard_keys(
+        &mut self,
+        account_token: AccountToken,
+    ) -> Result<Vec<mullvad_types::wireguard::RegisteredKey>, rest::Error> {
+        let service = self.handle.service.clone();
+
+        let response = rest::send_request(
+            &self.handle.factory,
+            service,
+            "/v1/wireguard-keys",
+            Method::GET,
+            Some(account_token),
+            &[StatusCode::OK],
+        )
+        .await?;
+
+        rest::deserialize_body(response).await
+    }
+
     pub fn remove_wireguard_key(
         &mut self,
         account_token: AccountToken,
@@ -606,4 +827,109 @@ impl ApiProxy {
 
         rest::deserialize_body(response).await
     }
+
+    /// Checks the system clock against the time of the API server. The skew is detected either
+    /// from the `Date` header of a response, or from the API certificate being invalid only
+    /// because of the system clock. Returns `None` if the response has no valid `Date` header.
+    pub async fn get_clock_skew(&self) -> Result<Option<ClockSkew>, rest::Error> {
+        let service = self.handle.service.clone();
+
+        let result = rest::send_request(
+            &self.handle.factory,
+            service,
+            "/v1/api-addrs",
+            Method::GET,
+            None,
+            &[StatusCode::OK],
+        )
+        .await;
+
+        match result {
+            Ok(response) => Ok(clock_skew::response_date_skew(&response)),
+            Err(rest::Error::ClockSkew(skew)) => Ok(Some(skew)),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "api-override"))]
+mod test {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    const OVERRIDE_PATH: &str = "/settings/api-override.json";
+
+    #[test]
+    fn test_parse_api_override() {
+        let config = ApiOverrideConfig::parse(
+            Path::new(OVERRIDE_PATH),
+            br#"{"host": "api.example.com", "address": "10.0.0.1:443"}"#,
+        );
+        assert_eq!(
+            config,
+            ApiOverrideConfig {
+                host: Some("api.example.com".to_owned()),
+                address: Some("10.0.0.1:443".parse().unwrap()),
+                root_cert: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_api_override_is_ignored() {
+        let path = Path::new(OVERRIDE_PATH);
+        assert_eq!(
+            ApiOverrideConfig::parse(path, b"{\"host\": "),
+            ApiOverrideConfig::default()
+        );
+        assert_eq!(
+            ApiOverrideConfig::parse(path, br#"{"address": "not an address"}"#),
+            ApiOverrideConfig::default()
+        );
+        assert_eq!(
+            ApiOverrideConfig::load_from(Path::new("/nonexistent/api-override.json")),
+            ApiOverrideConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_env_overrides_take_precedence() {
+        let file_config = || ApiOverrideConfig {
+            host: Some("file.example.com".to_owned()),
+            address: Some("10.0.0.1:443".parse().unwrap()),
+            root_cert: Some(PathBuf::from("/file/root.pem")),
+        };
+
+        assert_eq!(
+            file_config().with_env_overrides(None, None, None),
+            file_config()
+        );
+
+        let env_address: SocketAddr = "10.0.0.2:8443".parse().unwrap();
+        assert_eq!(
+            file_config().with_env_overrides(
+                Some("env.example.com".to_owned()),
+                Some(env_address),
+                Some(PathBuf::from("/env/root.pem")),
+            ),
+            ApiOverrideConfig {
+                host: Some("env.example.com".to_owned()),
+                address: Some(env_address),
+                root_cert: Some(PathBuf::from("/env/root.pem")),
+            }
+        );
+
+        // Each variable only replaces its own field
+        assert_eq!(
+            ApiOverrideConfig::default().with_env_overrides(
+                Some("env.example.com".to_owned()),
+                None,
+                None
+            ),
+            ApiOverrideConfig {
+                host: Some("env.example.com".to_owned()),
+                ..ApiOverrideConfig::default()
+            }
+        );
+    }
 }
@@ -38,6 +38,7 @@ impl RelayListProxy {
         let future = async move {
             let mut request = request?;
             request.set_timeout(RELAY_LIST_TIMEOUT);
+            request.add_header(header::ACCEPT_ENCODING, "deflate")?;
 
             if let Some(ref tag) = etag {
                 request.add_header(header::IF_NONE_MATCH, tag)?;
@@ -2,12 +2,16 @@
 pub use crate::https_client_with_sni::SocketBypassRequest;
 use crate::{
     address_cache::AddressCache,
+    api_access::ApiAccess,
     availability::ApiAvailabilityHandle,
-    https_client_with_sni::{HttpsConnectorWithSni, HttpsConnectorWithSniHandle},
+    clock_skew::{self, ClockSkew},
+    https_client_with_sni::{HttpsConnectorWithSni, HttpsConnectorWithSniHandle, TunnelRouteState},
+    system_proxy::SystemProxyState,
+    trace::{ApiTrace, ApiTraceEntry},
 };
 use futures::{
     channel::{mpsc, oneshot},
-    future::{abortable, AbortHandle, Aborted},
+    future::{AbortHandle, AbortRegistration, Abortable, Aborted},
     sink::SinkExt,
     stream::StreamExt,
     TryFutureExt,
@@ -25,6 +29,7 @@ use std::{
     str::FromStr,
     time::{Duration, Instant},
 };
+use talpid_future::retry::{ExponentialBackoff, Jittered};
 use talpid_types::ErrorExt;
 use tokio::runtime::Handle;
 
@@ -36,10 +41,15 @@ pub type Response = hyper::Response<hyper::Body>;
 const TIMER_CHECK_INTERVAL: Duration = Duration::from_secs(60);
 const API_IP_CHECK_DELAY: Duration = Duration::from_secs(15 * 60);
 const API_IP_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
-const API_IP_CHECK_ERROR_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const API_IP_CHECK_ERROR_INTERVAL_INITIAL: Duration = Duration::from_secs(15 * 60);
+const API_IP_CHECK_ERROR_INTERVAL_FACTOR: u32 = 2;
+const API_IP_CHECK_ERROR_INTERVAL_MAX: Duration = Duration::from_secs(6 * 60 * 60);
 
 pub type Result<T> = std::result::Result<T, Error>;
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest size that a compressed response body may be inflated to. The relay list, the largest
+/// response, is a few megabytes.
+const MAX_INFLATED_BODY_SIZE: usize = 32 * 1024 * 1024;
 
 /// Describes all the ways a REST request can fail
 #[derive(err_derive::Error, Debug)]
@@ -62,6 +72,9 @@ pub enum Error {
     #[error(display = "Failed to deserialize data")]
     DeserializeError(#[error(source)] serde_json::Error),
 
+    #[error(display = "Failed to decompress the response body")]
+    DecompressError,
+
     #[error(display = "Failed to send request to rest client")]
     SendError,
 
@@ -75,6 +88,10 @@ pub enum Error {
     /// The string given was not a valid URI.
     #[error(display = "Not a valid URI")]
     UriError(#[error(source)] http::uri::InvalidUri),
+
+    /// The API certificate is only invalid because the system clock is wrong.
+    #[error(display = "The system clock is at least {}", _0)]
+    ClockSkew(ClockSkew),
 }
 
 impl Error {
@@ -84,6 +101,17 @@ impl Error {
             _ => false,
         }
     }
+
+    /// Replaces connection errors that were caused by a wrong system clock with
+    /// `Error::ClockSkew`.
+    fn detect_clock_skew(self) -> Self {
+        if let Error::HyperError(hyper_error) = &self {
+            if let Some(skew) = clock_skew::from_hyper_error(hyper_error) {
+                return Error::ClockSkew(skew);
+            }
+        }
+        self
+    }
 }
 
 /// A service that executes HTTP requests, allowing for on-demand termination of all in-flight
@@ -98,6 +126,9 @@ pub(crate) struct RequestService {
     in_flight_requests: BTreeMap<u64, AbortHandle>,
     api_availability: ApiAvailabilityHandle,
     address_cache: AddressCache,
+    trace: ApiTrace,
+    api_access: ApiAccess,
+    uses_api_sni: bool,
 }
 
 impl RequestService {
@@ -107,11 +138,19 @@ impl RequestService {
         sni_hostname: Option<String>,
         api_availability: ApiAvailabilityHandle,
         address_cache: AddressCache,
+        trace: ApiTrace,
+        system_proxy: SystemProxyState,
+        tunnel_route: TunnelRouteState,
+        api_access: ApiAccess,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> RequestService {
+        let uses_api_sni = sni_hostname.is_some();
         let (connector, connector_handle) = HttpsConnectorWithSni::new(
             handle.clone(),
             sni_hostname,
+            system_proxy,
+            tunnel_route,
+            api_access.clone(),
             #[cfg(target_os = "android")]
             socket_bypass_tx.clone(),
         );
@@ -129,6 +168,9 @@ impl RequestService {
             next_id: 0,
             api_availability,
             address_cache,
+            trace,
+            api_access,
+            uses_api_sni,
         }
     }
 
@@ -144,64 +186,8 @@ impl RequestService {
         match command {
             RequestCommand::NewRequest(request, completion_tx) => {
                 let id = self.id();
-                let mut tx = self.command_tx.clone();
-                let timeout = request.timeout();
-
-                let hyper_request = request.into_request();
-                let host_addr = get_request_socket_addr(&hyper_request);
-
-                let api_availability = self.api_availability.clone();
-                let suspend_fut = api_availability.wait_for_unsuspend();
-                let request_fut = self.client.request(hyper_request).map_err(Error::from);
-
-                let (request_future, abort_handle) = abortable(async move {
-                    let _ = suspend_fut.await;
-                    request_fut.await
-                });
-                let address_cache = self.address_cache.clone();
-                let handle = self.handle.clone();
-
-                let future = async move {
-                    let response =
-                        tokio::time::timeout(timeout, request_future.map_err(Error::Aborted))
-                            .await
-                            .map_err(Error::TimeoutError);
-
-                    let response = flatten_result(flatten_result(response));
-                    if let Some(host_addr) = host_addr {
-                        if let Err(err) = &response {
-                            if err.is_network_error() {
-                                log::error!(
-                                    "{}",
-                                    err.display_chain_with_msg("HTTP request failed")
-                                );
-                                if !api_availability.get_state().is_offline() {
-                                    let current_address = address_cache.peek_address();
-                                    if current_address == host_addr
-                                        && address_cache.has_tried_current_address()
-                                    {
-                                        handle.spawn(async move {
-                                            address_cache.select_new_address().await;
-                                            let new_address = address_cache.peek_address();
-                                            log::error!(
-                                                "Request failed using address {}. Trying next API address: {}",
-                                                current_address,
-                                                new_address,
-                                            );
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-                    if completion_tx.send(response).is_err() {
-                        log::trace!(
-                            "Failed to send response to caller, caller channel is shut down"
-                        );
-                    }
-                    let _ = tx.send(RequestCommand::RequestFinished(id)).await;
-                };
+                let (abort_handle, abort_registration) = AbortHandle::new_pair();
+                let future = self.request_future(id, request, completion_tx, abort_registration);
 
                 self.in_flight_requests.insert(id, abort_handle);
                 self.handle.spawn(future);
@@ -216,6 +202,115 @@ impl RequestService {
         }
     }
 
+    /// Returns a future that executes the request once the API is no longer suspended. The
+    /// request is cancelled if the service is reset or if the caller stops waiting for the
+    /// response. Dropping the hyper request closes its connection instead of leaving it in flight.
+    fn request_future(
+        &self,
+        id: u64,
+        request: RestRequest,
+        mut completion_tx: oneshot::Sender<Result<Response>>,
+        abort_registration: AbortRegistration,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let mut tx = self.command_tx.clone();
+        let timeout = request.timeout();
+
+        let hyper_request = request.into_request();
+        let host_addr = get_request_socket_addr(&hyper_request);
+        let mut trace_entry = ApiTraceEntry {
+            timestamp: chrono::Utc::now(),
+            method: hyper_request.method().to_string(),
+            path: hyper_request.uri().path().to_owned(),
+            address: host_addr,
+            uses_api_sni: self.uses_api_sni,
+            result: String::new(),
+            success: false,
+            duration: Duration::from_secs(0),
+            attempt: 0,
+        };
+        let trace = self.trace.clone();
+        let start = Instant::now();
+
+        let api_availability = self.api_availability.clone();
+        let suspend_fut = api_availability.wait_for_unsuspend();
+        let request_fut = self
+            .client
+            .request(hyper_request)
+            .map_err(|error| Error::from(error).detect_clock_skew());
+        let api_access = self.api_access.clone();
+
+        let request_future = Abortable::new(
+            async move {
+                let _ = suspend_fut.await;
+                // Keeps the API reachable for the duration of the request, whether it opens a
+                // new connection or reuses one
+                let _api_access_lease = api_access.acquire_for_request().await;
+                request_fut.await
+            },
+            abort_registration,
+        );
+        let address_cache = self.address_cache.clone();
+        let handle = self.handle.clone();
+
+        async move {
+            let response_future =
+                tokio::time::timeout(timeout, request_future.map_err(Error::Aborted));
+            let response = tokio::select! {
+                response = response_future => {
+                    flatten_result(flatten_result(response.map_err(Error::TimeoutError)))
+                }
+                _ = completion_tx.cancellation() => {
+                    log::trace!("Request was cancelled by the caller");
+                    let _ = tx.send(RequestCommand::RequestFinished(id)).await;
+                    return;
+                }
+            };
+
+            if let Ok(response) = &response {
+                clock_skew::log_response_date_offset(response);
+            }
+
+            trace_entry.duration = start.elapsed();
+            match &response {
+                Ok(response) => {
+                    trace_entry.success = true;
+                    trace_entry.result = response.status().to_string();
+                }
+                Err(error) => trace_entry.result = error.display_chain(),
+            }
+            trace.record(trace_entry);
+
+            if let Some(host_addr) = host_addr {
+                if let Err(err) = &response {
+                    if err.is_network_error() {
+                        log::error!("{}", err.display_chain_with_msg("HTTP request failed"));
+                        if !api_availability.get_state().is_offline() {
+                            let current_address = address_cache.peek_address();
+                            if current_address == host_addr
+                                && address_cache.has_tried_current_address()
+                            {
+                                handle.spawn(async move {
+                                    address_cache.select_new_address().await;
+                                    let new_address = address_cache.peek_address();
+                                    log::error!(
+                                        "Request failed using address {}. Trying next API address: {}",
+                                        current_address,
+                                        new_address,
+                                    );
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+
+            if completion_tx.send(response).is_err() {
+                log::trace!("Failed to send response to caller, caller channel is shut down");
+            }
+            let _ = tx.send(RequestCommand::RequestFinished(id)).await;
+        }
+    }
+
     fn reset(&mut self) {
         let old_requests = mem::take(&mut self.in_flight_requests);
         for (_, abort_handle) in old_requests {
@@ -268,7 +363,8 @@ impl RequestServiceHandle {
         let _ = done_rx.await;
     }
 
-    /// Submits a `RestRequest` for exectuion to the request service.
+    /// Submits a `RestRequest` for exectuion to the request service. Dropping the returned future
+    /// cancels the request.
     pub async fn request(&self, request: RestRequest) -> Result<Response> {
         let (completion_tx, completion_rx) = oneshot::channel();
         let mut tx = self.tx.clone();
@@ -565,9 +661,38 @@ pub async fn deserialize_body<T: serde::de::DeserializeOwned>(mut response: Resp
         body.extend(&chunk?);
     }
 
+    let is_deflated = response
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .map(|encoding| encoding.as_bytes().eq_ignore_ascii_case(b"deflate"))
+        .unwrap_or(false);
+    if is_deflated {
+        body = inflate_body(&body, MAX_INFLATED_BODY_SIZE)?;
+    }
+
     serde_json::from_slice(&body).map_err(Error::DeserializeError)
 }
 
+/// Decompresses a body with the `deflate` content encoding. This should be zlib data, but some
+/// servers send raw deflate data, so that is accepted as well. Fails if the decompressed body
+/// would be larger than `max_size`.
+fn inflate_body(body: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    // The limit applies to the output buffer, which is grown by doubling it, so a body that is
+    // just below `max_size` may need a buffer that is almost twice as large
+    let buffer_limit = max_size.saturating_mul(2);
+    let inflated = miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(body, buffer_limit)
+        .or_else(|_| miniz_oxide::inflate::decompress_to_vec_with_limit(body, buffer_limit))
+        .map_err(|status| {
+            log::error!("Failed to inflate response body: {:?}", status);
+            Error::DecompressError
+        })?;
+    if inflated.len() > max_size {
+        log::error!("Inflated response body is larger than {} bytes", max_size);
+        return Err(Error::DecompressError);
+    }
+    Ok(inflated)
+}
+
 pub async fn parse_rest_response(
     response: Response,
     expected_statuses: &'static [hyper::StatusCode],
@@ -637,7 +762,16 @@ impl MullvadRestHandle {
             let api_proxy = crate::ApiProxy::new(handle);
             let mut next_check = Instant::now() + API_IP_CHECK_DELAY;
 
-            let next_error_check = || Instant::now() + API_IP_CHECK_ERROR_INTERVAL;
+            let new_error_delays = || {
+                Jittered::jitter(
+                    ExponentialBackoff::new(
+                        API_IP_CHECK_ERROR_INTERVAL_INITIAL,
+                        API_IP_CHECK_ERROR_INTERVAL_FACTOR,
+                    )
+                    .max_delay(API_IP_CHECK_ERROR_INTERVAL_MAX),
+                )
+            };
+            let mut error_delays = new_error_delays();
             let next_regular_check = || Instant::now() + API_IP_CHECK_INTERVAL;
 
             let mut interval = tokio::time::interval_at(next_check.into(), TIMER_CHECK_INTERVAL);
@@ -647,7 +781,7 @@ impl MullvadRestHandle {
                 if next_check < Instant::now() {
                     if let Err(error) = availability.wait_background().await {
                         log::error!("Failed while waiting for API: {}", error);
-                        next_check = next_error_check();
+                        next_check = Instant::now() + error_delays.next().unwrap_or(API_IP_CHECK_ERROR_INTERVAL_MAX);
                         continue;
                     }
                     match api_proxy.clone().get_api_addrs().await {
@@ -657,10 +791,12 @@ impl MullvadRestHandle {
                                 log::error!("Failed to save newly updated API addresses: {}", err);
                             }
                             next_check = next_regular_check();
+                            error_delays = new_error_delays();
                         }
                         Err(err) => {
-                            log::error!("Failed to fetch new API addresses: {}, will retry again in {} seconds", err, API_IP_CHECK_ERROR_INTERVAL.as_secs());
-                            next_check = next_error_check();
+                            let delay = error_delays.next().unwrap_or(API_IP_CHECK_ERROR_INTERVAL_MAX);
+                            log::error!("Failed to fetch new API addresses: {}, will retry again in {} seconds", err, delay.as_secs());
+                            next_check = Instant::now() + delay;
                         }
                     }
                 }
@@ -685,3 +821,26 @@ fn flatten_result<T, E>(
         Err(err) => Err(err),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::inflate_body;
+
+    #[test]
+    fn test_inflate_body() {
+        let data = vec![b'a'; 1024];
+        let zlib = miniz_oxide::deflate::compress_to_vec_zlib(&data, 6);
+        assert_eq!(inflate_body(&zlib, data.len()).unwrap(), data);
+        let raw = miniz_oxide::deflate::compress_to_vec(&data, 6);
+        assert_eq!(inflate_body(&raw, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_inflate_body_limit() {
+        let data = vec![0u8; 1024 * 1024];
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&data, 10);
+        assert!(compressed.len() < 4096);
+        assert!(inflate_body(&compressed, data.len() - 1).is_err());
+        assert_eq!(inflate_body(&compressed, data.len()).unwrap(), data);
+    }
+}
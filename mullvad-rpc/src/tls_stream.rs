@@ -1,4 +1,8 @@
-//! Provides a TLS 1.3 stream with SNI and LE root cert only.
+//! Provides a TLS 1.3 stream with SNI and LE root cert only. The root certificates are compiled
+//! in and rustls is used on all platforms, so the certificate is validated in the same way
+//! everywhere. With the `custom-root-cert` feature, the roots in the PEM file at
+//! `MULLVAD_API_ROOT_CERT` are trusted instead. With the `api-override` feature, the roots can
+//! also be replaced at runtime.
 use std::{
     io::{self, ErrorKind},
     pin::Pin,
@@ -6,14 +10,21 @@ use std::{
     task::{self, Poll},
 };
 
+use crate::clock_skew::ClockSkewVerifier;
 use hyper::client::connect::{Connected, Connection};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio_rustls::{
-    rustls::{self, ClientConfig, ServerName},
+    rustls::{self, client::WebPkiVerifier, ClientConfig, ServerName},
     TlsConnector,
 };
 
-const LE_ROOT_CERT: &[u8] = include_bytes!("../le_root_cert.pem");
+pub(crate) const LE_ROOT_CERT: &[u8] = include_bytes!("../le_root_cert.pem");
+
+/// The root certificates that the API certificate must chain up to.
+#[cfg(not(feature = "custom-root-cert"))]
+const ROOT_CERTS: &[u8] = LE_ROOT_CERT;
+#[cfg(feature = "custom-root-cert")]
+const ROOT_CERTS: &[u8] = include_bytes!(env!("MULLVAD_API_ROOT_CERT"));
 
 pub struct TlsStream<S: AsyncRead + AsyncWrite + Unpin> {
     stream: Pin<Box<tokio_rustls::client::TlsStream<S>>>,
@@ -25,19 +36,21 @@ where
 {
     pub async fn connect_https(stream: S, domain: &str) -> io::Result<TlsStream<S>> {
         lazy_static::lazy_static! {
-            static ref TLS_CONFIG: Arc<ClientConfig> = {
-                let config = ClientConfig::builder()
-                    .with_safe_default_cipher_suites()
-                    .with_safe_default_kx_groups()
-                    .with_protocol_versions(&[&rustls::version::TLS13])
-                    .unwrap()
-                    .with_root_certificates(read_cert_store())
-                    .with_no_client_auth();
-                Arc::new(config)
-            };
+            static ref CERT_VERIFIER: Arc<WebPkiVerifier> =
+                Arc::new(WebPkiVerifier::new(read_cert_store(), None));
         }
 
-        let connector = TlsConnector::from(TLS_CONFIG.clone());
+        // The verifier records the clock skew of this connection, so it cannot be shared
+        let verifier = Arc::new(ClockSkewVerifier::new(CERT_VERIFIER.clone()));
+        let config = ClientConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_custom_certificate_verifier(verifier.clone())
+            .with_no_client_auth();
+
+        let connector = TlsConnector::from(Arc::new(config));
 
         let host = match ServerName::try_from(domain) {
             Ok(n) => n,
@@ -49,7 +62,12 @@ where
             }
         };
 
-        let tls_stream = connector.connect(host, stream).await?;
+        let tls_stream = connector.connect(host, stream).await.map_err(|error| {
+            match verifier.detected_skew() {
+                Some(skew) => io::Error::new(ErrorKind::InvalidData, skew),
+                None => error,
+            }
+        })?;
 
         Ok(TlsStream {
             stream: Box::pin(tls_stream),
@@ -60,10 +78,11 @@ where
 fn read_cert_store() -> rustls::RootCertStore {
     let mut cert_store = rustls::RootCertStore::empty();
 
-    let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(LE_ROOT_CERT))
+    let root_certs = crate::API.root_certs.as_deref().unwrap_or(ROOT_CERTS);
+    let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(root_certs))
         .expect("Failed to parse pem file");
     let (num_certs_added, num_failures) = cert_store.add_parsable_certificates(&certs);
-    if num_failures > 0 || num_certs_added != 1 {
+    if num_failures > 0 || num_certs_added == 0 {
         panic!("Failed to add root cert");
     }
 
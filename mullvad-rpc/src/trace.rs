@@ -0,0 +1,113 @@
+//! Records the API requests made since the daemon started, so that it can be determined why
+//! requests such as logging in or registering a WireGuard key failed.
+
+use chrono::{DateTime, Utc};
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// The number of requests that are kept. Older entries are discarded first.
+const MAX_ENTRIES: usize = 256;
+
+/// A completed API request.
+#[derive(Debug, Clone)]
+pub struct ApiTraceEntry {
+    /// When the request was submitted.
+    pub timestamp: DateTime<Utc>,
+    pub method: String,
+    /// Path of the request, without the query string.
+    pub path: String,
+    /// The address that the request was sent to. This is `None` if the host is not an IP address.
+    pub address: Option<SocketAddr>,
+    /// Whether the request used the API hostname for SNI, or the host of the URI.
+    pub uses_api_sni: bool,
+    /// The response status code, or a description of the error.
+    pub result: String,
+    /// Whether a response was received. This is also true for unexpected status codes.
+    pub success: bool,
+    /// Time from the submission of the request until the response or error, including any time
+    /// spent waiting for the API to be unsuspended.
+    pub duration: Duration,
+    /// Counts the consecutive failed requests for the same method and path that came before this
+    /// one, starting at 1. Since the callers retry by submitting new requests, this is how retries
+    /// show up in the trace.
+    pub attempt: u32,
+}
+
+/// A bounded log of API requests. Clones share the same log.
+#[derive(Clone, Default)]
+pub struct ApiTrace {
+    entries: Arc<Mutex<VecDeque<ApiTraceEntry>>>,
+}
+
+impl ApiTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the recorded requests, oldest first.
+    pub fn entries(&self) -> Vec<ApiTraceEntry> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub(crate) fn record(&self, mut entry: ApiTraceEntry) {
+        let mut entries = self.entries.lock().unwrap();
+        entry.attempt = entries
+            .iter()
+            .rev()
+            .find(|previous| previous.method == entry.method && previous.path == entry.path)
+            .filter(|previous| !previous.success)
+            .map(|previous| previous.attempt + 1)
+            .unwrap_or(1);
+        if entries.len() >= MAX_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(path: &str, success: bool) -> ApiTraceEntry {
+        ApiTraceEntry {
+            timestamp: Utc::now(),
+            method: "GET".to_owned(),
+            path: path.to_owned(),
+            address: None,
+            uses_api_sni: true,
+            result: String::new(),
+            success,
+            duration: Duration::from_secs(0),
+            attempt: 0,
+        }
+    }
+
+    #[test]
+    fn test_attempts() {
+        let trace = ApiTrace::new();
+        trace.record(entry("/a", false));
+        trace.record(entry("/b", true));
+        trace.record(entry("/a", false));
+        trace.record(entry("/a", true));
+        trace.record(entry("/a", true));
+
+        let attempts: Vec<_> = trace.entries().iter().map(|entry| entry.attempt).collect();
+        assert_eq!(attempts, vec![1, 1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn test_bounded() {
+        let trace = ApiTrace::new();
+        for i in 0..MAX_ENTRIES + 1 {
+            trace.record(entry(&format!("/{}", i), true));
+        }
+        let entries = trace.entries();
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0].path, "/1");
+    }
+}
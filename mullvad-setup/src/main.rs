@@ -3,11 +3,9 @@ use mullvad_management_interface::new_rpc_client;
 use mullvad_rpc::MullvadRpcRuntime;
 use mullvad_types::version::ParsedAppVersion;
 use std::{path::PathBuf, process, time::Duration};
-use talpid_core::{
-    firewall::{self, Firewall, FirewallArguments, InitialFirewallState},
-    future_retry::{constant_interval, retry_future_n},
-};
-use talpid_types::ErrorExt;
+use talpid_core::firewall::{self, Firewall, FirewallArguments, InitialFirewallState};
+use talpid_future::retry::{constant_interval, retry_future_n};
+use talpid_types::{firewall::BlockedStatePolicy, ErrorExt};
 
 pub const PRODUCT_VERSION: &str = include_str!(concat!(env!("OUT_DIR"), "/product-version.txt"));
 
@@ -160,6 +158,8 @@ async fn reset_firewall() -> Result<(), Error> {
     let mut firewall = Firewall::new(FirewallArguments {
         initial_state: InitialFirewallState::None,
         allow_lan: true,
+        lan_allow_list: None,
+        blocked_state_policy: BlockedStatePolicy::default(),
         #[cfg(target_os = "macos")]
         exclusion_gid: 0,
     })
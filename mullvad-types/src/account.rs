@@ -1,4 +1,4 @@
-use chrono::{offset::Utc, DateTime};
+use chrono::{offset::Utc, DateTime, Duration};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
@@ -6,6 +6,22 @@ use serde::{Deserialize, Serialize};
 /// Identifier used to authenticate or identify a Mullvad account.
 pub type AccountToken = String;
 
+/// Minimum length of a voucher code, not counting separators.
+pub const MIN_VOUCHER_LENGTH: usize = 16;
+
+/// Removes whitespace and dashes from a voucher code. Returns `None` if the code contains any
+/// other characters than ASCII letters and digits, or if it is too short to be a voucher code.
+pub fn normalize_voucher_code(code: &str) -> Option<String> {
+    let code: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect();
+    if code.len() < MIN_VOUCHER_LENGTH || !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some(code)
+}
+
 /// Account expiration info returned by the API via `/v1/me`.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 #[cfg_attr(target_os = "android", derive(IntoJava))]
@@ -35,3 +51,118 @@ pub struct VoucherSubmission {
     #[cfg_attr(target_os = "android", jnix(map = "|expiry| expiry.to_string()"))]
     pub new_expiry: DateTime<Utc>,
 }
+
+/// Token that identifies an account in a Google Play purchase. It is passed to the billing library
+/// as the obfuscated account ID.
+pub type PlayPurchasePaymentToken = String;
+
+/// A completed Google Play purchase that must be verified before time is added to the account.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PlayPurchase {
+    pub product_id: String,
+    #[serde(rename = "token")]
+    pub purchase_token: String,
+}
+
+/// How close an account is to running out of time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(target_os = "android", derive(IntoJava))]
+#[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
+pub enum ExpiryWarning {
+    /// Less than three days are left.
+    ThreeDaysLeft,
+    /// Less than one day is left.
+    OneDayLeft,
+    /// The account has run out of time.
+    Expired,
+}
+
+impl ExpiryWarning {
+    /// Returns the warning for an account that expires at `expiry`, if there is one.
+    pub fn for_expiry(expiry: DateTime<Utc>, now: DateTime<Utc>) -> Option<Self> {
+        let time_left = expiry - now;
+        if time_left < Duration::zero() {
+            Some(ExpiryWarning::Expired)
+        } else if time_left < Duration::days(1) {
+            Some(ExpiryWarning::OneDayLeft)
+        } else if time_left < Duration::days(3) {
+            Some(ExpiryWarning::ThreeDaysLeft)
+        } else {
+            None
+        }
+    }
+
+    /// Returns when the next warning takes effect for an account that expires at `expiry`, or
+    /// `None` if the account has already expired.
+    pub fn next_threshold(expiry: DateTime<Utc>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        [
+            expiry - Duration::days(3),
+            expiry - Duration::days(1),
+            expiry,
+        ]
+        .iter()
+        .copied()
+        .find(|threshold| *threshold >= now)
+    }
+}
+
+/// The expiry of the current account, which is sent to clients when it changes or when the
+/// account crosses a warning threshold.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(target_os = "android", derive(IntoJava))]
+#[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
+pub struct AccountExpiryEvent {
+    #[cfg_attr(target_os = "android", jnix(map = "|expiry| expiry.to_string()"))]
+    pub expiry: DateTime<Utc>,
+    pub warning: Option<ExpiryWarning>,
+}
+
+impl AccountExpiryEvent {
+    pub fn new(expiry: DateTime<Utc>, now: DateTime<Utc>) -> Self {
+        Self {
+            expiry,
+            warning: ExpiryWarning::for_expiry(expiry, now),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_voucher_code() {
+        assert_eq!(
+            normalize_voucher_code(" ABCD-EFGH-1234-5678\n").as_deref(),
+            Some("ABCDEFGH12345678")
+        );
+        assert_eq!(normalize_voucher_code("ABCD-EFGH-1234"), None);
+        assert_eq!(normalize_voucher_code("ABCD-EFGH-1234-567!"), None);
+    }
+
+    #[test]
+    fn test_expiry_warnings() {
+        let now = Utc::now();
+        let warning = |time_left| ExpiryWarning::for_expiry(now + time_left, now);
+        assert_eq!(warning(Duration::days(5)), None);
+        assert_eq!(
+            warning(Duration::hours(71)),
+            Some(ExpiryWarning::ThreeDaysLeft)
+        );
+        assert_eq!(
+            warning(Duration::hours(23)),
+            Some(ExpiryWarning::OneDayLeft)
+        );
+        assert_eq!(warning(Duration::seconds(-1)), Some(ExpiryWarning::Expired));
+
+        let expiry = now + Duration::hours(48);
+        assert_eq!(
+            ExpiryWarning::next_threshold(expiry, now),
+            Some(expiry - Duration::days(1))
+        );
+        assert_eq!(
+            ExpiryWarning::next_threshold(expiry, expiry + Duration::seconds(1)),
+            None
+        );
+    }
+}
@@ -53,6 +53,13 @@ impl<'a> From<&'a str> for AuthFailed {
     }
 }
 
+impl AuthFailed {
+    /// Returns true if authentication failed because the account has run out of time.
+    pub fn is_expired_account(&self) -> bool {
+        matches!(self.reason, AuthFailedInner::ExpiredAccount)
+    }
+}
+
 impl fmt::Display for AuthFailed {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use self::AuthFailedInner::*;
@@ -104,4 +111,17 @@ mod tests {
             assert_eq!(*expected_output, parse_string(input));
         }
     }
+
+    #[test]
+    fn test_expired_account() {
+        assert!(
+            AuthFailed::from("[EXPIRED_ACCOUNT] This account has no time left")
+                .is_expired_account()
+        );
+        assert!(
+            !AuthFailed::from("[INVALID_ACCOUNT] This is not a valid Mullvad account")
+                .is_expired_account()
+        );
+        assert!(!AuthFailed::from("This account has no time left").is_expired_account());
+    }
 }
@@ -22,12 +22,12 @@ const RAIDUS_OF_EARTH: f64 = 6372.8;
 
 impl Location {
     pub fn distance_from(&self, other: &Location) -> f64 {
-        haversine_dist_deg(
-            self.latitude,
-            self.longitude,
-            other.latitude,
-            other.longitude,
-        )
+        self.distance_from_coordinates(other.latitude, other.longitude)
+    }
+
+    /// Returns the distance in kilometers to the given latitude and longitude degrees.
+    pub fn distance_from_coordinates(&self, latitude: f64, longitude: f64) -> f64 {
+        haversine_dist_deg(self.latitude, self.longitude, latitude, longitude)
     }
 }
 
@@ -84,6 +84,10 @@ pub struct GeoIpLocation {
     pub bridge_hostname: Option<String>,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub entry_hostname: Option<String>,
+    /// Index of the relay fallback tier that the relay was selected from, starting at 1. `None`
+    /// if the normal relay constraints were used.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub relay_fallback_tier: Option<u32>,
 }
 
 impl From<AmIMullvad> for GeoIpLocation {
@@ -104,6 +108,7 @@ impl From<AmIMullvad> for GeoIpLocation {
             hostname: None,
             bridge_hostname: None,
             entry_hostname: None,
+            relay_fallback_tier: None,
         }
     }
 }
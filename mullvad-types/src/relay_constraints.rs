@@ -185,6 +185,8 @@ pub struct RelayConstraints {
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub providers: Constraint<Providers>,
     #[cfg_attr(target_os = "android", jnix(skip))]
+    pub ownership: Constraint<Ownership>,
+    #[cfg_attr(target_os = "android", jnix(skip))]
     pub tunnel_protocol: Constraint<TunnelType>,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub wireguard_constraints: WireguardConstraints,
@@ -199,6 +201,7 @@ impl Default for RelayConstraints {
             tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
             location: Constraint::default(),
             providers: Constraint::default(),
+            ownership: Constraint::default(),
             wireguard_constraints: WireguardConstraints::default(),
             openvpn_constraints: OpenVpnConstraints::default(),
         }
@@ -210,6 +213,7 @@ impl RelayConstraints {
         RelayConstraints {
             location: update.location.unwrap_or_else(|| self.location.clone()),
             providers: update.providers.unwrap_or_else(|| self.providers.clone()),
+            ownership: update.ownership.unwrap_or(self.ownership),
             tunnel_protocol: update
                 .tunnel_protocol
                 .unwrap_or_else(|| self.tunnel_protocol.clone()),
@@ -250,8 +254,12 @@ impl fmt::Display for RelayConstraints {
         }
         write!(f, " using ")?;
         match self.providers {
-            Constraint::Any => write!(f, "any provider"),
-            Constraint::Only(ref constraint) => constraint.fmt(f),
+            Constraint::Any => write!(f, "any provider")?,
+            Constraint::Only(ref constraint) => constraint.fmt(f)?,
+        }
+        match self.ownership {
+            Constraint::Any => Ok(()),
+            Constraint::Only(ref ownership) => write!(f, " and {}", ownership),
         }
     }
 }
@@ -328,6 +336,7 @@ pub struct Providers {
 }
 
 /// Returned if the iterator contained no providers.
+#[derive(Debug)]
 pub struct NoProviders(());
 
 impl Providers {
@@ -368,6 +377,33 @@ impl fmt::Display for Providers {
     }
 }
 
+/// Limits the set of [`crate::relay_list::Relay`]s used by a `RelaySelector` based on whether
+/// the servers are owned by Mullvad or rented.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ownership {
+    MullvadOwned,
+    Rented,
+}
+
+impl Match<Relay> for Ownership {
+    fn matches(&self, relay: &Relay) -> bool {
+        match self {
+            Ownership::MullvadOwned => relay.owned,
+            Ownership::Rented => !relay.owned,
+        }
+    }
+}
+
+impl fmt::Display for Ownership {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Ownership::MullvadOwned => write!(f, "Mullvad-owned servers"),
+            Ownership::Rented => write!(f, "rented servers"),
+        }
+    }
+}
+
 impl fmt::Display for LocationConstraint {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match self {
@@ -427,6 +463,8 @@ impl Match<OpenVpnEndpointData> for OpenVpnConstraints {
 #[serde(default)]
 pub struct WireguardConstraints {
     pub port: Constraint<TransportPort>,
+    /// Limits the ports that are selected from when no specific port is set.
+    pub port_range: Constraint<PortRange>,
     pub ip_version: Constraint<IpVersion>,
     pub use_multihop: bool,
     pub entry_location: Constraint<LocationConstraint>,
@@ -435,10 +473,10 @@ pub struct WireguardConstraints {
 impl fmt::Display for WireguardConstraints {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match self.port {
-            Constraint::Any => write!(f, "any port")?,
+            Constraint::Any => self.fmt_port_range(f)?,
             Constraint::Only(port) => {
                 match port.port {
-                    Constraint::Any => write!(f, "any port")?,
+                    Constraint::Any => self.fmt_port_range(f)?,
                     Constraint::Only(port) => write!(f, "port {}", port)?,
                 }
                 write!(f, " over {}", port.protocol)?;
@@ -460,6 +498,68 @@ impl fmt::Display for WireguardConstraints {
     }
 }
 
+impl WireguardConstraints {
+    fn fmt_port_range(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self.port_range {
+            Constraint::Any => write!(f, "any port"),
+            Constraint::Only(range) => write!(f, "ports {}", range),
+        }
+    }
+}
+
+/// An inclusive range of ports.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct PortRange {
+    first: u16,
+    last: u16,
+}
+
+impl PortRange {
+    /// Returns a range of the ports from `first` to `last`, or `None` if `first` is greater than
+    /// `last`.
+    pub fn new(first: u16, last: u16) -> Option<Self> {
+        if first <= last {
+            Some(PortRange { first, last })
+        } else {
+            None
+        }
+    }
+
+    pub fn first(&self) -> u16 {
+        self.first
+    }
+
+    pub fn last(&self) -> u16 {
+        self.last
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        self.first <= port && port <= self.last
+    }
+
+    /// Returns the ports in `range`, given as an inclusive `(first, last)` pair, that are also in
+    /// this range.
+    pub fn intersection(&self, range: (u16, u16)) -> Option<(u16, u16)> {
+        let first = std::cmp::max(self.first, range.0);
+        let last = std::cmp::min(self.last, range.1);
+        if first <= last {
+            Some((first, last))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        if self.first == self.last {
+            write!(f, "{}", self.first)
+        } else {
+            write!(f, "{}-{}", self.first, self.last)
+        }
+    }
+}
+
 /// Specifies a specific endpoint or [`BridgeConstraints`] to use when `mullvad-daemon` selects a
 /// bridge server.
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
@@ -477,6 +577,7 @@ pub enum BridgeSettings {
 pub struct BridgeConstraints {
     pub location: Constraint<LocationConstraint>,
     pub providers: Constraint<Providers>,
+    pub ownership: Constraint<Ownership>,
 }
 
 impl fmt::Display for BridgeConstraints {
@@ -487,8 +588,12 @@ impl fmt::Display for BridgeConstraints {
         }
         write!(f, " using ")?;
         match self.providers {
-            Constraint::Any => write!(f, "any provider"),
-            Constraint::Only(ref constraint) => constraint.fmt(f),
+            Constraint::Any => write!(f, "any provider")?,
+            Constraint::Only(ref constraint) => constraint.fmt(f)?,
+        }
+        match self.ownership {
+            Constraint::Any => Ok(()),
+            Constraint::Only(ref ownership) => write!(f, " and {}", ownership),
         }
     }
 }
@@ -520,6 +625,7 @@ impl fmt::Display for BridgeState {
 pub struct InternalBridgeConstraints {
     pub location: Constraint<LocationConstraint>,
     pub providers: Constraint<Providers>,
+    pub ownership: Constraint<Ownership>,
     pub transport_protocol: Constraint<TransportProtocol>,
 }
 
@@ -571,6 +677,8 @@ pub struct RelayConstraintsUpdate {
     #[cfg_attr(target_os = "android", jnix(default))]
     pub providers: Option<Constraint<Providers>>,
     #[cfg_attr(target_os = "android", jnix(default))]
+    pub ownership: Option<Constraint<Ownership>>,
+    #[cfg_attr(target_os = "android", jnix(default))]
     pub tunnel_protocol: Option<Constraint<TunnelType>>,
     #[cfg_attr(target_os = "android", jnix(default))]
     pub wireguard_constraints: Option<WireguardConstraints>,
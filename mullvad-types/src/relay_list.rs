@@ -14,6 +14,9 @@ use talpid_types::net::{
     wireguard, Endpoint, TransportProtocol,
 };
 
+/// Name of the file that the relay list is cached in, and that it is bundled with the app as.
+pub const RELAYS_FILENAME: &str = "relays.json";
+
 /// Stores a list of relays for each country obtained from the API using
 /// `mullvad_rpc::RelayListProxy`. This can also be passed to frontends.
 #[derive(Debug, Clone, Deserialize, Serialize)]
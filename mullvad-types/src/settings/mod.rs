@@ -5,13 +5,17 @@ use crate::{
     },
     wireguard,
 };
+use ipnetwork::IpNetwork;
 #[cfg(target_os = "android")]
 use jnix::{jni::objects::JObject, FromJava, IntoJava, JnixEnv};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::net::IpAddr;
+use std::{collections::BTreeMap, net::IpAddr};
 #[cfg(target_os = "windows")]
 use std::{collections::HashSet, path::PathBuf};
-use talpid_types::net::{self, openvpn, GenericTunnelOptions};
+use talpid_types::{
+    firewall::BlockedStatePolicy,
+    net::{self, openvpn, proxy::EntryProxy, GenericTunnelOptions},
+};
 
 /// The version used by the current version of the code. Should always be the
 /// latest version that exists in `SettingsVersion`.
@@ -65,26 +69,76 @@ pub struct Settings {
     #[cfg_attr(target_os = "android", jnix(skip))]
     wireguard: Option<wireguard::WireguardData>,
     relay_settings: RelaySettings,
+    /// Ordered relay constraints to fall back to when connecting with the normal relay
+    /// constraints repeatedly fails.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub relay_fallback_tiers: Vec<RelayConstraints>,
+    /// If relays with a low measured latency should be more likely to be selected among those
+    /// matching the relay constraints.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub prefer_low_latency: bool,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub bridge_settings: BridgeSettings,
     #[cfg_attr(target_os = "android", jnix(skip))]
     bridge_state: BridgeState,
     /// If the daemon should allow communication with private (LAN) networks.
     pub allow_lan: bool,
+    /// Private networks that LAN communication is limited to when `allow_lan` is set. All
+    /// private networks are reachable if this is `None`.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub lan_allow_list: Option<Vec<IpNetwork>>,
+    /// Local network services that are permitted while all other traffic is blocked.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub blocked_state_policy: BlockedStatePolicy,
     /// Extra level of kill switch. When this setting is on, the disconnected state will block
     /// the firewall to not allow any traffic in or out.
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub block_when_disconnected: bool,
+    /// If the daemon should keep blocking all traffic when the account runs out of time while the
+    /// tunnel is secured, rather than disconnecting.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub block_when_account_expired: bool,
+    /// If the tunnel should only be reported as connected once the applied firewall rules and
+    /// routes have been read back and confirmed.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub strict_kill_switch: bool,
     /// If the daemon should connect the VPN tunnel directly on start or not.
     pub auto_connect: bool,
+    /// If a secured tunnel should be reconnected when the device joins a different network.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub reconnect_on_network_change: bool,
+    /// Networks on which the tunnel is disconnected automatically.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub trusted_networks: TrustedNetworks,
     /// Options that should be applied to tunnels of a specific type regardless of where the relays
     /// might be located.
     pub tunnel_options: TunnelOptions,
     /// Whether to notify users of beta updates.
     pub show_beta_releases: bool,
+    /// How much identifying information is removed from the daemon and tunnel logs.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub log_privacy: LogPrivacy,
+    /// SOCKS5 proxy that all traffic to the relay is sent through.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub entry_proxy: Option<EntryProxy>,
     /// Split tunneling settings
     #[cfg(windows)]
     pub split_tunnel: SplitTunnelSettings,
+    /// If clients of Internet Connection Sharing or Mobile Hotspot should be allowed to send
+    /// their traffic through the tunnel.
+    #[cfg(windows)]
+    pub allow_connection_sharing: bool,
+    /// Networks whose traffic bypasses the tunnel
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub excluded_networks: Vec<IpNetwork>,
+    /// If API connections that cannot be established directly may go through the HTTP proxy
+    /// configured in the OS.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub use_system_proxy: bool,
+    /// Feature flags that have been turned on or off, by name. Flags that are not listed here
+    /// have their default state.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub feature_flags: BTreeMap<String, bool>,
     /// Specifies settings schema version
     #[cfg_attr(target_os = "android", jnix(skip))]
     settings_version: SettingsVersion,
@@ -99,6 +153,34 @@ pub struct SplitTunnelSettings {
     pub apps: HashSet<PathBuf>,
 }
 
+/// Networks that are trusted enough to not need the tunnel. The tunnel is disconnected when the
+/// device joins one of them, and connected again when it leaves.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct TrustedNetworks {
+    /// SSIDs of trusted Wi-Fi networks.
+    pub ssids: Vec<String>,
+    /// Trusted local subnets. A network is trusted if the subnet of any local interface lies
+    /// within one of these.
+    pub subnets: Vec<IpNetwork>,
+}
+
+impl TrustedNetworks {
+    pub fn is_empty(&self) -> bool {
+        self.ssids.is_empty() && self.subnets.is_empty()
+    }
+
+    /// Returns whether a network with the given Wi-Fi SSIDs and local subnets is trusted.
+    pub fn contains(&self, ssids: &[String], subnets: &[IpNetwork]) -> bool {
+        ssids.iter().any(|ssid| self.ssids.contains(ssid))
+            || subnets.iter().any(|subnet| {
+                self.subnets.iter().any(|trusted| {
+                    trusted.contains(subnet.network()) && subnet.prefix() >= trusted.prefix()
+                })
+            })
+    }
+}
+
 impl Default for Settings {
     fn default() -> Self {
         Settings {
@@ -108,15 +190,31 @@ impl Default for Settings {
                 location: Constraint::Only(LocationConstraint::Country("se".to_owned())),
                 ..Default::default()
             }),
+            relay_fallback_tiers: Vec::new(),
+            prefer_low_latency: false,
             bridge_settings: BridgeSettings::Normal(BridgeConstraints::default()),
             bridge_state: BridgeState::Auto,
             allow_lan: false,
+            lan_allow_list: None,
+            blocked_state_policy: BlockedStatePolicy::default(),
             block_when_disconnected: false,
+            block_when_account_expired: true,
+            strict_kill_switch: false,
             auto_connect: false,
+            reconnect_on_network_change: false,
+            trusted_networks: TrustedNetworks::default(),
             tunnel_options: TunnelOptions::default(),
             show_beta_releases: false,
+            log_privacy: LogPrivacy::default(),
+            entry_proxy: None,
             #[cfg(windows)]
             split_tunnel: SplitTunnelSettings::default(),
+            #[cfg(windows)]
+            allow_connection_sharing: false,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            excluded_networks: Vec::new(),
+            use_system_proxy: false,
+            feature_flags: BTreeMap::new(),
             settings_version: CURRENT_SETTINGS_VERSION,
         }
     }
@@ -222,6 +320,23 @@ pub struct TunnelOptions {
     pub dns_options: DnsOptions,
 }
 
+/// How much identifying information is removed from the logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogPrivacy {
+    /// Logs are written as is. Problem reports are redacted when they are collected.
+    Standard,
+    /// All IP addresses, relay hostnames, account numbers and WireGuard keys are removed before
+    /// log messages are written.
+    Aggressive,
+}
+
+impl Default for LogPrivacy {
+    fn default() -> Self {
+        Self::Standard
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
 #[serde(rename_all = "snake_case")]
 pub enum DnsState {
@@ -297,6 +412,10 @@ pub struct DefaultDnsOptions {
     pub block_ads: bool,
     pub block_trackers: bool,
     pub block_malware: bool,
+    pub block_adult_content: bool,
+    pub block_gambling: bool,
+    /// Reach the resolvers on the relay using DNS-over-TLS. Only supported on Linux.
+    pub dns_over_tls: bool,
 }
 
 /// Custom DNS config
@@ -146,6 +146,15 @@ pub struct AssociatedAddresses {
     pub ipv6_address: ipnetwork::Ipv6Network,
 }
 
+/// A WireGuard key that is registered on an account. Each key uses one of the limited number of
+/// key slots of the account, and usually belongs to one device.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RegisteredKey {
+    pub pubkey: wireguard::PublicKey,
+    #[serde(flatten)]
+    pub addresses: AssociatedAddresses,
+}
+
 /// Event that is emitted when the daemon has finished generating a key.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
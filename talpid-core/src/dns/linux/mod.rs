@@ -36,12 +36,18 @@ pub enum Error {
     /// No suitable DNS monitor implementation detected
     #[error(display = "No suitable DNS monitor implementation detected")]
     NoDnsMonitor,
+
+    /// The DNS monitor in use cannot configure DNS-over-TLS
+    #[error(display = "DNS-over-TLS is not supported when managing DNS via {}", _0)]
+    DnsOverTlsUnsupported(String),
 }
 
 pub struct DnsMonitor {
     route_manager: RouteManagerHandle,
     handle: tokio::runtime::Handle,
     inner: Option<DnsMonitorHolder>,
+    /// Tunnel device that NetworkManager has been told not to manage.
+    unmanaged_device: Option<String>,
 }
 
 impl super::DnsMonitorT for DnsMonitor {
@@ -52,6 +58,7 @@ impl super::DnsMonitorT for DnsMonitor {
             route_manager,
             handle,
             inner: None,
+            unmanaged_device: None,
         })
     }
 
@@ -60,6 +67,9 @@ impl super::DnsMonitorT for DnsMonitor {
         // Creating a new DNS monitor for each set, in case the system changed how it manages DNS.
         let mut inner = DnsMonitorHolder::new()?;
         if !servers.is_empty() {
+            if !matches!(inner, DnsMonitorHolder::NetworkManager(_)) {
+                self.unmanage_device(interface);
+            }
             inner.set(&self.handle, &self.route_manager, interface, servers)?;
             self.inner = Some(inner);
         }
@@ -70,10 +80,46 @@ impl super::DnsMonitorT for DnsMonitor {
         if let Some(mut inner) = self.inner.take() {
             inner.reset(&self.handle)?;
         }
+        if let Some(device) = self.unmanaged_device.take() {
+            network_manager::set_device_managed(&device, true);
+        }
         Ok(())
     }
 }
 
+impl DnsMonitor {
+    pub fn set_dns_over_tls(
+        &mut self,
+        interface: &str,
+        servers: &[(IpAddr, Option<String>)],
+    ) -> Result<()> {
+        use super::DnsMonitorT;
+
+        self.reset()?;
+        let mut inner = DnsMonitorHolder::new()?;
+        if !servers.is_empty() {
+            if !matches!(inner, DnsMonitorHolder::NetworkManager(_)) {
+                self.unmanage_device(interface);
+            }
+            inner.set_dns_over_tls(&self.handle, interface, servers)?;
+            self.inner = Some(inner);
+        }
+        Ok(())
+    }
+
+    /// Stops NetworkManager from pushing its own DNS config for the tunnel device while DNS is
+    /// managed some other way. The tunnel is only created by NetworkManager if it also manages
+    /// DNS, in which case the device must stay managed.
+    fn unmanage_device(&mut self, interface: &str) {
+        if will_use_nm() {
+            return;
+        }
+        if network_manager::set_device_managed(interface, false) {
+            self.unmanaged_device = Some(interface.to_owned());
+        }
+    }
+}
+
 pub enum DnsMonitorHolder {
     SystemdResolved(SystemdResolved),
     NetworkManager(NetworkManager),
@@ -118,7 +164,7 @@ impl DnsMonitorHolder {
                         systemd_resolved::SystemdDbusError::NoSystemdResolved(_),
                     ) => (),
                     other_error => {
-                        log::debug!("NetworkManager is not being used because {}", other_error)
+                        log::debug!("systemd-resolved is not being used because {}", other_error)
                     }
                 }
                 NetworkManager::new().map(DnsMonitorHolder::NetworkManager)
@@ -150,6 +196,21 @@ impl DnsMonitorHolder {
         Ok(())
     }
 
+    fn set_dns_over_tls(
+        &mut self,
+        handle: &tokio::runtime::Handle,
+        interface: &str,
+        servers: &[(IpAddr, Option<String>)],
+    ) -> Result<()> {
+        match self {
+            DnsMonitorHolder::SystemdResolved(ref mut systemd_resolved) => {
+                handle.block_on(systemd_resolved.set_dns_over_tls(interface, servers))?
+            }
+            other => return Err(Error::DnsOverTlsUnsupported(other.to_string())),
+        }
+        Ok(())
+    }
+
     fn reset(&mut self, handle: &tokio::runtime::Handle) -> Result<()> {
         use self::DnsMonitorHolder::*;
         match self {
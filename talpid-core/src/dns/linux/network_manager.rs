@@ -1,9 +1,12 @@
 use std::net::IpAddr;
 pub use talpid_dbus::network_manager::Error;
 use talpid_dbus::network_manager::{self, DeviceConfig, NetworkManager as DBus};
+use talpid_types::ErrorExt;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+const DBUS_SERVICE_UNKNOWN: &str = "org.freedesktop.DBus.Error.ServiceUnknown";
+
 pub struct NetworkManager {
     pub connection: DBus,
     device: Option<String>,
@@ -53,3 +56,33 @@ impl NetworkManager {
         Ok(())
     }
 }
+
+/// Sets whether NetworkManager should manage a device, if NetworkManager is running. Returns
+/// true if the device was managed and now is not, or the other way around.
+pub fn set_device_managed(interface_name: &str, managed: bool) -> bool {
+    let result =
+        DBus::new().and_then(|connection| connection.set_device_managed(interface_name, managed));
+    match result {
+        Ok(was_managed) => {
+            if was_managed != managed {
+                log::debug!(
+                    "Set NetworkManager managed state of {} to {}",
+                    interface_name,
+                    managed
+                );
+            }
+            was_managed != managed
+        }
+        Err(Error::DeviceNotFound) => false,
+        Err(Error::Dbus(error)) if error.name() == Some(DBUS_SERVICE_UNKNOWN) => false,
+        Err(error) => {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg(
+                    "Failed to change whether NetworkManager manages the tunnel device"
+                )
+            );
+            false
+        }
+    }
+}
@@ -21,7 +21,8 @@ pub enum Error {
 
 pub struct SystemdResolved {
     pub dbus_interface: AsyncHandle,
-    tunnel_index: u32,
+    /// The link that DNS has been configured on, if any.
+    tunnel_index: Option<u32>,
 }
 
 impl SystemdResolved {
@@ -30,7 +31,7 @@ impl SystemdResolved {
 
         let systemd_resolved = SystemdResolved {
             dbus_interface,
-            tunnel_index: 0,
+            tunnel_index: None,
         };
 
         Ok(systemd_resolved)
@@ -43,42 +44,67 @@ impl SystemdResolved {
         servers: &[IpAddr],
     ) -> Result<()> {
         let tunnel_index = iface_index(interface_name)?;
-        self.tunnel_index = tunnel_index;
+        self.tunnel_index = Some(tunnel_index);
 
-        if let Err(error) = self.dbus_interface.disable_dot(self.tunnel_index).await {
+        if let Err(error) = self.dbus_interface.disable_dot(tunnel_index).await {
             log::error!("Failed to disable DoT: {}", error.display_chain());
         }
 
-        if let Err(error) = self
+        self.configure_link(tunnel_index).await;
+
+        let _ = self
             .dbus_interface
-            .set_domains(tunnel_index, &[(".", true)])
-            .await
-        {
-            log::error!("Failed to set search domains: {}", error.display_chain());
-        }
+            .set_dns(tunnel_index, servers.to_vec())
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_dns_over_tls(
+        &mut self,
+        interface_name: &str,
+        servers: &[(IpAddr, Option<String>)],
+    ) -> Result<()> {
+        let tunnel_index = iface_index(interface_name)?;
+        self.tunnel_index = Some(tunnel_index);
+
+        self.configure_link(tunnel_index).await;
 
         let _ = self
             .dbus_interface
-            .set_dns(self.tunnel_index, servers.to_vec())
+            .set_dns_over_tls(tunnel_index, servers.to_vec())
             .await?;
 
         Ok(())
     }
 
-    pub async fn reset(&mut self) -> Result<()> {
+    /// Routes all DNS queries to the tunnel link. Failures are logged, since the servers on the
+    /// link are still used for most queries.
+    async fn configure_link(&self, tunnel_index: u32) {
         if let Err(error) = self
             .dbus_interface
-            .set_domains(self.tunnel_index, &[])
+            .set_domains(tunnel_index, &[(".", true)])
             .await
         {
             log::error!("Failed to set search domains: {}", error.display_chain());
         }
 
-        let _ = self
+        if let Err(error) = self
             .dbus_interface
-            .set_dns(self.tunnel_index, vec![])
-            .await?;
+            .set_exclusive_link_options(tunnel_index)
+            .await
+        {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to configure the tunnel link")
+            );
+        }
+    }
 
+    pub async fn reset(&mut self) -> Result<()> {
+        if let Some(tunnel_index) = self.tunnel_index.take() {
+            self.dbus_interface.revert(tunnel_index).await?;
+        }
         Ok(())
     }
 }
@@ -1,3 +1,4 @@
+use futures::channel::mpsc;
 use parking_lot::Mutex;
 use std::{
     collections::HashMap,
@@ -5,6 +6,7 @@ use std::{
     net::{AddrParseError, IpAddr},
     sync::{mpsc as sync_mpsc, Arc},
     thread,
+    time::{Duration, Instant},
 };
 use system_configuration::{
     core_foundation::{
@@ -52,6 +54,12 @@ pub enum Error {
 const STATE_PATH_PATTERN: &str = "State:/Network/Service/.*/DNS";
 const SETUP_PATH_PATTERN: &str = "Setup:/Network/Service/.*/DNS";
 
+/// Period during which DNS changes made by other processes are counted.
+const INTERFERENCE_WINDOW: Duration = Duration::from_secs(30);
+/// Number of overwritten DNS entries within `INTERFERENCE_WINDOW` that is considered to be
+/// repeated interference rather than a one-off change, such as a DHCP lease renewal.
+const INTERFERENCE_THRESHOLD: u32 = 5;
+
 type ServicePath = String;
 type DnsServer = String;
 
@@ -60,6 +68,51 @@ struct State {
     dns_settings: DnsSettings,
     /// The backup of all DNS settings. These are being applied back on reset.
     backup: HashMap<ServicePath, Option<DnsSettings>>,
+    /// Keeps track of how often other processes overwrite the DNS settings.
+    interference: InterferenceTracker,
+}
+
+/// Counts changes to the DNS settings that had to be reverted, so that another process fighting
+/// over the DNS config can be detected.
+struct InterferenceTracker {
+    window_start: Instant,
+    count: u32,
+    reported: bool,
+    /// Signaled once per window in which the threshold is reached.
+    interference_tx: mpsc::UnboundedSender<()>,
+}
+
+impl InterferenceTracker {
+    fn new(interference_tx: mpsc::UnboundedSender<()>) -> Self {
+        Self {
+            window_start: Instant::now(),
+            count: 0,
+            reported: false,
+            interference_tx,
+        }
+    }
+
+    /// Registers a change to the DNS settings at `path` that was reverted at `now`.
+    fn register(&mut self, path: &str, now: Instant) {
+        if now.saturating_duration_since(self.window_start) > INTERFERENCE_WINDOW {
+            self.window_start = now;
+            self.count = 0;
+            self.reported = false;
+        }
+        self.count += 1;
+
+        if self.count >= INTERFERENCE_THRESHOLD && !self.reported {
+            self.reported = true;
+            log::warn!(
+                "DNS settings were overwritten {} times in less than {} seconds, most recently \
+                 for {}. Another application may be managing DNS",
+                self.count,
+                INTERFERENCE_WINDOW.as_secs(),
+                path,
+            );
+            let _ = self.interference_tx.unbounded_send(());
+        }
+    }
 }
 
 /// Holds the configuration for one service.
@@ -202,6 +255,8 @@ pub struct DnsMonitor {
     /// When it's `Some(state)` we are actively making sure `state.dns_settings` is configured
     /// on all network interfaces.
     state: Arc<Mutex<Option<State>>>,
+
+    interference_tx: mpsc::UnboundedSender<()>,
 }
 
 /// SAFETY: The `SCDynamicStore` can be sent to other threads since it doesn't share mutable state
@@ -215,12 +270,13 @@ impl super::DnsMonitorT for DnsMonitor {
     /// DNS settings for all network interfaces. If any changes occur it will instantly reset
     /// the DNS settings for that interface back to the last server list set to this instance
     /// with `set_dns`.
-    fn new() -> Result<Self> {
+    fn new(interference_tx: mpsc::UnboundedSender<()>) -> Result<Self> {
         let state = Arc::new(Mutex::new(None));
         Self::spawn(state.clone())?;
         Ok(DnsMonitor {
             store: SCDynamicStoreBuilder::new("mullvad-dns").build(),
             state,
+            interference_tx,
         })
     }
 
@@ -238,6 +294,7 @@ impl super::DnsMonitorT for DnsMonitor {
                 State {
                     dns_settings: settings,
                     backup,
+                    interference: InterferenceTracker::new(self.interference_tx.clone()),
                 }
             }
             Some(state) => {
@@ -248,6 +305,7 @@ impl super::DnsMonitorT for DnsMonitor {
                     State {
                         dns_settings: settings,
                         backup: state.backup,
+                        interference: state.interference,
                     }
                 } else {
                     log::debug!("No change, new DNS same as the one already set");
@@ -398,6 +456,9 @@ fn dns_change_callback_internal(
             }
         };
         if should_set_dns {
+            state
+                .interference
+                .register(&path.to_string(), Instant::now());
             if let Err(e) = state.dns_settings.save(&store, path.clone()) {
                 log::error!("Failed changing DNS for {}: {}", *path, e);
             }
@@ -458,3 +519,36 @@ fn state_to_setup_path(state_path: &str) -> Option<String> {
         None
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_interference_threshold() {
+        let (interference_tx, mut interference_rx) = mpsc::unbounded();
+        let mut tracker = InterferenceTracker::new(interference_tx);
+        let start = tracker.window_start;
+        let path = "State:/Network/Service/test/DNS";
+
+        for _ in 1..INTERFERENCE_THRESHOLD {
+            tracker.register(path, start);
+        }
+        assert!(interference_rx.try_next().is_err());
+
+        // Reaching the threshold is reported once per window
+        tracker.register(path, start);
+        assert_eq!(interference_rx.try_next().unwrap(), Some(()));
+        tracker.register(path, start);
+        assert!(interference_rx.try_next().is_err());
+
+        // Changes in a new window are counted from zero
+        let next_window = start + INTERFERENCE_WINDOW + Duration::from_secs(1);
+        for _ in 1..INTERFERENCE_THRESHOLD {
+            tracker.register(path, next_window);
+        }
+        assert!(interference_rx.try_next().is_err());
+        tracker.register(path, next_window);
+        assert_eq!(interference_rx.try_next().unwrap(), Some(()));
+    }
+}
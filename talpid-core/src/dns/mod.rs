@@ -1,5 +1,7 @@
 #[cfg(target_os = "linux")]
 use crate::routing::RouteManagerHandle;
+#[cfg(target_os = "macos")]
+use futures::channel::mpsc;
 use std::net::IpAddr;
 
 #[cfg(target_os = "macos")]
@@ -29,10 +31,12 @@ pub struct DnsMonitor {
 }
 
 impl DnsMonitor {
-    /// Returns a new `DnsMonitor` that can set and monitor the system DNS.
+    /// Returns a new `DnsMonitor` that can set and monitor the system DNS. On macOS,
+    /// `interference_tx` is signaled when another program keeps overwriting the DNS settings.
     pub fn new(
         #[cfg(target_os = "linux")] handle: tokio::runtime::Handle,
         #[cfg(target_os = "linux")] route_manager: RouteManagerHandle,
+        #[cfg(target_os = "macos")] interference_tx: mpsc::UnboundedSender<()>,
     ) -> Result<Self, Error> {
         Ok(DnsMonitor {
             inner: imp::DnsMonitor::new(
@@ -40,6 +44,8 @@ impl DnsMonitor {
                 handle,
                 #[cfg(target_os = "linux")]
                 route_manager,
+                #[cfg(target_os = "macos")]
+                interference_tx,
             )?,
         })
     }
@@ -64,6 +70,28 @@ impl DnsMonitor {
         self.inner.set(interface, servers)
     }
 
+    /// Set DNS to the given servers, requiring that they are reached using DNS-over-TLS. The
+    /// certificate presented by a server is verified against its server name, if one is given.
+    #[cfg(target_os = "linux")]
+    pub fn set_dns_over_tls(
+        &mut self,
+        interface: &str,
+        servers: &[(IpAddr, Option<String>)],
+    ) -> Result<(), Error> {
+        log::info!(
+            "Setting DNS-over-TLS servers to {}",
+            servers
+                .iter()
+                .map(|(ip, server_name)| match server_name {
+                    Some(server_name) => format!("{} ({})", ip, server_name),
+                    None => ip.to_string(),
+                })
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        self.inner.set_dns_over_tls(interface, servers)
+    }
+
     /// Reset system DNS settings to what it was before being set by this instance.
     /// This succeeds if the interface does not exist.
     pub fn reset(&mut self) -> Result<(), Error> {
@@ -81,7 +109,10 @@ trait DnsMonitorT: Sized {
         route_manager: RouteManagerHandle,
     ) -> Result<Self, Self::Error>;
 
-    #[cfg(not(target_os = "linux"))]
+    #[cfg(target_os = "macos")]
+    fn new(interference_tx: mpsc::UnboundedSender<()>) -> Result<Self, Self::Error>;
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
     fn new() -> Result<Self, Self::Error>;
 
     fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Self::Error>;
@@ -1,13 +1,13 @@
 use crate::{
     logging::windows::{log_sink, LogSink},
-    windows::luid_from_alias,
+    windows::{guid_from_luid, string_from_guid},
 };
 
 use lazy_static::lazy_static;
-use std::{env, io, net::IpAddr, path::Path};
+use std::{collections::HashMap, env, io, net::IpAddr, path::Path};
 use talpid_types::ErrorExt;
+use talpid_windows_net::{alias_from_luid, luid_from_alias, Luid};
 use widestring::WideCString;
-use winapi::shared::ifdef::NET_LUID;
 use winreg::{
     enums::{HKEY_LOCAL_MACHINE, REG_MULTI_SZ},
     transaction::Transaction,
@@ -16,6 +16,15 @@ use winreg::{
 
 const DNS_CACHE_POLICY_GUID: &str = "{d57d2750-f971-408e-8e55-cfddb37e60ae}";
 
+/// Keeps the original DNS configuration of every interface whose DNS servers have been
+/// overridden, so that it can be restored after a crash.
+const DNS_RECOVERY_KEY: &str = r#"SOFTWARE\Mullvad VPN\DnsRecovery"#;
+
+const TCPIP_INTERFACES_KEY: &str =
+    r#"SYSTEM\CurrentControlSet\Services\Tcpip\Parameters\Interfaces"#;
+const TCPIP6_INTERFACES_KEY: &str =
+    r#"SYSTEM\CurrentControlSet\Services\Tcpip6\Parameters\Interfaces"#;
+
 lazy_static! {
     /// Specifies whether to override per-interface DNS resolvers with a global DNS policy.
     static ref GLOBAL_DNS_CACHE_POLICY: bool = env::var("TALPID_DNS_CACHE_POLICY")
@@ -46,9 +55,28 @@ pub enum Error {
     /// Failure to set new DNS servers.
     #[error(display = "Failed to update dnscache policy config")]
     UpdateDnsCachePolicy(#[error(source)] io::Error),
+
+    /// Failure to read the DNS servers that are configured on an interface.
+    #[error(display = "Failed to read the DNS configuration of the interface")]
+    ReadDnsConfig(#[error(source)] io::Error),
+
+    /// Failure to restore the original DNS configuration of one or more interfaces.
+    #[error(display = "Failed to restore the DNS configuration of an interface")]
+    RestoreDnsConfig,
 }
 
-pub struct DnsMonitor {}
+/// DNS servers that were statically configured on an interface before they were overridden.
+/// An empty list means that the servers were assigned by DHCP or router advertisements.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct OriginalDnsConfig {
+    ipv4: Vec<IpAddr>,
+    ipv6: Vec<IpAddr>,
+}
+
+pub struct DnsMonitor {
+    /// Interfaces whose DNS servers have been overridden, and their original configuration.
+    overridden: HashMap<Luid, OriginalDnsConfig>,
+}
 
 impl super::DnsMonitorT for DnsMonitor {
     type Error = Error;
@@ -56,48 +84,50 @@ impl super::DnsMonitorT for DnsMonitor {
     fn new() -> Result<Self, Error> {
         unsafe { WinDns_Initialize(Some(log_sink), b"WinDns\0".as_ptr()).into_result()? };
 
-        let mut monitor = DnsMonitor {};
-        monitor.reset()?;
+        // Repair interfaces that were left pointing at a tunnel resolver if the previous
+        // instance was not shut down cleanly
+        let mut monitor = DnsMonitor {
+            overridden: load_recovery_records(),
+        };
+        match monitor.reset() {
+            Err(Error::RestoreDnsConfig) => {
+                log::error!("Failed to restore the DNS config of some interfaces at startup")
+            }
+            result => result?,
+        }
 
         Ok(monitor)
     }
 
     fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Error> {
-        let ipv4 = servers
-            .iter()
-            .filter(|ip| ip.is_ipv4())
-            .map(ip_to_widestring)
-            .collect::<Vec<_>>();
-        let ipv6 = servers
-            .iter()
-            .filter(|ip| ip.is_ipv6())
-            .map(ip_to_widestring)
-            .collect::<Vec<_>>();
-
-        let mut ipv4_address_ptrs = ipv4
-            .iter()
-            .map(|ip_cstr| ip_cstr.as_ptr())
-            .collect::<Vec<_>>();
-        let mut ipv6_address_ptrs = ipv6
-            .iter()
-            .map(|ip_cstr| ip_cstr.as_ptr())
-            .collect::<Vec<_>>();
-
-        log::trace!("ipv4 ips: {:?} ({})", ipv4, ipv4.len());
-        log::trace!("ipv6 ips: {:?} ({})", ipv6, ipv6.len());
-
         let luid = luid_from_alias(interface).map_err(Error::InterfaceLuidError)?;
 
-        unsafe {
-            WinDns_Set(
-                &luid,
-                ipv4_address_ptrs.as_mut_ptr(),
-                ipv4_address_ptrs.len() as u32,
-                ipv6_address_ptrs.as_mut_ptr(),
-                ipv6_address_ptrs.len() as u32,
-            )
-            .into_result()
-        }?;
+        // Only one interface is overridden at a time
+        let previous_interfaces: Vec<Luid> = self
+            .overridden
+            .keys()
+            .filter(|overridden_luid| **overridden_luid != luid)
+            .cloned()
+            .collect();
+        for previous_luid in previous_interfaces {
+            if let Err(error) = self.restore_interface(previous_luid) {
+                log::error!("{}", error.display_chain());
+            }
+        }
+
+        if !self.overridden.contains_key(&luid) {
+            let original = OriginalDnsConfig::read(luid)?;
+            log::debug!("Original DNS config of {}: {:?}", interface, original);
+            if let Err(error) = save_recovery_record(luid, &original) {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to save the original DNS config")
+                );
+            }
+            self.overridden.insert(luid, original);
+        }
+
+        set_interface_dns(luid, servers)?;
 
         if *GLOBAL_DNS_CACHE_POLICY {
             if let Err(error) = set_dns_cache_policy(servers) {
@@ -110,12 +140,192 @@ impl super::DnsMonitorT for DnsMonitor {
     }
 
     fn reset(&mut self) -> Result<(), Error> {
+        let mut result = Ok(());
+        let overridden: Vec<Luid> = self.overridden.keys().cloned().collect();
+        for luid in overridden {
+            if let Err(error) = self.restore_interface(luid) {
+                log::error!("{}", error.display_chain());
+                result = Err(Error::RestoreDnsConfig);
+            }
+        }
+
         if *GLOBAL_DNS_CACHE_POLICY {
-            reset_dns_cache_policy()
-        } else {
-            Ok(())
+            reset_dns_cache_policy()?;
+        }
+        result
+    }
+}
+
+impl DnsMonitor {
+    /// Restores the DNS configuration that an interface had before it was overridden. Static
+    /// servers are set again, and interfaces that got their servers from DHCP go back to using
+    /// DHCP. Nothing needs to be done if the interface no longer exists.
+    fn restore_interface(&mut self, luid: Luid) -> Result<(), Error> {
+        let original = match self.overridden.get(&luid) {
+            Some(original) => original.clone(),
+            None => return Ok(()),
+        };
+
+        if alias_from_luid(luid).is_ok() {
+            log::debug!("Restoring DNS config of interface {:?}", luid);
+            let servers: Vec<IpAddr> = original
+                .ipv4
+                .iter()
+                .chain(original.ipv6.iter())
+                .cloned()
+                .collect();
+            set_interface_dns(luid, &servers)?;
+        }
+
+        self.overridden.remove(&luid);
+        if let Err(error) = delete_recovery_record(luid) {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to remove the original DNS config")
+            );
+        }
+        Ok(())
+    }
+}
+
+impl OriginalDnsConfig {
+    /// Reads the statically configured DNS servers of an interface from the registry.
+    fn read(luid: Luid) -> Result<Self, Error> {
+        let guid = guid_from_luid(luid).map_err(Error::ReadDnsConfig)?;
+        let guid = string_from_guid(&guid);
+        Ok(OriginalDnsConfig {
+            ipv4: read_static_name_servers(TCPIP_INTERFACES_KEY, &guid)
+                .map_err(Error::ReadDnsConfig)?,
+            ipv6: read_static_name_servers(TCPIP6_INTERFACES_KEY, &guid)
+                .map_err(Error::ReadDnsConfig)?,
+        })
+    }
+}
+
+/// Returns the servers in the `NameServer` value of an interface. The value is empty when the
+/// servers are assigned by DHCP.
+fn read_static_name_servers(interfaces_key: &str, guid: &str) -> io::Result<Vec<IpAddr>> {
+    let interface_key = match RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey(Path::new(interfaces_key).join(guid))
+    {
+        Ok(key) => key,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(error),
+    };
+    match interface_key.get_value::<String, _>("NameServer") {
+        Ok(servers) => Ok(parse_name_servers(&servers)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(error) => Err(error),
+    }
+}
+
+/// Parses a list of servers as stored in the registry. They may be separated by commas or spaces.
+fn parse_name_servers(servers: &str) -> Vec<IpAddr> {
+    servers
+        .split(|c| c == ',' || c == ' ')
+        .filter(|server| !server.is_empty())
+        .filter_map(|server| server.parse().ok())
+        .collect()
+}
+
+fn join_name_servers(servers: &[IpAddr]) -> String {
+    servers
+        .iter()
+        .map(|server| server.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn save_recovery_record(luid: Luid, config: &OriginalDnsConfig) -> io::Result<()> {
+    let (record, _) = RegKey::predef(HKEY_LOCAL_MACHINE)
+        .create_subkey(Path::new(DNS_RECOVERY_KEY).join(format!("{:016x}", luid.as_raw())))?;
+    record.set_value("Ipv4NameServer", &join_name_servers(&config.ipv4))?;
+    record.set_value("Ipv6NameServer", &join_name_servers(&config.ipv6))
+}
+
+fn delete_recovery_record(luid: Luid) -> io::Result<()> {
+    let recovery_key = match RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey(DNS_RECOVERY_KEY) {
+        Ok(key) => key,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error),
+    };
+    match recovery_key.delete_subkey_all(format!("{:016x}", luid.as_raw())) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the original DNS configurations that were saved by a previous instance.
+fn load_recovery_records() -> HashMap<Luid, OriginalDnsConfig> {
+    let mut records = HashMap::new();
+    let recovery_key = match RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey(DNS_RECOVERY_KEY) {
+        Ok(key) => key,
+        Err(_) => return records,
+    };
+    for name in recovery_key.enum_keys().filter_map(Result::ok) {
+        let luid = match u64::from_str_radix(&name, 16) {
+            Ok(value) => Luid::from_raw(value),
+            Err(_) => continue,
+        };
+        if let Ok(record) = recovery_key.open_subkey(&name) {
+            let read_servers = |value_name| {
+                record
+                    .get_value::<String, _>(value_name)
+                    .map(|servers| parse_name_servers(&servers))
+                    .unwrap_or_default()
+            };
+            log::info!(
+                "Found DNS config that was not restored for interface {:?}",
+                luid
+            );
+            records.insert(
+                luid,
+                OriginalDnsConfig {
+                    ipv4: read_servers("Ipv4NameServer"),
+                    ipv6: read_servers("Ipv6NameServer"),
+                },
+            );
         }
     }
+    records
+}
+
+/// Sets static DNS servers on an interface. Address families without any servers are set to use
+/// DHCP.
+fn set_interface_dns(luid: Luid, servers: &[IpAddr]) -> Result<(), Error> {
+    let ipv4 = servers
+        .iter()
+        .filter(|ip| ip.is_ipv4())
+        .map(ip_to_widestring)
+        .collect::<Vec<_>>();
+    let ipv6 = servers
+        .iter()
+        .filter(|ip| ip.is_ipv6())
+        .map(ip_to_widestring)
+        .collect::<Vec<_>>();
+
+    let mut ipv4_address_ptrs = ipv4
+        .iter()
+        .map(|ip_cstr| ip_cstr.as_ptr())
+        .collect::<Vec<_>>();
+    let mut ipv6_address_ptrs = ipv6
+        .iter()
+        .map(|ip_cstr| ip_cstr.as_ptr())
+        .collect::<Vec<_>>();
+
+    log::trace!("ipv4 ips: {:?} ({})", ipv4, ipv4.len());
+    log::trace!("ipv6 ips: {:?} ({})", ipv6, ipv6.len());
+
+    unsafe {
+        WinDns_Set(
+            &luid,
+            ipv4_address_ptrs.as_mut_ptr(),
+            ipv4_address_ptrs.len() as u32,
+            ipv6_address_ptrs.as_mut_ptr(),
+            ipv6_address_ptrs.len() as u32,
+        )
+        .into_result()
+    }
 }
 
 fn ip_to_widestring(ip: &IpAddr) -> WideCString {
@@ -229,10 +439,28 @@ extern "stdcall" {
     // Configure which DNS servers should be used and start enforcing these settings.
     #[link_name = "WinDns_Set"]
     pub fn WinDns_Set(
-        interface_luid: *const NET_LUID,
+        interface_luid: *const Luid,
         v4_ips: *mut *const u16,
         v4_n_ips: u32,
         v6_ips: *mut *const u16,
         v6_n_ips: u32,
     ) -> SettingResult;
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_name_servers() {
+        assert_eq!(parse_name_servers(""), Vec::<IpAddr>::new());
+        assert_eq!(
+            parse_name_servers("192.0.2.1,192.0.2.2 invalid 2001:db8::1"),
+            vec![
+                "192.0.2.1".parse::<IpAddr>().unwrap(),
+                "192.0.2.2".parse().unwrap(),
+                "2001:db8::1".parse().unwrap(),
+            ]
+        );
+    }
+}
@@ -22,4 +22,17 @@ impl FirewallT for Firewall {
     fn reset_policy(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
+
+    fn policy_verifier(&mut self, _policy: &FirewallPolicy) -> Result<PolicyVerifier, Self::Error> {
+        Ok(PolicyVerifier)
+    }
+}
+
+/// The Android stub implementation for verifying the firewall policy.
+pub struct PolicyVerifier;
+
+impl PolicyVerifier {
+    pub fn verify(&self) -> Result<bool, Error> {
+        Ok(true)
+    }
 }
@@ -1,4 +1,11 @@
-use super::{FirewallArguments, FirewallPolicy, FirewallT};
+//! Firewall implementation for Linux, built directly on nftables through netlink.
+//!
+//! All rules live in tables owned by this module. Every policy is applied as a single netlink
+//! batch that deletes and recreates those tables, and the kernel commits a batch as one
+//! transaction. A policy is therefore either fully applied or, if any message in the batch is
+//! rejected, not applied at all, leaving the previous rule set in place.
+
+use super::{FirewallArguments, FirewallPolicy, FirewallT, InitialFirewallState};
 use crate::{split_tunnel, tunnel};
 use ipnetwork::IpNetwork;
 use lazy_static::lazy_static;
@@ -9,6 +16,7 @@ use nftnl::{
     nft_expr, table, Batch, Chain, FinalizedBatch, ProtoFamily, Rule, Table,
 };
 use std::{
+    collections::BTreeMap,
     env,
     ffi::{CStr, CString},
     io,
@@ -104,19 +112,36 @@ struct FirewallTables {
     mangle_v6: Table,
 }
 
+impl FirewallTables {
+    fn new() -> Self {
+        FirewallTables {
+            main: Table::new(&*TABLE_NAME, ProtoFamily::Inet),
+            mangle_v4: Table::new(&*MANGLE_TABLE_NAME_V4, ProtoFamily::Ipv4),
+            mangle_v6: Table::new(&*MANGLE_TABLE_NAME_V6, ProtoFamily::Ipv6),
+        }
+    }
+}
+
 impl FirewallT for Firewall {
     type Error = Error;
 
-    fn new(_args: FirewallArguments) -> Result<Self> {
-        Ok(Firewall(()))
+    fn new(args: FirewallArguments) -> Result<Self> {
+        let mut firewall = Firewall(());
+        // Atomically replace any rules that are already in place, such as those applied during
+        // early boot, so that nothing leaks while the daemon is starting
+        if let InitialFirewallState::Blocked(allowed_endpoint) = args.initial_state {
+            firewall.apply_policy(FirewallPolicy::Blocked {
+                allow_lan: args.allow_lan,
+                lan_allow_list: args.lan_allow_list,
+                allowed_endpoint: Some(allowed_endpoint),
+                blocked_state_policy: args.blocked_state_policy,
+            })?;
+        }
+        Ok(firewall)
     }
 
     fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
-        let tables = FirewallTables {
-            main: Table::new(&*TABLE_NAME, ProtoFamily::Inet),
-            mangle_v4: Table::new(&*MANGLE_TABLE_NAME_V4, ProtoFamily::Ipv4),
-            mangle_v6: Table::new(&*MANGLE_TABLE_NAME_V6, ProtoFamily::Ipv6),
-        };
+        let tables = FirewallTables::new();
         let batch = PolicyBatch::new(&tables).finalize(&policy)?;
         self.send_and_process(&batch)?;
         Self::apply_kernel_config(&policy);
@@ -142,6 +167,28 @@ impl FirewallT for Firewall {
         self.send_and_process(&batch)?;
         Ok(())
     }
+
+    fn policy_verifier(&mut self, policy: &FirewallPolicy) -> Result<PolicyVerifier> {
+        let tables = FirewallTables::new();
+        let batch = PolicyBatch::new(&tables).finalize(policy)?;
+        Ok(PolicyVerifier {
+            expected: Ruleset::from_batch(&batch),
+        })
+    }
+}
+
+/// Compares the chains and rules loaded into the tables owned by this module against the ones
+/// generated for a policy. Only holds the generated rules, so it can be used from any thread.
+pub struct PolicyVerifier {
+    expected: Ruleset,
+}
+
+impl PolicyVerifier {
+    /// Dumps the loaded chains and rules from netfilter and returns whether they match.
+    pub fn verify(&self) -> Result<bool> {
+        let loaded = Ruleset::load()?;
+        Ok(self.expected.matches(&loaded))
+    }
 }
 
 impl Firewall {
@@ -226,6 +273,337 @@ impl Firewall {
     }
 }
 
+/// Attributes of netfilter messages. Not exported by `libc`.
+const NFTA_CHAIN_TABLE: u16 = 1;
+const NFTA_CHAIN_NAME: u16 = 3;
+const NFTA_CHAIN_HOOK: u16 = 4;
+const NFTA_CHAIN_POLICY: u16 = 5;
+const NFTA_CHAIN_TYPE: u16 = 7;
+const NFTA_RULE_TABLE: u16 = 1;
+const NFTA_RULE_CHAIN: u16 = 2;
+const NFTA_RULE_EXPRESSIONS: u16 = 4;
+const NFTA_LIST_ELEM: u16 = 1;
+const NFTA_EXPR_NAME: u16 = 1;
+const NFTA_EXPR_DATA: u16 = 2;
+
+/// Size of `nlmsghdr` and `nfgenmsg`.
+const NLMSG_HEADER_LEN: usize = 16;
+const NFGENMSG_LEN: usize = 4;
+
+/// Identifies a chain by the family and name of its table, and its own name.
+type ChainId = (u8, Vec<u8>, Vec<u8>);
+/// Attribute types and their payloads, with the flags of nested attributes cleared.
+type Attributes = BTreeMap<u16, Vec<u8>>;
+
+/// The chains and rules of the tables owned by this module, either as generated for a policy or
+/// as dumped from netfilter.
+#[derive(Debug, Default)]
+struct Ruleset {
+    chains: BTreeMap<ChainId, Attributes>,
+    rules: BTreeMap<ChainId, Vec<Vec<Expression>>>,
+}
+
+#[derive(Debug)]
+struct Expression {
+    name: Vec<u8>,
+    data: Attributes,
+}
+
+impl Ruleset {
+    /// Collects the chains and rules added by `batch`.
+    fn from_batch(batch: &FinalizedBatch) -> Self {
+        let mut ruleset = Ruleset::default();
+        for buffer in batch {
+            for (message_type, family, attributes) in parse_messages(buffer) {
+                ruleset.add_message(message_type, family, attributes);
+            }
+        }
+        ruleset
+    }
+
+    /// Dumps the chains and rules that are currently loaded.
+    fn load() -> Result<Self> {
+        let mut ruleset = Ruleset::default();
+        for message_type in &[libc::NFT_MSG_GETCHAIN, libc::NFT_MSG_GETRULE] {
+            for (message_type, family, attributes) in dump(*message_type as u16)? {
+                let attributes = attributes
+                    .iter()
+                    .map(|(t, payload)| (*t, payload.as_slice()))
+                    .collect();
+                ruleset.add_message(message_type, family, attributes);
+            }
+        }
+        Ok(ruleset)
+    }
+
+    fn add_message(&mut self, message_type: u16, family: u8, attributes: Vec<(u16, &[u8])>) {
+        let attribute = |attribute_type| {
+            attributes
+                .iter()
+                .find(|(t, _)| *t == attribute_type)
+                .map(|(_, payload)| *payload)
+        };
+        match i32::from(message_type & 0xff) {
+            libc::NFT_MSG_NEWCHAIN => {
+                let id = match (attribute(NFTA_CHAIN_TABLE), attribute(NFTA_CHAIN_NAME)) {
+                    (Some(table), Some(name)) => (family, table.to_vec(), name.to_vec()),
+                    _ => return,
+                };
+                if !is_owned_table(family, &id.1) {
+                    return;
+                }
+                let chain = [NFTA_CHAIN_HOOK, NFTA_CHAIN_POLICY, NFTA_CHAIN_TYPE]
+                    .iter()
+                    .filter_map(|t| attribute(*t).map(|payload| (*t, normalize(payload))))
+                    .collect();
+                self.chains.insert(id, chain);
+            }
+            libc::NFT_MSG_NEWRULE => {
+                let id = match (attribute(NFTA_RULE_TABLE), attribute(NFTA_RULE_CHAIN)) {
+                    (Some(table), Some(chain)) => (family, table.to_vec(), chain.to_vec()),
+                    _ => return,
+                };
+                if !is_owned_table(family, &id.1) {
+                    return;
+                }
+                let expressions = attribute(NFTA_RULE_EXPRESSIONS)
+                    .and_then(parse_attributes)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|(t, _)| *t == NFTA_LIST_ELEM)
+                    .filter_map(|(_, element)| {
+                        let element = parse_attributes(element)?;
+                        let field = |field_type| {
+                            element
+                                .iter()
+                                .find(|(t, _)| *t == field_type)
+                                .map(|(_, payload)| *payload)
+                        };
+                        Some(Expression {
+                            name: field(NFTA_EXPR_NAME)?.to_vec(),
+                            data: field(NFTA_EXPR_DATA)
+                                .and_then(parse_attributes)
+                                .unwrap_or_default()
+                                .into_iter()
+                                .map(|(t, payload)| (t, normalize(payload)))
+                                .collect(),
+                        })
+                    })
+                    .collect();
+                self.rules.entry(id).or_default().push(expressions);
+            }
+            _ => (),
+        }
+    }
+
+    /// Returns whether `loaded` has exactly the chains and rules of this ruleset, in the same
+    /// order. The kernel adds attributes of its own when dumping, such as counter values and
+    /// defaults for optional attributes, so only the attributes of this ruleset are compared.
+    fn matches(&self, loaded: &Ruleset) -> bool {
+        if self.chains.len() != loaded.chains.len() {
+            log::warn!(
+                "Expected {} netfilter chains, found {}",
+                self.chains.len(),
+                loaded.chains.len()
+            );
+            return false;
+        }
+        for (id, expected) in &self.chains {
+            match loaded.chains.get(id) {
+                Some(chain) if is_subset(expected, chain) => (),
+                Some(_) => {
+                    log::warn!("Netfilter chain {} has been modified", describe_chain(id));
+                    return false;
+                }
+                None => {
+                    log::warn!("Netfilter chain {} is missing", describe_chain(id));
+                    return false;
+                }
+            }
+        }
+
+        let no_rules = vec![];
+        for id in self.chains.keys() {
+            let expected = self.rules.get(id).unwrap_or(&no_rules);
+            let rules = loaded.rules.get(id).unwrap_or(&no_rules);
+            let rules_match = expected.len() == rules.len()
+                && expected.iter().zip(rules).all(|(expected, rule)| {
+                    expected.len() == rule.len()
+                        && expected.iter().zip(rule).all(|(expected, expression)| {
+                            expected.name == expression.name
+                                && (expected.name.starts_with(b"counter\0")
+                                    || is_subset(&expected.data, &expression.data))
+                        })
+                });
+            if !rules_match {
+                log::warn!(
+                    "The rules in netfilter chain {} have been modified",
+                    describe_chain(id)
+                );
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn is_owned_table(family: u8, table: &[u8]) -> bool {
+    let owned_tables = [
+        (libc::NFPROTO_INET, &*TABLE_NAME),
+        (libc::NFPROTO_IPV4, &*MANGLE_TABLE_NAME_V4),
+        (libc::NFPROTO_IPV6, &*MANGLE_TABLE_NAME_V6),
+    ];
+    owned_tables.iter().any(|(owned_family, owned_table)| {
+        *owned_family as u8 == family && owned_table.as_bytes_with_nul() == table
+    })
+}
+
+fn is_subset(expected: &Attributes, loaded: &Attributes) -> bool {
+    expected
+        .iter()
+        .all(|(attribute_type, payload)| loaded.get(attribute_type) == Some(payload))
+}
+
+fn describe_chain((_, table, chain): &ChainId) -> String {
+    let name = |bytes: &[u8]| {
+        String::from_utf8_lossy(bytes)
+            .trim_end_matches('\0')
+            .to_owned()
+    };
+    format!("{}/{}", name(table), name(chain))
+}
+
+/// Sends a dump request for all objects of the given type and returns the replies.
+fn dump(message_type: u16) -> Result<Vec<(u16, u8, Vec<(u16, Vec<u8>)>)>> {
+    let socket = mnl::Socket::new(mnl::Bus::Netfilter).map_err(Error::NetlinkOpenError)?;
+    let seq = 0u32;
+
+    let mut request = Vec::with_capacity(NLMSG_HEADER_LEN + NFGENMSG_LEN);
+    request.extend_from_slice(&((NLMSG_HEADER_LEN + NFGENMSG_LEN) as u32).to_ne_bytes());
+    request.extend_from_slice(
+        &(((libc::NFNL_SUBSYS_NFTABLES as u16) << 8) | message_type).to_ne_bytes(),
+    );
+    request.extend_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+    request.extend_from_slice(&seq.to_ne_bytes());
+    request.extend_from_slice(&0u32.to_ne_bytes());
+    request.push(libc::NFPROTO_UNSPEC as u8);
+    request.push(libc::NFNETLINK_V0 as u8);
+    request.extend_from_slice(&0u16.to_be_bytes());
+    socket.send(&request).map_err(Error::NetlinkSendError)?;
+
+    let mut replies = vec![];
+    let mut buffer = vec![0; nftnl::nft_nlmsg_maxsize() as usize];
+    loop {
+        let received = match Firewall::socket_recv(&socket, &mut buffer)? {
+            Some(received) => received,
+            None => return Ok(replies),
+        };
+        for (header_type, payload) in split_messages(received) {
+            match i32::from(header_type) {
+                libc::NLMSG_DONE => return Ok(replies),
+                libc::NLMSG_ERROR => {
+                    let errno = payload
+                        .get(..4)
+                        .map(|errno| i32::from_ne_bytes([errno[0], errno[1], errno[2], errno[3]]))
+                        .unwrap_or(0);
+                    if errno != 0 {
+                        return Err(Error::ProcessNetlinkError(io::Error::from_raw_os_error(
+                            -errno,
+                        )));
+                    }
+                }
+                _ => {
+                    if let Some((family, attributes)) = parse_nfgen_payload(payload) {
+                        replies.push((
+                            header_type,
+                            family,
+                            attributes
+                                .into_iter()
+                                .map(|(t, payload)| (t, payload.to_vec()))
+                                .collect(),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Parses the netfilter messages in `buffer` into their type, family and attributes.
+fn parse_messages(buffer: &[u8]) -> Vec<(u16, u8, Vec<(u16, &[u8])>)> {
+    split_messages(buffer)
+        .into_iter()
+        .filter_map(|(header_type, payload)| {
+            let (family, attributes) = parse_nfgen_payload(payload)?;
+            Some((header_type, family, attributes))
+        })
+        .collect()
+}
+
+/// Splits `buffer` into the types and payloads of the netlink messages in it.
+fn split_messages(mut buffer: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut messages = vec![];
+    while buffer.len() >= NLMSG_HEADER_LEN {
+        let len = u32::from_ne_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize;
+        if len < NLMSG_HEADER_LEN || len > buffer.len() {
+            break;
+        }
+        let header_type = u16::from_ne_bytes([buffer[4], buffer[5]]);
+        messages.push((header_type, &buffer[NLMSG_HEADER_LEN..len]));
+        buffer = &buffer[align(len).min(buffer.len())..];
+    }
+    messages
+}
+
+fn parse_nfgen_payload(payload: &[u8]) -> Option<(u8, Vec<(u16, &[u8])>)> {
+    if payload.len() < NFGENMSG_LEN {
+        return None;
+    }
+    Some((payload[0], parse_attributes(&payload[NFGENMSG_LEN..])?))
+}
+
+/// Parses a stream of netlink attributes into their types and payloads. Returns `None` if `data`
+/// is not a well-formed stream of attributes.
+fn parse_attributes(mut data: &[u8]) -> Option<Vec<(u16, &[u8])>> {
+    let mut attributes = vec![];
+    while !data.is_empty() {
+        if data.len() < 4 {
+            return None;
+        }
+        let len = usize::from(u16::from_ne_bytes([data[0], data[1]]));
+        let attribute_type = u16::from_ne_bytes([data[2], data[3]]) & libc::NLA_TYPE_MASK as u16;
+        if len < 4 || len > data.len() {
+            return None;
+        }
+        attributes.push((attribute_type, &data[4..len]));
+        data = &data[align(len).min(data.len())..];
+    }
+    Some(attributes)
+}
+
+/// Clears the flags of any nested attributes in `payload`. Netfilter does not set
+/// `NLA_F_NESTED` when dumping, while libnftnl does when building messages.
+fn normalize(payload: &[u8]) -> Vec<u8> {
+    match parse_attributes(payload) {
+        Some(attributes) if !attributes.is_empty() => {
+            let mut normalized = Vec::with_capacity(payload.len());
+            for (attribute_type, payload) in attributes {
+                let payload = normalize(payload);
+                normalized.extend_from_slice(&((4 + payload.len()) as u16).to_ne_bytes());
+                normalized.extend_from_slice(&attribute_type.to_ne_bytes());
+                normalized.extend_from_slice(&payload);
+                normalized.resize(align(normalized.len()), 0);
+            }
+            normalized
+        }
+        _ => payload.to_vec(),
+    }
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
 struct PolicyBatch<'a> {
     batch: Batch,
     in_chain: Chain<'a>,
@@ -318,7 +696,7 @@ impl<'a> PolicyBatch<'a> {
         self.add_loopback_rules()?;
         self.add_split_tunneling_rules(policy)?;
         self.add_dhcp_client_rules();
-        self.add_ndp_rules();
+        self.add_ndp_rules(policy);
         self.add_policy_specific_rules(policy)?;
 
         Ok(self.batch.finalize())
@@ -370,6 +748,31 @@ impl<'a> PolicyBatch<'a> {
             self.batch.add(&rule, nftnl::MsgType::Add);
         }
 
+        // Traffic to excluded networks is tagged the same way as traffic from excluded processes
+        let excluded_networks = match policy {
+            FirewallPolicy::Connecting {
+                excluded_networks, ..
+            }
+            | FirewallPolicy::Connected {
+                excluded_networks, ..
+            } => &excluded_networks[..],
+            FirewallPolicy::Blocked { .. } => &[],
+        };
+        for net in excluded_networks {
+            let chain = if net.is_ipv4() {
+                &self.mangle_chain_v4
+            } else {
+                &self.mangle_chain_v6
+            };
+            let mut rule = Rule::new(chain);
+            check_net(&mut rule, End::Dst, *net);
+            rule.add_expr(&nft_expr!(immediate data split_tunnel::MARK));
+            rule.add_expr(&nft_expr!(ct mark set));
+            rule.add_expr(&nft_expr!(immediate data crate::linux::TUNNEL_FW_MARK));
+            rule.add_expr(&nft_expr!(meta mark set));
+            self.batch.add(&rule, nftnl::MsgType::Add);
+        }
+
         for chain in &[&self.in_chain, &self.out_chain] {
             let mut rule = Rule::new(chain);
             rule.add_expr(&nft_expr!(ct mark));
@@ -482,7 +885,26 @@ impl<'a> PolicyBatch<'a> {
         }
     }
 
-    fn add_ndp_rules(&mut self) {
+    fn add_mdns_rules(&mut self) {
+        use self::TransportProtocol::Udp;
+        for mdns_addr in &*super::MDNS_MULTICAST_ADDRS {
+            // Outgoing mDNS queries and multicast responses
+            let mut out_rule = Rule::new(&self.out_chain);
+            check_ip(&mut out_rule, End::Dst, *mdns_addr);
+            check_port(&mut out_rule, Udp, End::Dst, super::MDNS_PORT);
+            add_verdict(&mut out_rule, &Verdict::Accept);
+            self.batch.add(&out_rule, nftnl::MsgType::Add);
+
+            // Incoming mDNS queries and multicast responses
+            let mut in_rule = Rule::new(&self.in_chain);
+            check_ip(&mut in_rule, End::Dst, *mdns_addr);
+            check_port(&mut in_rule, Udp, End::Dst, super::MDNS_PORT);
+            add_verdict(&mut in_rule, &Verdict::Accept);
+            self.batch.add(&in_rule, nftnl::MsgType::Add);
+        }
+    }
+
+    fn add_ndp_rules(&mut self, policy: &FirewallPolicy) {
         // Outgoing Router solicitation (part of NDP)
         for chain in &[&self.out_chain, &self.forward_chain] {
             let mut rule = Rule::new(chain);
@@ -495,21 +917,23 @@ impl<'a> PolicyBatch<'a> {
             add_verdict(&mut rule, &Verdict::Accept);
             self.batch.add(&rule, nftnl::MsgType::Add);
         }
-        // Incoming Router advertisement (part of NDP)
-        for chain in &[&self.in_chain, &self.forward_chain] {
-            let mut rule = Rule::new(chain);
-            check_net(&mut rule, End::Src, *super::IPV6_LINK_LOCAL);
-            check_icmpv6(&mut rule, 134, 0);
-            add_verdict(&mut rule, &Verdict::Accept);
-            self.batch.add(&rule, nftnl::MsgType::Add);
-        }
-        // Incoming Redirect (part of NDP)
-        for chain in &[&self.in_chain, &self.forward_chain] {
-            let mut rule = Rule::new(chain);
-            check_net(&mut rule, End::Src, *super::IPV6_LINK_LOCAL);
-            check_icmpv6(&mut rule, 137, 0);
-            add_verdict(&mut rule, &Verdict::Accept);
-            self.batch.add(&rule, nftnl::MsgType::Add);
+        if policy.allow_ndp_router_messages() {
+            // Incoming Router advertisement (part of NDP)
+            for chain in &[&self.in_chain, &self.forward_chain] {
+                let mut rule = Rule::new(chain);
+                check_net(&mut rule, End::Src, *super::IPV6_LINK_LOCAL);
+                check_icmpv6(&mut rule, 134, 0);
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
+            // Incoming Redirect (part of NDP)
+            for chain in &[&self.in_chain, &self.forward_chain] {
+                let mut rule = Rule::new(chain);
+                check_net(&mut rule, End::Src, *super::IPV6_LINK_LOCAL);
+                check_icmpv6(&mut rule, 137, 0);
+                add_verdict(&mut rule, &Verdict::Accept);
+                self.batch.add(&rule, nftnl::MsgType::Add);
+            }
         }
         // Outgoing Neighbor solicitation (part of NDP)
         for chain in &[&self.out_chain, &self.forward_chain] {
@@ -552,15 +976,19 @@ impl<'a> PolicyBatch<'a> {
     }
 
     fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) -> Result<()> {
-        let allow_lan = match policy {
+        let (allow_lan, lan_allow_list) = match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                lan_allow_list,
                 allowed_endpoint,
+                ..
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint);
-                self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
+                if let Some(allowed_endpoint) = allowed_endpoint {
+                    self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
+                }
 
                 // Important to block DNS after allow relay rule (so the relay can operate
                 // over port 53) but before allow LAN (so DNS does not leak to the LAN)
@@ -572,13 +1000,15 @@ impl<'a> PolicyBatch<'a> {
                         self.add_block_cve_2019_14899(tunnel);
                     }
                 }
-                *allow_lan
+                (*allow_lan, lan_allow_list)
             }
             FirewallPolicy::Connected {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                lan_allow_list,
                 dns_servers,
+                ..
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint);
                 self.add_allow_dns_rules(tunnel, &dns_servers, TransportProtocol::Udp)?;
@@ -590,22 +1020,29 @@ impl<'a> PolicyBatch<'a> {
                 if *allow_lan {
                     self.add_block_cve_2019_14899(tunnel);
                 }
-                *allow_lan
+                (*allow_lan, lan_allow_list)
             }
             FirewallPolicy::Blocked {
                 allow_lan,
+                lan_allow_list,
                 allowed_endpoint,
+                blocked_state_policy,
             } => {
-                self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
+                if let Some(allowed_endpoint) = allowed_endpoint {
+                    self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
+                }
+                if blocked_state_policy.allow_mdns {
+                    self.add_mdns_rules();
+                }
 
                 // Important to drop DNS before allowing LAN (to stop DNS leaking to the LAN)
                 self.add_drop_dns_rule();
-                *allow_lan
+                (*allow_lan, lan_allow_list)
             }
         };
 
         if allow_lan {
-            self.add_allow_lan_rules();
+            self.add_allow_lan_rules(&super::allowed_lan_nets(lan_allow_list));
         }
 
         // Reject any remaining outgoing traffic
@@ -686,7 +1123,7 @@ impl<'a> PolicyBatch<'a> {
     ) -> Result<()> {
         let (local_resolvers, remote_resolvers): (Vec<IpAddr>, Vec<IpAddr>) = dns_servers
             .iter()
-            .partition(|server| is_local_dns_address(tunnel, server));
+            .partition(|server| super::is_local_dns_address(tunnel, server));
 
         for resolver in &local_resolvers {
             self.add_allow_local_dns_rule(&tunnel.interface, protocol, *resolver)?;
@@ -811,11 +1248,11 @@ impl<'a> PolicyBatch<'a> {
         }
     }
 
-    fn add_allow_lan_rules(&mut self) {
+    fn add_allow_lan_rules(&mut self, lan_nets: &[IpNetwork]) {
         // Output and forward chains
         for chain in &[&self.out_chain, &self.forward_chain] {
             // LAN -> LAN
-            for net in &*super::ALLOWED_LAN_NETS {
+            for net in lan_nets {
                 let mut out_rule = Rule::new(chain);
                 check_net(&mut out_rule, End::Dst, *net);
                 add_verdict(&mut out_rule, &Verdict::Accept);
@@ -833,7 +1270,7 @@ impl<'a> PolicyBatch<'a> {
 
         // Input chain
         // LAN -> LAN
-        for net in &*super::ALLOWED_LAN_NETS {
+        for net in lan_nets {
             let mut in_rule = Rule::new(&self.in_chain);
             check_net(&mut in_rule, End::Src, *net);
             add_verdict(&mut in_rule, &Verdict::Accept);
@@ -867,12 +1304,6 @@ impl<'a> PolicyBatch<'a> {
     }
 }
 
-fn is_local_dns_address(tunnel: &tunnel::TunnelMetadata, server: &IpAddr) -> bool {
-    super::is_local_address(server)
-        && server != &tunnel.ipv4_gateway
-        && Some(server) != tunnel.ipv6_gateway.map(IpAddr::from).as_ref()
-}
-
 fn allow_tunnel_dns_rule<'a>(
     chain: &'a Chain<'_>,
     iface: &str,
@@ -1029,3 +1460,205 @@ fn add_verdict(rule: &mut Rule<'_>, verdict: &expr::Verdict) {
     }
     rule.add_expr(verdict);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn attribute(attribute_type: u16, payload: &[u8]) -> Vec<u8> {
+        let mut attribute = vec![];
+        attribute.extend_from_slice(&((4 + payload.len()) as u16).to_ne_bytes());
+        attribute.extend_from_slice(&attribute_type.to_ne_bytes());
+        attribute.extend_from_slice(payload);
+        attribute.resize(align(attribute.len()), 0);
+        attribute
+    }
+
+    fn chain_id(chain: &str) -> ChainId {
+        (
+            libc::NFPROTO_INET as u8,
+            TABLE_NAME.as_bytes_with_nul().to_vec(),
+            CString::new(chain).unwrap().into_bytes_with_nul(),
+        )
+    }
+
+    fn ruleset(policy: u32, rules: Vec<Vec<Expression>>) -> Ruleset {
+        let mut ruleset = Ruleset::default();
+        ruleset.chains.insert(
+            chain_id("output"),
+            vec![(NFTA_CHAIN_POLICY, policy.to_be_bytes().to_vec())]
+                .into_iter()
+                .collect(),
+        );
+        ruleset.rules.insert(chain_id("output"), rules);
+        ruleset
+    }
+
+    fn expression(name: &str, data: Vec<(u16, Vec<u8>)>) -> Expression {
+        Expression {
+            name: CString::new(name).unwrap().into_bytes_with_nul(),
+            data: data.into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn test_normalize_clears_nested_flag() {
+        let code = attribute(1, &0u32.to_be_bytes());
+        let nested = attribute(2 | libc::NLA_F_NESTED as u16, &code);
+        let unflagged = attribute(2, &code);
+        assert_eq!(normalize(&nested), normalize(&unflagged));
+        assert_ne!(
+            normalize(&nested),
+            normalize(&attribute(2, &attribute(1, &[1, 0, 0, 0])))
+        );
+    }
+
+    #[test]
+    fn test_matches_ignores_added_attributes_and_counters() {
+        let expected = ruleset(
+            0,
+            vec![vec![
+                expression("counter", vec![(1, vec![0; 8])]),
+                expression("bitwise", vec![(1, vec![1, 0, 0, 0])]),
+            ]],
+        );
+        let loaded = ruleset(
+            0,
+            vec![vec![
+                expression("counter", vec![(1, vec![9; 8])]),
+                expression("bitwise", vec![(1, vec![1, 0, 0, 0]), (6, vec![0; 4])]),
+            ]],
+        );
+        assert!(expected.matches(&loaded));
+    }
+
+    #[test]
+    fn test_matches_detects_modifications() {
+        let rule = || vec![expression("cmp", vec![(1, vec![1, 0, 0, 0])])];
+        let expected = ruleset(0, vec![rule()]);
+
+        assert!(!expected.matches(&ruleset(1, vec![rule()])));
+        assert!(!expected.matches(&ruleset(0, vec![rule(), rule()])));
+        assert!(!expected.matches(&ruleset(
+            0,
+            vec![vec![expression("cmp", vec![(1, vec![2, 0, 0, 0])])]]
+        )));
+        assert!(!expected.matches(&Ruleset::default()));
+    }
+
+    const NFTA_CMP_DATA: u16 = 3;
+    const NFTA_DATA_VALUE: u16 = 1;
+
+    fn policy_ruleset(policy: &FirewallPolicy) -> Ruleset {
+        let tables = FirewallTables::new();
+        let batch = PolicyBatch::new(&tables).finalize(policy).unwrap();
+        Ruleset::from_batch(&batch)
+    }
+
+    fn policies() -> Vec<FirewallPolicy> {
+        let peer_endpoint = Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 51820, TransportProtocol::Udp);
+        // The loopback interface exists everywhere, so it stands in for the tunnel interface
+        let tunnel = tunnel::TunnelMetadata {
+            interface: "lo".to_owned(),
+            ips: vec!["10.64.0.2".parse().unwrap()],
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: Some("fc00:bbbb:bbbb:bb01::1".parse().unwrap()),
+        };
+        vec![
+            FirewallPolicy::Connecting {
+                peer_endpoint,
+                tunnel: Some(tunnel.clone()),
+                allow_lan: false,
+                lan_allow_list: None,
+                allowed_endpoint: None,
+                excluded_networks: vec![],
+            },
+            FirewallPolicy::Connected {
+                peer_endpoint,
+                tunnel,
+                allow_lan: false,
+                lan_allow_list: None,
+                dns_servers: vec![],
+                excluded_networks: vec![],
+            },
+            FirewallPolicy::Blocked {
+                allow_lan: false,
+                lan_allow_list: None,
+                allowed_endpoint: None,
+                blocked_state_policy: Default::default(),
+            },
+        ]
+    }
+
+    /// Returns the values that `rule` compares registers against, in order.
+    fn compared_values(rule: &[Expression]) -> Vec<Vec<u8>> {
+        rule.iter()
+            .filter(|expression| expression.name == b"cmp\0")
+            .filter_map(|expression| {
+                let data = parse_attributes(expression.data.get(&NFTA_CMP_DATA)?)?;
+                data.into_iter()
+                    .find(|(t, _)| *t == NFTA_DATA_VALUE)
+                    .map(|(_, value)| value.to_vec())
+            })
+            .collect()
+    }
+
+    /// Returns the ICMPv6 types accepted by the rules in the given chain.
+    fn accepted_icmpv6_types(ruleset: &Ruleset, chain: &str) -> Vec<u8> {
+        ruleset.rules[&chain_id(chain)]
+            .iter()
+            .filter_map(|rule| {
+                let values = compared_values(rule);
+                let protocol = values
+                    .iter()
+                    .position(|value| value == &[libc::IPPROTO_ICMPV6 as u8])?;
+                values.get(protocol + 1).map(|icmp_type| icmp_type[0])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_router_messages_blocked_while_connected() {
+        for policy in policies() {
+            let ruleset = policy_ruleset(&policy);
+            let connected = matches!(policy, FirewallPolicy::Connected { .. });
+            for chain in &["input", "forward"] {
+                let types = accepted_icmpv6_types(&ruleset, chain);
+                // Router advertisements and redirects
+                assert_eq!(types.contains(&134), !connected, "{}: {}", policy, chain);
+                assert_eq!(types.contains(&137), !connected, "{}: {}", policy, chain);
+                // Neighbor discovery is still needed to reach the relay
+                assert!(types.contains(&135), "{}: {}", policy, chain);
+                assert!(types.contains(&136), "{}: {}", policy, chain);
+            }
+        }
+    }
+
+    #[test]
+    fn test_traffic_dropped_by_default() {
+        for policy in policies() {
+            let ruleset = policy_ruleset(&policy);
+            for chain in &["input", "output", "forward"] {
+                assert_eq!(
+                    ruleset.chains[&chain_id(chain)].get(&NFTA_CHAIN_POLICY),
+                    Some(&(libc::NF_DROP as u32).to_be_bytes().to_vec()),
+                    "{}: {}",
+                    policy,
+                    chain
+                );
+            }
+            // Anything not explicitly allowed is rejected, including IPv6 outside the tunnel
+            for chain in &["output", "forward"] {
+                let last_rule = ruleset.rules[&chain_id(chain)].last().unwrap();
+                assert!(
+                    last_rule
+                        .iter()
+                        .any(|expression| expression.name == b"reject\0"),
+                    "{}: {}",
+                    policy,
+                    chain
+                );
+            }
+        }
+    }
+}
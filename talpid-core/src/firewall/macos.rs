@@ -1,7 +1,8 @@
-use super::{FirewallArguments, FirewallPolicy, FirewallT};
+use super::{FirewallArguments, FirewallException, FirewallPolicy, FirewallT};
 use ipnetwork::IpNetwork;
 use pfctl::{DropAction, FilterRuleAction, Uid};
 use std::{
+    collections::BTreeMap,
     env,
     net::{IpAddr, Ipv4Addr},
 };
@@ -16,10 +17,34 @@ type Result<T> = std::result::Result<T, Error>;
 /// replaced by allowing the anchor name to be configured from the public API of this crate.
 const ANCHOR_NAME: &'static str = "mullvad";
 
+/// Referenced from the main ruleset, so that every filter sub-anchor of [`ANCHOR_NAME`] is
+/// evaluated, in the order of their names. The main ruleset is then never changed while a policy
+/// is applied, and each sub-anchor can be rewritten on its own.
+const FILTER_ANCHOR_CALL: &'static str = "mullvad/*";
+
+/// Sub-anchor holding the rules of all exceptions. It is evaluated before
+/// [`POLICY_ANCHOR_NAME`], so that the exceptions take precedence over the quick block rules of
+/// the active policy.
+const EXCEPTIONS_ANCHOR_NAME: &'static str = "mullvad/exceptions";
+
+/// Sub-anchor holding the filter rules of the active policy. The redirect rules are held by
+/// [`ANCHOR_NAME`] itself.
+const POLICY_ANCHOR_NAME: &'static str = "mullvad/policy";
+
+/// Anchors referenced from the main ruleset by earlier versions.
+const LEGACY_FILTER_ANCHORS: [&'static str; 2] = ["mullvad.exceptions", "mullvad"];
+
 pub struct Firewall {
     pf: pfctl::PfCtl,
     pf_was_enabled: Option<bool>,
     rule_logging: RuleLogging,
+    /// The traffic allowed by each exception, keyed by scope.
+    exceptions: BTreeMap<String, Vec<FirewallException>>,
+    /// The DNS redirect port of the redirect rules in the anchor, or `None` if the redirect rules
+    /// are unknown and must be set again.
+    applied_dns_redirect_port: Option<Option<u16>>,
+    /// The rules in the anchor as listed by pfctl right after they were set.
+    applied_rules: Option<AnchorRules>,
     /// An exclusion group ID may be used in the future to help split tunneling in the future.
     _exclusion_gid: u32,
 }
@@ -43,6 +68,9 @@ impl FirewallT for Firewall {
             pf: pfctl::PfCtl::new()?,
             pf_was_enabled: None,
             rule_logging,
+            exceptions: BTreeMap::new(),
+            applied_dns_redirect_port: None,
+            applied_rules: None,
             _exclusion_gid: args.exclusion_gid,
         })
     }
@@ -57,57 +85,238 @@ impl FirewallT for Firewall {
         // Implemented this way to not early return on an error.
         // We always want all three methods to run, and then return
         // the first error it encounterd, if any.
+        self.applied_dns_redirect_port = None;
+        self.applied_rules = None;
+        self.exceptions.clear();
         self.remove_rules()
             .and(self.remove_anchor())
             .and(self.restore_state())
     }
+
+    fn policy_verifier(&mut self, _policy: &FirewallPolicy) -> Result<PolicyVerifier> {
+        Ok(PolicyVerifier {
+            expected: self.applied_rules.clone(),
+        })
+    }
+
+    fn forget_applied_rules(&mut self) {
+        self.applied_dns_redirect_port = None;
+        self.applied_rules = None;
+    }
+}
+
+/// Compares the rules in the anchor against the rules that were set for the active policy. The
+/// kernel does not hand back rules in the form they are added in, so the rules are compared as
+/// listed by pfctl, right after they were set and when verifying.
+pub struct PolicyVerifier {
+    expected: Option<AnchorRules>,
+}
+
+impl PolicyVerifier {
+    /// Returns whether pf is enabled, the anchors are still referenced from the main ruleset,
+    /// and the anchor holds the same rules as when the policy was applied.
+    pub fn verify(&self) -> Result<bool> {
+        let expected = match &self.expected {
+            Some(expected) => expected,
+            None => return Ok(false),
+        };
+        if !pfctl_output(&["-s", "info"])?.contains("Status: Enabled") {
+            log::warn!("pf has been disabled");
+            return Ok(false);
+        }
+        let main_filter_rules = pfctl_output(&["-s", "rules"])?;
+        let main_redirect_rules = pfctl_output(&["-s", "nat"])?;
+        let anchors_referenced = has_line(
+            &main_filter_rules,
+            &format!("anchor \"{}\" all", FILTER_ANCHOR_CALL),
+        ) && has_line(
+            &main_redirect_rules,
+            &format!("rdr-anchor \"{}\" all", ANCHOR_NAME),
+        );
+        if !anchors_referenced {
+            log::warn!("The firewall anchors have been removed from the main ruleset");
+            return Ok(false);
+        }
+        if AnchorRules::read()? != *expected {
+            log::warn!("The rules in the firewall anchor have been modified");
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+/// The filter rules of [`POLICY_ANCHOR_NAME`] and the redirect rules of [`ANCHOR_NAME`], as
+/// listed by pfctl.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AnchorRules {
+    filter: String,
+    redirect: String,
+}
+
+impl AnchorRules {
+    fn read() -> Result<Self> {
+        Ok(AnchorRules {
+            filter: pfctl_output(&["-a", POLICY_ANCHOR_NAME, "-s", "rules"])?,
+            redirect: pfctl_output(&["-a", ANCHOR_NAME, "-s", "nat"])?,
+        })
+    }
+
+    fn filter_rule_count(&self) -> usize {
+        self.filter
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .count()
+    }
+}
+
+fn pfctl_output(args: &[&str]) -> Result<String> {
+    let output = duct::cmd("/sbin/pfctl", args.iter().copied())
+        .stderr_null()
+        .stdout_capture()
+        .run()
+        .map_err(|error| Error::from(format!("Failed to execute pfctl: {}", error)))?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn has_line(output: &str, expected: &str) -> bool {
+    output.lines().any(|line| line.trim() == expected)
 }
 
 impl Firewall {
+    /// Allows the traffic described by `exceptions` regardless of the active policy, until
+    /// [`Self::remove_exception`] is called with the same `scope` or the policy is reset. Any
+    /// previous exception with the same scope is replaced. Only the exceptions sub-anchor is
+    /// rewritten, so the rules of the active policy are left in place.
+    pub fn add_exception(&mut self, scope: &str, exceptions: Vec<FirewallException>) -> Result<()> {
+        self.add_anchor()?;
+        let previous = self.exceptions.insert(scope.to_owned(), exceptions);
+        if let Err(error) = self.set_exception_rules() {
+            match previous {
+                Some(previous) => self.exceptions.insert(scope.to_owned(), previous),
+                None => self.exceptions.remove(scope),
+            };
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Removes the exception with the given `scope`, if there is one. Only the exceptions
+    /// sub-anchor is rewritten.
+    pub fn remove_exception(&mut self, scope: &str) -> Result<()> {
+        if self.exceptions.remove(scope).is_some() {
+            self.set_exception_rules()?;
+        }
+        Ok(())
+    }
+
+    fn set_exception_rules(&mut self) -> Result<()> {
+        let mut rules = vec![];
+        for (scope, exceptions) in &self.exceptions {
+            rules.append(&mut self.get_exception_rules(scope, exceptions)?);
+        }
+        let mut anchor_change = pfctl::AnchorChange::new();
+        anchor_change.set_filter_rules(rules);
+        Ok(self.pf.set_rules(EXCEPTIONS_ANCHOR_NAME, anchor_change)?)
+    }
+
+    fn get_exception_rules(
+        &self,
+        scope: &str,
+        exceptions: &[FirewallException],
+    ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
+        for exception in exceptions {
+            let mut rule_builder =
+                self.create_rule_builder(FilterRuleAction::Pass, RuleTag::Exception);
+            rule_builder
+                .quick(true)
+                .interface(&exception.interface)
+                .label(format!("{}-{}", RuleTag::Exception.label(), scope))
+                .direction(pfctl::Direction::Out)
+                .proto(as_pfctl_proto(exception.endpoint.protocol))
+                .to(exception.endpoint.address)
+                .keep_state(pfctl::StatePolicy::Keep);
+            if exception.endpoint.protocol == net::TransportProtocol::Tcp {
+                rule_builder.tcp_flags(Self::get_tcp_flags());
+            }
+            rules.push(rule_builder.build()?);
+        }
+        Ok(rules)
+    }
+
     fn set_rules(&mut self, policy: FirewallPolicy) -> Result<()> {
         let mut new_filter_rules = vec![];
 
         new_filter_rules.append(&mut self.get_allow_loopback_rules()?);
         new_filter_rules.append(&mut self.get_allow_dhcp_client_rules()?);
-        new_filter_rules.append(&mut self.get_allow_ndp_rules()?);
+        new_filter_rules.append(&mut self.get_allow_ndp_rules(&policy)?);
         new_filter_rules.append(&mut self.get_policy_specific_rules(&policy)?);
 
         let return_out_rule = self
-            .create_rule_builder(FilterRuleAction::Drop(DropAction::Return))
+            .create_rule_builder(
+                FilterRuleAction::Drop(DropAction::Return),
+                RuleTag::BlockAll,
+            )
             .direction(pfctl::Direction::Out)
             .quick(true)
             .build()?;
         new_filter_rules.push(return_out_rule);
 
         let drop_all_rule = self
-            .create_rule_builder(FilterRuleAction::Drop(DropAction::Drop))
+            .create_rule_builder(FilterRuleAction::Drop(DropAction::Drop), RuleTag::BlockAll)
             .quick(true)
             .build()?;
         new_filter_rules.push(drop_all_rule);
 
-        let mut anchor_change = pfctl::AnchorChange::new();
-        anchor_change.set_filter_rules(new_filter_rules);
-        anchor_change.set_redirect_rules(self.get_dns_redirect_rules(&policy)?);
-        Ok(self.pf.set_rules(ANCHOR_NAME, anchor_change)?)
+        let filter_rule_count = new_filter_rules.len();
+        let mut filter_change = pfctl::AnchorChange::new();
+        filter_change.set_filter_rules(new_filter_rules);
+        self.pf.set_rules(POLICY_ANCHOR_NAME, filter_change)?;
+
+        // Only replace the redirect ruleset when it changes, which is rarely the case when
+        // switching between policies
+        let dns_redirect_port = match &policy {
+            FirewallPolicy::Blocked {
+                dns_redirect_port, ..
+            } => Some(*dns_redirect_port),
+            _ => None,
+        };
+        if self.applied_dns_redirect_port != Some(dns_redirect_port) {
+            let mut redirect_change = pfctl::AnchorChange::new();
+            redirect_change.set_redirect_rules(self.get_dns_redirect_rules(dns_redirect_port)?);
+            self.pf.set_rules(ANCHOR_NAME, redirect_change)?;
+        }
+        self.applied_dns_redirect_port = Some(dns_redirect_port);
+
+        let applied_rules = AnchorRules::read()?;
+        if applied_rules.filter_rule_count() != filter_rule_count {
+            log::error!(
+                "Expected {} rules in the firewall anchor, pfctl lists {}",
+                filter_rule_count,
+                applied_rules.filter_rule_count()
+            );
+            self.applied_rules = None;
+        } else {
+            self.applied_rules = Some(applied_rules);
+        }
+        Ok(())
     }
 
     fn get_dns_redirect_rules(
         &mut self,
-        policy: &FirewallPolicy,
+        dns_redirect_port: Option<u16>,
     ) -> Result<Vec<pfctl::RedirectRule>> {
-        let redirect_rules = match policy {
-            FirewallPolicy::Blocked {
-                dns_redirect_port, ..
-            } => {
+        let redirect_rules = match dns_redirect_port {
+            Some(dns_redirect_port) => {
                 vec![pfctl::RedirectRuleBuilder::default()
                     .action(pfctl::RedirectRuleAction::Redirect)
                     .interface("lo0")
                     .proto(pfctl::Proto::Udp)
                     .to(pfctl::Port::from(53))
-                    .redirect_to(pfctl::Port::from(*dns_redirect_port))
+                    .redirect_to(pfctl::Port::from(dns_redirect_port))
                     .build()?]
             }
-            _ => vec![],
+            None => vec![],
         };
         Ok(redirect_rules)
     }
@@ -121,21 +330,26 @@ impl Firewall {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                lan_allow_list,
                 allowed_endpoint,
+                excluded_networks,
             } => {
                 let mut rules = vec![self.get_allow_relay_rule(*peer_endpoint)?];
-                rules.push(self.get_allowed_endpoint_rule(allowed_endpoint.endpoint)?);
+                if let Some(allowed_endpoint) = allowed_endpoint {
+                    rules.push(self.get_allowed_endpoint_rule(allowed_endpoint.endpoint)?);
+                }
 
                 // Important to block DNS after allow relay rule (so the relay can operate
                 // over port 53) but before allow LAN (so DNS does not leak to the LAN)
                 rules.append(&mut self.get_block_dns_rules()?);
+                rules.append(&mut self.get_allow_excluded_networks_rules(excluded_networks)?);
 
                 if let Some(tunnel) = tunnel {
                     rules.push(self.get_allow_tunnel_rule(&tunnel.interface)?);
                 }
 
                 if *allow_lan {
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                    rules.append(&mut self.get_allow_lan_rules(lan_allow_list)?);
                 }
                 Ok(rules)
             }
@@ -143,7 +357,9 @@ impl Firewall {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                lan_allow_list,
                 dns_servers,
+                excluded_networks,
             } => {
                 let mut rules = vec![];
 
@@ -156,27 +372,36 @@ impl Firewall {
                 // Important to block DNS *before* we allow the tunnel and allow LAN. So DNS
                 // can't leak to the wrong IPs in the tunnel or on the LAN.
                 rules.append(&mut self.get_block_dns_rules()?);
+                rules.append(&mut self.get_allow_excluded_networks_rules(excluded_networks)?);
 
                 rules.push(self.get_allow_tunnel_rule(tunnel.interface.as_str())?);
 
                 if *allow_lan {
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                    rules.append(&mut self.get_allow_lan_rules(lan_allow_list)?);
                 }
 
                 Ok(rules)
             }
             FirewallPolicy::Blocked {
                 allow_lan,
+                lan_allow_list,
                 allowed_endpoint,
+                blocked_state_policy,
                 ..
             } => {
                 let mut rules = Vec::new();
-                rules.push(self.get_allowed_endpoint_rule(allowed_endpoint.endpoint)?);
+                if let Some(allowed_endpoint) = allowed_endpoint {
+                    rules.push(self.get_allowed_endpoint_rule(allowed_endpoint.endpoint)?);
+                }
+
+                if blocked_state_policy.allow_mdns {
+                    rules.append(&mut self.get_allow_mdns_rules()?);
+                }
 
                 if *allow_lan {
                     // Important to block DNS before allow LAN (so DNS does not leak to the LAN)
                     rules.append(&mut self.get_block_dns_rules()?);
-                    rules.append(&mut self.get_allow_lan_rules()?);
+                    rules.append(&mut self.get_allow_lan_rules(lan_allow_list)?);
                 }
 
                 Ok(rules)
@@ -191,17 +416,10 @@ impl Firewall {
     ) -> Result<Vec<pfctl::FilterRule>> {
         let mut rules = Vec::with_capacity(4);
 
-        let is_local = super::is_local_address(&server)
-            && server != tunnel.ipv4_gateway
-            && !tunnel
-                .ipv6_gateway
-                .map(|ref gateway| &server == gateway)
-                .unwrap_or(false);
-
-        if is_local {
+        if super::is_local_dns_address(tunnel, &server) {
             // Block requests on the tunnel interface
             let block_tunnel_tcp = self
-                .create_rule_builder(FilterRuleAction::Drop(DropAction::Return))
+                .create_rule_builder(FilterRuleAction::Drop(DropAction::Return), RuleTag::Dns)
                 .direction(pfctl::Direction::Out)
                 .quick(true)
                 .interface(&tunnel.interface)
@@ -211,7 +429,7 @@ impl Firewall {
                 .build()?;
             rules.push(block_tunnel_tcp);
             let block_tunnel_udp = self
-                .create_rule_builder(FilterRuleAction::Drop(DropAction::Return))
+                .create_rule_builder(FilterRuleAction::Drop(DropAction::Return), RuleTag::Dns)
                 .direction(pfctl::Direction::Out)
                 .quick(true)
                 .interface(&tunnel.interface)
@@ -223,7 +441,7 @@ impl Firewall {
 
             // Allow requests on other interfaces
             let allow_nontunnel_tcp = self
-                .create_rule_builder(FilterRuleAction::Pass)
+                .create_rule_builder(FilterRuleAction::Pass, RuleTag::Dns)
                 .direction(pfctl::Direction::Out)
                 .quick(true)
                 .proto(pfctl::Proto::Tcp)
@@ -233,7 +451,7 @@ impl Firewall {
                 .build()?;
             rules.push(allow_nontunnel_tcp);
             let allow_nontunnel_udp = self
-                .create_rule_builder(FilterRuleAction::Pass)
+                .create_rule_builder(FilterRuleAction::Pass, RuleTag::Dns)
                 .direction(pfctl::Direction::Out)
                 .quick(true)
                 .proto(pfctl::Proto::Udp)
@@ -244,7 +462,7 @@ impl Firewall {
         } else {
             // Allow outgoing requests on the tunnel interface only
             let allow_tunnel_tcp = self
-                .create_rule_builder(FilterRuleAction::Pass)
+                .create_rule_builder(FilterRuleAction::Pass, RuleTag::Dns)
                 .direction(pfctl::Direction::Out)
                 .quick(true)
                 .interface(&tunnel.interface)
@@ -255,7 +473,7 @@ impl Firewall {
                 .build()?;
             rules.push(allow_tunnel_tcp);
             let allow_tunnel_udp = self
-                .create_rule_builder(FilterRuleAction::Pass)
+                .create_rule_builder(FilterRuleAction::Pass, RuleTag::Dns)
                 .direction(pfctl::Direction::Out)
                 .quick(true)
                 .interface(&tunnel.interface)
@@ -272,7 +490,7 @@ impl Firewall {
         let pfctl_proto = as_pfctl_proto(relay_endpoint.protocol);
 
         Ok(self
-            .create_rule_builder(FilterRuleAction::Pass)
+            .create_rule_builder(FilterRuleAction::Pass, RuleTag::Relay)
             .direction(pfctl::Direction::Out)
             .to(relay_endpoint.address)
             .proto(pfctl_proto)
@@ -292,7 +510,7 @@ impl Firewall {
         let pfctl_proto = as_pfctl_proto(allowed_endpoint.protocol);
 
         Ok(self
-            .create_rule_builder(FilterRuleAction::Pass)
+            .create_rule_builder(FilterRuleAction::Pass, RuleTag::AllowedEndpoint)
             .direction(pfctl::Direction::Out)
             .to(allowed_endpoint.address)
             .proto(pfctl_proto)
@@ -304,14 +522,20 @@ impl Firewall {
 
     fn get_block_dns_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
         let block_tcp_dns_rule = self
-            .create_rule_builder(FilterRuleAction::Drop(DropAction::Return))
+            .create_rule_builder(
+                FilterRuleAction::Drop(DropAction::Return),
+                RuleTag::BlockDns,
+            )
             .direction(pfctl::Direction::Out)
             .quick(true)
             .proto(pfctl::Proto::Tcp)
             .to(pfctl::Port::from(53))
             .build()?;
         let block_udp_dns_rule = self
-            .create_rule_builder(FilterRuleAction::Drop(DropAction::Return))
+            .create_rule_builder(
+                FilterRuleAction::Drop(DropAction::Return),
+                RuleTag::BlockDns,
+            )
             .direction(pfctl::Direction::Out)
             .quick(true)
             .proto(pfctl::Proto::Udp)
@@ -323,7 +547,7 @@ impl Firewall {
 
     fn get_allow_tunnel_rule(&self, tunnel_interface: &str) -> Result<pfctl::FilterRule> {
         Ok(self
-            .create_rule_builder(FilterRuleAction::Pass)
+            .create_rule_builder(FilterRuleAction::Pass, RuleTag::Tunnel)
             .quick(true)
             .interface(tunnel_interface)
             .keep_state(pfctl::StatePolicy::Keep)
@@ -333,7 +557,7 @@ impl Firewall {
 
     fn get_allow_loopback_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
         let lo0_rule = self
-            .create_rule_builder(FilterRuleAction::Pass)
+            .create_rule_builder(FilterRuleAction::Pass, RuleTag::Loopback)
             .quick(true)
             .interface("lo0")
             .keep_state(pfctl::StatePolicy::Keep)
@@ -341,19 +565,22 @@ impl Firewall {
         Ok(vec![lo0_rule])
     }
 
-    fn get_allow_lan_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
+    fn get_allow_lan_rules(
+        &self,
+        lan_allow_list: &Option<Vec<IpNetwork>>,
+    ) -> Result<Vec<pfctl::FilterRule>> {
         let mut rules = vec![];
-        for net in &*super::ALLOWED_LAN_NETS {
-            let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+        for net in super::allowed_lan_nets(lan_allow_list) {
+            let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass, RuleTag::Lan);
             rule_builder.quick(true);
             let allow_out = rule_builder
                 .direction(pfctl::Direction::Out)
                 .from(pfctl::Ip::Any)
-                .to(pfctl::Ip::from(*net))
+                .to(pfctl::Ip::from(net))
                 .build()?;
             let allow_in = rule_builder
                 .direction(pfctl::Direction::In)
-                .from(pfctl::Ip::from(*net))
+                .from(pfctl::Ip::from(net))
                 .to(pfctl::Ip::Any)
                 .build()?;
             rules.push(allow_out);
@@ -361,7 +588,7 @@ impl Firewall {
         }
         for multicast_net in &*super::ALLOWED_LAN_MULTICAST_NETS {
             let allow_multicast_out = self
-                .create_rule_builder(FilterRuleAction::Pass)
+                .create_rule_builder(FilterRuleAction::Pass, RuleTag::Lan)
                 .quick(true)
                 .direction(pfctl::Direction::Out)
                 .to(pfctl::Ip::from(*multicast_net))
@@ -370,7 +597,7 @@ impl Firewall {
         }
 
         let dhcpv4_out = self
-            .create_rule_builder(FilterRuleAction::Pass)
+            .create_rule_builder(FilterRuleAction::Pass, RuleTag::Lan)
             .quick(true)
             .direction(pfctl::Direction::Out)
             .af(pfctl::AddrFamily::Ipv4)
@@ -379,7 +606,7 @@ impl Firewall {
             .to(pfctl::Port::from(super::DHCPV4_CLIENT_PORT))
             .build()?;
         let dhcpv4_in = self
-            .create_rule_builder(FilterRuleAction::Pass)
+            .create_rule_builder(FilterRuleAction::Pass, RuleTag::Lan)
             .quick(true)
             .direction(pfctl::Direction::In)
             .proto(pfctl::Proto::Udp)
@@ -395,8 +622,34 @@ impl Firewall {
         Ok(rules)
     }
 
+    /// Allows traffic to and from networks that are routed outside the tunnel.
+    fn get_allow_excluded_networks_rules(
+        &self,
+        excluded_networks: &[IpNetwork],
+    ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
+        for net in excluded_networks {
+            let mut rule_builder =
+                self.create_rule_builder(FilterRuleAction::Pass, RuleTag::ExcludedNetwork);
+            rule_builder.quick(true);
+            let allow_out = rule_builder
+                .direction(pfctl::Direction::Out)
+                .from(pfctl::Ip::Any)
+                .to(pfctl::Ip::from(*net))
+                .build()?;
+            let allow_in = rule_builder
+                .direction(pfctl::Direction::In)
+                .from(pfctl::Ip::from(*net))
+                .to(pfctl::Ip::Any)
+                .build()?;
+            rules.push(allow_out);
+            rules.push(allow_in);
+        }
+        Ok(rules)
+    }
+
     fn get_allow_dhcp_client_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
-        let mut dhcp_rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+        let mut dhcp_rule_builder = self.create_rule_builder(FilterRuleAction::Pass, RuleTag::Dhcp);
         dhcp_rule_builder.quick(true).proto(pfctl::Proto::Udp);
 
         let mut rules = Vec::new();
@@ -451,8 +704,35 @@ impl Firewall {
         Ok(rules)
     }
 
-    fn get_allow_ndp_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
-        let mut ndp_rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+    fn get_allow_mdns_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
+        let mut mdns_rule_builder = self.create_rule_builder(FilterRuleAction::Pass, RuleTag::Mdns);
+        mdns_rule_builder.quick(true).proto(pfctl::Proto::Udp);
+
+        let mut rules = Vec::new();
+        for mdns_addr in &*super::MDNS_MULTICAST_ADDRS {
+            let mdns_endpoint =
+                pfctl::Endpoint::new(*mdns_addr, pfctl::Port::from(super::MDNS_PORT));
+            mdns_rule_builder.af(match mdns_addr {
+                IpAddr::V4(_) => pfctl::AddrFamily::Ipv4,
+                IpAddr::V6(_) => pfctl::AddrFamily::Ipv6,
+            });
+            let allow_outgoing_mdns = mdns_rule_builder
+                .direction(pfctl::Direction::Out)
+                .to(mdns_endpoint.clone())
+                .build()?;
+            let allow_incoming_mdns = mdns_rule_builder
+                .direction(pfctl::Direction::In)
+                .to(mdns_endpoint)
+                .build()?;
+            rules.push(allow_outgoing_mdns);
+            rules.push(allow_incoming_mdns);
+        }
+
+        Ok(rules)
+    }
+
+    fn get_allow_ndp_rules(&self, policy: &FirewallPolicy) -> Result<Vec<pfctl::FilterRule>> {
+        let mut ndp_rule_builder = self.create_rule_builder(FilterRuleAction::Pass, RuleTag::Ndp);
         ndp_rule_builder
             .quick(true)
             .af(pfctl::AddrFamily::Ipv6)
@@ -470,25 +750,27 @@ impl Firewall {
                 .build()?,
         );
 
-        // Incoming router advertisement from `fe80::/10`
-        rules.push(
-            ndp_rule_builder
-                .clone()
-                .direction(pfctl::Direction::In)
-                .icmp_type(pfctl::IcmpType::Icmp6(pfctl::Icmp6Type::RouterAdv))
-                .from(pfctl::Ip::from(IpNetwork::V6(*super::IPV6_LINK_LOCAL)))
-                .build()?,
-        );
-
-        // Incoming Redirect from `fe80::/10`
-        rules.push(
-            ndp_rule_builder
-                .clone()
-                .direction(pfctl::Direction::In)
-                .icmp_type(pfctl::IcmpType::Icmp6(pfctl::Icmp6Type::Redir))
-                .from(pfctl::Ip::from(IpNetwork::V6(*super::IPV6_LINK_LOCAL)))
-                .build()?,
-        );
+        if policy.allow_ndp_router_messages() {
+            // Incoming router advertisement from `fe80::/10`
+            rules.push(
+                ndp_rule_builder
+                    .clone()
+                    .direction(pfctl::Direction::In)
+                    .icmp_type(pfctl::IcmpType::Icmp6(pfctl::Icmp6Type::RouterAdv))
+                    .from(pfctl::Ip::from(IpNetwork::V6(*super::IPV6_LINK_LOCAL)))
+                    .build()?,
+            );
+
+            // Incoming Redirect from `fe80::/10`
+            rules.push(
+                ndp_rule_builder
+                    .clone()
+                    .direction(pfctl::Direction::In)
+                    .icmp_type(pfctl::IcmpType::Icmp6(pfctl::Icmp6Type::Redir))
+                    .from(pfctl::Ip::from(IpNetwork::V6(*super::IPV6_LINK_LOCAL)))
+                    .build()?,
+            );
+        }
 
         // Outgoing neighbor solicitation to `ff02::1:ff00:0/104` and `fe80::/10`
         rules.push(
@@ -542,9 +824,13 @@ impl Firewall {
         Ok(rules)
     }
 
-    fn create_rule_builder(&self, action: FilterRuleAction) -> pfctl::FilterRuleBuilder {
+    fn create_rule_builder(
+        &self,
+        action: FilterRuleAction,
+        tag: RuleTag,
+    ) -> pfctl::FilterRuleBuilder {
         let mut builder = pfctl::FilterRuleBuilder::default();
-        builder.action(action);
+        builder.action(action).label(tag.label());
         let rule_log = pfctl::RuleLog::IncludeMatchingState;
         let do_log = match action {
             FilterRuleAction::Pass => match self.rule_logging {
@@ -572,7 +858,9 @@ impl Firewall {
     fn remove_rules(&mut self) -> Result<()> {
         // remove_anchor() does not deactivate active rules
         self.pf
-            .flush_rules(ANCHOR_NAME, pfctl::RulesetKind::Filter)?;
+            .flush_rules(EXCEPTIONS_ANCHOR_NAME, pfctl::RulesetKind::Filter)?;
+        self.pf
+            .flush_rules(POLICY_ANCHOR_NAME, pfctl::RulesetKind::Filter)?;
         Ok(())
     }
 
@@ -608,19 +896,30 @@ impl Firewall {
         }
     }
 
+    /// Adds the anchors to the main ruleset if they are missing. The anchors are only added once,
+    /// and are then left in place while policies and exceptions change.
     fn add_anchor(&mut self) -> Result<()> {
         self.pf
-            .try_add_anchor(ANCHOR_NAME, pfctl::AnchorKind::Filter)?;
+            .try_add_anchor(FILTER_ANCHOR_CALL, pfctl::AnchorKind::Filter)?;
         self.pf
             .try_add_anchor(ANCHOR_NAME, pfctl::AnchorKind::Redirect)?;
+        // The new anchor is in place before the ones of earlier versions are removed
+        for legacy_anchor in &LEGACY_FILTER_ANCHORS {
+            self.pf
+                .try_remove_anchor(legacy_anchor, pfctl::AnchorKind::Filter)?;
+        }
         Ok(())
     }
 
     fn remove_anchor(&mut self) -> Result<()> {
         self.pf
-            .try_remove_anchor(ANCHOR_NAME, pfctl::AnchorKind::Filter)?;
+            .try_remove_anchor(FILTER_ANCHOR_CALL, pfctl::AnchorKind::Filter)?;
         self.pf
             .try_remove_anchor(ANCHOR_NAME, pfctl::AnchorKind::Redirect)?;
+        for legacy_anchor in &LEGACY_FILTER_ANCHORS {
+            self.pf
+                .try_remove_anchor(legacy_anchor, pfctl::AnchorKind::Filter)?;
+        }
         Ok(())
     }
 }
@@ -639,3 +938,43 @@ enum RuleLogging {
     Drop,
     All,
 }
+
+/// Identifies what a rule is for. Every rule is labeled with its tag, which makes the rules
+/// distinguishable in `pfctl -s rules` and `pfctl -s labels`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum RuleTag {
+    Loopback,
+    Dhcp,
+    Ndp,
+    Mdns,
+    Relay,
+    AllowedEndpoint,
+    Dns,
+    BlockDns,
+    ExcludedNetwork,
+    Tunnel,
+    Lan,
+    BlockAll,
+    Exception,
+}
+
+impl RuleTag {
+    fn label(&self) -> String {
+        let tag = match self {
+            RuleTag::Loopback => "loopback",
+            RuleTag::Dhcp => "dhcp",
+            RuleTag::Ndp => "ndp",
+            RuleTag::Mdns => "mdns",
+            RuleTag::Relay => "relay",
+            RuleTag::AllowedEndpoint => "allowed-endpoint",
+            RuleTag::Dns => "dns",
+            RuleTag::BlockDns => "block-dns",
+            RuleTag::ExcludedNetwork => "excluded-network",
+            RuleTag::Tunnel => "tunnel",
+            RuleTag::Lan => "lan",
+            RuleTag::BlockAll => "block-all",
+            RuleTag::Exception => "exception",
+        };
+        format!("{}-{}", ANCHOR_NAME, tag)
+    }
+}
@@ -1,15 +1,15 @@
-#[cfg(unix)]
 use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
-#[cfg(unix)]
 use lazy_static::lazy_static;
 use std::fmt;
 #[cfg(not(target_os = "android"))]
 use std::net::IpAddr;
-#[cfg(unix)]
 use std::net::{Ipv4Addr, Ipv6Addr};
 #[cfg(windows)]
 use std::path::PathBuf;
-use talpid_types::net::{AllowedEndpoint, Endpoint};
+use talpid_types::{
+    firewall::{BlockedStatePolicy, FirewallPolicyDescription, FirewallPolicyState},
+    net::{AllowedEndpoint, Endpoint},
+};
 
 #[cfg(target_os = "macos")]
 #[path = "macos.rs"]
@@ -29,7 +29,6 @@ mod imp;
 
 pub use self::imp::Error;
 
-#[cfg(unix)]
 lazy_static! {
     /// When "allow local network" is enabled the app will allow traffic to and from these networks.
     pub(crate) static ref ALLOWED_LAN_NETS: [IpNetwork; 6] = [
@@ -40,6 +39,10 @@ lazy_static! {
         IpNetwork::V6(Ipv6Network::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), 10).unwrap()),
         IpNetwork::V6(Ipv6Network::new(Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0), 7).unwrap()),
     ];
+}
+
+#[cfg(unix)]
+lazy_static! {
     /// When "allow local network" is enabled the app will allow traffic to these networks.
     pub(crate) static ref ALLOWED_LAN_MULTICAST_NETS: [IpNetwork; 8] = [
         // Local network broadcast. Not routable
@@ -73,6 +76,14 @@ lazy_static! {
     ];
 }
 #[cfg(all(unix, not(target_os = "android")))]
+lazy_static! {
+    /// The multicast groups that mDNS queries and responses are sent to.
+    static ref MDNS_MULTICAST_ADDRS: [IpAddr; 2] = [
+        IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)),
+        IpAddr::V6(Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb)),
+    ];
+}
+#[cfg(all(unix, not(target_os = "android")))]
 const DHCPV4_SERVER_PORT: u16 = 67;
 #[cfg(all(unix, not(target_os = "android")))]
 const DHCPV4_CLIENT_PORT: u16 = 68;
@@ -81,6 +92,8 @@ const DHCPV6_SERVER_PORT: u16 = 547;
 #[cfg(all(unix, not(target_os = "android")))]
 const DHCPV6_CLIENT_PORT: u16 = 546;
 #[cfg(all(unix, not(target_os = "android")))]
+const MDNS_PORT: u16 = 5353;
+#[cfg(all(unix, not(target_os = "android")))]
 const ROOT_UID: u32 = 0;
 
 #[cfg(all(unix, not(target_os = "android")))]
@@ -93,6 +106,31 @@ pub fn is_local_address(address: &IpAddr) -> bool {
         .any(|net| net.contains(address))
 }
 
+/// Returns whether a DNS server is on the local network rather than inside the tunnel. The
+/// gateways of the tunnel are private addresses, but are only reachable through the tunnel.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn is_local_dns_address(tunnel: &crate::tunnel::TunnelMetadata, server: &IpAddr) -> bool {
+    is_local_address(server) && !tunnel.gateways().contains(server)
+}
+
+/// Returns whether a network lies entirely within one of the private networks that may be
+/// allowed by the "allow local network" setting.
+pub fn is_private_network(network: &IpNetwork) -> bool {
+    ALLOWED_LAN_NETS.iter().any(|private_net| {
+        private_net.prefix() <= network.prefix() && private_net.contains(network.network())
+    })
+}
+
+/// Returns the private networks that traffic is allowed to and from when LAN access is
+/// allowed. If an allow list is given, only the networks in it are allowed.
+#[cfg(unix)]
+fn allowed_lan_nets(lan_allow_list: &Option<Vec<IpNetwork>>) -> Vec<IpNetwork> {
+    match lan_allow_list {
+        Some(networks) => networks.clone(),
+        None => ALLOWED_LAN_NETS.to_vec(),
+    }
+}
+
 /// A enum that describes network security strategy
 ///
 /// # Firewall block/allow specification.
@@ -109,8 +147,14 @@ pub enum FirewallPolicy {
         tunnel: Option<crate::tunnel::TunnelMetadata>,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
-        /// Host that should be reachable while connecting.
-        allowed_endpoint: AllowedEndpoint,
+        /// Private networks that LAN communication is limited to when `allow_lan` is set. All
+        /// private networks are reachable if this is `None`.
+        lan_allow_list: Option<Vec<IpNetwork>>,
+        /// Host that should be reachable while connecting, if any.
+        allowed_endpoint: Option<AllowedEndpoint>,
+        /// Networks whose traffic should bypass the tunnel.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        excluded_networks: Vec<IpNetwork>,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
@@ -124,20 +168,36 @@ pub enum FirewallPolicy {
         tunnel: crate::tunnel::TunnelMetadata,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Private networks that LAN communication is limited to when `allow_lan` is set. All
+        /// private networks are reachable if this is `None`.
+        lan_allow_list: Option<Vec<IpNetwork>>,
         /// Servers that are allowed to respond to DNS requests.
         #[cfg(not(target_os = "android"))]
         dns_servers: Vec<IpAddr>,
+        /// Networks whose traffic should bypass the tunnel.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        excluded_networks: Vec<IpNetwork>,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
+        /// Network that is shared with other devices using Internet Connection Sharing or
+        /// Mobile Hotspot. Clients on this network are allowed to reach the host, which forwards
+        /// their traffic through the tunnel.
+        #[cfg(windows)]
+        shared_network: Option<Ipv4Network>,
     },
 
     /// Block all network traffic in and out from the computer.
     Blocked {
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
-        /// Host that should be reachable while in the blocked state.
-        allowed_endpoint: AllowedEndpoint,
+        /// Private networks that LAN communication is limited to when `allow_lan` is set. All
+        /// private networks are reachable if this is `None`.
+        lan_allow_list: Option<Vec<IpNetwork>>,
+        /// Host that should be reachable while in the blocked state, if any.
+        allowed_endpoint: Option<AllowedEndpoint>,
+        /// Local network services that should remain reachable while in the blocked state.
+        blocked_state_policy: BlockedStatePolicy,
         /// Desination port for DNS traffic redirection. Traffic destined to `127.0.0.1:53` will be
         /// redirected to `127.0.0.1:$dns_redirect_port`.
         #[cfg(target_os = "macos")]
@@ -157,7 +217,7 @@ impl fmt::Display for FirewallPolicy {
                 if let Some(tunnel) = tunnel {
                     write!(
                         f,
-                        "Connecting to {} over \"{}\" (ip: {}, v4 gw: {}, v6 gw: {:?}), {} LAN",
+                        "Connecting to {} over \"{}\" (ip: {}, v4 gw: {:?}, v6 gw: {:?}), {} LAN",
                         peer_endpoint,
                         tunnel.interface,
                         tunnel
@@ -166,7 +226,7 @@ impl fmt::Display for FirewallPolicy {
                             .map(|ip| ip.to_string())
                             .collect::<Vec<_>>()
                             .join(","),
-                        tunnel.ipv4_gateway,
+                        tunnel.reachable_ipv4_gateway(),
                         tunnel.ipv6_gateway,
                         if *allow_lan { "Allowing" } else { "Blocking" }
                     )
@@ -186,7 +246,7 @@ impl fmt::Display for FirewallPolicy {
                 ..
             } => write!(
                 f,
-                "Connected to {} over \"{}\" (ip: {}, v4 gw: {}, v6 gw: {:?}), {} LAN",
+                "Connected to {} over \"{}\" (ip: {}, v4 gw: {:?}, v6 gw: {:?}), {} LAN",
                 peer_endpoint,
                 tunnel.interface,
                 tunnel
@@ -195,28 +255,150 @@ impl fmt::Display for FirewallPolicy {
                     .map(|ip| ip.to_string())
                     .collect::<Vec<_>>()
                     .join(","),
-                tunnel.ipv4_gateway,
+                tunnel.reachable_ipv4_gateway(),
                 tunnel.ipv6_gateway,
                 if *allow_lan { "Allowing" } else { "Blocking" }
             ),
             FirewallPolicy::Blocked {
                 allow_lan,
                 allowed_endpoint,
+                blocked_state_policy,
                 ..
-            } => write!(
-                f,
-                "Blocked. {} LAN. Allowing endpoint {}",
-                if *allow_lan { "Allowing" } else { "Blocking" },
+            } => {
+                write!(
+                    f,
+                    "Blocked. {} LAN. {} mDNS. ",
+                    if *allow_lan { "Allowing" } else { "Blocking" },
+                    if blocked_state_policy.allow_mdns {
+                        "Allowing"
+                    } else {
+                        "Blocking"
+                    },
+                )?;
+                match allowed_endpoint {
+                    Some(allowed_endpoint) => write!(f, "Allowing endpoint {}", allowed_endpoint),
+                    None => write!(f, "No allowed endpoint"),
+                }
+            }
+        }
+    }
+}
+
+impl FirewallPolicy {
+    /// Returns a structured description of what this policy allows.
+    pub fn describe(&self) -> FirewallPolicyDescription {
+        match self {
+            FirewallPolicy::Connecting {
+                peer_endpoint,
+                tunnel,
+                allow_lan,
+                lan_allow_list,
                 allowed_endpoint,
-            ),
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                excluded_networks,
+                ..
+            } => FirewallPolicyDescription {
+                state: FirewallPolicyState::Connecting,
+                peer_endpoint: Some(*peer_endpoint),
+                allowed_endpoint: allowed_endpoint.as_ref().map(|endpoint| endpoint.endpoint),
+                tunnel_interface: tunnel.as_ref().map(|tunnel| tunnel.interface.clone()),
+                allow_lan: *allow_lan,
+                lan_networks: lan_networks(*allow_lan, lan_allow_list),
+                dns_servers: vec![],
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                excluded_networks: excluded_networks.clone(),
+                #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+                excluded_networks: vec![],
+            },
+            FirewallPolicy::Connected {
+                peer_endpoint,
+                tunnel,
+                allow_lan,
+                lan_allow_list,
+                #[cfg(not(target_os = "android"))]
+                dns_servers,
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                excluded_networks,
+                ..
+            } => FirewallPolicyDescription {
+                state: FirewallPolicyState::Connected,
+                peer_endpoint: Some(*peer_endpoint),
+                allowed_endpoint: None,
+                tunnel_interface: Some(tunnel.interface.clone()),
+                allow_lan: *allow_lan,
+                lan_networks: lan_networks(*allow_lan, lan_allow_list),
+                #[cfg(not(target_os = "android"))]
+                dns_servers: dns_servers.clone(),
+                #[cfg(target_os = "android")]
+                dns_servers: vec![],
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                excluded_networks: excluded_networks.clone(),
+                #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+                excluded_networks: vec![],
+            },
+            FirewallPolicy::Blocked {
+                allow_lan,
+                lan_allow_list,
+                allowed_endpoint,
+                ..
+            } => FirewallPolicyDescription {
+                state: FirewallPolicyState::Blocked,
+                peer_endpoint: None,
+                allowed_endpoint: allowed_endpoint.as_ref().map(|endpoint| endpoint.endpoint),
+                tunnel_interface: None,
+                allow_lan: *allow_lan,
+                lan_networks: lan_networks(*allow_lan, lan_allow_list),
+                dns_servers: vec![],
+                excluded_networks: vec![],
+            },
         }
     }
+
+    /// Returns whether incoming ICMPv6 router advertisements and redirects should be accepted. All
+    /// traffic is routed through the tunnel while connected, so they are not needed then, and they
+    /// could otherwise install routes or make the system use a different router on the local
+    /// network.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn allow_ndp_router_messages(&self) -> bool {
+        !matches!(self, FirewallPolicy::Connected { .. })
+    }
+}
+
+/// Returns the networks that are reachable when LAN access is allowed. Unless an allow list is
+/// used, the Windows firewall module defines these networks itself, so nothing is listed there.
+fn lan_networks(allow_lan: bool, lan_allow_list: &Option<Vec<IpNetwork>>) -> Vec<IpNetwork> {
+    if !allow_lan {
+        return vec![];
+    }
+    #[cfg(unix)]
+    {
+        allowed_lan_nets(lan_allow_list)
+            .into_iter()
+            .chain(ALLOWED_LAN_MULTICAST_NETS.iter().cloned())
+            .collect()
+    }
+    #[cfg(windows)]
+    {
+        lan_allow_list.clone().unwrap_or_default()
+    }
+}
+
+/// Traffic that is allowed regardless of the applied policy.
+#[cfg(target_os = "macos")]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FirewallException {
+    /// The interface that the traffic is allowed on.
+    pub interface: String,
+    /// The remote endpoint that traffic is allowed to. Only the replies to this traffic are let
+    /// in.
+    pub endpoint: Endpoint,
 }
 
 /// Manages network security of the computer/device. Can apply and enforce firewall policies
 /// by manipulating the OS firewall and DNS settings.
 pub struct Firewall {
     inner: imp::Firewall,
+    applied_policy: Option<FirewallPolicy>,
 }
 
 /// Arguments required when first initializing the firewall.
@@ -225,6 +407,10 @@ pub struct FirewallArguments {
     pub initial_state: InitialFirewallState,
     /// This argument is required for the blocked state to configure the firewall correctly.
     pub allow_lan: bool,
+    /// Private networks that LAN communication is limited to in the blocked state.
+    pub lan_allow_list: Option<Vec<IpNetwork>>,
+    /// Local network services that are permitted in the blocked state.
+    pub blocked_state_policy: BlockedStatePolicy,
     #[cfg(target_os = "macos")]
     /// This argument is required on macOS to know which group's traffic should be excluded, if at
     /// all.
@@ -242,8 +428,23 @@ pub enum InitialFirewallState {
 impl Firewall {
     /// Returns a new `Firewall`, ready to apply policies.
     pub fn new(args: FirewallArguments) -> Result<Self, Error> {
+        // Only the Windows and Linux firewall modules apply the initial state during init.
+        #[cfg(any(windows, target_os = "linux"))]
+        let initial_policy = match &args.initial_state {
+            InitialFirewallState::None => None,
+            InitialFirewallState::Blocked(allowed_endpoint) => Some(FirewallPolicy::Blocked {
+                allow_lan: args.allow_lan,
+                lan_allow_list: args.lan_allow_list.clone(),
+                allowed_endpoint: Some(allowed_endpoint.clone()),
+                blocked_state_policy: args.blocked_state_policy,
+            }),
+        };
+        #[cfg(not(any(windows, target_os = "linux")))]
+        let initial_policy = None;
+
         Ok(Firewall {
             inner: imp::Firewall::new(args)?,
+            applied_policy: initial_policy,
         })
     }
 
@@ -251,14 +452,125 @@ impl Firewall {
     /// until this method is called again with another policy, or until `reset_policy` is called.
     pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Error> {
         log::info!("Applying firewall policy: {}", policy);
-        self.inner.apply_policy(policy)
+        self.inner.apply_policy(policy.clone())?;
+        self.applied_policy = Some(policy);
+        Ok(())
     }
 
     /// Resets/removes any currently enforced `FirewallPolicy`. Returns the system to the same state
     /// it had before any policy was applied through this `Firewall` instance.
     pub fn reset_policy(&mut self) -> Result<(), Error> {
         log::info!("Resetting firewall policy");
-        self.inner.reset_policy()
+        self.inner.reset_policy()?;
+        self.applied_policy = None;
+        Ok(())
+    }
+
+    /// Returns a `PolicyVerifier` that compares the loaded rules against the rules of the given
+    /// `FirewallPolicy`. The verifier can be used from any thread, so that reading back the
+    /// system firewall state does not hold up the caller.
+    pub fn policy_verifier(&mut self, policy: &FirewallPolicy) -> Result<PolicyVerifier, Error> {
+        if self.applied_policy.as_ref() != Some(policy) {
+            log::warn!("Cannot verify a firewall policy that has not been applied");
+            return Ok(PolicyVerifier { inner: None });
+        }
+        Ok(PolicyVerifier {
+            inner: Some(self.inner.policy_verifier(policy)?),
+        })
+    }
+
+    /// Reads back the system firewall state and returns whether the rules enforcing the given
+    /// `FirewallPolicy` are still in place.
+    pub fn verify_policy(&mut self, policy: &FirewallPolicy) -> Result<bool, Error> {
+        log::debug!("Verifying firewall policy: {}", policy);
+        self.policy_verifier(policy)?.verify()
+    }
+
+    /// Reads back the system firewall state and returns whether the rules of the policy that was
+    /// last applied are still in place, or `None` if no policy has been applied. Nothing is
+    /// changed.
+    pub fn check_applied_policy(&mut self) -> Result<Option<bool>, Error> {
+        match self.applied_policy.clone() {
+            Some(policy) => self.verify_policy(&policy).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads back the system firewall state and applies the policy that was last applied again if
+    /// its rules are no longer in place. Returns whether the policy had to be restored.
+    pub fn restore_policy(&mut self) -> Result<bool, Error> {
+        let policy = match &self.applied_policy {
+            Some(policy) => policy.clone(),
+            None => return Ok(false),
+        };
+        if self.verify_policy(&policy)? {
+            return Ok(false);
+        }
+        log::warn!("Firewall rules have been removed or modified. Restoring them");
+        self.inner.forget_applied_rules();
+        self.apply_policy(policy)?;
+        Ok(true)
+    }
+
+    /// Installs or removes filters that block all traffic during boot, until the daemon has
+    /// started and applied a policy. They are lifted until the next reboot, and so do not
+    /// interfere with the active policy.
+    #[cfg(windows)]
+    pub fn set_boot_time_blocking(&mut self, enable: bool) -> Result<(), Error> {
+        self.inner.set_boot_time_blocking(enable)
+    }
+
+    /// Allows the traffic described by `exceptions` regardless of the applied policy, until the
+    /// exception is removed or the policy is reset. Used for narrow exceptions such as captive
+    /// portals. An exception replaces any previous exception with the same `scope`.
+    #[cfg(target_os = "macos")]
+    pub fn add_exception(
+        &mut self,
+        scope: &str,
+        exceptions: Vec<FirewallException>,
+    ) -> Result<(), Error> {
+        log::info!("Adding firewall exception \"{}\": {:?}", scope, exceptions);
+        self.inner.add_exception(scope, exceptions)
+    }
+
+    /// Removes the exception with the given `scope`, if any, without reapplying the policy.
+    #[cfg(target_os = "macos")]
+    pub fn remove_exception(&mut self, scope: &str) -> Result<(), Error> {
+        log::info!("Removing firewall exception \"{}\"", scope);
+        self.inner.remove_exception(scope)
+    }
+
+    /// Returns a structured description of the policy that was last applied successfully.
+    pub fn describe_policy(&self) -> FirewallPolicyDescription {
+        match &self.applied_policy {
+            Some(policy) => policy.describe(),
+            None => FirewallPolicyDescription {
+                state: FirewallPolicyState::None,
+                peer_endpoint: None,
+                allowed_endpoint: None,
+                tunnel_interface: None,
+                allow_lan: true,
+                lan_networks: vec![],
+                dns_servers: vec![],
+                excluded_networks: vec![],
+            },
+        }
+    }
+}
+
+/// Compares the rules loaded into the system firewall against the rules of a policy.
+pub struct PolicyVerifier {
+    /// `None` if the policy is not the one that was last applied.
+    inner: Option<imp::PolicyVerifier>,
+}
+
+impl PolicyVerifier {
+    /// Returns whether the rules of the policy are in place.
+    pub fn verify(&self) -> Result<bool, Error> {
+        match &self.inner {
+            Some(verifier) => verifier.verify(),
+            None => Ok(false),
+        }
     }
 }
 
@@ -276,4 +588,82 @@ trait FirewallT: Sized {
     /// Revert the system firewall state to what it was before this instance started
     /// modifying the system.
     fn reset_policy(&mut self) -> Result<(), Self::Error>;
+
+    /// Return a verifier for the rules of the given FirewallPolicy, which must be the policy
+    /// that was last applied
+    fn policy_verifier(
+        &mut self,
+        policy: &FirewallPolicy,
+    ) -> Result<imp::PolicyVerifier, Self::Error>;
+
+    /// Forget any state kept about the rules that were applied, since they have been modified
+    /// by someone else
+    fn forget_applied_rules(&mut self) {}
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tunnel::TunnelMetadata;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    const IPV4_GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 64, 0, 1);
+    const IPV6_GATEWAY: Ipv6Addr = Ipv6Addr::new(0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0, 1);
+
+    fn tunnel_metadata(ips: Vec<IpAddr>) -> TunnelMetadata {
+        TunnelMetadata {
+            interface: "wg-mullvad".to_owned(),
+            ips,
+            ipv4_gateway: IPV4_GATEWAY,
+            ipv6_gateway: Some(IPV6_GATEWAY),
+        }
+    }
+
+    fn ipv6_only_metadata() -> TunnelMetadata {
+        tunnel_metadata(vec![IpAddr::V6(Ipv6Addr::new(
+            0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0x1, 0x2,
+        ))])
+    }
+
+    #[test]
+    fn test_ipv6_only_tunnel_gateways() {
+        let tunnel = ipv6_only_metadata();
+        assert_eq!(tunnel.reachable_ipv4_gateway(), None);
+        assert_eq!(tunnel.gateways(), vec![IpAddr::V6(IPV6_GATEWAY)]);
+
+        let tunnel = tunnel_metadata(vec![
+            IpAddr::V4(Ipv4Addr::new(10, 64, 0, 2)),
+            IpAddr::V6(Ipv6Addr::new(
+                0xfc00, 0xbbbb, 0xbbbb, 0xbb01, 0, 0, 0x1, 0x2,
+            )),
+        ]);
+        assert_eq!(tunnel.reachable_ipv4_gateway(), Some(IPV4_GATEWAY));
+        assert_eq!(
+            tunnel.gateways(),
+            vec![IpAddr::V4(IPV4_GATEWAY), IpAddr::V6(IPV6_GATEWAY)]
+        );
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_ipv6_only_tunnel_local_dns() {
+        let tunnel = ipv6_only_metadata();
+        assert!(!super::is_local_dns_address(
+            &tunnel,
+            &IpAddr::V6(IPV6_GATEWAY)
+        ));
+        // The IPv4 gateway is not reachable through the tunnel, so it is treated like any other
+        // private address
+        assert!(super::is_local_dns_address(
+            &tunnel,
+            &IpAddr::V4(IPV4_GATEWAY)
+        ));
+        assert!(super::is_local_dns_address(
+            &tunnel,
+            &IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))
+        ));
+        assert!(!super::is_local_dns_address(
+            &tunnel,
+            &IpAddr::V4(Ipv4Addr::new(194, 242, 2, 2))
+        ));
+    }
 }
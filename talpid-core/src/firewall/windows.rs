@@ -4,10 +4,11 @@ use std::{net::IpAddr, path::Path, ptr};
 
 use self::winfw::*;
 use super::{FirewallArguments, FirewallPolicy, FirewallT, InitialFirewallState};
-use crate::winnet;
+use crate::{windows::connection_sharing, winnet};
 use talpid_types::{
     net::{AllowedEndpoint, Endpoint},
     tunnel::FirewallPolicyError,
+    ErrorExt,
 };
 use widestring::WideCString;
 
@@ -39,6 +40,14 @@ pub enum Error {
     #[error(display = "Failed to reset firewall policies")]
     ResettingPolicy(#[error(source)] FirewallPolicyError),
 
+    /// Failure to read back the filters of the active policy
+    #[error(display = "Failed to verify firewall policy")]
+    VerifyingPolicy(#[error(source)] FirewallPolicyError),
+
+    /// Failure to install or remove boot-time blocking filters
+    #[error(display = "Failed to update boot-time blocking filters")]
+    SetBootTimeBlocking(#[error(source)] FirewallPolicyError),
+
     /// Failure to set virtual adapter metric
     #[error(display = "Unable to set virtual adapter metric")]
     SetTunMetric(#[error(source)] crate::winnet::Error),
@@ -57,12 +66,13 @@ impl FirewallT for Firewall {
         let logging_context = b"WinFw\0".as_ptr();
 
         if let InitialFirewallState::Blocked(allowed_endpoint) = args.initial_state {
-            let cfg = &WinFwSettings::new(args.allow_lan);
+            let mut cfg = WinFwSettingsContainer::new(args.allow_lan, args.lan_allow_list);
+            cfg.set_permit_mdns(args.blocked_state_policy.allow_mdns);
             let allowed_endpoint = WinFwAllowedEndpointContainer::from(allowed_endpoint);
             unsafe {
                 WinFw_InitializeBlocked(
                     WINFW_TIMEOUT_SECONDS,
-                    &cfg,
+                    &cfg.as_settings(),
                     &allowed_endpoint.as_endpoint(),
                     Some(log_sink),
                     logging_context,
@@ -86,16 +96,21 @@ impl FirewallT for Firewall {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                lan_allow_list,
                 allowed_endpoint,
                 relay_client,
             } => {
-                let cfg = &WinFwSettings::new(allow_lan);
+                let cfg = WinFwSettingsContainer::new(allow_lan, lan_allow_list);
+                let allowed_endpoint = allowed_endpoint.map(WinFwAllowedEndpointContainer::from);
 
                 self.set_connecting_state(
                     &peer_endpoint,
-                    &cfg,
+                    &cfg.as_settings(),
                     &tunnel,
-                    &WinFwAllowedEndpointContainer::from(allowed_endpoint).as_endpoint(),
+                    allowed_endpoint
+                        .as_ref()
+                        .map(|endpoint| endpoint.as_endpoint())
+                        .as_ref(),
                     &relay_client,
                 )
             }
@@ -103,20 +118,39 @@ impl FirewallT for Firewall {
                 peer_endpoint,
                 tunnel,
                 allow_lan,
+                lan_allow_list,
                 dns_servers,
                 relay_client,
+                shared_network,
             } => {
-                let cfg = &WinFwSettings::new(allow_lan);
-                self.set_connected_state(&peer_endpoint, &cfg, &tunnel, &dns_servers, &relay_client)
+                let mut cfg = WinFwSettingsContainer::new(allow_lan, lan_allow_list);
+                match shared_network {
+                    Some(network) => cfg.set_shared_network(network),
+                    None => warn_if_connection_sharing_active(),
+                }
+                self.set_connected_state(
+                    &peer_endpoint,
+                    &cfg.as_settings(),
+                    &tunnel,
+                    &dns_servers,
+                    &relay_client,
+                )
             }
             FirewallPolicy::Blocked {
                 allow_lan,
+                lan_allow_list,
                 allowed_endpoint,
+                blocked_state_policy,
             } => {
-                let cfg = &WinFwSettings::new(allow_lan);
+                let mut cfg = WinFwSettingsContainer::new(allow_lan, lan_allow_list);
+                cfg.set_permit_mdns(blocked_state_policy.allow_mdns);
+                let allowed_endpoint = allowed_endpoint.map(WinFwAllowedEndpointContainer::from);
                 self.set_blocked_state(
-                    &cfg,
-                    &WinFwAllowedEndpointContainer::from(allowed_endpoint).as_endpoint(),
+                    &cfg.as_settings(),
+                    allowed_endpoint
+                        .as_ref()
+                        .map(|endpoint| endpoint.as_endpoint())
+                        .as_ref(),
                 )
             }
         }
@@ -126,6 +160,26 @@ impl FirewallT for Firewall {
         unsafe { WinFw_Reset().into_result().map_err(Error::ResettingPolicy) }?;
         Ok(())
     }
+
+    fn policy_verifier(&mut self, _policy: &FirewallPolicy) -> Result<PolicyVerifier, Self::Error> {
+        Ok(PolicyVerifier(()))
+    }
+}
+
+/// Checks whether the filters of the active policy are still installed. WinFw records every
+/// provider, sublayer and filter that it adds for a policy, and looks each of them up in the
+/// filter engine.
+pub struct PolicyVerifier(());
+
+impl PolicyVerifier {
+    /// Returns whether all objects added for the active policy are still present.
+    pub fn verify(&self) -> Result<bool, Error> {
+        let mut intact = false;
+        unsafe { WinFw_VerifyPolicy(&mut intact) }
+            .into_result()
+            .map_err(Error::VerifyingPolicy)?;
+        Ok(intact)
+    }
 }
 
 impl Drop for Firewall {
@@ -143,12 +197,26 @@ impl Drop for Firewall {
 }
 
 impl Firewall {
+    /// Installs or removes filters that block all traffic while the machine boots, until the
+    /// daemon has started and applied a policy. These persist across reboots.
+    pub fn set_boot_time_blocking(&mut self, enable: bool) -> Result<(), Error> {
+        log::trace!(
+            "{} boot-time blocking filters",
+            if enable { "Installing" } else { "Removing" }
+        );
+        unsafe {
+            WinFw_SetBootTimeBlocking(enable)
+                .into_result()
+                .map_err(Error::SetBootTimeBlocking)
+        }
+    }
+
     fn set_connecting_state(
         &mut self,
         endpoint: &Endpoint,
-        winfw_settings: &WinFwSettings,
+        winfw_settings: &WinFwSettings<'_>,
         tunnel_metadata: &Option<TunnelMetadata>,
-        allowed_endpoint: &WinFwAllowedEndpoint<'_>,
+        allowed_endpoint: Option<&WinFwAllowedEndpoint<'_>>,
         relay_client: &Path,
     ) -> Result<(), Error> {
         log::trace!("Applying 'connecting' firewall policy");
@@ -176,7 +244,7 @@ impl Firewall {
                 &winfw_relay,
                 relay_client.as_ptr(),
                 interface_wstr_ptr,
-                allowed_endpoint,
+                allowed_endpoint.map_or(ptr::null(), |endpoint| endpoint as *const _),
             )
             .into_result()
             .map_err(Error::ApplyingConnectingPolicy)
@@ -186,14 +254,16 @@ impl Firewall {
     fn set_connected_state(
         &mut self,
         endpoint: &Endpoint,
-        winfw_settings: &WinFwSettings,
+        winfw_settings: &WinFwSettings<'_>,
         tunnel_metadata: &TunnelMetadata,
         dns_servers: &[IpAddr],
         relay_client: &Path,
     ) -> Result<(), Error> {
         log::trace!("Applying 'connected' firewall policy");
         let ip_str = widestring_ip(endpoint.address.ip());
-        let v4_gateway = widestring_ip(tunnel_metadata.ipv4_gateway.into());
+        let v4_gateway = tunnel_metadata
+            .reachable_ipv4_gateway()
+            .map(|v4_ip| widestring_ip(v4_ip.into()));
         let v6_gateway = tunnel_metadata
             .ipv6_gateway
             .map(|v6_ip| widestring_ip(v6_ip.into()));
@@ -216,6 +286,10 @@ impl Firewall {
             log::debug!("Network interface metrics were not changed");
         }
 
+        let v4_gateway_ptr = match &v4_gateway {
+            Some(v4_ip) => v4_ip.as_ptr(),
+            None => ptr::null(),
+        };
         let v6_gateway_ptr = match &v6_gateway {
             Some(v6_ip) => v6_ip.as_ptr(),
             None => ptr::null(),
@@ -233,7 +307,7 @@ impl Firewall {
                 &winfw_relay,
                 relay_client.as_ptr(),
                 tunnel_alias.as_ptr(),
-                v4_gateway.as_ptr(),
+                v4_gateway_ptr,
                 v6_gateway_ptr,
                 dns_servers.as_ptr(),
                 dns_servers.len(),
@@ -245,18 +319,35 @@ impl Firewall {
 
     fn set_blocked_state(
         &mut self,
-        winfw_settings: &WinFwSettings,
-        allowed_endpoint: &WinFwAllowedEndpoint<'_>,
+        winfw_settings: &WinFwSettings<'_>,
+        allowed_endpoint: Option<&WinFwAllowedEndpoint<'_>>,
     ) -> Result<(), Error> {
         log::trace!("Applying 'blocked' firewall policy");
         unsafe {
-            WinFw_ApplyPolicyBlocked(winfw_settings, allowed_endpoint)
-                .into_result()
-                .map_err(Error::ApplyingBlockedPolicy)
+            WinFw_ApplyPolicyBlocked(
+                winfw_settings,
+                allowed_endpoint.map_or(ptr::null(), |endpoint| endpoint as *const _),
+            )
+            .into_result()
+            .map_err(Error::ApplyingBlockedPolicy)
         }
     }
 }
 
+fn warn_if_connection_sharing_active() {
+    match connection_sharing::is_connection_sharing_active() {
+        Ok(true) => log::warn!(
+            "Internet Connection Sharing or Mobile Hotspot is active, but connection sharing is \
+             not allowed. Shared clients will not be able to use the tunnel"
+        ),
+        Ok(false) => (),
+        Err(error) => log::debug!(
+            "{}",
+            error.display_chain_with_msg("Failed to detect Internet Connection Sharing")
+        ),
+    }
+}
+
 fn widestring_ip(ip: IpAddr) -> WideCString {
     WideCString::from_str_truncate(ip.to_string())
 }
@@ -265,7 +356,9 @@ fn widestring_ip(ip: IpAddr) -> WideCString {
 mod winfw {
     use super::{widestring_ip, AllowedEndpoint, Error, WideCString};
     use crate::logging::windows::LogSink;
+    use ipnetwork::{IpNetwork, Ipv4Network};
     use libc;
+    use std::ptr;
     use talpid_types::net::TransportProtocol;
 
     pub struct WinFwAllowedEndpointContainer {
@@ -347,21 +440,94 @@ mod winfw {
         }
     }
 
-    #[repr(C)]
-    pub struct WinFwSettings {
-        permitDhcp: bool,
-        permitLan: bool,
+    pub struct WinFwSettingsContainer {
+        permit_lan: bool,
+        _lan_network_ips: Box<[WideCString]>,
+        lan_networks: Option<Box<[WinFwLanNetwork]>>,
+        shared_network: Option<(WideCString, u8)>,
+        permit_mdns: bool,
     }
 
-    impl WinFwSettings {
-        pub fn new(permit_lan: bool) -> WinFwSettings {
+    impl WinFwSettingsContainer {
+        pub fn new(permit_lan: bool, lan_allow_list: Option<Vec<IpNetwork>>) -> Self {
+            let lan_allow_list = lan_allow_list.unwrap_or_default();
+            let lan_network_ips = lan_allow_list
+                .iter()
+                .map(|network| widestring_ip(network.network()))
+                .collect::<Box<_>>();
+            let lan_networks = lan_network_ips
+                .iter()
+                .zip(lan_allow_list.iter())
+                .map(|(ip, network)| WinFwLanNetwork {
+                    ip: ip.as_ptr(),
+                    prefix: network.prefix(),
+                })
+                .collect::<Box<_>>();
+
+            WinFwSettingsContainer {
+                permit_lan,
+                _lan_network_ips: lan_network_ips,
+                lan_networks: if lan_allow_list.is_empty() {
+                    None
+                } else {
+                    Some(lan_networks)
+                },
+                shared_network: None,
+                permit_mdns: false,
+            }
+        }
+
+        pub fn set_permit_mdns(&mut self, permit_mdns: bool) {
+            self.permit_mdns = permit_mdns;
+        }
+
+        pub fn set_shared_network(&mut self, network: Ipv4Network) {
+            self.shared_network = Some((widestring_ip(network.network().into()), network.prefix()));
+        }
+
+        pub fn as_settings(&self) -> WinFwSettings<'_> {
+            let (lan_networks, num_lan_networks) = match &self.lan_networks {
+                Some(networks) => (networks.as_ptr(), networks.len() as u32),
+                None => (ptr::null(), 0),
+            };
+            let (shared_network_ip, shared_network_prefix) = match &self.shared_network {
+                Some((ip, prefix)) => (ip.as_ptr(), *prefix),
+                None => (ptr::null(), 0),
+            };
+
             WinFwSettings {
                 permitDhcp: true,
-                permitLan: permit_lan,
+                permitLan: self.permit_lan,
+                lanNetworks: lan_networks,
+                numLanNetworks: num_lan_networks,
+                sharedNetworkIp: shared_network_ip,
+                sharedNetworkPrefix: shared_network_prefix,
+                permitMdns: self.permit_mdns,
+
+                _phantom: std::marker::PhantomData,
             }
         }
     }
 
+    #[repr(C)]
+    pub struct WinFwLanNetwork {
+        ip: *const libc::wchar_t,
+        prefix: u8,
+    }
+
+    #[repr(C)]
+    pub struct WinFwSettings<'a> {
+        permitDhcp: bool,
+        permitLan: bool,
+        lanNetworks: *const WinFwLanNetwork,
+        numLanNetworks: u32,
+        sharedNetworkIp: *const libc::wchar_t,
+        sharedNetworkPrefix: u8,
+        permitMdns: bool,
+
+        _phantom: std::marker::PhantomData<&'a WinFwSettingsContainer>,
+    }
+
     #[allow(dead_code)]
     #[repr(u32)]
     #[derive(Clone, Copy)]
@@ -412,7 +578,7 @@ mod winfw {
         #[link_name = "WinFw_InitializeBlocked"]
         pub fn WinFw_InitializeBlocked(
             timeout: libc::c_uint,
-            settings: &WinFwSettings,
+            settings: &WinFwSettings<'_>,
             allowed_endpoint: *const WinFwAllowedEndpoint<'_>,
             sink: Option<LogSink>,
             sink_context: *const u8,
@@ -423,7 +589,7 @@ mod winfw {
 
         #[link_name = "WinFw_ApplyPolicyConnecting"]
         pub fn WinFw_ApplyPolicyConnecting(
-            settings: &WinFwSettings,
+            settings: &WinFwSettings<'_>,
             relay: &WinFwEndpoint,
             relayClient: *const libc::wchar_t,
             tunnelIfaceAlias: *const libc::wchar_t,
@@ -432,7 +598,7 @@ mod winfw {
 
         #[link_name = "WinFw_ApplyPolicyConnected"]
         pub fn WinFw_ApplyPolicyConnected(
-            settings: &WinFwSettings,
+            settings: &WinFwSettings<'_>,
             relay: &WinFwEndpoint,
             relayClient: *const libc::wchar_t,
             tunnelIfaceAlias: *const libc::wchar_t,
@@ -444,11 +610,17 @@ mod winfw {
 
         #[link_name = "WinFw_ApplyPolicyBlocked"]
         pub fn WinFw_ApplyPolicyBlocked(
-            settings: &WinFwSettings,
+            settings: &WinFwSettings<'_>,
             allowed_endpoint: *const WinFwAllowedEndpoint<'_>,
         ) -> WinFwPolicyStatus;
 
         #[link_name = "WinFw_Reset"]
         pub fn WinFw_Reset() -> WinFwPolicyStatus;
+
+        #[link_name = "WinFw_VerifyPolicy"]
+        pub fn WinFw_VerifyPolicy(intact: *mut bool) -> WinFwPolicyStatus;
+
+        #[link_name = "WinFw_SetBootTimeBlocking"]
+        pub fn WinFw_SetBootTimeBlocking(enable: bool) -> WinFwPolicyStatus;
     }
 }
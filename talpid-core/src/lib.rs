@@ -25,6 +25,10 @@ pub mod routing;
 
 mod offline;
 
+/// Detection of sleep and wake
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod power;
+
 /// Split tunneling
 pub mod split_tunnel;
 
@@ -49,9 +53,6 @@ pub mod dns;
 /// State machine to handle tunnel configuration.
 pub mod tunnel_state_machine;
 
-/// Future utilities
-pub mod future_retry;
-
 #[cfg(not(target_os = "android"))]
 /// Internal code for managing bundled proxy software.
 mod proxy;
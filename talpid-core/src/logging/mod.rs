@@ -1,4 +1,12 @@
-use std::{fs, io, path::Path};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Removal of identifying information from log messages.
+pub mod redact;
 
 /// Types/implementations for logging through a callback.
 #[cfg(windows)]
@@ -27,3 +35,116 @@ pub fn rotate_log(file: &Path) -> Result<(), RotateLogError> {
 
     fs::File::create(file).map(|_| ()).map_err(RotateLogError)
 }
+
+/// Limits on how large and old a log file may get before it is rotated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotationLimits {
+    /// Maximum size of a log file, in bytes.
+    pub max_size: u64,
+    /// Maximum time that a log file is written to.
+    pub max_age: Duration,
+}
+
+impl Default for RotationLimits {
+    fn default() -> Self {
+        RotationLimits {
+            max_size: 16 * 1024 * 1024,
+            max_age: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// A log file that is rotated with [`rotate_log`] once it exceeds the given [`RotationLimits`].
+/// Only one old log is kept, so the logs never take up much more than twice the size limit.
+pub struct RotatingLogFile {
+    path: PathBuf,
+    file: fs::File,
+    size: u64,
+    opened: Instant,
+    limits: RotationLimits,
+    /// Whether the most recent rotation attempt failed. The failure is only reported once.
+    rotation_failed: bool,
+}
+
+impl RotatingLogFile {
+    /// Opens the log file at `path` for appending, creating it if it does not exist.
+    pub fn new(path: &Path, limits: RotationLimits) -> io::Result<Self> {
+        let file = Self::open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingLogFile {
+            path: path.to_owned(),
+            file,
+            size,
+            opened: Instant::now(),
+            limits,
+            rotation_failed: false,
+        })
+    }
+
+    fn open(path: &Path) -> io::Result<fs::File> {
+        fs::OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn rotate_if_needed(&mut self) -> io::Result<()> {
+        if self.size < self.limits.max_size && self.opened.elapsed() < self.limits.max_age {
+            return Ok(());
+        }
+        self.file.flush()?;
+        rotate_log(&self.path).map_err(|RotateLogError(error)| error)?;
+        self.file = Self::open(&self.path)?;
+        self.size = 0;
+        self.opened = Instant::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingLogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.rotate_if_needed() {
+            Ok(()) => self.rotation_failed = false,
+            Err(error) if !self.rotation_failed => {
+                self.rotation_failed = true;
+                // Using the logger here could deadlock, since this may be the logger's output.
+                // Report the failure in the log file itself instead.
+                let message = format!("Failed to rotate log file: {}\n", error);
+                if let Ok(written) = self.file.write(message.as_bytes()) {
+                    self.size += written as u64;
+                }
+            }
+            Err(_) => (),
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rotate_on_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        let limits = RotationLimits {
+            max_size: 10,
+            max_age: Duration::from_secs(60),
+        };
+
+        let mut file = RotatingLogFile::new(&path, limits).unwrap();
+        file.write_all(b"0123456789").unwrap();
+        file.write_all(b"abc").unwrap();
+        file.flush().unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"abc");
+        assert_eq!(
+            fs::read(path.with_extension("old.log")).unwrap(),
+            b"0123456789"
+        );
+    }
+}
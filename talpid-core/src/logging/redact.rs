@@ -0,0 +1,144 @@
+//! Removes identifying information from log messages before they are written, when aggressive
+//! log redaction is enabled.
+//!
+//! Aggressive redaction removes all IP addresses, including local and private ones, relay
+//! hostnames, account numbers and WireGuard keys. Since messages are redacted before they reach
+//! the log files, the same information is missing from problem reports.
+
+use regex::Regex;
+use std::{
+    borrow::Cow,
+    net::{IpAddr, SocketAddr},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static AGGRESSIVE: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    static ref RELAY_HOSTNAME: Regex =
+        Regex::new(r"\b[a-z]{2}[0-9]?-[a-z]{3}-(?:[a-z]{2,4}-)?[0-9]{3}\b").unwrap();
+    static ref ACCOUNT_NUMBER: Regex = Regex::new(r"\b[0-9]{16}\b").unwrap();
+    static ref WIREGUARD_KEY: Regex =
+        Regex::new(r"[A-Za-z0-9+/]{42}[AEIMQUYcgkosw048]=").unwrap();
+}
+
+/// Enables or disables aggressive redaction of log messages.
+pub fn set_aggressive(enabled: bool) {
+    AGGRESSIVE.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether log messages are aggressively redacted.
+pub fn is_aggressive() -> bool {
+    AGGRESSIVE.load(Ordering::Relaxed)
+}
+
+/// Returns `message` with identifying information removed if aggressive redaction is enabled, or
+/// `message` unchanged otherwise.
+pub fn scrub(message: &str) -> Cow<'_, str> {
+    if is_aggressive() {
+        Cow::Owned(redact(message))
+    } else {
+        Cow::Borrowed(message)
+    }
+}
+
+fn redact(message: &str) -> String {
+    let message = redact_ip_addresses(message);
+    let message = RELAY_HOSTNAME.replace_all(&message, "[REDACTED RELAY]");
+    let message = ACCOUNT_NUMBER.replace_all(&message, "[REDACTED ACCOUNT NUMBER]");
+    WIREGUARD_KEY
+        .replace_all(&message, "[REDACTED KEY]")
+        .into_owned()
+}
+
+/// Replaces every word that parses as an IP address, or as an IPv4 address with a port, with
+/// `[REDACTED]`. Words consist of alphanumeric characters, dots and colons, so zone IDs, prefix
+/// lengths and brackets around IPv6 addresses are left in place.
+fn redact_ip_addresses(message: &str) -> Cow<'_, str> {
+    let is_word_char = |c: char| c.is_ascii_alphanumeric() || c == '.' || c == ':';
+
+    let mut redacted = String::new();
+    let mut copied_until = 0;
+    let mut rest = message;
+    let mut offset = 0;
+
+    while let Some(start) = rest.find(is_word_char) {
+        let end = rest[start..]
+            .find(|c: char| !is_word_char(c))
+            .map(|len| start + len)
+            .unwrap_or(rest.len());
+        let word = &rest[start..end];
+        // Leave punctuation at the end of a sentence alone
+        let trimmed_word = word.trim_end_matches(|c| c == '.' || c == ':');
+        let address = [word, trimmed_word]
+            .into_iter()
+            .find(|word| is_ip_address(word));
+
+        if let Some(address) = address {
+            redacted.push_str(&message[copied_until..offset + start]);
+            redacted.push_str("[REDACTED]");
+            copied_until = offset + start + address.len();
+        }
+
+        rest = &rest[end..];
+        offset += end;
+    }
+
+    if copied_until == 0 {
+        return Cow::Borrowed(message);
+    }
+    redacted.push_str(&message[copied_until..]);
+    Cow::Owned(redacted)
+}
+
+fn is_ip_address(word: &str) -> bool {
+    word.parse::<IpAddr>().is_ok() || word.parse::<SocketAddr>().is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::redact;
+
+    #[test]
+    fn test_redact_ip_addresses() {
+        assert_eq!(
+            redact("Connecting to 1.2.3.4:51820"),
+            "Connecting to [REDACTED]"
+        );
+        assert_eq!(redact("Resolver 127.0.0.1."), "Resolver [REDACTED].");
+        assert_eq!(
+            redact("Route 10.0.0.0/8 via fe80::1%eth0"),
+            "Route [REDACTED]/8 via [REDACTED]%eth0"
+        );
+        assert_eq!(
+            redact("Endpoint [2001:db8::1]:53"),
+            "Endpoint [[REDACTED]]:53"
+        );
+        assert_eq!(
+            redact("Gateway: 192.168.1.1, ::1"),
+            "Gateway: [REDACTED], [REDACTED]"
+        );
+    }
+
+    #[test]
+    fn test_does_not_redact_non_addresses() {
+        let message = "[2022-01-01 11:04:38.123][talpid_core::firewall][INFO] Applying 3.5 rules";
+        assert_eq!(redact(message), message);
+    }
+
+    #[test]
+    fn test_redact_identifiers() {
+        assert_eq!(
+            redact("Selected relay se-got-wg-001 (se-got-001)"),
+            "Selected relay [REDACTED RELAY] ([REDACTED RELAY])"
+        );
+        assert_eq!(
+            redact("Account 1234567890123456 has expired"),
+            "Account [REDACTED ACCOUNT NUMBER] has expired"
+        );
+        assert_eq!(
+            redact("Rotated key to bmXOC+F1FxEMF9dyiK2H5/1SUtzH0JuVo51h2wPfgyo="),
+            "Rotated key to [REDACTED KEY]"
+        );
+    }
+}
@@ -1,9 +1,14 @@
 use crate::routing::{self, RouteManagerHandle};
-use futures::{channel::mpsc::UnboundedSender, StreamExt};
+use futures::{
+    channel::mpsc::{self, UnboundedSender},
+    StreamExt,
+};
 use std::{
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     sync::Arc,
+    thread,
 };
+use talpid_dbus::network_manager::NetworkManager;
 use talpid_types::ErrorExt;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -45,10 +50,14 @@ pub async fn spawn_monitor(
 ) -> Result<MonitorHandle> {
     let mut is_offline = public_ip_unreachable(&route_manager).await?;
 
-    let mut listener = route_manager
+    let listener = route_manager
         .change_listener()
         .await
         .map_err(Error::RouteManagerError)?;
+    let mut events = futures::stream::select(
+        listener.map(|_event| ()),
+        watch_network_manager_connectivity(),
+    );
 
     let notify_tx = Arc::new(notify_tx);
     let sender = Arc::downgrade(&notify_tx);
@@ -58,7 +67,7 @@ pub async fn spawn_monitor(
     };
 
     tokio::spawn(async move {
-        while let Some(_event) = listener.next().await {
+        while let Some(()) = events.next().await {
             match sender.upgrade() {
                 Some(sender) => {
                     let new_offline_state = public_ip_unreachable(&route_manager)
@@ -83,6 +92,48 @@ pub async fn spawn_monitor(
     Ok(monitor_handle)
 }
 
+/// Returns a stream that yields whenever NetworkManager reports that the connectivity of the host
+/// has changed. This can happen without any routes changing, such as when a network stops
+/// forwarding traffic. The stream never yields if NetworkManager is not running.
+fn watch_network_manager_connectivity() -> mpsc::UnboundedReceiver<()> {
+    let (tx, rx) = mpsc::unbounded();
+    thread::spawn(move || {
+        let mut network_manager = match NetworkManager::new_connection() {
+            Ok(network_manager) => network_manager,
+            Err(error) => {
+                log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Not watching NetworkManager connectivity")
+                );
+                return;
+            }
+        };
+        if let Err(error) = network_manager.connectivity() {
+            log::debug!(
+                "{}",
+                error.display_chain_with_msg("Not watching NetworkManager connectivity")
+            );
+            return;
+        }
+
+        let should_continue_tx = tx.clone();
+        let result = network_manager.watch_connectivity(
+            move |connectivity| {
+                log::debug!("NetworkManager connectivity changed: {:?}", connectivity);
+                let _ = tx.unbounded_send(());
+            },
+            move || !should_continue_tx.is_closed(),
+        );
+        if let Err(error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to watch NetworkManager connectivity")
+            );
+        }
+    });
+    rx
+}
+
 async fn public_ip_unreachable(handle: &RouteManagerHandle) -> Result<bool> {
     Ok(handle
         .get_destination_route(PUBLIC_INTERNET_ADDRESS_V4, true)
@@ -12,6 +12,7 @@ use std::{
     time::Duration,
 };
 use talpid_types::ErrorExt;
+use talpid_windows_net::{AddressFamily, InterfaceChangeHandle, Luid};
 use winapi::um::winuser::{
     DefWindowProcW, PBT_APMRESUMEAUTOMATIC, PBT_APMSUSPEND, WM_POWERBROADCAST,
 };
@@ -22,12 +23,18 @@ pub enum Error {
     ThreadCreationError(#[error(source)] io::Error),
     #[error(display = "Failed to start connectivity monitor")]
     ConnectivityMonitorError(#[error(source)] winnet::DefaultRouteCallbackError),
+    #[error(display = "Failed to register for IP interface notifications")]
+    InterfaceMonitorError(#[error(source)] io::Error),
 }
 
+/// Monitors the default routes and the IP interfaces that they use. The machine is considered
+/// offline when there is no default route whose interface is connected to a network, or while it
+/// is suspended.
 pub struct BroadcastListener {
     window: WindowCloseHandle,
     system_state: Arc<Mutex<SystemState>>,
     _callback_handle: winnet::WinNetCallbackHandle,
+    _interface_change_handle: InterfaceChangeHandle,
     _notify_tx: Arc<UnboundedSender<bool>>,
 }
 
@@ -69,34 +76,20 @@ impl BroadcastListener {
 
         let callback_handle =
             unsafe { Self::setup_network_connectivity_listener(system_state.clone())? };
+        let interface_change_handle = Self::setup_interface_listener(system_state.clone())?;
 
         Ok(BroadcastListener {
             window,
             system_state,
             _callback_handle: callback_handle,
+            _interface_change_handle: interface_change_handle,
             _notify_tx: notify_tx,
         })
     }
 
     fn check_initial_connectivity() -> (bool, bool) {
-        let v4_connectivity = winnet::get_best_default_route(winnet::WinNetAddrFamily::IPV4)
-            .map(|route| route.is_some())
-            .unwrap_or_else(|error| {
-                log::error!(
-                    "{}",
-                    error.display_chain_with_msg("Failed to check initial IPv4 connectivity")
-                );
-                true
-            });
-        let v6_connectivity = winnet::get_best_default_route(winnet::WinNetAddrFamily::IPV6)
-            .map(|route| route.is_some())
-            .unwrap_or_else(|error| {
-                log::error!(
-                    "{}",
-                    error.display_chain_with_msg("Failed to check initial IPv6 connectivity")
-                );
-                true
-            });
+        let v4_connectivity = check_connectivity(winnet::WinNetAddrFamily::IPV4);
+        let v6_connectivity = check_connectivity(winnet::WinNetAddrFamily::IPV6);
 
         let is_online = v4_connectivity || v6_connectivity;
         log::info!("Initial connectivity: {}", is_offline_str(!is_online));
@@ -116,15 +109,40 @@ impl BroadcastListener {
         Ok(change_handle)
     }
 
+    /// A default route may remain on an interface that has lost its connection, e.g. when a
+    /// network cable is unplugged. Connectivity is therefore rechecked whenever an IP interface
+    /// changes.
+    fn setup_interface_listener(
+        system_state: Arc<Mutex<SystemState>>,
+    ) -> Result<InterfaceChangeHandle, Error> {
+        talpid_windows_net::notify_interface_changes(
+            move |_luid, family| {
+                let change = match family {
+                    AddressFamily::Ipv4 => StateChange::NetworkV4Connectivity(check_connectivity(
+                        winnet::WinNetAddrFamily::IPV4,
+                    )),
+                    AddressFamily::Ipv6 => StateChange::NetworkV6Connectivity(check_connectivity(
+                        winnet::WinNetAddrFamily::IPV6,
+                    )),
+                };
+                system_state.lock().apply_change(change);
+            },
+            None,
+        )
+        .map_err(Error::InterfaceMonitorError)
+    }
+
     unsafe extern "system" fn connectivity_callback(
         event_type: winnet::WinNetDefaultRouteChangeEventType,
         family: winnet::WinNetAddrFamily,
-        _default_route: winnet::WinNetDefaultRoute,
+        default_route: winnet::WinNetDefaultRoute,
         ctx: *mut c_void,
     ) {
         let state_lock: &mut Arc<Mutex<SystemState>> = &mut *(ctx as *mut _);
         let connectivity = match event_type {
-            winnet::WinNetDefaultRouteChangeEventType::DefaultRouteChanged => true,
+            winnet::WinNetDefaultRouteChangeEventType::DefaultRouteChanged => {
+                is_interface_connected(family, default_route.interface_luid)
+            }
             winnet::WinNetDefaultRouteChangeEventType::DefaultRouteRemoved => false,
         };
         let change = match family {
@@ -194,6 +212,49 @@ impl SystemState {
     }
 }
 
+/// Returns whether there is a default route for `family` that uses a connected interface. Errors
+/// are logged and connectivity is presumed, so that the monitor never blocks the tunnel by
+/// mistake.
+fn check_connectivity(family: winnet::WinNetAddrFamily) -> bool {
+    match winnet::get_best_default_route(family) {
+        Ok(Some(route)) => is_interface_connected(family, route.interface_luid),
+        Ok(None) => false,
+        Err(error) => {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg(&format!(
+                    "Failed to obtain the default {} route",
+                    address_family(family)
+                ))
+            );
+            true
+        }
+    }
+}
+
+fn is_interface_connected(family: winnet::WinNetAddrFamily, luid: u64) -> bool {
+    let family = address_family(family);
+    talpid_windows_net::is_interface_connected(family, Luid::from_raw(luid)).unwrap_or_else(
+        |error| {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg(&format!(
+                    "Failed to check whether the {} interface is connected",
+                    family
+                ))
+            );
+            true
+        },
+    )
+}
+
+fn address_family(family: winnet::WinNetAddrFamily) -> AddressFamily {
+    match family {
+        winnet::WinNetAddrFamily::IPV4 => AddressFamily::Ipv4,
+        winnet::WinNetAddrFamily::IPV6 => AddressFamily::Ipv6,
+    }
+}
+
 // If `offline` is true, return "Offline". Otherwise, return "Connected".
 fn is_offline_str(offline: bool) -> &'static str {
     if offline {
@@ -3,7 +3,7 @@ use rand::Rng;
 use socket2::{Domain, Protocol, Socket, Type};
 use std::{
     io::{self, Write},
-    net::{Ipv4Addr, SocketAddr},
+    net::{IpAddr, SocketAddr},
     thread,
     time::Duration,
 };
@@ -49,10 +49,13 @@ pub struct Pinger {
 }
 
 impl Pinger {
-    pub fn new(addr: Ipv4Addr, #[cfg(target_os = "linux")] interface_name: String) -> Result<Self> {
-        let addr = SocketAddr::new(addr.into(), 0);
-        let sock = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))
-            .map_err(Error::OpenError)?;
+    pub fn new(addr: IpAddr, #[cfg(target_os = "linux")] interface_name: String) -> Result<Self> {
+        let sock = match addr {
+            IpAddr::V4(_) => Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4)),
+            IpAddr::V6(_) => Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6)),
+        }
+        .map_err(Error::OpenError)?;
+        let addr = SocketAddr::new(addr, 0);
         sock.set_nonblocking(true).map_err(Error::OpenError)?;
 
         #[cfg(target_os = "linux")]
@@ -88,8 +91,13 @@ impl Pinger {
         result
     }
 
-    fn construct_icmpv4_packet(&mut self, buffer: &mut [u8]) -> Result<()> {
-        if !construct_icmpv4_packet_inner(buffer, self) {
+    fn construct_icmp_packet(&mut self, buffer: &mut [u8]) -> Result<()> {
+        let constructed = if self.addr.is_ipv4() {
+            construct_icmpv4_packet_inner(buffer, self)
+        } else {
+            construct_icmpv6_packet_inner(buffer, self)
+        };
+        if !constructed {
             return Err(Error::BufferTooSmall);
         }
         Ok(())
@@ -99,7 +107,7 @@ impl Pinger {
 impl super::Pinger for Pinger {
     fn send_icmp(&mut self) -> Result<()> {
         let mut message = [0u8; 50];
-        self.construct_icmpv4_packet(&mut message)?;
+        self.construct_icmp_packet(&mut message)?;
         self.send_ping_request(&message, self.addr)
     }
 }
@@ -131,13 +139,40 @@ fn construct_icmpv4_packet_inner(
     packet_writer: &mut impl PayloadWriter,
 ) -> bool {
     const ICMP_CHECKSUM_OFFSET: usize = 2;
+    // ICMP type - Echo (ping) request
+    if !construct_echo_request(buffer, 0x08, packet_writer) {
+        return false;
+    }
+
+    let checksum = internet_checksum::checksum(buffer);
+    (&mut buffer[ICMP_CHECKSUM_OFFSET..])
+        .write(&checksum)
+        .unwrap();
+
+    true
+}
+
+/// The checksum of ICMPv6 packets covers a pseudo-header with the source address, so it is left
+/// for the kernel to fill in.
+fn construct_icmpv6_packet_inner(
+    buffer: &mut [u8],
+    packet_writer: &mut impl PayloadWriter,
+) -> bool {
+    // ICMPv6 type - Echo request
+    construct_echo_request(buffer, 0x80, packet_writer)
+}
+
+fn construct_echo_request(
+    buffer: &mut [u8],
+    icmp_type: u8,
+    packet_writer: &mut impl PayloadWriter,
+) -> bool {
     if buffer.len() < 14 {
         return false;
     }
 
     let mut writer = &mut buffer[..];
-    // ICMP type - Echo (ping) request
-    writer.write_u8(0x08).unwrap();
+    writer.write_u8(icmp_type).unwrap();
     // Code - 0
     writer.write_u8(0x00).unwrap();
     // Checksum -filled in later
@@ -153,11 +188,6 @@ fn construct_icmpv4_packet_inner(
     // payload
     packet_writer.write_payload(writer);
 
-    let checksum = internet_checksum::checksum(buffer);
-    (&mut buffer[ICMP_CHECKSUM_OFFSET..])
-        .write(&checksum)
-        .unwrap();
-
     true
 }
 
@@ -210,6 +240,19 @@ mod test {
         assert_eq!(buffer, expected_packet);
     }
 
+    #[test]
+    fn test_icmpv6_packet() {
+        let mut buffer = [0u8; 16];
+        assert!(construct_icmpv6_packet_inner(
+            &mut buffer[..],
+            &mut TestPayload {}
+        ));
+        assert_eq!(
+            buffer[..10],
+            [0x80, 0x00, 0x00, 0x00, 0x1d, 0xcd, 0x00, 0x01, 0xb6, 0xe0]
+        );
+    }
+
     #[test]
     fn test_icmpv4_packet_too_short() {
         assert!(!construct_icmpv4_packet_inner(
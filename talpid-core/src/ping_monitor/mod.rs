@@ -18,7 +18,7 @@ pub trait Pinger: Send {
 
 /// Create a new pinger
 pub fn new_pinger(
-    addr: std::net::Ipv4Addr,
+    addr: std::net::IpAddr,
     #[cfg(not(target_os = "windows"))] interface_name: String,
 ) -> Result<Box<dyn Pinger>, Error> {
     Ok(Box::new(imp::Pinger::new(
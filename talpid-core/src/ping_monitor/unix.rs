@@ -1,4 +1,4 @@
-use std::{io, net::Ipv4Addr};
+use std::{io, net::IpAddr};
 
 /// Pinger errors
 #[derive(err_derive::Error, Debug)]
@@ -14,14 +14,14 @@ pub enum Error {
 
 /// A pinger that sends ICMP requests without waiting for responses
 pub struct Pinger {
-    addr: Ipv4Addr,
+    addr: IpAddr,
     interface_name: String,
     processes: Vec<duct::Handle>,
 }
 
 impl Pinger {
     /// Creates a new pinger that will send ICMP requests only through the specified interface
-    pub fn new(addr: Ipv4Addr, interface_name: String) -> Result<Self, Error> {
+    pub fn new(addr: IpAddr, interface_name: String) -> Result<Self, Error> {
         Ok(Self {
             processes: vec![],
             addr,
@@ -77,22 +77,27 @@ impl Drop for Pinger {
     }
 }
 
-fn ping_cmd(ip: Ipv4Addr, timeout_secs: u16, interface: &str) -> duct::Expression {
+fn ping_cmd(ip: IpAddr, timeout_secs: u16, interface: &str) -> duct::Expression {
     let mut args = vec!["-n", "-i", "1"];
 
-    let timeout_flag = if cfg!(target_os = "linux") || cfg!(target_os = "android") {
-        "-w"
-    } else {
-        "-t"
-    };
     let timeout_secs = timeout_secs.to_string();
-
-    args.extend_from_slice(&[timeout_flag, &timeout_secs]);
+    if cfg!(target_os = "linux") || cfg!(target_os = "android") {
+        args.extend_from_slice(&["-w", &timeout_secs]);
+    } else if ip.is_ipv4() {
+        args.extend_from_slice(&["-t", &timeout_secs]);
+    } else {
+        // `ping6` on macOS has no timeout option, so only a single request is sent
+        args.extend_from_slice(&["-c", "1"]);
+    }
 
     let interface_flag = if cfg!(target_os = "linux") {
         Some("-I")
     } else if cfg!(target_os = "macos") {
-        Some("-b")
+        if ip.is_ipv4() {
+            Some("-b")
+        } else {
+            Some("-B")
+        }
     } else {
         None
     };
@@ -101,10 +106,11 @@ fn ping_cmd(ip: Ipv4Addr, timeout_secs: u16, interface: &str) -> duct::Expressio
         args.extend_from_slice(&[interface_flag, interface]);
     }
 
-    let ip = ip.to_string();
-    args.push(&ip);
+    let ip_str = ip.to_string();
+    args.push(&ip_str);
 
-    duct::cmd("ping", args)
+    let program = if ip.is_ipv4() { "ping" } else { "ping6" };
+    duct::cmd(program, args)
         .stdin_null()
         .stdout_null()
         .unchecked()
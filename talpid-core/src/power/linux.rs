@@ -0,0 +1,74 @@
+use super::SLEEP_DELAY;
+use futures::channel::mpsc::UnboundedSender;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+use talpid_dbus::{dbus::arg::OwnedFd, logind::Logind};
+use talpid_types::ErrorExt;
+
+pub use talpid_dbus::logind::Error;
+
+const INHIBITOR_WHO: &str = "Mullvad VPN";
+const INHIBITOR_WHY: &str = "Closing the tunnel before sleep";
+
+pub struct MonitorHandle {
+    should_stop: Arc<AtomicBool>,
+}
+
+impl Drop for MonitorHandle {
+    fn drop(&mut self) {
+        self.should_stop.store(true, Ordering::Release);
+    }
+}
+
+/// Listens for the `PrepareForSleep` signal from systemd-logind. A delay inhibitor lock is held
+/// while the machine is awake, so that logind waits for the tunnel to be closed before sleeping.
+pub fn spawn_monitor(sender: UnboundedSender<bool>) -> Result<MonitorHandle, Error> {
+    let mut logind = Logind::new_connection()?;
+    let mut inhibitor = Some(Logind::inhibit_sleep(INHIBITOR_WHO, INHIBITOR_WHY)?);
+
+    let should_stop = Arc::new(AtomicBool::new(false));
+    let thread_should_stop = should_stop.clone();
+
+    thread::spawn(move || {
+        let callback = move |sleeping| {
+            if sleeping {
+                log::info!("Machine is preparing to sleep");
+                let _ = sender.unbounded_send(true);
+                if inhibitor.is_some() {
+                    thread::sleep(SLEEP_DELAY);
+                    // Closing the file descriptor releases the lock
+                    inhibitor = None;
+                }
+            } else {
+                log::info!("Machine has woken up from sleep");
+                inhibitor = take_inhibitor();
+                let _ = sender.unbounded_send(false);
+            }
+        };
+        let should_continue = move || !thread_should_stop.load(Ordering::Acquire);
+        if let Err(error) = logind.watch_sleep(callback, should_continue) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to listen for sleep signals")
+            );
+        }
+    });
+
+    Ok(MonitorHandle { should_stop })
+}
+
+fn take_inhibitor() -> Option<OwnedFd> {
+    Logind::inhibit_sleep(INHIBITOR_WHO, INHIBITOR_WHY)
+        .map_err(|error| {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to delay sleep until the tunnel is closed")
+            );
+        })
+        .ok()
+}
@@ -0,0 +1,115 @@
+use super::SLEEP_DELAY;
+use futures::channel::mpsc::UnboundedSender;
+use std::{ffi::c_void, ptr, sync::mpsc as sync_mpsc, thread};
+use system_configuration::core_foundation::{
+    base::TCFType,
+    runloop::{kCFRunLoopCommonModes, CFRunLoop, CFRunLoopSource, CFRunLoopSourceRef},
+};
+
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    #[error(display = "Failed to register for system power notifications")]
+    RegisterError,
+}
+
+type IoConnect = u32;
+type IoObject = u32;
+type IoNotificationPortRef = *mut c_void;
+type IoServiceInterestCallback = extern "C" fn(
+    refcon: *mut c_void,
+    service: IoObject,
+    message_type: u32,
+    message_argument: *mut c_void,
+);
+
+const IO_MESSAGE_CAN_SYSTEM_SLEEP: u32 = 0xe000_0270;
+const IO_MESSAGE_SYSTEM_WILL_SLEEP: u32 = 0xe000_0280;
+const IO_MESSAGE_SYSTEM_HAS_POWERED_ON: u32 = 0xe000_0300;
+
+#[link(name = "IOKit", kind = "framework")]
+extern "C" {
+    fn IORegisterForSystemPower(
+        refcon: *mut c_void,
+        the_port_ref: *mut IoNotificationPortRef,
+        callback: IoServiceInterestCallback,
+        notifier: *mut IoObject,
+    ) -> IoConnect;
+    fn IONotificationPortGetRunLoopSource(notify: IoNotificationPortRef) -> CFRunLoopSourceRef;
+    fn IOAllowPowerChange(kernel_port: IoConnect, notification_id: isize) -> i32;
+}
+
+pub struct MonitorHandle(());
+
+struct Context {
+    sender: UnboundedSender<bool>,
+    root_port: IoConnect,
+}
+
+/// Registers for system power notifications from IOKit on a thread running a `CFRunLoop`. The
+/// thread runs for the lifetime of the process.
+pub fn spawn_monitor(sender: UnboundedSender<bool>) -> Result<MonitorHandle, Error> {
+    let (result_tx, result_rx) = sync_mpsc::channel();
+    thread::spawn(move || {
+        // The context must outlive the registration, which is never removed.
+        let context = Box::into_raw(Box::new(Context {
+            sender,
+            root_port: 0,
+        }));
+        let mut notify_port: IoNotificationPortRef = ptr::null_mut();
+        let mut notifier: IoObject = 0;
+        let root_port = unsafe {
+            IORegisterForSystemPower(
+                context as *mut c_void,
+                &mut notify_port,
+                power_callback,
+                &mut notifier,
+            )
+        };
+        if root_port == 0 {
+            let _ = unsafe { Box::from_raw(context) };
+            let _ = result_tx.send(Err(Error::RegisterError));
+            return;
+        }
+        unsafe { (*context).root_port = root_port };
+
+        let run_loop_source = unsafe {
+            CFRunLoopSource::wrap_under_get_rule(IONotificationPortGetRunLoopSource(notify_port))
+        };
+        CFRunLoop::get_current().add_source(&run_loop_source, unsafe { kCFRunLoopCommonModes });
+        let _ = result_tx.send(Ok(()));
+
+        log::trace!("Entering power CFRunLoop");
+        CFRunLoop::run_current();
+        log::error!("Power CFRunLoop exited");
+    });
+    result_rx
+        .recv()
+        .map_err(|_| Error::RegisterError)?
+        .map(MonitorHandle)
+}
+
+extern "C" fn power_callback(
+    refcon: *mut c_void,
+    _service: IoObject,
+    message_type: u32,
+    message_argument: *mut c_void,
+) {
+    let context = unsafe { &*(refcon as *const Context) };
+    match message_type {
+        IO_MESSAGE_CAN_SYSTEM_SLEEP => unsafe {
+            IOAllowPowerChange(context.root_port, message_argument as isize);
+        },
+        IO_MESSAGE_SYSTEM_WILL_SLEEP => {
+            log::info!("Machine is preparing to sleep");
+            let _ = context.sender.unbounded_send(true);
+            // Sleep is delayed until the change is allowed, or for at most 30 seconds.
+            thread::sleep(SLEEP_DELAY);
+            unsafe { IOAllowPowerChange(context.root_port, message_argument as isize) };
+        }
+        IO_MESSAGE_SYSTEM_HAS_POWERED_ON => {
+            log::info!("Machine has woken up from sleep");
+            let _ = context.sender.unbounded_send(false);
+        }
+        _ => (),
+    }
+}
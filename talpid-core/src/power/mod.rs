@@ -0,0 +1,31 @@
+//! Detects when the machine is about to sleep and when it has woken up. The tunnel state machine
+//! treats the machine as offline while it is suspended, so that the tunnel is closed before sleep
+//! and reconnected right after waking up, instead of after the handshakes have timed out.
+//!
+//! On Windows, suspend and resume are reported by the offline monitor instead.
+
+use futures::channel::mpsc::UnboundedSender;
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+#[path = "linux.rs"]
+mod imp;
+
+#[cfg(target_os = "macos")]
+#[path = "macos.rs"]
+mod imp;
+
+pub use self::imp::Error;
+
+/// Time that the tunnel state machine is given to close the tunnel before the machine is allowed
+/// to sleep.
+const SLEEP_DELAY: Duration = Duration::from_secs(2);
+
+/// Stops the monitor when dropped.
+pub struct MonitorHandle(imp::MonitorHandle);
+
+/// Starts listening for power events. `true` is sent to `sender` when the machine is about to
+/// sleep, and `false` when it has woken up.
+pub fn spawn_monitor(sender: UnboundedSender<bool>) -> Result<MonitorHandle, Error> {
+    imp::spawn_monitor(sender).map(MonitorHandle)
+}
@@ -2,13 +2,18 @@ use duct;
 
 use super::stoppable_process::StoppableProcess;
 use atty;
-use os_pipe::{pipe, PipeWriter};
+use os_pipe::{pipe, PipeReader, PipeWriter};
 use parking_lot::Mutex;
 use shell_escape;
 use std::{
     ffi::{OsStr, OsString},
-    fmt, io,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
     path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
 };
 use talpid_types::{net, ErrorExt};
 
@@ -85,6 +90,7 @@ pub struct OpenVpnCommand {
     iproute_bin: Option<OsString>,
     plugin: Option<(PathBuf, Vec<String>)>,
     log: Option<PathBuf>,
+    status: Option<PathBuf>,
     tunnel_options: net::openvpn::TunnelOptions,
     proxy_settings: Option<net::openvpn::ProxySettings>,
     #[cfg(windows)]
@@ -109,6 +115,7 @@ impl OpenVpnCommand {
             iproute_bin: None,
             plugin: None,
             log: None,
+            status: None,
             tunnel_options: net::openvpn::TunnelOptions::default(),
             proxy_settings: None,
             #[cfg(windows)]
@@ -169,12 +176,23 @@ impl OpenVpnCommand {
         self
     }
 
-    /// Sets a log file path.
+    /// Sets a log file path. The output of OpenVPN is written to this file.
     pub fn log(&mut self, path: impl AsRef<Path>) -> &mut Self {
         self.log = Some(path.as_ref().to_path_buf());
         self
     }
 
+    /// Returns the log file path, if one is set.
+    pub fn log_path(&self) -> Option<&Path> {
+        self.log.as_deref()
+    }
+
+    /// Sets a status file path. OpenVPN writes its traffic counters to this file every second.
+    pub fn status(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.status = Some(path.as_ref().to_path_buf());
+        self
+    }
+
     /// Sets extra options
     pub fn tunnel_options(&mut self, tunnel_options: &net::openvpn::TunnelOptions) -> &mut Self {
         self.tunnel_options = tunnel_options.clone();
@@ -252,9 +270,10 @@ impl OpenVpnCommand {
             args.extend(plugin_args.iter().map(OsString::from));
         }
 
-        if let Some(ref path) = self.log {
-            args.push(OsString::from("--log"));
-            args.push(OsString::from(path))
+        if let Some(ref status) = self.status {
+            args.push(OsString::from("--status"));
+            args.push(OsString::from(status.as_os_str()));
+            args.push(OsString::from("1"));
         }
 
         if let Some(mssfix) = self.tunnel_options.mssfix {
@@ -401,34 +420,168 @@ impl fmt::Display for OpenVpnCommand {
     }
 }
 
+/// How long to wait for the remaining output of OpenVPN after it has exited.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Failure reported by OpenVPN in its output.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputError {
+    /// The server rejected the credentials. Contains the reason given by the server, if any.
+    AuthFailed(Option<String>),
+    /// The TLS handshake with the server failed.
+    TlsError(String),
+    /// The proxy that OpenVPN connects through failed.
+    ProxyError(String),
+}
+
+impl OutputError {
+    /// Classifies a line of OpenVPN output. Returns `None` if the line does not report a known
+    /// failure.
+    pub fn from_line(line: &str) -> Option<Self> {
+        if let Some(index) = line.find("AUTH_FAILED") {
+            let reason = line[index + "AUTH_FAILED".len()..]
+                .strip_prefix(',')
+                .map(str::trim)
+                .filter(|reason| !reason.is_empty())
+                .map(str::to_owned);
+            return Some(OutputError::AuthFailed(reason));
+        }
+
+        let message = Self::strip_timestamp(line);
+        if line.contains("TLS Error") || line.contains("TLS_ERROR") || line.contains("VERIFY ERROR")
+        {
+            return Some(OutputError::TlsError(message.to_owned()));
+        }
+
+        let lowercase_line = line.to_lowercase();
+        let is_proxy_line =
+            lowercase_line.contains("http proxy") || lowercase_line.contains("socks");
+        let is_failure = ["error", "fail", "bad status", "timeout"]
+            .iter()
+            .any(|keyword| lowercase_line.contains(keyword));
+        if is_proxy_line && is_failure {
+            return Some(OutputError::ProxyError(message.to_owned()));
+        }
+
+        None
+    }
+
+    /// Removes the timestamp that OpenVPN prefixes every line with.
+    fn strip_timestamp(line: &str) -> &str {
+        match line.char_indices().nth(24) {
+            Some((index, ' '))
+                if line
+                    .chars()
+                    .next()
+                    .map(|c| c.is_ascii_alphabetic())
+                    .unwrap_or(false) =>
+            {
+                &line[index + 1..]
+            }
+            _ => line,
+        }
+    }
+}
+
 /// Proc handle for an openvpn process
 pub struct OpenVpnProcHandle {
     /// Duct handle
     pub inner: duct::Handle,
     /// Standard input handle
     pub stdin: Mutex<Option<PipeWriter>>,
+    /// The most recent failure reported in the output
+    output_error: Arc<Mutex<Option<OutputError>>>,
+    /// Signalled when all output has been read
+    output_done_rx: Mutex<mpsc::Receiver<()>>,
 }
 
 /// Impl for proc handle
 impl OpenVpnProcHandle {
-    /// Constructor for a new openvpn proc handle
-    pub fn new(mut cmd: duct::Expression) -> io::Result<Self> {
-        if !atty::is(atty::Stream::Stdout) {
-            cmd = cmd.stdout_null();
-        }
-
-        if !atty::is(atty::Stream::Stderr) {
-            cmd = cmd.stderr_null();
-        }
+    /// Constructor for a new openvpn proc handle. The output of the process is written to the file
+    /// at `log_path` if one is given, and to the standard output of this process otherwise, if it
+    /// is a terminal.
+    pub fn new(cmd: duct::Expression, log_path: Option<&Path>) -> io::Result<Self> {
+        let log_file = log_path.map(File::create).transpose()?;
 
         let (reader, writer) = pipe()?;
-        let proc_handle = cmd.stdin_file(reader).start()?;
+        let (output_reader, output_writer) = pipe()?;
+        let proc_handle = cmd
+            .stdin_file(reader)
+            .stderr_to_stdout()
+            .stdout_file(output_writer)
+            .start()?;
+
+        let output_error = Arc::new(Mutex::new(None));
+        let (output_done_tx, output_done_rx) = mpsc::channel();
+        let thread_output_error = output_error.clone();
+        thread::spawn(move || {
+            Self::process_output(output_reader, log_file, thread_output_error);
+            let _ = output_done_tx.send(());
+        });
 
         Ok(Self {
             inner: proc_handle,
             stdin: Mutex::new(Some(writer)),
+            output_error,
+            output_done_rx: Mutex::new(output_done_rx),
         })
     }
+
+    /// Reads the output of OpenVPN line by line until the process exits, forwarding it to the log
+    /// and keeping track of the failures that it reports.
+    fn process_output(
+        output: PipeReader,
+        mut log_file: Option<File>,
+        output_error: Arc<Mutex<Option<OutputError>>>,
+    ) {
+        let mut stdout = if log_file.is_none() && atty::is(atty::Stream::Stdout) {
+            Some(io::stdout())
+        } else {
+            None
+        };
+        let mut output = BufReader::new(output);
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+            match output.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => (),
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read OpenVPN output")
+                    );
+                    break;
+                }
+            }
+
+            let sink: Option<&mut dyn Write> = match (log_file.as_mut(), stdout.as_mut()) {
+                (Some(file), _) => Some(file),
+                (None, Some(stdout)) => Some(stdout),
+                (None, None) => None,
+            };
+            if let Some(sink) = sink {
+                let _ = sink.write_all(&line).and_then(|_| sink.flush());
+            }
+
+            let text = String::from_utf8_lossy(&line);
+            if let Some(error) = OutputError::from_line(text.trim_end()) {
+                log::debug!("OpenVPN reported a failure: {:?}", error);
+                *output_error.lock() = Some(error);
+            }
+        }
+    }
+
+    /// Returns the most recent failure reported in the output of OpenVPN. This should be called
+    /// after the process has exited, and waits briefly for the remaining output to be read.
+    pub fn output_error(&self) -> Option<OutputError> {
+        let _ = self
+            .output_done_rx
+            .lock()
+            .recv_timeout(OUTPUT_DRAIN_TIMEOUT);
+        self.output_error.lock().clone()
+    }
 }
 
 impl StoppableProcess for OpenVpnProcHandle {
@@ -478,7 +631,7 @@ impl Drop for OpenVpnProcHandle {
 
 #[cfg(test)]
 mod tests {
-    use super::OpenVpnCommand;
+    use super::{OpenVpnCommand, OutputError};
     use std::{ffi::OsString, net::Ipv4Addr};
     use talpid_types::net::{Endpoint, TransportProtocol};
 
@@ -507,4 +660,57 @@ mod tests {
         assert!(testee_args.contains(&OsString::from("123")));
         assert!(testee_args.contains(&OsString::from("cde")));
     }
+
+    #[test]
+    fn passes_no_log_argument() {
+        let testee_args = OpenVpnCommand::new("").log("./a/log").get_arguments();
+        assert!(!testee_args.contains(&OsString::from("--log")));
+    }
+
+    #[test]
+    fn passes_status_argument() {
+        let testee_args = OpenVpnCommand::new("").status("./a/status").get_arguments();
+        let position = testee_args
+            .iter()
+            .position(|arg| arg == "--status")
+            .expect("missing --status argument");
+        assert_eq!(
+            &testee_args[position + 1..position + 3],
+            &[OsString::from("./a/status"), OsString::from("1")]
+        );
+    }
+
+    #[test]
+    fn classifies_output() {
+        assert_eq!(
+            OutputError::from_line(
+                "Thu Jan  6 12:00:00 2022 AUTH: Received control message: AUTH_FAILED,Expired"
+            ),
+            Some(OutputError::AuthFailed(Some("Expired".to_owned())))
+        );
+        assert_eq!(
+            OutputError::from_line(
+                "Thu Jan  6 12:00:00 2022 AUTH: Received control message: AUTH_FAILED"
+            ),
+            Some(OutputError::AuthFailed(None))
+        );
+        assert_eq!(
+            OutputError::from_line("Thu Jan  6 12:00:00 2022 TLS Error: TLS handshake failed"),
+            Some(OutputError::TlsError(
+                "TLS Error: TLS handshake failed".to_owned()
+            ))
+        );
+        assert_eq!(
+            OutputError::from_line(
+                "Thu Jan  6 12:00:00 2022 recv_socks_reply: TCP port read timeout expired"
+            ),
+            Some(OutputError::ProxyError(
+                "recv_socks_reply: TCP port read timeout expired".to_owned()
+            ))
+        );
+        assert_eq!(
+            OutputError::from_line("Thu Jan  6 12:00:00 2022 Initialization Sequence Completed"),
+            None
+        );
+    }
 }
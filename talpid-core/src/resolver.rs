@@ -1,6 +1,6 @@
 use std::{
     io,
-    net::{Ipv4Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     str::FromStr,
     sync::{Arc, Weak},
 };
@@ -24,7 +24,11 @@ use trust_dns_server::{
         op::{header::MessageType, op_code::OpCode, Header},
         rr::{domain::Name, record_data::RData, Record},
     },
-    resolver::lookup::Lookup,
+    resolver::{
+        config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+        lookup::Lookup,
+        TokioAsyncResolver,
+    },
     server::{Request, RequestHandler, ResponseHandler, ResponseInfo},
     ServerFuture,
 };
@@ -36,6 +40,23 @@ const TTL_SECONDS: u32 = 3;
 /// belongs to the documentation range so should never be reachable.
 const RESOLVED_ADDR: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 1);
 
+/// Resolves `host` using `servers` instead of the resolvers configured by the system. This lets
+/// hosts be looked up while DNS is blocked, given a firewall exception for `servers`.
+pub async fn lookup_with_servers(host: &str, servers: &[IpAddr]) -> io::Result<Vec<IpAddr>> {
+    let config = ResolverConfig::from_parts(
+        None,
+        vec![],
+        NameServerConfigGroup::from_ips_clear(servers, 53, true),
+    );
+    let resolver = TokioAsyncResolver::tokio(config, ResolverOpts::default())
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+    let lookup = resolver
+        .lookup_ip(host)
+        .await
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+    Ok(lookup.iter().collect())
+}
+
 /// Starts a resolver. Returns a cloneable handle, which can activate, deactivate and shut down the
 /// resolver. When all instances of a handle are dropped, the server will stop.
 pub(crate) async fn start_resolver() -> Result<ResolverHandle, Error> {
@@ -272,10 +293,6 @@ impl LookupObject for ForwardLookup {
 mod test {
     use super::*;
     use std::{mem, net::UdpSocket, thread, time::Duration};
-    use trust_dns_server::resolver::{
-        config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
-        TokioAsyncResolver,
-    };
 
     async fn start_resolver() -> ResolverHandle {
         super::start_resolver().await.unwrap()
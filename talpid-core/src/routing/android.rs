@@ -31,6 +31,9 @@ impl RouteManagerImpl {
                     let _ = tx.send(Ok(()));
                 }
                 RouteManagerCommand::ClearRoutes => (),
+                RouteManagerCommand::GetAppliedRoutes(tx) => {
+                    let _ = tx.send(vec![]);
+                }
             }
         }
         Ok(())
@@ -1,11 +1,12 @@
 use crate::routing::{
     imp::{CallbackMessage, RouteManagerCommand},
-    NetNode, Node, RequiredRoute, Route,
+    NetNode, Node, RequiredRoute, RestoreBackoff, Route,
 };
 use std::{
     collections::{BTreeMap, HashSet},
     io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::{Duration, Instant},
 };
 use talpid_types::ErrorExt;
 
@@ -31,13 +32,23 @@ use netlink_packet_route::{
     NetlinkMessage, NetlinkPayload, RtnlMessage,
 };
 use rtnetlink::{
-    constants::{RTMGRP_IPV4_ROUTE, RTMGRP_IPV6_ROUTE, RTMGRP_LINK, RTMGRP_NOTIFY},
+    constants::{
+        RTMGRP_IPV4_ROUTE, RTMGRP_IPV4_RULE, RTMGRP_IPV6_ROUTE, RTMGRP_LINK, RTMGRP_NOTIFY,
+    },
     sys::SocketAddr,
     Handle, IpVersion,
 };
 
 use libc::{AF_INET, AF_INET6};
 
+/// Multicast group for IPv6 routing rule changes. Unlike the IPv4 group, this has no legacy
+/// `RTMGRP_*` constant, so it is derived from `RTNLGRP_IPV6_RULE`.
+const RTMGRP_IPV6_RULE: u32 = 1 << (19 - 1);
+
+/// How often to check for removed or overridden routes that were held back and may now be added
+/// back.
+const RESTORE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 lazy_static! {
     static ref SUPPRESS_RULE_V4: RuleMessage = RuleMessage {
         header: RuleHeader {
@@ -129,6 +140,12 @@ pub struct RouteManagerImpl {
 
     // currently added routes
     added_routes: HashSet<Route>,
+
+    // limits how often removed or overridden routes are added back
+    restore_backoff: RestoreBackoff,
+
+    // whether the routing rules are applied, and if so, whether the IPv6 rules are included
+    routing_rules: Option<bool>,
 }
 
 impl RouteManagerImpl {
@@ -136,7 +153,12 @@ impl RouteManagerImpl {
         let (mut connection, handle, messages) =
             rtnetlink::new_connection().map_err(Error::ConnectError)?;
 
-        let mgroup_flags = RTMGRP_IPV4_ROUTE | RTMGRP_IPV6_ROUTE | RTMGRP_LINK | RTMGRP_NOTIFY;
+        let mgroup_flags = RTMGRP_IPV4_ROUTE
+            | RTMGRP_IPV6_ROUTE
+            | RTMGRP_IPV4_RULE
+            | RTMGRP_IPV6_RULE
+            | RTMGRP_LINK
+            | RTMGRP_NOTIFY;
         let addr = SocketAddr::new(0, mgroup_flags);
         connection
             .socket_mut()
@@ -153,6 +175,8 @@ impl RouteManagerImpl {
             iface_map,
             listeners: vec![],
             added_routes: HashSet::new(),
+            restore_backoff: RestoreBackoff::default(),
+            routing_rules: None,
         };
 
         monitor.clear_routing_rules().await?;
@@ -162,68 +186,92 @@ impl RouteManagerImpl {
     }
 
     async fn create_routing_rules(&mut self, enable_ipv6: bool) -> Result<()> {
-        use netlink_packet_route::constants::*;
-
         self.clear_routing_rules().await?;
 
-        for rule in ALL_RULES
+        for rule in Self::expected_rules(enable_ipv6) {
+            self.add_rule(rule.clone()).await?;
+        }
+        self.routing_rules = Some(enable_ipv6);
+        Ok(())
+    }
+
+    /// Adds any routing rules that are expected to be applied but have been removed by something
+    /// else, such as a network manager that removes rules it does not know about.
+    async fn restore_routing_rules(&mut self) -> Result<()> {
+        let enable_ipv6 = match self.routing_rules {
+            Some(enable_ipv6) => enable_ipv6,
+            None => return Ok(()),
+        };
+        let rules = self.get_rules().await?;
+        for rule in Self::expected_rules(enable_ipv6) {
+            if !rules
+                .iter()
+                .any(|found_rule| Self::rule_matches(found_rule, rule))
+            {
+                log::warn!("Routing rule was removed. Adding it back: {:?}", rule);
+                self.add_rule(rule.clone()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn expected_rules(enable_ipv6: bool) -> impl Iterator<Item = &'static RuleMessage> {
+        ALL_RULES
             .iter()
-            .filter(|rule| rule.header.family as u16 == AF_INET || enable_ipv6)
-        {
-            let mut req = NetlinkMessage::from(RtnlMessage::NewRule((*rule).clone()));
-            req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE;
+            .copied()
+            .filter(move |rule| rule.header.family == AF_INET as u8 || enable_ipv6)
+    }
 
-            let mut response = self.handle.request(req).map_err(Error::NetlinkError)?;
+    async fn add_rule(&mut self, rule: RuleMessage) -> Result<()> {
+        use netlink_packet_route::constants::*;
 
-            while let Some(message) = response.next().await {
-                if let NetlinkPayload::Error(error) = message.payload {
-                    return Err(Error::NetlinkError(rtnetlink::Error::NetlinkError(error)));
-                }
+        let mut req = NetlinkMessage::from(RtnlMessage::NewRule(rule));
+        req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE;
+
+        let mut response = self.handle.request(req).map_err(Error::NetlinkError)?;
+
+        while let Some(message) = response.next().await {
+            if let NetlinkPayload::Error(error) = message.payload {
+                return Err(Error::NetlinkError(rtnetlink::Error::NetlinkError(error)));
             }
         }
         Ok(())
     }
 
     async fn clear_routing_rules(&mut self) -> Result<()> {
+        self.routing_rules = None;
+
         let rules = self.get_rules().await?;
         for rule in &*ALL_RULES {
-            let mut matching_rule = None;
-
-            // `RTM_DELRULE` is way too picky about which rules are considered the same.
-            // So iterate over all rules and ignore irrelevant attributes.
-            for found_rule in &rules {
-                // Match header
-                if found_rule.header.family != rule.header.family {
-                    continue;
-                }
-                if found_rule.header.action != rule.header.action {
-                    continue;
-                }
-                if (found_rule.header.flags & rule.header.flags) != rule.header.flags {
-                    continue;
-                }
-                // Match NLAs
-                let mut contains_nlas = true;
-                for nla in &rule.nlas {
-                    if !found_rule.nlas.contains(nla) {
-                        contains_nlas = false;
-                        break;
-                    }
-                }
-                if contains_nlas {
-                    log::trace!("Existing routing rule matched: {:?}", found_rule);
-                    matching_rule = Some(found_rule);
-                    break;
-                }
-            }
+            let matching_rule = rules
+                .iter()
+                .find(|found_rule| Self::rule_matches(found_rule, rule));
 
             if let Some(rule) = matching_rule {
-                self.delete_rule_if_exists((*rule).clone()).await?;
+                log::trace!("Existing routing rule matched: {:?}", rule);
+                self.delete_rule_if_exists(rule.clone()).await?;
             }
         }
         Ok(())
     }
 
+    /// Returns whether `found_rule` is equivalent to `rule`. `RTM_DELRULE` is way too picky about
+    /// which rules are considered the same, so irrelevant attributes are ignored.
+    fn rule_matches(found_rule: &RuleMessage, rule: &RuleMessage) -> bool {
+        // Match header
+        if found_rule.header.family != rule.header.family {
+            return false;
+        }
+        if found_rule.header.action != rule.header.action {
+            return false;
+        }
+        if (found_rule.header.flags & rule.header.flags) != rule.header.flags {
+            return false;
+        }
+        // Match NLAs
+        rule.nlas.iter().all(|nla| found_rule.nlas.contains(nla))
+    }
+
     async fn get_rules(&mut self) -> Result<Vec<RuleMessage>> {
         use netlink_packet_route::constants::*;
 
@@ -306,12 +354,96 @@ impl RouteManagerImpl {
             .map(|(idx, _name)| *idx)
     }
 
-    fn process_deleted_route(&mut self, route: &Route) -> Result<()> {
-        self.added_routes.remove(route);
+    /// Adds back a route that was applied by the route manager but has since been removed by
+    /// something else. Routes whose device has been removed are forgotten instead.
+    async fn process_deleted_route(&mut self, route: &Route) -> Result<()> {
+        // The metric is ignored since the kernel may assign one if none was given.
+        let added_route = self
+            .added_routes
+            .iter()
+            .find(|added_route| {
+                added_route.prefix == route.prefix
+                    && added_route.node.matches(&route.node)
+                    && added_route.table_id == route.table_id
+            })
+            .cloned();
+        let added_route = match added_route {
+            Some(added_route) => added_route,
+            None => return Ok(()),
+        };
+        self.added_routes.remove(&added_route);
+
+        if !self.device_exists(&added_route) {
+            log::debug!("Route removed along with its device: {}", added_route);
+            self.restore_backoff.forget(&added_route);
+            return Ok(());
+        }
+
+        log::warn!("Route was removed: {}", added_route);
+        self.restore_route(added_route).await;
         Ok(())
     }
 
+    /// Applies a route again if a route added by something else takes precedence over it.
+    async fn process_new_route(&mut self, route: &Route) {
+        let overridden_route = self
+            .added_routes
+            .iter()
+            .find(|added_route| {
+                added_route.prefix == route.prefix
+                    && added_route.table_id == route.table_id
+                    && !added_route.node.matches(&route.node)
+                    && effective_metric(route) <= effective_metric(added_route)
+            })
+            .cloned();
+        if let Some(overridden_route) = overridden_route {
+            log::warn!("Route {} was overridden by {}", overridden_route, route);
+            self.restore_route(overridden_route).await;
+        }
+    }
+
+    fn device_exists(&self, route: &Route) -> bool {
+        route
+            .node
+            .get_device()
+            .map(|device| self.find_iface_idx(device).is_some())
+            .unwrap_or(true)
+    }
+
+    /// Adds back a route that was removed or overridden, unless that was done too recently. Such
+    /// routes are added back later by `restore_due_routes`.
+    async fn restore_route(&mut self, route: Route) {
+        if !self.restore_backoff.try_restore(&route, Instant::now()) {
+            log::debug!("Waiting before adding back route {}", route);
+            return;
+        }
+
+        log::info!("Adding back route {}", route);
+        let result = if self.added_routes.contains(&route) {
+            self.add_route_direct(route.clone()).await
+        } else {
+            self.add_route(route.clone()).await
+        };
+        if let Err(error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg(&format!("Failed to add back route {}", route))
+            );
+        }
+    }
+
+    async fn restore_due_routes(&mut self) {
+        for route in self.restore_backoff.due_routes(Instant::now()) {
+            if !self.added_routes.contains(&route) && !self.device_exists(&route) {
+                self.restore_backoff.forget(&route);
+                continue;
+            }
+            self.restore_route(route).await;
+        }
+    }
+
     async fn cleanup_routes(&mut self) {
+        self.restore_backoff.clear();
         for route in self.added_routes.drain().collect::<Vec<_>>().iter() {
             if let Err(e) = self.delete_route_if_exists(&route).await {
                 log::error!("Failed to remove route: {}: {}", route, e);
@@ -324,6 +456,7 @@ impl RouteManagerImpl {
         manage_rx: UnboundedReceiver<RouteManagerCommand>,
     ) -> Result<()> {
         let mut manage_rx = manage_rx.fuse();
+        let mut restore_interval = tokio::time::interval(RESTORE_CHECK_INTERVAL);
         loop {
             futures::select! {
                 command = manage_rx.select_next_some() => {
@@ -334,6 +467,9 @@ impl RouteManagerImpl {
                         log::error!("{}", error.display_chain_with_msg("Failed to process netlink message"));
                     }
                 }
+                _ = restore_interval.tick().fuse() => {
+                    self.restore_due_routes().await;
+                }
             };
         }
     }
@@ -367,6 +503,10 @@ impl RouteManagerImpl {
                 log::debug!("Clearing routes");
                 self.cleanup_routes().await;
             }
+            RouteManagerCommand::GetAppliedRoutes(result_tx) => {
+                let routes = self.added_routes.iter().map(Route::debug_info).collect();
+                let _ = result_tx.send(routes);
+            }
         }
         Ok(())
     }
@@ -385,15 +525,24 @@ impl RouteManagerImpl {
             }
             NetlinkPayload::InnerMessage(RtnlMessage::NewRoute(new_route)) => {
                 if let Some(addition) = self.parse_route_message(new_route)? {
+                    self.process_new_route(&addition).await;
                     self.notify_change_listeners(CallbackMessage::NewRoute(addition));
                 }
             }
             NetlinkPayload::InnerMessage(RtnlMessage::DelRoute(old_route)) => {
                 if let Some(deletion) = self.parse_route_message(old_route)? {
-                    self.process_deleted_route(&deletion)?;
+                    self.process_deleted_route(&deletion).await?;
                     self.notify_change_listeners(CallbackMessage::DelRoute(deletion));
                 }
             }
+            NetlinkPayload::InnerMessage(RtnlMessage::DelRule(old_rule)) => {
+                if ALL_RULES
+                    .iter()
+                    .any(|rule| Self::rule_matches(&old_rule, rule))
+                {
+                    self.restore_routing_rules().await?;
+                }
+            }
             _ => (),
         };
         Ok(())
@@ -758,6 +907,16 @@ fn ip_to_bytes(addr: IpAddr) -> Vec<u8> {
     }
 }
 
+/// Returns the metric of a route, or the metric the kernel assigns to routes without one.
+fn effective_metric(route: &Route) -> u32 {
+    const DEFAULT_IPV6_METRIC: u32 = 1024;
+    route.metric.unwrap_or(if route.prefix.is_ipv4() {
+        0
+    } else {
+        DEFAULT_IPV6_METRIC
+    })
+}
+
 fn compat_table_id(id: u32) -> u8 {
     // RT_TABLE_COMPAT must be combined with nla Table(id)
     if id > 255 {
@@ -840,4 +999,16 @@ mod test {
         });
         std::mem::drop(manager);
     }
+
+    #[test]
+    fn test_effective_metric() {
+        let node = Node::device("eth0".to_owned());
+        let mut v4_route = Route::new(node.clone(), "192.0.2.0/24".parse().unwrap());
+        let v6_route = Route::new(node, "2001:db8::/32".parse().unwrap());
+
+        assert_eq!(effective_metric(&v4_route), 0);
+        assert_eq!(effective_metric(&v6_route), 1024);
+        v4_route.metric = Some(100);
+        assert_eq!(effective_metric(&v4_route), 100);
+    }
 }
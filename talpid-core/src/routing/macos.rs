@@ -1,8 +1,10 @@
-use crate::routing::{imp::RouteManagerCommand, NetNode, Node, RequiredRoute, Route};
+use crate::routing::{
+    imp::RouteManagerCommand, NetNode, Node, RequiredRoute, RestoreBackoff, Route,
+};
 
 use futures::{
     channel::mpsc,
-    future,
+    future::{self, FutureExt},
     stream::{FusedStream, Stream, StreamExt, TryStreamExt},
 };
 use ipnetwork::IpNetwork;
@@ -11,6 +13,7 @@ use std::{
     io,
     net::IpAddr,
     process::{ExitStatus, Stdio},
+    time::{Duration, Instant},
 };
 use talpid_types::net::IpVersion;
 use tokio::{io::AsyncBufReadExt, process::Command};
@@ -18,6 +21,9 @@ use tokio_stream::wrappers::LinesStream;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// How often to check for removed routes that were held back and may now be added back.
+const RESTORE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Errors that can happen in the macOS routing integration.
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
@@ -53,10 +59,13 @@ pub enum Error {
 /// operation, the route manager will add all the required routes during startup and will start
 /// waiting for changes to the route table.  If any change is detected, it will stop listening for
 /// new changes, obtain new default routes and reapply routes that should be routed through the
-/// default nodes. Once the routes are reapplied, the route table changes are monitored again.
+/// default nodes. Applied routes that have been removed are also added back, unless another
+/// program has replaced them. Once the routes are reapplied, the route table changes are monitored
+/// again.
 pub struct RouteManagerImpl {
     default_destinations: HashSet<IpNetwork>,
     applied_routes: HashSet<Route>,
+    restore_backoff: RestoreBackoff,
     v4_gateway: Option<Node>,
     v6_gateway: Option<Node>,
     connectivity_change:
@@ -68,11 +77,12 @@ impl RouteManagerImpl {
         let v4_gateway = Self::get_default_node(IpVersion::V4).await?;
         let v6_gateway = Self::get_default_node(IpVersion::V6).await?;
 
-        let monitor = listen_for_default_route_changes()?;
+        let monitor = listen_for_route_changes(false)?;
 
         let mut manager = Self {
             default_destinations: HashSet::new(),
             applied_routes: HashSet::new(),
+            restore_backoff: RestoreBackoff::default(),
             connectivity_change: Some(Box::new(monitor.fuse())),
             v4_gateway,
             v6_gateway,
@@ -86,6 +96,7 @@ impl RouteManagerImpl {
     pub(crate) async fn run(mut self, manage_rx: mpsc::UnboundedReceiver<RouteManagerCommand>) {
         let mut manage_rx = manage_rx.fuse();
         let mut connectivity_change = self.connectivity_change.take().unwrap();
+        let mut restore_interval = tokio::time::interval(RESTORE_CHECK_INTERVAL);
 
         loop {
             futures::select! {
@@ -104,6 +115,11 @@ impl RouteManagerImpl {
                         Some(RouteManagerCommand::ClearRoutes) => {
                             self.cleanup_routes().await;
                         },
+                        Some(RouteManagerCommand::GetAppliedRoutes(result_tx)) => {
+                            let routes =
+                                self.applied_routes.iter().map(Route::debug_info).collect();
+                            let _ = result_tx.send(routes);
+                        },
                         None => {
                             break;
                         }
@@ -123,6 +139,14 @@ impl RouteManagerImpl {
                         self.v6_gateway = v6_gateway;
                         self.apply_new_default_route(&self.v6_gateway, false).await;
                     }
+
+                    self.restore_removed_routes().await;
+                },
+
+                _ = restore_interval.tick().fuse() => {
+                    if !self.restore_backoff.due_routes(Instant::now()).is_empty() {
+                        self.restore_removed_routes().await;
+                    }
                 },
                 complete => {
                     break;
@@ -162,7 +186,7 @@ impl RouteManagerImpl {
             };
         }
 
-        self.default_destinations = default_destinations;
+        self.default_destinations.extend(default_destinations);
 
         Ok(())
     }
@@ -184,6 +208,19 @@ impl RouteManagerImpl {
         Ok(Self::parse_route(&output))
     }
 
+    // Retrieves the node that's currently used to reach the given destination
+    pub(crate) async fn get_destination_node(destination: IpAddr) -> Result<Option<Node>> {
+        let mut cmd = Command::new("route");
+        cmd.arg("-n").arg("get").arg(destination.to_string());
+
+        let output = cmd.output().await.map_err(Error::FailedToRunRoute)?;
+        let output = String::from_utf8(output.stdout).map_err(|e| {
+            log::error!("Failed to parse utf-8 bytes from output of route: {}", e);
+            Error::BadOutputFromNetstat
+        })?;
+        Ok(Self::parse_route(&output))
+    }
+
     fn parse_route(route_output: &str) -> Option<Node> {
         let mut address: Option<IpAddr> = None;
         let mut device = None;
@@ -250,12 +287,14 @@ impl RouteManagerImpl {
         cmd.status().await.map_err(Error::FailedToAddRoute)
     }
 
-    async fn cleanup_routes(&self) -> () {
-        let destinations_to_remove = self
-            .applied_routes
+    async fn cleanup_routes(&mut self) -> () {
+        self.restore_backoff.clear();
+        let applied_routes = std::mem::take(&mut self.applied_routes);
+        let default_destinations = std::mem::take(&mut self.default_destinations);
+        let destinations_to_remove = applied_routes
             .iter()
             .map(|route| &route.prefix)
-            .chain(self.default_destinations.iter());
+            .chain(default_destinations.iter());
 
         for destination in destinations_to_remove {
             match Self::delete_route(*destination).await {
@@ -269,6 +308,102 @@ impl RouteManagerImpl {
         }
     }
 
+    /// Adds back applied routes that are no longer used to reach their destination because they
+    /// have been removed, unless that was done too recently. A route that another program has
+    /// replaced with its own route to the same destination is left alone, since only one of them
+    /// can exist.
+    async fn restore_removed_routes(&mut self) {
+        // Routes through the default nodes are kept up to date by `apply_new_default_route`.
+        let routes: Vec<_> = self
+            .applied_routes
+            .iter()
+            .filter(|route| !self.default_destinations.contains(&route.prefix))
+            .cloned()
+            .collect();
+        for route in routes {
+            let (destination, current_node) = match Self::get_prefix_route(route.prefix).await {
+                Ok(current_route) => current_route,
+                Err(error) => {
+                    log::error!("Failed to look up route for {}: {}", route.prefix, error);
+                    continue;
+                }
+            };
+            if current_node
+                .as_ref()
+                .map(|node| route.node.matches(node))
+                .unwrap_or(false)
+            {
+                continue;
+            }
+
+            if !self.restore_backoff.try_restore(&route, Instant::now()) {
+                log::debug!("Waiting before adding back route {}", route);
+                continue;
+            }
+            if destination == Some(route.prefix) {
+                log::warn!(
+                    "Route {} was replaced by another program. Not adding it back",
+                    route
+                );
+                continue;
+            }
+
+            log::warn!("Route was removed. Adding it back: {}", route);
+            match Self::add_route(&route).await {
+                Ok(status) => {
+                    if !status.success() {
+                        log::error!("Failed to add back route {}", route);
+                    }
+                }
+                Err(e) => log::error!("Failed to add back route {}: {}", route, e),
+            }
+        }
+    }
+
+    // Retrieves the destination of the route that's currently used to reach the given network,
+    // and its node
+    async fn get_prefix_route(prefix: IpNetwork) -> Result<(Option<IpNetwork>, Option<Node>)> {
+        let mut cmd = Command::new("route");
+        cmd.arg("-n")
+            .arg("get")
+            .arg(ip_vers(prefix))
+            .arg(prefix.to_string());
+
+        let output = cmd.output().await.map_err(Error::FailedToRunRoute)?;
+        let output = String::from_utf8(output.stdout).map_err(|e| {
+            log::error!("Failed to parse utf-8 bytes from output of route: {}", e);
+            Error::BadOutputFromNetstat
+        })?;
+        Ok((
+            Self::parse_route_destination(&output),
+            Self::parse_route(&output),
+        ))
+    }
+
+    /// Parses the destination network out of the output of `route get`. Returns `None` for the
+    /// default route.
+    fn parse_route_destination(route_output: &str) -> Option<IpNetwork> {
+        let mut destination = None;
+        let mut mask = None;
+        for line in route_output.lines() {
+            let tokens: Vec<_> = line.split_whitespace().collect();
+            if tokens.len() == 2 {
+                match tokens[0].trim() {
+                    "destination:" => destination = Self::parse_gateway_line(tokens[1]),
+                    "mask:" => mask = Self::parse_gateway_line(tokens[1]),
+                    _ => continue,
+                }
+            }
+        }
+
+        let destination = destination?;
+        match mask {
+            Some(mask) => IpNetwork::with_netmask(destination, mask).ok(),
+            // Host routes have no mask
+            None => Some(IpNetwork::from(destination)),
+        }
+    }
+
     async fn apply_new_default_route(&self, new_node: &Option<Node>, v4: bool) {
         for destination in self.default_destinations.iter() {
             if destination.is_ipv4() == v4 {
@@ -302,6 +437,12 @@ fn ip_vers(prefix: IpNetwork) -> &'static str {
 /// the routing table.
 pub(crate) fn listen_for_default_route_changes() -> Result<impl Stream<Item = std::io::Result<()>>>
 {
+    listen_for_route_changes(true)
+}
+
+/// Returns a stream that produces an item whenever a route is either added or deleted from the
+/// routing table. If `only_default` is true, only changes to default routes are reported.
+fn listen_for_route_changes(only_default: bool) -> Result<impl Stream<Item = std::io::Result<()>>> {
     let mut cmd = Command::new("route");
     cmd.arg("-n")
         .arg("monitor")
@@ -339,7 +480,7 @@ pub(crate) fn listen_for_default_route_changes() -> Result<impl Stream<Item = st
             }
             if line.trim().is_empty() {
                 add_or_delete_message = false;
-                if contains_default {
+                if contains_default || !only_default {
                     contains_default = false;
                     return future::ready(Ok(Some(())));
                 }
@@ -352,3 +493,57 @@ pub(crate) fn listen_for_default_route_changes() -> Result<impl Stream<Item = st
 
     Ok(monitor)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_route_destination() {
+        let network_route = "   route to: 10.1.2.3
+destination: 10.0.0.0
+       mask: 255.0.0.0
+    gateway: 192.168.1.1
+  interface: en0
+      flags: <UP,GATEWAY,DONE,STATIC,PRCLONING>
+";
+        assert_eq!(
+            RouteManagerImpl::parse_route_destination(network_route),
+            Some("10.0.0.0/8".parse().unwrap())
+        );
+        assert_eq!(
+            RouteManagerImpl::parse_route(network_route),
+            Some(Node::new("192.168.1.1".parse().unwrap(), "en0".to_owned()))
+        );
+
+        let v6_route = "   route to: 2001:db8::1
+destination: 2001:db8::
+       mask: ffff:ffff::
+  interface: utun3
+";
+        assert_eq!(
+            RouteManagerImpl::parse_route_destination(v6_route),
+            Some("2001:db8::/32".parse().unwrap())
+        );
+
+        let host_route = "   route to: 192.0.2.1
+destination: 192.0.2.1
+  interface: utun3
+";
+        assert_eq!(
+            RouteManagerImpl::parse_route_destination(host_route),
+            Some("192.0.2.1/32".parse().unwrap())
+        );
+
+        let default_route = "   route to: default
+destination: default
+       mask: default
+    gateway: 192.168.1.1
+  interface: en0
+";
+        assert_eq!(
+            RouteManagerImpl::parse_route_destination(default_route),
+            None
+        );
+    }
+}
@@ -2,7 +2,13 @@
 #![cfg_attr(target_os = "windows", allow(dead_code))]
 
 use ipnetwork::IpNetwork;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 use std::{fmt, net::IpAddr};
+use talpid_types::tunnel::RouteDebugInfo;
 
 #[cfg(target_os = "windows")]
 #[path = "windows.rs"]
@@ -16,7 +22,9 @@ mod imp;
 use netlink_packet_route::rtnl::constants::RT_TABLE_MAIN;
 
 #[cfg(target_os = "macos")]
-pub(crate) use imp::{get_default_routes, listen_for_default_route_changes, PlatformError};
+pub(crate) use imp::{
+    get_default_routes, get_destination_node, listen_for_default_route_changes, PlatformError,
+};
 
 pub use imp::{Error, RouteManager};
 
@@ -53,6 +61,21 @@ impl Route {
     pub fn get_node(&self) -> &Node {
         &self.node
     }
+
+    /// Returns a description of the route for debugging purposes.
+    #[cfg(not(windows))]
+    fn debug_info(&self) -> RouteDebugInfo {
+        RouteDebugInfo {
+            destination: self.prefix,
+            gateway: self.node.ip,
+            interface: self.node.device.clone(),
+            metric: self.metric,
+            #[cfg(target_os = "linux")]
+            table_id: Some(self.table_id),
+            #[cfg(not(target_os = "linux"))]
+            table_id: None,
+        }
+    }
 }
 
 impl fmt::Display for Route {
@@ -96,6 +119,23 @@ impl RequiredRoute {
         self.table_id = new_id;
         self
     }
+
+    /// Returns a description of the route for debugging purposes. Routes through the default
+    /// node have neither a gateway nor an interface.
+    #[cfg(windows)]
+    fn debug_info(&self) -> RouteDebugInfo {
+        let (gateway, interface) = match &self.node {
+            NetNode::RealNode(node) => (node.ip, node.device.clone()),
+            NetNode::DefaultNode => (None, None),
+        };
+        RouteDebugInfo {
+            destination: self.prefix,
+            gateway,
+            interface,
+            metric: None,
+            table_id: None,
+        }
+    }
 }
 
 /// A NetNode represents a network node - either a real one or a symbolic default one.
@@ -159,6 +199,20 @@ impl Node {
     pub fn get_device(&self) -> Option<&str> {
         self.device.as_ref().map(|s| s.as_ref())
     }
+
+    /// Returns whether `reported` could be the system's description of a route added through this
+    /// node. Only the attributes that were specified for this node are compared, since the
+    /// system may fill in the gateway or the device when only one of them was given.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn matches(&self, reported: &Node) -> bool {
+        self.ip
+            .map(|address| reported.ip == Some(address))
+            .unwrap_or(true)
+            && self
+                .get_device()
+                .map(|device| reported.get_device() == Some(device))
+                .unwrap_or(true)
+    }
 }
 
 impl fmt::Display for Node {
@@ -173,3 +227,141 @@ impl fmt::Display for Node {
         Ok(())
     }
 }
+
+/// Delay before a route that was added back may be added back again.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const INITIAL_RESTORE_DELAY: Duration = Duration::from_secs(1);
+/// The delay is doubled for every restoration, up to this limit.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const MAX_RESTORE_DELAY: Duration = Duration::from_secs(2 * 60);
+/// The delay starts over once a route has not been added back for this long.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const RESTORE_DELAY_RESET: Duration = Duration::from_secs(10 * 60);
+
+/// Limits how often routes that keep being removed or overridden are added back, so that the
+/// route manager does not fight endlessly with another program that manages the same routes.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[derive(Default)]
+struct RestoreBackoff {
+    routes: HashMap<Route, RestoreState>,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+struct RestoreState {
+    last_restore: Instant,
+    delay: Duration,
+    /// Set when the route should have been added back but was held back.
+    pending: bool,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl RestoreBackoff {
+    /// Returns whether `route` may be added back at `now`, and if so, counts it as added back.
+    /// Otherwise, the route is returned by `due_routes` once it may be added back.
+    fn try_restore(&mut self, route: &Route, now: Instant) -> bool {
+        let state = match self.routes.get_mut(route) {
+            Some(state) => state,
+            None => {
+                self.routes.insert(
+                    route.clone(),
+                    RestoreState {
+                        last_restore: now,
+                        delay: INITIAL_RESTORE_DELAY,
+                        pending: false,
+                    },
+                );
+                return true;
+            }
+        };
+
+        let elapsed = now.saturating_duration_since(state.last_restore);
+        if elapsed >= RESTORE_DELAY_RESET {
+            state.delay = INITIAL_RESTORE_DELAY;
+        } else if elapsed < state.delay {
+            state.pending = true;
+            return false;
+        } else {
+            state.delay = std::cmp::min(state.delay * 2, MAX_RESTORE_DELAY);
+        }
+        state.last_restore = now;
+        state.pending = false;
+        true
+    }
+
+    /// Returns the routes that were held back and may now be added back.
+    fn due_routes(&self, now: Instant) -> Vec<Route> {
+        self.routes
+            .iter()
+            .filter(|(_, state)| {
+                state.pending && now.saturating_duration_since(state.last_restore) >= state.delay
+            })
+            .map(|(route, _)| route.clone())
+            .collect()
+    }
+
+    /// Forgets `route`, so that it is no longer returned by `due_routes`.
+    #[cfg(target_os = "linux")]
+    fn forget(&mut self, route: &Route) {
+        self.routes.remove(route);
+    }
+
+    fn clear(&mut self) {
+        self.routes.clear();
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "macos")))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_node_matches() {
+        let gateway: IpAddr = "192.0.2.1".parse().unwrap();
+        let reported = Node::new(gateway, "eth0".to_owned());
+
+        assert!(Node::address(gateway).matches(&reported));
+        assert!(Node::device("eth0".to_owned()).matches(&reported));
+        assert!(reported.matches(&reported));
+        assert!(!Node::device("eth1".to_owned()).matches(&reported));
+        assert!(!Node::address("192.0.2.2".parse().unwrap()).matches(&reported));
+        assert!(!reported.matches(&Node::address(gateway)));
+    }
+
+    #[test]
+    fn test_restore_backoff() {
+        let route = Route::new(
+            Node::device("eth0".to_owned()),
+            "192.0.2.0/24".parse().unwrap(),
+        );
+        let mut backoff = RestoreBackoff::default();
+        let start = Instant::now();
+
+        assert!(backoff.try_restore(&route, start));
+        assert!(!backoff.try_restore(&route, start));
+        assert!(backoff.due_routes(start).is_empty());
+
+        // The delay doubles with every restoration
+        let mut now = start + INITIAL_RESTORE_DELAY;
+        assert_eq!(backoff.due_routes(now), vec![route.clone()]);
+        assert!(backoff.try_restore(&route, now));
+        assert!(backoff.due_routes(now).is_empty());
+        assert!(!backoff.try_restore(&route, now + INITIAL_RESTORE_DELAY));
+        now += 2 * INITIAL_RESTORE_DELAY;
+        assert!(backoff.try_restore(&route, now));
+
+        for _ in 0..16 {
+            now += MAX_RESTORE_DELAY;
+            assert!(backoff.try_restore(&route, now));
+        }
+        assert!(!backoff.try_restore(&route, now + MAX_RESTORE_DELAY / 2));
+
+        // The delay starts over after a quiet period
+        now += RESTORE_DELAY_RESET;
+        assert!(backoff.try_restore(&route, now));
+        assert!(!backoff.try_restore(&route, now));
+        assert!(backoff.try_restore(&route, now + INITIAL_RESTORE_DELAY));
+
+        backoff.clear();
+        assert!(backoff.try_restore(&route, now));
+    }
+}
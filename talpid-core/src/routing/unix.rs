@@ -12,6 +12,7 @@ use futures::channel::{
 use std::{collections::HashSet, io};
 #[cfg(target_os = "macos")]
 use talpid_types::net::IpVersion;
+use talpid_types::tunnel::RouteDebugInfo;
 
 #[cfg(target_os = "linux")]
 use futures::stream::Stream;
@@ -74,6 +75,15 @@ impl RouteManagerHandle {
             .map_err(Error::PlatformError)
     }
 
+    /// Returns the routes that are currently applied by the route manager.
+    pub async fn get_applied_routes(&self) -> Result<Vec<RouteDebugInfo>, Error> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::GetAppliedRoutes(response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx.await.map_err(|_| Error::ManagerChannelDown)
+    }
+
     /// Ensure that packets are routed using the correct tables.
     #[cfg(target_os = "linux")]
     pub async fn create_routing_rules(&self, enable_ipv6: bool) -> Result<(), Error> {
@@ -143,6 +153,7 @@ pub(crate) enum RouteManagerCommand {
         oneshot::Sender<Result<(), PlatformError>>,
     ),
     ClearRoutes,
+    GetAppliedRoutes(oneshot::Sender<Vec<RouteDebugInfo>>),
     Shutdown(oneshot::Sender<()>),
     #[cfg(target_os = "linux")]
     CreateRoutingRules(bool, oneshot::Sender<Result<(), PlatformError>>),
@@ -278,3 +289,13 @@ pub(crate) async fn get_default_routes() -> Result<(Option<super::Node>, Option<
         imp::RouteManagerImpl::get_default_node(IpVersion::V6).map_err(Into::into)
     )
 }
+
+/// Returns the node that traffic to the given destination is currently routed through.
+#[cfg(target_os = "macos")]
+pub(crate) async fn get_destination_node(
+    destination: std::net::IpAddr,
+) -> Result<Option<super::Node>, Error> {
+    imp::RouteManagerImpl::get_destination_node(destination)
+        .await
+        .map_err(Into::into)
+}
@@ -8,6 +8,7 @@ use futures::{
     StreamExt,
 };
 use std::collections::HashSet;
+use talpid_types::tunnel::RouteDebugInfo;
 
 /// Windows routing errors.
 #[derive(err_derive::Error, Debug)]
@@ -54,11 +55,22 @@ impl RouteManagerHandle {
             .map_err(|_| Error::RouteManagerDown)?;
         response_rx.await.map_err(|_| Error::ManagerChannelDown)?
     }
+
+    /// Returns the routes that are currently applied by the route manager.
+    pub async fn get_applied_routes(&self) -> Result<Vec<RouteDebugInfo>> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::GetAppliedRoutes(response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx.await.map_err(|_| Error::ManagerChannelDown)
+    }
 }
 
 #[derive(Debug)]
 pub enum RouteManagerCommand {
     AddRoutes(HashSet<RequiredRoute>, oneshot::Sender<Result<()>>),
+    GetAppliedRoutes(oneshot::Sender<Vec<RouteDebugInfo>>),
+    RoutesCleared,
     Shutdown,
 }
 
@@ -89,10 +101,13 @@ impl RouteManager {
     }
 
     async fn listen(mut manage_rx: UnboundedReceiver<RouteManagerCommand>) {
+        // WinNet keeps track of the routes it applies, but does not expose them, so a copy is kept
+        // here.
+        let mut applied_routes = HashSet::new();
         while let Some(command) = manage_rx.next().await {
             match command {
-                RouteManagerCommand::AddRoutes(routes, tx) => {
-                    let routes: Vec<_> = routes
+                RouteManagerCommand::AddRoutes(routes_to_apply, tx) => {
+                    let routes: Vec<_> = routes_to_apply
                         .iter()
                         .map(|route| {
                             let destination = winnet::WinNetIpNetwork::from(route.prefix);
@@ -108,10 +123,24 @@ impl RouteManager {
                         })
                         .collect();
 
+                    let result =
+                        winnet::routing_manager_add_routes(&routes).map_err(Error::AddRoutesFailed);
+                    if result.is_ok() {
+                        applied_routes.extend(routes_to_apply);
+                    }
+                    let _ = tx.send(result);
+                }
+                RouteManagerCommand::GetAppliedRoutes(tx) => {
                     let _ = tx.send(
-                        winnet::routing_manager_add_routes(&routes).map_err(Error::AddRoutesFailed),
+                        applied_routes
+                            .iter()
+                            .map(RequiredRoute::debug_info)
+                            .collect(),
                     );
                 }
+                RouteManagerCommand::RoutesCleared => {
+                    applied_routes.clear();
+                }
                 RouteManagerCommand::Shutdown => {
                     break;
                 }
@@ -164,6 +193,9 @@ impl RouteManager {
     /// [`RouteManager::add_routes`].
     pub fn clear_routes(&self) -> Result<()> {
         if winnet::routing_manager_delete_applied_routes() {
+            if let Some(tx) = &self.manage_tx {
+                let _ = tx.unbounded_send(RouteManagerCommand::RoutesCleared);
+            }
             Ok(())
         } else {
             Err(Error::ClearRoutesFailed)
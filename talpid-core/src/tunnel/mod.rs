@@ -7,7 +7,12 @@ use std::{
 };
 #[cfg(not(target_os = "android"))]
 use talpid_types::net::openvpn as openvpn_types;
-use talpid_types::net::{wireguard as wireguard_types, TunnelParameters};
+#[cfg(target_os = "windows")]
+use talpid_types::ErrorExt;
+use talpid_types::{
+    net::{wireguard as wireguard_types, TunnelParameters},
+    tunnel::{TrafficCounters, TunnelDebugInfo},
+};
 
 #[cfg(target_os = "android")]
 pub use self::tun_provider::TunConfig;
@@ -16,6 +21,9 @@ pub use self::tun_provider::TunConfig;
 #[cfg(not(target_os = "android"))]
 pub mod openvpn;
 
+/// Reachability check of the first hop of a tunnel.
+pub mod probe;
+
 /// A module for all WireGuard related tunnel management.
 pub mod wireguard;
 
@@ -60,6 +68,10 @@ pub enum Error {
     /// There was an error listening for events from the Wireguard tunnel
     #[error(display = "Failed while listening for events from the Wireguard tunnel")]
     WireguardTunnelMonitoringError(#[error(source)] wireguard::Error),
+
+    /// The relay did not respond to a reachability probe before the tunnel was started
+    #[error(display = "The relay did not respond to a reachability probe")]
+    RelayUnreachable(#[error(source)] io::Error),
 }
 
 /// Possible events from the VPN tunnel and the child process managing it.
@@ -73,6 +85,21 @@ pub enum TunnelEvent {
     Up(TunnelMetadata),
     /// Sent when the tunnel goes down.
     Down,
+    /// Sent when a handshake or endpoint event is parsed from the log output of wireguard-go.
+    WireguardEvent(wireguard::WgGoEvent),
+}
+
+impl TunnelEvent {
+    /// Returns a short description of the kind of event.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TunnelEvent::AuthFailed(_) => "auth failed",
+            TunnelEvent::InterfaceUp(_) => "interface up",
+            TunnelEvent::Up(_) => "up",
+            TunnelEvent::Down => "down",
+            TunnelEvent::WireguardEvent(_) => "wireguard event",
+        }
+    }
 }
 
 /// Information about a VPN tunnel.
@@ -88,6 +115,26 @@ pub struct TunnelMetadata {
     pub ipv6_gateway: Option<Ipv6Addr>,
 }
 
+impl TunnelMetadata {
+    /// Returns the IPv4 gateway, unless the tunnel has no IPv4 address to reach it from.
+    pub fn reachable_ipv4_gateway(&self) -> Option<Ipv4Addr> {
+        if self.ips.iter().any(|ip| ip.is_ipv4()) {
+            Some(self.ipv4_gateway)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the gateways that are reachable through the tunnel.
+    pub fn gateways(&self) -> Vec<IpAddr> {
+        self.reachable_ipv4_gateway()
+            .map(IpAddr::V4)
+            .into_iter()
+            .chain(self.ipv6_gateway.map(IpAddr::V6))
+            .collect()
+    }
+}
+
 /// Abstraction for monitoring a generic VPN tunnel.
 pub struct TunnelMonitor {
     monitor: InternalTunnelMonitor,
@@ -270,6 +317,12 @@ impl TunnelMonitor {
         self.monitor.close_handle()
     }
 
+    /// Creates a handle that can be used to inspect the tunnel while some other thread is
+    /// blocked in `wait`.
+    pub fn debug_handle(&self) -> DebugHandle {
+        self.monitor.debug_handle()
+    }
+
     /// Consumes the monitor and blocks until the tunnel exits or there is an error.
     pub fn wait(self) -> Result<()> {
         self.monitor.wait().map_err(Error::from)
@@ -299,6 +352,39 @@ impl CloseHandle {
     }
 }
 
+/// A handle used to inspect the internals of a `TunnelMonitor`.
+#[derive(Clone)]
+pub enum DebugHandle {
+    #[cfg(not(target_os = "android"))]
+    /// OpenVPN only exposes its traffic counters
+    OpenVpn(openvpn::DebugHandle),
+    /// Wireguard debug handle
+    Wireguard(wireguard::DebugHandle),
+}
+
+impl DebugHandle {
+    /// Returns a snapshot of the internals of the tunnel.
+    pub fn debug_info(&self) -> TunnelDebugInfo {
+        match self {
+            #[cfg(not(target_os = "android"))]
+            DebugHandle::OpenVpn(_) => TunnelDebugInfo {
+                tunnel_type: Some(talpid_types::net::TunnelType::OpenVpn),
+                ..TunnelDebugInfo::default()
+            },
+            DebugHandle::Wireguard(handle) => handle.debug_info(),
+        }
+    }
+
+    /// Returns the traffic through the tunnel, or `None` if it cannot be read.
+    pub fn traffic(&self) -> Option<TrafficCounters> {
+        match self {
+            #[cfg(not(target_os = "android"))]
+            DebugHandle::OpenVpn(handle) => handle.traffic(),
+            DebugHandle::Wireguard(handle) => handle.traffic(),
+        }
+    }
+}
+
 enum InternalTunnelMonitor {
     #[cfg(not(target_os = "android"))]
     OpenVpn(openvpn::OpenVpnMonitor),
@@ -314,6 +400,14 @@ impl InternalTunnelMonitor {
         }
     }
 
+    fn debug_handle(&self) -> DebugHandle {
+        match self {
+            #[cfg(not(target_os = "android"))]
+            InternalTunnelMonitor::OpenVpn(tun) => DebugHandle::OpenVpn(tun.debug_handle()),
+            InternalTunnelMonitor::Wireguard(tun) => DebugHandle::Wireguard(tun.debug_handle()),
+        }
+    }
+
     fn wait(self) -> Result<()> {
         match self {
             #[cfg(not(target_os = "android"))]
@@ -325,6 +419,70 @@ impl InternalTunnelMonitor {
     }
 }
 
+/// Unloads the driver DLLs used by the tunnels once they are no longer in use, so that they are
+/// loaded again by the next tunnel.
+#[cfg(target_os = "windows")]
+pub fn unload_driver_dlls() {
+    wireguard::unload_wg_nt_dll();
+    openvpn::unload_wintun_dll();
+}
+
+/// Adapters and network profiles removed by [`cleanup_drivers`].
+#[cfg(target_os = "windows")]
+#[derive(Debug, Default, Clone)]
+pub struct DriverCleanup {
+    /// Names of the removed Wintun adapters.
+    pub adapters: Vec<String>,
+    /// Names of the removed network profiles.
+    pub network_profiles: Vec<String>,
+    /// Whether Windows must be restarted to finish removing the adapters.
+    pub reboot_required: bool,
+}
+
+/// Removes tunnel adapters and network profiles left behind by crashes or earlier versions.
+/// WireGuardNT adapters are removed by the driver when they are closed, so only the Wintun pool
+/// has to be cleaned up. Network profiles are removed for the OpenVPN adapter and for the
+/// WireGuard adapter, which is named `wireguard_interface_name` if one is configured. This must
+/// not be called while a tunnel is running.
+#[cfg(target_os = "windows")]
+pub fn cleanup_drivers(
+    resource_dir: &Path,
+    wireguard_interface_name: Option<&str>,
+) -> DriverCleanup {
+    let mut cleanup = DriverCleanup::default();
+
+    match openvpn::delete_orphaned_wintun_adapters(resource_dir) {
+        Ok((adapters, reboot_required)) => {
+            cleanup.adapters = adapters;
+            cleanup.reboot_required = reboot_required;
+        }
+        Err(error) => log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to remove orphaned Wintun adapters")
+        ),
+    }
+
+    let mut adapter_names = vec![
+        openvpn::ADAPTER_NAME,
+        wireguard_interface_name.unwrap_or(wireguard::config::DEFAULT_INTERFACE_NAME),
+    ];
+    adapter_names.dedup();
+    for adapter_name in adapter_names {
+        match crate::windows::network_profiles::remove_duplicate_profiles(adapter_name) {
+            Ok(profiles) => cleanup.network_profiles.extend(profiles),
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg(&format!(
+                    "Failed to remove stale network profiles of {}",
+                    adapter_name
+                ))
+            ),
+        }
+    }
+
+    cleanup
+}
+
 #[cfg(target_os = "windows")]
 fn is_ipv6_enabled_in_os() -> bool {
     use winreg::{enums::*, RegKey};
@@ -4,7 +4,7 @@ use crate::routing::RequiredRoute;
 use crate::{
     mktemp,
     process::{
-        openvpn::{OpenVpnCommand, OpenVpnProcHandle},
+        openvpn::{OpenVpnCommand, OpenVpnProcHandle, OutputError},
         stoppable_process::StoppableProcess,
     },
     proxy::{self, ProxyMonitor, ProxyResourceData},
@@ -13,7 +13,7 @@ use crate::{
 #[cfg(windows)]
 use lazy_static::lazy_static;
 #[cfg(target_os = "linux")]
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 #[cfg(windows)]
 use std::ffi::OsString;
 use std::{
@@ -28,21 +28,42 @@ use std::{
     thread,
     time::Duration,
 };
-use talpid_types::{net::openvpn, ErrorExt};
+use talpid_types::{net::openvpn, tunnel::TrafficCounters, ErrorExt};
+#[cfg(windows)]
+use talpid_windows_net::Luid;
 use tokio::task;
 #[cfg(target_os = "linux")]
 use which;
 #[cfg(windows)]
 use widestring::U16CString;
 #[cfg(windows)]
-use winapi::shared::{guiddef::GUID, ifdef::NET_LUID};
+use winapi::shared::guiddef::GUID;
 
 #[cfg(windows)]
 mod wintun;
 
+/// Forgets the shared Wintun DLL, so that it is loaded again by the next tunnel.
+#[cfg(windows)]
+pub fn unload_wintun_dll() {
+    wintun::WintunDll::unload_instance();
+}
+
+/// Deletes the adapters in the Mullvad Wintun pool. This must only be done while no OpenVPN tunnel
+/// is running, since any adapter in the pool is then left over from a crash or an earlier version.
+/// Returns the names of the deleted adapters and whether a reboot is required.
+#[cfg(windows)]
+pub fn delete_orphaned_wintun_adapters(resource_dir: &Path) -> io::Result<(Vec<String>, bool)> {
+    let dll = wintun::WintunDll::instance(resource_dir)?;
+    wintun::delete_pool_adapters(dll, &*ADAPTER_POOL)
+}
+
+/// Name of the Wintun adapter used by OpenVPN.
+#[cfg(windows)]
+pub const ADAPTER_NAME: &str = "Mullvad";
+
 #[cfg(windows)]
 lazy_static! {
-    static ref ADAPTER_ALIAS: U16CString = U16CString::from_str("Mullvad").unwrap();
+    static ref ADAPTER_ALIAS: U16CString = U16CString::from_str(ADAPTER_NAME).unwrap();
     static ref ADAPTER_POOL: U16CString = U16CString::from_str("Mullvad").unwrap();
 }
 
@@ -101,6 +122,18 @@ pub enum Error {
     #[error(display = "OpenVPN process died unexpectedly")]
     ChildProcessDied,
 
+    /// OpenVPN exited because the server rejected the credentials
+    #[error(display = "OpenVPN authentication failed")]
+    AuthFailed(Option<String>),
+
+    /// OpenVPN exited after the TLS handshake with the server failed
+    #[error(display = "OpenVPN TLS error: {}", _0)]
+    TlsError(String),
+
+    /// OpenVPN exited after the proxy that it connects through failed
+    #[error(display = "OpenVPN proxy error: {}", _0)]
+    ProxyError(String),
+
     /// Failed before OpenVPN started
     #[error(display = "Failed to start OpenVPN")]
     StartProcessError,
@@ -136,21 +169,6 @@ pub enum Error {
         _0
     )]
     ProxyExited(String),
-
-    /// The map is missing 'dev'
-    #[cfg(target_os = "linux")]
-    #[error(display = "Failed to obtain tunnel interface name")]
-    MissingTunnelInterface,
-
-    /// The map has no 'route_n' entries
-    #[cfg(target_os = "linux")]
-    #[error(display = "Failed to obtain OpenVPN server")]
-    MissingRemoteHost,
-
-    /// Cannot parse the remote_n in the provided map
-    #[cfg(target_os = "linux")]
-    #[error(display = "Cannot parse remote host string")]
-    ParseRemoteHost(#[error(source)] std::net::AddrParseError),
 }
 
 #[cfg(unix)]
@@ -187,6 +205,8 @@ pub struct OpenVpnMonitor<C: OpenVpnBuilder = OpenVpnCommand> {
     _user_pass_file: mktemp::TempFile,
     /// Keep the 'TempFile' for the proxy user-pass file in the struct, so it's removed on drop.
     _proxy_auth_file: Option<mktemp::TempFile>,
+    /// Keep the `TempFile` for the status file in the struct, so it's removed on drop.
+    status_file: Option<mktemp::TempFile>,
 
     runtime: tokio::runtime::Runtime,
     event_server_abort_tx: triggered::Trigger,
@@ -199,7 +219,7 @@ pub struct OpenVpnMonitor<C: OpenVpnBuilder = OpenVpnCommand> {
 #[cfg(windows)]
 #[async_trait::async_trait]
 trait WintunContext: Send + Sync {
-    fn luid(&self) -> NET_LUID;
+    fn luid(&self) -> Luid;
     fn ipv6(&self) -> bool;
     async fn wait_for_interfaces(&self) -> io::Result<()>;
     fn disable_unused_features(&self) {}
@@ -211,7 +231,7 @@ impl std::fmt::Debug for dyn WintunContext {
         write!(
             f,
             "WintunContext {{ luid: {}, ipv6: {} }}",
-            self.luid().Value,
+            self.luid().as_raw(),
             self.ipv6()
         )
     }
@@ -228,7 +248,7 @@ struct WintunContextImpl {
 #[cfg(windows)]
 #[async_trait::async_trait]
 impl WintunContext for WintunContextImpl {
-    fn luid(&self) -> NET_LUID {
+    fn luid(&self) -> Luid {
         self.adapter.adapter().luid()
     }
 
@@ -238,7 +258,7 @@ impl WintunContext for WintunContextImpl {
 
     async fn wait_for_interfaces(&self) -> io::Result<()> {
         let luid = self.adapter.adapter().luid();
-        crate::windows::wait_for_interfaces(luid, true, self.wait_v6_interface).await
+        talpid_windows_net::wait_for_interfaces(luid, true, self.wait_v6_interface).await
     }
 
     fn disable_unused_features(&self) {
@@ -309,7 +329,8 @@ impl OpenVpnMonitor<OpenVpnCommand> {
         #[cfg(windows)]
         log::debug!("Adapter alias: {}", adapter_alias.to_string_lossy());
 
-        let cmd = Self::create_openvpn_cmd(
+        let status_file = mktemp::TempFile::new();
+        let mut cmd = Self::create_openvpn_cmd(
             params,
             user_pass_file.as_ref(),
             proxy_auth_file.as_ref().map(AsRef::as_ref),
@@ -318,6 +339,7 @@ impl OpenVpnMonitor<OpenVpnCommand> {
             #[cfg(windows)]
             adapter_alias.to_os_string(),
         )?;
+        cmd.status(&status_file);
 
         let plugin_path = Self::get_plugin_path(resource_dir)?;
 
@@ -328,7 +350,7 @@ impl OpenVpnMonitor<OpenVpnCommand> {
 
         let (event_server_abort_tx, event_server_abort_rx) = triggered::trigger();
 
-        Self::new_internal(
+        let mut monitor = Self::new_internal(
             cmd,
             event_server_abort_tx.clone(),
             event_server_abort_rx,
@@ -353,19 +375,20 @@ impl OpenVpnMonitor<OpenVpnCommand> {
                 wait_v6_interface: params.generic_options.enable_ipv6,
                 _logger: wintun_logger,
             }),
-        )
+        )?;
+        monitor.status_file = Some(status_file);
+        Ok(monitor)
     }
 }
 
 #[cfg(target_os = "linux")]
-fn extract_routes(env: &HashMap<String, String>) -> Result<HashSet<RequiredRoute>> {
-    let tun_interface = env.get("dev").ok_or(Error::MissingTunnelInterface)?;
+fn extract_routes(tun_interface: &str) -> HashSet<RequiredRoute> {
     let tun_node = routing::Node::device(tun_interface.to_string());
     let mut routes = HashSet::new();
     for network in &["0.0.0.0/0".parse().unwrap(), "::/0".parse().unwrap()] {
         routes.insert(RequiredRoute::new(*network, tun_node.clone()));
     }
-    Ok(routes)
+    routes
 }
 
 impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
@@ -415,7 +438,8 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
         #[cfg(windows)]
         let wintun = Arc::new(wintun);
 
-        cmd.plugin(plugin_path, vec![ipc_path])
+        let protocol_version = event_server::ProtocolVersion::Current as i32;
+        cmd.plugin(plugin_path, vec![ipc_path, protocol_version.to_string()])
             .log(log_path.as_ref().map(|p| p.as_path()));
         let (spawn_task, abort_spawn) = futures::future::abortable(Self::prepare_process(
             cmd,
@@ -432,6 +456,7 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
             closed: Arc::new(AtomicBool::new(false)),
             _user_pass_file: user_pass_file,
             _proxy_auth_file: proxy_auth_file,
+            status_file: None,
 
             runtime,
             event_server_abort_tx,
@@ -465,6 +490,13 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
         }
     }
 
+    /// Creates a handle used to read the traffic counters of the tunnel.
+    pub fn debug_handle(&self) -> DebugHandle {
+        DebugHandle {
+            status_path: self.status_file.as_ref().map(mktemp::TempFile::to_path_buf),
+        }
+    }
+
     /// Consumes the monitor and waits for both proxy and tunnel, as applicable.
     pub fn wait(mut self) -> Result<()> {
         if let Some(mut proxy_monitor) = self.proxy_monitor.take() {
@@ -526,7 +558,7 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
                 }
                 _ => Ok(()),
             },
-            WaitResult::Child(Ok(exit_status), closed) => {
+            WaitResult::Child(Ok(exit_status), closed, output_error) => {
                 if exit_status.success() || closed {
                     log::debug!(
                         "OpenVPN exited, as expected, with exit status: {}",
@@ -535,10 +567,15 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
                     Ok(())
                 } else {
                     log::error!("OpenVPN died unexpectedly with status: {}", exit_status);
-                    Err(Error::ChildProcessDied)
+                    Err(match output_error {
+                        Some(OutputError::AuthFailed(reason)) => Error::AuthFailed(reason),
+                        Some(OutputError::TlsError(message)) => Error::TlsError(message),
+                        Some(OutputError::ProxyError(message)) => Error::ProxyError(message),
+                        None => Error::ChildProcessDied,
+                    })
                 }
             }
-            WaitResult::Child(Err(e), _) => {
+            WaitResult::Child(Err(e), _, _) => {
                 log::error!("OpenVPN process wait error: {}", e);
                 Err(Error::ChildProcessError("Error when waiting", e))
             }
@@ -585,7 +622,10 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
         thread::spawn(move || {
             let result = child.wait();
             let closed = closed_handle.load(Ordering::SeqCst);
-            child_tx.send(WaitResult::Child(result, closed)).unwrap();
+            let output_error = child.output_error();
+            child_tx
+                .send(WaitResult::Child(result, closed, output_error))
+                .unwrap();
             event_server_abort_tx.trigger();
         });
 
@@ -719,6 +759,42 @@ impl<C: OpenVpnBuilder + Send + 'static> OpenVpnMonitor<C> {
     }
 }
 
+/// A handle used to read the traffic counters from the OpenVPN status file.
+#[derive(Debug, Clone)]
+pub struct DebugHandle {
+    status_path: Option<PathBuf>,
+}
+
+impl DebugHandle {
+    /// Returns the traffic through the tunnel, as last reported by OpenVPN.
+    pub fn traffic(&self) -> Option<TrafficCounters> {
+        let contents = fs::read_to_string(self.status_path.as_ref()?).ok()?;
+        parse_status_traffic(&contents)
+    }
+}
+
+/// Parses the byte counters of the tun device from an OpenVPN client status file. Bytes read from
+/// the tun device are sent through the tunnel, and bytes written to it have been received.
+fn parse_status_traffic(contents: &str) -> Option<TrafficCounters> {
+    let mut tx_bytes = None;
+    let mut rx_bytes = None;
+    for line in contents.lines() {
+        let mut parts = line.splitn(2, ',');
+        let counter = match parts.next()? {
+            "TUN/TAP read bytes" => &mut tx_bytes,
+            "TUN/TAP write bytes" => &mut rx_bytes,
+            _ => continue,
+        };
+        *counter = parts
+            .next()
+            .and_then(|value| value.trim().parse::<u64>().ok());
+    }
+    Some(TrafficCounters {
+        tx_bytes: tx_bytes?,
+        rx_bytes: rx_bytes?,
+    })
+}
+
 /// A handle to an `OpenVpnMonitor` for closing it.
 #[derive(Debug, Clone)]
 pub struct OpenVpnCloseHandle<H: ProcessHandle = OpenVpnProcHandle> {
@@ -747,7 +823,7 @@ impl<H: ProcessHandle> OpenVpnCloseHandle<H> {
 #[derive(Debug)]
 enum WaitResult {
     Preparation(io::Result<()>),
-    Child(io::Result<ExitStatus>, bool),
+    Child(io::Result<ExitStatus>, bool, Option<OutputError>),
     EventDispatcher,
 }
 
@@ -773,6 +849,11 @@ pub trait ProcessHandle: Send + Sync + 'static {
 
     /// Kill the subprocess.
     fn kill(&self) -> io::Result<()>;
+
+    /// Returns the most recent failure reported by the subprocess in its output.
+    fn output_error(&self) -> Option<OutputError> {
+        None
+    }
 }
 
 impl OpenVpnBuilder for OpenVpnCommand {
@@ -791,7 +872,7 @@ impl OpenVpnBuilder for OpenVpnCommand {
     }
 
     fn start(&self) -> io::Result<OpenVpnProcHandle> {
-        OpenVpnProcHandle::new(self.build())
+        OpenVpnProcHandle::new(self.build(), self.log_path())
     }
 }
 
@@ -803,6 +884,10 @@ impl ProcessHandle for OpenVpnProcHandle {
     fn kill(&self) -> io::Result<()> {
         self.nice_kill(OPENVPN_DIE_TIMEOUT)
     }
+
+    fn output_error(&self) -> Option<OutputError> {
+        OpenVpnProcHandle::output_error(self)
+    }
 }
 
 mod event_server {
@@ -810,7 +895,6 @@ mod event_server {
     use futures::stream::TryStreamExt;
     use parity_tokio_ipc::Endpoint as IpcEndpoint;
     use std::{
-        collections::HashMap,
         pin::Pin,
         task::{Context, Poll},
     };
@@ -828,7 +912,7 @@ mod event_server {
     }
     pub use proto::{
         openvpn_event_proxy_server::{OpenvpnEventProxy, OpenvpnEventProxyServer},
-        EventDetails,
+        AuthFailedEvent, ProtocolVersion, TunnelMetadata as TunnelMetadataEvent,
     };
 
     #[derive(err_derive::Error, Debug)]
@@ -873,21 +957,18 @@ mod event_server {
     {
         async fn up_inner(
             &self,
-            request: Request<EventDetails>,
+            request: Request<TunnelMetadataEvent>,
         ) -> std::result::Result<Response<()>, tonic::Status> {
-            let env = request.into_inner().env;
-            (self.on_event)(super::TunnelEvent::InterfaceUp(Self::get_tunnel_metadata(
-                &env,
-            )?))
-            .await;
+            let metadata = Self::get_tunnel_metadata(request.into_inner())?;
+            (self.on_event)(super::TunnelEvent::InterfaceUp(metadata)).await;
             Ok(Response::new(()))
         }
 
         async fn route_up_inner(
             &self,
-            request: Request<EventDetails>,
+            request: Request<TunnelMetadataEvent>,
         ) -> std::result::Result<Response<()>, tonic::Status> {
-            let env = request.into_inner().env;
+            let metadata = Self::get_tunnel_metadata(request.into_inner())?;
 
             let _ = tokio::fs::remove_file(&self.user_pass_file_path).await;
             if let Some(ref file_path) = &self.proxy_auth_file_path {
@@ -899,11 +980,7 @@ mod event_server {
                 let route_handle = self.route_manager_handle.clone();
                 let ipv6_enabled = self.ipv6_enabled;
 
-                let routes = super::extract_routes(&env)
-                    .map_err(|err| {
-                        log::error!("{}", err.display_chain_with_msg("Failed to obtain routes"));
-                        tonic::Status::failed_precondition("Failed to obtain routes")
-                    })?
+                let routes = super::extract_routes(&metadata.interface)
                     .into_iter()
                     .filter(|route| route.prefix.is_ipv4() || ipv6_enabled)
                     .collect();
@@ -918,16 +995,14 @@ mod event_server {
                 }
             }
 
-            let metadata = Self::get_tunnel_metadata(&env)?;
-
             #[cfg(windows)]
             {
                 let tunnel_device = metadata.interface.clone();
-                let luid = crate::windows::luid_from_alias(tunnel_device).map_err(|error| {
+                let luid = talpid_windows_net::luid_from_alias(tunnel_device).map_err(|error| {
                     log::error!("{}", error.display_chain_with_msg("luid_from_alias failed"));
                     tonic::Status::unavailable("failed to obtain interface luid")
                 })?;
-                crate::windows::wait_for_addresses(luid)
+                talpid_windows_net::wait_for_addresses(luid)
                     .await
                     .map_err(|error| {
                         log::error!(
@@ -944,38 +1019,28 @@ mod event_server {
         }
 
         fn get_tunnel_metadata(
-            env: &HashMap<String, String>,
+            event: TunnelMetadataEvent,
         ) -> std::result::Result<TunnelMetadata, tonic::Status> {
-            let tunnel_alias = env
-                .get("dev")
-                .ok_or(tonic::Status::invalid_argument("missing tunnel alias"))?
-                .to_string();
-
-            let mut ips = vec![env
-                .get("ifconfig_local")
-                .ok_or(tonic::Status::invalid_argument(
-                    "missing \"ifconfig_local\" in up event",
-                ))?
+            if event.interface.is_empty() {
+                return Err(tonic::Status::invalid_argument("missing tunnel alias"));
+            }
+
+            let mut ips = vec![event
+                .ipv4_address
                 .parse()
                 .map_err(|_| tonic::Status::invalid_argument("Invalid tunnel IPv4 address"))?];
-            if let Some(ipv6_address) = env.get("ifconfig_ipv6_local") {
+            if !event.ipv6_address.is_empty() {
                 ips.push(
-                    ipv6_address.parse().map_err(|_| {
+                    event.ipv6_address.parse().map_err(|_| {
                         tonic::Status::invalid_argument("Invalid tunnel IPv6 address")
                     })?,
                 );
             }
-            let ipv4_gateway = env
-                .get("route_vpn_gateway")
-                .ok_or(tonic::Status::invalid_argument(
-                    "No \"route_vpn_gateway\" in tunnel up event",
-                ))?
-                .parse()
-                .map_err(|_| {
-                    tonic::Status::invalid_argument("Invalid tunnel gateway IPv4 address")
-                })?;
-            let ipv6_gateway = if let Some(ipv6_address) = env.get("route_ipv6_gateway_1") {
-                Some(ipv6_address.parse().map_err(|_| {
+            let ipv4_gateway = event.ipv4_gateway.parse().map_err(|_| {
+                tonic::Status::invalid_argument("Invalid tunnel gateway IPv4 address")
+            })?;
+            let ipv6_gateway = if !event.ipv6_gateway.is_empty() {
+                Some(event.ipv6_gateway.parse().map_err(|_| {
                     tonic::Status::invalid_argument("Invalid tunnel gateway IPv6 address")
                 })?)
             } else {
@@ -983,7 +1048,7 @@ mod event_server {
             };
 
             Ok(TunnelMetadata {
-                interface: tunnel_alias,
+                interface: event.interface,
                 ips,
                 ipv4_gateway,
                 ipv6_gateway,
@@ -1004,19 +1069,21 @@ mod event_server {
     {
         async fn auth_failed(
             &self,
-            request: Request<EventDetails>,
+            request: Request<AuthFailedEvent>,
         ) -> std::result::Result<Response<()>, tonic::Status> {
-            let env = request.into_inner().env;
-            (self.on_event)(super::TunnelEvent::AuthFailed(
-                env.get("auth_failed_reason").cloned(),
-            ))
-            .await;
+            let reason = request.into_inner().reason;
+            let reason = if reason.is_empty() {
+                None
+            } else {
+                Some(reason)
+            };
+            (self.on_event)(super::TunnelEvent::AuthFailed(reason)).await;
             Ok(Response::new(()))
         }
 
         async fn up(
             &self,
-            request: Request<EventDetails>,
+            request: Request<TunnelMetadataEvent>,
         ) -> std::result::Result<Response<()>, tonic::Status> {
             self.up_inner(request).await.map_err(|error| {
                 self.abort_server_tx.trigger();
@@ -1026,7 +1093,7 @@ mod event_server {
 
         async fn route_up(
             &self,
-            request: Request<EventDetails>,
+            request: Request<TunnelMetadataEvent>,
         ) -> std::result::Result<Response<()>, tonic::Status> {
             self.route_up_inner(request).await.map_err(|error| {
                 self.abort_server_tx.trigger();
@@ -1036,7 +1103,7 @@ mod event_server {
 
         async fn route_predown(
             &self,
-            _request: Request<EventDetails>,
+            _request: Request<()>,
         ) -> std::result::Result<Response<()>, tonic::Status> {
             (self.on_event)(super::TunnelEvent::Down).await;
             Ok(Response::new(()))
@@ -1120,8 +1187,8 @@ mod tests {
     #[cfg(windows)]
     #[async_trait::async_trait]
     impl WintunContext for TestWintunContext {
-        fn luid(&self) -> NET_LUID {
-            NET_LUID { Value: 0u64 }
+        fn luid(&self) -> Luid {
+            Luid::from_raw(0)
         }
         fn ipv6(&self) -> bool {
             false
@@ -1137,25 +1204,25 @@ mod tests {
     impl event_server::OpenvpnEventProxy for TestOpenvpnEventProxy {
         async fn auth_failed(
             &self,
-            _request: tonic::Request<event_server::EventDetails>,
+            _request: tonic::Request<event_server::AuthFailedEvent>,
         ) -> std::result::Result<tonic::Response<()>, tonic::Status> {
             Ok(tonic::Response::new(()))
         }
         async fn up(
             &self,
-            _request: tonic::Request<event_server::EventDetails>,
+            _request: tonic::Request<event_server::TunnelMetadataEvent>,
         ) -> std::result::Result<tonic::Response<()>, tonic::Status> {
             Ok(tonic::Response::new(()))
         }
         async fn route_up(
             &self,
-            _request: tonic::Request<event_server::EventDetails>,
+            _request: tonic::Request<event_server::TunnelMetadataEvent>,
         ) -> std::result::Result<tonic::Response<()>, tonic::Status> {
             Ok(tonic::Response::new(()))
         }
         async fn route_predown(
             &self,
-            _request: tonic::Request<event_server::EventDetails>,
+            _request: tonic::Request<()>,
         ) -> std::result::Result<tonic::Response<()>, tonic::Status> {
             Ok(tonic::Response::new(()))
         }
@@ -1344,4 +1411,19 @@ mod tests {
             _ => panic!("Wrong error"),
         }
     }
+
+    #[test]
+    fn parses_status_traffic() {
+        let status = "OpenVPN STATISTICS\nUpdated,Thu Jan  6 12:00:00 2022\n\
+                      TUN/TAP read bytes,1024\nTUN/TAP write bytes,4096\n\
+                      TCP/UDP read bytes,5000\nTCP/UDP write bytes,2000\nEND\n";
+        assert_eq!(
+            parse_status_traffic(status),
+            Some(TrafficCounters {
+                tx_bytes: 1024,
+                rx_bytes: 4096,
+            })
+        );
+        assert_eq!(parse_status_traffic("OpenVPN STATISTICS\nEND\n"), None);
+    }
 }
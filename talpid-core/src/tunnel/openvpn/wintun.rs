@@ -1,4 +1,4 @@
-use crate::windows::{get_ip_interface_entry, set_ip_interface_entry, AddressFamily};
+use crate::windows::{guid_from_luid, string_from_guid};
 use lazy_static::lazy_static;
 use std::{
     ffi::CStr,
@@ -9,16 +9,13 @@ use std::{
     sync::{Arc, Mutex},
 };
 use talpid_types::ErrorExt;
+use talpid_windows_net::{prepare_tunnel_interface, AddressFamily, Luid};
 use widestring::{U16CStr, U16CString};
 use winapi::{
     shared::{
         guiddef::GUID,
-        ifdef::NET_LUID,
-        minwindef::{BOOL, FARPROC, HINSTANCE, HMODULE},
-        netioapi::ConvertInterfaceLuidToGuid,
-        nldef::RouterDiscoveryDisabled,
-        ntdef::FALSE,
-        winerror::NO_ERROR,
+        minwindef::{BOOL, FARPROC, HINSTANCE, HMODULE, LPARAM},
+        winerror::ERROR_NOT_FOUND,
     },
     um::{
         libloaderapi::{
@@ -61,7 +58,15 @@ type WintunDeleteAdapterFn = unsafe extern "stdcall" fn(
 type WintunGetAdapterNameFn =
     unsafe extern "stdcall" fn(adapter: RawHandle, name: *mut u16) -> BOOL;
 
-type WintunGetAdapterLuidFn = unsafe extern "stdcall" fn(adapter: RawHandle, luid: *mut NET_LUID);
+type WintunGetAdapterLuidFn = unsafe extern "stdcall" fn(adapter: RawHandle, luid: *mut Luid);
+
+type WintunEnumCallbackFn = extern "stdcall" fn(adapter: RawHandle, param: LPARAM) -> BOOL;
+
+type WintunEnumAdaptersFn = unsafe extern "stdcall" fn(
+    pool: *const u16,
+    callback: WintunEnumCallbackFn,
+    param: LPARAM,
+) -> BOOL;
 
 type WintunLoggerCbFn = extern "stdcall" fn(WintunLoggerLevel, *const u16);
 
@@ -83,6 +88,7 @@ pub struct WintunDll {
     func_delete: WintunDeleteAdapterFn,
     func_get_adapter_name: WintunGetAdapterNameFn,
     func_get_adapter_luid: WintunGetAdapterLuidFn,
+    func_enum_adapters: WintunEnumAdaptersFn,
     func_set_logger: WintunSetLoggerFn,
 }
 
@@ -188,14 +194,11 @@ impl WintunAdapter {
         // Disable DAD, DHCP, and router discovery
         let luid = self.luid();
         for family in &[AddressFamily::Ipv4, AddressFamily::Ipv6] {
-            if let Ok(mut row) = get_ip_interface_entry(*family, &luid) {
-                row.SitePrefixLength = 0;
-                row.RouterDiscoveryBehavior = RouterDiscoveryDisabled;
-                row.DadTransmits = 0;
-                row.ManagedAddressConfigurationSupported = FALSE;
-                row.OtherStatefulConfigurationSupported = FALSE;
-
-                if let Err(error) = set_ip_interface_entry(&row) {
+            match prepare_tunnel_interface(luid, *family, None) {
+                Ok(()) => (),
+                // The IP interface is missing if the family is disabled
+                Err(error) if error.raw_os_error() == Some(ERROR_NOT_FOUND as i32) => (),
+                Err(error) => {
                     log::error!(
                         "{} (family: {})",
                         error.display_chain_with_msg("Failed to update Wintun interface"),
@@ -217,17 +220,12 @@ impl WintunAdapter {
         unsafe { self.dll_handle.get_adapter_name(self.handle) }
     }
 
-    pub fn luid(&self) -> NET_LUID {
+    pub fn luid(&self) -> Luid {
         unsafe { self.dll_handle.get_adapter_luid(self.handle) }
     }
 
     pub fn guid(&self) -> io::Result<GUID> {
-        let mut guid = mem::MaybeUninit::zeroed();
-        let result = unsafe { ConvertInterfaceLuidToGuid(&self.luid(), guid.as_mut_ptr()) };
-        if result != NO_ERROR {
-            return Err(io::Error::from_raw_os_error(result as i32));
-        }
-        Ok(unsafe { guid.assume_init() })
+        guid_from_luid(self.luid())
     }
 
     fn restore_missing_component_id(&self) {
@@ -282,6 +280,12 @@ impl Drop for WintunAdapter {
 }
 
 impl WintunDll {
+    /// Forgets the shared Wintun DLL, so that it is loaded again by the next call to `instance`.
+    /// The library is unloaded once every adapter using it has been closed.
+    pub fn unload_instance() {
+        *(*WINTUN_DLL).lock().expect("Wintun mutex poisoned") = None;
+    }
+
     pub fn instance(resource_dir: &Path) -> io::Result<Arc<Self>> {
         let mut dll = (*WINTUN_DLL).lock().expect("Wintun mutex poisoned");
         match &*dll {
@@ -352,6 +356,12 @@ impl WintunDll {
                     CStr::from_bytes_with_nul(b"WintunGetAdapterLUID\0").unwrap(),
                 )?) as *const _ as *const _)
             },
+            func_enum_adapters: unsafe {
+                *((&get_proc_fn(
+                    handle,
+                    CStr::from_bytes_with_nul(b"WintunEnumAdapters\0").unwrap(),
+                )?) as *const _ as *const _)
+            },
             func_set_logger: unsafe {
                 *((&get_proc_fn(
                     handle,
@@ -424,12 +434,49 @@ impl WintunDll {
         Ok(U16CString::from_vec_truncate(alias_buffer))
     }
 
-    pub unsafe fn get_adapter_luid(&self, adapter: RawHandle) -> NET_LUID {
-        let mut luid = mem::MaybeUninit::<NET_LUID>::zeroed();
+    pub unsafe fn get_adapter_luid(&self, adapter: RawHandle) -> Luid {
+        let mut luid = mem::MaybeUninit::<Luid>::zeroed();
         (self.func_get_adapter_luid)(adapter, luid.as_mut_ptr());
         luid.assume_init()
     }
 
+    /// Returns the names of all adapters in the given pool.
+    pub fn adapter_names(&self, pool: &U16CStr) -> io::Result<Vec<U16CString>> {
+        struct Context<'a> {
+            dll: &'a WintunDll,
+            names: Vec<U16CString>,
+        }
+
+        extern "stdcall" fn callback(adapter: RawHandle, param: LPARAM) -> BOOL {
+            let context = unsafe { &mut *(param as *mut Context<'_>) };
+            // The adapter handle is only valid for the duration of the callback
+            match unsafe { context.dll.get_adapter_name(adapter) } {
+                Ok(name) => context.names.push(name),
+                Err(error) => log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to obtain Wintun adapter name")
+                ),
+            }
+            1
+        }
+
+        let mut context = Context {
+            dll: self,
+            names: vec![],
+        };
+        let result = unsafe {
+            (self.func_enum_adapters)(
+                pool.as_ptr(),
+                callback,
+                &mut context as *mut Context<'_> as LPARAM,
+            )
+        };
+        if result == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(context.names)
+    }
+
     pub fn activate_logging(self: &Arc<Self>) -> WintunLoggerHandle {
         WintunLoggerHandle::from_handle(self.clone())
     }
@@ -483,22 +530,44 @@ impl Drop for WintunLoggerHandle {
     }
 }
 
-/// Obtain a string representation for a GUID object.
-fn string_from_guid(guid: &GUID) -> String {
-    use std::{ffi::OsString, os::windows::ffi::OsStringExt};
-    use winapi::um::combaseapi::StringFromGUID2;
-
-    let mut buffer = [0u16; 40];
-    let length = unsafe { StringFromGUID2(guid, &mut buffer[0] as *mut _, buffer.len() as i32 - 1) }
-        as usize;
-    if length > 0 {
-        let length = length - 1;
-        OsString::from_wide(&buffer[0..length])
-            .to_string_lossy()
-            .to_string()
-    } else {
-        "".to_string()
+/// Deletes every adapter in the given pool. Sessions are not force-closed, so adapters that are
+/// still in use are left alone. Returns the names of the deleted adapters.
+pub fn delete_pool_adapters(
+    dll_handle: Arc<WintunDll>,
+    pool: &U16CStr,
+) -> io::Result<(Vec<String>, RebootRequired)> {
+    let mut deleted = vec![];
+    let mut reboot_required = false;
+
+    for name in dll_handle.adapter_names(pool)? {
+        let adapter = match WintunAdapter::open(dll_handle.clone(), pool, &name) {
+            Ok(adapter) => adapter,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to open Wintun adapter")
+                );
+                continue;
+            }
+        };
+        match adapter.delete(false) {
+            Ok(adapter_reboot_required) => {
+                reboot_required |= adapter_reboot_required;
+                deleted.push(name.to_string_lossy());
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Failed to delete Wintun adapter \"{}\"",
+                        name.to_string_lossy()
+                    ))
+                );
+            }
+        }
     }
+
+    Ok((deleted, reboot_required))
 }
 
 /// Returns the registry key for a network device identified by its GUID.
@@ -541,35 +610,4 @@ mod tests {
     fn test_wintun_imports() {
         WintunDll::new_inner(ptr::null_mut(), get_proc_fn).unwrap();
     }
-
-    #[test]
-    fn guid_to_string() {
-        let guids = [
-            (
-                "{AFE43773-E1F8-4EBB-8536-576AB86AFE9A}",
-                GUID {
-                    Data1: 0xAFE43773,
-                    Data2: 0xE1F8,
-                    Data3: 0x4EBB,
-                    Data4: [0x85, 0x36, 0x57, 0x6A, 0xB8, 0x6A, 0xFE, 0x9A],
-                },
-            ),
-            (
-                "{00000000-0000-0000-0000-000000000000}",
-                GUID {
-                    Data1: 0,
-                    Data2: 0,
-                    Data3: 0,
-                    Data4: [0; 8],
-                },
-            ),
-        ];
-
-        for (expected_str, guid) in &guids {
-            assert_eq!(
-                string_from_guid(guid).as_str().to_lowercase(),
-                expected_str.to_lowercase()
-            );
-        }
-    }
 }
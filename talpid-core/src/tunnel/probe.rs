@@ -0,0 +1,415 @@
+//! Checks whether the first hop of a tunnel responds at all before the tunnel is started, so that
+//! an unreachable relay can be replaced without waiting out the timeouts of the tunnel.
+
+use blake2::{digest::VariableOutput, Blake2s, Digest, VarBlake2s};
+use futures::{channel::oneshot, future::Fuse, FutureExt};
+use rand::RngCore;
+use ring::aead;
+use std::{
+    io,
+    net::SocketAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use talpid_types::net::{
+    openvpn,
+    wireguard::{self, PrivateKey, PublicKey},
+    Endpoint, TransportProtocol, TunnelParameters,
+};
+use tokio::{
+    net::{TcpSocket, UdpSocket},
+    task::JoinHandle,
+};
+
+/// How long to wait for the relay to respond.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Opcode of an OpenVPN `P_CONTROL_HARD_RESET_CLIENT_V2` packet, shifted into place for key ID 0.
+const OPCODE_HARD_RESET_CLIENT_V2: u8 = 7 << 3;
+
+const NOISE_CONSTRUCTION: &[u8] = b"Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s";
+const WIREGUARD_IDENTIFIER: &[u8] = b"WireGuard v1 zx2c4 Jason@zx2c4.com";
+const WIREGUARD_LABEL_MAC1: &[u8] = b"mac1----";
+
+const MESSAGE_HANDSHAKE_INITIATION: u8 = 1;
+const MESSAGE_HANDSHAKE_RESPONSE: u8 = 2;
+const MESSAGE_COOKIE_REPLY: u8 = 3;
+
+const HANDSHAKE_INITIATION_LEN: usize = 148;
+const AEAD_TAG_LEN: usize = 16;
+const BLAKE2S_BLOCK_LEN: usize = 64;
+
+/// TAI64 label of the Unix epoch.
+const TAI64_EPOCH: u64 = 0x400000000000000a;
+
+/// A probe of the first hop of a tunnel, running in the background. The probe is aborted when
+/// this is dropped.
+pub struct RelayProbe {
+    task: JoinHandle<()>,
+    result: Fuse<oneshot::Receiver<io::Result<()>>>,
+}
+
+impl RelayProbe {
+    /// Starts probing the endpoint that the tunnel connects to first. Returns `None` if the
+    /// tunnel goes through a proxy or an obfuscation layer, since there is nothing to probe then.
+    pub fn spawn(runtime: &tokio::runtime::Handle, params: &TunnelParameters) -> Option<Self> {
+        let target = ProbeTarget::from_parameters(params)?;
+        let (result_tx, result_rx) = oneshot::channel();
+        let task = runtime.spawn(async move {
+            let result = tokio::time::timeout(PROBE_TIMEOUT, target.probe())
+                .await
+                .unwrap_or_else(|_| {
+                    Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "the relay did not respond",
+                    ))
+                });
+            let _ = result_tx.send(result);
+        });
+        Some(RelayProbe {
+            task,
+            result: result_rx.fuse(),
+        })
+    }
+
+    /// Waits for the probe to finish.
+    pub async fn result(&mut self) -> io::Result<()> {
+        (&mut self.result).await.unwrap_or(Ok(()))
+    }
+}
+
+impl Drop for RelayProbe {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+enum ProbeTarget {
+    Tcp(SocketAddr),
+    OpenVpnUdp(SocketAddr),
+    Wireguard {
+        address: SocketAddr,
+        private_key: PrivateKey,
+        peer_public_key: PublicKey,
+    },
+}
+
+impl ProbeTarget {
+    fn from_parameters(params: &TunnelParameters) -> Option<Self> {
+        match params {
+            TunnelParameters::OpenVpn(params) => Self::from_openvpn_parameters(params),
+            TunnelParameters::Wireguard(params) => Self::from_wireguard_parameters(params),
+        }
+    }
+
+    fn from_openvpn_parameters(params: &openvpn::TunnelParameters) -> Option<Self> {
+        let endpoint = match &params.proxy {
+            Some(openvpn::ProxySettings::Local(_)) => return None,
+            Some(proxy) => proxy.get_endpoint().endpoint,
+            None => params.config.endpoint,
+        };
+        Some(match endpoint {
+            Endpoint {
+                address,
+                protocol: TransportProtocol::Tcp,
+            } => ProbeTarget::Tcp(address),
+            Endpoint {
+                address,
+                protocol: TransportProtocol::Udp,
+            } => ProbeTarget::OpenVpnUdp(address),
+        })
+    }
+
+    fn from_wireguard_parameters(params: &wireguard::TunnelParameters) -> Option<Self> {
+        let peer = &params.connection.peer;
+        if params.entry_proxy.is_some() || peer.protocol != TransportProtocol::Udp {
+            return None;
+        }
+        Some(ProbeTarget::Wireguard {
+            address: peer.endpoint,
+            private_key: params.connection.tunnel.private_key.clone(),
+            peer_public_key: peer.public_key.clone(),
+        })
+    }
+
+    async fn probe(self) -> io::Result<()> {
+        match self {
+            ProbeTarget::Tcp(address) => probe_tcp(address).await,
+            ProbeTarget::OpenVpnUdp(address) => probe_openvpn_udp(address).await,
+            ProbeTarget::Wireguard {
+                address,
+                private_key,
+                peer_public_key,
+            } => probe_wireguard(address, &private_key, &peer_public_key).await,
+        }
+    }
+}
+
+/// Completes a TCP handshake with the relay.
+async fn probe_tcp(address: SocketAddr) -> io::Result<()> {
+    let socket = if address.is_ipv4() {
+        TcpSocket::new_v4()
+    } else {
+        TcpSocket::new_v6()
+    }?;
+
+    #[cfg(target_os = "linux")]
+    socket2::SockRef::from(&socket).set_mark(crate::linux::TUNNEL_FW_MARK)?;
+
+    socket.connect(address).await.map(|_| ())
+}
+
+async fn connect_udp(address: SocketAddr) -> io::Result<UdpSocket> {
+    let bind_address: SocketAddr = if address.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(bind_address).await?;
+
+    #[cfg(target_os = "linux")]
+    socket2::SockRef::from(&socket).set_mark(crate::linux::TUNNEL_FW_MARK)?;
+
+    socket.connect(address).await?;
+    Ok(socket)
+}
+
+/// Sends the first packet of an OpenVPN session to the relay and waits for any reply. The relays
+/// do not use `tls-auth`, so they respond to an unauthenticated reset.
+async fn probe_openvpn_udp(address: SocketAddr) -> io::Result<()> {
+    let socket = connect_udp(address).await?;
+    socket.send(&hard_reset_packet()).await?;
+
+    let mut reply = [0u8; 1500];
+    socket.recv(&mut reply).await.map(|_| ())
+}
+
+/// Sends a WireGuard handshake initiation to the relay and waits for a handshake response or a
+/// cookie reply. The relay only answers initiations from keys that it knows about.
+async fn probe_wireguard(
+    address: SocketAddr,
+    private_key: &PrivateKey,
+    peer_public_key: &PublicKey,
+) -> io::Result<()> {
+    let socket = connect_udp(address).await?;
+    let initiation = handshake_initiation(
+        private_key,
+        peer_public_key,
+        &PrivateKey::new_from_random(),
+        rand::random(),
+        tai64n(SystemTime::now()),
+    );
+    socket.send(&initiation).await?;
+
+    let mut reply = [0u8; 1500];
+    loop {
+        let len = socket.recv(&mut reply).await?;
+        if len > 0 && [MESSAGE_HANDSHAKE_RESPONSE, MESSAGE_COOKIE_REPLY].contains(&reply[0]) {
+            return Ok(());
+        }
+    }
+}
+
+/// Returns a `P_CONTROL_HARD_RESET_CLIENT_V2` packet with a random session ID, an empty ACK array
+/// and message packet ID 0.
+fn hard_reset_packet() -> [u8; 14] {
+    let mut packet = [0u8; 14];
+    packet[0] = OPCODE_HARD_RESET_CLIENT_V2;
+    rand::thread_rng().fill_bytes(&mut packet[1..9]);
+    packet
+}
+
+/// Builds the first message of the Noise IK handshake that WireGuard uses. No preshared key or
+/// cookie is involved, so `mac2` is left empty.
+fn handshake_initiation(
+    private_key: &PrivateKey,
+    peer_public_key: &PublicKey,
+    ephemeral_key: &PrivateKey,
+    sender_index: u32,
+    timestamp: [u8; 12],
+) -> [u8; HANDSHAKE_INITIATION_LEN] {
+    let mut message = [0u8; HANDSHAKE_INITIATION_LEN];
+    message[0] = MESSAGE_HANDSHAKE_INITIATION;
+    message[4..8].copy_from_slice(&sender_index.to_le_bytes());
+
+    let chaining_key = hash(&[NOISE_CONSTRUCTION]);
+    let handshake_hash = hash(&[&chaining_key, WIREGUARD_IDENTIFIER]);
+    let handshake_hash = hash(&[&handshake_hash, peer_public_key.as_bytes()]);
+
+    let ephemeral_public_key = ephemeral_key.public_key();
+    message[8..40].copy_from_slice(ephemeral_public_key.as_bytes());
+    let chaining_key = kdf1(&chaining_key, ephemeral_public_key.as_bytes());
+    let handshake_hash = hash(&[&handshake_hash, ephemeral_public_key.as_bytes()]);
+
+    let (chaining_key, key) = kdf2(
+        &chaining_key,
+        &ephemeral_key.diffie_hellman(peer_public_key),
+    );
+    seal(
+        &key,
+        &handshake_hash,
+        private_key.public_key().as_bytes(),
+        &mut message[40..88],
+    );
+    let handshake_hash = hash(&[&handshake_hash, &message[40..88]]);
+
+    let (_, key) = kdf2(&chaining_key, &private_key.diffie_hellman(peer_public_key));
+    seal(&key, &handshake_hash, &timestamp, &mut message[88..116]);
+
+    let mac1_key = hash(&[WIREGUARD_LABEL_MAC1, peer_public_key.as_bytes()]);
+    let mac1 = mac(&mac1_key, &message[..116]);
+    message[116..132].copy_from_slice(&mac1);
+
+    message
+}
+
+fn tai64n(time: SystemTime) -> [u8; 12] {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut timestamp = [0u8; 12];
+    timestamp[..8].copy_from_slice(&(TAI64_EPOCH + since_epoch.as_secs()).to_be_bytes());
+    timestamp[8..].copy_from_slice(&since_epoch.subsec_nanos().to_be_bytes());
+    timestamp
+}
+
+fn hash(inputs: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Blake2s::new();
+    for input in inputs {
+        hasher.update(input);
+    }
+    let mut output = [0u8; 32];
+    output.copy_from_slice(&hasher.finalize());
+    output
+}
+
+fn mac(key: &[u8; 32], input: &[u8]) -> [u8; 16] {
+    let mut hasher = VarBlake2s::new_keyed(key, 16);
+    blake2::digest::Update::update(&mut hasher, input);
+    let mut output = [0u8; 16];
+    hasher.finalize_variable(|result| output.copy_from_slice(result));
+    output
+}
+
+fn hmac(key: &[u8; 32], inputs: &[&[u8]]) -> [u8; 32] {
+    let mut inner_key = [0x36u8; BLAKE2S_BLOCK_LEN];
+    let mut outer_key = [0x5cu8; BLAKE2S_BLOCK_LEN];
+    for (i, byte) in key.iter().enumerate() {
+        inner_key[i] ^= byte;
+        outer_key[i] ^= byte;
+    }
+    let mut inner = Vec::with_capacity(inputs.len() + 1);
+    inner.push(&inner_key[..]);
+    inner.extend_from_slice(inputs);
+    hash(&[&outer_key, &hash(&inner)])
+}
+
+fn kdf1(key: &[u8; 32], input: &[u8]) -> [u8; 32] {
+    let secret = hmac(key, &[input]);
+    hmac(&secret, &[&[1]])
+}
+
+fn kdf2(key: &[u8; 32], input: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let secret = hmac(key, &[input]);
+    let first = hmac(&secret, &[&[1]]);
+    let second = hmac(&secret, &[&first, &[2]]);
+    (first, second)
+}
+
+/// Encrypts `plaintext` into `output` with a zero nonce, followed by the authentication tag.
+fn seal(key: &[u8; 32], additional_data: &[u8], plaintext: &[u8], output: &mut [u8]) {
+    let key = aead::LessSafeKey::new(
+        aead::UnboundKey::new(&aead::CHACHA20_POLY1305, key).expect("key has the correct length"),
+    );
+    debug_assert_eq!(output.len(), plaintext.len() + AEAD_TAG_LEN);
+    let (ciphertext, tag) = output.split_at_mut(plaintext.len());
+    ciphertext.copy_from_slice(plaintext);
+    let computed_tag = key
+        .seal_in_place_separate_tag(
+            aead::Nonce::assume_unique_for_key([0u8; 12]),
+            aead::Aad::from(additional_data),
+            ciphertext,
+        )
+        .expect("message is short enough");
+    tag.copy_from_slice(computed_tag.as_ref());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hard_reset_packet() {
+        let packet = hard_reset_packet();
+        assert_eq!(packet[0], 0x38);
+        // Empty ACK array followed by message packet ID 0
+        assert_eq!(packet[9..], [0u8; 5]);
+        assert_ne!(packet[1..9], hard_reset_packet()[1..9]);
+    }
+
+    #[test]
+    fn test_handshake_initiation_layout() {
+        let private_key = PrivateKey::new_from_random();
+        let peer_public_key = PrivateKey::new_from_random().public_key();
+        let ephemeral_key = PrivateKey::new_from_random();
+        let message = handshake_initiation(
+            &private_key,
+            &peer_public_key,
+            &ephemeral_key,
+            0x01020304,
+            tai64n(SystemTime::now()),
+        );
+
+        assert_eq!(message[..8], [1, 0, 0, 0, 4, 3, 2, 1]);
+        assert_eq!(message[8..40], ephemeral_key.public_key().as_bytes()[..]);
+
+        let mac1_key = hash(&[WIREGUARD_LABEL_MAC1, peer_public_key.as_bytes()]);
+        assert_eq!(message[116..132], mac(&mac1_key, &message[..116]));
+        assert_eq!(message[132..], [0u8; 16]);
+    }
+
+    /// Processes the initiation the way the relay does and checks that it recovers the static
+    /// key of the client.
+    #[test]
+    fn test_handshake_initiation_static_key() {
+        let private_key = PrivateKey::new_from_random();
+        let peer_private_key = PrivateKey::new_from_random();
+        let message = handshake_initiation(
+            &private_key,
+            &peer_private_key.public_key(),
+            &PrivateKey::new_from_random(),
+            0,
+            tai64n(SystemTime::now()),
+        );
+
+        let mut ephemeral_public_key = [0u8; 32];
+        ephemeral_public_key.copy_from_slice(&message[8..40]);
+        let ephemeral_public_key = PublicKey::from(ephemeral_public_key);
+
+        let chaining_key = hash(&[NOISE_CONSTRUCTION]);
+        let handshake_hash = hash(&[&chaining_key, WIREGUARD_IDENTIFIER]);
+        let handshake_hash = hash(&[&handshake_hash, peer_private_key.public_key().as_bytes()]);
+        let chaining_key = kdf1(&chaining_key, ephemeral_public_key.as_bytes());
+        let handshake_hash = hash(&[&handshake_hash, ephemeral_public_key.as_bytes()]);
+        let (_, key) = kdf2(
+            &chaining_key,
+            &peer_private_key.diffie_hellman(&ephemeral_public_key),
+        );
+
+        let key =
+            aead::LessSafeKey::new(aead::UnboundKey::new(&aead::CHACHA20_POLY1305, &key).unwrap());
+        let mut encrypted_static = message[40..40 + 32 + AEAD_TAG_LEN].to_vec();
+        let static_key = key
+            .open_in_place(
+                aead::Nonce::assume_unique_for_key([0u8; 12]),
+                aead::Aad::from(&handshake_hash),
+                &mut encrypted_static,
+            )
+            .unwrap();
+        assert_eq!(static_key, private_key.public_key().as_bytes());
+    }
+
+    #[test]
+    fn test_tai64n() {
+        let timestamp = tai64n(UNIX_EPOCH + Duration::new(1, 2));
+        assert_eq!(timestamp[..8], (TAI64_EPOCH + 1).to_be_bytes());
+        assert_eq!(timestamp[8..], [0, 0, 0, 2]);
+    }
+}
@@ -1,9 +1,9 @@
 use std::{
-    borrow::Cow,
-    ffi::CString,
-    net::{Ipv4Addr, Ipv6Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::raw::c_char,
 };
-use talpid_types::net::{wireguard, GenericTunnelOptions};
+use talpid_types::net::{proxy::EntryProxy, wireguard, GenericTunnelOptions};
+use zeroize::Zeroizing;
 
 /// Config required to set up a single WireGuard tunnel
 pub struct Config {
@@ -11,12 +11,14 @@ pub struct Config {
     pub tunnel: wireguard::TunnelConfig,
     /// List of peer configurations
     pub peers: Vec<wireguard::PeerConfig>,
-    /// IPv4 gateway
+    /// IPv4 gateway. Only reachable if the tunnel has an IPv4 address
     pub ipv4_gateway: Ipv4Addr,
     /// IPv6 gateway
     pub ipv6_gateway: Option<Ipv6Addr>,
     /// Maximum transmission unit for the tunnel
     pub mtu: u16,
+    /// SOCKS5 proxy that the entry peer is reached through
+    pub entry_proxy: Option<EntryProxy>,
     /// Firewall mark
     #[cfg(target_os = "linux")]
     pub fwmark: u32,
@@ -26,9 +28,20 @@ pub struct Config {
     /// Temporary switch for wireguard-nt
     #[cfg(target_os = "windows")]
     pub use_wireguard_nt: bool,
+    /// Name of the tunnel interface
+    #[cfg(any(target_os = "linux", windows))]
+    pub interface_name: String,
 }
 
-const DEFAULT_MTU: u16 = 1380;
+/// MTU of the tunnel interface unless another MTU is configured.
+pub const DEFAULT_MTU: u16 = 1380;
+
+/// Name of the tunnel interface unless another name is configured.
+#[cfg(target_os = "linux")]
+pub const DEFAULT_INTERFACE_NAME: &str = "wg-mullvad";
+/// Name of the tunnel interface unless another name is configured.
+#[cfg(windows)]
+pub const DEFAULT_INTERFACE_NAME: &str = "Mullvad";
 
 /// Configuration errors
 #[derive(err_derive::Error, Debug)]
@@ -44,6 +57,10 @@ pub enum Error {
     /// Parameters don't contain any peers
     #[error(display = "No peers supplied")]
     NoPeersSuppliedError,
+
+    /// The tunnel only has IPv6 addresses, but there is no IPv6 gateway
+    #[error(display = "No IPv6 gateway supplied for an IPv6-only tunnel")]
+    NoIpv6GatewayError,
 }
 
 impl Config {
@@ -60,6 +77,7 @@ impl Config {
             &params.connection,
             &params.options,
             &params.generic_options,
+            params.entry_proxy.clone(),
         )
     }
 
@@ -70,6 +88,7 @@ impl Config {
         connection_config: &wireguard::ConnectionConfig,
         wg_options: &wireguard::TunnelOptions,
         generic_options: &GenericTunnelOptions,
+        entry_proxy: Option<EntryProxy>,
     ) -> Result<Config, Error> {
         if peers.is_empty() {
             return Err(Error::NoPeersSuppliedError);
@@ -87,20 +106,23 @@ impl Config {
             }
         }
 
-        if tunnel.addresses.is_empty() {
-            return Err(Error::InvalidTunnelIpError);
-        }
         tunnel.addresses = tunnel
             .addresses
             .into_iter()
             .filter(|ip| ip.is_ipv4() || generic_options.enable_ipv6)
             .collect();
+        if tunnel.addresses.is_empty() {
+            return Err(Error::InvalidTunnelIpError);
+        }
 
         let ipv6_gateway = if generic_options.enable_ipv6 {
             connection_config.ipv6_gateway
         } else {
             None
         };
+        if !tunnel.addresses.iter().any(|ip| ip.is_ipv4()) && ipv6_gateway.is_none() {
+            return Err(Error::NoIpv6GatewayError);
+        }
 
         Ok(Config {
             tunnel,
@@ -108,22 +130,48 @@ impl Config {
             ipv4_gateway: connection_config.ipv4_gateway,
             ipv6_gateway,
             mtu,
+            entry_proxy,
             #[cfg(target_os = "linux")]
             fwmark: crate::linux::TUNNEL_FW_MARK,
             #[cfg(target_os = "linux")]
             enable_ipv6: generic_options.enable_ipv6,
             #[cfg(target_os = "windows")]
             use_wireguard_nt: wg_options.use_wireguard_nt,
+            #[cfg(any(target_os = "linux", windows))]
+            interface_name: wg_options
+                .interface_name
+                .clone()
+                .unwrap_or_else(|| DEFAULT_INTERFACE_NAME.to_owned()),
         })
     }
 
-    /// Returns a CString with the appropriate config for WireGuard-go
+    /// Returns whether the tunnel interface is assigned an IPv4 address
+    pub fn has_ipv4(&self) -> bool {
+        self.tunnel.addresses.iter().any(|ip| ip.is_ipv4())
+    }
+
+    /// Returns whether the tunnel interface is assigned an IPv6 address
+    pub fn has_ipv6(&self) -> bool {
+        self.tunnel.addresses.iter().any(|ip| ip.is_ipv6())
+    }
+
+    /// Returns the gateway used for connectivity checks and, by default, DNS. This is the IPv4
+    /// gateway unless the tunnel only has IPv6 addresses.
+    pub fn primary_gateway(&self) -> IpAddr {
+        match self.ipv6_gateway {
+            Some(ipv6_gateway) if !self.has_ipv4() => IpAddr::V6(ipv6_gateway),
+            _ => IpAddr::V4(self.ipv4_gateway),
+        }
+    }
+
+    /// Returns the appropriate config for WireGuard-go
     // TODO: Consider outputting both overriding and additive configs
-    pub fn to_userspace_format(&self) -> CString {
+    pub fn to_userspace_format(&self) -> UserspaceConfig {
         // the order of insertion matters, public key entry denotes a new peer entry
+        let private_key = Zeroizing::new(self.tunnel.private_key.to_bytes());
         let mut wg_conf = WgConfigBuffer::new();
         wg_conf
-            .add("private_key", self.tunnel.private_key.to_bytes().as_ref())
+            .add("private_key", &private_key[..])
             .add("listen_port", "0");
 
         #[cfg(target_os = "linux")]
@@ -141,8 +189,23 @@ impl Config {
             }
         }
 
-        let bytes = wg_conf.into_config();
-        CString::new(bytes).expect("null bytes inside config")
+        wg_conf.into_config()
+    }
+}
+
+/// A null-terminated config string for WireGuard-go. Since it contains the private key, the
+/// buffer is zeroed when it is dropped.
+pub struct UserspaceConfig(Zeroizing<Vec<u8>>);
+
+impl UserspaceConfig {
+    /// Returns a pointer to the null-terminated config string.
+    pub fn as_ptr(&self) -> *const c_char {
+        self.0.as_ptr() as *const c_char
+    }
+
+    #[cfg(test)]
+    fn as_bytes(&self) -> &[u8] {
+        &self.0[..self.0.len() - 1]
     }
 }
 
@@ -163,34 +226,78 @@ impl<'a> From<&'a [u8]> for ConfValue<'a> {
     }
 }
 
-impl<'a> ConfValue<'a> {
-    fn to_bytes(&self) -> Cow<'a, [u8]> {
-        match self {
-            ConfValue::String(s) => s.as_bytes().into(),
-            ConfValue::Bytes(bytes) => Cow::Owned(hex::encode(bytes).into_bytes()),
-        }
-    }
-}
-
+/// Builds the config string. The buffer is never reallocated in place, so that no copies of the
+/// private key are left in freed memory.
 struct WgConfigBuffer {
-    buf: Vec<u8>,
+    buf: Zeroizing<Vec<u8>>,
 }
 
 impl WgConfigBuffer {
     pub fn new() -> WgConfigBuffer {
-        WgConfigBuffer { buf: Vec::new() }
+        WgConfigBuffer {
+            buf: Zeroizing::new(Vec::with_capacity(1024)),
+        }
     }
 
     pub fn add<'a, C: Into<ConfValue<'a>> + 'a>(&mut self, key: &str, value: C) -> &mut Self {
+        match value.into() {
+            ConfValue::String(s) => self.add_entry(key, s.as_bytes()),
+            ConfValue::Bytes(bytes) => {
+                let encoded = Zeroizing::new(hex::encode(bytes));
+                self.add_entry(key, encoded.as_bytes())
+            }
+        }
+        self
+    }
+
+    fn add_entry(&mut self, key: &str, value: &[u8]) {
+        self.reserve(key.len() + value.len() + 2);
         self.buf.extend(key.as_bytes());
         self.buf.extend(b"=");
-        self.buf.extend(value.into().to_bytes().as_ref());
+        self.buf.extend(value);
         self.buf.extend(b"\n");
-        self
     }
 
-    pub fn into_config(mut self) -> Vec<u8> {
-        self.buf.push(b'\n');
-        self.buf
+    /// Moves the contents to a larger buffer if `additional` bytes do not fit. The old buffer is
+    /// zeroed when it is dropped.
+    fn reserve(&mut self, additional: usize) {
+        let required = self.buf.len() + additional;
+        if required > self.buf.capacity() {
+            let mut buf = Vec::with_capacity(std::cmp::max(required, 2 * self.buf.capacity()));
+            buf.extend_from_slice(&self.buf);
+            self.buf = Zeroizing::new(buf);
+        }
+    }
+
+    pub fn into_config(mut self) -> UserspaceConfig {
+        self.reserve(2);
+        self.buf.extend(b"\n\0");
+        assert!(
+            !self.buf[..self.buf.len() - 1].contains(&0),
+            "null bytes inside config"
+        );
+        UserspaceConfig(self.buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WgConfigBuffer;
+
+    #[test]
+    fn test_config_buffer() {
+        let mut wg_conf = WgConfigBuffer::new();
+        wg_conf.add("private_key", &[0xab; 32][..]);
+        for _ in 0..100 {
+            wg_conf.add("allowed_ip", "10.0.0.0/8");
+        }
+        let config = wg_conf.into_config();
+
+        let expected = format!(
+            "private_key={}\n{}\n",
+            "ab".repeat(32),
+            "allowed_ip=10.0.0.0/8\n".repeat(100)
+        );
+        assert_eq!(config.as_bytes(), expected.as_bytes());
     }
 }
@@ -4,12 +4,12 @@ use crate::{
 };
 use std::{
     cmp,
-    net::Ipv4Addr,
+    net::IpAddr,
     sync::{mpsc, Mutex, Weak},
     time::{Duration, Instant},
 };
 
-use super::{Tunnel, TunnelError};
+use super::{logging::LoggedEvent, Tunnel, TunnelError};
 
 /// Sleep time used when initially establishing connectivity
 const DELAY_ON_INITIAL_SETUP: Duration = Duration::from_millis(50);
@@ -34,6 +34,11 @@ const ESTABLISH_TIMEOUT_MULTIPLIER: u32 = 2;
 const MAX_ESTABLISH_TIMEOUT: Duration = PING_TIMEOUT;
 /// Number of seconds to wait between sending ICMP packets
 const SECONDS_PER_PING: Duration = Duration::from_secs(3);
+/// Maximum age of the last handshake while traffic is flowing. WireGuard renews session keys well
+/// before they are rejected after 180 seconds, so an older handshake means that renewal is
+/// failing. Once this timeout is hit, a ping will be sent every `SECONDS_PER_PING` until
+/// `PING_TIMEOUT` is reached or traffic is received.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(180);
 
 /// Connectivity monitor errors
 #[derive(err_derive::Error, Debug)]
@@ -66,6 +71,8 @@ pub enum Error {
 /// traffic for longer than `BYTES_RX_TIMEOUT`, then the monitor will start pinging.
 /// - In case that no increase in outgoing or incoming traffic has been observed for longer than
 /// `TRAFFIC_TIMEOUT`, then the monitor will start pinging as well.
+/// - In case that traffic has been sent recently but the last handshake with a peer is older than
+/// `HANDSHAKE_TIMEOUT`, then the monitor will start pinging too.
 ///
 /// Once a connection established, a connection is only considered broken once the connectivity
 /// monitor has started pinging and no traffic has been received for a duration of `PING_TIMEOUT`.
@@ -80,7 +87,7 @@ pub struct ConnectivityMonitor {
 
 impl ConnectivityMonitor {
     pub(super) fn new(
-        addr: Ipv4Addr,
+        addr: IpAddr,
         #[cfg(not(target_os = "windows"))] interface: String,
         tunnel_handle: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
         close_receiver: mpsc::Receiver<()>,
@@ -218,11 +225,33 @@ impl ConnectivityMonitor {
             .map(|tunnel| tunnel.get_tunnel_stats().map_err(Error::ConfigReadError))
     }
 
+    /// Logs the handshake and endpoint events most recently reported by the tunnel, to help
+    /// diagnose why connectivity could not be established or was lost.
+    pub(super) fn log_recent_events(&self) {
+        let events = match self.get_recent_events() {
+            Some(events) if !events.is_empty() => events,
+            _ => return,
+        };
+        let events: Vec<String> = events.iter().map(ToString::to_string).collect();
+        log::debug!("Recent tunnel events:\n{}", events.join("\n"));
+    }
+
+    fn get_recent_events(&self) -> Option<Vec<LoggedEvent>> {
+        self.tunnel_handle
+            .upgrade()?
+            .lock()
+            .ok()?
+            .as_ref()
+            .map(|tunnel| tunnel.recent_events())
+    }
+
     fn maybe_send_ping(&mut self, now: Instant) -> Result<(), Error> {
-        // Only send out a ping if we haven't received a byte in a while or no traffic has flowed
-        // in the last 2 minutes, but if a ping already has been sent out, only send one out every
-        // 3 seconds.
-        if (self.conn_state.rx_timed_out() || self.conn_state.traffic_timed_out())
+        // Only send out a ping if we haven't received a byte in a while, no traffic has flowed
+        // in the last 2 minutes or handshakes have stopped succeeding, but if a ping already has
+        // been sent out, only send one out every 3 seconds.
+        if (self.conn_state.rx_timed_out()
+            || self.conn_state.traffic_timed_out()
+            || self.conn_state.handshake_timed_out())
             && self
                 .initial_ping_timestamp
                 .map(|initial_ping_timestamp| {
@@ -379,6 +408,27 @@ impl ConnState {
         }
     }
 
+    // check if traffic is being sent but the session keys have not been renewed in time
+    pub fn handshake_timed_out(&self) -> bool {
+        match self {
+            ConnState::Connecting { .. } => false,
+            ConnState::Connected {
+                tx_timestamp,
+                stats,
+                ..
+            } => {
+                tx_timestamp.elapsed() < HANDSHAKE_TIMEOUT
+                    && stats.values().any(|stats| {
+                        stats
+                            .last_handshake
+                            .and_then(|last_handshake| last_handshake.elapsed().ok())
+                            .map(|age| age >= HANDSHAKE_TIMEOUT)
+                            .unwrap_or(false)
+                    })
+            }
+        }
+    }
+
     pub fn connected(&self) -> bool {
         match self {
             ConnState::Connected { .. } => true,
@@ -399,7 +449,7 @@ mod test {
             atomic::{AtomicBool, Ordering},
             Arc, Mutex,
         },
-        time::{Duration, Instant},
+        time::{Duration, Instant, SystemTime},
     };
 
     /// Test if a newly created ConnState won't have timed out or consider itself connected
@@ -437,6 +487,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
         conn_state.update(Instant::now(), stats);
@@ -462,6 +513,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
         conn_state.update(connect_time, stats);
@@ -471,6 +523,40 @@ mod test {
         assert!(conn_state.traffic_timed_out());
     }
 
+    /// Test if ConnState::Connected times out when traffic is sent but the last handshake is older
+    /// than HANDSHAKE_TIMEOUT
+    #[test]
+    fn test_conn_state_handshake_times_out() {
+        let start = Instant::now().checked_sub(Duration::from_secs(1)).unwrap();
+        let mut conn_state = ConnState::new(start, Default::default());
+
+        let mut stats = StatsMap::new();
+        stats.insert(
+            [0u8; 32],
+            Stats {
+                rx_bytes: 1,
+                tx_bytes: 1,
+                last_handshake: Some(SystemTime::now()),
+            },
+        );
+        conn_state.update(start, stats);
+        assert!(!conn_state.handshake_timed_out());
+
+        let mut stats = StatsMap::new();
+        stats.insert(
+            [0u8; 32],
+            Stats {
+                rx_bytes: 2,
+                tx_bytes: 2,
+                last_handshake: SystemTime::now().checked_sub(HANDSHAKE_TIMEOUT),
+            },
+        );
+        conn_state.update(Instant::now(), stats);
+
+        assert!(conn_state.connected());
+        assert!(conn_state.handshake_timed_out());
+    }
+
     /// Test if ConnState::Connected correctly times out after BYTES_RX_TIMEOUT when no incoming
     /// traffic is observed
     #[test]
@@ -486,6 +572,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 0,
+                last_handshake: None,
             },
         );
         conn_state.update(start, stats);
@@ -497,6 +584,7 @@ mod test {
             Stats {
                 rx_bytes: 1,
                 tx_bytes: 1,
+                last_handshake: None,
             },
         );
         conn_state.update(update_time, stats);
@@ -540,6 +628,7 @@ mod test {
                 stats::Stats {
                     tx_bytes: 0,
                     rx_bytes: 0,
+                    last_handshake: None,
                 },
             );
             let peers = Mutex::new(map);
@@ -564,6 +653,7 @@ mod test {
                         stats::Stats {
                             tx_bytes: 0,
                             rx_bytes: 0,
+                            last_handshake: None,
                         },
                     );
                     Ok(map)
@@ -589,6 +679,10 @@ mod test {
             "mock-tunnel".to_string()
         }
 
+        fn backend_name(&self) -> &'static str {
+            "mock"
+        }
+
         fn stop(self: Box<Self>) -> Result<(), TunnelError> {
             Ok(())
         }
@@ -622,6 +716,7 @@ mod test {
             stats::Stats {
                 tx_bytes: 0,
                 rx_bytes: 0,
+                last_handshake: None,
             },
         );
         ConnState::Connected {
@@ -720,6 +815,7 @@ mod test {
             stats::Stats {
                 tx_bytes: 0,
                 rx_bytes: 0,
+                last_handshake: None,
             },
         );
         let tunnel_stats = Mutex::new(map);
@@ -771,6 +867,7 @@ mod test {
             stats::Stats {
                 tx_bytes: 0,
                 rx_bytes: 0,
+                last_handshake: None,
             },
         );
 
@@ -1,8 +1,43 @@
+use crate::logging::{RotatingLogFile, RotationLimits};
+use futures::channel::mpsc;
 use parking_lot::Mutex;
-use std::{collections::HashMap, fmt, fs, io::Write, path::Path};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, fs,
+    io::Write,
+    path::Path,
+    time::SystemTime,
+};
+use talpid_types::tunnel::EndpointChangeDebugInfo;
 
 lazy_static::lazy_static! {
-    static ref LOG_MUTEX: Mutex<HashMap<u32, fs::File>> = Mutex::new(HashMap::new());
+    static ref LOG_MUTEX: Mutex<HashMap<u32, LogContext>> = Mutex::new(HashMap::new());
+}
+
+/// Maximum number of parsed events to remember for each tunnel.
+const MAX_EVENTS: usize = 32;
+
+struct LogContext {
+    file: Box<dyn Write + Send>,
+    events: VecDeque<LoggedEvent>,
+    endpoint_changes: EndpointChangeTracker,
+    /// Channels that receive every event as it is parsed.
+    subscribers: Vec<mpsc::UnboundedSender<WgGoEvent>>,
+}
+
+impl LogContext {
+    fn push_event(&mut self, event: WgGoEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(event).is_ok());
+        self.endpoint_changes.record(event, SystemTime::now());
+        if self.events.len() >= MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(LoggedEvent {
+            time: chrono::Local::now(),
+            event,
+        });
+    }
 }
 
 static mut LOG_CONTEXT_NEXT_ORDINAL: u32 = 0;
@@ -22,7 +57,15 @@ pub fn initialize_logging(log_path: Option<&Path>) -> Result<u32, Error> {
         let mut map = LOG_MUTEX.lock();
         let ordinal = LOG_CONTEXT_NEXT_ORDINAL;
         LOG_CONTEXT_NEXT_ORDINAL += 1;
-        map.insert(ordinal, log_file);
+        map.insert(
+            ordinal,
+            LogContext {
+                file: log_file,
+                events: VecDeque::new(),
+                endpoint_changes: EndpointChangeTracker::default(),
+                subscribers: vec![],
+            },
+        );
         ordinal
     };
 
@@ -35,8 +78,21 @@ static NULL_DEVICE: &str = "NUL";
 #[cfg(not(target_os = "windows"))]
 static NULL_DEVICE: &str = "/dev/null";
 
-fn create_log_file(log_path: Option<&Path>) -> Result<fs::File, Error> {
-    fs::File::create(log_path.unwrap_or(NULL_DEVICE.as_ref())).map_err(Error::PrepareLogFileError)
+fn create_log_file(log_path: Option<&Path>) -> Result<Box<dyn Write + Send>, Error> {
+    match log_path {
+        Some(log_path) => {
+            // Start from an empty file, and rotate it if the tunnel is up for long enough to
+            // exceed the limits.
+            fs::File::create(log_path).map_err(Error::PrepareLogFileError)?;
+            let file = RotatingLogFile::new(log_path, RotationLimits::default())
+                .map_err(Error::PrepareLogFileError)?;
+            Ok(Box::new(file))
+        }
+        None => {
+            let file = fs::File::create(NULL_DEVICE).map_err(Error::PrepareLogFileError)?;
+            Ok(Box::new(file))
+        }
+    }
 }
 
 pub fn clean_up_logging(ordinal: u32) {
@@ -44,6 +100,154 @@ pub fn clean_up_logging(ordinal: u32) {
     map.remove(&ordinal);
 }
 
+/// Returns the most recent events parsed from the log output of the given context, oldest first.
+pub fn recent_events(ordinal: u32) -> Vec<LoggedEvent> {
+    let map = LOG_MUTEX.lock();
+    map.get(&ordinal)
+        .map(|context| context.events.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Returns a stream of the events parsed from the log output of the given context from now on.
+/// The stream ends when the context is cleaned up.
+pub fn subscribe_events(ordinal: u32) -> mpsc::UnboundedReceiver<WgGoEvent> {
+    let (tx, rx) = mpsc::unbounded();
+    let mut map = LOG_MUTEX.lock();
+    if let Some(context) = map.get_mut(&ordinal) {
+        context.subscribers.push(tx);
+    }
+    rx
+}
+
+/// Handle used to read how often the endpoint of a logging context is likely to have changed, as
+/// seen by the peer. Reading doesn't require access to the tunnel.
+#[derive(Debug, Clone, Copy)]
+pub struct EndpointChangeHandle(u32);
+
+impl EndpointChangeHandle {
+    pub fn new(ordinal: u32) -> Self {
+        Self(ordinal)
+    }
+
+    /// Returns the endpoint changes, or `None` if the context has been cleaned up.
+    pub fn get(&self) -> Option<EndpointChangeDebugInfo> {
+        let map = LOG_MUTEX.lock();
+        map.get(&self.0)
+            .map(|context| context.endpoint_changes.info.clone())
+    }
+}
+
+/// Derives likely changes of the source address seen by the peer from the events of a tunnel.
+/// A rebound socket always gets a new address. A handshake that only completes after being
+/// retransmitted usually means that the NAT mapping expired, so the response was sent to a new
+/// mapping.
+#[derive(Default)]
+struct EndpointChangeTracker {
+    info: EndpointChangeDebugInfo,
+    /// Whether handshakes have been retransmitted since the last completed handshake.
+    retransmitting: bool,
+    /// Whether the socket has been rebound since the last completed handshake. The rebind is
+    /// already counted, so retransmissions that follow it are not counted again.
+    rebound: bool,
+}
+
+impl EndpointChangeTracker {
+    fn record(&mut self, event: WgGoEvent, now: SystemTime) {
+        match event {
+            WgGoEvent::EndpointChange => {
+                self.info.socket_rebinds += 1;
+                self.rebound = true;
+                self.count_change(now);
+            }
+            WgGoEvent::HandshakeRetry { .. } => {
+                self.info.handshake_retransmissions += 1;
+                self.retransmitting = true;
+            }
+            WgGoEvent::HandshakeComplete => {
+                if self.retransmitting && !self.rebound {
+                    self.count_change(now);
+                }
+                self.retransmitting = false;
+                self.rebound = false;
+            }
+            WgGoEvent::HandshakeInitiation | WgGoEvent::HandshakeGaveUp => (),
+        }
+    }
+
+    fn count_change(&mut self, now: SystemTime) {
+        self.info.count += 1;
+        self.info.last_change = Some(now);
+    }
+}
+
+/// Tunnel events that can be recognized in the log output of wireguard-go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WgGoEvent {
+    /// A handshake initiation was sent to the peer.
+    HandshakeInitiation,
+    /// A handshake response was received from the peer, completing the handshake.
+    HandshakeComplete,
+    /// A handshake did not complete in time and is being retried.
+    HandshakeRetry { attempt: u32 },
+    /// No handshake completed after the maximum number of attempts.
+    HandshakeGaveUp,
+    /// The UDP socket was rebound, for example after the default route changed.
+    EndpointChange,
+}
+
+impl WgGoEvent {
+    /// Parses a single line of wireguard-go log output.
+    pub fn parse(msg: &str) -> Option<Self> {
+        let msg = msg.trim_end();
+        if msg.ends_with("Sending handshake initiation") {
+            Some(WgGoEvent::HandshakeInitiation)
+        } else if msg.ends_with("Received handshake response") {
+            Some(WgGoEvent::HandshakeComplete)
+        } else if msg.contains("Handshake did not complete after") {
+            if msg.ends_with("giving up") {
+                return Some(WgGoEvent::HandshakeGaveUp);
+            }
+            let attempt = msg
+                .rsplit("(try ")
+                .next()
+                .and_then(|attempt| attempt.strip_suffix(')'))
+                .and_then(|attempt| attempt.parse().ok())?;
+            Some(WgGoEvent::HandshakeRetry { attempt })
+        } else if msg.ends_with("UDP bind has been updated") {
+            Some(WgGoEvent::EndpointChange)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for WgGoEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WgGoEvent::HandshakeInitiation => f.write_str("handshake initiation sent"),
+            WgGoEvent::HandshakeComplete => f.write_str("handshake completed"),
+            WgGoEvent::HandshakeRetry { attempt } => {
+                write!(f, "handshake retried (attempt {})", attempt)
+            }
+            WgGoEvent::HandshakeGaveUp => f.write_str("handshake attempts exhausted"),
+            WgGoEvent::EndpointChange => f.write_str("UDP socket rebound"),
+        }
+    }
+}
+
+/// An event parsed from the log output, along with the time it was logged.
+#[derive(Debug, Clone)]
+pub struct LoggedEvent {
+    pub time: chrono::DateTime<chrono::Local>,
+    pub event: WgGoEvent,
+}
+
+impl fmt::Display for LoggedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.time.format("[%H:%M:%S%.3f]"), self.event)
+    }
+}
+
 #[allow(dead_code)]
 pub enum LogLevel {
     Verbose,
@@ -72,19 +276,19 @@ impl AsRef<str> for LogLevel {
 #[cfg(windows)]
 pub fn log(context: u32, level: LogLevel, tag: &str, msg: &str) {
     let mut map = LOG_MUTEX.lock();
-    if let Some(logfile) = map.get_mut(&(context as u32)) {
-        log_inner(logfile, level, tag, msg);
+    if let Some(context) = map.get_mut(&(context as u32)) {
+        log_inner(&mut context.file, level, tag, msg);
     }
 }
 
-fn log_inner(logfile: &mut fs::File, level: LogLevel, tag: &str, msg: &str) {
+fn log_inner(logfile: &mut dyn Write, level: LogLevel, tag: &str, msg: &str) {
     let _ = write!(
         logfile,
         "{}[{}][{}] {}",
         chrono::Local::now().format("[%Y-%m-%d %H:%M:%S%.3f]"),
         tag,
         level,
-        msg,
+        crate::logging::redact::scrub(msg),
     );
 }
 
@@ -95,7 +299,7 @@ pub unsafe extern "system" fn wg_go_logging_callback(
     context: *mut libc::c_void,
 ) {
     let mut map = LOG_MUTEX.lock();
-    if let Some(logfile) = map.get_mut(&(context as u32)) {
+    if let Some(context) = map.get_mut(&(context as u32)) {
         let managed_msg = if !msg.is_null() {
             #[cfg(not(target_os = "windows"))]
             let m = std::ffi::CStr::from_ptr(msg).to_string_lossy().to_string();
@@ -113,7 +317,10 @@ pub unsafe extern "system" fn wg_go_logging_callback(
             WG_GO_LOG_VERBOSE => LogLevel::Verbose,
             WG_GO_LOG_ERROR | _ => LogLevel::Error,
         };
-        log_inner(logfile, level, "wireguard-go", &managed_msg);
+        if let Some(event) = WgGoEvent::parse(&managed_msg) {
+            context.push_event(event);
+        }
+        log_inner(&mut context.file, level, "wireguard-go", &managed_msg);
     }
 }
 
@@ -122,3 +329,74 @@ pub type WgLogLevel = u32;
 // const WG_GO_LOG_SILENT: WgLogLevel = 0;
 const WG_GO_LOG_ERROR: WgLogLevel = 1;
 const WG_GO_LOG_VERBOSE: WgLogLevel = 2;
+
+#[cfg(test)]
+mod test {
+    use super::{EndpointChangeTracker, WgGoEvent};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_parse_wg_go_events() {
+        assert_eq!(
+            WgGoEvent::parse("peer(bmXO…fgyo) - Sending handshake initiation\n"),
+            Some(WgGoEvent::HandshakeInitiation)
+        );
+        assert_eq!(
+            WgGoEvent::parse("peer(bmXO…fgyo) - Received handshake response\n"),
+            Some(WgGoEvent::HandshakeComplete)
+        );
+        assert_eq!(
+            WgGoEvent::parse(
+                "peer(bmXO…fgyo) - Handshake did not complete after 5 seconds, retrying (try 3)\r\n"
+            ),
+            Some(WgGoEvent::HandshakeRetry { attempt: 3 })
+        );
+        assert_eq!(
+            WgGoEvent::parse(
+                "peer(bmXO…fgyo) - Handshake did not complete after 20 attempts, giving up\n"
+            ),
+            Some(WgGoEvent::HandshakeGaveUp)
+        );
+        assert_eq!(
+            WgGoEvent::parse("UDP bind has been updated\n"),
+            Some(WgGoEvent::EndpointChange)
+        );
+        assert_eq!(
+            WgGoEvent::parse("peer(bmXO…fgyo) - Sending keepalive packet\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_endpoint_change_tracking() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut tracker = EndpointChangeTracker::default();
+
+        // Handshakes that complete on the first attempt are not endpoint changes
+        tracker.record(WgGoEvent::HandshakeInitiation, start);
+        tracker.record(WgGoEvent::HandshakeComplete, start);
+        assert_eq!(tracker.info.count, 0);
+
+        // A handshake that completes after retransmissions is counted once
+        tracker.record(WgGoEvent::HandshakeRetry { attempt: 2 }, start);
+        tracker.record(WgGoEvent::HandshakeRetry { attempt: 3 }, start);
+        let completed = start + Duration::from_secs(10);
+        tracker.record(WgGoEvent::HandshakeComplete, completed);
+        assert_eq!(tracker.info.count, 1);
+        assert_eq!(tracker.info.handshake_retransmissions, 2);
+        assert_eq!(tracker.info.last_change, Some(completed));
+
+        // Retransmissions after a rebind are part of the same change
+        let rebound = start + Duration::from_secs(20);
+        tracker.record(WgGoEvent::EndpointChange, rebound);
+        tracker.record(WgGoEvent::HandshakeRetry { attempt: 2 }, rebound);
+        tracker.record(
+            WgGoEvent::HandshakeComplete,
+            start + Duration::from_secs(25),
+        );
+        assert_eq!(tracker.info.count, 2);
+        assert_eq!(tracker.info.socket_rebinds, 1);
+        assert_eq!(tracker.info.handshake_retransmissions, 3);
+        assert_eq!(tracker.info.last_change, Some(rebound));
+    }
+}
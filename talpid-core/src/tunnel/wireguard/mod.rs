@@ -3,37 +3,63 @@ use self::config::Config;
 use super::tun_provider;
 use super::{tun_provider::TunProvider, TunnelEvent, TunnelMetadata};
 use crate::routing::{self, RequiredRoute};
-use futures::future::abortable;
 #[cfg(windows)]
-use futures::{channel::mpsc, StreamExt};
+use futures::channel::mpsc;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use futures::future::{AbortHandle, Abortable};
+use futures::StreamExt;
 #[cfg(target_os = "linux")]
 use lazy_static::lazy_static;
 #[cfg(target_os = "linux")]
 use std::env;
-#[cfg(windows)]
+#[cfg(not(target_os = "android"))]
 use std::io;
 use std::{
-    net::{IpAddr, SocketAddr},
+    net::IpAddr,
     path::Path,
-    sync::{mpsc as sync_mpsc, Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc as sync_mpsc, Arc, Mutex, Weak,
+    },
+    time::SystemTime,
+};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use talpid_types::net::IpVersion;
+use talpid_types::{
+    net::{Endpoint, TransportProtocol, TunnelType},
+    tunnel::{
+        AdapterDebugInfo, EndpointChangeDebugInfo, ObfuscatorDebugInfo, ObfuscatorKind,
+        OperationTimingDebugInfo, PeerDebugInfo, TrafficCounters, TunnelDebugInfo,
+    },
+    BoxedError, ErrorExt,
 };
-#[cfg(windows)]
-use talpid_types::BoxedError;
-use talpid_types::{net::TransportProtocol, ErrorExt};
-use udp_over_tcp::{TcpOptions, Udp2Tcp};
 
 /// WireGuard config data-types
 pub mod config;
 mod connectivity_check;
 mod logging;
+mod operation_pool;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod socket_binding;
+mod socks5;
 mod stats;
+pub mod transport_plugin;
+mod udp2tcp;
 mod wireguard_go;
 #[cfg(target_os = "linux")]
 pub(crate) mod wireguard_kernel;
 #[cfg(windows)]
 mod wireguard_nt;
 
-use self::wireguard_go::WgGoTunnel;
+pub use self::logging::WgGoEvent;
+#[cfg(windows)]
+pub use self::wireguard_nt::{reset_wireguard_nt_fallback, unload_wg_nt_dll};
+use self::{operation_pool::OperationTimings, udp2tcp::TcpProxy, wireguard_go::WgGoTunnel};
+
+/// How long the tunnel state machine waits for the tunnel to report its statistics. Reading them
+/// may block while wireguard-go performs handshakes.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const STATS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
 
 type Result<T> = std::result::Result<T, Error>;
 
@@ -55,11 +81,19 @@ pub enum Error {
 
     /// Failed to set up Udp2Tcp
     #[error(display = "Failed to start UDP-over-TCP proxy")]
-    Udp2TcpError(#[error(source)] udp_over_tcp::udp2tcp::ConnectError),
+    Udp2TcpError(#[error(source)] std::io::Error),
+
+    /// Failed to connect to the relay through the entry proxy
+    #[error(display = "Failed to connect through the entry proxy")]
+    EntryProxyError(#[error(source)] socks5::Error),
+
+    /// The registered transport plugin failed to start
+    #[error(display = "Failed to start the transport plugin")]
+    TransportPluginError(#[error(source)] BoxedError),
 
-    /// Failed to obtain the local UDP socket address
-    #[error(display = "Failed obtain local address for the UDP socket in Udp2Tcp")]
-    GetLocalUdpAddress(#[error(source)] std::io::Error),
+    /// The entry proxy can only carry traffic to peers that accept TCP
+    #[error(display = "The entry peer must use TCP when connecting through an entry proxy")]
+    EntryProxyRequiresTcp,
 
     /// Failed to set up connectivity monitor
     #[error(display = "Connectivity monitor failed")]
@@ -92,6 +126,12 @@ pub struct WireguardMonitor {
     close_msg_receiver: sync_mpsc::Receiver<CloseMsg>,
     pinger_stop_sender: sync_mpsc::Sender<()>,
     _tcp_proxies: Vec<TcpProxy>,
+    _entry_proxy: Option<socks5::Socks5Forwarder>,
+    _transport: Option<Box<dyn transport_plugin::TransportHandle>>,
+    /// Stops rebinding the tunnel sockets when the route towards the relay changes.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    rebind_abort_handle: AbortHandle,
+    debug_handle: DebugHandle,
 }
 
 #[cfg(target_os = "linux")]
@@ -106,54 +146,6 @@ lazy_static! {
         .unwrap_or(false);
 }
 
-struct TcpProxy {
-    local_addr: SocketAddr,
-    abort_handle: futures::future::AbortHandle,
-}
-
-impl TcpProxy {
-    pub fn new(runtime: &tokio::runtime::Handle, endpoint: SocketAddr) -> Result<Self> {
-        let listen_addr = if endpoint.is_ipv4() {
-            SocketAddr::new("127.0.0.1".parse().unwrap(), 0)
-        } else {
-            SocketAddr::new("::1".parse().unwrap(), 0)
-        };
-
-        let udp2tcp = runtime
-            .block_on(Udp2Tcp::new(
-                listen_addr,
-                endpoint,
-                TcpOptions {
-                    #[cfg(target_os = "linux")]
-                    fwmark: Some(crate::linux::TUNNEL_FW_MARK),
-                    ..TcpOptions::default()
-                },
-            ))
-            .map_err(Error::Udp2TcpError)?;
-        let local_addr = udp2tcp
-            .local_udp_addr()
-            .map_err(Error::GetLocalUdpAddress)?;
-
-        let (udp2tcp_future, abort_handle) = abortable(udp2tcp.run());
-        runtime.spawn(udp2tcp_future);
-
-        Ok(Self {
-            local_addr,
-            abort_handle,
-        })
-    }
-
-    pub fn local_udp_addr(&self) -> SocketAddr {
-        self.local_addr
-    }
-}
-
-impl Drop for TcpProxy {
-    fn drop(&mut self) {
-        self.abort_handle.abort();
-    }
-}
-
 impl WireguardMonitor {
     /// Starts a WireGuard tunnel with the given config
     pub fn start<
@@ -172,23 +164,114 @@ impl WireguardMonitor {
         route_manager: &mut routing::RouteManager,
         retry_attempt: u32,
     ) -> Result<WireguardMonitor> {
+        let route_handle = route_manager.handle().map_err(Error::SetupRoutingError)?;
         let mut tcp_proxies = vec![];
         let mut endpoint_addrs = vec![];
+        let mut obfuscators = vec![];
+        let peers = config
+            .peers
+            .iter()
+            .map(|peer| PeerDebugInfo {
+                public_key: peer.public_key.clone(),
+                endpoint: peer.endpoint,
+                allowed_ips: peer.allowed_ips.clone(),
+                tx_bytes: 0,
+                rx_bytes: 0,
+                last_handshake: None,
+            })
+            .collect();
 
-        for peer in &mut config.peers {
-            endpoint_addrs.push(peer.endpoint.ip());
-            if peer.protocol == TransportProtocol::Tcp {
-                let udp2tcp = TcpProxy::new(&runtime, peer.endpoint.clone())?;
+        let transport_plugin = transport_plugin::registered();
 
-                // Replace remote peer with proxy
-                peer.endpoint = udp2tcp.local_udp_addr();
-                tcp_proxies.push(udp2tcp);
+        // Sockets of proxied peers only talk to the local proxy or transport plugin, so only
+        // direct UDP peers are pinned to a physical interface.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let bind_endpoint = config
+            .peers
+            .iter()
+            .find(|peer| peer.protocol == TransportProtocol::Udp)
+            .filter(|_| transport_plugin.is_none())
+            .map(|peer| peer.endpoint.ip());
+
+        let mut entry_proxy = None;
+        let mut transport = None;
+        for (index, peer) in config.peers.iter_mut().enumerate() {
+            match (&config.entry_proxy, &transport_plugin) {
+                // The entry peer is reached through the user's proxy, which wraps the traffic
+                // the same way as udp-over-tcp.
+                (Some(proxy), _) if index == 0 && peer.protocol == TransportProtocol::Tcp => {
+                    endpoint_addrs.push(proxy.address.ip());
+                    let forwarder = socks5::Socks5Forwarder::new(&runtime, proxy, peer.endpoint)
+                        .map_err(Error::EntryProxyError)?;
+                    obfuscators.push((
+                        ObfuscatorDebugInfo {
+                            kind: ObfuscatorKind::Socks5,
+                            local_address: forwarder.local_udp_addr(),
+                            remote_address: proxy.address,
+                            is_running: true,
+                        },
+                        forwarder.is_running(),
+                    ));
+
+                    // Replace remote peer with proxy
+                    peer.endpoint = forwarder.local_udp_addr();
+                    entry_proxy = Some(forwarder);
+                }
+                (Some(_), _) if index == 0 => return Err(Error::EntryProxyRequiresTcp),
+                (None, Some(plugin)) if index == 0 => {
+                    endpoint_addrs.push(peer.endpoint.ip());
+                    log::info!("Starting transport plugin \"{}\"", plugin.name());
+                    let handle = plugin
+                        .start(
+                            &runtime,
+                            Endpoint::from_socket_address(peer.endpoint, peer.protocol),
+                        )
+                        .map_err(Error::TransportPluginError)?;
+                    obfuscators.push((
+                        ObfuscatorDebugInfo {
+                            kind: ObfuscatorKind::Plugin,
+                            local_address: handle.local_udp_addr(),
+                            remote_address: peer.endpoint,
+                            is_running: true,
+                        },
+                        handle.is_running(),
+                    ));
+
+                    // Replace remote peer with the transport
+                    peer.endpoint = handle.local_udp_addr();
+                    transport = Some(handle);
+                }
+                _ => {
+                    endpoint_addrs.push(peer.endpoint.ip());
+                    if peer.protocol == TransportProtocol::Tcp {
+                        let udp2tcp = TcpProxy::new(
+                            &runtime,
+                            peer.endpoint,
+                            #[cfg(any(target_os = "linux", target_os = "macos"))]
+                            &route_handle,
+                        )
+                        .map_err(Error::Udp2TcpError)?;
+                        obfuscators.push((
+                            ObfuscatorDebugInfo {
+                                kind: ObfuscatorKind::Udp2Tcp,
+                                local_address: udp2tcp.local_udp_addr(),
+                                remote_address: peer.endpoint,
+                                is_running: true,
+                            },
+                            udp2tcp.is_running(),
+                        ));
+
+                        // Replace remote peer with proxy
+                        peer.endpoint = udp2tcp.local_udp_addr();
+                        tcp_proxies.push(udp2tcp);
+                    }
+                }
             }
         }
 
         #[cfg(target_os = "windows")]
         let (setup_done_tx, mut setup_done_rx) = mpsc::channel(0);
-        let tunnel = Self::open_tunnel(
+        let (tunnel, fallback_reason) = Self::open_tunnel(
             runtime.clone(),
             &config,
             log_path,
@@ -199,21 +282,55 @@ impl WireguardMonitor {
             setup_done_tx,
         )?;
         let iface_name = tunnel.get_interface_name().to_string();
+        let backend = tunnel.backend_name();
+        let operation_timings = tunnel.operation_timings();
+        let endpoint_changes = tunnel.endpoint_changes();
+        if let Some(mut tunnel_events) = tunnel.subscribe_events() {
+            let on_event = on_event.clone();
+            runtime.spawn(async move {
+                while let Some(event) = tunnel_events.next().await {
+                    (on_event)(TunnelEvent::WireguardEvent(event)).await;
+                }
+            });
+        }
+        let tunnel = Arc::new(Mutex::new(Some(tunnel)));
 
         let event_callback = Box::new(on_event.clone());
         let (close_msg_sender, close_msg_receiver) = sync_mpsc::channel();
         let (pinger_tx, pinger_rx) = sync_mpsc::channel();
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let (rebind_abort_handle, rebind_abort_registration) = AbortHandle::new_pair();
         let monitor = WireguardMonitor {
             runtime: runtime.clone(),
-            tunnel: Arc::new(Mutex::new(Some(tunnel))),
+            tunnel: tunnel.clone(),
             event_callback,
             close_msg_sender,
             close_msg_receiver,
             pinger_stop_sender: pinger_tx,
             _tcp_proxies: tcp_proxies,
+            _entry_proxy: entry_proxy,
+            _transport: transport,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            rebind_abort_handle,
+            debug_handle: DebugHandle {
+                tunnel: Arc::downgrade(&tunnel),
+                adapter: AdapterDebugInfo {
+                    name: iface_name.clone(),
+                    backend: backend.to_owned(),
+                    is_running: true,
+                    mtu: Some(config.mtu),
+                    addresses: config.tunnel.addresses.clone(),
+                    fallback_reason,
+                },
+                peers,
+                obfuscators,
+                bound_interface: Arc::new(Mutex::new(None)),
+                operation_timings,
+                endpoint_changes,
+            },
         };
 
-        let gateway = config.ipv4_gateway;
+        let gateway = config.primary_gateway();
         let close_sender = monitor.close_msg_sender.clone();
         let mut connectivity_monitor = connectivity_check::ConnectivityMonitor::new(
             gateway,
@@ -224,7 +341,12 @@ impl WireguardMonitor {
         )
         .map_err(Error::ConnectivityMonitorError)?;
 
-        let route_handle = route_manager.handle().map_err(Error::SetupRoutingError)?;
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let bind_route_handle = route_handle.clone();
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let bind_tunnel = Arc::downgrade(&monitor.tunnel);
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let bound_interface = monitor.debug_handle.bound_interface.clone();
 
         let metadata = Self::tunnel_metadata(&iface_name, &config);
 
@@ -277,6 +399,26 @@ impl WireguardMonitor {
                 return;
             }
 
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            if let Some(endpoint) = bind_endpoint {
+                Self::bind_tunnel_sockets(
+                    &bind_tunnel,
+                    &bind_route_handle,
+                    endpoint,
+                    &bound_interface,
+                )
+                .await;
+                tokio::spawn(Abortable::new(
+                    Self::rebind_on_route_changes(
+                        bind_tunnel,
+                        bind_route_handle,
+                        endpoint,
+                        bound_interface,
+                    ),
+                    rebind_abort_registration,
+                ));
+            }
+
             tokio::task::spawn_blocking(move || {
                 match connectivity_monitor.establish_connectivity(retry_attempt) {
                     Ok(true) => {
@@ -297,6 +439,7 @@ impl WireguardMonitor {
                         );
                     }
                 }
+                connectivity_monitor.log_recent_events();
             })
             .await
             .expect("connectivity monitor thread panicked");
@@ -316,14 +459,14 @@ impl WireguardMonitor {
         tun_provider: &mut TunProvider,
         route_manager: &mut routing::RouteManager,
         #[cfg(windows)] setup_done_tx: mpsc::Sender<std::result::Result<(), BoxedError>>,
-    ) -> Result<Box<dyn Tunnel>> {
+    ) -> Result<(Box<dyn Tunnel>, Option<String>)> {
         #[cfg(target_os = "linux")]
         if !*FORCE_USERSPACE_WIREGUARD {
             if crate::dns::will_use_nm() {
                 match wireguard_kernel::NetworkManagerTunnel::new(runtime, config) {
                     Ok(tunnel) => {
                         log::debug!("Using NetworkManager to use kernel WireGuard implementation");
-                        return Ok(Box::new(tunnel));
+                        return Ok((Box::new(tunnel), None));
                     }
                     Err(err) => {
                         log::error!(
@@ -338,7 +481,7 @@ impl WireguardMonitor {
                 match wireguard_kernel::NetlinkTunnel::new(runtime, config) {
                     Ok(tunnel) => {
                         log::debug!("Using kernel WireGuard implementation");
-                        return Ok(Box::new(tunnel));
+                        return Ok((Box::new(tunnel), None));
                     }
                     Err(error) => {
                         log::error!(
@@ -352,30 +495,53 @@ impl WireguardMonitor {
             }
         }
 
+        #[allow(unused_mut)]
+        let mut fallback_reason = None;
+
         #[cfg(target_os = "windows")]
         if config.use_wireguard_nt {
-            match wireguard_nt::WgNtTunnel::start_tunnel(
-                config,
-                log_path,
-                resource_dir,
-                setup_done_tx.clone(),
-            ) {
-                Ok(tunnel) => {
-                    log::debug!("Using WireGuardNT");
-                    return Ok(Box::new(tunnel));
-                }
-                Err(error) => {
-                    log::error!(
-                        "{}",
-                        error.display_chain_with_msg("Failed to setup WireGuardNT tunnel")
-                    );
+            if let Some(reason) = wireguard_nt::driver_blocked_reason() {
+                log::debug!("Not trying WireGuardNT since Windows blocked its driver previously");
+                fallback_reason = Some(reason);
+            } else {
+                match wireguard_nt::WgNtTunnel::start_tunnel(
+                    config,
+                    log_path,
+                    resource_dir,
+                    setup_done_tx.clone(),
+                ) {
+                    Ok(tunnel) => {
+                        log::debug!("Using WireGuardNT");
+                        return Ok((Box::new(tunnel), None));
+                    }
+                    Err(error) if error.is_driver_blocked() => {
+                        log::warn!(
+                            "{}",
+                            error.display_chain_with_msg(
+                                "Windows blocked the WireGuardNT driver, possibly because Memory \
+                                 integrity or the vulnerable driver blocklist is enabled. Using \
+                                 wireguard-go and Wintun until the daemon restarts or WireGuardNT \
+                                 is enabled again"
+                            )
+                        );
+                        let reason = error.display_chain();
+                        wireguard_nt::set_driver_blocked_reason(reason.clone());
+                        fallback_reason = Some(reason);
+                    }
+                    Err(error) => {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to setup WireGuardNT tunnel")
+                        );
+                        fallback_reason = Some(error.display_chain());
+                    }
                 }
             }
         }
 
         #[cfg(any(target_os = "linux", windows))]
         log::debug!("Using userspace WireGuard implementation");
-        Ok(Box::new(
+        let tunnel: Box<dyn Tunnel> = Box::new(
             WgGoTunnel::start_tunnel(
                 &config,
                 log_path,
@@ -389,7 +555,114 @@ impl WireguardMonitor {
                 setup_done_tx,
             )
             .map_err(Error::TunnelError)?,
-        ))
+        );
+        Ok((tunnel, fallback_reason))
+    }
+
+    /// Rebinds the sockets of the tunnel whenever the route towards `endpoint` may have moved to
+    /// another interface.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn rebind_on_route_changes(
+        tunnel: std::sync::Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+        route_handle: routing::RouteManagerHandle,
+        endpoint: IpAddr,
+        bound_interface: Arc<Mutex<Option<String>>>,
+    ) {
+        #[cfg(target_os = "linux")]
+        let route_changes = route_handle.change_listener().await;
+        #[cfg(target_os = "macos")]
+        let route_changes = routing::listen_for_default_route_changes();
+
+        let route_changes = match route_changes {
+            Ok(route_changes) => route_changes,
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg(
+                        "Failed to listen for route changes. Tunnel sockets will not be rebound"
+                    )
+                );
+                return;
+            }
+        };
+
+        futures::pin_mut!(route_changes);
+        while route_changes.next().await.is_some() {
+            if tunnel.strong_count() == 0 {
+                break;
+            }
+            Self::bind_tunnel_sockets(&tunnel, &route_handle, endpoint, &bound_interface).await;
+        }
+    }
+
+    /// Binds the sockets of the tunnel to the interface that currently routes traffic to
+    /// `endpoint`, unless they are already bound to it. Failing to do so is not fatal, since the
+    /// tunnel still works on hosts that don't filter traffic arriving on the "wrong" interface.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn bind_tunnel_sockets(
+        tunnel: &std::sync::Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+        route_handle: &routing::RouteManagerHandle,
+        endpoint: IpAddr,
+        bound_interface: &Mutex<Option<String>>,
+    ) {
+        let interface = match socket_binding::interface_for_endpoint(route_handle, endpoint).await {
+            Ok(interface) => interface,
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Not binding tunnel sockets to an interface")
+                );
+                return;
+            }
+        };
+        if bound_interface
+            .lock()
+            .expect("Bound interface lock poisoned")
+            .as_ref()
+            == Some(&interface)
+        {
+            return;
+        }
+        let ip_version = if endpoint.is_ipv4() {
+            IpVersion::V4
+        } else {
+            IpVersion::V6
+        };
+
+        let tunnel = match tunnel.upgrade() {
+            Some(tunnel) => tunnel,
+            None => return,
+        };
+        // Binding requires the tunnel lock, which may be held for a long time by a blocking call
+        // into the tunnel implementation, so don't wait for it on the async runtime.
+        let bind_interface = interface.clone();
+        let result = operation_pool::run(move || {
+            let tunnel = tunnel.lock().expect("Tunnel lock poisoned");
+            tunnel
+                .as_ref()
+                .map(|tunnel| tunnel.bind_to_interface(&bind_interface, ip_version))
+        })
+        .await;
+        match result {
+            Ok(Some(Ok(()))) => {
+                log::debug!("Bound tunnel sockets to interface {}", interface);
+                *bound_interface
+                    .lock()
+                    .expect("Bound interface lock poisoned") = Some(interface);
+            }
+            Ok(Some(Err(error))) => log::warn!(
+                "{}",
+                error.display_chain_with_msg(&format!(
+                    "Failed to bind tunnel sockets to interface {}",
+                    interface
+                ))
+            ),
+            Ok(None) => (),
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to bind tunnel sockets")
+            ),
+        }
     }
 
     /// Returns a close handle for the tunnel
@@ -399,6 +672,11 @@ impl WireguardMonitor {
         }
     }
 
+    /// Returns a handle that can be used to inspect the tunnel while it is running.
+    pub fn debug_handle(&self) -> DebugHandle {
+        self.debug_handle.clone()
+    }
+
     /// Blocks the current thread until tunnel disconnects
     pub fn wait(mut self) -> Result<()> {
         let wait_result = match self.close_msg_receiver.recv() {
@@ -409,6 +687,8 @@ impl WireguardMonitor {
         };
 
         let _ = self.pinger_stop_sender.send(());
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        self.rebind_abort_handle.abort();
 
         self.stop_tunnel();
 
@@ -461,8 +741,13 @@ impl WireguardMonitor {
         iface_name: &str,
         config: &'a Config,
     ) -> impl Iterator<Item = RequiredRoute> + 'a {
-        let node_v4 =
-            routing::Node::new(config.ipv4_gateway.clone().into(), iface_name.to_string());
+        // Without an IPv4 address, the IPv4 gateway is unreachable. IPv4 traffic is still routed
+        // into the tunnel so that it does not leak.
+        let node_v4 = if config.has_ipv4() {
+            routing::Node::new(config.ipv4_gateway.clone().into(), iface_name.to_string())
+        } else {
+            routing::Node::device(iface_name.to_string())
+        };
         let node_v6 = if let Some(ipv6_gateway) = config.ipv6_gateway.as_ref() {
             routing::Node::new(ipv6_gateway.clone().into(), iface_name.to_string())
         } else {
@@ -515,13 +800,13 @@ impl WireguardMonitor {
                     RequiredRoute::new(network, node.clone()).table(u32::from(RT_TABLE_MAIN))
                 }
             })
-            .chain(std::iter::once(
+            .chain(config.has_ipv4().then(|| {
                 RequiredRoute::new(
                     ipnetwork::Ipv4Network::from(config.ipv4_gateway).into(),
                     v4_node,
                 )
-                .table(u32::from(RT_TABLE_MAIN)),
-            ))
+                .table(u32::from(RT_TABLE_MAIN))
+            }))
             .chain(config.ipv6_gateway.map(|gateway| {
                 RequiredRoute::new(ipnetwork::Ipv6Network::from(gateway).into(), v6_node)
                     .table(u32::from(RT_TABLE_MAIN))
@@ -554,6 +839,44 @@ enum CloseMsg {
     SetupError(Error),
 }
 
+/// Changes the MTU of a running WireGuard tunnel interface, or restores the default MTU if `mtu`
+/// is `None`. The tunnel does not have to be restarted.
+#[cfg(not(target_os = "android"))]
+pub fn set_interface_mtu(interface: &str, mtu: Option<u16>) -> io::Result<()> {
+    let mtu = mtu.unwrap_or(config::DEFAULT_MTU);
+
+    #[cfg(target_os = "linux")]
+    {
+        duct::cmd!(
+            "ip",
+            "link",
+            "set",
+            "dev",
+            interface,
+            "mtu",
+            mtu.to_string()
+        )
+        .stdout_null()
+        .run()
+        .map(|_| ())
+    }
+    #[cfg(target_os = "macos")]
+    {
+        duct::cmd!("ifconfig", interface, "mtu", mtu.to_string())
+            .stdout_null()
+            .run()
+            .map(|_| ())
+    }
+    #[cfg(windows)]
+    {
+        use talpid_windows_net::AddressFamily;
+
+        let luid = talpid_windows_net::luid_from_alias(interface)?;
+        talpid_windows_net::set_ip_interface_mtu(luid, AddressFamily::Ipv4, u32::from(mtu))?;
+        talpid_windows_net::set_ip_interface_mtu(luid, AddressFamily::Ipv6, u32::from(mtu))
+    }
+}
+
 /// Close handle for a WireGuard tunnel.
 #[derive(Clone, Debug)]
 pub struct CloseHandle {
@@ -569,10 +892,184 @@ impl CloseHandle {
     }
 }
 
+/// Handle used to inspect the internals of a WireGuard tunnel while it is running.
+#[derive(Clone)]
+pub struct DebugHandle {
+    tunnel: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+    adapter: AdapterDebugInfo,
+    peers: Vec<PeerDebugInfo>,
+    obfuscators: Vec<(ObfuscatorDebugInfo, Arc<AtomicBool>)>,
+    bound_interface: Arc<Mutex<Option<String>>>,
+    operation_timings: Option<Arc<OperationTimings>>,
+    endpoint_changes: Option<logging::EndpointChangeHandle>,
+}
+
+impl DebugHandle {
+    /// Returns a snapshot of the tunnel internals. Routes are not known by the tunnel, so none
+    /// are included.
+    pub fn debug_info(&self) -> TunnelDebugInfo {
+        let mut is_running = false;
+        let mut stats = stats::StatsMap::new();
+        match self.tunnel_stats() {
+            Some(Ok(tunnel_stats)) => {
+                is_running = true;
+                stats = tunnel_stats;
+            }
+            Some(Err(error)) => {
+                is_running = true;
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to obtain tunnel stats")
+                );
+            }
+            None => (),
+        }
+
+        let now = SystemTime::now();
+        let peers = self
+            .peers
+            .iter()
+            .map(|peer| match stats.get(peer.public_key.as_bytes()) {
+                Some(peer_stats) => PeerDebugInfo {
+                    tx_bytes: peer_stats.tx_bytes,
+                    rx_bytes: peer_stats.rx_bytes,
+                    last_handshake: peer_stats
+                        .last_handshake
+                        .and_then(|handshake| now.duration_since(handshake).ok()),
+                    ..peer.clone()
+                },
+                None => peer.clone(),
+            })
+            .collect();
+
+        TunnelDebugInfo {
+            tunnel_type: Some(TunnelType::Wireguard),
+            adapter: Some(AdapterDebugInfo {
+                is_running,
+                ..self.adapter.clone()
+            }),
+            peers,
+            obfuscators: self
+                .obfuscators
+                .iter()
+                .map(|(obfuscator, is_running)| ObfuscatorDebugInfo {
+                    is_running: is_running.load(Ordering::SeqCst),
+                    ..obfuscator.clone()
+                })
+                .collect(),
+            bound_interface: self
+                .bound_interface
+                .lock()
+                .expect("Bound interface lock poisoned")
+                .clone(),
+            routes: vec![],
+        }
+    }
+
+    /// Returns the traffic through the tunnel, or `None` if the tunnel has stopped. The counters
+    /// of the exit peer are used, since with multihop, the entry peer also counts the traffic of
+    /// the inner tunnel.
+    pub fn traffic(&self) -> Option<TrafficCounters> {
+        let stats = match self.tunnel_stats()? {
+            Ok(stats) => stats,
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to obtain tunnel stats")
+                );
+                return None;
+            }
+        };
+        let peer_stats = stats.get(self.peers.last()?.public_key.as_bytes())?;
+        Some(TrafficCounters {
+            tx_bytes: peer_stats.tx_bytes,
+            rx_bytes: peer_stats.rx_bytes,
+        })
+    }
+
+    /// Returns how long calls into the tunnel implementation have taken. Only the userspace
+    /// implementation records any.
+    pub fn operation_timings(&self) -> Vec<OperationTimingDebugInfo> {
+        self.operation_timings
+            .as_ref()
+            .map(|timings| timings.debug_info())
+            .unwrap_or_default()
+    }
+
+    /// Returns how often the relay is likely to have seen the tunnel traffic arrive from a new
+    /// address, or `None` if the tunnel has stopped or doesn't track this.
+    pub fn endpoint_changes(&self) -> Option<EndpointChangeDebugInfo> {
+        self.endpoint_changes.as_ref()?.get()
+    }
+
+    /// Reads the peer statistics from the tunnel, or returns `None` if the tunnel has stopped.
+    /// On Linux and macOS, the read runs on the tunnel operation threads and is abandoned after
+    /// `STATS_TIMEOUT`, so that a busy tunnel cannot stall the caller.
+    fn tunnel_stats(&self) -> Option<std::result::Result<stats::StatsMap, TunnelError>> {
+        let tunnel = self.tunnel.upgrade()?;
+
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            operation_pool::run_with_timeout(
+                move || {
+                    let tunnel = tunnel.lock().expect("Tunnel lock poisoned");
+                    tunnel.as_ref().map(|tunnel| tunnel.get_tunnel_stats())
+                },
+                STATS_TIMEOUT,
+            )
+            .unwrap_or_else(|error| Some(Err(TunnelError::OperationError(error))))
+        }
+
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        {
+            let tunnel = tunnel.lock().expect("Tunnel lock poisoned");
+            tunnel.as_ref().map(|tunnel| tunnel.get_tunnel_stats())
+        }
+    }
+}
+
 pub(crate) trait Tunnel: Send {
     fn get_interface_name(&self) -> String;
+    /// Returns a short name of the WireGuard implementation.
+    fn backend_name(&self) -> &'static str;
     fn stop(self: Box<Self>) -> std::result::Result<(), TunnelError>;
     fn get_tunnel_stats(&self) -> std::result::Result<stats::StatsMap, TunnelError>;
+
+    /// Returns recent events parsed from the log output of the tunnel, oldest first.
+    /// Only the userspace implementation produces any.
+    fn recent_events(&self) -> Vec<logging::LoggedEvent> {
+        vec![]
+    }
+
+    /// Returns a stream of the events parsed from the log output of the tunnel. Only the
+    /// userspace implementation produces any.
+    fn subscribe_events(&self) -> Option<futures::channel::mpsc::UnboundedReceiver<WgGoEvent>> {
+        None
+    }
+
+    /// Returns the durations of the calls into the tunnel implementation, which are recorded for
+    /// as long as the tunnel runs. Only the userspace implementation records any.
+    fn operation_timings(&self) -> Option<Arc<OperationTimings>> {
+        None
+    }
+
+    /// Returns a handle used to read how often the peer is likely to have seen the tunnel traffic
+    /// arrive from a new address. Only the userspace implementation logs the events that this is
+    /// derived from.
+    fn endpoint_changes(&self) -> Option<logging::EndpointChangeHandle> {
+        None
+    }
+
+    /// Binds the sockets that send encrypted traffic to the given physical interface.
+    /// Implementations whose sockets are owned by the kernel rely on the firewall mark instead.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn bind_to_interface(
+        &self,
+        _interface: &str,
+        _ip_version: IpVersion,
+    ) -> std::result::Result<(), TunnelError> {
+        Ok(())
+    }
 }
 
 /// Errors to be returned from WireGuard implementations, namely implementers of the Tunnel trait
@@ -629,6 +1126,16 @@ pub enum TunnelError {
     #[error(display = "Failed to configure Wireguard sockets to bypass the tunnel")]
     BypassError(#[error(source)] tun_provider::Error),
 
+    /// Failed to bind the tunnel sockets to a physical interface.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[error(display = "Failed to bind tunnel sockets to interface")]
+    BindSocketError(#[error(source)] socket_binding::Error),
+
+    /// A call into the tunnel implementation on the tunnel operation threads failed.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[error(display = "Tunnel operation failed")]
+    OperationError(#[error(source)] operation_pool::Error),
+
     /// Invalid tunnel interface name.
     #[error(display = "Invalid tunnel interface name")]
     InterfaceNameError(#[error(source)] std::ffi::NulError),
@@ -0,0 +1,233 @@
+//! Dedicated threads for blocking calls into the userspace WireGuard implementation, and timing of
+//! those calls. wireguard-go holds its device lock while it performs handshakes, so calls such as
+//! reading the configuration may block for a long time when many peers rekey at once. Running them
+//! here keeps them from stalling the threads of the async runtime.
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use lazy_static::lazy_static;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc},
+    thread,
+};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use talpid_types::tunnel::OperationTimingDebugInfo;
+
+/// Errors that can occur when running an operation on the dedicated threads.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[derive(err_derive::Error, Debug)]
+pub enum Error {
+    /// The threads that run tunnel operations have stopped.
+    #[error(display = "Tunnel operation threads have stopped")]
+    PoolStopped,
+
+    /// The operation panicked before producing a result.
+    #[error(display = "Tunnel operation panicked")]
+    OperationPanicked,
+
+    /// The operation did not finish in time. It keeps running in the background.
+    #[error(display = "Tunnel operation timed out")]
+    Timeout,
+}
+
+/// Number of threads that run blocking tunnel operations.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const POOL_SIZE: usize = 2;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+type Job = Box<dyn FnOnce() + Send>;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+lazy_static! {
+    static ref POOL: OperationPool = OperationPool::new(POOL_SIZE);
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+struct OperationPool {
+    tx: Mutex<mpsc::Sender<Job>>,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl OperationPool {
+    fn new(num_threads: usize) -> Self {
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        for index in 0..num_threads {
+            let rx = rx.clone();
+            thread::Builder::new()
+                .name(format!("wg-operation-{}", index))
+                .spawn(move || loop {
+                    let job = match rx.lock().expect("Job queue lock poisoned").recv() {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+                    // Keep the thread alive if the job panics. The result channel of the job is
+                    // dropped, which is reported to the caller.
+                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                })
+                .expect("Failed to spawn tunnel operation thread");
+        }
+        Self { tx: Mutex::new(tx) }
+    }
+
+    fn submit(&self, job: Job) -> Result<(), Error> {
+        self.tx
+            .lock()
+            .expect("Job queue lock poisoned")
+            .send(job)
+            .map_err(|_| Error::PoolStopped)
+    }
+}
+
+/// Runs `operation` on the dedicated threads and waits for its result without blocking the
+/// async runtime.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub(super) async fn run<T, F>(operation: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (result_tx, result_rx) = futures::channel::oneshot::channel();
+    POOL.submit(Box::new(move || {
+        let _ = result_tx.send(operation());
+    }))?;
+    result_rx.await.map_err(|_| Error::OperationPanicked)
+}
+
+/// Runs `operation` on the dedicated threads and blocks the calling thread until it returns, or
+/// until `timeout` has elapsed. Used from threads outside of the async runtime that must not be
+/// stalled for long, such as the tunnel state machine.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub(super) fn run_with_timeout<T, F>(operation: F, timeout: Duration) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (result_tx, result_rx) = mpsc::channel();
+    POOL.submit(Box::new(move || {
+        let _ = result_tx.send(operation());
+    }))?;
+    result_rx
+        .recv_timeout(timeout)
+        .map_err(|error| match error {
+            mpsc::RecvTimeoutError::Timeout => Error::Timeout,
+            mpsc::RecvTimeoutError::Disconnected => Error::OperationPanicked,
+        })
+}
+
+/// Durations of the operations performed on a tunnel, grouped by operation.
+#[derive(Default)]
+pub(crate) struct OperationTimings {
+    timings: Mutex<Vec<Timing>>,
+}
+
+struct Timing {
+    operation: &'static str,
+    count: u64,
+    total: Duration,
+    last: Duration,
+    max: Duration,
+}
+
+impl OperationTimings {
+    /// Runs `operation` and records how long it took under the name `name`.
+    pub fn time<T>(&self, name: &'static str, operation: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = operation();
+        self.record(name, start.elapsed());
+        result
+    }
+
+    /// Records that the operation `name` took `duration`.
+    pub fn record(&self, name: &'static str, duration: Duration) {
+        let mut timings = self.timings.lock().expect("Timings lock poisoned");
+        match timings.iter_mut().find(|timing| timing.operation == name) {
+            Some(timing) => {
+                timing.count += 1;
+                timing.total += duration;
+                timing.last = duration;
+                timing.max = timing.max.max(duration);
+            }
+            None => timings.push(Timing {
+                operation: name,
+                count: 1,
+                total: duration,
+                last: duration,
+                max: duration,
+            }),
+        }
+    }
+
+    /// Returns a summary of the recorded timings, in the order the operations were first seen.
+    pub fn debug_info(&self) -> Vec<OperationTimingDebugInfo> {
+        self.timings
+            .lock()
+            .expect("Timings lock poisoned")
+            .iter()
+            .map(|timing| OperationTimingDebugInfo {
+                operation: timing.operation.to_owned(),
+                count: timing.count,
+                last: timing.last,
+                average: timing.total / u32::try_from(timing.count).unwrap_or(u32::MAX),
+                max: timing.max,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record_timings() {
+        let timings = OperationTimings::default();
+        timings.record("get config", Duration::from_millis(10));
+        timings.record("turn on", Duration::from_millis(50));
+        timings.record("get config", Duration::from_millis(30));
+
+        let info = timings.debug_info();
+        assert_eq!(info.len(), 2);
+        assert_eq!(info[0].operation, "get config");
+        assert_eq!(info[0].count, 2);
+        assert_eq!(info[0].last, Duration::from_millis(30));
+        assert_eq!(info[0].average, Duration::from_millis(20));
+        assert_eq!(info[0].max, Duration::from_millis(30));
+        assert_eq!(info[1].operation, "turn on");
+        assert_eq!(info[1].count, 1);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_run_on_pool() {
+        let thread_name =
+            futures::executor::block_on(run(|| thread::current().name().map(str::to_owned)))
+                .unwrap();
+        assert!(thread_name.unwrap().starts_with("wg-operation-"));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_operation_panics() {
+        let result = futures::executor::block_on(run(|| panic!("operation failed")));
+        assert!(matches!(result, Err(Error::OperationPanicked)));
+
+        // The pool must still be usable after a panic.
+        let result = run_with_timeout(|| 1 + 1, Duration::from_secs(5));
+        assert_eq!(result.unwrap(), 2);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_operation_timeout() {
+        let result = run_with_timeout(
+            || thread::sleep(Duration::from_millis(500)),
+            Duration::from_millis(10),
+        );
+        assert!(matches!(result, Err(Error::Timeout)));
+    }
+}
@@ -0,0 +1,143 @@
+//! Pins the sockets used to reach a relay to the physical interface that currently carries the
+//! route towards it. Without this, hosts with several active interfaces may send tunnel traffic
+//! out of a different interface than the one the reply arrives on, which strict reverse path
+//! filtering then drops as martian packets.
+
+use crate::routing;
+#[cfg(target_os = "macos")]
+use std::ffi::CString;
+use std::{io, net::IpAddr, os::unix::io::RawFd};
+use talpid_types::net::IpVersion;
+
+/// Missing from the `libc` crate. Defined in `netinet6/in6.h`.
+#[cfg(target_os = "macos")]
+const IPV6_BOUND_IF: libc::c_int = 125;
+
+/// Errors that can occur while binding tunnel sockets to an interface.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to look up the route towards the relay.
+    #[error(display = "Failed to look up the route towards the relay")]
+    RouteLookupError(#[error(source)] routing::Error),
+
+    /// There is no route towards the relay outside the tunnel.
+    #[error(display = "No route towards {} outside the tunnel", _0)]
+    NoRouteError(IpAddr),
+
+    /// Failed to get the index of an interface.
+    #[cfg(target_os = "macos")]
+    #[error(display = "Failed to get the index of interface {}", _0)]
+    InterfaceIndexError(String, #[error(source)] io::Error),
+
+    /// Failed to bind a socket to an interface.
+    #[error(display = "Failed to bind socket to interface {}", _0)]
+    BindError(String, #[error(source)] io::Error),
+}
+
+/// Returns the name of the interface that traffic to `endpoint` leaves through when it is not
+/// routed through the tunnel.
+#[cfg(target_os = "linux")]
+pub async fn interface_for_endpoint(
+    route_handle: &routing::RouteManagerHandle,
+    endpoint: IpAddr,
+) -> Result<String, Error> {
+    let route = route_handle
+        .get_destination_route(endpoint, true)
+        .await
+        .map_err(Error::RouteLookupError)?;
+    route
+        .as_ref()
+        .and_then(|route| route.get_node().get_device())
+        .map(str::to_owned)
+        .ok_or(Error::NoRouteError(endpoint))
+}
+
+/// Returns the name of the interface that traffic to `endpoint` leaves through when it is not
+/// routed through the tunnel.
+#[cfg(target_os = "macos")]
+pub async fn interface_for_endpoint(
+    _route_handle: &routing::RouteManagerHandle,
+    endpoint: IpAddr,
+) -> Result<String, Error> {
+    let (v4_node, v6_node) = routing::get_default_routes()
+        .await
+        .map_err(Error::RouteLookupError)?;
+    let node = if endpoint.is_ipv4() { v4_node } else { v6_node };
+    node.as_ref()
+        .and_then(|node| node.get_device())
+        .map(str::to_owned)
+        .ok_or(Error::NoRouteError(endpoint))
+}
+
+/// Converts an interface name into the corresponding index.
+#[cfg(target_os = "macos")]
+pub fn interface_index(interface: &str) -> Result<u32, Error> {
+    let c_name = CString::new(interface).map_err(|error| {
+        Error::InterfaceIndexError(
+            interface.to_owned(),
+            io::Error::new(io::ErrorKind::InvalidInput, error),
+        )
+    })?;
+    // SAFETY: `c_name` is a valid nul-terminated string that outlives the call.
+    let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+    if index == 0 {
+        return Err(Error::InterfaceIndexError(
+            interface.to_owned(),
+            io::Error::last_os_error(),
+        ));
+    }
+    Ok(index)
+}
+
+/// Binds the socket `fd` to `interface` using `SO_BINDTODEVICE`.
+#[cfg(target_os = "linux")]
+pub fn bind_to_device(fd: RawFd, interface: &str, _ip_version: IpVersion) -> Result<(), Error> {
+    let name = interface.as_bytes();
+    // SAFETY: `name` is valid for `name.len()` bytes, and the kernel does not require the name to
+    // be nul-terminated when the length is given explicitly.
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            name.as_ptr() as *const libc::c_void,
+            name.len() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(Error::BindError(
+            interface.to_owned(),
+            io::Error::last_os_error(),
+        ));
+    }
+    Ok(())
+}
+
+/// Binds the socket `fd` to `interface` using `IP_BOUND_IF` or `IPV6_BOUND_IF`, depending on the
+/// address family of the socket.
+#[cfg(target_os = "macos")]
+pub fn bind_to_device(fd: RawFd, interface: &str, ip_version: IpVersion) -> Result<(), Error> {
+    let index = interface_index(interface)?;
+    let (level, option) = match ip_version {
+        IpVersion::V4 => (libc::IPPROTO_IP, libc::IP_BOUND_IF),
+        IpVersion::V6 => (libc::IPPROTO_IPV6, IPV6_BOUND_IF),
+    };
+    // SAFETY: `index` is valid for the size of a `u32` for the duration of the call.
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            option,
+            &index as *const u32 as *const libc::c_void,
+            std::mem::size_of::<u32>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(Error::BindError(
+            interface.to_owned(),
+            io::Error::last_os_error(),
+        ));
+    }
+    Ok(())
+}
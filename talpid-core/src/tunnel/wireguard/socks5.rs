@@ -0,0 +1,269 @@
+//! Forwards WireGuard traffic to a relay through a user-provided SOCKS5 proxy.
+//!
+//! Most proxies that users are required to go through do not support UDP, so datagrams are
+//! framed the same way as by udp-over-tcp and sent over a TCP connection that the proxy makes to
+//! the TCP port of the relay.
+
+use super::udp2tcp::forward;
+use futures::future::{abortable, AbortHandle};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use talpid_types::{
+    net::{openvpn::ProxyAuth, proxy::EntryProxy},
+    ErrorExt,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpSocket, TcpStream, UdpSocket},
+};
+
+/// How long to wait for the proxy to accept the connection and complete the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_NONE: u8 = 0x00;
+const AUTH_USERNAME_PASSWORD: u8 = 0x02;
+const USERNAME_PASSWORD_VERSION: u8 = 0x01;
+const COMMAND_CONNECT: u8 = 0x01;
+const ADDRESS_TYPE_IPV4: u8 = 0x01;
+const ADDRESS_TYPE_DOMAIN: u8 = 0x03;
+const ADDRESS_TYPE_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+
+/// Errors that can occur when connecting through the entry proxy.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Failed to reach the proxy.
+    #[error(display = "Failed to connect to the entry proxy")]
+    ConnectProxy(#[error(source)] io::Error),
+
+    /// The proxy did not complete the handshake in time.
+    #[error(display = "Timed out waiting for the entry proxy to respond")]
+    Timeout,
+
+    /// The proxy did not respond as a SOCKS5 server.
+    #[error(display = "The entry proxy is not a SOCKS5 proxy")]
+    NotSocks5,
+
+    /// The proxy requires an authentication method that is not supported or configured.
+    #[error(display = "The entry proxy requires an unsupported authentication method")]
+    UnsupportedAuthMethod,
+
+    /// The proxy rejected the username or password.
+    #[error(display = "The entry proxy rejected the credentials")]
+    AuthFailed,
+
+    /// The proxy could not connect to the relay.
+    #[error(
+        display = "The entry proxy failed to connect to the relay, reply code {}",
+        _0
+    )]
+    ConnectRelay(u8),
+
+    /// Failed to bind the local UDP socket that WireGuard sends to.
+    #[error(display = "Failed to bind local UDP socket")]
+    BindUdp(#[error(source)] io::Error),
+}
+
+/// Local UDP endpoint that forwards WireGuard traffic to a relay through a SOCKS5 proxy.
+pub struct Socks5Forwarder {
+    local_addr: SocketAddr,
+    abort_handle: AbortHandle,
+    is_running: Arc<AtomicBool>,
+}
+
+impl Socks5Forwarder {
+    /// Connects to `relay` through `proxy`, and starts forwarding datagrams sent to the returned
+    /// forwarder's local address. Fails if the proxy cannot be reached or refuses to connect to
+    /// the relay, which serves as a health check of the proxy before the tunnel is brought up.
+    pub fn new(
+        runtime: &tokio::runtime::Handle,
+        proxy: &EntryProxy,
+        relay: SocketAddr,
+    ) -> Result<Self, Error> {
+        let (udp_socket, stream) = runtime.block_on(async {
+            let stream = tokio::time::timeout(HANDSHAKE_TIMEOUT, connect(proxy, relay))
+                .await
+                .map_err(|_| Error::Timeout)??;
+            let listen_addr = if relay.is_ipv4() {
+                SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)
+            } else {
+                SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 0)
+            };
+            let udp_socket = UdpSocket::bind(listen_addr).await.map_err(Error::BindUdp)?;
+            Ok::<_, Error>((udp_socket, stream))
+        })?;
+        let local_addr = udp_socket.local_addr().map_err(Error::BindUdp)?;
+
+        log::debug!("Connected to {} through {}", relay, proxy);
+
+        let (forward_future, abort_handle) = abortable(async move {
+            if let Err(error) = forward(udp_socket, stream).await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Entry proxy connection failed")
+                );
+            }
+        });
+        let is_running = Arc::new(AtomicBool::new(true));
+        let task_is_running = is_running.clone();
+        runtime.spawn(async move {
+            let _ = forward_future.await;
+            task_is_running.store(false, Ordering::SeqCst);
+        });
+
+        Ok(Self {
+            local_addr,
+            abort_handle,
+            is_running,
+        })
+    }
+
+    pub fn local_udp_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns a flag that is cleared once the forwarder stops forwarding traffic.
+    pub fn is_running(&self) -> Arc<AtomicBool> {
+        self.is_running.clone()
+    }
+}
+
+impl Drop for Socks5Forwarder {
+    fn drop(&mut self) {
+        self.abort_handle.abort();
+    }
+}
+
+/// Opens a TCP connection to `target` through the SOCKS5 proxy.
+async fn connect(proxy: &EntryProxy, target: SocketAddr) -> Result<TcpStream, Error> {
+    let socket = if proxy.address.is_ipv4() {
+        TcpSocket::new_v4()
+    } else {
+        TcpSocket::new_v6()
+    }
+    .map_err(Error::ConnectProxy)?;
+
+    #[cfg(target_os = "linux")]
+    socket2::SockRef::from(&socket)
+        .set_mark(crate::linux::TUNNEL_FW_MARK)
+        .map_err(Error::ConnectProxy)?;
+
+    let mut stream = socket
+        .connect(proxy.address)
+        .await
+        .map_err(Error::ConnectProxy)?;
+    stream.set_nodelay(true).map_err(Error::ConnectProxy)?;
+
+    authenticate(&mut stream, proxy.auth.as_ref()).await?;
+    request_connect(&mut stream, target).await?;
+
+    Ok(stream)
+}
+
+async fn authenticate(stream: &mut TcpStream, auth: Option<&ProxyAuth>) -> Result<(), Error> {
+    let greeting: &[u8] = match auth {
+        Some(_) => &[SOCKS_VERSION, 2, AUTH_NONE, AUTH_USERNAME_PASSWORD],
+        None => &[SOCKS_VERSION, 1, AUTH_NONE],
+    };
+    stream
+        .write_all(greeting)
+        .await
+        .map_err(Error::ConnectProxy)?;
+
+    let mut reply = [0u8; 2];
+    stream
+        .read_exact(&mut reply)
+        .await
+        .map_err(Error::ConnectProxy)?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(Error::NotSocks5);
+    }
+
+    match (reply[1], auth) {
+        (AUTH_NONE, _) => Ok(()),
+        (AUTH_USERNAME_PASSWORD, Some(auth)) => {
+            // RFC 1929
+            let username = auth.username.as_bytes();
+            let password = auth.password.as_bytes();
+            if username.len() > usize::from(u8::MAX) || password.len() > usize::from(u8::MAX) {
+                return Err(Error::AuthFailed);
+            }
+            let mut request = Vec::with_capacity(3 + username.len() + password.len());
+            request.push(USERNAME_PASSWORD_VERSION);
+            request.push(username.len() as u8);
+            request.extend_from_slice(username);
+            request.push(password.len() as u8);
+            request.extend_from_slice(password);
+            stream
+                .write_all(&request)
+                .await
+                .map_err(Error::ConnectProxy)?;
+
+            let mut reply = [0u8; 2];
+            stream
+                .read_exact(&mut reply)
+                .await
+                .map_err(Error::ConnectProxy)?;
+            if reply[1] != 0 {
+                return Err(Error::AuthFailed);
+            }
+            Ok(())
+        }
+        _ => Err(Error::UnsupportedAuthMethod),
+    }
+}
+
+async fn request_connect(stream: &mut TcpStream, target: SocketAddr) -> Result<(), Error> {
+    let mut request = vec![SOCKS_VERSION, COMMAND_CONNECT, 0];
+    match target.ip() {
+        IpAddr::V4(address) => {
+            request.push(ADDRESS_TYPE_IPV4);
+            request.extend_from_slice(&address.octets());
+        }
+        IpAddr::V6(address) => {
+            request.push(ADDRESS_TYPE_IPV6);
+            request.extend_from_slice(&address.octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream
+        .write_all(&request)
+        .await
+        .map_err(Error::ConnectProxy)?;
+
+    let mut reply = [0u8; 4];
+    stream
+        .read_exact(&mut reply)
+        .await
+        .map_err(Error::ConnectProxy)?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(Error::NotSocks5);
+    }
+    if reply[1] != REPLY_SUCCEEDED {
+        return Err(Error::ConnectRelay(reply[1]));
+    }
+
+    // Skip the address the proxy bound to
+    let address_len = match reply[3] {
+        ADDRESS_TYPE_IPV4 => 4,
+        ADDRESS_TYPE_IPV6 => 16,
+        ADDRESS_TYPE_DOMAIN => usize::from(stream.read_u8().await.map_err(Error::ConnectProxy)?),
+        _ => return Err(Error::NotSocks5),
+    };
+    let mut bound_address = vec![0u8; address_len + 2];
+    stream
+        .read_exact(&mut bound_address)
+        .await
+        .map_err(Error::ConnectProxy)?;
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+//! Extension point for transports that carry WireGuard traffic to the entry relay, so that
+//! experimental obfuscators can be developed outside of this crate.
+//!
+//! A registered plugin wraps the traffic of the entry peer in place of UDP-over-TCP. It is not
+//! used when connecting through an entry proxy.
+
+use lazy_static::lazy_static;
+use std::{
+    net::SocketAddr,
+    sync::{atomic::AtomicBool, Arc, Mutex},
+};
+use talpid_types::{
+    net::{Endpoint, TunnelEndpoint, TunnelParameters},
+    tunnel::ObfuscatorKind,
+    BoxedError,
+};
+
+lazy_static! {
+    static ref TRANSPORT_PLUGIN: Mutex<Option<Arc<dyn TunnelTransportPlugin>>> = Mutex::new(None);
+}
+
+/// Carries WireGuard traffic between a local UDP socket and a relay.
+///
+/// Only traffic to the endpoint of the peer is allowed through the firewall, so the transport
+/// must reach the relay at that address, port and protocol. On Linux, its sockets must also be
+/// marked with [`crate::linux::TUNNEL_FW_MARK`] to be routed outside the tunnel.
+pub trait TunnelTransportPlugin: Send + Sync {
+    /// Name of the transport, used in logs.
+    fn name(&self) -> &str;
+
+    /// Starts forwarding datagrams between a local UDP socket and `endpoint`. This is called
+    /// once for every tunnel that is opened.
+    fn start(
+        &self,
+        runtime: &tokio::runtime::Handle,
+        endpoint: Endpoint,
+    ) -> Result<Box<dyn TransportHandle>, BoxedError>;
+}
+
+/// A running transport. It must stop forwarding traffic when dropped.
+pub trait TransportHandle: Send + Sync {
+    /// Local address that WireGuard sends the traffic of the peer to.
+    fn local_udp_addr(&self) -> SocketAddr;
+
+    /// Returns a flag that is cleared once the transport stops forwarding traffic.
+    fn is_running(&self) -> Arc<AtomicBool>;
+}
+
+/// Makes new WireGuard tunnels use `plugin`, replacing any plugin that was registered before.
+pub fn register(plugin: Arc<dyn TunnelTransportPlugin>) {
+    *TRANSPORT_PLUGIN
+        .lock()
+        .expect("transport plugin lock poisoned") = Some(plugin);
+}
+
+/// Makes new WireGuard tunnels stop using a transport plugin.
+pub fn unregister() {
+    *TRANSPORT_PLUGIN
+        .lock()
+        .expect("transport plugin lock poisoned") = None;
+}
+
+/// Returns the plugin that new WireGuard tunnels use, if any.
+pub fn registered() -> Option<Arc<dyn TunnelTransportPlugin>> {
+    TRANSPORT_PLUGIN
+        .lock()
+        .expect("transport plugin lock poisoned")
+        .clone()
+}
+
+/// Returns the tunnel endpoint of `params`, with the obfuscation set if the traffic is carried by
+/// the registered plugin.
+pub(crate) fn get_tunnel_endpoint(params: &TunnelParameters) -> TunnelEndpoint {
+    let mut endpoint = params.get_tunnel_endpoint();
+    if let TunnelParameters::Wireguard(params) = params {
+        if params.entry_proxy.is_none() && registered().is_some() {
+            endpoint.obfuscation = Some(ObfuscatorKind::Plugin);
+        }
+    }
+    endpoint
+}
@@ -0,0 +1,186 @@
+//! Forwards WireGuard traffic to the TCP port of a relay. Each datagram is prefixed with its
+//! length as a big-endian 16-bit integer, which is the framing used by udp-over-tcp on the relays.
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use super::socket_binding;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use crate::routing;
+use futures::future::{self, abortable, AbortHandle, Either};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::os::unix::io::AsRawFd;
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use talpid_types::net::IpVersion;
+use talpid_types::ErrorExt;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpSocket, TcpStream, UdpSocket,
+    },
+};
+
+/// Largest datagram that can be framed with a 16-bit length header.
+const MAX_DATAGRAM_SIZE: usize = u16::MAX as usize;
+
+/// Local UDP endpoint that forwards WireGuard traffic to the TCP port of a relay.
+pub struct TcpProxy {
+    local_addr: SocketAddr,
+    abort_handle: AbortHandle,
+    is_running: Arc<AtomicBool>,
+}
+
+impl TcpProxy {
+    /// Connects to `endpoint` and starts forwarding datagrams sent to the returned proxy's local
+    /// address. On Linux and macOS, the TCP socket is bound to the interface that currently
+    /// routes traffic to `endpoint`. A connected socket cannot be moved to another interface, so
+    /// the connection is not rebound if the route changes later on.
+    pub fn new(
+        runtime: &tokio::runtime::Handle,
+        endpoint: SocketAddr,
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        route_handle: &routing::RouteManagerHandle,
+    ) -> io::Result<Self> {
+        let (udp_socket, stream) = runtime.block_on(async {
+            let socket = if endpoint.is_ipv4() {
+                TcpSocket::new_v4()
+            } else {
+                TcpSocket::new_v6()
+            }?;
+
+            #[cfg(target_os = "linux")]
+            socket2::SockRef::from(&socket).set_mark(crate::linux::TUNNEL_FW_MARK)?;
+
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            bind_to_endpoint_interface(&socket, route_handle, endpoint).await;
+
+            let stream = socket.connect(endpoint).await?;
+            stream.set_nodelay(true)?;
+
+            let listen_addr = if endpoint.is_ipv4() {
+                SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)
+            } else {
+                SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 0)
+            };
+            let udp_socket = UdpSocket::bind(listen_addr).await?;
+            Ok::<_, io::Error>((udp_socket, stream))
+        })?;
+        let local_addr = udp_socket.local_addr()?;
+
+        let (forward_future, abort_handle) = abortable(async move {
+            if let Err(error) = forward(udp_socket, stream).await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("UDP-over-TCP connection failed")
+                );
+            }
+        });
+        let is_running = Arc::new(AtomicBool::new(true));
+        let task_is_running = is_running.clone();
+        runtime.spawn(async move {
+            let _ = forward_future.await;
+            task_is_running.store(false, Ordering::SeqCst);
+        });
+
+        Ok(Self {
+            local_addr,
+            abort_handle,
+            is_running,
+        })
+    }
+
+    pub fn local_udp_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns a flag that is cleared once the proxy stops forwarding traffic.
+    pub fn is_running(&self) -> Arc<AtomicBool> {
+        self.is_running.clone()
+    }
+}
+
+impl Drop for TcpProxy {
+    fn drop(&mut self) {
+        self.abort_handle.abort();
+    }
+}
+
+/// Binds `socket` to the interface that currently routes traffic to `endpoint`. Failing to do so
+/// is not fatal, for the same reason as for the sockets of the tunnel itself.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+async fn bind_to_endpoint_interface(
+    socket: &TcpSocket,
+    route_handle: &routing::RouteManagerHandle,
+    endpoint: SocketAddr,
+) {
+    let ip_version = if endpoint.is_ipv4() {
+        IpVersion::V4
+    } else {
+        IpVersion::V6
+    };
+    let result = socket_binding::interface_for_endpoint(route_handle, endpoint.ip())
+        .await
+        .and_then(|interface| {
+            socket_binding::bind_to_device(socket.as_raw_fd(), &interface, ip_version)?;
+            Ok(interface)
+        });
+    match result {
+        Ok(interface) => log::debug!("Bound UDP-over-TCP socket to interface {}", interface),
+        Err(error) => log::warn!(
+            "{}",
+            error.display_chain_with_msg("Not binding UDP-over-TCP socket to an interface")
+        ),
+    }
+}
+
+/// Forwards datagrams between the local UDP socket and the TCP stream until either side fails.
+pub(super) async fn forward(udp_socket: UdpSocket, stream: TcpStream) -> io::Result<()> {
+    let (mut tcp_rx, mut tcp_tx) = stream.into_split();
+
+    // WireGuard sends the first datagram before the relay can respond, so the local peer is
+    // always known by the time anything needs to be sent back.
+    let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+    let (len, peer) = udp_socket.recv_from(&mut buffer).await?;
+    udp_socket.connect(peer).await?;
+    write_datagram(&mut tcp_tx, &buffer[..len]).await?;
+
+    let udp_to_tcp = Box::pin(udp_to_tcp(&udp_socket, &mut tcp_tx, buffer));
+    let tcp_to_udp = Box::pin(tcp_to_udp(&mut tcp_rx, &udp_socket));
+    match future::select(udp_to_tcp, tcp_to_udp).await {
+        Either::Left((result, _)) | Either::Right((result, _)) => result,
+    }
+}
+
+async fn udp_to_tcp(
+    udp_socket: &UdpSocket,
+    tcp_tx: &mut OwnedWriteHalf,
+    mut buffer: Vec<u8>,
+) -> io::Result<()> {
+    loop {
+        let len = udp_socket.recv(&mut buffer).await?;
+        write_datagram(tcp_tx, &buffer[..len]).await?;
+    }
+}
+
+async fn tcp_to_udp(tcp_rx: &mut OwnedReadHalf, udp_socket: &UdpSocket) -> io::Result<()> {
+    let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let len = usize::from(tcp_rx.read_u16().await?);
+        tcp_rx.read_exact(&mut buffer[..len]).await?;
+        udp_socket.send(&buffer[..len]).await?;
+    }
+}
+
+async fn write_datagram(tcp_tx: &mut OwnedWriteHalf, datagram: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(2 + datagram.len());
+    frame.extend_from_slice(&(datagram.len() as u16).to_be_bytes());
+    frame.extend_from_slice(datagram);
+    tcp_tx.write_all(&frame).await
+}
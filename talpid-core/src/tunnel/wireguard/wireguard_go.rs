@@ -1,4 +1,7 @@
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use super::socket_binding;
 use super::{
+    operation_pool::OperationTimings,
     stats::{Stats, StatsMap},
     Config, Tunnel, TunnelError,
 };
@@ -7,7 +10,8 @@ use crate::routing;
 #[cfg(not(windows))]
 use crate::tunnel::tun_provider::TunProvider;
 use crate::tunnel::wireguard::logging::{
-    clean_up_logging, initialize_logging, wg_go_logging_callback, WgLogLevel,
+    clean_up_logging, initialize_logging, recent_events, subscribe_events, wg_go_logging_callback,
+    EndpointChangeHandle, LoggedEvent, WgGoEvent, WgLogLevel,
 };
 #[cfg(windows)]
 use futures::SinkExt;
@@ -17,7 +21,11 @@ use std::{
     ffi::{c_void, CStr},
     os::raw::c_char,
     path::Path,
+    sync::Arc,
+    time::Instant,
 };
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use talpid_types::net::IpVersion;
 #[cfg(windows)]
 use talpid_types::BoxedError;
 use zeroize::Zeroize;
@@ -60,8 +68,10 @@ pub struct WgGoTunnel {
     // live long enough and get closed when the tunnel is stopped
     #[cfg(not(target_os = "windows"))]
     _tunnel_device: Tun,
-    // context that maps to fs::File instance, used with logging callback
-    _logging_context: LoggingContext,
+    // context that maps to fs::File instance and parsed events, used with logging callback
+    logging_context: LoggingContext,
+    // durations of the calls into wireguard-go
+    timings: Arc<OperationTimings>,
     #[cfg(target_os = "windows")]
     _route_callback_handle: Option<crate::winnet::WinNetCallbackHandle>,
     #[cfg(target_os = "windows")]
@@ -86,6 +96,7 @@ impl WgGoTunnel {
 
         #[cfg(not(target_os = "android"))]
         let mtu = config.mtu as isize;
+        let start = Instant::now();
         let handle = unsafe {
             wgTurnOn(
                 #[cfg(not(target_os = "android"))]
@@ -97,6 +108,8 @@ impl WgGoTunnel {
             )
         };
         check_wg_status(handle)?;
+        let timings = Arc::new(OperationTimings::default());
+        timings.record("turn on", start.elapsed());
 
         #[cfg(target_os = "android")]
         Self::bypass_tunnel_sockets(&mut tunnel_device, handle)
@@ -106,7 +119,8 @@ impl WgGoTunnel {
             interface_name,
             handle: Some(handle),
             _tunnel_device: tunnel_device,
-            _logging_context: logging_context,
+            logging_context,
+            timings,
         })
     }
 
@@ -125,7 +139,7 @@ impl WgGoTunnel {
         }
 
         let wg_config_str = config.to_userspace_format();
-        let iface_name: String = "Mullvad".to_string();
+        let iface_name = &config.interface_name;
         let cstr_iface_name =
             CString::new(iface_name.as_bytes()).map_err(TunnelError::InterfaceNameError)?;
         let logging_context = initialize_logging(log_path)
@@ -135,6 +149,7 @@ impl WgGoTunnel {
         let mut alias_ptr = std::ptr::null_mut();
         let mut interface_luid = 0u64;
 
+        let start = Instant::now();
         let handle = unsafe {
             wgTurnOn(
                 cstr_iface_name.as_ptr(),
@@ -147,6 +162,8 @@ impl WgGoTunnel {
             )
         };
         check_wg_status(handle)?;
+        let timings = Arc::new(OperationTimings::default());
+        timings.record("turn on", start.elapsed());
 
         let actual_iface_name = {
             let actual_iface_name_c = unsafe { CStr::from_ptr(alias_ptr) };
@@ -159,17 +176,22 @@ impl WgGoTunnel {
         };
 
         log::debug!("Adapter alias: {}", actual_iface_name);
+        if actual_iface_name != *iface_name {
+            log::warn!(
+                "The interface name \"{}\" is used by another adapter. Using \"{}\" instead",
+                iface_name,
+                actual_iface_name
+            );
+        }
 
-        let has_ipv6 = config.tunnel.addresses.iter().any(|addr| addr.is_ipv6());
+        let has_ipv4 = config.has_ipv4();
+        let has_ipv6 = config.has_ipv6();
         let setup_handle = tokio::spawn(async move {
-            use winapi::shared::ifdef::NET_LUID;
-            let luid = NET_LUID {
-                Value: interface_luid,
-            };
+            let luid = talpid_windows_net::Luid::from_raw(interface_luid);
             log::debug!("Waiting for tunnel IP interfaces to arrive");
             let _ = done_tx
                 .send(
-                    crate::windows::wait_for_interfaces(luid, true, has_ipv6)
+                    talpid_windows_net::wait_for_interfaces(luid, has_ipv4, has_ipv6)
                         .await
                         .map_err(|error| BoxedError::new(TunnelError::SetupIpInterfaces(error))),
                 )
@@ -181,7 +203,8 @@ impl WgGoTunnel {
             interface_name: actual_iface_name,
             handle: Some(handle),
             setup_handle,
-            _logging_context: logging_context,
+            logging_context,
+            timings,
             _route_callback_handle: route_callback_handle,
         })
     }
@@ -222,7 +245,10 @@ impl WgGoTunnel {
 
     #[cfg(not(target_os = "windows"))]
     fn create_tunnel_config(config: &Config, routes: impl Iterator<Item = IpNetwork>) -> TunConfig {
-        let mut dns_servers = vec![IpAddr::V4(config.ipv4_gateway)];
+        let mut dns_servers = vec![];
+        if config.has_ipv4() {
+            dns_servers.push(IpAddr::V4(config.ipv4_gateway));
+        }
         dns_servers.extend(config.ipv6_gateway.map(IpAddr::V6));
 
         TunConfig {
@@ -237,8 +263,13 @@ impl WgGoTunnel {
 
     #[cfg(target_os = "android")]
     fn create_required_routes(config: &Config) -> Vec<IpNetwork> {
-        let mut required_routes = vec![IpNetwork::new(IpAddr::V4(config.ipv4_gateway), 32)
-            .expect("Invalid IPv4 network prefix")];
+        let mut required_routes = vec![];
+        if config.has_ipv4() {
+            required_routes.push(
+                IpNetwork::new(IpAddr::V4(config.ipv4_gateway), 32)
+                    .expect("Invalid IPv4 network prefix"),
+            );
+        }
 
         required_routes.extend(config.ipv6_gateway.map(|address| {
             IpNetwork::new(IpAddr::V6(address), 128).expect("Invalid IPv6 network prefix")
@@ -315,9 +346,31 @@ impl Tunnel for WgGoTunnel {
         self.interface_name.clone()
     }
 
+    fn backend_name(&self) -> &'static str {
+        "wireguard-go"
+    }
+
+    fn recent_events(&self) -> Vec<LoggedEvent> {
+        recent_events(self.logging_context.0)
+    }
+
+    fn subscribe_events(&self) -> Option<futures::channel::mpsc::UnboundedReceiver<WgGoEvent>> {
+        Some(subscribe_events(self.logging_context.0))
+    }
+
+    fn operation_timings(&self) -> Option<Arc<OperationTimings>> {
+        Some(self.timings.clone())
+    }
+
+    fn endpoint_changes(&self) -> Option<EndpointChangeHandle> {
+        Some(EndpointChangeHandle::new(self.logging_context.0))
+    }
+
     fn get_tunnel_stats(&self) -> Result<StatsMap> {
         let config_str = unsafe {
-            let ptr = wgGetConfig(self.handle.unwrap());
+            let ptr = self
+                .timings
+                .time("get config", || wgGetConfig(self.handle.unwrap()));
             if ptr.is_null() {
                 log::error!("Failed to get config !");
                 return Err(TunnelError::GetConfigError);
@@ -346,6 +399,56 @@ impl Tunnel for WgGoTunnel {
     fn stop(mut self: Box<Self>) -> Result<()> {
         self.stop_tunnel()
     }
+
+    #[cfg(target_os = "linux")]
+    fn bind_to_interface(&self, interface: &str, ip_version: IpVersion) -> Result<()> {
+        let handle = match self.handle {
+            Some(handle) => handle,
+            None => return Ok(()),
+        };
+        let socket = match ip_version {
+            IpVersion::V4 => unsafe { wgGetSocketV4(handle) },
+            IpVersion::V6 => unsafe { wgGetSocketV6(handle) },
+        };
+        if socket < 0 {
+            log::debug!("wireguard-go has no {} socket to bind", ip_version);
+            return Ok(());
+        }
+        self.timings
+            .time("bind socket", || {
+                socket_binding::bind_to_device(socket, interface, ip_version)
+            })
+            .map_err(TunnelError::BindSocketError)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn bind_to_interface(&self, interface: &str, ip_version: IpVersion) -> Result<()> {
+        let handle = match self.handle {
+            Some(handle) => handle,
+            None => return Ok(()),
+        };
+        let index =
+            socket_binding::interface_index(interface).map_err(TunnelError::BindSocketError)?;
+        let family = match ip_version {
+            IpVersion::V4 => libc::AF_INET,
+            IpVersion::V6 => libc::AF_INET6,
+        };
+        let status = self.timings.time("bind socket", || unsafe {
+            wgBindTunnelSocket(handle, family as u16, index)
+        });
+        if status < 0 {
+            return Err(TunnelError::BindSocketError(
+                socket_binding::Error::BindError(
+                    interface.to_owned(),
+                    std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        "wireguard-go failed to bind the socket",
+                    ),
+                ),
+            ));
+        }
+        Ok(())
+    }
 }
 
 fn check_wg_status(wg_code: i32) -> Result<()> {
@@ -418,13 +521,17 @@ extern "C" {
     fn wgFreePtr(ptr: *mut c_void);
 
     // Returns the file descriptor of the tunnel IPv4 socket.
-    #[cfg(target_os = "android")]
+    #[cfg(any(target_os = "android", target_os = "linux"))]
     fn wgGetSocketV4(handle: i32) -> Fd;
 
     // Returns the file descriptor of the tunnel IPv6 socket.
-    #[cfg(target_os = "android")]
+    #[cfg(any(target_os = "android", target_os = "linux"))]
     fn wgGetSocketV6(handle: i32) -> Fd;
 
+    // Bind the tunnel socket of the given address family to an interface index.
+    #[cfg(target_os = "macos")]
+    fn wgBindTunnelSocket(handle: i32, family: u16, interface_index: u32) -> i32;
+
     // Rebind tunnel socket when network interfaces change
     #[cfg(target_os = "windows")]
     fn wgRebindTunnelSocket(family: u16, interfaceIndex: u32);
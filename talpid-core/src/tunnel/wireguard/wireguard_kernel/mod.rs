@@ -1,5 +1,6 @@
 use super::{Config, Tunnel, TunnelError};
 use futures::future::{abortable, AbortHandle};
+use futures::TryStreamExt;
 use netlink_packet_core::{constants::*, NetlinkDeserializable};
 use netlink_packet_route::{
     rtnl::{
@@ -29,6 +30,10 @@ pub use netlink_tunnel::NetlinkTunnel;
 pub mod nm_tunnel;
 pub use nm_tunnel::NetworkManagerTunnel;
 
+/// Alias given to the WireGuard devices created here. It tells a device left behind by a previous
+/// run apart from a device with the same name that was created by someone else.
+const DEVICE_ALIAS: &str = "talpid";
+
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
 pub enum Error {
@@ -74,18 +79,25 @@ pub enum Error {
     #[error(display = "Create device error")]
     NetlinkCreateDeviceError(#[error(source)] rtnetlink::Error),
 
+    #[error(display = "The interface name {} is used by another interface", _0)]
+    InterfaceNameInUse(String),
+
     #[error(display = "Add IP to device error")]
     NetlinkSetIpError(rtnetlink::Error),
 
     #[error(display = "Failed to delete device")]
     DeleteDeviceError(#[error(source)] rtnetlink::Error),
 
+    #[error(display = "Failed to get device")]
+    GetDeviceError(rtnetlink::Error),
+
+    #[error(display = "Failed to set device alias")]
+    SetDeviceAliasError(rtnetlink::Error),
+
     #[error(display = "NetworkManager error")]
     NetworkManager(#[error(source)] nm_tunnel::Error),
 }
 
-pub(crate) const MULLVAD_INTERFACE_NAME: &str = "wg-mullvad";
-
 #[derive(Debug)]
 pub struct Handle {
     pub wg_handle: WireguardConnection,
@@ -153,8 +165,20 @@ impl Handle {
         result
     }
 
-    // create a wireguard device with the given name.
+    // create a wireguard device with the given name. A device with the same name that was left
+    // behind by a previous run that did not shut down cleanly is removed first. Any other
+    // interface with the same name is left alone.
     pub async fn create_device(&mut self, name: String, mtu: u32) -> Result<u32, Error> {
+        if let Ok(stale_device) = self.wg_handle.get_by_name(name.clone()).await {
+            if let Some(index) = device_index(&stale_device) {
+                if self.get_device_alias(index).await?.as_deref() != Some(DEVICE_ALIAS) {
+                    return Err(Error::InterfaceNameInUse(name));
+                }
+                log::warn!("Removing stale WireGuard interface {}", name);
+                self.delete_device(index).await?;
+            }
+        }
+
         let mut message = LinkMessage::default();
 
         // set link to be up
@@ -172,32 +196,73 @@ impl Handle {
             ))]));
 
         let mut add_request = NetlinkMessage::from(RtnlMessage::NewLink(message));
-        add_request.header.flags =
-            NLM_F_REQUEST | NLM_F_ACK | NLM_F_REPLACE | NLM_F_CREATE | NLM_F_MATCH;
+        add_request.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL;
         let mut response = self
             .route_handle
             .request(add_request)
             .map_err(Error::NetlinkCreateDeviceError)?;
         while let Some(response_message) = response.next().await {
             if let NetlinkPayload::Error(err) = response_message.payload {
-                // if the device exists, verify that it's a wireguard device
-                if -err.code != libc::EEXIST {
-                    return Err(Error::NetlinkCreateDeviceError(
-                        rtnetlink::Error::NetlinkError(err),
-                    ));
+                // Any WireGuard device with this name has been removed, so the name is taken by
+                // some other kind of interface
+                if -err.code == libc::EEXIST {
+                    return Err(Error::InterfaceNameInUse(name));
                 }
+                return Err(Error::NetlinkCreateDeviceError(
+                    rtnetlink::Error::NetlinkError(err),
+                ));
             }
         }
 
         // fetch interface index of new device
         let new_device = self.wg_handle.get_by_name(name).await?;
-        for nla in new_device.nlas {
-            if let DeviceNla::IfIndex(index) = nla {
-                return Ok(index);
+        let index = device_index(&new_device).ok_or(Error::NoDevice)?;
+
+        if let Err(error) = self.set_device_alias(index).await {
+            if let Err(delete_error) = self.delete_device(index).await {
+                log::error!("Failed to delete WireGuard device: {}", delete_error);
             }
+            return Err(error);
         }
+        Ok(index)
+    }
 
-        Err(Error::NoDevice)
+    async fn get_device_alias(&mut self, index: u32) -> Result<Option<String>, Error> {
+        let link = self
+            .route_handle
+            .link()
+            .get()
+            .match_index(index)
+            .execute()
+            .try_next()
+            .await
+            .map_err(Error::GetDeviceError)?
+            .ok_or(Error::NoDevice)?;
+        Ok(link.nlas.into_iter().find_map(|nla| match nla {
+            LinkNla::IfAlias(alias) => Some(alias),
+            _ => None,
+        }))
+    }
+
+    async fn set_device_alias(&mut self, index: u32) -> Result<(), Error> {
+        let mut message = LinkMessage::default();
+        message.header.index = index;
+        message
+            .nlas
+            .push(LinkNla::IfAlias(DEVICE_ALIAS.to_string()));
+
+        let mut request = NetlinkMessage::from(RtnlMessage::SetLink(message));
+        request.header.flags = NLM_F_REQUEST | NLM_F_ACK;
+
+        let mut response = self
+            .route_handle
+            .request(request)
+            .map_err(Error::SetDeviceAliasError)?;
+        while let Some(response_message) = response.next().await {
+            consume_netlink_error(response_message, Error::SetDeviceAliasError)?;
+        }
+
+        Ok(())
     }
 
     pub async fn set_ip_address(&mut self, index: u32, addr: IpAddr) -> Result<(), Error> {
@@ -235,6 +300,13 @@ impl Handle {
     }
 }
 
+fn device_index(device: &DeviceMessage) -> Option<u32> {
+    device.nlas.iter().find_map(|nla| match nla {
+        DeviceNla::IfIndex(index) => Some(*index),
+        _ => None,
+    })
+}
+
 impl Drop for Handle {
     fn drop(&mut self) {
         self.wg_abort_handle.abort();
@@ -1,11 +1,12 @@
 use super::{
     super::stats::{Stats, StatsMap},
     wg_message::DeviceNla,
-    Config, Error, Handle, Tunnel, TunnelError, MULLVAD_INTERFACE_NAME,
+    Config, Error, Handle, Tunnel, TunnelError,
 };
 
 pub struct NetlinkTunnel {
     interface_index: u32,
+    interface_name: String,
     netlink_connections: Handle,
     tokio_handle: tokio::runtime::Handle,
 }
@@ -15,11 +16,12 @@ impl NetlinkTunnel {
         tokio_handle.clone().block_on(async {
             let mut netlink_connections = Handle::connect().await?;
             let interface_index = netlink_connections
-                .create_device(MULLVAD_INTERFACE_NAME.to_string(), config.mtu as u32)
+                .create_device(config.interface_name.clone(), config.mtu as u32)
                 .await?;
 
             let mut tunnel = Self {
                 interface_index,
+                interface_name: config.interface_name.clone(),
                 netlink_connections,
                 tokio_handle,
             };
@@ -74,17 +76,22 @@ impl Tunnel for NetlinkTunnel {
         match result {
             Ok(name) => name.to_string_lossy().to_string(),
             Err(err) => {
-                log::error!("Failed to deduce interface name at runtime, will attempt to use the default name. {}", err);
-                MULLVAD_INTERFACE_NAME.to_string()
+                log::error!("Failed to deduce interface name at runtime, will attempt to use the configured name. {}", err);
+                self.interface_name.clone()
             }
         }
     }
 
+    fn backend_name(&self) -> &'static str {
+        "kernel"
+    }
+
     fn stop(self: Box<Self>) -> std::result::Result<(), TunnelError> {
         let Self {
             mut netlink_connections,
             interface_index,
             tokio_handle,
+            ..
         } = *self;
         tokio_handle.block_on(async move {
             if let Err(err) = netlink_connections.delete_device(interface_index).await {
@@ -1,15 +1,16 @@
 use super::{
     super::stats::{Stats, StatsMap},
-    Config, Error as WgKernelError, Handle, Tunnel, TunnelError, MULLVAD_INTERFACE_NAME,
+    Config, Error as WgKernelError, Handle, Tunnel, TunnelError,
 };
 use std::collections::HashMap;
 use talpid_dbus::{
-    dbus,
+    dbus::{self, arg::RefArg},
     network_manager::{
         DeviceConfig, Error as NetworkManagerError, NetworkManager, Variant, VariantMap,
         WireguardTunnel,
     },
 };
+use zeroize::Zeroize;
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -36,16 +37,16 @@ impl NetworkManagerTunnel {
         let network_manager = NetworkManager::new()
             .map_err(Error::NetworkManager)
             .map_err(WgKernelError::NetworkManager)?;
-        let config_map = convert_config_to_dbus(config);
-        let tunnel = network_manager
-            .create_wg_tunnel(&config_map)
-            .map_err(|err| WgKernelError::NetworkManager(err.into()))?;
+        let mut config_map = convert_config_to_dbus(config);
+        let result = network_manager.create_wg_tunnel(&config_map);
+        zeroize_private_key(&mut config_map);
+        let tunnel = result.map_err(|err| WgKernelError::NetworkManager(err.into()))?;
 
         let interface_name = match network_manager.get_interface_name(&tunnel) {
             Ok(name) => name,
             Err(error) => {
                 log::error!("Failed to fetch interface name from NM: {}", error);
-                MULLVAD_INTERFACE_NAME.to_string()
+                config.interface_name.clone()
             }
         };
         let netlink_connections = tokio_handle.block_on(Handle::connect())?;
@@ -65,6 +66,10 @@ impl Tunnel for NetworkManagerTunnel {
         self.interface_name.clone()
     }
 
+    fn backend_name(&self) -> &'static str {
+        "kernel (NetworkManager)"
+    }
+
     fn stop(mut self: Box<Self>) -> std::result::Result<(), TunnelError> {
         if let Some(tunnel) = self.tunnel.take() {
             if let Err(err) = self.network_manager.remove_tunnel(tunnel) {
@@ -134,11 +139,11 @@ fn convert_config_to_dbus(config: &Config) -> DeviceConfig {
     connection_config.insert("type".into(), Variant(Box::new("wireguard".to_string())));
     connection_config.insert(
         "id".into(),
-        Variant(Box::new(MULLVAD_INTERFACE_NAME.to_string())),
+        Variant(Box::new(config.interface_name.clone())),
     );
     connection_config.insert(
         "interface-name".into(),
-        Variant(Box::new(MULLVAD_INTERFACE_NAME.to_string())),
+        Variant(Box::new(config.interface_name.clone())),
     );
     connection_config.insert("autoconnect".into(), Variant(Box::new(true)));
 
@@ -183,3 +188,14 @@ fn convert_config_to_dbus(config: &Config) -> DeviceConfig {
 
     settings
 }
+
+/// Zeroes the private key in a config created by `convert_config_to_dbus`.
+fn zeroize_private_key(config: &mut DeviceConfig) {
+    if let Some(private_key) = config
+        .get_mut("wireguard")
+        .and_then(|wireguard_config| wireguard_config.get_mut("private-key"))
+        .and_then(|private_key| (*private_key.0).as_any_mut().downcast_mut::<String>())
+    {
+        private_key.zeroize();
+    }
+}
@@ -11,6 +11,7 @@ use netlink_packet_utils::{
 };
 use nix::sys::{socket::InetAddr, time::TimeSpec};
 use std::{ffi::CString, io::Write, mem, net::IpAddr};
+use zeroize::Zeroizing;
 
 /// WireGuard netlink constants
 mod constants {
@@ -63,7 +64,8 @@ mod constants {
 use constants::*;
 pub use constants::{WG_CMD_GET_DEVICE, WG_CMD_SET_DEVICE};
 
-type PrivateKey = [u8; 32];
+/// Zeroed when the message is dropped.
+type PrivateKey = Zeroizing<[u8; 32]>;
 type PublicKey = [u8; 32];
 type PresharedKey = [u8; 32];
 
@@ -93,7 +95,7 @@ impl DeviceMessage {
             DeviceNla::IfIndex(interface_index),
             DeviceNla::ListenPort(0),
             DeviceNla::Fwmark(crate::linux::TUNNEL_FW_MARK),
-            DeviceNla::PrivateKey(config.tunnel.private_key.to_bytes()),
+            DeviceNla::PrivateKey(Zeroizing::new(config.tunnel.private_key.to_bytes())),
             DeviceNla::Flags(WGDEVICE_F_REPLACE_PEERS),
             DeviceNla::Peers(peers),
         ];
@@ -205,7 +207,8 @@ impl Nla for DeviceNla {
         match self {
             IfIndex(_) | Fwmark(_) | Flags(_) => 4,
             IfName(name) => name.as_bytes_with_nul().len(),
-            PrivateKey(key) | PublicKey(key) => key.len(),
+            PrivateKey(key) => key.len(),
+            PublicKey(key) => key.len(),
             ListenPort(_) => 2,
             Peers(peers) => peers.as_slice().buffer_len(),
             Unspec(payload) => payload.len(),
@@ -238,7 +241,10 @@ impl Nla for DeviceNla {
                     .write(interface_name.as_bytes_with_nul())
                     .expect("Failed to write interface name");
             }
-            PrivateKey(key) | PublicKey(key) => {
+            PrivateKey(key) => {
+                let _ = buffer.write(&key[..]).expect("Failed to write key");
+            }
+            PublicKey(key) => {
                 let _ = buffer.write(key).expect("Failed to write key");
             }
             ListenPort(port) => NativeEndian::write_u16(buffer, *port),
@@ -262,7 +268,7 @@ impl<'a, T: AsRef<[u8]> + 'a + ?Sized + core::fmt::Debug> Parseable<NlaBuffer<&'
         let nla = match kind {
             WGDEVICE_A_IFINDEX => IfIndex(parsers::parse_u32(value)?),
             WGDEVICE_A_IFNAME => IfName(parsers::parse_cstring(value)?),
-            WGDEVICE_A_PRIVATE_KEY => PrivateKey(parsers::parse_wg_key(value)?.into()),
+            WGDEVICE_A_PRIVATE_KEY => PrivateKey(Zeroizing::new(parsers::parse_wg_key(value)?)),
             WGDEVICE_A_PUBLIC_KEY => PublicKey(parsers::parse_wg_key(value)?.into()),
             WGDEVICE_A_FLAGS => Flags(parsers::parse_u32(value)?),
             WGDEVICE_A_LISTEN_PORT => ListenPort(parsers::parse_u16(value)?),
@@ -776,10 +782,10 @@ mod test {
                 Fwmark(0),
                 IfIndex(320),
                 IfName(if_name),
-                PrivateKey([
+                PrivateKey(Zeroizing::new([
                     56, 71, 244, 173, 101, 223, 85, 22, 171, 175, 15, 39, 53, 180, 193, 198, 73,
                     55, 53, 59, 188, 26, 52, 74, 173, 179, 22, 213, 161, 71, 252, 125,
-                ]),
+                ])),
                 DeviceNla::PublicKey([
                     102, 218, 178, 222, 191, 21, 59, 83, 124, 180, 124, 41, 91, 10, 134, 199, 84,
                     186, 27, 218, 53, 216, 20, 93, 203, 82, 68, 74, 189, 142, 99, 59,
@@ -849,10 +855,10 @@ mod test {
             message_type: 0,
             nlas: [
                 IfName(if_name),
-                PrivateKey([
+                PrivateKey(Zeroizing::new([
                     56, 71, 244, 173, 101, 223, 85, 22, 171, 175, 15, 39, 53, 180, 193, 198, 73,
                     55, 53, 59, 188, 26, 52, 74, 173, 179, 22, 213, 161, 71, 252, 125,
-                ]),
+                ])),
                 ListenPort(51820),
                 Peers([peer_1, peer_2].to_vec()),
             ]
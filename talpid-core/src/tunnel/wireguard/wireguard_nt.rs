@@ -17,32 +17,34 @@ use std::{
     os::windows::io::RawHandle,
     path::Path,
     ptr,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{self, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use talpid_types::{BoxedError, ErrorExt};
+use talpid_windows_net::{prepare_tunnel_interface, AddressFamily, InetSockAddr, Luid};
 use widestring::{U16CStr, U16CString};
 use winapi::{
     shared::{
         guiddef::GUID,
-        ifdef::NET_LUID,
-        in6addr::IN6_ADDR,
-        inaddr::IN_ADDR,
         minwindef::{BOOL, FARPROC, HINSTANCE, HMODULE},
-        nldef::RouterDiscoveryDisabled,
-        ntdef::FALSE,
-        winerror::ERROR_MORE_DATA,
+        winerror::{ERROR_DRIVER_BLOCKED, ERROR_INVALID_IMAGE_HASH, ERROR_MORE_DATA},
         ws2def::{ADDRESS_FAMILY, AF_INET, AF_INET6},
-        ws2ipdef::SOCKADDR_INET,
     },
     um::libloaderapi::{
         FreeLibrary, GetProcAddress, LoadLibraryExW, LOAD_WITH_ALTERED_SEARCH_PATH,
     },
 };
+use zeroize::Zeroize;
 
 lazy_static! {
     static ref WG_NT_DLL: Mutex<Option<Arc<WgNtDll>>> = Mutex::new(None);
+    /// Why Windows refused to load the driver, if it has. Connection attempts do not try
+    /// WireGuardNT while this is set.
+    static ref DRIVER_BLOCKED_REASON: Mutex<Option<String>> = Mutex::new(None);
     static ref ADAPTER_TYPE: U16CString = U16CString::from_str("Mullvad").unwrap();
-    static ref ADAPTER_ALIAS: U16CString = U16CString::from_str("Mullvad").unwrap();
 }
 
 const ADAPTER_GUID: GUID = GUID {
@@ -58,8 +60,7 @@ type WireGuardCreateAdapterFn = unsafe extern "stdcall" fn(
     requested_guid: *const GUID,
 ) -> RawHandle;
 type WireGuardCloseAdapterFn = unsafe extern "stdcall" fn(adapter: RawHandle);
-type WireGuardGetAdapterLuidFn =
-    unsafe extern "stdcall" fn(adapter: RawHandle, luid: *mut NET_LUID);
+type WireGuardGetAdapterLuidFn = unsafe extern "stdcall" fn(adapter: RawHandle, luid: *mut Luid);
 type WireGuardSetConfigurationFn = unsafe extern "stdcall" fn(
     adapter: RawHandle,
     config: *const MaybeUninit<u8>,
@@ -116,6 +117,10 @@ pub enum Error {
     #[error(display = "Failed to load mullvad-wireguard.dll")]
     DllError(#[error(source)] io::Error),
 
+    /// The interface name contains a null character
+    #[error(display = "Invalid interface name")]
+    InvalidInterfaceName,
+
     /// Failed to create tunnel interface
     #[error(display = "Failed to create WireGuard device")]
     CreateTunnelDeviceError(#[error(source)] io::Error),
@@ -169,6 +174,37 @@ pub enum Error {
     InvalidConfigData,
 }
 
+/// Returns why Windows refused to load the driver, if it has since the daemon started or since
+/// [`reset_wireguard_nt_fallback`] was last called.
+pub(super) fn driver_blocked_reason() -> Option<String> {
+    DRIVER_BLOCKED_REASON.lock().unwrap().clone()
+}
+
+pub(super) fn set_driver_blocked_reason(reason: String) {
+    *DRIVER_BLOCKED_REASON.lock().unwrap() = Some(reason);
+}
+
+/// Forgets that the driver was blocked, so that the next connection attempt tries WireGuardNT
+/// again. Should be called when the user changes settings that affect the tunnel implementation.
+pub fn reset_wireguard_nt_fallback() {
+    DRIVER_BLOCKED_REASON.lock().unwrap().take();
+}
+
+impl Error {
+    /// Returns whether Windows refused to load the driver, for example because Memory integrity
+    /// (HVCI) is enabled or because the driver is on the vulnerable driver blocklist. Creating
+    /// the adapter again will fail the same way.
+    pub fn is_driver_blocked(&self) -> bool {
+        match self {
+            Error::CreateTunnelDeviceError(error) => matches!(
+                error.raw_os_error().map(|code| code as u32),
+                Some(ERROR_DRIVER_BLOCKED) | Some(ERROR_INVALID_IMAGE_HASH)
+            ),
+            _ => false,
+        }
+    }
+}
+
 pub struct WgNtTunnel {
     device: Arc<Mutex<Option<WgNtAdapter>>>,
     interface_name: String,
@@ -182,8 +218,8 @@ const WIREGUARD_KEY_LENGTH: usize = 32;
 #[derive(Clone, Copy)]
 #[repr(C, align(8))]
 union WgIpAddr {
-    v4: IN_ADDR,
-    v6: IN6_ADDR,
+    v4: [u8; 4],
+    v6: [u8; 16],
 }
 
 impl From<IpAddr> for WgIpAddr {
@@ -198,7 +234,7 @@ impl From<IpAddr> for WgIpAddr {
 impl From<Ipv6Addr> for WgIpAddr {
     fn from(address: Ipv6Addr) -> Self {
         Self {
-            v6: windows::in6addr_from_ipaddr(address),
+            v6: address.octets(),
         }
     }
 }
@@ -206,7 +242,7 @@ impl From<Ipv6Addr> for WgIpAddr {
 impl From<Ipv4Addr> for WgIpAddr {
     fn from(address: Ipv4Addr) -> Self {
         Self {
-            v4: windows::inaddr_from_ipaddr(address),
+            v4: address.octets(),
         }
     }
 }
@@ -237,7 +273,7 @@ impl WgAllowedIp {
                     return Err(Error::InvalidAllowedIpCidr);
                 }
                 let host_mask = u32::MAX.checked_shr(u32::from(cidr)).unwrap_or(0);
-                if host_mask & (unsafe { *(address.v4.S_un.S_addr()) }.to_be()) != 0 {
+                if host_mask & u32::from_be_bytes(unsafe { address.v4 }) != 0 {
                     return Err(Error::InvalidAllowedIpBits);
                 }
             }
@@ -246,7 +282,7 @@ impl WgAllowedIp {
                     return Err(Error::InvalidAllowedIpCidr);
                 }
                 let mut host_mask = u128::MAX.checked_shr(u32::from(cidr)).unwrap_or(0);
-                let bytes = unsafe { address.v6.u.Byte() };
+                let bytes = unsafe { address.v6 };
                 for byte in bytes.iter().rev() {
                     if byte & ((host_mask & 0xff) as u8) != 0 {
                         return Err(Error::InvalidAllowedIpBits);
@@ -266,14 +302,8 @@ impl PartialEq for WgAllowedIp {
             return false;
         }
         match self.address_family as i32 {
-            AF_INET => {
-                windows::ipaddr_from_inaddr(unsafe { self.address.v4 })
-                    == windows::ipaddr_from_inaddr(unsafe { other.address.v4 })
-            }
-            AF_INET6 => {
-                windows::ipaddr_from_in6addr(unsafe { self.address.v6 })
-                    == windows::ipaddr_from_in6addr(unsafe { other.address.v6 })
-            }
+            AF_INET => unsafe { self.address.v4 == other.address.v4 },
+            AF_INET6 => unsafe { self.address.v6 == other.address.v6 },
             _ => {
                 log::error!("Allowed IP uses unknown address family");
                 true
@@ -287,14 +317,8 @@ impl fmt::Debug for WgAllowedIp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut s = f.debug_struct("WgAllowedIp");
         match self.address_family as i32 {
-            AF_INET => s.field(
-                "address",
-                &windows::ipaddr_from_inaddr(unsafe { self.address.v4 }),
-            ),
-            AF_INET6 => s.field(
-                "address",
-                &windows::ipaddr_from_in6addr(unsafe { self.address.v6 }),
-            ),
+            AF_INET => s.field("address", &Ipv4Addr::from(unsafe { self.address.v4 })),
+            AF_INET6 => s.field("address", &Ipv6Addr::from(unsafe { self.address.v6 })),
             _ => s.field("address", &"<unknown>"),
         };
         s.field("address_family", &self.address_family)
@@ -317,7 +341,7 @@ bitflags! {
 }
 
 /// See `WIREGUARD_PEER` at https://git.zx2c4.com/wireguard-nt/tree/api/wireguard.h.
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 #[repr(C, align(8))]
 struct WgPeer {
     flags: WgPeerFlag,
@@ -325,61 +349,13 @@ struct WgPeer {
     public_key: [u8; WIREGUARD_KEY_LENGTH],
     preshared_key: [u8; WIREGUARD_KEY_LENGTH],
     persistent_keepalive: u16,
-    endpoint: SockAddrInet,
+    endpoint: InetSockAddr,
     tx_bytes: u64,
     rx_bytes: u64,
     last_handshake: u64,
     allowed_ips_count: u32,
 }
 
-#[derive(Clone, Copy)]
-#[repr(C)]
-struct SockAddrInet {
-    addr: SOCKADDR_INET,
-}
-
-impl From<SOCKADDR_INET> for SockAddrInet {
-    fn from(addr: SOCKADDR_INET) -> Self {
-        Self { addr }
-    }
-}
-impl PartialEq for SockAddrInet {
-    fn eq(&self, other: &Self) -> bool {
-        let self_addr = match windows::try_socketaddr_from_inet_sockaddr(self.addr) {
-            Ok(addr) => addr,
-            Err(error) => {
-                log::error!(
-                    "{}",
-                    error.display_chain_with_msg("Failed to convert socket address")
-                );
-                return true;
-            }
-        };
-        let other_addr = match windows::try_socketaddr_from_inet_sockaddr(other.addr) {
-            Ok(addr) => addr,
-            Err(error) => {
-                log::error!(
-                    "{}",
-                    error.display_chain_with_msg("Failed to convert socket address")
-                );
-                return true;
-            }
-        };
-        self_addr == other_addr
-    }
-}
-impl Eq for SockAddrInet {}
-
-impl fmt::Debug for SockAddrInet {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut s = f.debug_struct("SockAddrInet");
-        let self_addr = windows::try_socketaddr_from_inet_sockaddr(self.addr)
-            .map(|addr| addr.to_string())
-            .unwrap_or("<unknown>".to_string());
-        s.field("addr", &self_addr).finish()
-    }
-}
-
 bitflags! {
     /// See `WIREGUARD_INTERFACE_FLAG` at https://git.zx2c4.com/wireguard-nt/tree/api/wireguard.h.
     struct WgInterfaceFlag: u32 {
@@ -419,9 +395,11 @@ impl WgNtTunnel {
     ) -> Result<Self> {
         let dll = load_wg_nt_dll(resource_dir)?;
         let logger_handle = LoggerHandle::new(dll.clone(), log_path)?;
+        let adapter_alias = U16CString::from_str(&config.interface_name)
+            .map_err(|_| Error::InvalidInterfaceName)?;
         let device = WgNtAdapter::create(
             dll.clone(),
-            &*ADAPTER_ALIAS,
+            &adapter_alias,
             &*ADAPTER_TYPE,
             Some(ADAPTER_GUID.clone()),
         )
@@ -431,6 +409,13 @@ impl WgNtTunnel {
             .name()
             .map_err(Error::ObtainAliasError)?
             .to_string_lossy();
+        if interface_name != config.interface_name {
+            log::warn!(
+                "The interface name \"{}\" is used by another adapter. Using \"{}\" instead",
+                config.interface_name,
+                interface_name
+            );
+        }
 
         if let Err(error) = device.set_logging(WireGuardAdapterLogState::On) {
             log::error!(
@@ -444,7 +429,8 @@ impl WgNtTunnel {
         let setup_future = setup_ip_listener(
             device.clone(),
             u32::from(config.mtu),
-            config.tunnel.addresses.iter().any(|addr| addr.is_ipv6()),
+            config.has_ipv4(),
+            config.has_ipv6(),
         );
         let setup_handle = tokio::spawn(async move {
             let _ = done_tx
@@ -470,19 +456,24 @@ impl WgNtTunnel {
 async fn setup_ip_listener(
     device: Arc<Mutex<Option<WgNtAdapter>>>,
     mtu: u32,
+    has_ipv4: bool,
     has_ipv6: bool,
 ) -> Result<()> {
     let luid = { device.lock().unwrap().as_ref().unwrap().luid() };
 
     log::debug!("Waiting for tunnel IP interfaces to arrive");
-    windows::wait_for_interfaces(luid.clone(), true, has_ipv6)
+    talpid_windows_net::wait_for_interfaces(luid, has_ipv4, has_ipv6)
         .await
         .map_err(Error::IpInterfacesError)?;
     log::debug!("Waiting for tunnel IP interfaces: Done");
 
-    prepare_interface(&luid, AF_INET as u16, mtu).map_err(Error::SetTunnelIpv4MtuError)?;
+    if has_ipv4 {
+        prepare_tunnel_interface(luid, AddressFamily::Ipv4, Some(mtu))
+            .map_err(Error::SetTunnelIpv4MtuError)?;
+    }
     if has_ipv6 {
-        prepare_interface(&luid, AF_INET6 as u16, mtu).map_err(Error::SetTunnelIpv6MtuError)?;
+        prepare_tunnel_interface(luid, AddressFamily::Ipv6, Some(mtu))
+            .map_err(Error::SetTunnelIpv6MtuError)?;
     }
 
     if let Some(device) = &*device.lock().unwrap() {
@@ -572,34 +563,33 @@ impl WgNtAdapter {
     }
 
     fn name(&self) -> io::Result<U16CString> {
-        windows::alias_from_luid(&self.luid()).and_then(|alias| {
+        talpid_windows_net::alias_from_luid(self.luid()).and_then(|alias| {
             U16CString::from_os_str(alias)
                 .map_err(|_| io::Error::new(io::ErrorKind::Other, "unexpected null char"))
         })
     }
 
-    fn luid(&self) -> NET_LUID {
+    fn luid(&self) -> Luid {
         unsafe { self.dll_handle.get_adapter_luid(self.handle) }
     }
 
     fn set_config(&self, config: &Config) -> Result<()> {
-        let config_buffer = serialize_config(config)?;
-        unsafe {
+        let mut config_buffer = serialize_config(config)?;
+        let result = unsafe {
             self.dll_handle
                 .set_config(self.handle, config_buffer.as_ptr(), config_buffer.len())
                 .map_err(Error::SetWireGuardConfigError)
-        }
+        };
+        zeroize_buffer(&mut config_buffer);
+        result
     }
 
     fn get_config(&self) -> Result<(WgInterface, Vec<(WgPeer, Vec<WgAllowedIp>)>)> {
-        unsafe {
-            deserialize_config(
-                &self
-                    .dll_handle
-                    .get_config(self.handle)
-                    .map_err(Error::GetWireGuardConfigError)?,
-            )
-        }
+        let mut config_buffer = unsafe { self.dll_handle.get_config(self.handle) }
+            .map_err(Error::GetWireGuardConfigError)?;
+        let result = unsafe { deserialize_config(&config_buffer) };
+        zeroize_buffer(&mut config_buffer);
+        result
     }
 
     fn set_state(&self, state: WgAdapterState) -> io::Result<()> {
@@ -736,8 +726,8 @@ impl WgNtDll {
         (self.func_close)(adapter);
     }
 
-    pub unsafe fn get_adapter_luid(&self, adapter: RawHandle) -> NET_LUID {
-        let mut luid = mem::MaybeUninit::<NET_LUID>::zeroed();
+    pub unsafe fn get_adapter_luid(&self, adapter: RawHandle) -> Luid {
+        let mut luid = mem::MaybeUninit::<Luid>::zeroed();
         (self.func_get_adapter_luid)(adapter, luid.as_mut_ptr());
         luid.assume_init()
     }
@@ -807,6 +797,12 @@ impl Drop for WgNtDll {
     }
 }
 
+/// Forgets the shared WireGuardNT DLL, so that it is loaded again by the next tunnel. The library
+/// is unloaded once every tunnel using it has been closed.
+pub fn unload_wg_nt_dll() {
+    *(*WG_NT_DLL).lock().expect("WireGuardNT mutex poisoned") = None;
+}
+
 fn load_wg_nt_dll(resource_dir: &Path) -> Result<Arc<WgNtDll>> {
     let mut dll = (*WG_NT_DLL).lock().expect("WireGuardNT mutex poisoned");
     match &*dll {
@@ -820,9 +816,19 @@ fn load_wg_nt_dll(resource_dir: &Path) -> Result<Arc<WgNtDll>> {
 }
 
 fn serialize_config(config: &Config) -> Result<Vec<MaybeUninit<u8>>> {
-    let mut buffer = vec![];
-
-    let header = WgInterface {
+    // Allocate the whole buffer up front, so that no copies of the private key are left behind
+    // when it grows.
+    let size = mem::size_of::<WgInterface>()
+        + config
+            .peers
+            .iter()
+            .map(|peer| {
+                mem::size_of::<WgPeer>() + peer.allowed_ips.len() * mem::size_of::<WgAllowedIp>()
+            })
+            .sum::<usize>();
+    let mut buffer = Vec::with_capacity(size);
+
+    let mut header = WgInterface {
         flags: WgInterfaceFlag::HAS_PRIVATE_KEY | WgInterfaceFlag::REPLACE_PEERS,
         listen_port: 0,
         private_key: config.tunnel.private_key.to_bytes(),
@@ -831,6 +837,7 @@ fn serialize_config(config: &Config) -> Result<Vec<MaybeUninit<u8>>> {
     };
 
     buffer.extend(windows::as_uninit_byte_slice(&header));
+    header.private_key.zeroize();
 
     for peer in &config.peers {
         let wg_peer = WgPeer {
@@ -839,7 +846,7 @@ fn serialize_config(config: &Config) -> Result<Vec<MaybeUninit<u8>>> {
             public_key: peer.public_key.as_bytes().clone(),
             preshared_key: [0u8; WIREGUARD_KEY_LENGTH],
             persistent_keepalive: 0,
-            endpoint: windows::inet_sockaddr_from_socketaddr(peer.endpoint).into(),
+            endpoint: InetSockAddr::from(peer.endpoint),
             tx_bytes: 0,
             rx_bytes: 0,
             last_handshake: 0,
@@ -868,6 +875,15 @@ fn serialize_config(config: &Config) -> Result<Vec<MaybeUninit<u8>>> {
     Ok(buffer)
 }
 
+/// Overwrites a config buffer, since it contains the private key.
+fn zeroize_buffer(buffer: &mut [MaybeUninit<u8>]) {
+    for byte in buffer.iter_mut() {
+        // SAFETY: `byte` is a valid reference, and any value can be written to `MaybeUninit`.
+        unsafe { ptr::write_volatile(byte, MaybeUninit::new(0)) };
+    }
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
 unsafe fn deserialize_config(
     config: &[MaybeUninit<u8>],
 ) -> Result<(WgInterface, Vec<(WgPeer, Vec<WgAllowedIp>)>)> {
@@ -886,7 +902,7 @@ unsafe fn deserialize_config(
         let peer: WgPeer = *(peer_data.as_ptr() as *const WgPeer);
         tail = new_tail;
 
-        if let Err(error) = windows::try_socketaddr_from_inet_sockaddr(peer.endpoint.addr) {
+        if let Err(error) = peer.endpoint.to_socket_addr() {
             log::error!(
                 "{}",
                 error.display_chain_with_msg("Received invalid endpoint address")
@@ -927,17 +943,17 @@ unsafe fn deserialize_config(
     Ok((interface, peers))
 }
 
-fn prepare_interface(luid: &NET_LUID, family: u16, mtu: u32) -> io::Result<()> {
-    let family = windows::AddressFamily::try_from_af_family(family)
-        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
-    let mut iface = windows::get_ip_interface_entry(family, luid)?;
-    iface.SitePrefixLength = 0;
-    iface.NlMtu = mtu;
-    iface.RouterDiscoveryBehavior = RouterDiscoveryDisabled;
-    iface.DadTransmits = 0;
-    iface.ManagedAddressConfigurationSupported = FALSE;
-    iface.OtherStatefulConfigurationSupported = FALSE;
-    windows::set_ip_interface_entry(&iface)
+/// Number of 100 ns intervals between 1601-01-01 and the Unix epoch.
+const FILETIME_UNIX_EPOCH_OFFSET: u64 = 116_444_736_000_000_000;
+
+/// Converts a handshake timestamp in 100 ns intervals since 1601-01-01. Zero means that no
+/// handshake has been completed.
+fn filetime_to_system_time(filetime: u64) -> Option<SystemTime> {
+    if filetime == 0 {
+        return None;
+    }
+    let since_epoch = filetime.checked_sub(FILETIME_UNIX_EPOCH_OFFSET)?;
+    UNIX_EPOCH.checked_add(Duration::from_nanos(since_epoch.saturating_mul(100)))
 }
 
 impl Tunnel for WgNtTunnel {
@@ -945,22 +961,28 @@ impl Tunnel for WgNtTunnel {
         self.interface_name.clone()
     }
 
+    fn backend_name(&self) -> &'static str {
+        "wireguard-nt"
+    }
+
     fn get_tunnel_stats(&self) -> std::result::Result<StatsMap, super::TunnelError> {
         if let Some(ref device) = &*self.device.lock().unwrap() {
             let mut map = StatsMap::new();
-            let (_interface, peers) = device.get_config().map_err(|error| {
+            let (mut interface, peers) = device.get_config().map_err(|error| {
                 log::error!(
                     "{}",
                     error.display_chain_with_msg("Failed to obtain wg-nt tunnel config")
                 );
                 super::TunnelError::StatsError(super::stats::Error::NoTunnelConfig)
             })?;
+            interface.private_key.zeroize();
             for (peer, _allowed_ips) in &peers {
                 map.insert(
                     peer.public_key,
                     Stats {
                         tx_bytes: peer.tx_bytes,
                         rx_bytes: peer.rx_bytes,
+                        last_handshake: filetime_to_system_time(peer.last_handshake),
                     },
                 );
             }
@@ -982,9 +1004,10 @@ impl Tunnel for WgNtTunnel {
 mod tests {
     use super::*;
     use lazy_static::lazy_static;
+    use std::net::SocketAddr;
     use talpid_types::net::{wireguard, TransportProtocol};
 
-    #[derive(Debug, Eq, PartialEq, Clone, Copy)]
+    #[derive(Debug, PartialEq, Clone, Copy)]
     #[repr(C)]
     struct Interface {
         interface: WgInterface,
@@ -1011,7 +1034,9 @@ mod tests {
                 ipv4_gateway: "0.0.0.0".parse().unwrap(),
                 ipv6_gateway: None,
                 mtu: 0,
+                entry_proxy: None,
                 use_wireguard_nt: true,
+                interface_name: "Mullvad".to_owned(),
             }
         };
         static ref WG_STRUCT_CONFIG: Interface = Interface {
@@ -1028,8 +1053,7 @@ mod tests {
                 public_key: WG_PUBLIC_KEY.as_bytes().clone(),
                 preshared_key: [0; WIREGUARD_KEY_LENGTH],
                 persistent_keepalive: 0,
-                endpoint: windows::inet_sockaddr_from_socketaddr("1.2.3.4:1234".parse().unwrap())
-                    .into(),
+                endpoint: InetSockAddr::from("1.2.3.4:1234".parse::<SocketAddr>().unwrap()),
                 tx_bytes: 0,
                 rx_bytes: 0,
                 last_handshake: 0,
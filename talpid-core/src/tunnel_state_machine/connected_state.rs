@@ -1,21 +1,28 @@
 use super::{
-    AfterDisconnect, ConnectingState, DisconnectingState, ErrorState, EventConsequence,
-    EventResult, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver, TunnelState,
-    TunnelStateTransition, TunnelStateWrapper,
+    transition_history, AfterDisconnect, ConnectingState, DisconnectingState, ErrorState,
+    EventConsequence, EventResult, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver,
+    TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
+#[cfg(target_os = "linux")]
+use crate::routing::RouteManagerHandle;
 use crate::{
-    firewall::FirewallPolicy,
-    tunnel::{CloseHandle, TunnelEvent, TunnelMetadata},
+    firewall::{FirewallPolicy, PolicyVerifier},
+    tunnel::{wireguard::transport_plugin, CloseHandle, DebugHandle, TunnelEvent, TunnelMetadata},
 };
 use cfg_if::cfg_if;
 use futures::{
     channel::{mpsc, oneshot},
+    future,
     stream::Fuse,
-    StreamExt,
+    FutureExt, StreamExt,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+    time::Duration,
 };
-use std::net::IpAddr;
 use talpid_types::{
-    net::TunnelParameters,
+    net::{TunnelEndpoint, TunnelParameters},
     tunnel::{ErrorStateCause, FirewallPolicyError},
     BoxedError, ErrorExt,
 };
@@ -25,6 +32,73 @@ use crate::tunnel::TunnelMonitor;
 
 use super::connecting_state::TunnelCloseEvent;
 
+/// How long to keep reading back the firewall rules and routes in strict kill switch mode before
+/// giving up.
+const KILL_SWITCH_VERIFICATION_TIMEOUT: Duration = Duration::from_secs(5);
+/// Delay between attempts to verify the firewall rules and routes.
+const KILL_SWITCH_VERIFICATION_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Destinations that are looked up to check that traffic to the internet is routed through the
+/// tunnel. The routes through the tunnel may cover each half of the address space separately, so
+/// one address in each half is checked.
+const INTERNET_ROUTE_PROBES_V4: [Ipv4Addr; 2] =
+    [Ipv4Addr::new(1, 0, 0, 0), Ipv4Addr::new(128, 0, 0, 0)];
+const INTERNET_ROUTE_PROBES_V6: [Ipv6Addr; 2] = [
+    Ipv6Addr::new(0x2000, 0, 0, 0, 0, 0, 0, 0),
+    Ipv6Addr::new(0x8000, 0, 0, 0, 0, 0, 0, 0),
+];
+
+/// Public Mullvad DNS resolvers and the names that their certificates are issued for. Other
+/// resolvers, such as the ones inside the tunnel, are not verified against a name.
+#[cfg(target_os = "linux")]
+const PUBLIC_RESOLVER_TLS_NAMES: [(IpAddr, &str); 12] = [
+    (IpAddr::V4(Ipv4Addr::new(194, 242, 2, 2)), "dns.mullvad.net"),
+    (
+        IpAddr::V4(Ipv4Addr::new(194, 242, 2, 3)),
+        "adblock.dns.mullvad.net",
+    ),
+    (
+        IpAddr::V4(Ipv4Addr::new(194, 242, 2, 4)),
+        "base.dns.mullvad.net",
+    ),
+    (
+        IpAddr::V4(Ipv4Addr::new(194, 242, 2, 5)),
+        "extended.dns.mullvad.net",
+    ),
+    (
+        IpAddr::V4(Ipv4Addr::new(194, 242, 2, 6)),
+        "family.dns.mullvad.net",
+    ),
+    (
+        IpAddr::V4(Ipv4Addr::new(194, 242, 2, 9)),
+        "all.dns.mullvad.net",
+    ),
+    (
+        IpAddr::V6(Ipv6Addr::new(0x2a07, 0xe340, 0, 0, 0, 0, 0, 2)),
+        "dns.mullvad.net",
+    ),
+    (
+        IpAddr::V6(Ipv6Addr::new(0x2a07, 0xe340, 0, 0, 0, 0, 0, 3)),
+        "adblock.dns.mullvad.net",
+    ),
+    (
+        IpAddr::V6(Ipv6Addr::new(0x2a07, 0xe340, 0, 0, 0, 0, 0, 4)),
+        "base.dns.mullvad.net",
+    ),
+    (
+        IpAddr::V6(Ipv6Addr::new(0x2a07, 0xe340, 0, 0, 0, 0, 0, 5)),
+        "extended.dns.mullvad.net",
+    ),
+    (
+        IpAddr::V6(Ipv6Addr::new(0x2a07, 0xe340, 0, 0, 0, 0, 0, 6)),
+        "family.dns.mullvad.net",
+    ),
+    (
+        IpAddr::V6(Ipv6Addr::new(0x2a07, 0xe340, 0, 0, 0, 0, 0, 9)),
+        "all.dns.mullvad.net",
+    ),
+];
+
 pub(crate) type TunnelEventsReceiver =
     Fuse<mpsc::UnboundedReceiver<(TunnelEvent, oneshot::Sender<()>)>>;
 
@@ -34,6 +108,7 @@ pub struct ConnectedStateBootstrap {
     pub tunnel_parameters: TunnelParameters,
     pub tunnel_close_event: TunnelCloseEvent,
     pub close_handle: Option<CloseHandle>,
+    pub debug_handle: Option<DebugHandle>,
 }
 
 /// The tunnel is up and working.
@@ -43,6 +118,15 @@ pub struct ConnectedState {
     tunnel_parameters: TunnelParameters,
     tunnel_close_event: TunnelCloseEvent,
     close_handle: Option<CloseHandle>,
+    debug_handle: Option<DebugHandle>,
+    /// Completes with whether the firewall rules and routes could be verified, in strict kill
+    /// switch mode.
+    kill_switch_verification: future::Fuse<oneshot::Receiver<bool>>,
+    /// The policy that is being verified.
+    kill_switch_policy: Option<FirewallPolicy>,
+    /// Keeps the tunnel network public while connected.
+    #[cfg(windows)]
+    network_category_pin: Option<crate::winnet::PublicNetworkCategoryPin>,
 }
 
 impl ConnectedState {
@@ -53,6 +137,11 @@ impl ConnectedState {
             tunnel_parameters: bootstrap.tunnel_parameters,
             tunnel_close_event: bootstrap.tunnel_close_event,
             close_handle: bootstrap.close_handle,
+            debug_handle: bootstrap.debug_handle,
+            kill_switch_verification: future::Fuse::terminated(),
+            kill_switch_policy: None,
+            #[cfg(windows)]
+            network_category_pin: None,
         }
     }
 
@@ -87,36 +176,51 @@ impl ConnectedState {
         if let Some(ref servers) = shared_values.dns_servers {
             servers.clone()
         } else {
-            let mut dns_ips = vec![];
-            dns_ips.push(self.metadata.ipv4_gateway.into());
-            if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
-                dns_ips.push(ipv6_gateway.into());
-            };
-            dns_ips
+            self.get_gateway_dns_servers()
         }
         #[cfg(target_os = "android")]
         {
-            let mut dns_ips = vec![];
-            dns_ips.push(self.metadata.ipv4_gateway.into());
-            if let Some(ipv6_gateway) = self.metadata.ipv6_gateway {
-                dns_ips.push(ipv6_gateway.into());
-            };
-            dns_ips
+            self.get_gateway_dns_servers()
         }
     }
 
+    /// Returns the tunnel gateways, which are used as DNS servers unless custom DNS is set.
+    fn get_gateway_dns_servers(&self) -> Vec<IpAddr> {
+        self.metadata.gateways()
+    }
+
+    /// Returns the DNS servers that may be reached using plaintext DNS. When DNS-over-TLS is
+    /// used, no plaintext DNS is allowed to leave the host.
+    #[cfg(not(target_os = "android"))]
+    fn get_plaintext_dns_servers(&self, shared_values: &SharedTunnelStateValues) -> Vec<IpAddr> {
+        #[cfg(target_os = "linux")]
+        if shared_values.dns_over_tls {
+            return vec![];
+        }
+        self.get_dns_servers(shared_values)
+    }
+
     fn get_firewall_policy(&self, shared_values: &SharedTunnelStateValues) -> FirewallPolicy {
         FirewallPolicy::Connected {
             peer_endpoint: self.tunnel_parameters.get_next_hop_endpoint(),
             tunnel: self.metadata.clone(),
             allow_lan: shared_values.allow_lan,
+            lan_allow_list: shared_values.lan_allow_list.clone(),
             #[cfg(not(target_os = "android"))]
-            dns_servers: self.get_dns_servers(shared_values),
+            dns_servers: self.get_plaintext_dns_servers(shared_values),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            excluded_networks: shared_values.excluded_networks.clone(),
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(
                 &shared_values.resource_dir,
                 &self.tunnel_parameters,
             ),
+            #[cfg(windows)]
+            shared_network: if shared_values.allow_connection_sharing {
+                Some(crate::windows::connection_sharing::shared_network())
+            } else {
+                None
+            },
         }
     }
 
@@ -127,12 +231,22 @@ impl ConnectedState {
         let dns_ips = &dns_ips
             .into_iter()
             .filter(|ip| {
-                !crate::firewall::is_local_address(ip)
-                    || IpAddr::V4(self.metadata.ipv4_gateway) == *ip
-                    || self.metadata.ipv6_gateway.map(IpAddr::V6) == Some(*ip)
+                !crate::firewall::is_local_address(ip) || self.metadata.gateways().contains(ip)
             })
             .collect::<Vec<_>>();
 
+        #[cfg(target_os = "linux")]
+        if shared_values.dns_over_tls {
+            let servers = dns_ips
+                .iter()
+                .map(|ip| (*ip, resolver_tls_name(ip).map(str::to_owned)))
+                .collect::<Vec<_>>();
+            return shared_values
+                .dns_monitor
+                .set_dns_over_tls(&self.metadata.interface, &servers)
+                .map_err(BoxedError::new);
+        }
+
         shared_values
             .dns_monitor
             .set(&self.metadata.interface, &dns_ips)
@@ -141,6 +255,142 @@ impl ConnectedState {
         Ok(())
     }
 
+    /// Starts reading back the firewall rules and routes for the current policy in the
+    /// background. The result is handled in `handle_event`.
+    fn start_kill_switch_verification(&mut self, shared_values: &mut SharedTunnelStateValues) {
+        let policy = self.get_firewall_policy(shared_values);
+        let (result_tx, result_rx) = oneshot::channel();
+        self.kill_switch_verification = result_rx.fuse();
+        self.kill_switch_policy = Some(policy.clone());
+
+        // Dropping `result_tx` fails the verification
+        let verifier = match shared_values.firewall.policy_verifier(&policy) {
+            Ok(verifier) => Arc::new(verifier),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to read back firewall rules")
+                );
+                return;
+            }
+        };
+        #[cfg(target_os = "linux")]
+        let route_handle = match shared_values.route_manager.handle() {
+            Ok(handle) => handle,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to get route manager handle")
+                );
+                return;
+            }
+        };
+
+        let mut route_probes = vec![];
+        if self.metadata.ips.iter().any(IpAddr::is_ipv4) {
+            route_probes.extend(INTERNET_ROUTE_PROBES_V4.iter().copied().map(IpAddr::V4));
+        }
+        if self.metadata.ips.iter().any(IpAddr::is_ipv6) {
+            route_probes.extend(INTERNET_ROUTE_PROBES_V6.iter().copied().map(IpAddr::V6));
+        }
+
+        shared_values.runtime.spawn(verify_kill_switch(
+            verifier,
+            KillSwitchRoutes {
+                #[cfg(target_os = "linux")]
+                route_handle,
+                tunnel_interface: self.metadata.interface.clone(),
+                probes: route_probes,
+            },
+            result_tx,
+        ));
+    }
+
+    fn handle_kill_switch_verification(
+        mut self,
+        verified: bool,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence {
+        if verified {
+            log::debug!("Verified that the firewall rules and routes are in place");
+        } else if !shared_values.strict_kill_switch {
+            log::debug!("Strict kill switch mode was disabled while verifying the firewall rules");
+        } else if self.kill_switch_policy.as_ref() != Some(&self.get_firewall_policy(shared_values))
+        {
+            log::debug!("The firewall policy changed while it was being verified. Verifying again");
+            self.start_kill_switch_verification(shared_values);
+            return EventConsequence::SameState(self.into());
+        } else {
+            log::error!("Failed to verify that the firewall rules and routes are in place");
+            return self.disconnect(
+                shared_values,
+                AfterDisconnect::Block(ErrorStateCause::KillSwitchVerificationFailed),
+            );
+        }
+        self.kill_switch_policy = None;
+        EventConsequence::NewState(self.report_connected(shared_values))
+    }
+
+    fn tunnel_endpoint(&self) -> TunnelEndpoint {
+        let mut tunnel_endpoint = transport_plugin::get_tunnel_endpoint(&self.tunnel_parameters);
+        tunnel_endpoint.tunnel_interface = Some(self.metadata.interface.clone());
+        tunnel_endpoint
+    }
+
+    fn report_connected(
+        #[cfg_attr(not(windows), allow(unused_mut))] mut self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> (TunnelStateWrapper, TunnelStateTransition) {
+        #[cfg(windows)]
+        if self.network_category_pin.is_none() {
+            self.network_category_pin =
+                crate::winnet::pin_public_network_category(&self.metadata.interface)
+                    .map_err(|error| {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg(
+                                "Failed to set the category of the tunnel network"
+                            )
+                        )
+                    })
+                    .ok();
+        }
+        shared_values.connecting_watchdog.connected();
+        let tunnel_endpoint = self.tunnel_endpoint();
+        (
+            TunnelStateWrapper::from(self),
+            TunnelStateTransition::Connected(tunnel_endpoint),
+        )
+    }
+
+    /// Changes the MTU of the tunnel interface without reconnecting. Returns whether the MTU was
+    /// changed, which is only possible for WireGuard tunnels.
+    #[cfg_attr(target_os = "android", allow(unused_variables))]
+    fn set_wireguard_mtu(&self, mtu: Option<u16>) -> bool {
+        if !matches!(self.tunnel_parameters, TunnelParameters::Wireguard(_)) {
+            return false;
+        }
+
+        #[cfg(not(target_os = "android"))]
+        {
+            match crate::tunnel::wireguard::set_interface_mtu(&self.metadata.interface, mtu) {
+                Ok(()) => {
+                    log::debug!("Changed the MTU of {}", self.metadata.interface);
+                    true
+                }
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to change the tunnel interface MTU")
+                    );
+                    false
+                }
+            }
+        }
+        #[cfg(target_os = "android")]
+        false
+    }
+
     fn reset_dns(shared_values: &mut SharedTunnelStateValues) {
         if let Err(error) = shared_values.dns_monitor.reset() {
             log::error!("{}", error.display_chain_with_msg("Unable to reset DNS"));
@@ -168,6 +418,7 @@ impl ConnectedState {
         shared_values: &mut SharedTunnelStateValues,
         after_disconnect: AfterDisconnect,
     ) -> EventConsequence {
+        shared_values.end_traffic_session(self.debug_handle.as_ref());
         Self::reset_dns(shared_values);
         Self::reset_routes(shared_values);
 
@@ -185,6 +436,22 @@ impl ConnectedState {
         use self::EventConsequence::*;
 
         match command {
+            Some(TunnelCommand::SetBlockedStatePolicy(blocked_state_policy)) => {
+                let _ = shared_values.set_blocked_state_policy(blocked_state_policy);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::SetLanAllowList(lan_allow_list)) => {
+                if !shared_values.set_lan_allow_list(lan_allow_list) {
+                    return SameState(self.into());
+                }
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
             Some(TunnelCommand::AllowLan(allow_lan)) => {
                 if let Err(error_cause) = shared_values.set_allow_lan(allow_lan) {
                     self.disconnect(shared_values, AfterDisconnect::Block(error_cause))
@@ -241,8 +508,90 @@ impl ConnectedState {
                     self.disconnect(shared_values, AfterDisconnect::Block(error_cause))
                 }
             },
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::DnsOverTls(dns_over_tls)) => {
+                if shared_values.dns_over_tls == dns_over_tls {
+                    return SameState(self.into());
+                }
+                shared_values.dns_over_tls = dns_over_tls;
+
+                if let Err(error) = self.set_firewall_policy(shared_values) {
+                    return self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    );
+                }
+
+                match self.set_dns(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => {
+                        log::error!("{}", error.display_chain_with_msg("Failed to set DNS"));
+                        self.disconnect(
+                            shared_values,
+                            AfterDisconnect::Block(ErrorStateCause::SetDnsError),
+                        )
+                    }
+                }
+            }
+            #[cfg(windows)]
+            Some(TunnelCommand::AllowConnectionSharing(allow_connection_sharing)) => {
+                if shared_values.allow_connection_sharing == allow_connection_sharing {
+                    return SameState(self.into());
+                }
+                shared_values.allow_connection_sharing = allow_connection_sharing;
+
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
-                shared_values.block_when_disconnected = block_when_disconnected;
+                shared_values.set_block_when_disconnected(block_when_disconnected);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::DescribeFirewallPolicy(tx)) => {
+                let _ = tx.send(shared_values.firewall.describe_policy());
+                SameState(self.into())
+            }
+            Some(TunnelCommand::VerifyFirewallPolicy(tx)) => {
+                let _ = tx.send(shared_values.restore_firewall_policy());
+                SameState(self.into())
+            }
+            Some(TunnelCommand::CheckFirewallPolicy(tx)) => {
+                let _ = tx.send(shared_values.check_firewall_policy());
+                SameState(self.into())
+            }
+            #[cfg(target_os = "macos")]
+            Some(TunnelCommand::SetFirewallException(scope, endpoints, tx)) => {
+                let _ = tx.send(shared_values.set_firewall_exception(scope, endpoints));
+                SameState(self.into())
+            }
+            #[cfg(target_os = "macos")]
+            Some(TunnelCommand::GetSystemDnsServers(tx)) => {
+                let _ = tx.send(shared_values.system_dns_servers());
+                SameState(self.into())
+            }
+            Some(TunnelCommand::SetWireguardMtu(mtu, tx)) => {
+                let _ = tx.send(self.set_wireguard_mtu(mtu));
+                SameState(self.into())
+            }
+            Some(TunnelCommand::DescribeTunnel(tx)) => {
+                let _ = tx.send(shared_values.describe_tunnel(self.debug_handle.as_ref()));
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetTunnelStatistics(tx)) => {
+                let _ = tx.send(shared_values.tunnel_statistics(self.debug_handle.as_ref()));
+                SameState(self.into())
+            }
+            Some(TunnelCommand::DescribeStateMachine(tx)) => {
+                let _ = tx.send(shared_values.describe_state_machine(None));
+                SameState(self.into())
+            }
+            Some(TunnelCommand::StrictKillSwitch(strict_kill_switch)) => {
+                shared_values.strict_kill_switch = strict_kill_switch;
                 SameState(self.into())
             }
             Some(TunnelCommand::IsOffline(is_offline)) => {
@@ -275,6 +624,28 @@ impl ConnectedState {
                 shared_values.split_tunnel.set_paths(&paths, result_tx);
                 SameState(self.into())
             }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::SetExcludedNetworks(networks)) => {
+                if !shared_values.set_excluded_networks(networks) {
+                    return SameState(self.into());
+                }
+                cfg_if! {
+                    if #[cfg(target_os = "macos")] {
+                        // Routes cannot be removed individually, so start over
+                        self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                    } else {
+                        match self.set_firewall_policy(shared_values) {
+                            Ok(()) => SameState(self.into()),
+                            Err(error) => self.disconnect(
+                                shared_values,
+                                AfterDisconnect::Block(
+                                    ErrorStateCause::SetFirewallPolicyError(error),
+                                ),
+                            ),
+                        }
+                    }
+                }
+            }
         }
     }
 
@@ -289,6 +660,10 @@ impl ConnectedState {
             Some((TunnelEvent::Down, _)) | None => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
+            Some((TunnelEvent::WireguardEvent(event), _)) => {
+                log::trace!("WireGuard event while connected: {}", event);
+                SameState(self.into())
+            }
             Some(_) => SameState(self.into()),
         }
     }
@@ -300,6 +675,8 @@ impl ConnectedState {
     ) -> EventConsequence {
         use self::EventConsequence::*;
 
+        shared_values.end_traffic_session(self.debug_handle.as_ref());
+
         if let Some(block_reason) = block_reason {
             Self::reset_dns(shared_values);
             Self::reset_routes(shared_values);
@@ -321,8 +698,7 @@ impl TunnelState for ConnectedState {
         shared_values: &mut SharedTunnelStateValues,
         bootstrap: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
-        let connected_state = ConnectedState::from(bootstrap);
-        let tunnel_endpoint = connected_state.tunnel_parameters.get_tunnel_endpoint();
+        let mut connected_state = ConnectedState::from(bootstrap);
 
         if let Err(error) = connected_state.set_firewall_policy(shared_values) {
             DisconnectingState::enter(
@@ -343,11 +719,16 @@ impl TunnelState for ConnectedState {
                     AfterDisconnect::Block(ErrorStateCause::SetDnsError),
                 ),
             )
-        } else {
+        } else if shared_values.strict_kill_switch {
+            // The tunnel is reported as connecting until the rules and routes are verified
+            connected_state.start_kill_switch_verification(shared_values);
+            let tunnel_endpoint = connected_state.tunnel_endpoint();
             (
                 TunnelStateWrapper::from(connected_state),
-                TunnelStateTransition::Connected(tunnel_endpoint),
+                TunnelStateTransition::Connecting(tunnel_endpoint),
             )
+        } else {
+            connected_state.report_connected(shared_values)
         }
     }
 
@@ -359,11 +740,24 @@ impl TunnelState for ConnectedState {
     ) -> EventConsequence {
         let result = runtime.block_on(async {
             futures::select! {
-                command = commands.next() => EventResult::Command(command),
-                event = self.tunnel_events.next() => EventResult::Event(event),
-                result = &mut self.tunnel_close_event => EventResult::Close(result),
+                command = commands.next() => Ok(EventResult::Command(command)),
+                event = self.tunnel_events.next() => Ok(EventResult::Event(event)),
+                result = &mut self.tunnel_close_event => Ok(EventResult::Close(result)),
+                verified = &mut self.kill_switch_verification => Err(verified.unwrap_or(false)),
             }
         });
+        let result = match result {
+            Ok(result) => result,
+            Err(verified) => {
+                shared_values
+                    .transition_history
+                    .set_trigger("kill switch verification completed".to_owned());
+                return self.handle_kill_switch_verification(verified, shared_values);
+            }
+        };
+        shared_values
+            .transition_history
+            .set_trigger(transition_history::describe_event(&result));
 
         match result {
             EventResult::Command(command) => self.handle_commands(command, shared_values),
@@ -378,3 +772,130 @@ impl TunnelState for ConnectedState {
         }
     }
 }
+
+/// Returns the name that the certificate of a DNS resolver is issued for, if it is a public
+/// Mullvad resolver.
+#[cfg(target_os = "linux")]
+fn resolver_tls_name(resolver: &IpAddr) -> Option<&'static str> {
+    PUBLIC_RESOLVER_TLS_NAMES
+        .iter()
+        .find(|(address, _)| address == resolver)
+        .map(|(_, name)| *name)
+}
+
+/// What is needed to check the routes to the internet in strict kill switch mode.
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos")), allow(dead_code))]
+struct KillSwitchRoutes {
+    #[cfg(target_os = "linux")]
+    route_handle: RouteManagerHandle,
+    tunnel_interface: String,
+    probes: Vec<IpAddr>,
+}
+
+/// Repeatedly reads back the firewall rules and routes until they are confirmed to be in place,
+/// or until `KILL_SWITCH_VERIFICATION_TIMEOUT` has elapsed, and sends the outcome on `result_tx`.
+/// Runs outside of the state machine thread, which keeps handling events in the meantime.
+async fn verify_kill_switch(
+    verifier: Arc<PolicyVerifier>,
+    routes: KillSwitchRoutes,
+    result_tx: oneshot::Sender<bool>,
+) {
+    let deadline = tokio::time::Instant::now() + KILL_SWITCH_VERIFICATION_TIMEOUT;
+    loop {
+        let policy_verifier = verifier.clone();
+        let rules_in_place =
+            match tokio::task::spawn_blocking(move || policy_verifier.verify()).await {
+                Ok(Ok(in_place)) => in_place,
+                Ok(Err(error)) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read back firewall rules")
+                    );
+                    false
+                }
+                Err(_) => false,
+            };
+        let verified = rules_in_place && routes.verify().await;
+
+        if verified || result_tx.is_canceled() || tokio::time::Instant::now() >= deadline {
+            let _ = result_tx.send(verified);
+            return;
+        }
+        tokio::time::sleep(KILL_SWITCH_VERIFICATION_INTERVAL).await;
+    }
+}
+
+impl KillSwitchRoutes {
+    /// Returns whether traffic to every probe address is routed through the tunnel interface.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn verify(&self) -> bool {
+        for probe in &self.probes {
+            #[cfg(target_os = "linux")]
+            let node = self
+                .route_handle
+                .get_destination_route(*probe, false)
+                .await
+                .map(|route| route.map(|route| route.get_node().clone()));
+            #[cfg(target_os = "macos")]
+            let node = crate::routing::get_destination_node(*probe).await;
+
+            match node {
+                Ok(node) => {
+                    let interface = node.as_ref().and_then(|node| node.get_device());
+                    if interface != Some(self.tunnel_interface.as_str()) {
+                        log::warn!(
+                            "Traffic to {} is routed through {}, not the tunnel interface",
+                            probe,
+                            interface.unwrap_or("no interface")
+                        );
+                        return false;
+                    }
+                }
+                Err(error) => {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to look up route")
+                    );
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// The routes are managed by the tunnel itself on this platform.
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    async fn verify(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolver_tls_name() {
+        assert_eq!(
+            resolver_tls_name(&IpAddr::V4(Ipv4Addr::new(194, 242, 2, 2))),
+            Some("dns.mullvad.net")
+        );
+        assert_eq!(
+            resolver_tls_name(&"2a07:e340::3".parse().unwrap()),
+            Some("adblock.dns.mullvad.net")
+        );
+        // In-tunnel resolvers
+        assert_eq!(
+            resolver_tls_name(&IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1))),
+            None
+        );
+        assert_eq!(
+            resolver_tls_name(&IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))),
+            None
+        );
+        assert_eq!(
+            resolver_tls_name(&"fc00:bbbb:bbbb:bb01::1".parse().unwrap()),
+            None
+        );
+    }
+}
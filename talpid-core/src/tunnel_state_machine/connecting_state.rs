@@ -1,13 +1,18 @@
 use super::{
-    AfterDisconnect, ConnectedState, ConnectedStateBootstrap, DisconnectingState, ErrorState,
-    EventConsequence, EventResult, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver,
-    TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    connecting_watchdog, transition_history, AfterDisconnect, ConnectedState,
+    ConnectedStateBootstrap, DisconnectingState, ErrorState, EventConsequence, EventResult,
+    SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver, TunnelState,
+    TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::{
     firewall::FirewallPolicy,
     routing::RouteManager,
     tunnel::{
-        self, tun_provider::TunProvider, CloseHandle, TunnelEvent, TunnelMetadata, TunnelMonitor,
+        self,
+        probe::RelayProbe,
+        tun_provider::TunProvider,
+        wireguard::{transport_plugin, WgGoEvent},
+        CloseHandle, DebugHandle, TunnelEvent, TunnelMetadata, TunnelMonitor,
     },
 };
 use cfg_if::cfg_if;
@@ -17,6 +22,7 @@ use futures::{
     FutureExt, StreamExt,
 };
 use std::{
+    io,
     path::{Path, PathBuf},
     thread,
     time::{Duration, Instant},
@@ -37,6 +43,8 @@ use super::connected_state::TunnelEventsReceiver;
 
 pub(crate) type TunnelCloseEvent = Fuse<oneshot::Receiver<Option<ErrorStateCause>>>;
 
+type TunnelEventSender = mpsc::UnboundedSender<(TunnelEvent, oneshot::Sender<()>)>;
+
 #[cfg(target_os = "android")]
 const MAX_ATTEMPTS_WITH_SAME_TUN: u32 = 5;
 const MIN_TUNNEL_ALIVE_TIME: Duration = Duration::from_millis(1000);
@@ -50,7 +58,23 @@ pub struct ConnectingState {
     tunnel_metadata: Option<TunnelMetadata>,
     tunnel_close_event: TunnelCloseEvent,
     close_handle: Option<CloseHandle>,
+    debug_handle: Option<DebugHandle>,
     retry_attempt: u32,
+    pending_tunnel: Option<PendingTunnel>,
+}
+
+/// A tunnel that is started once the relay has responded to a probe.
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos")), allow(dead_code))]
+struct PendingTunnel {
+    probe: RelayProbe,
+    event_tx: TunnelEventSender,
+}
+
+/// Events that the connecting state waits for.
+enum ConnectingEvent {
+    Shared(EventResult),
+    RelayProbe(io::Result<()>),
+    WatchdogTimeout,
 }
 
 impl ConnectingState {
@@ -68,7 +92,10 @@ impl ConnectingState {
             peer_endpoint,
             tunnel: tunnel_metadata.clone(),
             allow_lan: shared_values.allow_lan,
+            lan_allow_list: shared_values.lan_allow_list.clone(),
             allowed_endpoint: shared_values.allowed_endpoint.clone(),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            excluded_networks: shared_values.excluded_networks.clone(),
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(&shared_values.resource_dir, &params),
         };
@@ -100,6 +127,44 @@ impl ConnectingState {
         retry_attempt: u32,
     ) -> crate::tunnel::Result<Self> {
         let (event_tx, event_rx) = mpsc::unbounded();
+        let mut state = ConnectingState {
+            tunnel_events: event_rx.fuse(),
+            tunnel_parameters: parameters,
+            tunnel_metadata: None,
+            tunnel_close_event: Fuse::terminated(),
+            close_handle: None,
+            debug_handle: None,
+            retry_attempt,
+            pending_tunnel: None,
+        };
+
+        // The firewall only lets the relay client reach the relay on Windows
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        if let Some(probe) = RelayProbe::spawn(&runtime, &state.tunnel_parameters) {
+            state.pending_tunnel = Some(PendingTunnel { probe, event_tx });
+            return Ok(state);
+        }
+
+        state.start_tunnel_monitor(
+            runtime,
+            event_tx,
+            log_dir,
+            resource_dir,
+            tun_provider,
+            route_manager,
+        )?;
+        Ok(state)
+    }
+
+    fn start_tunnel_monitor(
+        &mut self,
+        runtime: tokio::runtime::Handle,
+        event_tx: TunnelEventSender,
+        log_dir: &Option<PathBuf>,
+        resource_dir: &Path,
+        tun_provider: &mut TunProvider,
+        route_manager: &mut RouteManager,
+    ) -> crate::tunnel::Result<()> {
         let on_tunnel_event =
             move |event| -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>> {
                 let (tx, rx) = oneshot::channel();
@@ -111,26 +176,103 @@ impl ConnectingState {
 
         let monitor = TunnelMonitor::start(
             runtime,
-            &parameters,
+            &self.tunnel_parameters,
             log_dir,
             resource_dir,
             on_tunnel_event,
             tun_provider,
             route_manager,
-            retry_attempt,
+            self.retry_attempt,
         )?;
-        let close_handle = Some(monitor.close_handle());
-        let tunnel_close_event =
-            Self::spawn_tunnel_monitor_wait_thread(Some(monitor), retry_attempt);
+        self.close_handle = Some(monitor.close_handle());
+        self.debug_handle = Some(monitor.debug_handle());
+        self.tunnel_close_event =
+            Self::spawn_tunnel_monitor_wait_thread(Some(monitor), self.retry_attempt);
+        Ok(())
+    }
 
-        Ok(ConnectingState {
-            tunnel_events: event_rx.fuse(),
-            tunnel_parameters: parameters,
-            tunnel_metadata: None,
-            tunnel_close_event,
-            close_handle,
-            retry_attempt,
-        })
+    async fn wait_for_relay_probe(pending_tunnel: &mut Option<PendingTunnel>) -> io::Result<()> {
+        match pending_tunnel {
+            Some(pending_tunnel) => pending_tunnel.probe.result().await,
+            None => futures::future::pending().await,
+        }
+    }
+
+    /// Starts the tunnel if the relay responded to the probe, and moves on to another relay
+    /// otherwise.
+    fn handle_relay_probe_result(
+        mut self,
+        result: io::Result<()>,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence {
+        let pending_tunnel = match self.pending_tunnel.take() {
+            Some(pending_tunnel) => pending_tunnel,
+            None => return EventConsequence::SameState(self.into()),
+        };
+        let result = result
+            .map_err(tunnel::Error::RelayUnreachable)
+            .and_then(|()| {
+                self.start_tunnel_monitor(
+                    shared_values.runtime.clone(),
+                    pending_tunnel.event_tx,
+                    &shared_values.log_dir,
+                    &shared_values.resource_dir,
+                    &mut shared_values.tun_provider,
+                    &mut shared_values.route_manager,
+                )
+            });
+        match result {
+            Ok(()) => EventConsequence::SameState(self.into()),
+            Err(error) => EventConsequence::NewState(Self::handle_start_tunnel_error(
+                shared_values,
+                error,
+                self.retry_attempt,
+            )),
+        }
+    }
+
+    fn handle_start_tunnel_error(
+        shared_values: &mut SharedTunnelStateValues,
+        error: tunnel::Error,
+        retry_attempt: u32,
+    ) -> (TunnelStateWrapper, TunnelStateTransition) {
+        if should_retry(&error, retry_attempt) {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Retrying to connect after failing to start tunnel")
+            );
+            DisconnectingState::enter(
+                shared_values,
+                (
+                    None,
+                    Self::spawn_tunnel_monitor_wait_thread(None, retry_attempt),
+                    AfterDisconnect::Reconnect(retry_attempt + 1),
+                ),
+            )
+        } else {
+            log::error!("{}", error.display_chain_with_msg("Failed to start tunnel"));
+            let block_reason = match error {
+                tunnel::Error::EnableIpv6Error => ErrorStateCause::Ipv6Unavailable,
+                #[cfg(target_os = "android")]
+                tunnel::Error::WireguardTunnelMonitoringError(
+                    tunnel::wireguard::Error::TunnelError(
+                        tunnel::wireguard::TunnelError::SetupTunnelDeviceError(
+                            tun_provider::Error::PermissionDenied,
+                        ),
+                    ),
+                ) => ErrorStateCause::VpnPermissionDenied,
+                #[cfg(target_os = "android")]
+                tunnel::Error::WireguardTunnelMonitoringError(
+                    tunnel::wireguard::Error::TunnelError(
+                        tunnel::wireguard::TunnelError::SetupTunnelDeviceError(
+                            tun_provider::Error::InvalidDnsServers(addresses),
+                        ),
+                    ),
+                ) => ErrorStateCause::InvalidDnsServers(addresses),
+                _ => ErrorStateCause::StartTunnelError,
+            };
+            ErrorState::enter(shared_values, block_reason)
+        }
     }
 
     fn spawn_tunnel_monitor_wait_thread(
@@ -179,6 +321,13 @@ impl ConnectingState {
                     log::debug!("WireGuard tunnel timed out");
                     None
                 }
+                #[cfg(not(target_os = "android"))]
+                tunnel::Error::OpenVpnTunnelMonitoringError(
+                    tunnel::openvpn::Error::AuthFailed(reason),
+                ) => {
+                    log::error!("OpenVPN authentication failed");
+                    Some(ErrorStateCause::AuthFailed(reason))
+                }
                 error @ tunnel::Error::WireguardTunnelMonitoringError(..)
                     if !should_retry(&error, retry_attempt) =>
                 {
@@ -206,6 +355,47 @@ impl ConnectingState {
             tunnel_parameters: self.tunnel_parameters,
             tunnel_close_event: self.tunnel_close_event,
             close_handle: self.close_handle,
+            debug_handle: self.debug_handle,
+        }
+    }
+
+    /// Applies the remediation step that the watchdog escalated to, if any. This is done when
+    /// entering the state, since the previous tunnel has been closed by then.
+    fn apply_remediation_step(shared_values: &mut SharedTunnelStateValues) {
+        #[cfg_attr(any(target_os = "linux", target_os = "macos"), allow(unused_variables))]
+        let step = shared_values.connecting_watchdog.take_pending_step();
+
+        #[cfg(target_os = "android")]
+        if step == Some(connecting_watchdog::RemediationStep::RecreateAdapter) {
+            if let Err(error) = shared_values.tun_provider.create_tun() {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to recreate tun device")
+                );
+            }
+        }
+        #[cfg(windows)]
+        if step == Some(connecting_watchdog::RemediationStep::ReloadDriver) {
+            tunnel::unload_driver_dlls();
+        }
+    }
+
+    /// Takes the next remediation step after connecting has taken too long. Every step ends with
+    /// the current tunnel being closed, which also stops any helper processes.
+    fn handle_watchdog_timeout(
+        self,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence {
+        match shared_values.connecting_watchdog.escalate() {
+            Some(connecting_watchdog::RemediationStep::RotateRelay) => {
+                let retry_attempt = self.retry_attempt + 1;
+                self.disconnect(shared_values, AfterDisconnect::Reconnect(retry_attempt))
+            }
+            Some(_) => {
+                let retry_attempt = self.retry_attempt;
+                self.disconnect(shared_values, AfterDisconnect::Reconnect(retry_attempt))
+            }
+            None => EventConsequence::SameState(self.into()),
         }
     }
 
@@ -230,6 +420,7 @@ impl ConnectingState {
         shared_values: &mut SharedTunnelStateValues,
         after_disconnect: AfterDisconnect,
     ) -> EventConsequence {
+        shared_values.end_traffic_session(self.debug_handle.as_ref());
         Self::reset_routes(shared_values);
 
         EventConsequence::NewState(DisconnectingState::enter(
@@ -276,6 +467,17 @@ impl ConnectingState {
                     return next_state;
                 }
             }
+            Some(TunnelCommand::SetBlockedStatePolicy(blocked_state_policy)) => {
+                let _ = shared_values.set_blocked_state_policy(blocked_state_policy);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::SetLanAllowList(lan_allow_list)) => {
+                if shared_values.set_lan_allow_list(lan_allow_list) {
+                    self.reset_firewall(shared_values)
+                } else {
+                    SameState(self.into())
+                }
+            }
             Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                 if shared_values.set_allowed_endpoint(endpoint) {
                     if let Err(error) = Self::set_firewall_policy(
@@ -304,7 +506,59 @@ impl ConnectingState {
                 Err(cause) => self.disconnect(shared_values, AfterDisconnect::Block(cause)),
             },
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
-                shared_values.block_when_disconnected = block_when_disconnected;
+                shared_values.set_block_when_disconnected(block_when_disconnected);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::DnsOverTls(dns_over_tls)) => {
+                shared_values.dns_over_tls = dns_over_tls;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::DescribeFirewallPolicy(tx)) => {
+                let _ = tx.send(shared_values.firewall.describe_policy());
+                SameState(self.into())
+            }
+            Some(TunnelCommand::VerifyFirewallPolicy(tx)) => {
+                let _ = tx.send(shared_values.restore_firewall_policy());
+                SameState(self.into())
+            }
+            Some(TunnelCommand::CheckFirewallPolicy(tx)) => {
+                let _ = tx.send(shared_values.check_firewall_policy());
+                SameState(self.into())
+            }
+            #[cfg(target_os = "macos")]
+            Some(TunnelCommand::SetFirewallException(scope, endpoints, tx)) => {
+                let _ = tx.send(shared_values.set_firewall_exception(scope, endpoints));
+                SameState(self.into())
+            }
+            #[cfg(target_os = "macos")]
+            Some(TunnelCommand::GetSystemDnsServers(tx)) => {
+                let _ = tx.send(shared_values.system_dns_servers());
+                SameState(self.into())
+            }
+            Some(TunnelCommand::SetWireguardMtu(_mtu, tx)) => {
+                let _ = tx.send(false);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::DescribeTunnel(tx)) => {
+                let _ = tx.send(shared_values.describe_tunnel(self.debug_handle.as_ref()));
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetTunnelStatistics(tx)) => {
+                let _ = tx.send(shared_values.tunnel_statistics(self.debug_handle.as_ref()));
+                SameState(self.into())
+            }
+            Some(TunnelCommand::DescribeStateMachine(tx)) => {
+                let _ = tx.send(shared_values.describe_state_machine(Some(self.retry_attempt)));
+                SameState(self.into())
+            }
+            #[cfg(windows)]
+            Some(TunnelCommand::AllowConnectionSharing(allow_connection_sharing)) => {
+                shared_values.allow_connection_sharing = allow_connection_sharing;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::StrictKillSwitch(strict_kill_switch)) => {
+                shared_values.strict_kill_switch = strict_kill_switch;
                 SameState(self.into())
             }
             Some(TunnelCommand::IsOffline(is_offline)) => {
@@ -337,6 +591,21 @@ impl ConnectingState {
                 shared_values.split_tunnel.set_paths(&paths, result_tx);
                 SameState(self.into())
             }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::SetExcludedNetworks(networks)) => {
+                if shared_values.set_excluded_networks(networks) {
+                    cfg_if! {
+                        if #[cfg(target_os = "macos")] {
+                            // Routes cannot be removed individually, so start over
+                            self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+                        } else {
+                            self.reset_firewall(shared_values)
+                        }
+                    }
+                } else {
+                    SameState(self.into())
+                }
+            }
         }
     }
 
@@ -387,6 +656,15 @@ impl ConnectingState {
                 self.into_connected_state_bootstrap(metadata),
             )),
             Some((TunnelEvent::Down, _)) => SameState(self.into()),
+            Some((TunnelEvent::WireguardEvent(WgGoEvent::HandshakeGaveUp), _)) => {
+                log::info!("No handshake completed with the relay. Reconnecting");
+                let retry_attempt = self.retry_attempt + 1;
+                self.disconnect(shared_values, AfterDisconnect::Reconnect(retry_attempt))
+            }
+            Some((TunnelEvent::WireguardEvent(event), _)) => {
+                log::trace!("WireGuard event while connecting: {}", event);
+                SameState(self.into())
+            }
             None => {
                 // The channel was closed
                 log::debug!("The tunnel disconnected unexpectedly");
@@ -403,6 +681,8 @@ impl ConnectingState {
     ) -> EventConsequence {
         use self::EventConsequence::*;
 
+        shared_values.end_traffic_session(self.debug_handle.as_ref());
+
         if let Some(block_reason) = block_reason {
             Self::reset_routes(shared_values);
             return NewState(ErrorState::enter(shared_values, block_reason));
@@ -427,7 +707,10 @@ fn should_retry(error: &tunnel::Error, retry_attempt: u32) -> bool {
     use tunnel::wireguard::{Error, TunnelError};
 
     match error {
+        tunnel::Error::RelayUnreachable(_) => true,
         tunnel::Error::WireguardTunnelMonitoringError(Error::Udp2TcpError(_)) => true,
+        tunnel::Error::WireguardTunnelMonitoringError(Error::EntryProxyError(_)) => true,
+        tunnel::Error::WireguardTunnelMonitoringError(Error::TransportPluginError(_)) => true,
 
         #[cfg(not(windows))]
         tunnel::Error::WireguardTunnelMonitoringError(Error::TunnelError(
@@ -481,6 +764,9 @@ impl TunnelState for ConnectingState {
         if shared_values.is_offline {
             return ErrorState::enter(shared_values, ErrorStateCause::IsOffline);
         }
+        shared_values.connecting_watchdog.start();
+        Self::apply_remediation_step(shared_values);
+
         match shared_values
             .tunnel_parameters_generator
             .generate(retry_attempt)
@@ -531,57 +817,25 @@ impl TunnelState for ConnectingState {
                         retry_attempt,
                     ) {
                         Ok(connecting_state) => {
+                            #[cfg(target_os = "macos")]
+                            if let Err(error) = shared_values.add_excluded_network_routes() {
+                                log::error!(
+                                    "{}",
+                                    error.display_chain_with_msg(
+                                        "Failed to add routes for excluded networks"
+                                    )
+                                );
+                            }
                             let params = connecting_state.tunnel_parameters.clone();
                             (
                                 TunnelStateWrapper::from(connecting_state),
-                                TunnelStateTransition::Connecting(params.get_tunnel_endpoint()),
+                                TunnelStateTransition::Connecting(
+                                    transport_plugin::get_tunnel_endpoint(&params),
+                                ),
                             )
                         }
                         Err(error) => {
-                            if should_retry(&error, retry_attempt) {
-                                log::warn!(
-                                    "{}",
-                                    error.display_chain_with_msg(
-                                        "Retrying to connect after failing to start tunnel"
-                                    )
-                                );
-                                DisconnectingState::enter(
-                                    shared_values,
-                                    (
-                                        None,
-                                        Self::spawn_tunnel_monitor_wait_thread(None, retry_attempt),
-                                        AfterDisconnect::Reconnect(retry_attempt + 1),
-                                    ),
-                                )
-                            } else {
-                                log::error!(
-                                    "{}",
-                                    error.display_chain_with_msg("Failed to start tunnel")
-                                );
-                                let block_reason = match error {
-                                    tunnel::Error::EnableIpv6Error => {
-                                        ErrorStateCause::Ipv6Unavailable
-                                    }
-                                    #[cfg(target_os = "android")]
-                                    tunnel::Error::WireguardTunnelMonitoringError(
-                                        tunnel::wireguard::Error::TunnelError(
-                                            tunnel::wireguard::TunnelError::SetupTunnelDeviceError(
-                                                tun_provider::Error::PermissionDenied,
-                                            ),
-                                        ),
-                                    ) => ErrorStateCause::VpnPermissionDenied,
-                                    #[cfg(target_os = "android")]
-                                    tunnel::Error::WireguardTunnelMonitoringError(
-                                        tunnel::wireguard::Error::TunnelError(
-                                            tunnel::wireguard::TunnelError::SetupTunnelDeviceError(
-                                                tun_provider::Error::InvalidDnsServers(addresses),
-                                            ),
-                                        ),
-                                    ) => ErrorStateCause::InvalidDnsServers(addresses),
-                                    _ => ErrorStateCause::StartTunnelError,
-                                };
-                                ErrorState::enter(shared_values, block_reason)
-                            }
+                            Self::handle_start_tunnel_error(shared_values, error, retry_attempt)
                         }
                     }
                 }
@@ -595,24 +849,71 @@ impl TunnelState for ConnectingState {
         commands: &mut TunnelCommandReceiver,
         shared_values: &mut SharedTunnelStateValues,
     ) -> EventConsequence {
+        let watchdog_deadline = shared_values.connecting_watchdog.next_deadline();
         let result = runtime.block_on(async {
+            let watchdog_timeout = connecting_watchdog::wait_until(watchdog_deadline).fuse();
+            let relay_probe = Self::wait_for_relay_probe(&mut self.pending_tunnel).fuse();
+            futures::pin_mut!(watchdog_timeout, relay_probe);
             futures::select! {
-                command = commands.next() => EventResult::Command(command),
-                event = self.tunnel_events.next() => EventResult::Event(event),
-                result = &mut self.tunnel_close_event => EventResult::Close(result),
+                command = commands.next() => {
+                    ConnectingEvent::Shared(EventResult::Command(command))
+                }
+                event = self.tunnel_events.next() => {
+                    ConnectingEvent::Shared(EventResult::Event(event))
+                }
+                result = &mut self.tunnel_close_event => {
+                    ConnectingEvent::Shared(EventResult::Close(result))
+                }
+                result = relay_probe => ConnectingEvent::RelayProbe(result),
+                _ = watchdog_timeout => ConnectingEvent::WatchdogTimeout,
             }
         });
+        let trigger = match &result {
+            ConnectingEvent::Shared(event) => transition_history::describe_event(event),
+            ConnectingEvent::RelayProbe(Ok(())) => "relay responded to probe".to_owned(),
+            ConnectingEvent::RelayProbe(Err(_)) => "relay did not respond to probe".to_owned(),
+            ConnectingEvent::WatchdogTimeout => "connecting watchdog timeout".to_owned(),
+        };
+        shared_values.transition_history.set_trigger(trigger);
 
         match result {
-            EventResult::Command(command) => self.handle_commands(command, shared_values),
-            EventResult::Event(event) => self.handle_tunnel_events(event, shared_values),
-            EventResult::Close(result) => {
+            ConnectingEvent::Shared(EventResult::Command(command)) => {
+                self.handle_commands(command, shared_values)
+            }
+            ConnectingEvent::Shared(EventResult::Event(event)) => {
+                self.handle_tunnel_events(event, shared_values)
+            }
+            ConnectingEvent::Shared(EventResult::Close(result)) => {
                 if result.is_err() {
                     log::warn!("Tunnel monitor thread has stopped unexpectedly");
                 }
                 let block_reason = result.unwrap_or(None);
                 self.handle_tunnel_close_event(block_reason, shared_values)
             }
+            ConnectingEvent::RelayProbe(result) => {
+                self.handle_relay_probe_result(result, shared_values)
+            }
+            ConnectingEvent::WatchdogTimeout => self.handle_watchdog_timeout(shared_values),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_retry() {
+        assert!(!should_retry(&tunnel::Error::EnableIpv6Error, 0));
+        assert!(!should_retry(&tunnel::Error::UnsupportedPlatform, 0));
+
+        let unreachable = || {
+            tunnel::Error::RelayUnreachable(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "the relay did not respond",
+            ))
+        };
+        assert!(should_retry(&unreachable(), 0));
+        assert!(should_retry(&unreachable(), 10));
+    }
+}
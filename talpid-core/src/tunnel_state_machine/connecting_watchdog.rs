@@ -0,0 +1,149 @@
+//! Detects when the tunnel state machine has been trying to connect for too long and escalates
+//! through a series of remediation steps that may get it unstuck.
+//!
+//! The watchdog keeps running across reconnection attempts. It is only reset once a tunnel is
+//! established, or when the state machine leaves the connecting states for any other reason, such
+//! as the device going offline.
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Time spent connecting before the first remediation step is taken.
+const FIRST_STEP_DELAY: Duration = Duration::from_secs(60);
+/// Time to wait for a remediation step to take effect before escalating to the next one.
+const STEP_INTERVAL: Duration = Duration::from_secs(30);
+
+#[cfg(windows)]
+const REMEDIATION_STEPS: &[RemediationStep] = &[
+    RemediationStep::RestartTunnel,
+    RemediationStep::RotateRelay,
+    RemediationStep::ReloadDriver,
+];
+#[cfg(target_os = "android")]
+const REMEDIATION_STEPS: &[RemediationStep] = &[
+    RemediationStep::RestartTunnel,
+    RemediationStep::RecreateAdapter,
+    RemediationStep::RotateRelay,
+];
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const REMEDIATION_STEPS: &[RemediationStep] =
+    &[RemediationStep::RestartTunnel, RemediationStep::RotateRelay];
+
+/// Actions that are taken, in order, when connecting takes too long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemediationStep {
+    /// Close the tunnel, including helper processes such as OpenVPN and local proxies, and start
+    /// it again.
+    RestartTunnel,
+    /// Recreate the tunnel device before starting the tunnel again. On other platforms, the
+    /// tunnel adapter is recreated whenever the tunnel is restarted.
+    #[cfg(target_os = "android")]
+    RecreateAdapter,
+    /// Select a new relay instead of waiting for the current attempt to time out.
+    RotateRelay,
+    /// Unload the WireGuardNT and Wintun DLLs, so that they are loaded again by the next attempt.
+    #[cfg(windows)]
+    ReloadDriver,
+}
+
+impl fmt::Display for RemediationStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            RemediationStep::RestartTunnel => "restart tunnel",
+            #[cfg(target_os = "android")]
+            RemediationStep::RecreateAdapter => "recreate tunnel device",
+            RemediationStep::RotateRelay => "rotate relay",
+            #[cfg(windows)]
+            RemediationStep::ReloadDriver => "reload driver DLLs",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Keeps track of how long the state machine has been connecting, and of the remediation steps
+/// that have been taken so far.
+#[derive(Debug, Default)]
+pub struct ConnectingWatchdog {
+    started: Option<Instant>,
+    steps_taken: usize,
+    pending_step: Option<RemediationStep>,
+}
+
+impl ConnectingWatchdog {
+    /// Starts the watchdog, unless it is already running.
+    pub fn start(&mut self) {
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+    }
+
+    /// Stops the watchdog and forgets any remediation steps that have been taken.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Stops the watchdog after a tunnel has been established, and logs the remediation step
+    /// that made it succeed, if any.
+    pub fn connected(&mut self) {
+        if let (Some(started), Some(step)) = (self.started, self.last_step()) {
+            log::info!(
+                "Connected {}s after starting to connect, following remediation step: {}",
+                started.elapsed().as_secs(),
+                step
+            );
+        }
+        self.reset();
+    }
+
+    /// Returns when the next remediation step is due, or `None` if the watchdog is not running or
+    /// all steps have been taken.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let started = self.started?;
+        if self.steps_taken >= REMEDIATION_STEPS.len() {
+            return None;
+        }
+        Some(started + FIRST_STEP_DELAY + STEP_INTERVAL * self.steps_taken as u32)
+    }
+
+    /// Returns the remediation step that is taken at the next deadline, if any.
+    pub fn next_step(&self) -> Option<RemediationStep> {
+        self.started?;
+        REMEDIATION_STEPS.get(self.steps_taken).copied()
+    }
+
+    /// Returns the next remediation step and marks it as pending until the next connection
+    /// attempt is started.
+    pub fn escalate(&mut self) -> Option<RemediationStep> {
+        let started = self.started?;
+        let step = *REMEDIATION_STEPS.get(self.steps_taken)?;
+        self.steps_taken += 1;
+        self.pending_step = Some(step);
+        log::warn!(
+            "Still connecting after {}s. Attempting remediation step: {}",
+            started.elapsed().as_secs(),
+            step
+        );
+        Some(step)
+    }
+
+    /// Returns the remediation step that should be applied before the next connection attempt.
+    pub fn take_pending_step(&mut self) -> Option<RemediationStep> {
+        self.pending_step.take()
+    }
+
+    fn last_step(&self) -> Option<RemediationStep> {
+        self.steps_taken
+            .checked_sub(1)
+            .and_then(|index| REMEDIATION_STEPS.get(index).copied())
+    }
+}
+
+/// Completes when `deadline` has passed. Never completes if there is no deadline.
+pub async fn wait_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => futures::future::pending().await,
+    }
+}
@@ -1,6 +1,6 @@
 use super::{
-    ConnectingState, ErrorState, EventConsequence, SharedTunnelStateValues, TunnelCommand,
-    TunnelCommandReceiver, TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    transition_history, ConnectingState, ErrorState, EventConsequence, SharedTunnelStateValues,
+    TunnelCommand, TunnelCommandReceiver, TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
 #[cfg(target_os = "macos")]
 use crate::dns;
@@ -23,7 +23,9 @@ impl DisconnectedState {
         let result = if shared_values.block_when_disconnected {
             let policy = FirewallPolicy::Blocked {
                 allow_lan: shared_values.allow_lan,
+                lan_allow_list: shared_values.lan_allow_list.clone(),
                 allowed_endpoint: shared_values.allowed_endpoint.clone(),
+                blocked_state_policy: shared_values.blocked_state_policy,
                 #[cfg(target_os = "macos")]
                 dns_redirect_port: shared_values.filtering_resolver.listening_port(),
             };
@@ -97,6 +99,8 @@ impl TunnelState for DisconnectedState {
         shared_values: &mut SharedTunnelStateValues,
         should_reset_firewall: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
+        shared_values.connecting_watchdog.reset();
+
         #[cfg(target_os = "macos")]
         if shared_values.block_when_disconnected {
             if let Err(err) = Self::setup_local_dns_config(shared_values) {
@@ -136,7 +140,12 @@ impl TunnelState for DisconnectedState {
     ) -> EventConsequence {
         use self::EventConsequence::*;
 
-        match runtime.block_on(commands.next()) {
+        let command = runtime.block_on(commands.next());
+        shared_values
+            .transition_history
+            .set_trigger(transition_history::describe_command(command.as_ref()));
+
+        match command {
             Some(TunnelCommand::AllowLan(allow_lan)) => {
                 if shared_values.allow_lan != allow_lan {
                     // The only platform that can fail is Android, but Android doesn't support the
@@ -149,6 +158,18 @@ impl TunnelState for DisconnectedState {
                 }
                 SameState(self.into())
             }
+            Some(TunnelCommand::SetLanAllowList(lan_allow_list)) => {
+                if shared_values.set_lan_allow_list(lan_allow_list) {
+                    Self::set_firewall_policy(shared_values, true);
+                }
+                SameState(self.into())
+            }
+            Some(TunnelCommand::SetBlockedStatePolicy(blocked_state_policy)) => {
+                if shared_values.set_blocked_state_policy(blocked_state_policy) {
+                    Self::set_firewall_policy(shared_values, true);
+                }
+                SameState(self.into())
+            }
             Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                 if shared_values.set_allowed_endpoint(endpoint) {
                     Self::set_firewall_policy(shared_values, true);
@@ -167,8 +188,7 @@ impl TunnelState for DisconnectedState {
                 SameState(self.into())
             }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
-                if shared_values.block_when_disconnected != block_when_disconnected {
-                    shared_values.block_when_disconnected = block_when_disconnected;
+                if shared_values.set_block_when_disconnected(block_when_disconnected) {
                     Self::set_firewall_policy(shared_values, true);
                     #[cfg(windows)]
                     Self::register_split_tunnel_addresses(shared_values, true);
@@ -190,6 +210,58 @@ impl TunnelState for DisconnectedState {
                 }
                 SameState(self.into())
             }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::DnsOverTls(dns_over_tls)) => {
+                shared_values.dns_over_tls = dns_over_tls;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::DescribeFirewallPolicy(tx)) => {
+                let _ = tx.send(shared_values.firewall.describe_policy());
+                SameState(self.into())
+            }
+            Some(TunnelCommand::VerifyFirewallPolicy(tx)) => {
+                let _ = tx.send(shared_values.restore_firewall_policy());
+                SameState(self.into())
+            }
+            Some(TunnelCommand::CheckFirewallPolicy(tx)) => {
+                let _ = tx.send(shared_values.check_firewall_policy());
+                SameState(self.into())
+            }
+            #[cfg(target_os = "macos")]
+            Some(TunnelCommand::SetFirewallException(scope, endpoints, tx)) => {
+                let _ = tx.send(shared_values.set_firewall_exception(scope, endpoints));
+                SameState(self.into())
+            }
+            #[cfg(target_os = "macos")]
+            Some(TunnelCommand::GetSystemDnsServers(tx)) => {
+                let _ = tx.send(shared_values.system_dns_servers());
+                SameState(self.into())
+            }
+            Some(TunnelCommand::SetWireguardMtu(_mtu, tx)) => {
+                let _ = tx.send(false);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::DescribeTunnel(tx)) => {
+                let _ = tx.send(shared_values.describe_tunnel(None));
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetTunnelStatistics(tx)) => {
+                let _ = tx.send(shared_values.tunnel_statistics(None));
+                SameState(self.into())
+            }
+            Some(TunnelCommand::DescribeStateMachine(tx)) => {
+                let _ = tx.send(shared_values.describe_state_machine(None));
+                SameState(self.into())
+            }
+            #[cfg(windows)]
+            Some(TunnelCommand::AllowConnectionSharing(allow_connection_sharing)) => {
+                shared_values.allow_connection_sharing = allow_connection_sharing;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::StrictKillSwitch(strict_kill_switch)) => {
+                shared_values.strict_kill_switch = strict_kill_switch;
+                SameState(self.into())
+            }
             Some(TunnelCommand::IsOffline(is_offline)) => {
                 shared_values.is_offline = is_offline;
                 SameState(self.into())
@@ -209,6 +281,11 @@ impl TunnelState for DisconnectedState {
                 shared_values.split_tunnel.set_paths(&paths, result_tx);
                 SameState(self.into())
             }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::SetExcludedNetworks(networks)) => {
+                let _ = shared_values.set_excluded_networks(networks);
+                SameState(self.into())
+            }
             None => {
                 Self::reset_dns(shared_values);
                 Finished
@@ -1,7 +1,7 @@
 use super::{
-    connecting_state::TunnelCloseEvent, ConnectingState, DisconnectedState, ErrorState,
-    EventConsequence, EventResult, SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver,
-    TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    connecting_state::TunnelCloseEvent, transition_history, ConnectingState, DisconnectedState,
+    ErrorState, EventConsequence, EventResult, SharedTunnelStateValues, TunnelCommand,
+    TunnelCommandReceiver, TunnelState, TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::tunnel::CloseHandle;
 use futures::{future::FusedFuture, StreamExt};
@@ -32,6 +32,14 @@ impl DisconnectingState {
                     let _ = shared_values.set_allow_lan(allow_lan);
                     AfterDisconnect::Nothing
                 }
+                Some(TunnelCommand::SetLanAllowList(lan_allow_list)) => {
+                    let _ = shared_values.set_lan_allow_list(lan_allow_list);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::SetBlockedStatePolicy(blocked_state_policy)) => {
+                    let _ = shared_values.set_blocked_state_policy(blocked_state_policy);
+                    AfterDisconnect::Nothing
+                }
                 Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                     let _ = shared_values.set_allowed_endpoint(endpoint);
                     if let Err(_) = tx.send(()) {
@@ -44,7 +52,59 @@ impl DisconnectingState {
                     AfterDisconnect::Nothing
                 }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
-                    shared_values.block_when_disconnected = block_when_disconnected;
+                    shared_values.set_block_when_disconnected(block_when_disconnected);
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::DnsOverTls(dns_over_tls)) => {
+                    shared_values.dns_over_tls = dns_over_tls;
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::DescribeFirewallPolicy(tx)) => {
+                    let _ = tx.send(shared_values.firewall.describe_policy());
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::VerifyFirewallPolicy(tx)) => {
+                    let _ = tx.send(shared_values.restore_firewall_policy());
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::CheckFirewallPolicy(tx)) => {
+                    let _ = tx.send(shared_values.check_firewall_policy());
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "macos")]
+                Some(TunnelCommand::SetFirewallException(scope, endpoints, tx)) => {
+                    let _ = tx.send(shared_values.set_firewall_exception(scope, endpoints));
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "macos")]
+                Some(TunnelCommand::GetSystemDnsServers(tx)) => {
+                    let _ = tx.send(shared_values.system_dns_servers());
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::SetWireguardMtu(_mtu, tx)) => {
+                    let _ = tx.send(false);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::DescribeTunnel(tx)) => {
+                    let _ = tx.send(shared_values.describe_tunnel(None));
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::GetTunnelStatistics(tx)) => {
+                    let _ = tx.send(shared_values.tunnel_statistics(None));
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::DescribeStateMachine(tx)) => {
+                    let _ = tx.send(shared_values.describe_state_machine(None));
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(windows)]
+                Some(TunnelCommand::AllowConnectionSharing(allow_connection_sharing)) => {
+                    shared_values.allow_connection_sharing = allow_connection_sharing;
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::StrictKillSwitch(strict_kill_switch)) => {
+                    shared_values.strict_kill_switch = strict_kill_switch;
                     AfterDisconnect::Nothing
                 }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
@@ -64,12 +124,25 @@ impl DisconnectingState {
                     shared_values.split_tunnel.set_paths(&paths, result_tx);
                     AfterDisconnect::Nothing
                 }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::SetExcludedNetworks(networks)) => {
+                    let _ = shared_values.set_excluded_networks(networks);
+                    AfterDisconnect::Nothing
+                }
             },
             AfterDisconnect::Block(reason) => match command {
                 Some(TunnelCommand::AllowLan(allow_lan)) => {
                     let _ = shared_values.set_allow_lan(allow_lan);
                     AfterDisconnect::Block(reason)
                 }
+                Some(TunnelCommand::SetLanAllowList(lan_allow_list)) => {
+                    let _ = shared_values.set_lan_allow_list(lan_allow_list);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::SetBlockedStatePolicy(blocked_state_policy)) => {
+                    let _ = shared_values.set_blocked_state_policy(blocked_state_policy);
+                    AfterDisconnect::Block(reason)
+                }
                 Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                     let _ = shared_values.set_allowed_endpoint(endpoint);
                     if let Err(_) = tx.send(()) {
@@ -82,7 +155,59 @@ impl DisconnectingState {
                     AfterDisconnect::Block(reason)
                 }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
-                    shared_values.block_when_disconnected = block_when_disconnected;
+                    shared_values.set_block_when_disconnected(block_when_disconnected);
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::DnsOverTls(dns_over_tls)) => {
+                    shared_values.dns_over_tls = dns_over_tls;
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::DescribeFirewallPolicy(tx)) => {
+                    let _ = tx.send(shared_values.firewall.describe_policy());
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::VerifyFirewallPolicy(tx)) => {
+                    let _ = tx.send(shared_values.restore_firewall_policy());
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::CheckFirewallPolicy(tx)) => {
+                    let _ = tx.send(shared_values.check_firewall_policy());
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "macos")]
+                Some(TunnelCommand::SetFirewallException(scope, endpoints, tx)) => {
+                    let _ = tx.send(shared_values.set_firewall_exception(scope, endpoints));
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "macos")]
+                Some(TunnelCommand::GetSystemDnsServers(tx)) => {
+                    let _ = tx.send(shared_values.system_dns_servers());
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::SetWireguardMtu(_mtu, tx)) => {
+                    let _ = tx.send(false);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::DescribeTunnel(tx)) => {
+                    let _ = tx.send(shared_values.describe_tunnel(None));
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::GetTunnelStatistics(tx)) => {
+                    let _ = tx.send(shared_values.tunnel_statistics(None));
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::DescribeStateMachine(tx)) => {
+                    let _ = tx.send(shared_values.describe_state_machine(None));
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(windows)]
+                Some(TunnelCommand::AllowConnectionSharing(allow_connection_sharing)) => {
+                    shared_values.allow_connection_sharing = allow_connection_sharing;
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::StrictKillSwitch(strict_kill_switch)) => {
+                    shared_values.strict_kill_switch = strict_kill_switch;
                     AfterDisconnect::Block(reason)
                 }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
@@ -106,6 +231,11 @@ impl DisconnectingState {
                     shared_values.split_tunnel.set_paths(&paths, result_tx);
                     AfterDisconnect::Block(reason)
                 }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::SetExcludedNetworks(networks)) => {
+                    let _ = shared_values.set_excluded_networks(networks);
+                    AfterDisconnect::Block(reason)
+                }
                 None => AfterDisconnect::Block(reason),
             },
             AfterDisconnect::Reconnect(retry_attempt) => match command {
@@ -113,6 +243,14 @@ impl DisconnectingState {
                     let _ = shared_values.set_allow_lan(allow_lan);
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                Some(TunnelCommand::SetLanAllowList(lan_allow_list)) => {
+                    let _ = shared_values.set_lan_allow_list(lan_allow_list);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::SetBlockedStatePolicy(blocked_state_policy)) => {
+                    let _ = shared_values.set_blocked_state_policy(blocked_state_policy);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                     let _ = shared_values.set_allowed_endpoint(endpoint);
                     if let Err(_) = tx.send(()) {
@@ -125,7 +263,59 @@ impl DisconnectingState {
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
                 Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
-                    shared_values.block_when_disconnected = block_when_disconnected;
+                    shared_values.set_block_when_disconnected(block_when_disconnected);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::DnsOverTls(dns_over_tls)) => {
+                    shared_values.dns_over_tls = dns_over_tls;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::DescribeFirewallPolicy(tx)) => {
+                    let _ = tx.send(shared_values.firewall.describe_policy());
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::VerifyFirewallPolicy(tx)) => {
+                    let _ = tx.send(shared_values.restore_firewall_policy());
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::CheckFirewallPolicy(tx)) => {
+                    let _ = tx.send(shared_values.check_firewall_policy());
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "macos")]
+                Some(TunnelCommand::SetFirewallException(scope, endpoints, tx)) => {
+                    let _ = tx.send(shared_values.set_firewall_exception(scope, endpoints));
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "macos")]
+                Some(TunnelCommand::GetSystemDnsServers(tx)) => {
+                    let _ = tx.send(shared_values.system_dns_servers());
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::SetWireguardMtu(_mtu, tx)) => {
+                    let _ = tx.send(false);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::DescribeTunnel(tx)) => {
+                    let _ = tx.send(shared_values.describe_tunnel(None));
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::GetTunnelStatistics(tx)) => {
+                    let _ = tx.send(shared_values.tunnel_statistics(None));
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::DescribeStateMachine(tx)) => {
+                    let _ = tx.send(shared_values.describe_state_machine(Some(retry_attempt)));
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(windows)]
+                Some(TunnelCommand::AllowConnectionSharing(allow_connection_sharing)) => {
+                    shared_values.allow_connection_sharing = allow_connection_sharing;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::StrictKillSwitch(strict_kill_switch)) => {
+                    shared_values.strict_kill_switch = strict_kill_switch;
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
@@ -149,6 +339,11 @@ impl DisconnectingState {
                     shared_values.split_tunnel.set_paths(&paths, result_tx);
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::SetExcludedNetworks(networks)) => {
+                    let _ = shared_values.set_excluded_networks(networks);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
             },
         };
 
@@ -229,6 +424,9 @@ impl TunnelState for DisconnectingState {
                 }
             })
         };
+        shared_values
+            .transition_history
+            .set_trigger(transition_history::describe_event(&result));
 
         match result {
             EventResult::Command(command) => self.handle_commands(command, shared_values),
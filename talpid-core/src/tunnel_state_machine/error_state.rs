@@ -1,6 +1,7 @@
 use super::{
-    ConnectingState, DisconnectedState, EventConsequence, SharedTunnelStateValues, TunnelCommand,
-    TunnelCommandReceiver, TunnelState, TunnelStateTransition, TunnelStateWrapper,
+    transition_history, ConnectingState, DisconnectedState, EventConsequence,
+    SharedTunnelStateValues, TunnelCommand, TunnelCommandReceiver, TunnelState,
+    TunnelStateTransition, TunnelStateWrapper,
 };
 use crate::firewall::FirewallPolicy;
 use futures::StreamExt;
@@ -22,7 +23,9 @@ impl ErrorState {
     ) -> Result<(), FirewallPolicyError> {
         let policy = FirewallPolicy::Blocked {
             allow_lan: shared_values.allow_lan,
+            lan_allow_list: shared_values.lan_allow_list.clone(),
             allowed_endpoint: shared_values.allowed_endpoint.clone(),
+            blocked_state_policy: shared_values.blocked_state_policy,
             #[cfg(target_os = "macos")]
             dns_redirect_port: shared_values.filtering_resolver.listening_port(),
         };
@@ -79,6 +82,8 @@ impl TunnelState for ErrorState {
         shared_values: &mut SharedTunnelStateValues,
         block_reason: Self::Bootstrap,
     ) -> (TunnelStateWrapper, TunnelStateTransition) {
+        shared_values.connecting_watchdog.reset();
+
         #[cfg(windows)]
         if let Err(error) = shared_values.split_tunnel.set_tunnel_addresses(None) {
             log::error!(
@@ -134,7 +139,12 @@ impl TunnelState for ErrorState {
     ) -> EventConsequence {
         use self::EventConsequence::*;
 
-        match runtime.block_on(commands.next()) {
+        let command = runtime.block_on(commands.next());
+        shared_values
+            .transition_history
+            .set_trigger(transition_history::describe_command(command.as_ref()));
+
+        match command {
             Some(TunnelCommand::AllowLan(allow_lan)) => {
                 if let Err(error_state_cause) = shared_values.set_allow_lan(allow_lan) {
                     NewState(Self::enter(shared_values, error_state_cause))
@@ -143,6 +153,18 @@ impl TunnelState for ErrorState {
                     SameState(self.into())
                 }
             }
+            Some(TunnelCommand::SetLanAllowList(lan_allow_list)) => {
+                if shared_values.set_lan_allow_list(lan_allow_list) {
+                    let _ = Self::set_firewall_policy(shared_values);
+                }
+                SameState(self.into())
+            }
+            Some(TunnelCommand::SetBlockedStatePolicy(blocked_state_policy)) => {
+                if shared_values.set_blocked_state_policy(blocked_state_policy) {
+                    let _ = Self::set_firewall_policy(shared_values);
+                }
+                SameState(self.into())
+            }
             Some(TunnelCommand::AllowEndpoint(endpoint, tx)) => {
                 if shared_values.set_allowed_endpoint(endpoint) {
                     let _ = Self::set_firewall_policy(shared_values);
@@ -168,7 +190,59 @@ impl TunnelState for ErrorState {
                 }
             }
             Some(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
-                shared_values.block_when_disconnected = block_when_disconnected;
+                shared_values.set_block_when_disconnected(block_when_disconnected);
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::DnsOverTls(dns_over_tls)) => {
+                shared_values.dns_over_tls = dns_over_tls;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::DescribeFirewallPolicy(tx)) => {
+                let _ = tx.send(shared_values.firewall.describe_policy());
+                SameState(self.into())
+            }
+            Some(TunnelCommand::VerifyFirewallPolicy(tx)) => {
+                let _ = tx.send(shared_values.restore_firewall_policy());
+                SameState(self.into())
+            }
+            Some(TunnelCommand::CheckFirewallPolicy(tx)) => {
+                let _ = tx.send(shared_values.check_firewall_policy());
+                SameState(self.into())
+            }
+            #[cfg(target_os = "macos")]
+            Some(TunnelCommand::SetFirewallException(scope, endpoints, tx)) => {
+                let _ = tx.send(shared_values.set_firewall_exception(scope, endpoints));
+                SameState(self.into())
+            }
+            #[cfg(target_os = "macos")]
+            Some(TunnelCommand::GetSystemDnsServers(tx)) => {
+                let _ = tx.send(shared_values.system_dns_servers());
+                SameState(self.into())
+            }
+            Some(TunnelCommand::SetWireguardMtu(_mtu, tx)) => {
+                let _ = tx.send(false);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::DescribeTunnel(tx)) => {
+                let _ = tx.send(shared_values.describe_tunnel(None));
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetTunnelStatistics(tx)) => {
+                let _ = tx.send(shared_values.tunnel_statistics(None));
+                SameState(self.into())
+            }
+            Some(TunnelCommand::DescribeStateMachine(tx)) => {
+                let _ = tx.send(shared_values.describe_state_machine(None));
+                SameState(self.into())
+            }
+            #[cfg(windows)]
+            Some(TunnelCommand::AllowConnectionSharing(allow_connection_sharing)) => {
+                shared_values.allow_connection_sharing = allow_connection_sharing;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::StrictKillSwitch(strict_kill_switch)) => {
+                shared_values.strict_kill_switch = strict_kill_switch;
                 SameState(self.into())
             }
             Some(TunnelCommand::IsOffline(is_offline)) => {
@@ -204,6 +278,11 @@ impl TunnelState for ErrorState {
                 shared_values.split_tunnel.set_paths(&paths, result_tx);
                 SameState(self.into())
             }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::SetExcludedNetworks(networks)) => {
+                let _ = shared_values.set_excluded_networks(networks);
+                SameState(self.into())
+            }
         }
     }
 }
@@ -1,16 +1,26 @@
 mod connected_state;
 mod connecting_state;
+mod connecting_watchdog;
 mod disconnected_state;
 mod disconnecting_state;
 mod error_state;
+mod traffic_accounting;
+mod transition_history;
 
 use self::{
     connected_state::{ConnectedState, ConnectedStateBootstrap},
     connecting_state::ConnectingState,
+    connecting_watchdog::ConnectingWatchdog,
     disconnected_state::DisconnectedState,
     disconnecting_state::{AfterDisconnect, DisconnectingState},
     error_state::ErrorState,
+    traffic_accounting::TrafficAccounting,
+    transition_history::TransitionHistory,
 };
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use crate::power;
+#[cfg(target_os = "macos")]
+use crate::routing::{NetNode, RequiredRoute};
 #[cfg(windows)]
 use crate::split_tunnel;
 use crate::{
@@ -19,8 +29,9 @@ use crate::{
     mpsc::Sender,
     offline,
     routing::RouteManager,
-    tunnel::{tun_provider::TunProvider, TunnelEvent},
+    tunnel::{tun_provider::TunProvider, DebugHandle, TunnelEvent},
 };
+use ipnetwork::IpNetwork;
 #[cfg(windows)]
 use std::ffi::OsString;
 
@@ -30,12 +41,26 @@ use futures::{
 };
 #[cfg(target_os = "android")]
 use std::os::unix::io::RawFd;
-use std::{collections::HashSet, io, net::IpAddr, path::PathBuf, sync::Arc};
+use std::{
+    collections::HashSet,
+    io,
+    net::IpAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
 #[cfg(target_os = "android")]
-use talpid_types::{android::AndroidContext, ErrorExt};
+use talpid_types::android::AndroidContext;
+#[cfg(target_os = "macos")]
+use talpid_types::net::Endpoint;
 use talpid_types::{
+    firewall::{BlockedStatePolicy, FirewallPolicyDescription},
     net::{AllowedEndpoint, TunnelParameters},
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
+    tunnel::{
+        ErrorStateCause, ParameterGenerationError, PendingTimerDebugInfo, StateMachineDebugInfo,
+        TunnelDebugInfo, TunnelStateTransition, TunnelStatistics,
+    },
+    ErrorExt,
 };
 
 /// Errors that can happen when setting up or using the state machine.
@@ -80,10 +105,22 @@ pub enum Error {
 pub struct InitialTunnelState {
     /// Whether to allow LAN traffic when not in the (non-blocking) disconnected state.
     pub allow_lan: bool,
+    /// Private networks that LAN traffic is limited to. If `None`, all private networks are
+    /// allowed.
+    pub lan_allow_list: Option<Vec<IpNetwork>>,
+    /// Local network services that are permitted while traffic is blocked.
+    pub blocked_state_policy: BlockedStatePolicy,
     /// Block traffic unless connected to the VPN.
     pub block_when_disconnected: bool,
+    /// Confirm that the firewall rules and routes are in place before reporting the tunnel as
+    /// connected.
+    pub strict_kill_switch: bool,
     /// DNS servers to use. If `None`, the tunnel gateway is used.
     pub dns_servers: Option<Vec<IpAddr>>,
+    /// Whether the DNS servers are resolvers on the relay that should be reached using
+    /// DNS-over-TLS.
+    #[cfg(target_os = "linux")]
+    pub dns_over_tls: bool,
     /// A single endpoint that is allowed to communicate outside the tunnel, i.e.
     /// in any of the blocking states.
     pub allowed_endpoint: AllowedEndpoint,
@@ -92,6 +129,12 @@ pub struct InitialTunnelState {
     /// Programs to exclude from the tunnel using the split tunnel driver.
     #[cfg(windows)]
     pub exclude_paths: Vec<OsString>,
+    /// Whether clients of Internet Connection Sharing or Mobile Hotspot may use the tunnel.
+    #[cfg(windows)]
+    pub allow_connection_sharing: bool,
+    /// Networks whose traffic should bypass the tunnel.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub excluded_networks: Vec<IpNetwork>,
 }
 
 /// Spawn the tunnel state machine thread, returning a channel for sending tunnel commands.
@@ -102,6 +145,7 @@ pub async fn spawn(
     resource_dir: PathBuf,
     state_change_listener: impl Sender<TunnelStateTransition> + Send + 'static,
     offline_state_listener: mpsc::UnboundedSender<bool>,
+    #[cfg(target_os = "macos")] dns_interference_listener: mpsc::UnboundedSender<()>,
     shutdown_tx: oneshot::Sender<()>,
     #[cfg(target_os = "macos")] exclusion_gid: u32,
     #[cfg(target_os = "android")] android_context: AndroidContext,
@@ -125,6 +169,8 @@ pub async fn spawn(
         initial_settings,
         weak_command_tx,
         offline_state_listener,
+        #[cfg(target_os = "macos")]
+        dns_interference_listener,
         tunnel_parameters_generator,
         tun_provider,
         log_dir,
@@ -151,13 +197,53 @@ pub async fn spawn(
 pub enum TunnelCommand {
     /// Enable or disable LAN access in the firewall.
     AllowLan(bool),
-    /// Endpoint that should never be blocked.
+    /// Limit LAN access to the given private networks, or allow all private networks if `None`.
+    SetLanAllowList(Option<Vec<IpNetwork>>),
+    /// Set which local network services are permitted while traffic is blocked.
+    SetBlockedStatePolicy(BlockedStatePolicy),
+    /// Endpoint that should never be blocked, or `None` to stop allowing the previous one.
     /// If an error occurs, the sender is dropped.
-    AllowEndpoint(AllowedEndpoint, oneshot::Sender<()>),
+    AllowEndpoint(Option<AllowedEndpoint>, oneshot::Sender<()>),
     /// Set DNS servers to use.
     Dns(Option<Vec<IpAddr>>),
+    /// Enable or disable DNS-over-TLS to the resolvers on the relay.
+    #[cfg(target_os = "linux")]
+    DnsOverTls(bool),
     /// Enable or disable the block_when_disconnected feature.
     BlockWhenDisconnected(bool),
+    /// Allow or disallow clients of Internet Connection Sharing or Mobile Hotspot to use the
+    /// tunnel.
+    #[cfg(windows)]
+    AllowConnectionSharing(bool),
+    /// Enable or disable verification of the firewall rules and routes after connecting.
+    StrictKillSwitch(bool),
+    /// Describe the firewall policy that is currently being enforced.
+    DescribeFirewallPolicy(oneshot::Sender<FirewallPolicyDescription>),
+    /// Read back the firewall rules and apply the current policy again if they have been removed
+    /// or modified. Responds with whether the policy had to be restored.
+    VerifyFirewallPolicy(oneshot::Sender<bool>),
+    /// Read back the firewall rules and respond with whether the current policy is in place,
+    /// without changing them. Responds with `None` if they could not be read.
+    CheckFirewallPolicy(oneshot::Sender<Option<bool>>),
+    /// Allow traffic to the given endpoints on the physical interface regardless of the firewall
+    /// policy, or remove the exception if `None`. Exceptions are identified by their scope.
+    /// Responds with whether the exception was updated.
+    #[cfg(target_os = "macos")]
+    SetFirewallException(&'static str, Option<Vec<Endpoint>>, oneshot::Sender<bool>),
+    /// Return the DNS servers of the system, as configured before any changes by the state
+    /// machine.
+    #[cfg(target_os = "macos")]
+    GetSystemDnsServers(oneshot::Sender<Vec<IpAddr>>),
+    /// Describe the internals of the tunnel and the routes applied by the route manager.
+    DescribeTunnel(oneshot::Sender<TunnelDebugInfo>),
+    /// Return the traffic through the current tunnel and in total since the daemon started.
+    GetTunnelStatistics(oneshot::Sender<TunnelStatistics>),
+    /// Describe the current state, the most recent transitions and any pending timers.
+    DescribeStateMachine(oneshot::Sender<StateMachineDebugInfo>),
+    /// Change the MTU of the WireGuard tunnel interface, or restore the default MTU if `None`.
+    /// Responds with whether the MTU could be changed without reconnecting. Only a connected
+    /// WireGuard tunnel can be updated.
+    SetWireguardMtu(Option<u16>, oneshot::Sender<bool>),
     /// Notify the state machine of the connectivity of the device.
     IsOffline(bool),
     /// Open tunnel connection.
@@ -175,6 +261,50 @@ pub enum TunnelCommand {
         oneshot::Sender<Result<(), split_tunnel::Error>>,
         Vec<OsString>,
     ),
+    /// Set networks whose traffic should bypass the tunnel.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    SetExcludedNetworks(Vec<IpNetwork>),
+}
+
+impl TunnelCommand {
+    /// Returns the name of the command.
+    fn name(&self) -> &'static str {
+        match self {
+            TunnelCommand::AllowLan(_) => "allow LAN",
+            TunnelCommand::SetLanAllowList(_) => "set LAN allow list",
+            TunnelCommand::SetBlockedStatePolicy(_) => "set blocked state policy",
+            TunnelCommand::AllowEndpoint(..) => "allow endpoint",
+            TunnelCommand::Dns(_) => "set DNS servers",
+            #[cfg(target_os = "linux")]
+            TunnelCommand::DnsOverTls(_) => "set DNS-over-TLS",
+            TunnelCommand::BlockWhenDisconnected(_) => "block when disconnected",
+            #[cfg(windows)]
+            TunnelCommand::AllowConnectionSharing(_) => "allow connection sharing",
+            TunnelCommand::StrictKillSwitch(_) => "strict kill switch",
+            TunnelCommand::DescribeFirewallPolicy(_) => "describe firewall policy",
+            TunnelCommand::VerifyFirewallPolicy(_) => "verify firewall policy",
+            TunnelCommand::CheckFirewallPolicy(_) => "check firewall policy",
+            #[cfg(target_os = "macos")]
+            TunnelCommand::SetFirewallException(..) => "set firewall exception",
+            #[cfg(target_os = "macos")]
+            TunnelCommand::GetSystemDnsServers(_) => "get system DNS servers",
+            TunnelCommand::DescribeTunnel(_) => "describe tunnel",
+            TunnelCommand::GetTunnelStatistics(_) => "get tunnel statistics",
+            TunnelCommand::DescribeStateMachine(_) => "describe state machine",
+            TunnelCommand::SetWireguardMtu(..) => "set WireGuard MTU",
+            TunnelCommand::IsOffline(true) => "offline",
+            TunnelCommand::IsOffline(false) => "online",
+            TunnelCommand::Connect => "connect",
+            TunnelCommand::Disconnect => "disconnect",
+            TunnelCommand::Block(_) => "block",
+            #[cfg(target_os = "android")]
+            TunnelCommand::BypassSocket(..) => "bypass socket",
+            #[cfg(windows)]
+            TunnelCommand::SetExcludedApps(..) => "set excluded apps",
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            TunnelCommand::SetExcludedNetworks(_) => "set excluded networks",
+        }
+    }
 }
 
 type TunnelCommandReceiver = stream::Fuse<mpsc::UnboundedReceiver<TunnelCommand>>;
@@ -202,6 +332,7 @@ impl TunnelStateMachine {
         settings: InitialTunnelState,
         command_tx: std::sync::Weak<mpsc::UnboundedSender<TunnelCommand>>,
         offline_state_tx: mpsc::UnboundedSender<bool>,
+        #[cfg(target_os = "macos")] dns_interference_tx: mpsc::UnboundedSender<()>,
         tunnel_parameters_generator: impl TunnelParametersGenerator,
         tun_provider: TunProvider,
         log_dir: Option<PathBuf>,
@@ -226,6 +357,8 @@ impl TunnelStateMachine {
                 InitialFirewallState::None
             },
             allow_lan: settings.allow_lan,
+            lan_allow_list: settings.lan_allow_list.clone(),
+            blocked_state_policy: settings.blocked_state_policy,
             #[cfg(target_os = "macos")]
             exclusion_gid,
         };
@@ -241,21 +374,12 @@ impl TunnelStateMachine {
             route_manager
                 .handle()
                 .map_err(Error::InitRouteManagerError)?,
+            #[cfg(target_os = "macos")]
+            dns_interference_tx,
         )
         .map_err(Error::InitDnsMonitorError)?;
 
-        let (offline_tx, mut offline_rx) = mpsc::unbounded();
-        let initial_offline_state_tx = offline_state_tx.clone();
-        tokio::spawn(async move {
-            while let Some(offline) = offline_rx.next().await {
-                if let Some(tx) = command_tx.upgrade() {
-                    let _ = tx.unbounded_send(TunnelCommand::IsOffline(offline));
-                } else {
-                    break;
-                }
-                let _ = offline_state_tx.unbounded_send(offline);
-            }
-        });
+        let (offline_tx, offline_rx) = mpsc::unbounded();
         let mut offline_monitor = offline::spawn_monitor(
             offline_tx,
             #[cfg(target_os = "linux")]
@@ -268,7 +392,29 @@ impl TunnelStateMachine {
         .await
         .map_err(Error::OfflineMonitorError)?;
         let is_offline = offline_monitor.is_offline().await;
-        let _ = initial_offline_state_tx.unbounded_send(is_offline);
+        let _ = offline_state_tx.unbounded_send(is_offline);
+
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let (suspended_tx, suspended_rx) = mpsc::unbounded();
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let power_monitor = power::spawn_monitor(suspended_tx)
+            .map_err(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to start the power monitor")
+                );
+            })
+            .ok();
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        let suspended_rx = stream::pending::<bool>();
+
+        tokio::spawn(forward_offline_state(
+            command_tx,
+            offline_state_tx,
+            offline_rx,
+            suspended_rx,
+            is_offline,
+        ));
 
         #[cfg(windows)]
         split_tunnel
@@ -283,12 +429,26 @@ impl TunnelStateMachine {
             dns_monitor,
             route_manager,
             _offline_monitor: offline_monitor,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            _power_monitor: power_monitor,
             allow_lan: settings.allow_lan,
+            lan_allow_list: settings.lan_allow_list,
+            blocked_state_policy: settings.blocked_state_policy,
             block_when_disconnected: settings.block_when_disconnected,
+            strict_kill_switch: settings.strict_kill_switch,
             is_offline,
             dns_servers: settings.dns_servers,
-            allowed_endpoint: settings.allowed_endpoint,
+            #[cfg(target_os = "linux")]
+            dns_over_tls: settings.dns_over_tls,
+            allowed_endpoint: Some(settings.allowed_endpoint),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            excluded_networks: settings.excluded_networks,
+            #[cfg(windows)]
+            allow_connection_sharing: settings.allow_connection_sharing,
             tunnel_parameters_generator: Box::new(tunnel_parameters_generator),
+            connecting_watchdog: ConnectingWatchdog::default(),
+            transition_history: TransitionHistory::new("disconnected"),
+            traffic: TrafficAccounting::default(),
             tun_provider,
             log_dir,
             resource_dir,
@@ -299,6 +459,10 @@ impl TunnelStateMachine {
         };
 
         tokio::task::spawn_blocking(move || {
+            // Initializing the firewall removes any boot-time filters.
+            #[cfg(windows)]
+            shared_values.apply_boot_time_blocking();
+
             let (initial_state, _) =
                 DisconnectedState::enter(&mut shared_values, settings.reset_firewall);
 
@@ -322,6 +486,7 @@ impl TunnelStateMachine {
             {
                 NewState((state, transition)) => {
                     self.current_state = Some(state);
+                    self.shared_values.transition_history.record(&transition);
 
                     if let Err(error) = change_listener
                         .send(transition)
@@ -342,6 +507,46 @@ impl TunnelStateMachine {
     }
 }
 
+/// Forwards changes in connectivity to the state machine and to `offline_state_tx`. The machine is
+/// considered offline while it is suspended, so that the tunnel is closed before sleep.
+async fn forward_offline_state(
+    command_tx: std::sync::Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    offline_state_tx: mpsc::UnboundedSender<bool>,
+    offline_rx: mpsc::UnboundedReceiver<bool>,
+    suspended_rx: impl stream::Stream<Item = bool> + Unpin,
+    mut is_offline: bool,
+) {
+    let mut is_suspended = false;
+    let mut offline_rx = offline_rx.fuse();
+    let mut suspended_rx = suspended_rx.fuse();
+
+    loop {
+        let was_offline = is_offline || is_suspended;
+        futures::select! {
+            offline = offline_rx.next() => match offline {
+                Some(offline) => is_offline = offline,
+                None => break,
+            },
+            suspended = suspended_rx.next() => {
+                if let Some(suspended) = suspended {
+                    is_suspended = suspended;
+                }
+            }
+        }
+
+        let offline = is_offline || is_suspended;
+        if offline == was_offline {
+            continue;
+        }
+        if let Some(tx) = command_tx.upgrade() {
+            let _ = tx.unbounded_send(TunnelCommand::IsOffline(offline));
+        } else {
+            break;
+        }
+        let _ = offline_state_tx.unbounded_send(offline);
+    }
+}
+
 /// Trait for any type that can provide a stream of `TunnelParameters` to the `TunnelStateMachine`.
 pub trait TunnelParametersGenerator: Send + 'static {
     /// Given the number of consecutive failed retry attempts, it should yield a `TunnelParameters`
@@ -365,18 +570,43 @@ struct SharedTunnelStateValues {
     dns_monitor: DnsMonitor,
     route_manager: RouteManager,
     _offline_monitor: offline::MonitorHandle,
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    _power_monitor: Option<power::MonitorHandle>,
     /// Should LAN access be allowed outside the tunnel.
     allow_lan: bool,
+    /// Private networks that LAN access is limited to.
+    lan_allow_list: Option<Vec<IpNetwork>>,
+    /// Local network services that are permitted while traffic is blocked.
+    blocked_state_policy: BlockedStatePolicy,
     /// Should network access be allowed when in the disconnected state.
     block_when_disconnected: bool,
+    /// Should the firewall rules and routes be read back before entering the connected state.
+    strict_kill_switch: bool,
     /// True when the computer is known to be offline.
     is_offline: bool,
     /// DNS servers to use (overriding default).
     dns_servers: Option<Vec<IpAddr>>,
+    /// Should DNS-over-TLS be used to reach the resolvers on the relay.
+    #[cfg(target_os = "linux")]
+    dns_over_tls: bool,
     /// Endpoint that should not be blocked by the firewall.
-    allowed_endpoint: AllowedEndpoint,
+    allowed_endpoint: Option<AllowedEndpoint>,
+    /// Networks whose traffic should bypass the tunnel.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    excluded_networks: Vec<IpNetwork>,
+    /// Should clients of Internet Connection Sharing or Mobile Hotspot be allowed to use the
+    /// tunnel.
+    #[cfg(windows)]
+    allow_connection_sharing: bool,
     /// The generator of new `TunnelParameter`s
     tunnel_parameters_generator: Box<dyn TunnelParametersGenerator>,
+    /// Escalates through remediation steps when connecting takes too long.
+    connecting_watchdog: ConnectingWatchdog,
+    /// The most recent state transitions and the events that caused them.
+    transition_history: TransitionHistory,
+    /// Traffic through the current tunnel, as last read from it, and through all previous
+    /// tunnels.
+    traffic: TrafficAccounting,
     /// The provider of tunnel devices.
     tun_provider: TunProvider,
     /// Directory to store tunnel log file.
@@ -416,11 +646,238 @@ impl SharedTunnelStateValues {
         Ok(())
     }
 
-    pub fn set_allowed_endpoint(&mut self, endpoint: AllowedEndpoint) -> bool {
+    /// Restores the firewall policy if its rules have been removed or modified, for example by
+    /// another program. Returns whether the policy was restored.
+    pub fn restore_firewall_policy(&mut self) -> bool {
+        match self.firewall.restore_policy() {
+            Ok(restored) => restored,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to restore firewall policy")
+                );
+                false
+            }
+        }
+    }
+
+    /// Returns whether the rules of the applied firewall policy are in place, without changing
+    /// them, or `None` if no policy has been applied or the rules could not be read.
+    pub fn check_firewall_policy(&mut self) -> Option<bool> {
+        match self.firewall.check_applied_policy() {
+            Ok(intact) => intact,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to check firewall policy")
+                );
+                None
+            }
+        }
+    }
+
+    /// Adds, replaces or removes a firewall exception. The exception is scoped to the physical
+    /// interface that the default route of each IP version goes through. Returns whether it
+    /// succeeded.
+    #[cfg(target_os = "macos")]
+    pub fn set_firewall_exception(
+        &mut self,
+        scope: &str,
+        endpoints: Option<Vec<Endpoint>>,
+    ) -> bool {
+        let result = match endpoints {
+            Some(endpoints) => {
+                let exceptions = match self.scope_to_default_interfaces(endpoints) {
+                    Some(exceptions) => exceptions,
+                    None => return false,
+                };
+                self.firewall.add_exception(scope, exceptions)
+            }
+            None => self.firewall.remove_exception(scope),
+        };
+        if let Err(error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to update firewall exception")
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Pairs each endpoint with the interface of the default route for its IP version. Returns
+    /// `None` if the default routes cannot be determined, or if there is no default route for an
+    /// endpoint.
+    #[cfg(target_os = "macos")]
+    fn scope_to_default_interfaces(
+        &self,
+        endpoints: Vec<Endpoint>,
+    ) -> Option<Vec<crate::firewall::FirewallException>> {
+        let (v4_node, v6_node) = match self.runtime.block_on(crate::routing::get_default_routes()) {
+            Ok(nodes) => nodes,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to obtain default routes")
+                );
+                return None;
+            }
+        };
+        endpoints
+            .into_iter()
+            .map(|endpoint| {
+                let node = if endpoint.address.is_ipv4() {
+                    &v4_node
+                } else {
+                    &v6_node
+                };
+                match node.as_ref().and_then(|node| node.get_device()) {
+                    Some(interface) => Some(crate::firewall::FirewallException {
+                        interface: interface.to_owned(),
+                        endpoint,
+                    }),
+                    None => {
+                        log::error!("No default interface to allow {} on", endpoint);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the DNS servers of the system, ignoring any servers set by the state machine.
+    #[cfg(target_os = "macos")]
+    pub fn system_dns_servers(&self) -> Vec<IpAddr> {
+        match self.dns_monitor.get_system_config() {
+            Ok(config) => config.map(|(_, servers)| servers).unwrap_or_default(),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to read the system DNS config")
+                );
+                vec![]
+            }
+        }
+    }
+
+    /// Returns the internals of the running tunnel, if any, together with the routes that are
+    /// currently applied.
+    pub fn describe_tunnel(&self, debug_handle: Option<&DebugHandle>) -> TunnelDebugInfo {
+        let mut info = debug_handle
+            .map(DebugHandle::debug_info)
+            .unwrap_or_default();
+        let route_manager = &self.route_manager;
+        match self
+            .runtime
+            .block_on(async { route_manager.handle()?.get_applied_routes().await })
+        {
+            Ok(routes) => info.routes = routes,
+            Err(error) => log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to obtain applied routes")
+            ),
+        }
+        info
+    }
+
+    /// Returns the traffic through the current tunnel, if any, and in total, along with the
+    /// timings and endpoint changes of the current tunnel. The last known counters are used if the tunnel cannot be
+    /// read.
+    pub fn tunnel_statistics(&mut self, debug_handle: Option<&DebugHandle>) -> TunnelStatistics {
+        self.traffic
+            .update(debug_handle.and_then(DebugHandle::traffic));
+        TunnelStatistics {
+            session: self.traffic.session(),
+            cumulative: self.traffic.cumulative(),
+            operation_timings: debug_handle
+                .map(DebugHandle::operation_timings)
+                .unwrap_or_default(),
+            endpoint_changes: debug_handle.and_then(DebugHandle::endpoint_changes),
+        }
+    }
+
+    /// Adds the traffic through the tunnel that is being closed to the cumulative counters.
+    pub fn end_traffic_session(&mut self, debug_handle: Option<&DebugHandle>) {
+        self.traffic
+            .end_session(debug_handle.and_then(DebugHandle::traffic));
+    }
+
+    /// Returns the current state, the most recent transitions, and the remediation step that is
+    /// pending, if any. `retry_attempt` is the number of failed connection attempts, if the
+    /// current state is trying to connect.
+    pub fn describe_state_machine(&self, retry_attempt: Option<u32>) -> StateMachineDebugInfo {
+        let mut pending_timers = vec![];
+        if let (Some(deadline), Some(step)) = (
+            self.connecting_watchdog.next_deadline(),
+            self.connecting_watchdog.next_step(),
+        ) {
+            pending_timers.push(PendingTimerDebugInfo {
+                action: format!("connecting watchdog: {}", step),
+                deadline: SystemTime::now() + deadline.saturating_duration_since(Instant::now()),
+            });
+        }
+
+        StateMachineDebugInfo {
+            state: self.transition_history.state().to_owned(),
+            state_entered: self.transition_history.state_entered(),
+            retry_attempt,
+            pending_timers,
+            transitions: self.transition_history.transitions(),
+        }
+    }
+
+    /// Updates the `block_when_disconnected` setting. On Windows, this also installs or removes
+    /// the filters that block traffic during boot. Returns whether the setting changed.
+    pub fn set_block_when_disconnected(&mut self, block_when_disconnected: bool) -> bool {
+        if self.block_when_disconnected == block_when_disconnected {
+            return false;
+        }
+        self.block_when_disconnected = block_when_disconnected;
+        #[cfg(windows)]
+        self.apply_boot_time_blocking();
+        true
+    }
+
+    /// Installs boot-time blocking filters if `block_when_disconnected` is enabled, or removes
+    /// them otherwise.
+    #[cfg(windows)]
+    fn apply_boot_time_blocking(&mut self) {
+        if let Err(error) = self
+            .firewall
+            .set_boot_time_blocking(self.block_when_disconnected)
+        {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to update boot-time blocking filters")
+            );
+        }
+    }
+
+    pub fn set_lan_allow_list(&mut self, lan_allow_list: Option<Vec<IpNetwork>>) -> bool {
+        if self.lan_allow_list != lan_allow_list {
+            self.lan_allow_list = lan_allow_list;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn set_blocked_state_policy(&mut self, blocked_state_policy: BlockedStatePolicy) -> bool {
+        if self.blocked_state_policy != blocked_state_policy {
+            self.blocked_state_policy = blocked_state_policy;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn set_allowed_endpoint(&mut self, endpoint: Option<AllowedEndpoint>) -> bool {
         if self.allowed_endpoint != endpoint {
             #[cfg(target_os = "android")]
-            self.tun_provider
-                .set_allowed_endpoint(endpoint.endpoint.address.ip());
+            if let Some(endpoint) = &endpoint {
+                self.tun_provider
+                    .set_allowed_endpoint(endpoint.endpoint.address.ip());
+            }
 
             self.allowed_endpoint = endpoint;
 
@@ -430,6 +887,28 @@ impl SharedTunnelStateValues {
         }
     }
 
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn set_excluded_networks(&mut self, excluded_networks: Vec<IpNetwork>) -> bool {
+        if self.excluded_networks != excluded_networks {
+            self.excluded_networks = excluded_networks;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Routes traffic to the excluded networks outside the tunnel. On Linux, this is instead done
+    /// by marking the traffic in the firewall.
+    #[cfg(target_os = "macos")]
+    pub fn add_excluded_network_routes(&mut self) -> Result<(), crate::routing::Error> {
+        let routes = self
+            .excluded_networks
+            .iter()
+            .map(|network| RequiredRoute::new(*network, NetNode::DefaultNode))
+            .collect();
+        self.runtime.block_on(self.route_manager.add_routes(routes))
+    }
+
     pub fn set_dns_servers(
         &mut self,
         dns_servers: Option<Vec<IpAddr>>,
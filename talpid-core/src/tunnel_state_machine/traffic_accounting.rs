@@ -0,0 +1,91 @@
+//! Adds up the traffic through the tunnels that the state machine has opened, so that the total
+//! survives reconnects.
+
+use talpid_types::tunnel::TrafficCounters;
+
+/// Traffic through the current tunnel and through all tunnels that have been closed.
+#[derive(Default)]
+pub struct TrafficAccounting {
+    session: TrafficCounters,
+    previous: TrafficCounters,
+}
+
+impl TrafficAccounting {
+    /// Replaces the traffic through the current tunnel with `traffic`. The last known counters
+    /// are kept if the tunnel could not be read.
+    pub fn update(&mut self, traffic: Option<TrafficCounters>) {
+        if let Some(traffic) = traffic {
+            self.session = traffic;
+        }
+    }
+
+    /// Adds the traffic through the tunnel that is being closed to the cumulative counters.
+    pub fn end_session(&mut self, traffic: Option<TrafficCounters>) {
+        self.update(traffic);
+        self.previous = self.previous + self.session;
+        self.session = TrafficCounters::default();
+    }
+
+    /// Traffic through the current tunnel.
+    pub fn session(&self) -> TrafficCounters {
+        self.session
+    }
+
+    /// Traffic through all tunnels, including the current one.
+    pub fn cumulative(&self) -> TrafficCounters {
+        self.previous + self.session
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn counters(tx_bytes: u64, rx_bytes: u64) -> TrafficCounters {
+        TrafficCounters { tx_bytes, rx_bytes }
+    }
+
+    #[test]
+    fn test_session_traffic() {
+        let mut accounting = TrafficAccounting::default();
+        assert_eq!(accounting.session(), TrafficCounters::default());
+
+        accounting.update(Some(counters(10, 20)));
+        accounting.update(Some(counters(15, 30)));
+        assert_eq!(accounting.session(), counters(15, 30));
+        assert_eq!(accounting.cumulative(), counters(15, 30));
+
+        // An unreadable tunnel keeps the last known counters
+        accounting.update(None);
+        assert_eq!(accounting.session(), counters(15, 30));
+    }
+
+    #[test]
+    fn test_end_session() {
+        let mut accounting = TrafficAccounting::default();
+        accounting.update(Some(counters(10, 20)));
+        accounting.end_session(Some(counters(11, 22)));
+        assert_eq!(accounting.session(), TrafficCounters::default());
+        assert_eq!(accounting.cumulative(), counters(11, 22));
+
+        accounting.update(Some(counters(1, 2)));
+        assert_eq!(accounting.session(), counters(1, 2));
+        assert_eq!(accounting.cumulative(), counters(12, 24));
+
+        // The tunnel could not be read while closing, so its last known traffic is counted
+        accounting.end_session(None);
+        assert_eq!(accounting.cumulative(), counters(12, 24));
+
+        // Ending a session without a tunnel does not count anything twice
+        accounting.end_session(None);
+        assert_eq!(accounting.cumulative(), counters(12, 24));
+    }
+
+    #[test]
+    fn test_counters_saturate() {
+        let mut accounting = TrafficAccounting::default();
+        accounting.end_session(Some(counters(u64::MAX, 1)));
+        accounting.update(Some(counters(1, 1)));
+        assert_eq!(accounting.cumulative(), counters(u64::MAX, 2));
+    }
+}
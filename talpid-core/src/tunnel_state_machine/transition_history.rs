@@ -0,0 +1,115 @@
+//! Keeps a record of the most recent state transitions and of the events that caused them, so
+//! that a state machine that appears to be stuck can be inspected without a debug build.
+
+use super::{EventResult, TunnelCommand};
+use crate::tunnel::TunnelEvent;
+use std::{collections::VecDeque, time::SystemTime};
+use talpid_types::tunnel::{
+    ActionAfterDisconnect, StateTransitionDebugInfo, TunnelStateTransition,
+};
+
+/// Number of transitions that are remembered.
+const MAX_TRANSITIONS: usize = 20;
+
+/// The current state and the transitions that led to it.
+pub struct TransitionHistory {
+    state: &'static str,
+    state_entered: SystemTime,
+    trigger: Option<String>,
+    transitions: VecDeque<StateTransitionDebugInfo>,
+}
+
+impl TransitionHistory {
+    /// Creates a history that starts in the state `state`.
+    pub fn new(state: &'static str) -> Self {
+        Self {
+            state,
+            state_entered: SystemTime::now(),
+            trigger: None,
+            transitions: VecDeque::with_capacity(MAX_TRANSITIONS),
+        }
+    }
+
+    /// Name of the current state.
+    pub fn state(&self) -> &'static str {
+        self.state
+    }
+
+    /// When the current state was entered.
+    pub fn state_entered(&self) -> SystemTime {
+        self.state_entered
+    }
+
+    /// The recorded transitions, oldest first.
+    pub fn transitions(&self) -> Vec<StateTransitionDebugInfo> {
+        self.transitions.iter().cloned().collect()
+    }
+
+    /// Remembers the event that is being handled, so that it can be blamed for any transition
+    /// that follows.
+    pub fn set_trigger(&mut self, trigger: String) {
+        self.trigger = Some(trigger);
+    }
+
+    /// Records a transition to the state described by `transition`.
+    pub fn record(&mut self, transition: &TunnelStateTransition) {
+        let (state, details) = describe_transition(transition);
+        let now = SystemTime::now();
+
+        if self.transitions.len() >= MAX_TRANSITIONS {
+            self.transitions.pop_front();
+        }
+        self.transitions.push_back(StateTransitionDebugInfo {
+            time: now,
+            from: self.state.to_owned(),
+            to: state.to_owned(),
+            details,
+            trigger: self.trigger.take().unwrap_or_else(|| "unknown".to_owned()),
+        });
+
+        self.state = state;
+        self.state_entered = now;
+    }
+}
+
+fn describe_transition(transition: &TunnelStateTransition) -> (&'static str, Option<String>) {
+    match transition {
+        TunnelStateTransition::Disconnected => ("disconnected", None),
+        TunnelStateTransition::Connecting(endpoint) => ("connecting", Some(endpoint.to_string())),
+        TunnelStateTransition::Connected(endpoint) => ("connected", Some(endpoint.to_string())),
+        TunnelStateTransition::Disconnecting(action) => {
+            let action = match action {
+                ActionAfterDisconnect::Nothing => "nothing",
+                ActionAfterDisconnect::Block => "block",
+                ActionAfterDisconnect::Reconnect => "reconnect",
+            };
+            ("disconnecting", Some(format!("then {}", action)))
+        }
+        TunnelStateTransition::Error(error_state) => {
+            ("error", Some(error_state.cause().to_string()))
+        }
+    }
+}
+
+/// Describes an event received by one of the states.
+pub fn describe_event(event: &EventResult) -> String {
+    match event {
+        EventResult::Command(command) => describe_command(command.as_ref()),
+        EventResult::Event(Some((TunnelEvent::WireguardEvent(event), _))) => {
+            format!("tunnel event: {}", event)
+        }
+        EventResult::Event(Some((event, _))) => format!("tunnel event: {}", event.name()),
+        EventResult::Event(None) => "tunnel event stream ended".to_owned(),
+        EventResult::Close(Ok(Some(cause))) => format!("tunnel closed: {}", cause),
+        EventResult::Close(Ok(None)) => "tunnel closed".to_owned(),
+        EventResult::Close(Err(_)) => "tunnel monitor stopped unexpectedly".to_owned(),
+    }
+}
+
+/// Describes a command received by one of the states.
+pub fn describe_command(command: Option<&TunnelCommand>) -> String {
+    match command {
+        Some(command) => format!("command: {}", command.name()),
+        None => "command channel closed".to_owned(),
+    }
+}
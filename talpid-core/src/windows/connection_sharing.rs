@@ -0,0 +1,49 @@
+//! Detection of Internet Connection Sharing (ICS) and Mobile Hotspot.
+//!
+//! Both features are implemented by the SharedAccess service, which assigns a fixed address to
+//! the private (shared) interface and acts as DHCP server, DNS proxy and NAT for the clients on
+//! that network.
+
+use ipnetwork::Ipv4Network;
+use std::{io, net::Ipv4Addr};
+use talpid_windows_net::{get_unicast_addresses, AddressFamily};
+use winreg::{enums::HKEY_LOCAL_MACHINE, RegKey};
+
+const SHARED_ACCESS_PARAMETERS_KEY: &str =
+    r#"SYSTEM\CurrentControlSet\Services\SharedAccess\Parameters"#;
+const SCOPE_ADDRESS_VALUE: &str = "ScopeAddress";
+
+/// Address used by the SharedAccess service unless `ScopeAddress` has been overridden.
+const DEFAULT_SCOPE_ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 168, 137, 1);
+/// The SharedAccess service always hands out addresses from a /24 network.
+const SHARED_NETWORK_PREFIX: u8 = 24;
+
+/// Returns the address that the host is assigned on the shared network.
+pub fn scope_address() -> Ipv4Addr {
+    RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey(SHARED_ACCESS_PARAMETERS_KEY)
+        .and_then(|params| params.get_value::<String, _>(SCOPE_ADDRESS_VALUE))
+        .ok()
+        .and_then(|address| match address.trim().parse() {
+            Ok(address) => Some(address),
+            Err(_) => {
+                log::warn!("Ignoring invalid ICS scope address: {}", address);
+                None
+            }
+        })
+        .unwrap_or(DEFAULT_SCOPE_ADDRESS)
+}
+
+/// Returns the network that is shared with clients when ICS or Mobile Hotspot is enabled.
+pub fn shared_network() -> Ipv4Network {
+    Ipv4Network::new(scope_address(), SHARED_NETWORK_PREFIX).expect("prefix is always valid")
+}
+
+/// Returns whether ICS or Mobile Hotspot is currently sharing a connection, determined by
+/// whether any interface has been assigned the scope address.
+pub fn is_connection_sharing_active() -> io::Result<bool> {
+    let scope_address = scope_address();
+    Ok(get_unicast_addresses(Some(AddressFamily::Ipv4))?
+        .into_iter()
+        .any(|unicast| unicast.address == scope_address))
+}
@@ -0,0 +1,105 @@
+//! Network profiles are created by Windows for every network that an adapter is connected to.
+//! Since a new tunnel adapter is considered a new network, stale profiles named "Mullvad 2",
+//! "Mullvad 3", etc. accumulate when adapters are not removed properly. The tunnel adapters are
+//! created with fixed GUIDs, so that they keep using the same profile once the duplicates are
+//! gone.
+
+use std::io;
+use winreg::{
+    enums::{HKEY_LOCAL_MACHINE, KEY_READ, KEY_WRITE},
+    RegKey,
+};
+
+const PROFILES_KEY: &str = r"SOFTWARE\Microsoft\Windows NT\CurrentVersion\NetworkList\Profiles";
+const SIGNATURES_KEY: &str =
+    r"SOFTWARE\Microsoft\Windows NT\CurrentVersion\NetworkList\Signatures\Unmanaged";
+
+/// Removes the numbered duplicates of the network profile of adapters with the given alias, as
+/// well as the signatures that refer to them. The profile named after the alias itself is kept so
+/// that its category is preserved. Returns the names of the removed profiles.
+pub fn remove_duplicate_profiles(alias: &str) -> io::Result<Vec<String>> {
+    let profiles = RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey_with_flags(PROFILES_KEY, KEY_READ | KEY_WRITE)?;
+
+    let mut removed_guids = vec![];
+    let mut removed_names = vec![];
+
+    for profile_guid in profiles.enum_keys().filter_map(Result::ok) {
+        let profile_name: String = match profiles
+            .open_subkey(&profile_guid)
+            .and_then(|profile| profile.get_value("ProfileName"))
+        {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if profile_name == alias || !is_adapter_profile_name(alias, &profile_name) {
+            continue;
+        }
+        match profiles.delete_subkey_all(&profile_guid) {
+            Ok(()) => {
+                removed_guids.push(profile_guid.to_lowercase());
+                removed_names.push(profile_name);
+            }
+            Err(error) => log::error!(
+                "Failed to remove network profile \"{}\": {}",
+                profile_name,
+                error
+            ),
+        }
+    }
+
+    if !removed_guids.is_empty() {
+        if let Err(error) = remove_signatures(&removed_guids) {
+            log::error!("Failed to remove network signatures: {}", error);
+        }
+    }
+
+    Ok(removed_names)
+}
+
+fn remove_signatures(profile_guids: &[String]) -> io::Result<()> {
+    let signatures = RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey_with_flags(SIGNATURES_KEY, KEY_READ | KEY_WRITE)?;
+
+    for signature in signatures.enum_keys().filter_map(Result::ok) {
+        let profile_guid: String = match signatures
+            .open_subkey(&signature)
+            .and_then(|signature| signature.get_value("ProfileGuid"))
+        {
+            Ok(guid) => guid,
+            Err(_) => continue,
+        };
+        if profile_guids.contains(&profile_guid.to_lowercase()) {
+            signatures.delete_subkey_all(&signature)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns whether `name` is `alias` or `alias` followed by a number, which is how Windows names
+/// the profiles of networks that share a name.
+fn is_adapter_profile_name(alias: &str, name: &str) -> bool {
+    match name.strip_prefix(alias) {
+        Some("") => true,
+        Some(suffix) => suffix
+            .strip_prefix(' ')
+            .map(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+            .unwrap_or(false),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_adapter_profile_name;
+
+    #[test]
+    fn test_adapter_profile_name() {
+        assert!(is_adapter_profile_name("Mullvad", "Mullvad"));
+        assert!(is_adapter_profile_name("Mullvad", "Mullvad 12"));
+        assert!(!is_adapter_profile_name("Mullvad", "Mullvad VPN"));
+        assert!(!is_adapter_profile_name("Mullvad", "Mullvad "));
+        assert!(!is_adapter_profile_name("Mullvad", "Network 2"));
+    }
+}
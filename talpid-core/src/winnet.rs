@@ -47,6 +47,10 @@ pub enum Error {
     /// Failed to read IPv6 status on the TAP network interface.
     #[error(display = "Failed to read IPv6 status on the TAP network interface")]
     GetIpv6Status,
+
+    /// Failed to keep the category of the tunnel network set to "Public".
+    #[error(display = "Failed to pin the category of the tunnel network")]
+    PinNetworkCategory,
 }
 
 fn logging_context() -> *const u8 {
@@ -415,6 +419,42 @@ pub fn add_device_ip_addresses(iface: &String, addresses: &Vec<IpAddr>) -> bool
     }
 }
 
+/// Keeps the category of the network that an adapter is connected to set to "Public" until it is
+/// dropped.
+pub struct PublicNetworkCategoryPin(*mut c_void);
+
+unsafe impl Send for PublicNetworkCategoryPin {}
+
+impl Drop for PublicNetworkCategoryPin {
+    fn drop(&mut self) {
+        unsafe { WinNet_UnpinPublicNetworkCategory(self.0) };
+    }
+}
+
+/// Sets the category of the network that the adapter with the given alias is connected to to
+/// "Public", and keeps it that way while the returned pin is alive. This keeps Windows from asking
+/// whether the network should be discoverable.
+pub fn pin_public_network_category(
+    interface_alias: &str,
+) -> Result<PublicNetworkCategoryPin, Error> {
+    let interface_alias =
+        WideCString::from_str(interface_alias).map_err(Error::InvalidInterfaceAlias)?;
+    let mut handle = ptr::null_mut();
+    let pinned = unsafe {
+        WinNet_PinPublicNetworkCategory(
+            interface_alias.as_ptr(),
+            Some(log_sink),
+            logging_context(),
+            &mut handle,
+        )
+    };
+    if pinned {
+        Ok(PublicNetworkCategoryPin(handle))
+    } else {
+        Err(Error::PinNetworkCategory)
+    }
+}
+
 #[allow(non_snake_case)]
 mod api {
     use super::DefaultRouteChangedCallback;
@@ -506,5 +546,16 @@ mod api {
             sink: Option<LogSink>,
             sink_context: *const u8,
         ) -> bool;
+
+        #[link_name = "WinNet_PinPublicNetworkCategory"]
+        pub fn WinNet_PinPublicNetworkCategory(
+            interface_alias: *const wchar_t,
+            sink: Option<LogSink>,
+            sink_context: *const u8,
+            pin_handle: *mut *mut libc::c_void,
+        ) -> bool;
+
+        #[link_name = "WinNet_UnpinPublicNetworkCategory"]
+        pub fn WinNet_UnpinPublicNetworkCategory(pin_handle: *mut libc::c_void);
     }
 }
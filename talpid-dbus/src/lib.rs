@@ -3,6 +3,7 @@
 pub use dbus;
 use dbus::blocking::SyncConnection;
 use std::sync::{Arc, Mutex};
+pub mod logind;
 pub mod network_manager;
 pub mod systemd_resolved;
 
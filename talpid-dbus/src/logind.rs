@@ -0,0 +1,92 @@
+use dbus::{
+    arg::OwnedFd,
+    blocking::{Proxy, SyncConnection},
+    message::MatchRule,
+};
+use std::{sync::Arc, time::Duration};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to initialize a connection to D-Bus")]
+    ConnectDBus(#[error(source)] dbus::Error),
+
+    #[error(display = "Failed to take an inhibitor lock")]
+    InhibitError(#[error(source)] dbus::Error),
+
+    #[error(display = "Failed to add a match to listen for sleep signals")]
+    SleepMatchError(#[error(source)] dbus::Error),
+
+    #[error(display = "Failed to remove a match for sleep signals")]
+    SleepRemoveMatchError(#[error(source)] dbus::Error),
+}
+
+const LOGIND_BUS: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const INHIBIT_METHOD: &str = "Inhibit";
+const PREPARE_FOR_SLEEP_SIGNAL: &str = "PrepareForSleep";
+
+const RPC_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Listens for the machine going to sleep and waking up using systemd-logind.
+pub struct Logind {
+    dbus_connection: Arc<SyncConnection>,
+}
+
+impl Logind {
+    /// Creates a new connection that is used to listen for sleep signals.
+    pub fn new_connection() -> Result<Self> {
+        let dbus_connection = SyncConnection::new_system().map_err(Error::ConnectDBus)?;
+        Ok(Logind {
+            dbus_connection: Arc::new(dbus_connection),
+        })
+    }
+
+    /// Takes a delay inhibitor lock for sleep. logind postpones sleep until the lock is released,
+    /// by closing the returned file descriptor, or until `InhibitDelayMaxSec` has passed.
+    pub fn inhibit_sleep(who: &str, why: &str) -> Result<OwnedFd> {
+        let connection = crate::get_connection().map_err(Error::ConnectDBus)?;
+        let (fd,): (OwnedFd,) = Proxy::new(LOGIND_BUS, LOGIND_PATH, RPC_TIMEOUT, &*connection)
+            .method_call(
+                MANAGER_INTERFACE,
+                INHIBIT_METHOD,
+                ("sleep", who, why, "delay"),
+            )
+            .map_err(Error::InhibitError)?;
+        Ok(fd)
+    }
+
+    /// Calls `callback` with `true` when the machine is about to sleep, and with `false` when it
+    /// has woken up. Blocks until `should_continue` returns false.
+    pub fn watch_sleep<F: FnMut(bool) + Send + Sync + 'static, S: Fn() -> bool>(
+        &mut self,
+        mut callback: F,
+        should_continue: S,
+    ) -> Result<()> {
+        let mut match_rule = MatchRule::new_signal(MANAGER_INTERFACE, PREPARE_FOR_SLEEP_SIGNAL);
+        match_rule.path = Some(LOGIND_PATH.into());
+        let sleep_matcher = self
+            .dbus_connection
+            .add_match(
+                match_rule,
+                move |(sleeping,): (bool,), _connection, _message| {
+                    callback(sleeping);
+                    true
+                },
+            )
+            .map_err(Error::SleepMatchError)?;
+
+        while should_continue() {
+            if let Err(err) = self.dbus_connection.process(RPC_TIMEOUT) {
+                log::error!("Failed to process DBus messages: {}", err);
+            }
+        }
+
+        self.dbus_connection
+            .remove_match(sleep_matcher)
+            .map_err(Error::SleepRemoveMatchError)
+    }
+}
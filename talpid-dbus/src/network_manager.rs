@@ -3,8 +3,11 @@ use super::systemd_resolved;
 pub use dbus::arg::{RefArg, Variant};
 use dbus::{
     arg,
-    blocking::{stdintf::org_freedesktop_dbus::Properties, Proxy, SyncConnection},
-    message::MatchRule,
+    blocking::{
+        stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged},
+        Proxy, SyncConnection,
+    },
+    message::{MatchRule, SignalArgs},
 };
 use std::{
     collections::HashMap,
@@ -23,10 +26,15 @@ const NM_BUS: &str = "org.freedesktop.NetworkManager";
 const NM_MANAGER: &str = "org.freedesktop.NetworkManager";
 const NM_MANAGER_PATH: &str = "/org/freedesktop/NetworkManager";
 const CONNECTIVITY_CHECK_KEY: &str = "ConnectivityCheckEnabled";
+const CONNECTIVITY_KEY: &str = "Connectivity";
+const DEVICE_MANAGED_KEY: &str = "Managed";
 
 const NM_DNS_MANAGER: &str = "org.freedesktop.NetworkManager.DnsManager";
 const NM_DNS_MANAGER_PATH: &str = "/org/freedesktop/NetworkManager/DnsManager";
 const NM_DEVICE: &str = "org.freedesktop.NetworkManager.Device";
+const NM_DEVICE_WIRELESS: &str = "org.freedesktop.NetworkManager.Device.Wireless";
+const NM_ACCESS_POINT: &str = "org.freedesktop.NetworkManager.AccessPoint";
+const NM_DEVICE_TYPE_WIFI: u32 = 2;
 
 const NM_IP4_CONFIG: &str = "org.freedesktop.NetworkManager.IP4Config";
 const NM_IP6_CONFIG: &str = "org.freedesktop.NetworkManager.IP6Config";
@@ -113,6 +121,33 @@ pub type VariantMap = HashMap<String, VariantRefArg>;
 // settings are a{sa{sv}}
 pub type DeviceConfig = HashMap<String, VariantMap>;
 
+/// Connectivity of the host as reported by NetworkManager.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    /// The connectivity is not known, for example because connectivity checking is disabled.
+    Unknown,
+    /// The host is not connected to any network.
+    None,
+    /// The host is behind a captive portal.
+    Portal,
+    /// The host is connected to a network but cannot reach the internet.
+    Limited,
+    /// The host can reach the internet.
+    Full,
+}
+
+impl Connectivity {
+    fn from_raw(value: u32) -> Self {
+        match value {
+            1 => Connectivity::None,
+            2 => Connectivity::Portal,
+            3 => Connectivity::Limited,
+            4 => Connectivity::Full,
+            _ => Connectivity::Unknown,
+        }
+    }
+}
+
 /// Implements functionality to control NetworkManager over DBus.
 pub struct NetworkManager {
     connection: Arc<SyncConnection>,
@@ -125,6 +160,120 @@ impl NetworkManager {
         })
     }
 
+    /// Creates a new connection that is used to listen for connectivity changes.
+    pub fn new_connection() -> Result<Self> {
+        Ok(Self {
+            connection: Arc::new(SyncConnection::new_system()?),
+        })
+    }
+
+    /// Returns the connectivity of the host as NetworkManager sees it.
+    pub fn connectivity(&self) -> Result<Connectivity> {
+        let connectivity: u32 = self
+            .as_manager()
+            .get(NM_MANAGER, CONNECTIVITY_KEY)
+            .map_err(Error::Dbus)?;
+        Ok(Connectivity::from_raw(connectivity))
+    }
+
+    /// Calls `callback` whenever the connectivity reported by NetworkManager changes. Blocks
+    /// until `should_continue` returns false.
+    pub fn watch_connectivity<F: FnMut(Connectivity) + Send + Sync + 'static, S: Fn() -> bool>(
+        &mut self,
+        mut callback: F,
+        should_continue: S,
+    ) -> Result<()> {
+        let mut match_rule = MatchRule::new_signal(
+            PropertiesPropertiesChanged::INTERFACE,
+            PropertiesPropertiesChanged::NAME,
+        );
+        match_rule.path = Some(NM_MANAGER_PATH.into());
+        let connectivity_matcher = self.connection.add_match(
+            match_rule,
+            move |prop_changed: PropertiesPropertiesChanged, _connection, _message| {
+                if prop_changed.interface_name == NM_MANAGER {
+                    if let Some(connectivity) = prop_changed
+                        .changed_properties
+                        .get(CONNECTIVITY_KEY)
+                        .and_then(|value| value.0.as_u64())
+                    {
+                        callback(Connectivity::from_raw(connectivity as u32));
+                    }
+                }
+                true
+            },
+        )?;
+
+        while should_continue() {
+            if let Err(err) = self.connection.process(RPC_TIMEOUT) {
+                log::error!("Failed to process DBus messages: {}", err);
+            }
+        }
+
+        self.connection.remove_match(connectivity_matcher)?;
+        Ok(())
+    }
+
+    /// Calls `callback` whenever NetworkManager reports a change to its devices, access points or
+    /// IP configurations. Blocks until `should_continue` returns false.
+    pub fn watch_network_changes<F: FnMut() + Send + Sync + 'static, S: Fn() -> bool>(
+        &mut self,
+        mut callback: F,
+        should_continue: S,
+    ) -> Result<()> {
+        let mut match_rule = MatchRule::new_signal(
+            PropertiesPropertiesChanged::INTERFACE,
+            PropertiesPropertiesChanged::NAME,
+        );
+        match_rule.sender = Some(NM_BUS.into());
+        match_rule.path = Some(NM_MANAGER_PATH.into());
+        match_rule.path_is_namespace = true;
+        let network_matcher = self.connection.add_match(
+            match_rule,
+            move |prop_changed: PropertiesPropertiesChanged, _connection, _message| {
+                let interface = prop_changed.interface_name.as_str();
+                if [
+                    NM_MANAGER,
+                    NM_DEVICE,
+                    NM_DEVICE_WIRELESS,
+                    NM_IP4_CONFIG,
+                    NM_IP6_CONFIG,
+                ]
+                .contains(&interface)
+                {
+                    callback();
+                }
+                true
+            },
+        )?;
+
+        while should_continue() {
+            if let Err(err) = self.connection.process(RPC_TIMEOUT) {
+                log::error!("Failed to process DBus messages: {}", err);
+            }
+        }
+
+        self.connection.remove_match(network_matcher)?;
+        Ok(())
+    }
+
+    /// Sets whether NetworkManager should manage a device. NetworkManager does not configure
+    /// unmanaged devices, and does not push any DNS config for them. Returns whether the device
+    /// was managed before.
+    pub fn set_device_managed(&self, interface_name: &str, managed: bool) -> Result<bool> {
+        let device_path = self.fetch_device(interface_name)?;
+        let device = self.as_path(&device_path);
+        let was_managed: bool = device
+            .get(NM_DEVICE, DEVICE_MANAGED_KEY)
+            .map_err(Error::Dbus)?;
+        if was_managed != managed {
+            device
+                .set(NM_DEVICE, DEVICE_MANAGED_KEY, managed)
+                .map_err(Error::Dbus)?;
+        }
+        Ok(was_managed)
+    }
+
     pub fn create_wg_tunnel(&self, config: &DeviceConfig) -> Result<WireguardTunnel> {
         self.nm_supports_wireguard()?;
         let tunnel = self.create_wg_tunnel_inner(config)?;
@@ -636,6 +785,52 @@ impl NetworkManager {
         Err(Error::DeviceNotFound)
     }
 
+    /// Returns the SSIDs of the Wi-Fi networks that the host is currently connected to.
+    pub fn wifi_ssids(&self) -> Result<Vec<String>> {
+        let devices: Box<dyn RefArg> = self
+            .as_manager()
+            .get(NM_MANAGER, "Devices")
+            .map_err(Error::Dbus)?;
+        let devices = devices
+            .as_iter()
+            .ok_or(Error::ObtainDevices)?
+            .map(|device| device.box_clone())
+            .collect::<Vec<_>>();
+
+        let mut ssids = vec![];
+        for device_item in devices {
+            let device_path = device_item
+                .as_any()
+                .downcast_ref::<dbus::Path<'_>>()
+                .ok_or(Error::ObtainDevices)?;
+
+            let device_type: u32 = self
+                .as_path(device_path)
+                .get(NM_DEVICE, "DeviceType")
+                .map_err(Error::Dbus)?;
+            if device_type != NM_DEVICE_TYPE_WIFI {
+                continue;
+            }
+
+            let access_point: Box<dyn RefArg> = self
+                .as_path(device_path)
+                .get(NM_DEVICE_WIRELESS, "ActiveAccessPoint")
+                .map_err(Error::Dbus)?;
+            // The root path is used when the device is not connected
+            let access_point = match access_point.as_any().downcast_ref::<dbus::Path<'_>>() {
+                Some(path) if &**path != "/" => path.clone(),
+                _ => continue,
+            };
+
+            let ssid: Vec<u8> = self
+                .as_path(&access_point)
+                .get(NM_ACCESS_POINT, "Ssid")
+                .map_err(Error::Dbus)?;
+            ssids.push(String::from_utf8_lossy(&ssid).into_owned());
+        }
+        Ok(ssids)
+    }
+
     pub fn convert_address_to_dbus(address: &IpAddr) -> VariantMap {
         let mut map: VariantMap = HashMap::new();
         map.insert(
@@ -742,4 +937,12 @@ mod test {
         assert!(NetworkManager::ensure_nm_is_new_enough_for_wireguard(1, 14).is_err());
         assert!(NetworkManager::ensure_nm_is_old_enough_for_dns(1, 28).is_err());
     }
+
+    #[test]
+    fn test_connectivity_from_raw() {
+        assert_eq!(Connectivity::from_raw(0), Connectivity::Unknown);
+        assert_eq!(Connectivity::from_raw(1), Connectivity::None);
+        assert_eq!(Connectivity::from_raw(4), Connectivity::Full);
+        assert_eq!(Connectivity::from_raw(42), Connectivity::Unknown);
+    }
 }
@@ -46,6 +46,9 @@ pub enum Error {
     #[error(display = "Failed to replace DNS settings")]
     ReplaceDnsError,
 
+    #[error(display = "systemd-resolved does not support DNS-over-TLS with a server name")]
+    DnsOverTlsUnsupported,
+
     #[error(display = "Failed to perform RPC call on D-Bus")]
     DBusRpcError(#[error(source)] dbus::Error),
 
@@ -82,10 +85,21 @@ const DNS_DOMAINS: &str = "Domains";
 const DNS_SERVERS: &str = "DNS";
 const GET_LINK_METHOD: &str = "GetLink";
 const SET_DNS_METHOD: &str = "SetDNS";
+const SET_DNS_EX_METHOD: &str = "SetDNSEx";
 const SET_DNS_OVER_TLS_METHOD: &str = "SetDNSOverTLS";
 const SET_DOMAINS_METHOD: &str = "SetDomains";
+const SET_DEFAULT_ROUTE_METHOD: &str = "SetDefaultRoute";
+const SET_LLMNR_METHOD: &str = "SetLLMNR";
+const SET_MULTICAST_DNS_METHOD: &str = "SetMulticastDNS";
+const SET_DNSSEC_METHOD: &str = "SetDNSSEC";
 const REVERT_METHOD: &str = "Revert";
 
+const UNKNOWN_METHOD_ERROR: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+const UNKNOWN_OBJECT_ERROR: &str = "org.freedesktop.DBus.Error.UnknownObject";
+const NO_SUCH_LINK_ERROR: &str = "org.freedesktop.resolve1.NoSuchLink";
+
+const DNS_OVER_TLS_PORT: u16 = 853;
+
 #[derive(Clone)]
 pub struct SystemdResolved {
     pub dbus_connection: Arc<SyncConnection>,
@@ -271,6 +285,26 @@ impl SystemdResolved {
         })
     }
 
+    /// Sets the DNS servers of a link and requires that they are reached over TLS. The
+    /// certificate presented by a server must be valid for its server name, if one is given.
+    pub fn set_dns_over_tls(
+        &self,
+        interface_index: u32,
+        servers: Vec<(IpAddr, Option<String>)>,
+    ) -> Result<DnsState> {
+        let link_object_path = self
+            .fetch_link(interface_index)
+            .map_err(|e| Error::GetLinkError(Box::new(e)))?;
+        self.set_link_dns(&link_object_path, &[])?;
+        self.set_link_dns_ex(&link_object_path, &servers)?;
+        self.set_link_dns_over_tls_mode(&link_object_path, "yes")?;
+        Ok(DnsState {
+            interface_path: link_object_path,
+            interface_index,
+            set_servers: servers.into_iter().map(|(server, _)| server).collect(),
+        })
+    }
+
     pub fn get_domains(&self, interface_index: u32) -> Result<Vec<(String, bool)>> {
         let link_object_path = self
             .fetch_link(interface_index)
@@ -349,6 +383,35 @@ impl SystemdResolved {
             .map_err(Error::DBusRpcError)
     }
 
+    fn set_link_dns_ex<'a, 'b: 'a>(
+        &'a self,
+        link_object_path: &'b dbus::Path<'static>,
+        servers: &[(IpAddr, Option<String>)],
+    ) -> Result<()> {
+        let servers = servers.iter().map(dns_ex_server).collect::<Vec<_>>();
+        self.as_link_object(link_object_path.clone())
+            .method_call(LINK_INTERFACE, SET_DNS_EX_METHOD, (servers,))
+            .map_err(Self::map_dns_over_tls_error)
+    }
+
+    fn set_link_dns_over_tls_mode<'a, 'b: 'a>(
+        &'a self,
+        link_object_path: &'b dbus::Path<'static>,
+        mode: &str,
+    ) -> Result<()> {
+        self.as_link_object(link_object_path.clone())
+            .method_call(LINK_INTERFACE, SET_DNS_OVER_TLS_METHOD, (mode,))
+            .map_err(Self::map_dns_over_tls_error)
+    }
+
+    fn map_dns_over_tls_error(error: dbus::Error) -> Error {
+        if error.name() == Some(UNKNOWN_METHOD_ERROR) {
+            Error::DnsOverTlsUnsupported
+        } else {
+            Error::DBusRpcError(error)
+        }
+    }
+
     fn link_disable_dns_over_tls<'a, 'b: 'a>(&'a self, interface_index: u32) -> Result<()> {
         let link_object_path = self
             .fetch_link(interface_index)
@@ -358,7 +421,7 @@ impl SystemdResolved {
 
         link_object.method_call(LINK_INTERFACE, SET_DNS_OVER_TLS_METHOD, ("no",))
             .or_else(|error| {
-            if error.name() == Some("org.freedesktop.DBus.Error.UnknownMethod") {
+            if error.name() == Some(UNKNOWN_METHOD_ERROR) {
                 log::debug!(
                     "Didn't disable DNSOverTLS because systemd-resolved doesn't have 'SetDnsOverTLS' method. {}",
                     error);
@@ -380,11 +443,68 @@ impl SystemdResolved {
         Ok(domains)
     }
 
+    /// Makes a link the default route for all DNS queries and disables link-local name
+    /// resolution on it. The DNSSEC mode of the link is set to follow the global setting, so that
+    /// a mode left behind by another program does not apply to the tunnel. Settings that are not
+    /// supported by the running version of systemd-resolved are skipped.
+    pub fn set_exclusive_link_options(&self, interface_index: u32) -> Result<()> {
+        let link_object_path = self
+            .fetch_link(interface_index)
+            .map_err(|e| Error::GetLinkError(Box::new(e)))?;
+        let link_object = self.as_link_object(link_object_path);
+
+        Self::call_optional_link_method(&link_object, SET_DEFAULT_ROUTE_METHOD, (true,))?;
+        Self::call_optional_link_method(&link_object, SET_LLMNR_METHOD, ("no",))?;
+        Self::call_optional_link_method(&link_object, SET_MULTICAST_DNS_METHOD, ("no",))?;
+        Self::call_optional_link_method(&link_object, SET_DNSSEC_METHOD, ("",))
+    }
+
+    /// Calls a link method that may be missing in older versions of systemd-resolved.
+    fn call_optional_link_method<A: arg::AppendAll>(
+        link_object: &Proxy<'_, &SyncConnection>,
+        method: &str,
+        args: A,
+    ) -> Result<()> {
+        match link_object.method_call::<(), _, _, _>(LINK_INTERFACE, method, args) {
+            Ok(()) => Ok(()),
+            Err(error) if error.name() == Some(UNKNOWN_METHOD_ERROR) => {
+                log::debug!("systemd-resolved does not support {}", method);
+                Ok(())
+            }
+            Err(error) => Err(Error::DBusRpcError(error)),
+        }
+    }
+
+    /// Reverts all DNS settings of a link, including servers, domains, DNS-over-TLS and DNSSEC,
+    /// to what systemd-resolved would use if they had never been changed. Succeeds if the link
+    /// no longer exists.
+    pub fn revert(&self, interface_index: u32) -> Result<()> {
+        let link_object_path = match self.fetch_link(interface_index) {
+            Ok(path) => path,
+            Err(Error::DBusRpcError(error)) if error.name() == Some(NO_SUCH_LINK_ERROR) => {
+                log::trace!(
+                    "Not resetting DNS of interface {} because it no longer exists",
+                    interface_index
+                );
+                return Ok(());
+            }
+            Err(error) => return Err(Error::GetLinkError(Box::new(error))),
+        };
+        let mut interface = self.clone();
+        interface
+            .revert_link(&DnsState {
+                interface_path: link_object_path,
+                interface_index,
+                set_servers: vec![],
+            })
+            .map_err(|error| Error::RevertDnsError(interface_index.to_string(), error))
+    }
+
     pub fn revert_link(&mut self, dns_state: &DnsState) -> std::result::Result<(), dbus::Error> {
         let link = self.as_link_object(dns_state.interface_path.clone());
 
         if let Err(error) = link.method_call::<(), _, _, _>(LINK_INTERFACE, REVERT_METHOD, ()) {
-            if error.name() == Some("org.freedesktop.DBus.Error.UnknownObject") {
+            if error.name() == Some(UNKNOWN_OBJECT_ERROR) {
                 log::trace!(
                     "Not resetting DNS of interface {} because it no longer exists",
                     dns_state.interface_index
@@ -504,6 +624,17 @@ fn ip_version(address: &IpAddr) -> i32 {
     }
 }
 
+/// Converts a server to the argument format of `SetDNSEx`, where an empty server name means that
+/// the certificate is not checked against a name.
+fn dns_ex_server((address, server_name): &(IpAddr, Option<String>)) -> (i32, Vec<u8>, u16, String) {
+    (
+        ip_version(address),
+        ip_to_bytes(address),
+        DNS_OVER_TLS_PORT,
+        server_name.clone().unwrap_or_default(),
+    )
+}
+
 fn ip_to_bytes(address: &IpAddr) -> Vec<u8> {
     match address {
         IpAddr::V4(v4_address) => v4_address.octets().to_vec(),
@@ -553,6 +684,17 @@ impl AsyncHandle {
             .map_err(Error::AsyncTaskError)?
     }
 
+    pub async fn set_dns_over_tls(
+        &self,
+        interface_index: u32,
+        servers: Vec<(IpAddr, Option<String>)>,
+    ) -> Result<DnsState> {
+        let interface = self.dbus_interface.clone();
+        tokio::task::spawn_blocking(move || interface.set_dns_over_tls(interface_index, servers))
+            .await
+            .map_err(Error::AsyncTaskError)?
+    }
+
     pub async fn disable_dot(&self, interface_index: u32) -> Result<()> {
         let interface = self.dbus_interface.clone();
         tokio::task::spawn_blocking(move || interface.link_disable_dns_over_tls(interface_index))
@@ -572,6 +714,20 @@ impl AsyncHandle {
             .map_err(Error::AsyncTaskError)?
     }
 
+    pub async fn set_exclusive_link_options(&self, interface_index: u32) -> Result<()> {
+        let interface = self.dbus_interface.clone();
+        tokio::task::spawn_blocking(move || interface.set_exclusive_link_options(interface_index))
+            .await
+            .map_err(Error::AsyncTaskError)?
+    }
+
+    pub async fn revert(&self, interface_index: u32) -> Result<()> {
+        let interface = self.dbus_interface.clone();
+        tokio::task::spawn_blocking(move || interface.revert(interface_index))
+            .await
+            .map_err(Error::AsyncTaskError)?
+    }
+
     pub async fn revert_link(&self, state: DnsState) -> Result<()> {
         let mut interface = self.dbus_interface.clone();
         tokio::task::spawn_blocking(move || interface.revert_link(&state))
@@ -584,3 +740,35 @@ impl AsyncHandle {
         &self.dbus_interface
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn test_dns_ex_server_name() {
+        let address = Ipv6Addr::new(0x2a07, 0xe340, 0, 0, 0, 0, 0, 2);
+        let named = (IpAddr::V6(address), Some("dns.mullvad.net".to_owned()));
+        assert_eq!(
+            dns_ex_server(&named),
+            (
+                AF_INET6,
+                address.octets().to_vec(),
+                DNS_OVER_TLS_PORT,
+                "dns.mullvad.net".to_owned()
+            )
+        );
+
+        let unnamed = (IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1)), None);
+        assert_eq!(
+            dns_ex_server(&unnamed),
+            (
+                AF_INET,
+                vec![100, 64, 0, 1],
+                DNS_OVER_TLS_PORT,
+                String::new()
+            )
+        );
+    }
+}
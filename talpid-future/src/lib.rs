@@ -0,0 +1,7 @@
+//! Helpers for futures that are shared by the talpid and mullvad crates.
+
+#![deny(missing_docs)]
+#![deny(rust_2018_idioms)]
+
+/// Retrying of futures with backoff.
+pub mod retry;
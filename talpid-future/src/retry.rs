@@ -1,5 +1,8 @@
 use rand::{distributions::OpenClosed01, Rng};
-use std::{future::Future, time::Duration};
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
 
 /// Since timers often exhibit weird behavior if they are running for too long, a workaround is
 /// required - run a timer for 60 seconds until a delay is shorter than 5 minutes.
@@ -111,6 +114,36 @@ impl Iterator for ExponentialBackoff {
     }
 }
 
+/// Ends a delay iterator once waiting for the next delay would go past a maximum time, counted
+/// from when the iterator was created. The time spent between calls to `next` also counts, so
+/// this bounds the total time spent retrying.
+pub struct MaxElapsed<I: Iterator<Item = Duration>> {
+    inner: I,
+    deadline: Option<Instant>,
+}
+
+impl<I: Iterator<Item = Duration>> MaxElapsed<I> {
+    /// Create an iterator that ends `max_elapsed` from now
+    pub fn new(inner: I, max_elapsed: Duration) -> Self {
+        Self {
+            inner,
+            deadline: Instant::now().checked_add(max_elapsed),
+        }
+    }
+}
+
+impl<I: Iterator<Item = Duration>> Iterator for MaxElapsed<I> {
+    type Item = Duration;
+    fn next(&mut self) -> Option<Self::Item> {
+        let next_value = self.inner.next()?;
+        match (self.deadline, Instant::now().checked_add(next_value)) {
+            (Some(deadline), Some(next_attempt)) if next_attempt > deadline => None,
+            (Some(_), None) => None,
+            _ => Some(next_value),
+        }
+    }
+}
+
 /// Adds jitter to a duration iterator
 pub struct Jittered<I: Iterator<Item = Duration>> {
     inner: I,
@@ -191,6 +224,20 @@ mod test {
         assert_eq!(backoff.next(), Some(zero));
     }
 
+    #[test]
+    fn test_max_elapsed() {
+        let second = Duration::from_secs(1);
+        let mut delays = MaxElapsed::new(constant_interval(second), Duration::from_secs(60 * 60));
+        assert_eq!(delays.next(), Some(second));
+        assert_eq!(delays.next(), Some(second));
+
+        let mut delays = MaxElapsed::new(constant_interval(second), Duration::ZERO);
+        assert_eq!(delays.next(), None);
+
+        let mut delays = MaxElapsed::new(std::iter::empty(), Duration::MAX);
+        assert_eq!(delays.next(), None);
+    }
+
     #[test]
     fn test_rounding() {
         let second = Duration::from_secs(1);
@@ -30,6 +30,22 @@ pub enum Error {
 
     #[error(display = "Unhandled event type: {:?}", _0)]
     UnhandledEvent(openvpn_plugin::EventType),
+
+    #[error(display = "No protocol version given as second argument")]
+    MissingProtocolVersion,
+
+    #[error(
+        display = "Protocol version mismatch. Expected {}, got {}",
+        expected,
+        actual
+    )]
+    ProtocolVersionMismatch { expected: i32, actual: String },
+
+    #[error(display = "Missing environment variable \"{}\" from OpenVPN", _0)]
+    MissingEnvVar(&'static str),
+
+    #[error(display = "Invalid environment variable \"{}\" from OpenVPN", _0)]
+    InvalidEnvVar(&'static str),
 }
 
 /// All the OpenVPN events this plugin will register for listening to. Edit this variable to change
@@ -77,6 +93,15 @@ fn parse_args(args: &[CString]) -> Result<Arguments, Error> {
     let _plugin_path = args_iter.next();
     let ipc_socket_path: String = args_iter.next().ok_or_else(|| Error::MissingCoreServerId)?;
 
+    let expected_version = processing::proto::ProtocolVersion::Current as i32;
+    let version = args_iter.next().ok_or(Error::MissingProtocolVersion)?;
+    if version.parse::<i32>().ok() != Some(expected_version) {
+        return Err(Error::ProtocolVersionMismatch {
+            expected: expected_version,
+            actual: version,
+        });
+    }
+
     Ok(Arguments { ipc_socket_path })
 }
 
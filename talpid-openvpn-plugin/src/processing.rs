@@ -1,6 +1,10 @@
 use super::{Arguments, Error};
 use parity_tokio_ipc::Endpoint as IpcEndpoint;
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
 use tower::service_fn;
 
 use tonic::{
@@ -10,7 +14,7 @@ use tonic::{
 
 use tokio::runtime::{self, Runtime};
 
-mod proto {
+pub mod proto {
     tonic::include_proto!("talpid_openvpn_plugin");
 }
 use proto::openvpn_event_proxy_client::OpenvpnEventProxyClient;
@@ -58,21 +62,126 @@ impl EventProcessor {
     ) -> Result<(), Error> {
         log::debug!("Processing \"{:?}\" event", event);
 
-        let details = proto::EventDetails { env };
-
         let response = match event {
             openvpn_plugin::EventType::AuthFailed => {
+                let details = proto::AuthFailedEvent {
+                    reason: env.get("auth_failed_reason").cloned().unwrap_or_default(),
+                };
                 self.runtime.block_on(self.ipc_client.auth_failed(details))
             }
-            openvpn_plugin::EventType::Up => self.runtime.block_on(self.ipc_client.up(details)),
+            openvpn_plugin::EventType::Up => {
+                let metadata = tunnel_metadata(&env)?;
+                self.runtime.block_on(self.ipc_client.up(metadata))
+            }
             openvpn_plugin::EventType::RouteUp => {
-                self.runtime.block_on(self.ipc_client.route_up(details))
+                let metadata = tunnel_metadata(&env)?;
+                self.runtime.block_on(self.ipc_client.route_up(metadata))
+            }
+            openvpn_plugin::EventType::RoutePredown => {
+                self.runtime.block_on(self.ipc_client.route_predown(()))
             }
-            openvpn_plugin::EventType::RoutePredown => self
-                .runtime
-                .block_on(self.ipc_client.route_predown(details)),
             other => return Err(Error::UnhandledEvent(other)),
         };
         response.map(|_| ()).map_err(Error::SendEvent)
     }
 }
+
+/// Extracts the tunnel interface, addresses and gateways from the environment of an `up` or
+/// `route-up` event.
+fn tunnel_metadata(env: &HashMap<String, String>) -> Result<proto::TunnelMetadata, Error> {
+    Ok(proto::TunnelMetadata {
+        interface: required_var(env, "dev")?.to_owned(),
+        ipv4_address: parse_ip::<Ipv4Addr>(env, "ifconfig_local")?
+            .ok_or(Error::MissingEnvVar("ifconfig_local"))?
+            .to_string(),
+        ipv6_address: optional_ip::<Ipv6Addr>(env, "ifconfig_ipv6_local")?,
+        ipv4_gateway: parse_ip::<Ipv4Addr>(env, "route_vpn_gateway")?
+            .ok_or(Error::MissingEnvVar("route_vpn_gateway"))?
+            .to_string(),
+        ipv6_gateway: optional_ip::<Ipv6Addr>(env, "route_ipv6_gateway_1")?,
+    })
+}
+
+fn required_var<'a>(
+    env: &'a HashMap<String, String>,
+    name: &'static str,
+) -> Result<&'a str, Error> {
+    env.get(name)
+        .map(String::as_str)
+        .ok_or(Error::MissingEnvVar(name))
+}
+
+fn parse_ip<T: FromStr>(
+    env: &HashMap<String, String>,
+    name: &'static str,
+) -> Result<Option<T>, Error> {
+    env.get(name)
+        .map(|value| value.parse().map_err(|_| Error::InvalidEnvVar(name)))
+        .transpose()
+}
+
+/// Returns the address in the variable `name`, or an empty string if it is not set.
+fn optional_ip<T: FromStr + ToString>(
+    env: &HashMap<String, String>,
+    name: &'static str,
+) -> Result<String, Error> {
+    Ok(parse_ip::<T>(env, name)?
+        .map(|address| address.to_string())
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn up_env() -> HashMap<String, String> {
+        [
+            ("dev", "tun0"),
+            ("ifconfig_local", "10.8.0.2"),
+            ("route_vpn_gateway", "10.8.0.1"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+    }
+
+    #[test]
+    fn test_tunnel_metadata() {
+        let mut env = up_env();
+        let metadata = tunnel_metadata(&env).unwrap();
+        assert_eq!(metadata.interface, "tun0");
+        assert_eq!(metadata.ipv4_address, "10.8.0.2");
+        assert_eq!(metadata.ipv4_gateway, "10.8.0.1");
+        assert!(metadata.ipv6_address.is_empty());
+        assert!(metadata.ipv6_gateway.is_empty());
+
+        env.insert(
+            "ifconfig_ipv6_local".to_owned(),
+            "fdda:d0d0:cafe::2".to_owned(),
+        );
+        env.insert(
+            "route_ipv6_gateway_1".to_owned(),
+            "fdda:d0d0:cafe::1".to_owned(),
+        );
+        let metadata = tunnel_metadata(&env).unwrap();
+        assert_eq!(metadata.ipv6_address, "fdda:d0d0:cafe::2");
+        assert_eq!(metadata.ipv6_gateway, "fdda:d0d0:cafe::1");
+    }
+
+    #[test]
+    fn test_invalid_tunnel_metadata() {
+        let mut env = up_env();
+        env.remove("dev");
+        assert!(matches!(
+            tunnel_metadata(&env),
+            Err(Error::MissingEnvVar("dev"))
+        ));
+
+        let mut env = up_env();
+        env.insert("ifconfig_local".to_owned(), "fdda:d0d0:cafe::2".to_owned());
+        assert!(matches!(
+            tunnel_metadata(&env),
+            Err(Error::InvalidEnvVar("ifconfig_local"))
+        ));
+    }
+}
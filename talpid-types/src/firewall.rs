@@ -0,0 +1,53 @@
+use crate::net::Endpoint;
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/// Structured description of the firewall policy that is currently being enforced. Used to audit
+/// the leak protection.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirewallPolicyDescription {
+    /// The kind of policy that is applied.
+    pub state: FirewallPolicyState,
+    /// The relay endpoint that traffic is allowed to.
+    pub peer_endpoint: Option<Endpoint>,
+    /// An endpoint that may be reached outside the tunnel, such as the API.
+    pub allowed_endpoint: Option<Endpoint>,
+    /// The interface that tunnel traffic is allowed through.
+    pub tunnel_interface: Option<String>,
+    /// Whether traffic to and from the local network is allowed.
+    pub allow_lan: bool,
+    /// Networks that are treated as the local network when LAN access is allowed.
+    pub lan_networks: Vec<IpNetwork>,
+    /// DNS servers that are allowed to receive DNS requests.
+    pub dns_servers: Vec<IpAddr>,
+    /// Networks whose traffic is allowed to bypass the tunnel.
+    pub excluded_networks: Vec<IpNetwork>,
+}
+
+/// The kind of firewall policy being enforced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FirewallPolicyState {
+    /// No policy is applied. Traffic is not restricted.
+    None,
+    /// Traffic is only allowed to the relay and the allowed endpoint.
+    Connecting,
+    /// Traffic is allowed to the relay and through the tunnel.
+    Connected,
+    /// All traffic is blocked, except to the allowed endpoint.
+    Blocked,
+}
+
+/// Local network services that are permitted while all other traffic is blocked, so that the
+/// computer stays reachable on the local network while in the blocked state.
+///
+/// DHCP and NDP are always permitted, so that address leases are renewed and neighbors and routers
+/// can be discovered. ARP is not filtered by the firewall.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockedStatePolicy {
+    /// Permit multicast DNS (mDNS) queries and responses, to and from `224.0.0.251` and `ff02::fb`
+    /// on port 5353.
+    pub allow_mdns: bool,
+}
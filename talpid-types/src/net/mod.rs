@@ -1,3 +1,4 @@
+use crate::tunnel::ObfuscatorKind;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
@@ -30,6 +31,8 @@ impl TunnelParameters {
                 endpoint: params.config.endpoint,
                 proxy: params.proxy.as_ref().map(|proxy| proxy.get_endpoint()),
                 entry_endpoint: None,
+                obfuscation: None,
+                tunnel_interface: None,
             },
             TunnelParameters::Wireguard(params) => TunnelEndpoint {
                 tunnel_type: TunnelType::Wireguard,
@@ -37,11 +40,22 @@ impl TunnelParameters {
                     .connection
                     .get_exit_endpoint()
                     .unwrap_or(params.connection.get_endpoint()),
-                proxy: None,
+                proxy: params
+                    .entry_proxy
+                    .as_ref()
+                    .map(|proxy| proxy.get_endpoint()),
                 entry_endpoint: params
                     .connection
                     .get_exit_endpoint()
                     .map(|_| params.connection.get_endpoint()),
+                obfuscation: if params.entry_proxy.is_some() {
+                    Some(ObfuscatorKind::Socks5)
+                } else if params.connection.peer.protocol == TransportProtocol::Tcp {
+                    Some(ObfuscatorKind::Udp2Tcp)
+                } else {
+                    None
+                },
+                tunnel_interface: None,
             },
         }
     }
@@ -54,7 +68,11 @@ impl TunnelParameters {
                 .as_ref()
                 .map(|proxy| proxy.get_endpoint().endpoint)
                 .unwrap_or(params.config.endpoint),
-            TunnelParameters::Wireguard(params) => params.connection.get_endpoint(),
+            TunnelParameters::Wireguard(params) => params
+                .entry_proxy
+                .as_ref()
+                .map(|proxy| proxy.get_endpoint().endpoint)
+                .unwrap_or(params.connection.get_endpoint()),
         }
     }
 
@@ -108,7 +126,7 @@ impl fmt::Display for TunnelType {
 
 /// A tunnel endpoint is broadcast during the connecting and connected states of the tunnel state
 /// machine.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[cfg_attr(target_os = "android", derive(IntoJava))]
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.talpid.net"))]
 pub struct TunnelEndpoint {
@@ -120,6 +138,14 @@ pub struct TunnelEndpoint {
     pub proxy: Option<proxy::ProxyEndpoint>,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub entry_endpoint: Option<Endpoint>,
+    /// Local proxy that the tunnel traffic is obfuscated with, if any.
+    #[serde(default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub obfuscation: Option<ObfuscatorKind>,
+    /// Name of the tunnel interface. Only known once the tunnel is connected.
+    #[serde(default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub tunnel_interface: Option<String>,
 }
 
 impl fmt::Display for TunnelEndpoint {
@@ -139,6 +165,9 @@ impl fmt::Display for TunnelEndpoint {
                 if let Some(ref entry_endpoint) = self.entry_endpoint {
                     write!(f, " via {}", entry_endpoint)?;
                 }
+                if let Some(ref proxy) = self.proxy {
+                    write!(f, " via proxy {}", proxy.endpoint.address)?;
+                }
             }
         }
         Ok(())
@@ -1,6 +1,6 @@
-use crate::net::Endpoint;
+use crate::net::{openvpn, Endpoint, TransportProtocol};
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use std::{fmt, net::SocketAddr};
 
 /// Types of bridges that can be used to proxy a connection to a tunnel
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -27,3 +27,41 @@ pub struct ProxyEndpoint {
     pub endpoint: Endpoint,
     pub proxy_type: ProxyType,
 }
+
+/// A user-provided SOCKS5 proxy that all traffic to the relay is sent through.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct EntryProxy {
+    pub address: SocketAddr,
+    pub auth: Option<openvpn::ProxyAuth>,
+}
+
+impl EntryProxy {
+    pub fn get_endpoint(&self) -> ProxyEndpoint {
+        ProxyEndpoint {
+            endpoint: Endpoint {
+                address: self.address,
+                protocol: TransportProtocol::Tcp,
+            },
+            proxy_type: ProxyType::Custom,
+        }
+    }
+
+    /// Returns the settings that make OpenVPN connect through this proxy.
+    pub fn to_openvpn_proxy_settings(&self) -> openvpn::ProxySettings {
+        openvpn::ProxySettings::Remote(openvpn::RemoteProxySettings {
+            address: self.address,
+            auth: self.auth.clone(),
+        })
+    }
+}
+
+impl fmt::Display for EntryProxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        // The credentials are left out since this ends up in logs.
+        write!(f, "SOCKS5 proxy at {}", self.address)?;
+        if self.auth.is_some() {
+            write!(f, " with authentication")?;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use crate::net::{Endpoint, GenericTunnelOptions, TransportProtocol};
+use crate::net::{proxy::EntryProxy, Endpoint, GenericTunnelOptions, TransportProtocol};
 use ipnetwork::IpNetwork;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
@@ -17,6 +17,9 @@ pub struct TunnelParameters {
     pub connection: ConnectionConfig,
     pub options: TunnelOptions,
     pub generic_options: GenericTunnelOptions,
+    /// SOCKS5 proxy to reach the entry peer through. The peer must use TCP.
+    #[serde(default)]
+    pub entry_proxy: Option<EntryProxy>,
 }
 
 /// Connection-specific configuration in [`TunnelParameters`].
@@ -90,6 +93,33 @@ pub struct TunnelOptions {
     #[serde(default = "default_wgnt_setting")]
     #[serde(rename = "wireguard_nt")]
     pub use_wireguard_nt: bool,
+    /// Name of the tunnel interface, or `None` to use the default name
+    #[cfg(any(target_os = "linux", windows))]
+    #[serde(default)]
+    pub interface_name: Option<String>,
+}
+
+/// Returns whether `name` may be used as the name of a tunnel interface.
+#[cfg(any(target_os = "linux", windows))]
+pub fn is_valid_interface_name(name: &str) -> bool {
+    if name.is_empty() || name.chars().any(char::is_control) {
+        return false;
+    }
+    // Linux interface names must fit in `IFNAMSIZ` bytes, including the null terminator.
+    #[cfg(target_os = "linux")]
+    {
+        name.len() < 16
+            && name != "."
+            && name != ".."
+            && !name
+                .chars()
+                .any(|c| c == '/' || c == ':' || c.is_whitespace())
+    }
+    // Windows interface aliases may be at most `IF_MAX_STRING_SIZE` characters long.
+    #[cfg(windows)]
+    {
+        name.encode_utf16().count() <= 256
+    }
 }
 
 #[cfg(windows)]
@@ -103,6 +133,8 @@ impl Default for TunnelOptions {
             mtu: None,
             #[cfg(windows)]
             use_wireguard_nt: default_wgnt_setting(),
+            #[cfg(any(target_os = "linux", windows))]
+            interface_name: None,
         }
     }
 }
@@ -129,6 +161,11 @@ impl PrivateKey {
     pub fn to_base64(&self) -> String {
         base64::encode(self.0.to_bytes())
     }
+
+    /// Performs a Diffie-Hellman key exchange with the given public key
+    pub fn diffie_hellman(&self, public_key: &PublicKey) -> [u8; 32] {
+        self.0.diffie_hellman(&public_key.0).to_bytes()
+    }
 }
 
 impl From<[u8; 32]> for PrivateKey {
@@ -288,3 +325,37 @@ where
             Ok(From::from(key))
         })
 }
+
+#[cfg(all(test, any(target_os = "linux", windows)))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_valid_interface_names() {
+        assert!(is_valid_interface_name("wg-mullvad"));
+        assert!(is_valid_interface_name("wg0"));
+        assert!(!is_valid_interface_name(""));
+        assert!(!is_valid_interface_name("wg\n0"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_linux_interface_names() {
+        // At most 15 bytes, leaving room for the null terminator
+        assert!(is_valid_interface_name("wg-mullvad-0123"));
+        assert!(!is_valid_interface_name("wg-mullvad-01234"));
+        assert!(!is_valid_interface_name("."));
+        assert!(!is_valid_interface_name(".."));
+        assert!(!is_valid_interface_name("wg/0"));
+        assert!(!is_valid_interface_name("wg:0"));
+        assert!(!is_valid_interface_name("wg 0"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_windows_interface_names() {
+        assert!(is_valid_interface_name("Mullvad WireGuard"));
+        assert!(is_valid_interface_name(&"a".repeat(256)));
+        assert!(!is_valid_interface_name(&"a".repeat(257)));
+    }
+}
@@ -1,10 +1,13 @@
-use crate::net::TunnelEndpoint;
+use crate::net::{wireguard::PublicKey, TunnelEndpoint, TunnelType};
+use ipnetwork::IpNetwork;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
-use std::fmt;
-#[cfg(target_os = "android")]
-use std::net::IpAddr;
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, SystemTime},
+};
 
 /// Event emitted from the states in `talpid_core::tunnel_state_machine` when the tunnel state
 /// machine enters a new state.
@@ -85,12 +88,21 @@ impl ErrorState {
 pub enum ErrorStateCause {
     /// Authentication with remote server failed.
     AuthFailed(Option<String>),
+    /// The account has run out of time. Frontends should direct the user to add more time.
+    AccountExpired,
     /// Failed to configure IPv6 because it's disabled in the platform.
     Ipv6Unavailable,
     /// Failed to set firewall policy.
     SetFirewallPolicyError(FirewallPolicyError),
     /// Failed to set system DNS server.
     SetDnsError,
+    /// The firewall rules or routes could not be confirmed to be in place after connecting.
+    #[cfg(not(target_os = "android"))]
+    KillSwitchVerificationFailed,
+    /// The system clock is too far from the actual time for the tunnel to be established. Contains
+    /// the difference in seconds, which is positive if the system clock is ahead.
+    #[cfg(not(target_os = "android"))]
+    ClockSkew(i64),
     /// Android has rejected one or more DNS server addresses.
     #[cfg(target_os = "android")]
     InvalidDnsServers(Vec<IpAddr>),
@@ -136,6 +148,9 @@ pub enum ParameterGenerationError {
     /// Failure to resolve the hostname of a custom tunnel configuration
     #[error(display = "Can't resolve hostname for custom tunnel host")]
     CustomTunnelHostResultionError,
+    /// The public key of a relay does not match the one published in the relay list
+    #[error(display = "The public key of the relay does not match the relay list")]
+    RelayKeyMismatch,
 }
 
 /// Application that prevents setting the firewall policy.
@@ -176,6 +191,7 @@ impl fmt::Display for ErrorStateCause {
                     }
                 );
             }
+            AccountExpired => "The account has no time left",
             Ipv6Unavailable => "Failed to configure IPv6 because it's disabled in the platform",
             SetFirewallPolicyError(ref err) => {
                 return match err {
@@ -187,6 +203,19 @@ impl fmt::Display for ErrorStateCause {
                 };
             }
             SetDnsError => "Failed to set system DNS server",
+            #[cfg(not(target_os = "android"))]
+            KillSwitchVerificationFailed => {
+                "Failed to verify that the firewall rules and routes are in place"
+            }
+            #[cfg(not(target_os = "android"))]
+            ClockSkew(skew_secs) => {
+                return write!(
+                    f,
+                    "The system clock is {} seconds {}",
+                    skew_secs.unsigned_abs(),
+                    if skew_secs >= 0 { "ahead" } else { "behind" }
+                );
+            }
             #[cfg(target_os = "android")]
             InvalidDnsServers(ref addresses) => {
                 return write!(
@@ -213,3 +242,203 @@ impl fmt::Display for ErrorStateCause {
         write!(f, "{}", description)
     }
 }
+
+/// Number of bytes sent and received through the tunnel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrafficCounters {
+    /// Bytes sent through the tunnel.
+    pub tx_bytes: u64,
+    /// Bytes received through the tunnel.
+    pub rx_bytes: u64,
+}
+
+impl std::ops::Add for TrafficCounters {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            tx_bytes: self.tx_bytes.saturating_add(other.tx_bytes),
+            rx_bytes: self.rx_bytes.saturating_add(other.rx_bytes),
+        }
+    }
+}
+
+/// Traffic through the current tunnel and through all tunnels since the daemon started, and
+/// how the current tunnel is performing.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TunnelStatistics {
+    /// Traffic through the current tunnel. Zero if there is no tunnel.
+    pub session: TrafficCounters,
+    /// Traffic through all tunnels, including the current one.
+    pub cumulative: TrafficCounters,
+    /// How long calls into the current tunnel implementation have taken. Only recorded for
+    /// wireguard-go.
+    pub operation_timings: Vec<OperationTimingDebugInfo>,
+    /// How often the relay is likely to have seen the current tunnel traffic arrive from a new
+    /// address. Only recorded for wireguard-go.
+    pub endpoint_changes: Option<EndpointChangeDebugInfo>,
+}
+
+/// Structured snapshot of the internals of the tunnel backend, used for troubleshooting.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TunnelDebugInfo {
+    /// The type of the tunnel that is running, if any.
+    pub tunnel_type: Option<TunnelType>,
+    /// The tunnel interface, if it has been created.
+    pub adapter: Option<AdapterDebugInfo>,
+    /// WireGuard peers and their traffic counters.
+    pub peers: Vec<PeerDebugInfo>,
+    /// Local proxies that wrap the tunnel traffic before it is sent to the relay.
+    pub obfuscators: Vec<ObfuscatorDebugInfo>,
+    /// The physical interface that the sockets carrying tunnel traffic are bound to.
+    pub bound_interface: Option<String>,
+    /// Routes that are currently applied by the daemon.
+    pub routes: Vec<RouteDebugInfo>,
+}
+
+/// The tunnel interface and the implementation that drives it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdapterDebugInfo {
+    /// Name of the tunnel interface.
+    pub name: String,
+    /// The tunnel implementation in use, such as "wireguard-go".
+    pub backend: String,
+    /// Whether the tunnel implementation is still running.
+    pub is_running: bool,
+    /// MTU of the tunnel interface.
+    pub mtu: Option<u16>,
+    /// Addresses assigned to the tunnel interface.
+    pub addresses: Vec<IpAddr>,
+    /// Why the preferred tunnel implementation could not be used, if `backend` is a fallback.
+    pub fallback_reason: Option<String>,
+}
+
+/// State of a single WireGuard peer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerDebugInfo {
+    /// Public key of the peer.
+    pub public_key: PublicKey,
+    /// The relay endpoint of the peer. This is not the address of a local obfuscator.
+    pub endpoint: SocketAddr,
+    /// Networks that are routed to the peer.
+    pub allowed_ips: Vec<IpNetwork>,
+    /// Bytes sent to the peer.
+    pub tx_bytes: u64,
+    /// Bytes received from the peer.
+    pub rx_bytes: u64,
+    /// Time since the most recent successful handshake, if there has been one.
+    pub last_handshake: Option<Duration>,
+}
+
+/// State of a local proxy that tunnel traffic is sent through.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObfuscatorDebugInfo {
+    /// The kind of proxy.
+    pub kind: ObfuscatorKind,
+    /// Local address that the tunnel sends traffic to.
+    pub local_address: SocketAddr,
+    /// Address that the proxy forwards traffic to.
+    pub remote_address: SocketAddr,
+    /// Whether the proxy is still forwarding traffic.
+    pub is_running: bool,
+}
+
+/// Kinds of local proxies used to obfuscate tunnel traffic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObfuscatorKind {
+    /// WireGuard traffic is wrapped in a TCP stream.
+    Udp2Tcp,
+    /// WireGuard traffic is sent through a user-provided SOCKS5 proxy.
+    Socks5,
+    /// WireGuard traffic is carried by a transport plugin registered with the daemon.
+    Plugin,
+}
+
+/// Timing statistics for one kind of call into the tunnel implementation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationTimingDebugInfo {
+    /// Name of the operation, such as "get config".
+    pub operation: String,
+    /// Number of times the operation has been performed.
+    pub count: u64,
+    /// Duration of the most recent call.
+    pub last: Duration,
+    /// Average duration of all calls.
+    pub average: Duration,
+    /// Duration of the slowest call.
+    pub max: Duration,
+}
+
+/// Counts of events that make the relay see tunnel traffic coming from a new address. This happens
+/// when the local socket is rebound, or when a NAT mapping expires and handshakes have to be
+/// retransmitted before a new mapping is created. Frequent changes are common behind carrier-grade
+/// NAT and show up as short interruptions in the connection.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndpointChangeDebugInfo {
+    /// Number of likely endpoint changes. This is the number of socket rebinds plus the number of
+    /// handshakes that only completed after being retransmitted.
+    pub count: u64,
+    /// When the most recent endpoint change was observed.
+    pub last_change: Option<SystemTime>,
+    /// Number of times the local UDP socket was rebound.
+    pub socket_rebinds: u64,
+    /// Number of handshake initiations that were retransmitted because no response arrived.
+    pub handshake_retransmissions: u64,
+}
+
+/// A route that has been applied by the daemon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteDebugInfo {
+    /// The destination of the route.
+    pub destination: IpNetwork,
+    /// The gateway that traffic is routed through, if any.
+    pub gateway: Option<IpAddr>,
+    /// The interface that traffic is routed through. Routes without a gateway or an interface
+    /// follow the default route.
+    pub interface: Option<String>,
+    /// The metric of the route, if set explicitly.
+    pub metric: Option<u32>,
+    /// The routing table that the route is in. Only set on Linux.
+    pub table_id: Option<u32>,
+}
+
+/// Snapshot of the tunnel state machine, used to find out why it is stuck in a state.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateMachineDebugInfo {
+    /// Name of the current state, such as "connecting".
+    pub state: String,
+    /// When the current state was entered.
+    pub state_entered: SystemTime,
+    /// Number of failed connection attempts in a row. Only set while connecting, or while
+    /// disconnecting in order to reconnect.
+    pub retry_attempt: Option<u32>,
+    /// Timers that will make the state machine act without any external event.
+    pub pending_timers: Vec<PendingTimerDebugInfo>,
+    /// The most recent state transitions, oldest first.
+    pub transitions: Vec<StateTransitionDebugInfo>,
+}
+
+/// A transition between two states of the tunnel state machine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateTransitionDebugInfo {
+    /// When the transition happened.
+    pub time: SystemTime,
+    /// Name of the state that was left.
+    pub from: String,
+    /// Name of the state that was entered.
+    pub to: String,
+    /// Why the new state was entered, such as the error cause or the action after disconnecting.
+    pub details: Option<String>,
+    /// The event that caused the transition, such as a command or a tunnel event.
+    pub trigger: String,
+}
+
+/// A timer that is waited on by the tunnel state machine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingTimerDebugInfo {
+    /// What happens when the timer expires.
+    pub action: String,
+    /// When the timer expires.
+    pub deadline: SystemTime,
+}
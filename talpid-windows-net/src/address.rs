@@ -0,0 +1,231 @@
+use crate::{Error, Result};
+use socket2::SockAddr;
+use std::{fmt, mem, net::SocketAddr};
+use winapi::shared::{
+    ws2def::{AF_INET, AF_INET6, SOCKADDR_STORAGE as sockaddr_storage},
+    ws2ipdef::SOCKADDR_INET,
+};
+
+/// Address family. These correspond to the `AF_*` constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+    /// IPv4 address family
+    Ipv4 = AF_INET as isize,
+    /// IPv6 address family
+    Ipv6 = AF_INET6 as isize,
+}
+
+impl fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            AddressFamily::Ipv4 => write!(f, "IPv4 (AF_INET)"),
+            AddressFamily::Ipv6 => write!(f, "IPv6 (AF_INET6)"),
+        }
+    }
+}
+
+impl AddressFamily {
+    /// Convert one of the `AF_*` constants to an [`AddressFamily`].
+    pub fn try_from_af_family(family: u16) -> Result<AddressFamily> {
+        match family as i32 {
+            AF_INET => Ok(AddressFamily::Ipv4),
+            AF_INET6 => Ok(AddressFamily::Ipv6),
+            family => Err(Error::UnknownAddressFamily(family)),
+        }
+    }
+}
+
+/// An IPv4 or IPv6 socket address with the same layout as `SOCKADDR_INET`, so that it can be
+/// embedded in structs that are passed to Windows APIs or drivers.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct InetSockAddr(SOCKADDR_INET);
+
+impl InetSockAddr {
+    pub(crate) fn from_raw(addr: SOCKADDR_INET) -> Self {
+        InetSockAddr(addr)
+    }
+
+    /// Returns the raw address family, which is one of the `AF_*` constants for valid addresses.
+    pub fn family(&self) -> u16 {
+        unsafe { *self.0.si_family() }
+    }
+
+    /// Converts the address to a `SocketAddr`. Returns an error if the address family is neither
+    /// IPv4 nor IPv6.
+    pub fn to_socket_addr(&self) -> Result<SocketAddr> {
+        let addr = self.0;
+        unsafe {
+            let mut storage: sockaddr_storage = mem::zeroed();
+            *(&mut storage as *mut _ as *mut SOCKADDR_INET) = addr;
+            SockAddr::new(storage, mem::size_of_val(&addr) as i32)
+        }
+        .as_socket()
+        .ok_or(Error::UnknownAddressFamily(i32::from(self.family())))
+    }
+}
+
+impl From<SocketAddr> for InetSockAddr {
+    fn from(addr: SocketAddr) -> Self {
+        let mut sockaddr: SOCKADDR_INET = unsafe { mem::zeroed() };
+        match addr {
+            // SAFETY: `*const sockaddr` may be treated as `*const sockaddr_in` since we know it's a
+            // v4 address.
+            SocketAddr::V4(_) => unsafe {
+                *sockaddr.Ipv4_mut() = *(SockAddr::from(addr).as_ptr() as *const _)
+            },
+            // SAFETY: `*const sockaddr` may be treated as `*const sockaddr_in6` since we know it's a
+            // v6 address.
+            SocketAddr::V6(_) => unsafe {
+                *sockaddr.Ipv6_mut() = *(SockAddr::from(addr).as_ptr() as *const _)
+            },
+        }
+        InetSockAddr(sockaddr)
+    }
+}
+
+impl TryFrom<InetSockAddr> for SocketAddr {
+    type Error = Error;
+
+    fn try_from(addr: InetSockAddr) -> Result<SocketAddr> {
+        addr.to_socket_addr()
+    }
+}
+
+/// Addresses are equal if they convert to the same `SocketAddr`. Addresses with an unknown
+/// family are never equal to anything.
+impl PartialEq for InetSockAddr {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.to_socket_addr(), other.to_socket_addr()) {
+            (Ok(self_addr), Ok(other_addr)) => self_addr == other_addr,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for InetSockAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addr = self
+            .to_socket_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|_| format!("<unknown family {}>", self.family()));
+        f.debug_tuple("InetSockAddr").field(&addr).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+    use winapi::shared::ws2def::AF_UNSPEC;
+
+    fn roundtrip(addr: SocketAddr) -> SocketAddr {
+        InetSockAddr::from(addr).to_socket_addr().unwrap()
+    }
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(
+            mem::size_of::<InetSockAddr>(),
+            mem::size_of::<SOCKADDR_INET>()
+        );
+        assert_eq!(
+            mem::align_of::<InetSockAddr>(),
+            mem::align_of::<SOCKADDR_INET>()
+        );
+    }
+
+    #[test]
+    fn test_sockaddr_v4() {
+        let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(1, 2, 3, 4), 1234));
+        assert_eq!(addr, roundtrip(addr));
+        assert_eq!(InetSockAddr::from(addr).family(), AF_INET as u16);
+    }
+
+    #[test]
+    fn test_sockaddr_v4_zero_port() {
+        let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(10, 64, 0, 1), 0));
+        assert_eq!(addr, roundtrip(addr));
+    }
+
+    #[test]
+    fn test_sockaddr_v4_unspecified() {
+        let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+        assert_eq!(addr, roundtrip(addr));
+    }
+
+    #[test]
+    fn test_sockaddr_v6() {
+        let addr = SocketAddr::V6(SocketAddrV6::new(
+            Ipv6Addr::new(1, 2, 3, 4, 5, 6, 7, 8),
+            1234,
+            0,
+            0,
+        ));
+        assert_eq!(addr, roundtrip(addr));
+        assert_eq!(InetSockAddr::from(addr).family(), AF_INET6 as u16);
+    }
+
+    #[test]
+    fn test_sockaddr_v6_flowinfo_and_scope_id() {
+        let addr = SocketAddr::V6(SocketAddrV6::new(
+            Ipv6Addr::new(0xfe80, 0, 0, 0, 1, 2, 3, 4),
+            1234,
+            0xa,
+            0xb,
+        ));
+        let converted = roundtrip(addr);
+        assert_eq!(addr, converted);
+        match converted {
+            SocketAddr::V6(converted) => {
+                assert_eq!(converted.flowinfo(), 0xa);
+                assert_eq!(converted.scope_id(), 0xb);
+            }
+            SocketAddr::V4(_) => panic!("unexpected address family"),
+        }
+    }
+
+    #[test]
+    fn test_sockaddr_v6_zero_port() {
+        let addr = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 0, 0, 0));
+        assert_eq!(addr, roundtrip(addr));
+    }
+
+    #[test]
+    fn test_sockaddr_v6_unspecified() {
+        let addr = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0));
+        assert_eq!(addr, roundtrip(addr));
+    }
+
+    #[test]
+    fn test_sockaddr_unknown_family() {
+        let addr = InetSockAddr(unsafe { mem::zeroed() });
+        assert_eq!(addr.family(), AF_UNSPEC as u16);
+        assert!(matches!(
+            addr.to_socket_addr(),
+            Err(Error::UnknownAddressFamily(AF_UNSPEC))
+        ));
+        assert_ne!(addr, addr);
+    }
+
+    #[test]
+    fn test_sockaddr_eq() {
+        let addr: SocketAddr = "1.2.3.4:1234".parse().unwrap();
+        let other: SocketAddr = "1.2.3.4:1235".parse().unwrap();
+        assert_eq!(InetSockAddr::from(addr), InetSockAddr::from(addr));
+        assert_ne!(InetSockAddr::from(addr), InetSockAddr::from(other));
+    }
+
+    #[test]
+    fn test_address_family() {
+        assert_eq!(
+            AddressFamily::try_from_af_family(AF_INET as u16).unwrap(),
+            AddressFamily::Ipv4
+        );
+        assert_eq!(
+            AddressFamily::try_from_af_family(AF_INET6 as u16).unwrap(),
+            AddressFamily::Ipv6
+        );
+        assert!(AddressFamily::try_from_af_family(AF_UNSPEC as u16).is_err());
+    }
+}
@@ -0,0 +1,417 @@
+use crate::{AddressFamily, DadStateError, Error, InetSockAddr, Result};
+use std::{
+    ffi::{OsStr, OsString},
+    io, mem,
+    net::IpAddr,
+    os::windows::{
+        ffi::{OsStrExt, OsStringExt},
+        io::RawHandle,
+    },
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use winapi::shared::{
+    ifdef::NET_LUID,
+    netioapi::{
+        CancelMibChangeNotify2, ConvertInterfaceAliasToLuid, ConvertInterfaceLuidToAlias,
+        FreeMibTable, GetIpInterfaceEntry, GetUnicastIpAddressEntry, GetUnicastIpAddressTable,
+        MibAddInstance, NotifyIpInterfaceChange, SetIpInterfaceEntry, MIB_IPINTERFACE_ROW,
+        MIB_UNICASTIPADDRESS_ROW, MIB_UNICASTIPADDRESS_TABLE,
+    },
+    nldef::{IpDadStatePreferred, IpDadStateTentative, RouterDiscoveryDisabled, NL_DAD_STATE},
+    ntddndis::NDIS_IF_MAX_STRING_SIZE,
+    ntdef::FALSE,
+    winerror::{ERROR_NOT_FOUND, NO_ERROR},
+    ws2def::{AF_INET, AF_INET6, AF_UNSPEC},
+};
+
+const DAD_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+const DAD_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Locally unique identifier of a network interface. This has the same layout as `NET_LUID`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct Luid(u64);
+
+impl Luid {
+    /// Creates a `Luid` from the 64-bit value of a `NET_LUID`.
+    pub fn from_raw(value: u64) -> Self {
+        Luid(value)
+    }
+
+    /// Returns the 64-bit value of the `NET_LUID`.
+    pub fn as_raw(&self) -> u64 {
+        self.0
+    }
+
+    fn as_net_luid(&self) -> NET_LUID {
+        NET_LUID { Value: self.0 }
+    }
+}
+
+/// A unicast IP address assigned to an interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnicastAddress {
+    /// The interface that the address is assigned to.
+    pub luid: Luid,
+    /// The assigned address.
+    pub address: IpAddr,
+}
+
+/// Context for [`notify_ip_interface_change`]. When it is dropped,
+/// the callback is unregistered.
+struct IpNotifierHandle<'a> {
+    callback: Mutex<Box<dyn FnMut(&MIB_IPINTERFACE_ROW, u32) + Send + 'a>>,
+    handle: RawHandle,
+}
+
+unsafe impl Send for IpNotifierHandle<'_> {}
+
+impl<'a> Drop for IpNotifierHandle<'a> {
+    fn drop(&mut self) {
+        unsafe { CancelMibChangeNotify2(self.handle as *mut _) };
+    }
+}
+
+unsafe extern "system" fn inner_callback(
+    context: *mut winapi::ctypes::c_void,
+    row: *mut MIB_IPINTERFACE_ROW,
+    notify_type: u32,
+) {
+    let context = &mut *(context as *mut IpNotifierHandle<'_>);
+    context
+        .callback
+        .lock()
+        .expect("NotifyIpInterfaceChange mutex poisoned")(&*row, notify_type);
+}
+
+/// Registers a callback function that is invoked when an interface is added, removed,
+/// or changed.
+fn notify_ip_interface_change<'a, T: FnMut(&MIB_IPINTERFACE_ROW, u32) + Send + 'a>(
+    callback: T,
+    family: Option<AddressFamily>,
+) -> io::Result<Box<IpNotifierHandle<'a>>> {
+    let mut context = Box::new(IpNotifierHandle {
+        callback: Mutex::new(Box::new(callback)),
+        handle: std::ptr::null_mut(),
+    });
+
+    let status = unsafe {
+        NotifyIpInterfaceChange(
+            af_family_from_family(family),
+            Some(inner_callback),
+            &mut *context as *mut _ as *mut _,
+            FALSE,
+            (&mut context.handle) as *mut _,
+        )
+    };
+
+    if status == NO_ERROR {
+        Ok(context)
+    } else {
+        Err(io::Error::from_raw_os_error(status as i32))
+    }
+}
+
+/// Handle for a callback registered with [`notify_interface_changes`]. The callback is
+/// unregistered when the handle is dropped.
+pub struct InterfaceChangeHandle(Box<IpNotifierHandle<'static>>);
+
+/// Registers a callback that is invoked with the LUID and address family of an IP interface
+/// whenever one is added, removed, or changed. If `family` is `None`, both IPv4 and IPv6
+/// interfaces are monitored.
+pub fn notify_interface_changes<T: FnMut(Luid, AddressFamily) + Send + 'static>(
+    mut callback: T,
+    family: Option<AddressFamily>,
+) -> io::Result<InterfaceChangeHandle> {
+    notify_ip_interface_change(
+        move |row, _notification_type| {
+            if let Ok(family) = AddressFamily::try_from_af_family(row.Family) {
+                callback(Luid::from_raw(row.InterfaceLuid.Value), family);
+            }
+        },
+        family,
+    )
+    .map(InterfaceChangeHandle)
+}
+
+/// Returns whether an IP interface is connected to a network. Returns `false` if the interface
+/// does not exist.
+pub fn is_interface_connected(family: AddressFamily, luid: Luid) -> io::Result<bool> {
+    match get_ip_interface_entry(family, luid) {
+        Ok(row) => Ok(row.Connected != FALSE),
+        Err(error) if error.raw_os_error() == Some(ERROR_NOT_FOUND as i32) => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+/// Returns information about a network IP interface.
+fn get_ip_interface_entry(family: AddressFamily, luid: Luid) -> io::Result<MIB_IPINTERFACE_ROW> {
+    let mut row: MIB_IPINTERFACE_ROW = unsafe { mem::zeroed() };
+    row.Family = family as u16;
+    row.InterfaceLuid = luid.as_net_luid();
+
+    let result = unsafe { GetIpInterfaceEntry(&mut row) };
+    if result == NO_ERROR {
+        Ok(row)
+    } else {
+        Err(io::Error::from_raw_os_error(result as i32))
+    }
+}
+
+/// Set the properties of an IP interface.
+fn set_ip_interface_entry(row: &MIB_IPINTERFACE_ROW) -> io::Result<()> {
+    let result = unsafe { SetIpInterfaceEntry(row as *const _ as *mut _) };
+    if result == NO_ERROR {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(result as i32))
+    }
+}
+
+fn ip_interface_entry_exists(family: AddressFamily, luid: Luid) -> io::Result<bool> {
+    match get_ip_interface_entry(family, luid) {
+        Ok(_) => Ok(true),
+        Err(error) if error.raw_os_error() == Some(ERROR_NOT_FOUND as i32) => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+/// Disables DAD, DHCP, and router discovery on an IP interface of a tunnel adapter, since the
+/// tunnel addresses are assigned statically. The MTU is also set, if one is given.
+pub fn prepare_tunnel_interface(
+    luid: Luid,
+    family: AddressFamily,
+    mtu: Option<u32>,
+) -> io::Result<()> {
+    let mut row = get_ip_interface_entry(family, luid)?;
+    row.SitePrefixLength = 0;
+    row.RouterDiscoveryBehavior = RouterDiscoveryDisabled;
+    row.DadTransmits = 0;
+    row.ManagedAddressConfigurationSupported = FALSE;
+    row.OtherStatefulConfigurationSupported = FALSE;
+    if let Some(mtu) = mtu {
+        row.NlMtu = mtu;
+    }
+    set_ip_interface_entry(&row)
+}
+
+/// Sets the MTU of the IP interface of a network interface, if the interface has one for the
+/// given address family.
+pub fn set_ip_interface_mtu(luid: Luid, family: AddressFamily, mtu: u32) -> io::Result<()> {
+    if !ip_interface_entry_exists(family, luid)? {
+        return Ok(());
+    }
+    let mut row = get_ip_interface_entry(family, luid)?;
+    row.NlMtu = mtu;
+    set_ip_interface_entry(&row)
+}
+
+/// Waits until the specified IP interfaces have attached to a given network interface.
+pub async fn wait_for_interfaces(luid: Luid, ipv4: bool, ipv6: bool) -> io::Result<()> {
+    let (tx, rx) = futures::channel::oneshot::channel();
+
+    let mut found_ipv4 = !ipv4;
+    let mut found_ipv6 = !ipv6;
+
+    let mut tx = Some(tx);
+
+    let _handle = notify_ip_interface_change(
+        move |row, notification_type| {
+            if found_ipv4 && found_ipv6 {
+                return;
+            }
+            if notification_type != MibAddInstance {
+                return;
+            }
+            if row.InterfaceLuid.Value != luid.as_raw() {
+                return;
+            }
+            match row.Family as i32 {
+                AF_INET => found_ipv4 = true,
+                AF_INET6 => found_ipv6 = true,
+                _ => (),
+            }
+            if found_ipv4 && found_ipv6 {
+                if let Some(tx) = tx.take() {
+                    let _ = tx.send(());
+                }
+            }
+        },
+        None,
+    )?;
+
+    // Make sure they don't already exist
+    if (!ipv4 || ip_interface_entry_exists(AddressFamily::Ipv4, luid)?)
+        && (!ipv6 || ip_interface_entry_exists(AddressFamily::Ipv6, luid)?)
+    {
+        return Ok(());
+    }
+
+    let _ = rx.await;
+    Ok(())
+}
+
+#[allow(non_upper_case_globals)]
+impl From<NL_DAD_STATE> for DadStateError {
+    fn from(state: NL_DAD_STATE) -> DadStateError {
+        use winapi::shared::nldef::*;
+        match state {
+            IpDadStateInvalid => DadStateError::Invalid,
+            IpDadStateDuplicate => DadStateError::Duplicate,
+            IpDadStateDeprecated => DadStateError::Deprecated,
+            other => DadStateError::Unknown(other),
+        }
+    }
+}
+
+/// Wait for addresses to be usable on an network adapter.
+pub async fn wait_for_addresses(luid: Luid) -> Result<()> {
+    // Obtain unicast IP addresses
+    let mut unicast_rows: Vec<MIB_UNICASTIPADDRESS_ROW> = get_unicast_table(None)
+        .map_err(Error::ObtainUnicastAddress)?
+        .into_iter()
+        .filter(|row| row.InterfaceLuid.Value == luid.as_raw())
+        .collect();
+    if unicast_rows.is_empty() {
+        return Err(Error::NoUnicastAddress);
+    }
+
+    let (tx, rx) = futures::channel::oneshot::channel();
+    let mut addr_check_thread = move || {
+        // Poll DAD status using GetUnicastIpAddressEntry
+        // https://docs.microsoft.com/en-us/windows/win32/api/netioapi/nf-netioapi-createunicastipaddressentry
+
+        let deadline = Instant::now() + DAD_CHECK_TIMEOUT;
+        while Instant::now() < deadline {
+            let mut ready = true;
+
+            for row in &mut unicast_rows {
+                let status = unsafe { GetUnicastIpAddressEntry(row) };
+                if status != NO_ERROR {
+                    return Err(Error::ObtainUnicastAddress(io::Error::from_raw_os_error(
+                        status as i32,
+                    )));
+                }
+                if row.DadState == IpDadStateTentative {
+                    ready = false;
+                    break;
+                }
+                if row.DadState != IpDadStatePreferred {
+                    return Err(Error::DadStateError(DadStateError::from(row.DadState)));
+                }
+            }
+
+            if ready {
+                return Ok(());
+            }
+            std::thread::sleep(DAD_CHECK_INTERVAL);
+        }
+
+        Err(Error::DeviceReadyTimeout)
+    };
+    std::thread::spawn(move || {
+        let _ = tx.send(addr_check_thread());
+    });
+    rx.await.map_err(|_| Error::UnicastSenderDropped)?
+}
+
+/// Returns the unicast IP addresses assigned to all interfaces. If `family` is `None`, then
+/// addresses for all families are returned.
+pub fn get_unicast_addresses(family: Option<AddressFamily>) -> io::Result<Vec<UnicastAddress>> {
+    Ok(get_unicast_table(family)?
+        .into_iter()
+        .filter_map(|row| {
+            let address = InetSockAddr::from_raw(row.Address).to_socket_addr().ok()?;
+            Some(UnicastAddress {
+                luid: Luid::from_raw(row.InterfaceLuid.Value),
+                address: address.ip(),
+            })
+        })
+        .collect())
+}
+
+/// Returns the unicast IP address table. If `family` is `None`, then addresses for all families are
+/// returned.
+fn get_unicast_table(family: Option<AddressFamily>) -> io::Result<Vec<MIB_UNICASTIPADDRESS_ROW>> {
+    let mut unicast_rows = vec![];
+    let mut unicast_table: *mut MIB_UNICASTIPADDRESS_TABLE = std::ptr::null_mut();
+
+    let status =
+        unsafe { GetUnicastIpAddressTable(af_family_from_family(family), &mut unicast_table) };
+    if status != NO_ERROR {
+        return Err(io::Error::from_raw_os_error(status as i32));
+    }
+    let first_row = unsafe { &(*unicast_table).Table[0] } as *const MIB_UNICASTIPADDRESS_ROW;
+    for i in 0..unsafe { *unicast_table }.NumEntries {
+        unicast_rows.push(unsafe { *(first_row.offset(i as isize)) });
+    }
+    unsafe { FreeMibTable(unicast_table as *mut _) };
+
+    Ok(unicast_rows)
+}
+
+/// Returns the LUID of an interface given its alias.
+pub fn luid_from_alias<T: AsRef<OsStr>>(alias: T) -> io::Result<Luid> {
+    let alias_wide: Vec<u16> = alias
+        .as_ref()
+        .encode_wide()
+        .chain(std::iter::once(0u16))
+        .collect();
+    let mut luid: NET_LUID = unsafe { std::mem::zeroed() };
+    let status = unsafe { ConvertInterfaceAliasToLuid(alias_wide.as_ptr(), &mut luid) };
+    if status != NO_ERROR {
+        return Err(io::Error::from_raw_os_error(status as i32));
+    }
+    Ok(Luid::from_raw(luid.Value))
+}
+
+/// Returns the alias of an interface given its LUID.
+pub fn alias_from_luid(luid: Luid) -> io::Result<OsString> {
+    let mut buffer = [0u16; NDIS_IF_MAX_STRING_SIZE + 1];
+    let status = unsafe {
+        ConvertInterfaceLuidToAlias(&luid.as_net_luid(), &mut buffer[0] as *mut _, buffer.len())
+    };
+    if status != NO_ERROR {
+        return Err(io::Error::from_raw_os_error(status as i32));
+    }
+    let nul = buffer.iter().position(|&c| c == 0u16).unwrap();
+    Ok(OsString::from_wide(&buffer[0..nul]))
+}
+
+fn af_family_from_family(family: Option<AddressFamily>) -> u16 {
+    family
+        .map(|family| family as u16)
+        .unwrap_or(AF_UNSPEC as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_luid_layout() {
+        assert_eq!(mem::size_of::<Luid>(), mem::size_of::<NET_LUID>());
+        assert_eq!(mem::align_of::<Luid>(), mem::align_of::<NET_LUID>());
+    }
+
+    #[test]
+    fn test_luid_raw_value() {
+        let luid = Luid::from_raw(0x0006_8000_0000_0001);
+        assert_eq!(luid.as_raw(), 0x0006_8000_0000_0001);
+        assert_eq!(luid.as_net_luid().Value, luid.as_raw());
+    }
+
+    #[test]
+    fn test_af_family_from_family() {
+        assert_eq!(af_family_from_family(None), AF_UNSPEC as u16);
+        assert_eq!(
+            af_family_from_family(Some(AddressFamily::Ipv4)),
+            AF_INET as u16
+        );
+        assert_eq!(
+            af_family_from_family(Some(AddressFamily::Ipv6)),
+            AF_INET6 as u16
+        );
+    }
+}
@@ -0,0 +1,74 @@
+//! Safe wrappers around the parts of the Windows IP helper API that are used to manage tunnel
+//! interfaces, and conversions between Windows socket addresses and their standard library
+//! counterparts.
+//!
+//! No `winapi` types are part of the public interface. Types that must be passed to other Windows
+//! APIs, such as [`Luid`] and [`InetSockAddr`], have the same layout as their Windows counterparts.
+
+#![cfg(windows)]
+#![deny(missing_docs)]
+#![deny(rust_2018_idioms)]
+
+mod address;
+mod interface;
+
+pub use address::{AddressFamily, InetSockAddr};
+pub use interface::{
+    alias_from_luid, get_unicast_addresses, is_interface_connected, luid_from_alias,
+    notify_interface_changes, prepare_tunnel_interface, set_ip_interface_mtu, wait_for_addresses,
+    wait_for_interfaces, InterfaceChangeHandle, Luid, UnicastAddress,
+};
+
+use std::io;
+
+/// Result type for this crate.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors returned by some functions in this crate.
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    /// Error returned from `GetUnicastIpAddressTable`/`GetUnicastIpAddressEntry`
+    #[error(display = "Failed to obtain unicast IP address table")]
+    ObtainUnicastAddress(#[error(source)] io::Error),
+
+    /// `GetUnicastIpAddressTable` contained no addresses for the interface
+    #[error(display = "Found no addresses for the given adapter")]
+    NoUnicastAddress,
+
+    /// Unexpected DAD state returned for a unicast address
+    #[error(display = "Unexpected DAD state")]
+    DadStateError(#[error(source)] DadStateError),
+
+    /// DAD check failed.
+    #[error(display = "Timed out waiting on tunnel device")]
+    DeviceReadyTimeout,
+
+    /// Unicast DAD check fail.
+    #[error(display = "Unicast channel sender was unexpectedly dropped")]
+    UnicastSenderDropped,
+
+    /// Unknown address family
+    #[error(display = "Unknown address family: {}", _0)]
+    UnknownAddressFamily(i32),
+}
+
+/// Handles cases where there DAD state is neither tentative nor preferred.
+#[derive(err_derive::Error, Debug)]
+pub enum DadStateError {
+    /// Invalid DAD state.
+    #[error(display = "Invalid DAD state")]
+    Invalid,
+
+    /// Duplicate unicast address.
+    #[error(display = "A duplicate IP address was detected")]
+    Duplicate,
+
+    /// Deprecated unicast address.
+    #[error(display = "The IP address has been deprecated")]
+    Deprecated,
+
+    /// Unknown DAD state constant.
+    #[error(display = "Unknown DAD state: {}", _0)]
+    Unknown(u32),
+}
@@ -7,7 +7,9 @@
 #include "rules/baseline/blockall.h"
 #include "rules/baseline/permitdhcp.h"
 #include "rules/baseline/permitndp.h"
+#include "rules/baseline/permitmdns.h"
 #include "rules/baseline/permitdhcpserver.h"
+#include "rules/baseline/permitconnectionsharing.h"
 #include "rules/baseline/permitlan.h"
 #include "rules/baseline/permitlanservice.h"
 #include "rules/baseline/permitloopback.h"
@@ -45,25 +47,66 @@ namespace
 // sublayer permits this traffic. For this reason, whenever the relay is on port 53, the
 // PermitVpnRelay rule has to be installed to the DNS sublayer instead of the baseline sublayer.
 //
+// NDP router advertisements and redirects are only permitted if `permitNdpRouterMessages` is set,
+// so that they cannot install routes outside the tunnel while connected.
+//
 void AppendSettingsRules
 (
 	FwContext::Ruleset &ruleset,
-	const WinFwSettings &settings
+	const WinFwSettings &settings,
+	bool permitNdpRouterMessages
 )
 {
 	if (settings.permitDhcp)
 	{
 		ruleset.emplace_back(std::make_unique<baseline::PermitDhcp>());
-		ruleset.emplace_back(std::make_unique<baseline::PermitNdp>());
+		ruleset.emplace_back(std::make_unique<baseline::PermitNdp>(permitNdpRouterMessages));
+	}
+
+	if (settings.permitMdns)
+	{
+		ruleset.emplace_back(std::make_unique<baseline::PermitMdns>());
 	}
 
 	if (settings.permitLan)
 	{
-		ruleset.emplace_back(std::make_unique<baseline::PermitLan>());
-		ruleset.emplace_back(std::make_unique<baseline::PermitLanService>());
+		if (nullptr != settings.lanNetworks)
+		{
+			baseline::LanNetworks networks;
+
+			for (uint32_t i = 0; i < settings.numLanNetworks; ++i)
+			{
+				const auto &network = settings.lanNetworks[i];
+				const wfp::IpAddress ip(network.ip);
+
+				auto &collection = (ip.type() == wfp::IpAddress::Type::Ipv4 ? networks.ipv4 : networks.ipv6);
+				collection.emplace_back(ip, network.prefix);
+			}
+
+			ruleset.emplace_back(std::make_unique<baseline::PermitLan>(networks));
+			ruleset.emplace_back(std::make_unique<baseline::PermitLanService>(networks));
+		}
+		else
+		{
+			ruleset.emplace_back(std::make_unique<baseline::PermitLan>());
+			ruleset.emplace_back(std::make_unique<baseline::PermitLanService>());
+		}
+
 		ruleset.emplace_back(baseline::PermitDhcpServer::WithExtent(baseline::PermitDhcpServer::Extent::IPv4Only));
 	}
 
+	if (nullptr != settings.sharedNetworkIp)
+	{
+		const wfp::IpNetwork sharedNetwork(wfp::IpAddress(settings.sharedNetworkIp), settings.sharedNetworkPrefix);
+
+		ruleset.emplace_back(std::make_unique<baseline::PermitConnectionSharing>(sharedNetwork));
+
+		if (!settings.permitLan)
+		{
+			ruleset.emplace_back(baseline::PermitDhcpServer::WithExtent(baseline::PermitDhcpServer::Extent::IPv4Only));
+		}
+	}
+
 	//
 	// DNS management
 	//
@@ -191,7 +234,7 @@ bool FwContext::applyPolicyConnecting
 	Ruleset ruleset;
 
 	AppendNetBlockedRules(ruleset);
-	AppendSettingsRules(ruleset, settings);
+	AppendSettingsRules(ruleset, settings, true);
 	AppendRelayRules(ruleset, relay, relayClient);
 
 	if (allowedEndpoint.has_value())
@@ -233,7 +276,7 @@ bool FwContext::applyPolicyConnected
 	Ruleset ruleset;
 
 	AppendNetBlockedRules(ruleset);
-	AppendSettingsRules(ruleset, settings);
+	AppendSettingsRules(ruleset, settings, false);
 	AppendRelayRules(ruleset, relay, relayClient);
 
 	if (!tunnelDnsServers.empty())
@@ -294,6 +337,11 @@ bool FwContext::reset()
 	return status;
 }
 
+bool FwContext::verifyPolicy()
+{
+	return m_sessionController->verify();
+}
+
 FwContext::Policy FwContext::activePolicy() const
 {
 	return m_activePolicy;
@@ -304,7 +352,7 @@ FwContext::Ruleset FwContext::composePolicyBlocked(const WinFwSettings &settings
 	Ruleset ruleset;
 
 	AppendNetBlockedRules(ruleset);
-	AppendSettingsRules(ruleset, settings);
+	AppendSettingsRules(ruleset, settings, true);
 
 	if (allowedEndpoint.has_value())
 	{
@@ -50,6 +50,9 @@ public:
 
 	bool reset();
 
+	// Returns whether all filters of the active policy are still installed.
+	bool verifyPolicy();
+
 	enum class Policy
 	{
 		Connecting,
@@ -128,6 +128,8 @@ MullvadGuids::DetailedIdentityRegistry MullvadGuids::DetailedRegistry(IdentityQu
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitDhcp_Inbound_Response_Ipv6()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitDhcpServer_Inbound_Request_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitDhcpServer_Outbound_Response_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitConnectionSharing_Inbound_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitConnectionSharing_Outbound_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitVpnRelay()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitEndpoint()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitVpnTunnel_Outbound_Ipv4()));
@@ -141,6 +143,10 @@ MullvadGuids::DetailedIdentityRegistry MullvadGuids::DetailedRegistry(IdentityQu
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitNdp_Outbound_Neighbor_Advertisement()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitNdp_Inbound_Neighbor_Advertisement()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitNdp_Inbound_Redirect()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitMdns_Outbound_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitMdns_Outbound_Ipv6()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitMdns_Inbound_Ipv4()));
+	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitMdns_Inbound_Ipv6()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitDns_Outbound_Ipv4()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Baseline_PermitDns_Outbound_Ipv6()));
 	registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Dns_BlockAll_Outbound_Ipv4()));
@@ -164,6 +170,11 @@ MullvadGuids::DetailedIdentityRegistry MullvadGuids::DetailedRegistry(IdentityQu
 		registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Persistent_BlockAll_Outbound_Ipv4()));
 		registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Persistent_BlockAll_Inbound_Ipv6()));
 		registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Persistent_BlockAll_Outbound_Ipv6()));
+
+		registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Persistent_PermitAll_Inbound_Ipv4()));
+		registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Persistent_PermitAll_Outbound_Ipv4()));
+		registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Persistent_PermitAll_Inbound_Ipv6()));
+		registry.insert(std::make_pair(WfpObjectType::Filter, Filter_Persistent_PermitAll_Outbound_Ipv6()));
 	}
 
 	return registry;
@@ -351,6 +362,62 @@ const GUID &MullvadGuids::Filter_Persistent_BlockAll_Inbound_Ipv6()
 	return g;
 }
 
+//static
+const GUID &MullvadGuids::Filter_Persistent_PermitAll_Outbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0x087c1982,
+		0x9d64,
+		0x47cd,
+		{ 0x99, 0xfd, 0xfc, 0x17, 0x81, 0xae, 0xaa, 0x71 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Persistent_PermitAll_Inbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0x35b0fd2b,
+		0xcf1c,
+		0x4e9a,
+		{ 0xaf, 0x68, 0x29, 0xcb, 0x9d, 0x15, 0xd4, 0xbb }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Persistent_PermitAll_Outbound_Ipv6()
+{
+	static const GUID g =
+	{
+		0x05d346f6,
+		0x2bb4,
+		0x4b53,
+		{ 0x9b, 0xfe, 0xea, 0xa6, 0xf7, 0xfd, 0xd5, 0x51 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Persistent_PermitAll_Inbound_Ipv6()
+{
+	static const GUID g =
+	{
+		0x7376ac3e,
+		0x2ee2,
+		0x46b2,
+		{ 0xb0, 0x53, 0x9c, 0x36, 0x1c, 0x08, 0x08, 0x5d }
+	};
+
+	return g;
+}
+
 //static
 const GUID &MullvadGuids::Filter_Baseline_BlockAll_Outbound_Ipv4()
 {
@@ -632,6 +699,34 @@ const GUID &MullvadGuids::Filter_Baseline_PermitDhcpServer_Outbound_Response_Ipv
 	return g;
 }
 
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitConnectionSharing_Inbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0x5173a840,
+		0x5c8a,
+		0x4afc,
+		{ 0xae, 0x63, 0xca, 0xbc, 0xc4, 0x8, 0xb1, 0x19 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitConnectionSharing_Outbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0xa2cee76f,
+		0x1ffd,
+		0x4619,
+		{ 0xb9, 0x83, 0x18, 0x7b, 0x44, 0xc4, 0xcd, 0xaf }
+	};
+
+	return g;
+}
+
 //static
 const GUID &MullvadGuids::Filter_Baseline_PermitVpnRelay()
 {
@@ -814,6 +909,62 @@ const GUID &MullvadGuids::Filter_Baseline_PermitNdp_Inbound_Redirect()
 	return g;
 }
 
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitMdns_Outbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0xeac018f8,
+		0xb312,
+		0x46ff,
+		{ 0x9d, 0x5c, 0xdf, 0x8c, 0x21, 0x52, 0xc, 0x53 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitMdns_Outbound_Ipv6()
+{
+	static const GUID g =
+	{
+		0xe20ff76,
+		0x78ee,
+		0x445a,
+		{ 0x8b, 0x8a, 0xb7, 0x23, 0x20, 0xff, 0xe7, 0x7a }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitMdns_Inbound_Ipv4()
+{
+	static const GUID g =
+	{
+		0xc331e346,
+		0x24d1,
+		0x4bed,
+		{ 0xa0, 0xd1, 0x99, 0xf8, 0xe0, 0xbb, 0xe9, 0x62 }
+	};
+
+	return g;
+}
+
+//static
+const GUID &MullvadGuids::Filter_Baseline_PermitMdns_Inbound_Ipv6()
+{
+	static const GUID g =
+	{
+		0xd62b9f8b,
+		0xec80,
+		0x49c4,
+		{ 0x89, 0x17, 0x29, 0x52, 0x57, 0xa3, 0xf1, 0xdd }
+	};
+
+	return g;
+}
+
 //static
 const GUID &MullvadGuids::Filter_Baseline_PermitDns_Outbound_Ipv4()
 {
@@ -67,6 +67,9 @@ public:
 	static const GUID &Filter_Baseline_PermitDhcpServer_Inbound_Request_Ipv4();
 	static const GUID &Filter_Baseline_PermitDhcpServer_Outbound_Response_Ipv4();
 
+	static const GUID &Filter_Baseline_PermitConnectionSharing_Inbound_Ipv4();
+	static const GUID &Filter_Baseline_PermitConnectionSharing_Outbound_Ipv4();
+
 	static const GUID &Filter_Baseline_PermitVpnRelay();
 
 	static const GUID &Filter_Baseline_PermitEndpoint();
@@ -85,6 +88,11 @@ public:
 	static const GUID &Filter_Baseline_PermitNdp_Inbound_Neighbor_Advertisement();
 	static const GUID &Filter_Baseline_PermitNdp_Inbound_Redirect();
 
+	static const GUID &Filter_Baseline_PermitMdns_Outbound_Ipv4();
+	static const GUID &Filter_Baseline_PermitMdns_Outbound_Ipv6();
+	static const GUID &Filter_Baseline_PermitMdns_Inbound_Ipv4();
+	static const GUID &Filter_Baseline_PermitMdns_Inbound_Ipv6();
+
 	static const GUID &Filter_Baseline_PermitDns_Outbound_Ipv4();
 	static const GUID &Filter_Baseline_PermitDns_Outbound_Ipv6();
 
@@ -111,6 +119,15 @@ public:
 	static const GUID &Filter_Persistent_BlockAll_Outbound_Ipv4();
 	static const GUID &Filter_Persistent_BlockAll_Inbound_Ipv6();
 	static const GUID &Filter_Persistent_BlockAll_Outbound_Ipv6();
+
+	//
+	// Non-persistent filters in the persistent sublayer
+	//
+
+	static const GUID &Filter_Persistent_PermitAll_Inbound_Ipv4();
+	static const GUID &Filter_Persistent_PermitAll_Outbound_Ipv4();
+	static const GUID &Filter_Persistent_PermitAll_Inbound_Ipv6();
+	static const GUID &Filter_Persistent_PermitAll_Outbound_Ipv6();
 };
 
 inline MullvadGuids::IdentityQualifier operator|(MullvadGuids::IdentityQualifier lhs, MullvadGuids::IdentityQualifier rhs)
@@ -70,6 +70,28 @@ ObjectPurger::RemovalFunctor ObjectPurger::GetRemoveNonPersistentFunctor()
 	};
 }
 
+//static
+ObjectPurger::RemovalFunctor ObjectPurger::GetRemovePersistentFunctor()
+{
+	return [](wfp::FilterEngine &engine)
+	{
+		const auto nonPersistent = MullvadGuids::Registry(MullvadGuids::IdentityQualifier::IncludeDeprecated);
+		auto registry = MullvadGuids::DetailedRegistry(MullvadGuids::IdentityQualifier::IncludeAll);
+
+		for (auto it = registry.begin(); it != registry.end();)
+		{
+			it = (0 != nonPersistent.count(it->second)) ? registry.erase(it) : std::next(it);
+		}
+
+		// Resolve correct overload.
+		void(*deleter)(wfp::FilterEngine &, const GUID &) = wfp::ObjectDeleter::DeleteFilter;
+
+		RemoveRange(engine, deleter, registry.equal_range(WfpObjectType::Filter));
+		RemoveRange(engine, wfp::ObjectDeleter::DeleteSublayer, registry.equal_range(WfpObjectType::Sublayer));
+		RemoveRange(engine, wfp::ObjectDeleter::DeleteProvider, registry.equal_range(WfpObjectType::Provider));
+	};
+}
+
 //static
 bool ObjectPurger::Execute(RemovalFunctor f)
 {
@@ -16,6 +16,7 @@ public:
 	static RemovalFunctor GetRemoveFiltersFunctor();
 	static RemovalFunctor GetRemoveAllFunctor();
 	static RemovalFunctor GetRemoveNonPersistentFunctor();
+	static RemovalFunctor GetRemovePersistentFunctor();
 
 	static bool Execute(RemovalFunctor f);
 };
@@ -0,0 +1,69 @@
+#include "stdafx.h"
+#include "permitconnectionsharing.h"
+#include <winfw/mullvadguids.h>
+#include <libwfp/filterbuilder.h>
+#include <libwfp/conditionbuilder.h>
+#include <libwfp/ipaddress.h>
+#include <libwfp/ipnetwork.h>
+#include <libwfp/conditions/conditionip.h>
+
+using namespace wfp::conditions;
+
+namespace rules::baseline
+{
+
+PermitConnectionSharing::PermitConnectionSharing(const wfp::IpNetwork &sharedNetwork)
+	: m_sharedNetwork(sharedNetwork)
+{
+}
+
+bool PermitConnectionSharing::apply(IObjectInstaller &objectInstaller)
+{
+	wfp::FilterBuilder filterBuilder;
+
+	//
+	// #1 Permit inbound connections from clients on the shared network.
+	//
+	// This covers the DNS proxy and other services that Internet Connection Sharing provides
+	// to its clients. Client traffic is NATed and forwarded through the tunnel, which is already
+	// permitted by the PermitVpnTunnel rule.
+	//
+
+	filterBuilder
+		.key(MullvadGuids::Filter_Baseline_PermitConnectionSharing_Inbound_Ipv4())
+		.name(L"Permit inbound connections from the shared network (IPv4)")
+		.description(L"This filter is part of a rule that permits Internet Connection Sharing")
+		.provider(MullvadGuids::Provider())
+		.layer(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4)
+		.sublayer(MullvadGuids::SublayerBaseline())
+		.weight(wfp::FilterBuilder::WeightClass::Medium)
+		.permit();
+
+	{
+		wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
+
+		conditionBuilder.add_condition(ConditionIp::Remote(m_sharedNetwork));
+
+		if (!objectInstaller.addFilter(filterBuilder, conditionBuilder))
+		{
+			return false;
+		}
+	}
+
+	//
+	// #2 Permit outbound connections to clients on the shared network.
+	//
+
+	filterBuilder
+		.key(MullvadGuids::Filter_Baseline_PermitConnectionSharing_Outbound_Ipv4())
+		.name(L"Permit outbound connections to the shared network (IPv4)")
+		.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+
+	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
+
+	conditionBuilder.add_condition(ConditionIp::Remote(m_sharedNetwork));
+
+	return objectInstaller.addFilter(filterBuilder, conditionBuilder);
+}
+
+}
@@ -0,0 +1,23 @@
+#pragma once
+
+#include <winfw/rules/ifirewallrule.h>
+#include <libwfp/ipnetwork.h>
+
+namespace rules::baseline
+{
+
+class PermitConnectionSharing : public IFirewallRule
+{
+public:
+
+	PermitConnectionSharing(const wfp::IpNetwork &sharedNetwork);
+	~PermitConnectionSharing() = default;
+
+	bool apply(IObjectInstaller &objectInstaller) override;
+
+private:
+
+	const wfp::IpNetwork m_sharedNetwork;
+};
+
+}
@@ -12,6 +12,33 @@ using namespace wfp::conditions;
 namespace rules::baseline
 {
 
+//static
+LanNetworks LanNetworks::PrivateNetworks()
+{
+	LanNetworks networks;
+
+	networks.ipv4.emplace_back(wfp::IpAddress::Literal({ 10, 0, 0, 0 }), 8);
+	networks.ipv4.emplace_back(wfp::IpAddress::Literal({ 172, 16, 0, 0 }), 12);
+	networks.ipv4.emplace_back(wfp::IpAddress::Literal({ 192, 168, 0, 0 }), 16);
+	networks.ipv4.emplace_back(wfp::IpAddress::Literal({ 169, 254, 0, 0 }), 16);
+
+	// Link-local and unique local.
+	networks.ipv6.emplace_back(wfp::IpAddress::Literal6({ 0xFE80, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }), 10);
+	networks.ipv6.emplace_back(wfp::IpAddress::Literal6({ 0xFC00, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }), 7);
+
+	return networks;
+}
+
+PermitLan::PermitLan()
+	: m_networks(LanNetworks::PrivateNetworks())
+{
+}
+
+PermitLan::PermitLan(LanNetworks networks)
+	: m_networks(std::move(networks))
+{
+}
+
 bool PermitLan::apply(IObjectInstaller &objectInstaller)
 {
 	return applyIpv4(objectInstaller) && applyIpv6(objectInstaller);
@@ -37,14 +64,22 @@ bool PermitLan::applyIpv4(IObjectInstaller &objectInstaller) const
 
 	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V4);
 
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 10, 0, 0, 0 }), 8)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 172, 16, 0, 0 }), 12)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 192, 168, 0, 0 }), 16)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 169, 254, 0, 0 }), 16)));
+	//
+	// A filter without conditions would match all traffic, so skip it if
+	// no IPv4 networks are permitted.
+	//
 
-	if (!objectInstaller.addFilter(filterBuilder, conditionBuilder))
+	if (!m_networks.ipv4.empty())
 	{
-		return false;
+		for (const auto &network : m_networks.ipv4)
+		{
+			conditionBuilder.add_condition(ConditionIp::Remote(network));
+		}
+
+		if (!objectInstaller.addFilter(filterBuilder, conditionBuilder))
+		{
+			return false;
+		}
 	}
 
 	//
@@ -89,15 +124,17 @@ bool PermitLan::applyIpv6(IObjectInstaller &objectInstaller) const
 
 	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
 
-	const wfp::IpNetwork linkLocal(wfp::IpAddress::Literal6({ 0xFE80, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }), 10);
-	const wfp::IpNetwork uniqueLocal(wfp::IpAddress::Literal6({ 0xFC00, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }), 7);
-
-	conditionBuilder.add_condition(ConditionIp::Remote(linkLocal));
-	conditionBuilder.add_condition(ConditionIp::Remote(uniqueLocal));
-
-	if (!objectInstaller.addFilter(filterBuilder, conditionBuilder))
+	if (!m_networks.ipv6.empty())
 	{
-		return false;
+		for (const auto &network : m_networks.ipv6)
+		{
+			conditionBuilder.add_condition(ConditionIp::Remote(network));
+		}
+
+		if (!objectInstaller.addFilter(filterBuilder, conditionBuilder))
+		{
+			return false;
+		}
 	}
 
 	//
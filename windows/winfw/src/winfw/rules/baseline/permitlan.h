@@ -1,15 +1,30 @@
 #pragma once
 
 #include <winfw/rules/ifirewallrule.h>
+#include <libwfp/ipnetwork.h>
+#include <vector>
 
 namespace rules::baseline
 {
 
+//
+// Private networks that LAN traffic is permitted to and from.
+//
+struct LanNetworks
+{
+	std::vector<wfp::IpNetwork> ipv4;
+	std::vector<wfp::IpNetwork> ipv6;
+
+	// All private IPv4 and IPv6 networks.
+	static LanNetworks PrivateNetworks();
+};
+
 class PermitLan : public IFirewallRule
 {
 public:
 
-	PermitLan() = default;
+	PermitLan();
+	explicit PermitLan(LanNetworks networks);
 	~PermitLan() = default;
 	
 	bool apply(IObjectInstaller &objectInstaller) override;
@@ -18,6 +33,8 @@ private:
 
 	bool applyIpv4(IObjectInstaller &objectInstaller) const;
 	bool applyIpv6(IObjectInstaller &objectInstaller) const;
+
+	const LanNetworks m_networks;
 };
 
 }
@@ -12,6 +12,16 @@ using namespace wfp::conditions;
 namespace rules::baseline
 {
 
+PermitLanService::PermitLanService()
+	: m_networks(LanNetworks::PrivateNetworks())
+{
+}
+
+PermitLanService::PermitLanService(LanNetworks networks)
+	: m_networks(std::move(networks))
+{
+}
+
 bool PermitLanService::apply(IObjectInstaller &objectInstaller)
 {
 	return applyIpv4(objectInstaller) && applyIpv6(objectInstaller);
@@ -35,12 +45,21 @@ bool PermitLanService::applyIpv4(IObjectInstaller &objectInstaller) const
 		.weight(wfp::FilterBuilder::WeightClass::Medium)
 		.permit();
 
+	//
+	// A filter without conditions would match all traffic.
+	//
+
+	if (m_networks.ipv4.empty())
+	{
+		return true;
+	}
+
 	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
 
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 10, 0, 0, 0 }), 8)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 172, 16, 0, 0 }), 12)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 192, 168, 0, 0 }), 16)));
-	conditionBuilder.add_condition(ConditionIp::Remote(wfp::IpNetwork(wfp::IpAddress::Literal({ 169, 254, 0, 0 }), 16)));
+	for (const auto &network : m_networks.ipv4)
+	{
+		conditionBuilder.add_condition(ConditionIp::Remote(network));
+	}
 
 	return objectInstaller.addFilter(filterBuilder, conditionBuilder);
 }
@@ -63,13 +82,17 @@ bool PermitLanService::applyIpv6(IObjectInstaller &objectInstaller) const
 		.weight(wfp::FilterBuilder::WeightClass::Medium)
 		.permit();
 
-	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
+	if (m_networks.ipv6.empty())
+	{
+		return true;
+	}
 
-	const wfp::IpNetwork linkLocal(wfp::IpAddress::Literal6{ 0xFE80, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }, 10);
-	const wfp::IpNetwork uniqueLocal(wfp::IpAddress::Literal6({ 0xFC00, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }), 7);
+	wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
 
-	conditionBuilder.add_condition(ConditionIp::Remote(linkLocal));
-	conditionBuilder.add_condition(ConditionIp::Remote(uniqueLocal));
+	for (const auto &network : m_networks.ipv6)
+	{
+		conditionBuilder.add_condition(ConditionIp::Remote(network));
+	}
 
 	return objectInstaller.addFilter(filterBuilder, conditionBuilder);
 }
@@ -1,6 +1,7 @@
 #pragma once
 
 #include <winfw/rules/ifirewallrule.h>
+#include "permitlan.h"
 
 namespace rules::baseline
 {
@@ -9,7 +10,8 @@ class PermitLanService : public IFirewallRule
 {
 public:
 
-	PermitLanService() = default;
+	PermitLanService();
+	explicit PermitLanService(LanNetworks networks);
 	~PermitLanService() = default;
 	
 	bool apply(IObjectInstaller &objectInstaller) override;
@@ -18,6 +20,8 @@ private:
 
 	bool applyIpv4(IObjectInstaller &objectInstaller) const;
 	bool applyIpv6(IObjectInstaller &objectInstaller) const;
+
+	const LanNetworks m_networks;
 };
 
 }
@@ -0,0 +1,23 @@
+#pragma once
+
+#include <winfw/rules/ifirewallrule.h>
+
+namespace rules::baseline
+{
+
+class PermitMdns : public IFirewallRule
+{
+public:
+
+	PermitMdns() = default;
+	~PermitMdns() = default;
+
+	bool apply(IObjectInstaller &objectInstaller) override;
+
+private:
+
+	bool applyIpv4(IObjectInstaller &objectInstaller) const;
+	bool applyIpv6(IObjectInstaller &objectInstaller) const;
+};
+
+}
@@ -14,6 +14,11 @@ using namespace wfp::conditions;
 namespace rules::baseline
 {
 
+PermitNdp::PermitNdp(bool permitRouterMessages)
+	: m_permitRouterMessages(permitRouterMessages)
+{
+}
+
 bool PermitNdp::apply(IObjectInstaller &objectInstaller)
 {
 	const wfp::IpNetwork linkLocal(wfp::IpAddress::Literal6({ 0xFE80, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0 }), 10);
@@ -50,16 +55,17 @@ bool PermitNdp::apply(IObjectInstaller &objectInstaller)
 		}
 	}
 
-	//
-	// #2 Permit inbound router advertisement.
-	//
+	if (m_permitRouterMessages)
+	{
+		//
+		// #2 Permit inbound router advertisement.
+		//
 
-	filterBuilder
-		.key(MullvadGuids::Filter_Baseline_PermitNdp_Inbound_Router_Advertisement())
-		.name(L"Permit inbound NDP router advertisement")
-		.layer(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
+		filterBuilder
+			.key(MullvadGuids::Filter_Baseline_PermitNdp_Inbound_Router_Advertisement())
+			.name(L"Permit inbound NDP router advertisement")
+			.layer(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
 
-	{
 		wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
 
 		conditionBuilder.add_condition(ConditionProtocol::IcmpV6());
@@ -73,16 +79,17 @@ bool PermitNdp::apply(IObjectInstaller &objectInstaller)
 		}
 	}
 
-	//
-	// #3 Permit inbound redirect message.
-	//
+	if (m_permitRouterMessages)
+	{
+		//
+		// #3 Permit inbound redirect message.
+		//
 
-	filterBuilder
-		.key(MullvadGuids::Filter_Baseline_PermitNdp_Inbound_Redirect())
-		.name(L"Permit inbound NDP redirect")
-		.layer(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
+		filterBuilder
+			.key(MullvadGuids::Filter_Baseline_PermitNdp_Inbound_Redirect())
+			.name(L"Permit inbound NDP redirect")
+			.layer(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
 
-	{
 		wfp::ConditionBuilder conditionBuilder(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
 
 		conditionBuilder.add_condition(ConditionProtocol::IcmpV6());
@@ -9,10 +9,18 @@ class PermitNdp : public IFirewallRule
 {
 public:
 
-	PermitNdp() = default;
+	//
+	// Router advertisements and redirects are only permitted if `permitRouterMessages` is set.
+	// They are not needed while connected, when all traffic goes through the tunnel.
+	//
+	explicit PermitNdp(bool permitRouterMessages);
 	~PermitNdp() = default;
 	
 	bool apply(IObjectInstaller &objectInstaller) override;
+
+private:
+
+	const bool m_permitRouterMessages;
 };
 
 }
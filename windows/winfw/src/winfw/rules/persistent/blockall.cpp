@@ -7,14 +7,10 @@
 namespace rules::persistent
 {
 
-bool BlockAll::apply(IObjectInstaller &objectInstaller)
+bool BootTimeBlockAll::apply(IObjectInstaller &objectInstaller)
 {
 	wfp::FilterBuilder filterBuilder;
 
-	//
-	// Add boot-time filters (i.e., filters applied before BFE starts)
-	//
-
 	filterBuilder
 		.key(MullvadGuids::Filter_Boottime_BlockAll_Outbound_Ipv4())
 		.name(L"Block all outbound connections (IPv4)")
@@ -58,7 +54,16 @@ bool BlockAll::apply(IObjectInstaller &objectInstaller)
 		.name(L"Block all inbound connections (IPv6)")
 		.layer(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
 
-	if (false == objectInstaller.addFilter(filterBuilder, nullConditionBuilder))
+	return objectInstaller.addFilter(filterBuilder, nullConditionBuilder);
+}
+
+bool BlockAll::apply(IObjectInstaller &objectInstaller)
+{
+	//
+	// Add boot-time filters (i.e., filters applied before BFE starts)
+	//
+
+	if (false == BootTimeBlockAll().apply(objectInstaller))
 	{
 		return false;
 	}
@@ -66,8 +71,12 @@ bool BlockAll::apply(IObjectInstaller &objectInstaller)
 	//
 	// Add persistent filters (i.e., filters applied when BFE has started)
 	//
+	// These have the lowest weight in the sublayer, so that PermitAll
+	// can lift them while the system is running.
+	//
 
 	wfp::FilterBuilder persistentFilterBuilder;
+	wfp::NullConditionBuilder nullConditionBuilder;
 
 	persistentFilterBuilder
 		.key(MullvadGuids::Filter_Persistent_BlockAll_Outbound_Ipv4())
@@ -76,7 +85,7 @@ bool BlockAll::apply(IObjectInstaller &objectInstaller)
 		.provider(MullvadGuids::ProviderPersistent())
 		.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V4)
 		.sublayer(MullvadGuids::SublayerPersistent())
-		.weight(wfp::FilterBuilder::WeightClass::Max)
+		.weight(wfp::FilterBuilder::WeightClass::Min)
 		.persistent()
 		.block();
 
@@ -5,6 +5,25 @@
 namespace rules::persistent
 {
 
+//
+// Blocks all traffic from the time the TCP/IP driver is loaded until BFE starts.
+// Boot-time filters are not enforced while BFE is running, so this rule
+// can remain installed alongside any active policy.
+//
+class BootTimeBlockAll : public IFirewallRule
+{
+public:
+
+	BootTimeBlockAll() = default;
+	~BootTimeBlockAll() = default;
+
+	bool apply(IObjectInstaller &objectInstaller) override;
+};
+
+//
+// Blocks all traffic, both during boot and after BFE has started,
+// until WinFw is reinitialized or PermitAll is applied.
+//
 class BlockAll : public IFirewallRule
 {
 public:
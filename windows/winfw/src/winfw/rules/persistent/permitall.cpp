@@ -0,0 +1,58 @@
+#include "stdafx.h"
+#include "permitall.h"
+#include <winfw/mullvadguids.h>
+#include <libwfp/filterbuilder.h>
+#include <libwfp/nullconditionbuilder.h>
+
+namespace rules::persistent
+{
+
+bool PermitAll::apply(IObjectInstaller &objectInstaller)
+{
+	wfp::FilterBuilder filterBuilder;
+	wfp::NullConditionBuilder nullConditionBuilder;
+
+	filterBuilder
+		.key(MullvadGuids::Filter_Persistent_PermitAll_Outbound_Ipv4())
+		.name(L"Permit all outbound connections (IPv4)")
+		.description(L"This filter is part of a rule that lifts the persistent blocking filters while the system is running")
+		.provider(MullvadGuids::ProviderPersistent())
+		.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V4)
+		.sublayer(MullvadGuids::SublayerPersistent())
+		.weight(wfp::FilterBuilder::WeightClass::Max)
+		.permit();
+
+	if (false == objectInstaller.addFilter(filterBuilder, nullConditionBuilder))
+	{
+		return false;
+	}
+
+	filterBuilder
+		.key(MullvadGuids::Filter_Persistent_PermitAll_Inbound_Ipv4())
+		.name(L"Permit all inbound connections (IPv4)")
+		.layer(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V4);
+
+	if (false == objectInstaller.addFilter(filterBuilder, nullConditionBuilder))
+	{
+		return false;
+	}
+
+	filterBuilder
+		.key(MullvadGuids::Filter_Persistent_PermitAll_Outbound_Ipv6())
+		.name(L"Permit all outbound connections (IPv6)")
+		.layer(FWPM_LAYER_ALE_AUTH_CONNECT_V6);
+
+	if (false == objectInstaller.addFilter(filterBuilder, nullConditionBuilder))
+	{
+		return false;
+	}
+
+	filterBuilder
+		.key(MullvadGuids::Filter_Persistent_PermitAll_Inbound_Ipv6())
+		.name(L"Permit all inbound connections (IPv6)")
+		.layer(FWPM_LAYER_ALE_AUTH_RECV_ACCEPT_V6);
+
+	return objectInstaller.addFilter(filterBuilder, nullConditionBuilder);
+}
+
+}
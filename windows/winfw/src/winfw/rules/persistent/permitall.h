@@ -0,0 +1,24 @@
+#pragma once
+
+#include <winfw/rules/ifirewallrule.h>
+
+namespace rules::persistent
+{
+
+//
+// Permits all traffic in the persistent sublayer, overriding the persistent
+// blocking filters while the system is running. These filters are not
+// persistent, so traffic is blocked again after a reboot, until the
+// active policy has been applied.
+//
+class PermitAll : public IFirewallRule
+{
+public:
+
+	PermitAll() = default;
+	~PermitAll() = default;
+
+	bool apply(IObjectInstaller &objectInstaller) override;
+};
+
+}
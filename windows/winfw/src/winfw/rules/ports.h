@@ -14,6 +14,8 @@ enum Ports : uint16_t
 	DHCPV6_SERVER_PORT = 547,
 
 	DNS_SERVER_PORT = 53,
+
+	MDNS_PORT = 5353,
 };
 
 }
@@ -236,6 +236,32 @@ void SessionController::reset()
 	rewindState(m_transactionRecords.size());
 }
 
+bool SessionController::verify()
+{
+	bool intact = true;
+
+	const auto status = executeReadOnlyTransaction([this, &intact](SessionController &, wfp::FilterEngine &engine)
+	{
+		for (const auto &record : m_records)
+		{
+			if (false == record.exists(engine))
+			{
+				intact = false;
+				break;
+			}
+		}
+
+		return true;
+	});
+
+	if (false == status)
+	{
+		THROW_ERROR("Failed to read filter engine state");
+	}
+
+	return intact;
+}
+
 void SessionController::rewindState(size_t steps)
 {
 	auto purged = 0;
@@ -48,6 +48,12 @@ public:
 	//
 	void reset();
 
+	//
+	// Check whether all objects in the stack are still present in the filter engine
+	// This should be done outside of an active transaction
+	//
+	bool verify();
+
 private:
 
 	SessionController(const SessionController &) = delete;
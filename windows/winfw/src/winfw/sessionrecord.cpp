@@ -1,6 +1,7 @@
 #include "stdafx.h"
 #include "sessionrecord.h"
 #include "libwfp/objectdeleter.h"
+#include <fwpmu.h>
 #include <libcommon/error.h>
 #include <atomic>
 #include <cstdint>
@@ -52,6 +53,66 @@ void SessionRecord::purge(wfp::FilterEngine &engine)
 	};
 }
 
+bool SessionRecord::exists(wfp::FilterEngine &engine) const
+{
+	DWORD status = ERROR_SUCCESS;
+
+	switch (m_type)
+	{
+		case WfpObjectType::Provider:
+		{
+			FWPM_PROVIDER0 *provider = nullptr;
+			status = FwpmProviderGetByKey0(engine.session(), &m_id, &provider);
+			if (ERROR_SUCCESS == status)
+			{
+				FwpmFreeMemory0(reinterpret_cast<void **>(&provider));
+				return true;
+			}
+			if (FWP_E_PROVIDER_NOT_FOUND == status)
+			{
+				return false;
+			}
+			break;
+		}
+		case WfpObjectType::Sublayer:
+		{
+			FWPM_SUBLAYER0 *sublayer = nullptr;
+			status = FwpmSubLayerGetByKey0(engine.session(), &m_id, &sublayer);
+			if (ERROR_SUCCESS == status)
+			{
+				FwpmFreeMemory0(reinterpret_cast<void **>(&sublayer));
+				return true;
+			}
+			if (FWP_E_SUBLAYER_NOT_FOUND == status)
+			{
+				return false;
+			}
+			break;
+		}
+		case WfpObjectType::Filter:
+		{
+			FWPM_FILTER0 *filter = nullptr;
+			status = FwpmFilterGetById0(engine.session(), m_filterId, &filter);
+			if (ERROR_SUCCESS == status)
+			{
+				FwpmFreeMemory0(reinterpret_cast<void **>(&filter));
+				return true;
+			}
+			if (FWP_E_FILTER_NOT_FOUND == status)
+			{
+				return false;
+			}
+			break;
+		}
+		default:
+		{
+			THROW_ERROR("Missing case handler in switch clause");
+		}
+	};
+
+	THROW_ERROR("Failed to look up WFP object");
+}
+
 uint32_t SessionRecord::key() const
 {
 	return m_key;
@@ -18,6 +18,8 @@ public:
 
 	void purge(wfp::FilterEngine &engine);
 
+	bool exists(wfp::FilterEngine &engine) const;
+
 	uint32_t key() const;
 
 private:
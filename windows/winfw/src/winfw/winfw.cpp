@@ -4,6 +4,7 @@
 #include "objectpurger.h"
 #include "mullvadobjects.h"
 #include "rules/persistent/blockall.h"
+#include "rules/persistent/permitall.h"
 #include "libwfp/ipnetwork.h"
 #include <windows.h>
 #include <libcommon/error.h>
@@ -14,6 +15,7 @@ namespace
 {
 
 constexpr uint32_t DEINITIALIZE_TIMEOUT = 5000;
+constexpr uint32_t BOOT_TIME_BLOCKING_TIMEOUT = 5000;
 
 MullvadLogSink g_logSink = nullptr;
 void *g_logSinkContext = nullptr;
@@ -203,6 +205,12 @@ WinFw_Deinitialize(WINFW_CLEANUP_POLICY cleanupPolicy)
 			{
 				ObjectPurger::GetRemoveNonPersistentFunctor()(engine);
 
+				//
+				// Boot-time filters may already have been installed
+				// by WinFw_SetBootTimeBlocking().
+				//
+				ObjectPurger::GetRemovePersistentFunctor()(engine);
+
 				return controller.addProvider(*MullvadObjects::ProviderPersistent())
 					&& controller.addSublayer(*MullvadObjects::SublayerPersistent())
 					&& blockAll.apply(controller);
@@ -326,11 +334,6 @@ WinFw_ApplyPolicyConnected(
 			THROW_ERROR("Invalid argument: tunnelInterfaceAlias");
 		}
 
-		if (nullptr == v4Gateway)
-		{
-			THROW_ERROR("Invalid argument: v4Gateway");
-		}
-
 		if (nullptr == dnsServers)
 		{
 			THROW_ERROR("Invalid argument: dnsServers");
@@ -339,7 +342,9 @@ WinFw_ApplyPolicyConnected(
 		std::vector<wfp::IpAddress> tunnelDnsServers;
 		std::vector<wfp::IpAddress> nonTunnelDnsServers;
 
-		const auto v4GatewayIp = wfp::IpAddress(v4Gateway);
+		const auto v4GatewayIp = (nullptr != v4Gateway)
+			? std::make_optional(wfp::IpAddress(v4Gateway))
+			: std::nullopt;
 		const auto v6GatewayIp = (nullptr != v6Gateway)
 			? std::make_optional(wfp::IpAddress(v6Gateway))
 			: std::nullopt;
@@ -507,3 +512,92 @@ WinFw_Reset()
 		return WINFW_POLICY_STATUS_GENERAL_FAILURE;
 	}
 }
+
+WINFW_LINKAGE
+WINFW_POLICY_STATUS
+WINFW_API
+WinFw_VerifyPolicy(bool *intact)
+{
+	if (nullptr == g_fwContext || nullptr == intact)
+	{
+		return WINFW_POLICY_STATUS_GENERAL_FAILURE;
+	}
+
+	try
+	{
+		*intact = g_fwContext->verifyPolicy();
+		return WINFW_POLICY_STATUS_SUCCESS;
+	}
+	catch (common::error::WindowsException &err)
+	{
+		return HandlePolicyException(err);
+	}
+	catch (std::exception &err)
+	{
+		if (nullptr != g_logSink)
+		{
+			g_logSink(MULLVAD_LOG_LEVEL_ERROR, err.what(), g_logSinkContext);
+		}
+
+		return WINFW_POLICY_STATUS_GENERAL_FAILURE;
+	}
+	catch (...)
+	{
+		return WINFW_POLICY_STATUS_GENERAL_FAILURE;
+	}
+}
+
+WINFW_LINKAGE
+WINFW_POLICY_STATUS
+WINFW_API
+WinFw_SetBootTimeBlocking(bool enable)
+{
+	try
+	{
+		auto engine = wfp::FilterEngine::StandardSession(BOOT_TIME_BLOCKING_TIMEOUT);
+		auto sessionController = std::make_unique<SessionController>(std::move(engine));
+
+		rules::persistent::BlockAll blockAll;
+		rules::persistent::PermitAll permitAll;
+
+		return sessionController->executeTransaction([&](SessionController &controller, wfp::FilterEngine &engine)
+		{
+			ObjectPurger::GetRemovePersistentFunctor()(engine);
+
+			if (false == enable)
+			{
+				return true;
+			}
+
+			//
+			// The persistent filters block traffic after BFE has started, until
+			// the active policy is applied. The non-persistent PermitAll filters
+			// lift them until the next reboot, so that the active policy decides.
+			//
+
+			return controller.addProvider(*MullvadObjects::ProviderPersistent())
+				&& controller.addSublayer(*MullvadObjects::SublayerPersistent())
+				&& blockAll.apply(controller)
+				&& permitAll.apply(controller);
+		})
+			? WINFW_POLICY_STATUS_SUCCESS
+			: WINFW_POLICY_STATUS_GENERAL_FAILURE;
+	}
+	catch (common::error::WindowsException &err)
+	{
+		return HandlePolicyException(err);
+	}
+	catch (std::exception &err)
+	{
+		if (nullptr != g_logSink)
+		{
+			g_logSink(MULLVAD_LOG_LEVEL_ERROR, err.what(), g_logSinkContext);
+		}
+
+		return WINFW_POLICY_STATUS_GENERAL_FAILURE;
+	}
+	catch (...)
+	{
+		return WINFW_POLICY_STATUS_GENERAL_FAILURE;
+	}
+}
@@ -19,6 +19,13 @@
 // Structures
 ///////////////////////////////////////////////////////////////////////////////
 
+typedef struct tag_WinFwLanNetwork
+{
+	const wchar_t *ip;
+	uint8_t prefix;
+}
+WinFwLanNetwork;
+
 typedef struct tag_WinFwSettings
 {
 	// Permit outbound DHCP requests and inbound DHCP responses on all interfaces.
@@ -26,6 +33,21 @@ typedef struct tag_WinFwSettings
 
 	// Permit all traffic to and from private address ranges.
 	bool permitLan;
+
+	// Private networks that LAN traffic is limited to when permitLan is set.
+	// nullptr if all private address ranges should be permitted.
+	const WinFwLanNetwork *lanNetworks;
+	uint32_t numLanNetworks;
+
+	// Permit traffic to and from clients on a network shared using Internet Connection Sharing
+	// or Mobile Hotspot. nullptr if connection sharing should not be permitted.
+	const wchar_t *sharedNetworkIp;
+
+	// Prefix length of the shared network.
+	uint8_t sharedNetworkPrefix;
+
+	// Permit multicast DNS to and from the mDNS multicast groups, on all interfaces.
+	bool permitMdns;
 }
 WinFwSettings;
 
@@ -167,6 +189,10 @@ WinFw_ApplyPolicyConnecting(
 //
 // tunnelInterfaceAlias:
 //   Friendly name of VPN tunnel interface
+// v4Gateway:
+//   IPv4 gateway of the tunnel, or nullptr if the tunnel has no IPv4 address
+// v6Gateway:
+//   IPv6 gateway of the tunnel, or nullptr if the tunnel has no IPv6 address
 // dnsServers:
 //   Array of string-encoded IP addresses of DNS servers to use
 //
@@ -210,3 +236,41 @@ WINFW_LINKAGE
 WINFW_POLICY_STATUS
 WINFW_API
 WinFw_Reset();
+
+//
+// VerifyPolicy:
+//
+// Check whether the policy in effect is still enforced. Every provider,
+// sublayer and filter added for the policy is looked up in the filter engine,
+// and `intact` is set to false if any of them have been removed.
+//
+extern "C"
+WINFW_LINKAGE
+WINFW_POLICY_STATUS
+WINFW_API
+WinFw_VerifyPolicy(
+	bool *intact
+);
+
+//
+// SetBootTimeBlocking:
+//
+// Install or remove filters that block all traffic during boot, from the time
+// the TCP/IP driver is loaded until a policy is applied after the next start.
+//
+// This consists of boot-time filters, which are enforced until BFE starts, and
+// persistent filters, which are enforced once BFE has started. The persistent
+// filters are lifted by non-persistent filters until the next reboot, so they
+// do not interfere with the active policy. Keeping them installed ensures that
+// traffic is blocked during boot even if the process did not exit cleanly.
+//
+// Initializing WinFw removes these filters, so this function must be called
+// again after initialization.
+//
+extern "C"
+WINFW_LINKAGE
+WINFW_POLICY_STATUS
+WINFW_API
+WinFw_SetBootTimeBlocking(
+	bool enable
+);
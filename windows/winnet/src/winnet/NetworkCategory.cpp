@@ -0,0 +1,290 @@
+#include "stdafx.h"
+#include "NetworkCategory.h"
+#include <libcommon/error.h>
+#include <libcommon/string.h>
+#include <atomic>
+#include <sstream>
+
+using Microsoft::WRL::ComPtr;
+
+namespace
+{
+
+void UpdateCategory(PublicNetworkCategoryPin::State &state)
+{
+	std::scoped_lock<std::mutex> lock(state.lock);
+
+	if (!state.networkListManager)
+	{
+		return;
+	}
+
+	ComPtr<IEnumNetworkConnections> connections;
+
+	auto status = state.networkListManager->GetNetworkConnections(&connections);
+
+	if (FAILED(status))
+	{
+		state.logSink->error("Failed to enumerate network connections");
+		return;
+	}
+
+	for (;;)
+	{
+		ComPtr<INetworkConnection> connection;
+
+		if (S_OK != connections->Next(1, &connection, nullptr))
+		{
+			break;
+		}
+
+		GUID adapterGuid;
+
+		if (FAILED(connection->GetAdapterId(&adapterGuid))
+			|| FALSE == IsEqualGUID(adapterGuid, state.adapterGuid))
+		{
+			continue;
+		}
+
+		ComPtr<INetwork> network;
+		NLM_NETWORK_CATEGORY category;
+
+		if (FAILED(connection->GetNetwork(&network))
+			|| FAILED(network->GetCategory(&category))
+			|| NLM_NETWORK_CATEGORY_PUBLIC == category)
+		{
+			continue;
+		}
+
+		status = network->SetCategory(NLM_NETWORK_CATEGORY_PUBLIC);
+
+		if (FAILED(status))
+		{
+			std::stringstream ss;
+
+			ss << "Failed to set the category of the tunnel network to public: 0x"
+				<< std::hex << status;
+
+			state.logSink->error(ss.str().c_str());
+			continue;
+		}
+
+		state.logSink->info("Set the category of the tunnel network to public");
+	}
+}
+
+//
+// Receives notifications from the network list manager.
+//
+class NetworkEventSink : public INetworkEvents
+{
+public:
+
+	NetworkEventSink(std::shared_ptr<PublicNetworkCategoryPin::State> state)
+		: m_refCount(1)
+		, m_state(state)
+	{
+	}
+
+	HRESULT STDMETHODCALLTYPE QueryInterface(REFIID riid, void **object) override
+	{
+		if (nullptr == object)
+		{
+			return E_POINTER;
+		}
+
+		if (IsEqualIID(riid, IID_IUnknown) || IsEqualIID(riid, IID_INetworkEvents))
+		{
+			*object = static_cast<INetworkEvents *>(this);
+			AddRef();
+			return S_OK;
+		}
+
+		*object = nullptr;
+		return E_NOINTERFACE;
+	}
+
+	ULONG STDMETHODCALLTYPE AddRef() override
+	{
+		return ++m_refCount;
+	}
+
+	ULONG STDMETHODCALLTYPE Release() override
+	{
+		const auto refCount = --m_refCount;
+
+		if (0 == refCount)
+		{
+			delete this;
+		}
+
+		return refCount;
+	}
+
+	HRESULT STDMETHODCALLTYPE NetworkAdded(GUID) override
+	{
+		UpdateCategory(*m_state);
+		return S_OK;
+	}
+
+	HRESULT STDMETHODCALLTYPE NetworkDeleted(GUID) override
+	{
+		return S_OK;
+	}
+
+	HRESULT STDMETHODCALLTYPE NetworkConnectivityChanged(GUID, NLM_CONNECTIVITY) override
+	{
+		UpdateCategory(*m_state);
+		return S_OK;
+	}
+
+	HRESULT STDMETHODCALLTYPE NetworkPropertyChanged(GUID, NLM_NETWORK_PROPERTY_CHANGE) override
+	{
+		UpdateCategory(*m_state);
+		return S_OK;
+	}
+
+private:
+
+	std::atomic<ULONG> m_refCount;
+	std::shared_ptr<PublicNetworkCategoryPin::State> m_state;
+};
+
+} // anonymous namespace
+
+PublicNetworkCategoryPin::PublicNetworkCategoryPin
+(
+	const GUID &adapterGuid,
+	std::shared_ptr<common::logging::ILogSink> logSink
+)
+	: m_state(std::make_shared<State>())
+{
+	m_state->adapterGuid = adapterGuid;
+	m_state->logSink = logSink;
+
+	m_stopEvent = CreateEventW(nullptr, TRUE, FALSE, nullptr);
+
+	if (nullptr == m_stopEvent)
+	{
+		THROW_WINDOWS_ERROR(GetLastError(), "CreateEventW");
+	}
+
+	std::promise<void> started;
+	auto startedFuture = started.get_future();
+
+	m_thread = std::thread(&PublicNetworkCategoryPin::run, this, std::ref(started));
+
+	try
+	{
+		startedFuture.get();
+	}
+	catch (...)
+	{
+		m_thread.join();
+		CloseHandle(m_stopEvent);
+		throw;
+	}
+}
+
+PublicNetworkCategoryPin::~PublicNetworkCategoryPin()
+{
+	SetEvent(m_stopEvent);
+	m_thread.join();
+	CloseHandle(m_stopEvent);
+}
+
+void PublicNetworkCategoryPin::run(std::promise<void> &started)
+{
+	auto status = CoInitializeEx(nullptr, COINIT_MULTITHREADED);
+
+	if (FAILED(status))
+	{
+		try
+		{
+			THROW_WINDOWS_ERROR(status, "CoInitializeEx");
+		}
+		catch (...)
+		{
+			started.set_exception(std::current_exception());
+		}
+		return;
+	}
+
+	ComPtr<IConnectionPoint> connectionPoint;
+	DWORD adviseCookie = 0;
+
+	try
+	{
+		ComPtr<INetworkListManager> networkListManager;
+
+		status = CoCreateInstance(CLSID_NetworkListManager, nullptr, CLSCTX_ALL,
+			IID_PPV_ARGS(&networkListManager));
+
+		if (FAILED(status))
+		{
+			THROW_WINDOWS_ERROR(status, "Create network list manager");
+		}
+
+		ComPtr<IConnectionPointContainer> container;
+
+		status = networkListManager.As(&container);
+
+		if (SUCCEEDED(status))
+		{
+			status = container->FindConnectionPoint(IID_INetworkEvents, &connectionPoint);
+		}
+
+		if (FAILED(status))
+		{
+			THROW_WINDOWS_ERROR(status, "Find network events connection point");
+		}
+
+		{
+			std::scoped_lock<std::mutex> lock(m_state->lock);
+			m_state->networkListManager = networkListManager;
+		}
+
+		ComPtr<NetworkEventSink> sink;
+		sink.Attach(new NetworkEventSink(m_state));
+
+		status = connectionPoint->Advise(sink.Get(), &adviseCookie);
+
+		if (FAILED(status))
+		{
+			THROW_WINDOWS_ERROR(status, "Register for network events");
+		}
+	}
+	catch (...)
+	{
+		{
+			std::scoped_lock<std::mutex> lock(m_state->lock);
+			m_state->networkListManager.Reset();
+		}
+
+		connectionPoint.Reset();
+		CoUninitialize();
+
+		started.set_exception(std::current_exception());
+		return;
+	}
+
+	started.set_value();
+
+	//
+	// The network may already have been identified.
+	//
+
+	UpdateCategory(*m_state);
+
+	WaitForSingleObject(m_stopEvent, INFINITE);
+
+	connectionPoint->Unadvise(adviseCookie);
+	connectionPoint.Reset();
+
+	{
+		std::scoped_lock<std::mutex> lock(m_state->lock);
+		m_state->networkListManager.Reset();
+	}
+
+	CoUninitialize();
+}
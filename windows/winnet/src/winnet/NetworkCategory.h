@@ -0,0 +1,59 @@
+#pragma once
+
+#include <libcommon/logging/ilogsink.h>
+#include <windows.h>
+#include <netlistmgr.h>
+#include <wrl/client.h>
+#include <future>
+#include <memory>
+#include <mutex>
+#include <thread>
+
+//
+// Keeps the category of the network that an adapter is connected to set to "Public", so that
+// Windows does not ask whether the network should be discoverable.
+//
+// Windows identifies the network some time after the adapter comes up, and the category may be
+// changed by others later on. So the category is set again whenever the network list manager
+// reports that a network has been added or changed.
+//
+class PublicNetworkCategoryPin
+{
+public:
+
+	PublicNetworkCategoryPin(const GUID &adapterGuid, std::shared_ptr<common::logging::ILogSink> logSink);
+	~PublicNetworkCategoryPin();
+
+	PublicNetworkCategoryPin(const PublicNetworkCategoryPin &) = delete;
+	PublicNetworkCategoryPin(PublicNetworkCategoryPin &&) = delete;
+	PublicNetworkCategoryPin &operator=(const PublicNetworkCategoryPin &) = delete;
+	PublicNetworkCategoryPin &operator=(PublicNetworkCategoryPin &&) = delete;
+
+	//
+	// State that is shared with the event sink, which may be called by COM on any thread.
+	//
+	struct State
+	{
+		GUID adapterGuid;
+		std::shared_ptr<common::logging::ILogSink> logSink;
+
+		std::mutex lock;
+
+		// Reset when the pin is removed, so that late events are ignored.
+		Microsoft::WRL::ComPtr<INetworkListManager> networkListManager;
+	};
+
+private:
+
+	std::shared_ptr<State> m_state;
+
+	HANDLE m_stopEvent;
+
+	//
+	// COM objects are created and released on a dedicated thread in the multithreaded apartment,
+	// since the thread of the caller may belong to another apartment.
+	//
+	std::thread m_thread;
+
+	void run(std::promise<void> &started);
+};
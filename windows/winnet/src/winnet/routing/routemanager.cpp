@@ -22,6 +22,16 @@ namespace
 
 using Adapters = common::network::Adapters;
 
+const uint32_t POINT_TWO_SECOND_BURST = 200;
+const uint32_t TWO_SECOND_INTERFERENCE = 2000;
+
+//
+// Backoff used when adding back routes that keep being removed.
+//
+const std::chrono::milliseconds INITIAL_RESTORE_DELAY{ 1000 };
+const std::chrono::milliseconds MAX_RESTORE_DELAY{ 2 * 60 * 1000 };
+const std::chrono::milliseconds RESTORE_DELAY_RESET{ 10 * 60 * 1000 };
+
 NET_LUID InterfaceLuidFromGateway(const NodeAddress &gateway)
 {
 	const DWORD adapterFlags = GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST | GAA_FLAG_SKIP_DNS_SERVER
@@ -201,7 +211,18 @@ RouteManager::RouteManager(std::shared_ptr<common::logging::ILogSink> logSink)
 		std::bind(&RouteManager::defaultRouteChanged, this, static_cast<ADDRESS_FAMILY>(AF_INET6), _1, _2),
 		logSink
 	))
+	, m_restoreRoutesGuard(std::make_unique<common::BurstGuard>(
+		std::bind(&RouteManager::restoreDeletedRoutes, this),
+		POINT_TWO_SECOND_BURST,
+		TWO_SECOND_INTERFERENCE
+	))
 {
+	const auto status = NotifyRouteChange2(AF_UNSPEC, RouteChangeCallback, this, FALSE, &m_routeNotificationHandle);
+
+	if (NO_ERROR != status)
+	{
+		THROW_WINDOWS_ERROR(status, "Register for route table change notifications");
+	}
 }
 
 RouteManager::~RouteManager()
@@ -210,6 +231,9 @@ RouteManager::~RouteManager()
 	// Stop callbacks that are triggered by events in Windows from coming in.
 	//
 
+	CancelMibChangeNotify2(m_routeNotificationHandle);
+	m_restoreRoutesGuard.reset();
+
 	m_routeMonitorV4.reset();
 	m_routeMonitorV6.reset();
 
@@ -644,4 +668,118 @@ void RouteManager::defaultRouteChanged(ADDRESS_FAMILY family, DefaultRouteMonito
 	}
 }
 
+//static
+void NETIOAPI_API_ RouteManager::RouteChangeCallback
+(
+	void *context,
+	MIB_IPFORWARD_ROW2 *,
+	MIB_NOTIFICATION_TYPE notificationType
+)
+{
+	//
+	// We're only interested in routes being removed.
+	//
+
+	if (MibDeleteInstance != notificationType)
+	{
+		return;
+	}
+
+	reinterpret_cast<RouteManager *>(context)->m_restoreRoutesGuard->trigger();
+}
+
+void RouteManager::restoreDeletedRoutes()
+{
+	AutoLockType routesLock(m_routesLock);
+
+	const auto now = std::chrono::steady_clock::now();
+
+	for (auto &record : m_routes)
+	{
+		if (routePresentInRoutingTable(record.registeredRoute))
+		{
+			continue;
+		}
+
+		//
+		// Back off if the route keeps being removed, so as to not fight with another
+		// route manager. Routes that are held back are checked again on the next
+		// change to the routing table.
+		//
+
+		auto &state = record.restoreState;
+
+		if (state.lastRestore.has_value())
+		{
+			const auto elapsed = now - state.lastRestore.value();
+
+			if (elapsed < state.delay)
+			{
+				const auto msg = std::wstring(L"Waiting before adding back route: ")
+					.append(FormatRegisteredRoute(record.registeredRoute));
+
+				m_logSink->info(common::string::ToAnsi(msg).c_str());
+
+				continue;
+			}
+
+			state.delay = (elapsed >= RESTORE_DELAY_RESET)
+				? INITIAL_RESTORE_DELAY
+				: std::min(state.delay * 2, MAX_RESTORE_DELAY);
+		}
+		else
+		{
+			state.delay = INITIAL_RESTORE_DELAY;
+		}
+
+		state.lastRestore = now;
+
+		const auto msg = std::wstring(L"Route was removed. Adding it back: ")
+			.append(FormatRegisteredRoute(record.registeredRoute));
+
+		m_logSink->warning(common::string::ToAnsi(msg).c_str());
+
+		try
+		{
+			restoreIntoRoutingTable(record.registeredRoute);
+		}
+		catch (const std::exception &ex)
+		{
+			const auto err = std::string("Failed to add back removed route: ").append(ex.what());
+
+			m_logSink->error(err.c_str());
+		}
+	}
+}
+
+bool RouteManager::routePresentInRoutingTable(const RegisteredRoute &route)
+{
+	MIB_IPFORWARD_ROW2 r = { 0 };
+
+	r.InterfaceLuid = route.luid;
+	r.DestinationPrefix = route.network;
+	r.NextHop = route.nextHop;
+
+	const auto status = GetIpForwardEntry2(&r);
+
+	if (ERROR_NOT_FOUND == status)
+	{
+		return false;
+	}
+
+	if (NO_ERROR != status)
+	{
+		//
+		// Assume that the route is present rather than risk adding a duplicate.
+		//
+
+		const auto err = std::wstring(L"Failed to look up route in routing table: ")
+			.append(FormatRegisteredRoute(route));
+
+		m_logSink->warning(common::string::ToAnsi(err).c_str());
+	}
+
+	return true;
+}
+
 }
@@ -7,11 +7,13 @@
 #include <optional>
 #include <mutex>
 #include <functional>
+#include <chrono>
 #include <windows.h>
 #include <ws2def.h>
 #include <ifdef.h>
 #include <libcommon/string.h>
 #include <libcommon/logging/ilogsink.h>
+#include <libcommon/burstguard.h>
 #include "defaultroutemonitor.h"
 #include "helpers.h"
 
@@ -118,10 +120,21 @@ private:
 		}
 	};
 
+	//
+	// Tracks how often a route has been added back after being removed by someone else.
+	// Used to back off rather than fight endlessly with another program.
+	//
+	struct RestoreState
+	{
+		std::optional<std::chrono::steady_clock::time_point> lastRestore;
+		std::chrono::milliseconds delay{ 0 };
+	};
+
 	struct RouteRecord
 	{
 		Route route;
 		RegisteredRoute registeredRoute;
+		RestoreState restoreState;
 	};
 
 	std::list<RouteRecord> m_routes;
@@ -130,6 +143,12 @@ private:
 	std::list<DefaultRouteChangedCallback> m_defaultRouteCallbacks;
 	std::recursive_mutex m_defaultRouteCallbacksLock;
 
+	// This can't be a plain member variable.
+	// We need to be able to delete it explicitly in order to have a controlled tear down.
+	std::unique_ptr<common::BurstGuard> m_restoreRoutesGuard;
+
+	HANDLE m_routeNotificationHandle;
+
 	//
 	// Find record based on route registration data.
 	//
@@ -171,6 +190,15 @@ private:
 
 	void defaultRouteChanged(ADDRESS_FAMILY family, DefaultRouteMonitor::EventType eventType,
 		const std::optional<InterfaceAndGateway> &route);
+
+	static void NETIOAPI_API_ RouteChangeCallback(void *context, MIB_IPFORWARD_ROW2 *row, MIB_NOTIFICATION_TYPE notificationType);
+
+	//
+	// Add back routes owned by us that have been removed from the routing table.
+	//
+	void restoreDeletedRoutes();
+
+	bool routePresentInRoutingTable(const RegisteredRoute &route);
 };
 
 }
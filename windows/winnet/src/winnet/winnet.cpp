@@ -1,6 +1,7 @@
 #include "stdafx.h"
 #include "winnet.h"
 #include "NetworkInterfaces.h"
+#include "NetworkCategory.h"
 #include "routing/routemanager.h"
 #include "converters.h"
 #include <libshared/logging/logsinkadapter.h>
@@ -545,3 +546,74 @@ WinNet_AddDeviceIpAddresses(
 		return false;
 	}
 }
+
+extern "C"
+WINNET_LINKAGE
+bool
+WINNET_API
+WinNet_PinPublicNetworkCategory(
+	const wchar_t *deviceAlias,
+	MullvadLogSink logSink,
+	void *logSinkContext,
+	void **pinHandle
+)
+{
+	try
+	{
+		if (nullptr == deviceAlias)
+		{
+			THROW_ERROR("Invalid argument: deviceAlias")
+		}
+
+		if (nullptr == pinHandle)
+		{
+			THROW_ERROR("Invalid argument: pinHandle")
+		}
+
+		NET_LUID luid;
+
+		if (0 != ConvertInterfaceAliasToLuid(deviceAlias, &luid))
+		{
+			const auto msg = std::string("Unable to derive interface LUID from interface alias: ")
+				.append(common::string::ToAnsi(deviceAlias));
+
+			THROW_ERROR(msg.c_str());
+		}
+
+		GUID guid;
+
+		const auto status = ConvertInterfaceLuidToGuid(&luid, &guid);
+
+		if (NO_ERROR != status)
+		{
+			THROW_WINDOWS_ERROR(status, "ConvertInterfaceLuidToGuid");
+		}
+
+		*pinHandle = new PublicNetworkCategoryPin(
+			guid,
+			std::make_shared<shared::logging::LogSinkAdapter>(logSink, logSinkContext)
+		);
+
+		return true;
+	}
+	catch (const std::exception &err)
+	{
+		shared::logging::UnwindAndLog(logSink, logSinkContext, err);
+		return false;
+	}
+	catch (...)
+	{
+		return false;
+	}
+}
+
+extern "C"
+WINNET_LINKAGE
+void
+WINNET_API
+WinNet_UnpinPublicNetworkCategory(
+	void *pinHandle
+)
+{
+	delete reinterpret_cast<PublicNetworkCategoryPin *>(pinHandle);
+}
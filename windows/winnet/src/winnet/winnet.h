@@ -222,3 +222,26 @@ WinNet_AddDeviceIpAddresses(
 	void *logSinkContext
 );
 
+//
+// Keeps the category of the network that the adapter is connected to set to "Public"
+// until `WinNet_UnpinPublicNetworkCategory` is called with the returned handle.
+//
+extern "C"
+WINNET_LINKAGE
+bool
+WINNET_API
+WinNet_PinPublicNetworkCategory(
+	const wchar_t *deviceAlias,
+	MullvadLogSink logSink,
+	void *logSinkContext,
+	void **pinHandle
+);
+
+extern "C"
+WINNET_LINKAGE
+void
+WINNET_API
+WinNet_UnpinPublicNetworkCategory(
+	void *pinHandle
+);
+
@@ -73,31 +73,3 @@ func wgTurnOn(cSettings *C.char, fd int, logSink LogSink, logContext LogContext)
 
 	return handle
 }
-
-//export wgGetSocketV4
-func wgGetSocketV4(tunnelHandle int32) int32 {
-	tunnel, err := tunnels.Get(tunnelHandle)
-	if err != nil {
-		return ERROR_GENERAL_FAILURE
-	}
-	peek := tunnel.Device.Bind().(conn.PeekLookAtSocketFd)
-	fd, err := peek.PeekLookAtSocketFd4()
-	if err != nil {
-		return ERROR_GENERAL_FAILURE
-	}
-	return int32(fd)
-}
-
-//export wgGetSocketV6
-func wgGetSocketV6(tunnelHandle int32) int32 {
-	tunnel, err := tunnels.Get(tunnelHandle)
-	if err != nil {
-		return ERROR_GENERAL_FAILURE
-	}
-	peek := tunnel.Device.Bind().(conn.PeekLookAtSocketFd)
-	fd, err := peek.PeekLookAtSocketFd6()
-	if err != nil {
-		return ERROR_GENERAL_FAILURE
-	}
-	return int32(fd)
-}
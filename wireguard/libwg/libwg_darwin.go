@@ -0,0 +1,42 @@
+// +build darwin
+
+/* SPDX-License-Identifier: Apache-2.0
+ *
+ * Copyright (C) 2017-2019 Jason A. Donenfeld <Jason@zx2c4.com>. All Rights Reserved.
+ * Copyright (C) 2021 Mullvad VPN AB. All Rights Reserved.
+ */
+
+package main
+
+import (
+	"golang.org/x/sys/unix"
+
+	"golang.zx2c4.com/wireguard/conn"
+)
+
+//export wgBindTunnelSocket
+func wgBindTunnelSocket(tunnelHandle int32, family uint16, interfaceIndex uint32) int32 {
+	tunnel, err := tunnels.Get(tunnelHandle)
+	if err != nil {
+		return ERROR_GENERAL_FAILURE
+	}
+	bind, ok := tunnel.Device.Bind().(conn.BindSocketToInterface)
+	if !ok {
+		return ERROR_GENERAL_FAILURE
+	}
+
+	if family == unix.AF_INET {
+		tunnel.Logger.Verbosef("Binding v4 socket to interface %d\n", interfaceIndex)
+		err = bind.BindSocketToInterface4(interfaceIndex, false)
+	} else if family == unix.AF_INET6 {
+		tunnel.Logger.Verbosef("Binding v6 socket to interface %d\n", interfaceIndex)
+		err = bind.BindSocketToInterface6(interfaceIndex, false)
+	} else {
+		return ERROR_GENERAL_FAILURE
+	}
+	if err != nil {
+		tunnel.Logger.Errorf("%s\n", err)
+		return ERROR_GENERAL_FAILURE
+	}
+	return 0
+}
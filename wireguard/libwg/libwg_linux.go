@@ -0,0 +1,47 @@
+// +build linux
+
+/* SPDX-License-Identifier: Apache-2.0
+ *
+ * Copyright (C) 2017-2019 Jason A. Donenfeld <Jason@zx2c4.com>. All Rights Reserved.
+ * Copyright (C) 2021 Mullvad VPN AB. All Rights Reserved.
+ */
+
+package main
+
+import (
+	"golang.zx2c4.com/wireguard/conn"
+)
+
+//export wgGetSocketV4
+func wgGetSocketV4(tunnelHandle int32) int32 {
+	tunnel, err := tunnels.Get(tunnelHandle)
+	if err != nil {
+		return ERROR_GENERAL_FAILURE
+	}
+	peek, ok := tunnel.Device.Bind().(conn.PeekLookAtSocketFd)
+	if !ok {
+		return ERROR_GENERAL_FAILURE
+	}
+	fd, err := peek.PeekLookAtSocketFd4()
+	if err != nil {
+		return ERROR_GENERAL_FAILURE
+	}
+	return int32(fd)
+}
+
+//export wgGetSocketV6
+func wgGetSocketV6(tunnelHandle int32) int32 {
+	tunnel, err := tunnels.Get(tunnelHandle)
+	if err != nil {
+		return ERROR_GENERAL_FAILURE
+	}
+	peek, ok := tunnel.Device.Bind().(conn.PeekLookAtSocketFd)
+	if !ok {
+		return ERROR_GENERAL_FAILURE
+	}
+	fd, err := peek.PeekLookAtSocketFd6()
+	if err != nil {
+		return ERROR_GENERAL_FAILURE
+	}
+	return int32(fd)
+}